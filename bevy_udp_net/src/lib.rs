@@ -0,0 +1,445 @@
+//! UDP messaging as a Bevy plugin.
+//!
+//! Every app here needs the same few pieces: a socket, a thread receiving
+//! on it, and a way for systems to read and write messages.
+//! [`NetworkSocket::bind`] opens the socket, before the app is built, so a
+//! port that's taken ends the program with a clear error instead of a
+//! panic. [`UdpNetworkPlugin`] then starts the receive thread and turns the
+//! traffic into events:
+//!
+//! - every datagram that arrived becomes a [`NetworkMessageReceived`] in
+//!   `PreUpdate`, so `Update` systems see it the same frame
+//! - every [`SendNetworkMessage`] written during a frame goes out in
+//!   `PostUpdate`
+//!
+//! A system that has to know whether a send worked can call
+//! [`NetworkSocket::send_to`] on the resource instead. A datagram bigger
+//! than the receive buffer is dropped and counted (see
+//! [`NetworkSocket::truncated_packets`]), never handed on cut short.
+//!
+//! ```
+//! # use bevy::prelude::*;
+//! # use bevy_udp_net::{NetworkSocket, UdpNetworkPlugin};
+//! # fn main() -> std::io::Result<()> {
+//! let socket = NetworkSocket::bind("127.0.0.1:0", 1024)?;
+//! let mut app = App::new();
+//! app.add_plugins(UdpNetworkPlugin::new(socket));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! An app with more to do as datagrams arrive brings its own [`Receive`]
+//! and hands it to [`UdpNetworkPlugin::with_receiver`]. The plugin then
+//! adds the socket and starts the thread but leaves the events out: the
+//! receiver decides where datagrams go. The Ping/Pong client does this, to
+//! run each datagram through its middleware pipeline and stamp its arrival
+//! on the receive thread. The Ping/Pong server binds its control and data
+//! sockets here too, but starts their threads itself with
+//! [`NetworkSocket::receive`], since its listener controls close and
+//! rebind them while it runs.
+//!
+//! With the `memory` feature, `memory` has sockets that reach each other
+//! without the OS. `test-util` adds `sim`, a seeded scheduler over them,
+//! for running a server and its clients in one test in an order that can
+//! be replayed.
+//!
+//! The OS sockets and their receive threads are the `native-net` feature,
+//! on by default. Without it only the memory network is left, which builds
+//! for wasm. `web-net` is where a browser's transport will go, another link
+//! next to the memory one; until it exists the feature only turns on
+//! `memory`.
+
+#[cfg(not(any(feature = "native-net", feature = "memory")))]
+compile_error!("bevy_udp_net needs a transport: enable `native-net`, `web-net` or `memory`");
+
+#[cfg(feature = "memory")]
+pub mod memory;
+#[cfg(feature = "test-util")]
+pub mod sim;
+
+use bevy::prelude::*;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "native-net")]
+use std::{
+    net::UdpSocket,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// A datagram that arrived.
+#[derive(Event, Clone, Debug)]
+pub struct NetworkMessageReceived {
+    pub from: SocketAddr,
+    pub bytes: Vec<u8>,
+}
+
+impl NetworkMessageReceived {
+    /// The datagram as text, with anything that isn't UTF-8 replaced
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.bytes)
+    }
+}
+
+/// A datagram to send at the end of the frame.
+#[derive(Event, Clone, Debug)]
+pub struct SendNetworkMessage {
+    /// Anything `ToSocketAddrs` takes, such as `127.0.0.1:50051`
+    pub to: String,
+    pub bytes: Vec<u8>,
+}
+
+impl SendNetworkMessage {
+    /// `bytes` may be text, as a `&str` or `String`
+    pub fn new(to: impl Into<String>, bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            to: to.into(),
+            bytes: bytes.into(),
+        }
+    }
+}
+
+/// What a receive thread does with what arrives. Only
+/// [`Receive::received`] is needed; the rest have defaults.
+pub trait Receive: Send + 'static {
+    /// A datagram that fit the receive buffer
+    fn received(&mut self, from: SocketAddr, bytes: &[u8]);
+
+    /// A datagram that didn't, already dropped and counted in
+    /// [`NetworkSocket::truncated_packets`]. Windows doesn't say who sent
+    /// it or how big it was.
+    fn oversized(&mut self, _from: Option<SocketAddr>, _size: usize) {}
+
+    /// Receiving stopped on `error`
+    fn failed(&mut self, error: io::Error) {
+        error!("Receiving stopped: {}", error);
+    }
+
+    /// Before every read, for counting the thread's wakeups
+    fn woke(&mut self) {}
+
+    /// Bytes to read into. Anything read beyond the receive buffer marks a
+    /// datagram that didn't fit.
+    fn buffer_len(&self, recv_buffer: usize) -> usize {
+        recv_buffer + 1
+    }
+
+    /// Reads what arrived: the bytes read, the sender, and the size of each
+    /// datagram in them. One datagram a read unless overridden, as with
+    /// GRO, which hands several from one sender back to back.
+    #[cfg(feature = "native-net")]
+    fn read(
+        &mut self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, usize)> {
+        socket.recv_from(buf).map(|(size, addr)| (size, addr, size))
+    }
+}
+
+impl Receive for Box<dyn Receive> {
+    fn received(&mut self, from: SocketAddr, bytes: &[u8]) {
+        (**self).received(from, bytes);
+    }
+
+    fn oversized(&mut self, from: Option<SocketAddr>, size: usize) {
+        (**self).oversized(from, size);
+    }
+
+    fn failed(&mut self, error: io::Error) {
+        (**self).failed(error);
+    }
+
+    fn woke(&mut self) {
+        (**self).woke();
+    }
+
+    fn buffer_len(&self, recv_buffer: usize) -> usize {
+        (**self).buffer_len(recv_buffer)
+    }
+
+    #[cfg(feature = "native-net")]
+    fn read(
+        &mut self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, usize)> {
+        (**self).read(socket, buf)
+    }
+}
+
+/// Received and not yet turned into events, oldest first
+type Inbox = Arc<Mutex<VecDeque<(SocketAddr, Vec<u8>)>>>;
+
+/// The plugin's own receiver: datagrams into the inbox
+struct DatagramInbox(Inbox);
+
+impl Receive for DatagramInbox {
+    fn received(&mut self, from: SocketAddr, bytes: &[u8]) {
+        self.0.lock().unwrap().push_back((from, bytes.to_vec()));
+    }
+}
+
+#[derive(Resource, Clone, Default)]
+struct Arrived(Inbox);
+
+/// What a [`NetworkSocket`] sends through
+#[derive(Clone)]
+enum Link {
+    #[cfg(feature = "native-net")]
+    Udp(Arc<UdpSocket>),
+    #[cfg(feature = "memory")]
+    Memory(memory::MemoryLink),
+}
+
+#[derive(Resource, Clone)]
+pub struct NetworkSocket {
+    link: Link,
+    /// Largest datagram accepted, in bytes; the memory network keeps its
+    /// own
+    #[cfg(feature = "native-net")]
+    recv_buffer: usize,
+    truncated: Arc<AtomicU64>,
+}
+
+impl NetworkSocket {
+    /// Binds `addr`. Nothing is received until [`UdpNetworkPlugin`] or
+    /// [`NetworkSocket::receive`] starts the thread.
+    #[cfg(feature = "native-net")]
+    pub fn bind(addr: impl ToSocketAddrs, recv_buffer: usize) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            link: Link::Udp(Arc::new(socket)),
+            recv_buffer,
+            truncated: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.link {
+            #[cfg(feature = "native-net")]
+            Link::Udp(socket) => socket.local_addr(),
+            #[cfg(feature = "memory")]
+            Link::Memory(link) => Ok(link.addr()),
+        }
+    }
+
+    /// The OS socket, for options this crate doesn't cover (GSO); `None`
+    /// on a memory network
+    #[cfg(feature = "native-net")]
+    pub fn udp(&self) -> Option<&UdpSocket> {
+        match &self.link {
+            Link::Udp(socket) => Some(socket),
+            #[cfg(feature = "memory")]
+            Link::Memory(_) => None,
+        }
+    }
+
+    /// Sends right away, for when the result matters
+    pub fn send_to(&self, text: &str, addr: impl ToSocketAddrs) -> io::Result<()> {
+        self.send_bytes_to(text.as_bytes(), addr)
+    }
+
+    pub fn send_bytes_to(&self, bytes: &[u8], addr: impl ToSocketAddrs) -> io::Result<()> {
+        match &self.link {
+            #[cfg(feature = "native-net")]
+            Link::Udp(socket) => socket.send_to(bytes, addr).map(|_| ()),
+            #[cfg(feature = "memory")]
+            Link::Memory(link) => link.send_to(bytes, addr),
+        }
+    }
+
+    /// Datagrams dropped so far because they didn't fit the receive buffer
+    pub fn truncated_packets(&self) -> u64 {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// Starts a thread handing what arrives to `receiver`, until
+    /// [`Receiver::stop`].
+    pub fn receive(&self, receiver: impl Receive) -> Receiver {
+        match &self.link {
+            #[cfg(feature = "native-net")]
+            Link::Udp(socket) => {
+                let stop = Arc::new(AtomicBool::new(false));
+                let thread = spawn_receiver(
+                    socket.clone(),
+                    self.recv_buffer,
+                    self.truncated.clone(),
+                    stop.clone(),
+                    receiver,
+                );
+                Receiver {
+                    stop,
+                    thread: Some(thread),
+                    #[cfg(feature = "memory")]
+                    memory: None,
+                }
+            }
+            // The memory network hands datagrams over as it delivers them
+            #[cfg(feature = "memory")]
+            Link::Memory(link) => {
+                link.receive(Box::new(receiver));
+                Receiver {
+                    stop: Arc::default(),
+                    #[cfg(feature = "native-net")]
+                    thread: None,
+                    memory: Some(link.clone()),
+                }
+            }
+        }
+    }
+}
+
+/// A running receive thread. Dropping this leaves it running.
+pub struct Receiver {
+    stop: Arc<AtomicBool>,
+    #[cfg(feature = "native-net")]
+    thread: Option<JoinHandle<()>>,
+    #[cfg(feature = "memory")]
+    memory: Option<memory::MemoryLink>,
+}
+
+impl Receiver {
+    /// Ends the thread and waits for it, so what its [`Receive`] held (a
+    /// clone of the socket, say) is gone when this returns.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        #[cfg(feature = "native-net")]
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        #[cfg(feature = "memory")]
+        if let Some(link) = self.memory.take() {
+            link.stop_receiving();
+        }
+    }
+}
+
+#[cfg(feature = "native-net")]
+fn spawn_receiver(
+    socket: Arc<UdpSocket>,
+    recv_buffer: usize,
+    truncated: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    mut receiver: impl Receive,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        // At least one spare byte: if it gets filled, the datagram didn't
+        // fit and the OS has silently cut it short
+        let mut buf = vec![0u8; receiver.buffer_len(recv_buffer).max(recv_buffer + 1)];
+        while !stop.load(Ordering::Relaxed) {
+            receiver.woke();
+            match receiver.read(&socket, &mut buf) {
+                Ok((0, addr, _)) => receiver.received(addr, &[]),
+                Ok((size, addr, stride)) => {
+                    for datagram in buf[..size].chunks(stride.max(1)) {
+                        if datagram.len() > recv_buffer {
+                            truncated.fetch_add(1, Ordering::Relaxed);
+                            warn!(
+                                "Dropped datagram from {} larger than the {}-byte receive buffer",
+                                addr, recv_buffer
+                            );
+                            receiver.oversized(Some(addr), datagram.len());
+                        } else {
+                            receiver.received(addr, datagram);
+                        }
+                    }
+                }
+                Err(ref e) if is_message_too_large(e) => {
+                    truncated.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "Dropped datagram larger than the {}-byte receive buffer",
+                        recv_buffer
+                    );
+                    receiver.oversized(None, 0);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                // On Windows a send to a port nobody listens on shows up
+                // as a receive error; the peer may just not be up yet
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(e) => {
+                    receiver.failed(e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Windows reports an oversized datagram as an error (WSAEMSGSIZE) instead
+/// of truncating it silently.
+#[cfg(feature = "native-net")]
+fn is_message_too_large(error: &io::Error) -> bool {
+    cfg!(windows) && error.raw_os_error() == Some(10040)
+}
+
+/// Starts receiving on a socket and adds it as a resource, with the two
+/// events unless it was given a receiver of its own.
+pub struct UdpNetworkPlugin {
+    socket: NetworkSocket,
+    /// Taken when the plugin is built
+    receiver: Mutex<Option<Box<dyn Receive>>>,
+}
+
+impl UdpNetworkPlugin {
+    pub fn new(socket: NetworkSocket) -> Self {
+        Self {
+            socket,
+            receiver: Mutex::new(None),
+        }
+    }
+
+    /// Hands what arrives to `receiver` instead of turning it into
+    /// [`NetworkMessageReceived`] events.
+    pub fn with_receiver(socket: NetworkSocket, receiver: impl Receive) -> Self {
+        Self {
+            socket,
+            receiver: Mutex::new(Some(Box::new(receiver))),
+        }
+    }
+}
+
+impl Plugin for UdpNetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.socket.clone());
+        if let Some(receiver) = self.receiver.lock().unwrap().take() {
+            self.socket.receive(receiver);
+            return;
+        }
+        let arrived = Arrived::default();
+        self.socket.receive(DatagramInbox(arrived.0.clone()));
+        app.insert_resource(arrived)
+            .add_event::<NetworkMessageReceived>()
+            .add_event::<SendNetworkMessage>()
+            .add_systems(PreUpdate, receive_network_messages)
+            .add_systems(PostUpdate, send_network_messages);
+    }
+}
+
+fn receive_network_messages(
+    arrived: Res<Arrived>,
+    mut received: EventWriter<NetworkMessageReceived>,
+) {
+    let arrived: Vec<(SocketAddr, Vec<u8>)> = arrived.0.lock().unwrap().drain(..).collect();
+    for (from, bytes) in arrived {
+        received.send(NetworkMessageReceived { from, bytes });
+    }
+}
+
+fn send_network_messages(
+    socket: Res<NetworkSocket>,
+    mut outgoing: EventReader<SendNetworkMessage>,
+) {
+    for message in outgoing.read() {
+        if let Err(e) = socket.send_bytes_to(&message.bytes, message.to.as_str()) {
+            warn!("Sending to {} failed: {}", message.to, e);
+        }
+    }
+}
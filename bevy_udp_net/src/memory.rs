@@ -0,0 +1,289 @@
+//! Sockets that reach each other without the OS.
+//!
+//! A [`MemoryNetwork`] binds [`NetworkSocket`]s like
+//! [`NetworkSocket::bind`] does, and every datagram sent on one stays in
+//! flight until something delivers it: the `sim` scheduler, one at a time
+//! in an order a seed picks, or [`MemoryNetwork::deliver_all`], for an app
+//! that runs on a memory network outside of a test. Delivery hands the
+//! datagram to the socket's [`Receive`], the same as the receive thread
+//! would, dropping and counting it if it doesn't fit.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{Link, NetworkSocket, Receive};
+
+/// Where port 0 binds start, the bottom of the dynamic range
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// A datagram on the memory network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Datagram {
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    pub bytes: Vec<u8>,
+}
+
+/// A bound address: who hears its datagrams, and how big they may be
+struct Endpoint {
+    /// Set once the socket receives; see [`NetworkSocket::receive`]
+    receiver: Option<Arc<Mutex<Box<dyn Receive>>>>,
+    /// Delivered before anyone received, as an OS buffer would hold them
+    pending: Vec<Datagram>,
+    recv_buffer: usize,
+    truncated: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct Wires {
+    endpoints: HashMap<SocketAddr, Endpoint>,
+    /// Sent and not yet delivered, in the order they were sent
+    in_flight: Vec<Datagram>,
+    next_port: u16,
+}
+
+/// Sockets that reach each other without the OS. Cloning it gives another
+/// handle to the same network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork(Arc<Mutex<Wires>>);
+
+impl MemoryNetwork {
+    /// A socket on this network, like [`NetworkSocket::bind`]. Port 0 picks
+    /// a free port, and an unspecified address (`0.0.0.0`) binds
+    /// `127.0.0.1`, so what the peers see is an address they can send to.
+    pub fn bind(&self, addr: impl ToSocketAddrs, recv_buffer: usize) -> io::Result<NetworkSocket> {
+        let mut addr = resolve(addr)?;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+        let mut wires = self.0.lock().unwrap();
+        if addr.port() == 0 {
+            let mut port = wires.next_port.max(FIRST_EPHEMERAL_PORT);
+            while wires
+                .endpoints
+                .contains_key(&SocketAddr::new(addr.ip(), port))
+            {
+                port = port.checked_add(1).ok_or(io::ErrorKind::AddrNotAvailable)?;
+            }
+            wires.next_port = port.saturating_add(1);
+            addr.set_port(port);
+        } else if wires.endpoints.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already bound", addr),
+            ));
+        }
+        let socket = NetworkSocket {
+            link: Link::Memory(MemoryLink {
+                addr,
+                network: self.clone(),
+            }),
+            #[cfg(feature = "native-net")]
+            recv_buffer,
+            truncated: Arc::default(),
+        };
+        wires.endpoints.insert(
+            addr,
+            Endpoint {
+                receiver: None,
+                pending: Vec::new(),
+                recv_buffer,
+                truncated: socket.truncated.clone(),
+            },
+        );
+        Ok(socket)
+    }
+
+    /// Datagrams sent and not yet delivered
+    pub fn in_flight(&self) -> usize {
+        self.0.lock().unwrap().in_flight.len()
+    }
+
+    /// Delivers everything in flight, oldest first, and returns how many
+    /// there were. What the receivers send meanwhile waits for the next
+    /// call.
+    pub fn deliver_all(&self) -> usize {
+        let count = self.in_flight();
+        for _ in 0..count {
+            self.deliver(0);
+        }
+        count
+    }
+
+    /// Takes the `index`th datagram in flight off the network and hands it
+    /// to its socket's receiver. Whether anyone was bound there.
+    pub(crate) fn deliver(&self, index: usize) -> (Datagram, bool) {
+        let mut wires = self.0.lock().unwrap();
+        let datagram = wires.in_flight.remove(index);
+        let Some(endpoint) = wires.endpoints.get_mut(&datagram.to) else {
+            return (datagram, false);
+        };
+        let Some(receiver) = endpoint.receiver.clone() else {
+            endpoint.pending.push(datagram.clone());
+            return (datagram, true);
+        };
+        let Endpoint {
+            recv_buffer,
+            truncated,
+            ..
+        } = endpoint;
+        let (recv_buffer, truncated) = (*recv_buffer, truncated.clone());
+        // The receiver may send, which needs the wires
+        drop(wires);
+        hand_over(&receiver, recv_buffer, &truncated, &datagram);
+        (datagram, true)
+    }
+}
+
+/// What the receive thread would do with `datagram`: drop and count it if
+/// it doesn't fit, hand it on if it does
+fn hand_over(
+    receiver: &Mutex<Box<dyn Receive>>,
+    recv_buffer: usize,
+    truncated: &AtomicU64,
+    datagram: &Datagram,
+) {
+    let mut receiver = receiver.lock().unwrap();
+    if datagram.bytes.len() > recv_buffer {
+        truncated.fetch_add(1, Ordering::Relaxed);
+        receiver.oversized(Some(datagram.from), datagram.bytes.len());
+    } else {
+        receiver.received(datagram.from, &datagram.bytes);
+    }
+}
+
+/// A [`NetworkSocket`]'s way onto a [`MemoryNetwork`]
+#[derive(Clone)]
+pub(crate) struct MemoryLink {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+}
+
+impl MemoryLink {
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Puts the datagram in flight; it arrives when the scheduler delivers it
+    pub(crate) fn send_to(&self, bytes: &[u8], addr: impl ToSocketAddrs) -> io::Result<()> {
+        let to = resolve(addr)?;
+        self.network.0.lock().unwrap().in_flight.push(Datagram {
+            from: self.addr,
+            to,
+            bytes: bytes.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Hands what is delivered from now on to `receiver`, after whatever
+    /// was waiting for one
+    pub(crate) fn receive(&self, receiver: Box<dyn Receive>) {
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut wires = self.network.0.lock().unwrap();
+        let endpoint = wires
+            .endpoints
+            .get_mut(&self.addr)
+            .expect("memory sockets stay bound");
+        endpoint.receiver = Some(receiver.clone());
+        let pending = std::mem::take(&mut endpoint.pending);
+        let (recv_buffer, truncated) = (endpoint.recv_buffer, endpoint.truncated.clone());
+        drop(wires);
+        for datagram in &pending {
+            hand_over(&receiver, recv_buffer, &truncated, datagram);
+        }
+    }
+
+    /// Drops the receiver. What arrives after waits, as it did before the
+    /// first one
+    pub(crate) fn stop_receiving(&self) {
+        let receiver = self
+            .network
+            .0
+            .lock()
+            .unwrap()
+            .endpoints
+            .get_mut(&self.addr)
+            .and_then(|endpoint| endpoint.receiver.take());
+        drop(receiver);
+    }
+}
+
+fn resolve(addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a socket heard, in the order it heard it
+    #[derive(Clone, Default)]
+    struct Heard(Arc<Mutex<Vec<String>>>);
+
+    impl Receive for Heard {
+        fn received(&mut self, _from: SocketAddr, bytes: &[u8]) {
+            let text = String::from_utf8_lossy(bytes).to_string();
+            self.0.lock().unwrap().push(text);
+        }
+    }
+
+    #[test]
+    fn binds_pick_free_ports_and_refuse_taken_ones() {
+        let network = MemoryNetwork::default();
+        let first = network.bind("0.0.0.0:0", 64).unwrap().local_addr().unwrap();
+        let second = network
+            .bind("127.0.0.1:0", 64)
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert_eq!(first.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(first.port(), FIRST_EPHEMERAL_PORT);
+        assert_ne!(first, second);
+        let error = network.bind(first, 64).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[test]
+    fn deliver_all_keeps_the_order_they_were_sent_in() {
+        let network = MemoryNetwork::default();
+        let server = network.bind("127.0.0.1:50051", 64).unwrap();
+        let heard = Heard::default();
+        let _receiver = server.receive(heard.clone());
+        let client = network.bind("127.0.0.1:0", 64).unwrap();
+        for text in ["one", "two", "three"] {
+            client.send_to(text, "127.0.0.1:50051").unwrap();
+        }
+        assert_eq!(network.deliver_all(), 3);
+        assert_eq!(network.in_flight(), 0);
+        assert_eq!(*heard.0.lock().unwrap(), ["one", "two", "three"]);
+    }
+
+    /// Echoes everything back, from inside the delivery
+    struct Echo(NetworkSocket);
+
+    impl Receive for Echo {
+        fn received(&mut self, from: SocketAddr, bytes: &[u8]) {
+            self.0.send_bytes_to(bytes, from).unwrap();
+        }
+    }
+
+    #[test]
+    fn replies_sent_during_delivery_wait_for_the_next_one() {
+        let network = MemoryNetwork::default();
+        let server = network.bind("127.0.0.1:50051", 64).unwrap();
+        let _echo = server.receive(Echo(server.clone()));
+        let client = network.bind("127.0.0.1:0", 64).unwrap();
+        let heard = Heard::default();
+        let _receiver = client.receive(heard.clone());
+        client.send_to("ping", "127.0.0.1:50051").unwrap();
+        assert_eq!(network.deliver_all(), 1);
+        assert!(heard.0.lock().unwrap().is_empty());
+        assert_eq!(network.deliver_all(), 1);
+        assert_eq!(*heard.0.lock().unwrap(), ["ping"]);
+    }
+}
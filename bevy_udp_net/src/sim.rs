@@ -0,0 +1,422 @@
+//! A deterministic network for tests: a server app and its clients in one
+//! process, run in an order that a seed decides.
+//!
+//! A test binds its sockets on a [`MemoryNetwork`] instead of with
+//! [`NetworkSocket::bind`](crate::NetworkSocket::bind), and hands each app
+//! to a [`Scheduler`]. Nothing moves until the scheduler says so. Each [`Scheduler::step`] draws one
+//! thing to do from everything that could happen next: update one of the
+//! apps, or deliver one of the datagrams in flight. Datagrams are drawn
+//! from anywhere in the queue, so they arrive reordered the way UDP may
+//! reorder them, and an app can run several frames before another hears
+//! from it. The draws come from a `common::shared_rng::SharedRng`, so the
+//! same seed gives the same interleaving, step for step.
+//!
+//! [`Scheduler::from_env`] takes the seed from `NET_SCHEDULER_SEED`, or
+//! picks one and prints it. A test that fails one run in a hundred then
+//! says which seed it failed with, and setting the variable to that seed
+//! runs the failing order again. [`Scheduler::trace`] lists every step
+//! taken.
+//!
+//! The scheduler also takes the apps' clocks and threads out of the
+//! picture: every update advances an app's `Time` by one simulated frame
+//! (`TimeUpdateStrategy::ManualDuration`), however long it really took, and
+//! every schedule runs single-threaded, so systems run in the same order
+//! each frame. Each app should add `MinimalPlugins` (or at least the
+//! `TimePlugin`) and [`UdpNetworkPlugin`](crate::UdpNetworkPlugin), and
+//! not be run with `App::run`. Anything an app does on its own threads or
+//! with its own randomness is outside the scheduler's reach.
+//!
+//! ```
+//! # use bevy::prelude::*;
+//! # use bevy_udp_net::sim::Scheduler;
+//! # use bevy_udp_net::{NetworkMessageReceived, NetworkSocket, SendNetworkMessage, UdpNetworkPlugin};
+//! # #[derive(Resource, Default)]
+//! # struct Joined(bool);
+//! # fn server_app(socket: NetworkSocket) -> App {
+//! #     let mut app = App::new();
+//! #     app.add_plugins((MinimalPlugins, UdpNetworkPlugin::new(socket)))
+//! #         .add_systems(Update, |mut received: EventReader<NetworkMessageReceived>,
+//! #                               mut send: EventWriter<SendNetworkMessage>| {
+//! #             for message in received.read() {
+//! #                 send.send(SendNetworkMessage::new(message.from.to_string(), "Welcome"));
+//! #             }
+//! #         });
+//! #     app
+//! # }
+//! # fn client_app(socket: NetworkSocket, server: &'static str) -> App {
+//! #     let mut app = App::new();
+//! #     app.add_plugins((MinimalPlugins, UdpNetworkPlugin::new(socket)))
+//! #         .init_resource::<Joined>()
+//! #         .add_systems(Startup, move |mut send: EventWriter<SendNetworkMessage>| {
+//! #             send.send(SendNetworkMessage::new(server, "Join"));
+//! #         })
+//! #         .add_systems(Update, |mut received: EventReader<NetworkMessageReceived>,
+//! #                               mut joined: ResMut<Joined>| {
+//! #             joined.0 |= received.read().any(|message| message.text() == "Welcome");
+//! #         });
+//! #     app
+//! # }
+//! # fn joined(world: &World) -> bool {
+//! #     world.resource::<Joined>().0
+//! # }
+//! # fn main() -> std::io::Result<()> {
+//! let mut scheduler = Scheduler::from_env();
+//! let socket = scheduler.network().bind("127.0.0.1:50051", 1024)?;
+//! scheduler.add_app(server_app(socket));
+//! let socket = scheduler.network().bind("127.0.0.1:0", 1024)?;
+//! scheduler.add_app(client_app(socket, "127.0.0.1:50051"));
+//! assert!(scheduler.run_until(10_000, |scheduler| joined(scheduler.world(1))));
+//! # Ok(())
+//! # }
+//! ```
+
+use bevy::ecs::schedule::{ExecutorKind, Schedules};
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use common::shared_rng::SharedRng;
+use std::fmt;
+use std::time::Duration;
+
+pub use crate::memory::{Datagram, MemoryNetwork};
+
+/// Where [`Scheduler::from_env`] looks for the seed, decimal or `0x` hex
+pub const SEED_VAR: &str = "NET_SCHEDULER_SEED";
+
+/// Simulated time per app update, unless [`Scheduler::frame`] says otherwise
+const DEFAULT_FRAME: Duration = Duration::from_millis(16);
+
+/// One thing the scheduler did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Ran one frame of the app at this index
+    Update(usize),
+    Deliver(Datagram),
+    /// Sent to an address nothing was bound to, and dropped
+    Unreachable(Datagram),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Update(app) => write!(f, "update app {}", app),
+            Step::Deliver(datagram) => write!(
+                f,
+                "deliver {} -> {}: {}",
+                datagram.from,
+                datagram.to,
+                String::from_utf8_lossy(&datagram.bytes)
+            ),
+            Step::Unreachable(datagram) => write!(
+                f,
+                "drop {} -> {} (nothing bound): {}",
+                datagram.from,
+                datagram.to,
+                String::from_utf8_lossy(&datagram.bytes)
+            ),
+        }
+    }
+}
+
+pub struct Scheduler {
+    seed: u64,
+    rng: SharedRng,
+    network: MemoryNetwork,
+    apps: Vec<App>,
+    frame: Duration,
+    trace: Vec<Step>,
+}
+
+impl Scheduler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: SharedRng::new(seed),
+            network: MemoryNetwork::default(),
+            apps: Vec::new(),
+            frame: DEFAULT_FRAME,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Seeded from [`SEED_VAR`], or from the clock without it. Prints the
+    /// seed either way, so a failing test's output says how to replay it.
+    /// Panics if the variable is set to something that isn't a seed.
+    pub fn from_env() -> Self {
+        let seed = match std::env::var(SEED_VAR) {
+            Ok(value) => parse_seed(&value)
+                .unwrap_or_else(|| panic!("{} is not a seed: {}", SEED_VAR, value)),
+            Err(_) => SharedRng::random_seed(),
+        };
+        eprintln!(
+            "Scheduler seed {:#x}; {}={:#x} replays this run",
+            seed, SEED_VAR, seed
+        );
+        Self::new(seed)
+    }
+
+    /// Simulated time each update advances an app's clock by. Set it
+    /// before adding the apps.
+    pub fn frame(mut self, frame: Duration) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Where the apps' sockets should be bound
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// Takes over `app`: its clock, its executors and its updates. Returns
+    /// its index, for [`Scheduler::world`] and the trace.
+    pub fn add_app(&mut self, mut app: App) -> usize {
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(self.frame));
+        for (_, schedule) in app.world.resource_mut::<Schedules>().iter_mut() {
+            schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        }
+        // What `App::run` would do before the first update
+        app.finish();
+        app.cleanup();
+        self.apps.push(app);
+        self.apps.len() - 1
+    }
+
+    pub fn world(&self, app: usize) -> &World {
+        &self.apps[app].world
+    }
+
+    pub fn world_mut(&mut self, app: usize) -> &mut World {
+        &mut self.apps[app].world
+    }
+
+    /// Every step taken so far, oldest first
+    pub fn trace(&self) -> &[Step] {
+        &self.trace
+    }
+
+    /// Draws and takes the next step. `false` if there was nothing to do:
+    /// no apps and nothing in flight.
+    pub fn step(&mut self) -> bool {
+        let choices = self.apps.len() + self.network.in_flight();
+        if choices == 0 {
+            return false;
+        }
+        let choice = self.rng.below(choices);
+        let step = if choice < self.apps.len() {
+            self.apps[choice].update();
+            Step::Update(choice)
+        } else {
+            match self.network.deliver(choice - self.apps.len()) {
+                (datagram, true) => Step::Deliver(datagram),
+                (datagram, false) => Step::Unreachable(datagram),
+            }
+        };
+        self.trace.push(step);
+        true
+    }
+
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            if !self.step() {
+                break;
+            }
+        }
+    }
+
+    /// Steps until `done` holds, checking before each step, for at most
+    /// `max_steps`. Whether it came to hold.
+    pub fn run_until(&mut self, max_steps: usize, mut done: impl FnMut(&Self) -> bool) -> bool {
+        for _ in 0..max_steps {
+            if done(self) {
+                return true;
+            }
+            if !self.step() {
+                break;
+            }
+        }
+        done(self)
+    }
+}
+
+fn parse_seed(seed: &str) -> Option<u64> {
+    let seed = seed.trim();
+    match seed.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => seed.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NetworkMessageReceived, NetworkSocket, SendNetworkMessage, UdpNetworkPlugin};
+
+    const SEED: u64 = 0x5eed;
+    const SERVER: &str = "127.0.0.1:50051";
+
+    /// Everything an app has received, in the order its systems saw it
+    #[derive(Resource, Default)]
+    struct Received(Vec<String>);
+
+    fn record(mut received: EventReader<NetworkMessageReceived>, mut log: ResMut<Received>) {
+        log.0
+            .extend(received.read().map(|message| message.text().into_owned()));
+    }
+
+    fn echo(
+        mut received: EventReader<NetworkMessageReceived>,
+        mut send: EventWriter<SendNetworkMessage>,
+    ) {
+        for message in received.read() {
+            send.send(SendNetworkMessage::new(
+                message.from.to_string(),
+                format!("echo {}", message.text()),
+            ));
+        }
+    }
+
+    fn app(socket: NetworkSocket) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, UdpNetworkPlugin::new(socket)))
+            .init_resource::<Received>()
+            .add_systems(Update, record);
+        app
+    }
+
+    /// Sends `count` numbered messages to the server on its first frame
+    fn client(socket: NetworkSocket, count: usize) -> App {
+        let mut app = app(socket);
+        app.add_systems(Startup, move |mut send: EventWriter<SendNetworkMessage>| {
+            for n in 0..count {
+                send.send(SendNetworkMessage::new(SERVER, n.to_string()));
+            }
+        });
+        app
+    }
+
+    /// An echo server on [`SERVER`] and `clients` clients sending it
+    /// `count` messages each
+    fn echo_scheduler(seed: u64, clients: usize, count: usize) -> Scheduler {
+        let mut scheduler = Scheduler::new(seed);
+        let mut server = app(scheduler.network().bind(SERVER, 1024).unwrap());
+        server.add_systems(Update, echo.after(record));
+        scheduler.add_app(server);
+        for _ in 0..clients {
+            let socket = scheduler.network().bind("127.0.0.1:0", 1024).unwrap();
+            scheduler.add_app(client(socket, count));
+        }
+        scheduler
+    }
+
+    fn received(scheduler: &Scheduler, app: usize) -> &[String] {
+        &scheduler.world(app).resource::<Received>().0
+    }
+
+    fn all_echoed(scheduler: &Scheduler, clients: usize, count: usize) -> bool {
+        (1..=clients).all(|app| received(scheduler, app).len() == count)
+    }
+
+    #[test]
+    fn every_message_makes_the_round_trip() {
+        let mut scheduler = echo_scheduler(SEED, 3, 5);
+        assert!(scheduler.run_until(10_000, |scheduler| all_echoed(scheduler, 3, 5)));
+        assert_eq!(received(&scheduler, 0).len(), 15);
+        for app in 1..=3 {
+            let mut echoes = received(&scheduler, app).to_vec();
+            echoes.sort();
+            let expected: Vec<String> = (0..5).map(|n| format!("echo {}", n)).collect();
+            assert_eq!(echoes, expected);
+        }
+        assert_eq!(scheduler.network().in_flight(), 0);
+    }
+
+    #[test]
+    fn a_seed_replays_the_same_run() {
+        let mut first = echo_scheduler(SEED, 2, 4);
+        let mut second = echo_scheduler(SEED, 2, 4);
+        first.run(500);
+        second.run(500);
+        assert_eq!(first.trace(), second.trace());
+        for app in 0..3 {
+            assert_eq!(received(&first, app), received(&second, app));
+        }
+    }
+
+    #[test]
+    fn other_seeds_reorder_the_datagrams() {
+        let order = |seed| {
+            let mut scheduler = echo_scheduler(seed, 1, 8);
+            assert!(scheduler.run_until(10_000, |scheduler| all_echoed(scheduler, 1, 8)));
+            received(&scheduler, 0).to_vec()
+        };
+        let first = order(SEED);
+        assert!(
+            (1..20).any(|seed| order(SEED + seed) != first),
+            "twenty seeds all delivered in the same order"
+        );
+    }
+
+    #[test]
+    fn an_oversized_datagram_is_dropped_and_counted() {
+        let mut scheduler = Scheduler::new(SEED);
+        let server = scheduler.network().bind(SERVER, 8).unwrap();
+        let counter = server.clone();
+        scheduler.add_app(app(server));
+        let sender = scheduler.network().bind("127.0.0.1:0", 1024).unwrap();
+        sender.send_to("far too long", SERVER).unwrap();
+        sender.send_to("short", SERVER).unwrap();
+        assert!(scheduler.run_until(1_000, |scheduler| !received(scheduler, 0).is_empty()));
+        scheduler.run(100);
+        assert_eq!(received(&scheduler, 0), ["short"]);
+        assert_eq!(counter.truncated_packets(), 1);
+    }
+
+    #[test]
+    fn datagrams_wait_for_a_receiver() {
+        let mut scheduler = Scheduler::new(SEED);
+        let server = scheduler.network().bind(SERVER, 1024).unwrap();
+        let sender = scheduler.network().bind("127.0.0.1:0", 1024).unwrap();
+        sender.send_to("early", SERVER).unwrap();
+        assert!(scheduler.step());
+        assert!(matches!(&scheduler.trace()[0], Step::Deliver(_)));
+        scheduler.add_app(app(server));
+        scheduler.run(2);
+        assert_eq!(received(&scheduler, 0), ["early"]);
+    }
+
+    #[test]
+    fn a_datagram_to_nobody_is_dropped() {
+        let mut scheduler = Scheduler::new(SEED);
+        let sender = scheduler.network().bind("127.0.0.1:0", 1024).unwrap();
+        sender.send_to("hello?", "127.0.0.1:9").unwrap();
+        assert!(scheduler.step());
+        assert!(matches!(
+            &scheduler.trace()[0],
+            Step::Unreachable(datagram) if datagram.bytes == b"hello?"
+        ));
+        assert!(!scheduler.step());
+    }
+
+    #[test]
+    fn every_update_advances_the_clock_one_frame() {
+        let mut scheduler = Scheduler::new(SEED).frame(Duration::from_millis(50));
+        let socket = scheduler.network().bind("127.0.0.1:0", 64).unwrap();
+        scheduler.add_app(app(socket));
+        scheduler.run(4);
+        assert_eq!(scheduler.trace(), vec![Step::Update(0); 4]);
+        // The first update only starts the clock
+        let elapsed = scheduler.world(0).resource::<Time>().elapsed();
+        assert_eq!(elapsed, Duration::from_millis(150));
+    }
+
+    #[test]
+    fn seeds_parse_as_decimal_or_hex() {
+        assert_eq!(parse_seed("42"), Some(42));
+        assert_eq!(parse_seed(" 0x2a\n"), Some(42));
+        assert_eq!(parse_seed("0xzz"), None);
+        assert_eq!(parse_seed("seed"), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! Logging in to an account with `--user` and `--password`.
+//!
+//! After every Welcome the client starts a fresh reliable channel to the
+//! server and sends `Login user=<name> password=<len>:<password>` on it,
+//! unencrypted like everything else on the wire; the server answers
+//! `LoginOk` with the account's totals or `LoginFailed` with a reason. See
+//! the server's `accounts` module for the format. Without
+//! `--user` the client plays as a guest and doesn't log in, though the
+//! channel still carries crash reports (see `crash`), the server's
+//! settings changes (see `settings`) and its announcements, which are
+//! logged as `[MOTD]` or `[Announcement]` in a color of their own.
+
+use bevy::prelude::*;
+use common::handlers::{
+    Announcement, AnnouncementKind, ConfigUpdate, NetMessage, NetReceived, ReliableAck,
+    ReliableData, Welcome,
+};
+use common::protocol::login_payload;
+use common::reliable::{ReliableReceiver, ReliableSender};
+use common::session::message_kind;
+
+use crate::crash::CrashReporter;
+use crate::session::Session;
+use crate::{Args, ClientState, NetworkState};
+
+/// Most unacked login messages in flight
+const WINDOW: usize = 4;
+const RESEND_SECS: f32 = 0.5;
+
+#[derive(Resource)]
+pub struct AccountLink {
+    sender: ReliableSender,
+    receiver: ReliableReceiver,
+}
+
+impl AccountLink {
+    pub fn push(&mut self, payload: String) {
+        self.sender.push(payload);
+    }
+}
+
+impl Default for AccountLink {
+    fn default() -> Self {
+        Self {
+            sender: ReliableSender::new(WINDOW, RESEND_SECS),
+            receiver: ReliableReceiver::default(),
+        }
+    }
+}
+
+fn field<'a>(payload: &'a str, name: &str) -> Option<&'a str> {
+    payload
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+pub fn log_in_after_welcome(
+    args: Res<Args>,
+    mut welcomes: EventReader<NetReceived<Welcome>>,
+    mut link: ResMut<AccountLink>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for _ in welcomes.read() {
+        // The server starts its end afresh with the Welcome too
+        *link = AccountLink::default();
+        let Some(user) = &args.user else {
+            continue;
+        };
+        link.sender
+            .push(login_payload(user, args.password.as_deref().unwrap_or("")));
+        client_state.push_log(format!(
+            "[Account]: Logging in as {} (the password travels unencrypted)",
+            user
+        ));
+    }
+}
+
+pub fn handle_account_acks(
+    mut acks: EventReader<NetReceived<ReliableAck>>,
+    mut link: ResMut<AccountLink>,
+) {
+    for ack in acks.read() {
+        link.sender.ack(ack.message.seq);
+    }
+}
+
+pub fn handle_account_replies(
+    network: Res<NetworkState>,
+    mut replies: EventReader<NetReceived<ReliableData>>,
+    mut link: ResMut<AccountLink>,
+    mut crash_reporter: Option<ResMut<CrashReporter>>,
+    mut config_updates: EventWriter<NetReceived<ConfigUpdate>>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for reply in replies.read() {
+        let data = &reply.message;
+        let (ack, delivered) = link.receiver.receive(data.seq, &data.payload);
+        if let Some(ack) = ack {
+            network.send_to(ack.as_bytes(), reply.from.as_str());
+        }
+        for payload in delivered {
+            if message_kind(&payload) == ConfigUpdate::KIND {
+                // Applied and logged by `settings::apply_config_updates`
+                match ConfigUpdate::parse(&payload) {
+                    Some(message) => {
+                        config_updates.send(NetReceived {
+                            from: reply.from.clone(),
+                            message,
+                        });
+                    }
+                    None => warn!("Malformed ConfigUpdate from {}: {}", reply.from, payload),
+                }
+                continue;
+            }
+            if let Some(announcement) = Announcement::parse(&payload) {
+                let tag = match announcement.kind {
+                    AnnouncementKind::Motd => "MOTD",
+                    AnnouncementKind::Notice => "Announcement",
+                };
+                client_state.push_log(format!("[{}]: {}", tag, announcement.text));
+                continue;
+            }
+            let count = |name| field(&payload, name).unwrap_or("0");
+            let line = if payload.starts_with("LoginOk") {
+                let user = field(&payload, "user").unwrap_or("?");
+                if payload.split_whitespace().any(|field| field == "new") {
+                    format!("[Account]: Created account {}", user)
+                } else {
+                    format!(
+                        "[Account]: Logged in as {}: login #{}, {} pings and {} chat lines so far",
+                        user,
+                        count("logins"),
+                        count("pings"),
+                        count("chats")
+                    )
+                }
+            } else if payload.starts_with("LoginFailed") {
+                let reason = field(&payload, "reason").unwrap_or("unknown");
+                format!("[Error]: Login failed ({})", reason.replace('_', " "))
+            } else if let Some(reporter) = crash_reporter
+                .as_deref_mut()
+                .filter(|_| payload.starts_with("CrashReport"))
+            {
+                reporter.answered(&payload)
+            } else {
+                format!("[Rx]: {}", payload)
+            };
+            client_state.push_log(line);
+        }
+    }
+}
+
+pub fn flush_account_link(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    client_state: Res<ClientState>,
+    mut link: ResMut<AccountLink>,
+    mut session: ResMut<Session>,
+) {
+    for line in link.sender.poll(time.elapsed_seconds()) {
+        network.send_to(line.as_bytes(), client_state.server_addr.as_str());
+        session.record_sent("REL", line.len());
+    }
+}
@@ -0,0 +1,215 @@
+//! Address entry field: edit the server address and press Enter to connect
+//! there instead of `--server`. Unlike the connect dialog it stays on screen
+//! while connected, for switching servers. The transport menu sits next to
+//! it; see `transport`.
+//!
+//! With `--matchmaker` there's a code field too. Entering an invite code
+//! joins whichever server it was made for, so two players can meet without
+//! either typing the other's address, and the Invite button next to it asks
+//! the matchmaker for a code for the server we're on; see `ticket`.
+
+use bevy::prelude::*;
+use common::ui::text_input::{TextInput, TextInputSubmitted, spawn_text_input};
+use common::ui::widgets::{ButtonFlash, ThemedButton, UiTheme};
+
+use crate::connect::validate_address;
+use crate::gui::HeaderText;
+use crate::handshake::{Handshake, start_attempt};
+use crate::session::Session;
+use crate::ticket::fetch_invite;
+use crate::transport::spawn_transport_menu;
+use crate::{Args, ClientState, NetworkState};
+
+#[derive(Component)]
+pub struct AddressInput;
+
+#[derive(Component)]
+pub struct CodeInput;
+
+#[derive(Component)]
+pub struct InviteButton;
+
+pub fn setup_address_entry(mut commands: Commands, args: Res<Args>, theme: Res<UiTheme>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Server:",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            spawn_text_input(
+                parent,
+                TextInput::new("host:port").with_value(args.server.clone()),
+                Val::Px(240.0),
+                AddressInput,
+            );
+            spawn_transport_menu(parent, &theme, args.transport);
+            if args.matchmaker.is_some() {
+                spawn_code_entry(parent, &theme, &args);
+            }
+        });
+}
+
+fn spawn_code_entry(parent: &mut ChildBuilder, theme: &UiTheme, args: &Args) {
+    parent.spawn(TextBundle::from_section(
+        "Code:",
+        TextStyle {
+            font_size: 16.0,
+            color: Color::rgb(0.9, 0.9, 0.9),
+            ..default()
+        },
+    ));
+    spawn_text_input(
+        parent,
+        TextInput::new("invite").with_value(args.join_code.clone().unwrap_or_default()),
+        Val::Px(90.0),
+        CodeInput,
+    );
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(64.0),
+                    height: Val::Px(28.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: theme.button_color.into(),
+                ..default()
+            },
+            ThemedButton,
+            ButtonFlash::default(),
+            InviteButton,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                "Invite",
+                TextStyle {
+                    font_size: 16.0,
+                    color: theme.text_color,
+                    ..default()
+                },
+            ));
+        });
+}
+
+pub fn handle_address_submit(
+    mut submitted: EventReader<TextInputSubmitted>,
+    address_input: Query<(), With<AddressInput>>,
+    mut header: Query<&mut Text, With<HeaderText>>,
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    for event in submitted.read() {
+        if address_input.get(event.entity).is_err() {
+            continue;
+        }
+        let target = event.value.trim();
+        if target.is_empty() {
+            continue;
+        }
+        if let Err(reason) = validate_address(target) {
+            client_state.push_log(format!("[Error]: Invalid address {}: {}", target, reason));
+            continue;
+        }
+
+        session.end(
+            time.elapsed_seconds(),
+            &format!("switched to {}", target),
+            &mut client_state,
+        );
+        client_state.set_endpoints(target);
+        for mut text in header.iter_mut() {
+            text.sections[0].value = format!(
+                "Client connecting to {}{}",
+                client_state.server_target,
+                client_state.endpoint_position()
+            );
+        }
+        handshake.reset_attempts();
+        start_attempt(&network, &args, &mut handshake, &mut client_state);
+    }
+}
+
+/// Entering a code reconnects to its server, with a ticket from the
+/// matchmaker's `/join`.
+pub fn handle_code_submit(
+    mut submitted: EventReader<TextInputSubmitted>,
+    code_input: Query<(), With<CodeInput>>,
+    mut header: Query<&mut Text, With<HeaderText>>,
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    for event in submitted.read() {
+        if code_input.get(event.entity).is_err() {
+            continue;
+        }
+        let code = event.value.trim().to_ascii_uppercase();
+        if code.is_empty() {
+            continue;
+        }
+
+        session.end(
+            time.elapsed_seconds(),
+            &format!("joining by code {}", code),
+            &mut client_state,
+        );
+        for mut text in header.iter_mut() {
+            text.sections[0].value = format!("Client joining by code {}", code);
+        }
+        client_state.join_code = Some(code);
+        // The one we have may be for another server
+        client_state.ticket = None;
+        handshake.reset_attempts();
+        start_attempt(&network, &args, &mut handshake, &mut client_state);
+    }
+}
+
+pub fn invite_button_system(
+    buttons: Query<&Interaction, (Changed<Interaction>, With<InviteButton>)>,
+    args: Res<Args>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if !buttons
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        return;
+    }
+    let Some(matchmaker) = &args.matchmaker else {
+        return;
+    };
+    let server = client_state.server_target.clone();
+    match fetch_invite(matchmaker, &server) {
+        Ok(code) => client_state.push_log(format!(
+            "[Info]: Invite code {} for {}: entering it in the code field joins this server",
+            code, server
+        )),
+        Err(reason) => {
+            client_state.push_log(format!("[Error]: Matchmaker {}: {}", matchmaker, reason))
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Bug-report bundles, with `--features bug-report`: what the client looked
+//! like when something went wrong, zipped up for an issue.
+//!
+//! With `--bug-reports <dir>`, a network error or a replication desync (a
+//! resource that lost updates and needs a `ReplicationNack`) writes
+//! `bug-report-<unix secs>.zip` to `dir`, at most one every
+//! [`COOLDOWN_SECS`] so a burst of errors makes one bundle. In a window
+//! build F9 writes one on demand. A bundle holds:
+//!
+//! - `screenshot.png`: the window, taken at the end of that frame (not in
+//!   the `thin` build, which has none)
+//! - `state.txt`: why it was written, the connection, traffic, RTT and
+//!   loss, replication streams and the session so far
+//! - `log.txt`: the last [`LOG_LINES`] log entries, more than the panel
+//!   shows
+//!
+//! The state is read when the report is triggered; the zip is written on a
+//! thread of its own (after the screenshot comes back, when there is one),
+//! and the log says where it went. `--password` never goes in.
+
+use bevy::prelude::*;
+#[cfg(not(feature = "thin"))]
+use bevy::render::view::screenshot::ScreenshotManager;
+#[cfg(not(feature = "thin"))]
+use bevy::window::PrimaryWindow;
+use common::error::NetError;
+use common::replicate::{ReplicationNack, ReplicationStreams};
+use crossbeam::channel::{Receiver, Sender, unbounded};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+
+use crate::handshake::Handshake;
+use crate::session::Session;
+use crate::timeline::PacketTimeline;
+use crate::{ClientState, NetworkState};
+
+/// Log entries kept for a report
+pub const LOG_LINES: usize = 50;
+/// Seconds after a report before an error can trigger another
+pub const COOLDOWN_SECS: f32 = 30.0;
+
+#[derive(Resource)]
+pub struct BugReports {
+    dir: PathBuf,
+    /// When the last report was triggered
+    last_at: Option<f32>,
+    written: Sender<Result<PathBuf, String>>,
+    results: Receiver<Result<PathBuf, String>>,
+}
+
+impl BugReports {
+    pub fn new(dir: PathBuf) -> Self {
+        let (written, results) = unbounded();
+        Self {
+            dir,
+            last_at: None,
+            written,
+            results,
+        }
+    }
+}
+
+/// What goes in the zip besides the screenshot.
+struct Bundle {
+    path: PathBuf,
+    state: String,
+    log: String,
+}
+
+impl Bundle {
+    fn write(&self, screenshot: Option<&[u8]>) -> zip::result::ZipResult<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut zip = zip::ZipWriter::new(File::create(&self.path)?);
+        if let Some(png) = screenshot {
+            // Already compressed
+            zip.start_file(
+                "screenshot.png",
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored),
+            )?;
+            zip.write_all(png)?;
+        }
+        zip.start_file("state.txt", SimpleFileOptions::default())?;
+        zip.write_all(self.state.as_bytes())?;
+        zip.write_all(b"\n")?;
+        zip.start_file("log.txt", SimpleFileOptions::default())?;
+        zip.write_all(self.log.as_bytes())?;
+        zip.write_all(b"\n")?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Writes the zip and reports back to `written`. Blocks; run it on a
+    /// thread.
+    fn finish(self, screenshot: Option<Vec<u8>>, written: Sender<Result<PathBuf, String>>) {
+        let result = match self.write(screenshot.as_deref()) {
+            Ok(()) => Ok(self.path),
+            Err(e) => Err(format!("{}: {}", self.path.display(), e)),
+        };
+        let _ = written.send(result);
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn bundle_path(dir: &Path) -> PathBuf {
+    dir.join(format!("bug-report-{}.zip", unix_secs()))
+}
+
+#[cfg(not(feature = "thin"))]
+fn encode_png(image: Image) -> Result<Vec<u8>, String> {
+    let rgb = image
+        .try_into_dynamic()
+        .map_err(|e| e.to_string())?
+        .to_rgb8();
+    let mut png = std::io::Cursor::new(Vec::new());
+    rgb.write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png.into_inner())
+}
+
+fn describe_state(
+    trigger: &str,
+    now: f32,
+    network: &NetworkState,
+    client_state: &ClientState,
+    handshake: &Handshake,
+    timeline: &PacketTimeline,
+    streams: &ReplicationStreams,
+    session: &Session,
+) -> String {
+    let counters = &network.counters;
+    let mut lines = vec![
+        format!("trigger: {}", trigger),
+        format!("version: {}", env!("CARGO_PKG_VERSION")),
+        format!("os: {} {}", std::env::consts::OS, std::env::consts::ARCH),
+        format!("unix_time: {}", unix_secs()),
+        format!("uptime_secs: {:.1}", now),
+        String::new(),
+        format!("server_target: {}", client_state.server_target),
+        format!("server_addr: {}", client_state.server_addr),
+        format!(
+            "data_addr: {}",
+            client_state.data_addr.as_deref().unwrap_or("none")
+        ),
+        format!("transport: {}", client_state.transport.label()),
+        format!("handshake: {:?}", handshake.stage),
+        format!("ever_connected: {}", client_state.has_connected),
+        format!("silent_secs: {:.1}", now - handshake.last_heard),
+        format!(
+            "resume_token: {}",
+            if client_state.resume.is_some() {
+                "held"
+            } else {
+                "none"
+            }
+        ),
+        String::new(),
+        format!(
+            "packets: {} sent, {} received, {} truncated",
+            counters.packets_sent.load(Ordering::Relaxed),
+            counters.packets_received.load(Ordering::Relaxed),
+            counters.truncated_packets.load(Ordering::Relaxed)
+        ),
+        format!(
+            "bytes: {} sent, {} received",
+            counters.bytes_sent.load(Ordering::Relaxed),
+            counters.bytes_received.load(Ordering::Relaxed)
+        ),
+        format!(
+            "rtt_ms: {}",
+            timeline
+                .smoothed_rtt()
+                .map_or("none".to_string(), |rtt| format!("{:.1}", rtt * 1000.0))
+        ),
+        format!(
+            "loss: {}",
+            timeline
+                .loss()
+                .map_or("none".to_string(), |loss| format!("{:.2}", loss))
+        ),
+        format!("pipeline: {}", network.pipeline.names().join(", ")),
+    ];
+    let awaiting: Vec<&str> = streams.awaiting().collect();
+    lines.push(format!(
+        "replication: {} dropped, {} recovered, waiting for {}",
+        streams.dropped,
+        streams.recovered,
+        if awaiting.is_empty() {
+            "nothing".to_string()
+        } else {
+            awaiting.join(", ")
+        }
+    ));
+    lines.push(String::new());
+    match session.current() {
+        Some(stats) => lines.extend(stats.summary(now, "still open")),
+        None => lines.push("no session open".to_string()),
+    }
+    lines.join("\n")
+}
+
+/// Triggers a report on a network error, a desync or F9, and logs the
+/// bundles written.
+pub fn capture_bug_reports(
+    time: Res<Time>,
+    #[cfg(not(feature = "thin"))] keys: Res<ButtonInput<KeyCode>>,
+    mut errors: EventReader<NetError>,
+    mut nacks: EventReader<ReplicationNack>,
+    network: Res<NetworkState>,
+    handshake: Res<Handshake>,
+    timeline: Res<PacketTimeline>,
+    streams: Res<ReplicationStreams>,
+    session: Res<Session>,
+    #[cfg(not(feature = "thin"))] windows: Query<Entity, With<PrimaryWindow>>,
+    #[cfg(not(feature = "thin"))] mut screenshots: ResMut<ScreenshotManager>,
+    mut reports: ResMut<BugReports>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for result in reports.results.try_iter() {
+        client_state.push_log(match result {
+            Ok(path) => format!("[Info]: Wrote bug report {}", path.display()),
+            Err(e) => format!("[Error]: Could not write bug report {}", e),
+        });
+    }
+
+    let now = time.elapsed_seconds();
+    let error = errors
+        .read()
+        .last()
+        .map(|error| format!("network error: {}", error));
+    let desync = nacks
+        .read()
+        .last()
+        .map(|nack| format!("replication desync: {} lost updates", nack.name));
+    #[cfg(not(feature = "thin"))]
+    let requested = keys
+        .just_pressed(KeyCode::F9)
+        .then(|| "requested with F9".to_string());
+    #[cfg(feature = "thin")]
+    let requested = None;
+    let cooling = reports
+        .last_at
+        .is_some_and(|last_at| now - last_at < COOLDOWN_SECS);
+    let Some(trigger) = requested.or(error.or(desync).filter(|_| !cooling)) else {
+        return;
+    };
+    reports.last_at = Some(now);
+
+    let bundle = Bundle {
+        path: bundle_path(&reports.dir),
+        state: describe_state(
+            &trigger,
+            now,
+            &network,
+            &client_state,
+            &handshake,
+            &timeline,
+            &streams,
+            &session,
+        ),
+        log: client_state
+            .log_tail
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    let written = reports.written.clone();
+    #[cfg(not(feature = "thin"))]
+    if let Ok(window) = windows.get_single() {
+        // The screenshot comes back from the render world a frame or so later
+        let (sender, taken) = crossbeam::channel::bounded(1);
+        if screenshots
+            .take_screenshot(window, move |image| {
+                let _ = sender.send(encode_png(image));
+            })
+            .is_ok()
+        {
+            thread::spawn(move || {
+                let screenshot = match taken.recv() {
+                    Ok(Ok(png)) => Some(png),
+                    Ok(Err(e)) => {
+                        warn!("Bug report screenshot failed: {}", e);
+                        None
+                    }
+                    Err(_) => None,
+                };
+                bundle.finish(screenshot, written);
+            });
+            return;
+        }
+    }
+    thread::spawn(move || bundle.finish(None, written));
+}
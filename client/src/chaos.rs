@@ -0,0 +1,205 @@
+//! Chaos mode: the CHAOS button (or `--chaos`) keeps perturbing the
+//! connection so reconnects, duplicate handling and error reporting can be
+//! watched under fire.
+//!
+//! Every one to four seconds one of these happens at random:
+//!
+//! - the connection is dropped and the handshake starts over, as if the
+//!   socket had gone away
+//! - a datagram of random bytes arrives as if the server had sent it
+//! - every send goes out twice for a few seconds
+//! - sends are held back for a few seconds, then released all at once
+//!
+//! Every perturbation is logged with a `[Chaos]` prefix.
+
+use bevy::prelude::*;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use common::shared_rng::SharedRng;
+#[cfg(not(feature = "thin"))]
+use common::ui::widgets::{ButtonFlash, UiTheme, spawn_button};
+
+use crate::handshake::{Handshake, start_attempt};
+use crate::latency::Arrival;
+use crate::send_pacer::Queued;
+use crate::session::Session;
+use crate::{Args, ClientState, NetworkState};
+
+const GARBAGE_MAX_BYTES: usize = 64;
+
+/// Send-side switches, checked by `NetworkState::send_to`.
+#[derive(Default)]
+pub struct ChaosSwitches {
+    duplicate: AtomicBool,
+    stall: AtomicBool,
+    /// Sends held back while stalled, in order
+    held: Mutex<Vec<Queued>>,
+}
+
+impl ChaosSwitches {
+    /// How many copies of this datagram to send now: 0 while stalled (it is
+    /// held for later), 2 while duplicating, otherwise 1.
+    pub fn intercept(&self, send: &Queued) -> usize {
+        if self.stall.load(Ordering::Relaxed) {
+            self.held.lock().unwrap().push(send.clone());
+            0
+        } else if self.duplicate.load(Ordering::Relaxed) {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn clear(&self) -> Vec<Queued> {
+        self.duplicate.store(false, Ordering::Relaxed);
+        self.stall.store(false, Ordering::Relaxed);
+        std::mem::take(&mut *self.held.lock().unwrap())
+    }
+}
+
+#[derive(Resource)]
+pub struct Chaos {
+    pub enabled: bool,
+    /// When the next perturbation starts
+    next_event: f32,
+    /// When the current duplicate or stall period ends
+    period_ends: Option<f32>,
+    /// Seeded from the clock, not the server's seed: each client's chaos
+    /// is its own
+    rng: SharedRng,
+}
+
+impl Chaos {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            next_event: 0.0,
+            period_ends: None,
+            rng: SharedRng::new(SharedRng::random_seed()),
+        }
+    }
+
+    /// Uniform in `min..max`
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + self.rng.unit() * (max - min)
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct ChaosButton;
+
+#[cfg(not(feature = "thin"))]
+/// The CHAOS button sits to the left of PING.
+pub fn setup_chaos_button(mut commands: Commands, theme: Res<UiTheme>) {
+    let width = 120.0;
+    let button = spawn_button(&mut commands, &theme, "CHAOS", width, ChaosButton);
+    commands.entity(button).insert(Style {
+        width: Val::Px(width),
+        height: Val::Px(theme.button_height),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(theme.button_margin),
+        right: Val::Px(theme.button_margin * 2.0 + 120.0),
+        ..default()
+    });
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn chaos_button_system(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<ChaosButton>)>,
+    mut flash: Query<&mut ButtonFlash, With<ChaosButton>>,
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut chaos: ResMut<Chaos>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for interaction in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        chaos.enabled = !chaos.enabled;
+        if chaos.enabled {
+            chaos.next_event = time.elapsed_seconds();
+            client_state.push_log("[Chaos]: On".to_string());
+        } else {
+            end_period(&mut chaos, &network, &mut client_state);
+            client_state.push_log("[Chaos]: Off".to_string());
+        }
+        for mut flash in flash.iter_mut() {
+            flash.trigger();
+        }
+    }
+}
+
+/// Lifts a duplicate or stall period and sends whatever a stall held back.
+fn end_period(chaos: &mut Chaos, network: &NetworkState, client_state: &mut ClientState) {
+    if chaos.period_ends.take().is_none() {
+        return;
+    }
+    let held = network.chaos.clear();
+    if !held.is_empty() {
+        client_state.push_log(format!("[Chaos]: Releasing {} held send(s)", held.len()));
+    }
+    // Already through the middleware pipeline when they were held, and
+    // real-time ones held past their TTL are dropped
+    for send in held {
+        network.send_queued(send);
+    }
+}
+
+pub fn run_chaos(
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut chaos: ResMut<Chaos>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    if !chaos.enabled {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    if chaos.period_ends.is_some_and(|ends| now >= ends) {
+        end_period(&mut chaos, &network, &mut client_state);
+    }
+    if now < chaos.next_event || chaos.period_ends.is_some() {
+        return;
+    }
+    chaos.next_event = now + chaos.range(1.0, 4.0);
+
+    match chaos.rng.below(4) {
+        0 => {
+            client_state.push_log("[Chaos]: Dropping the connection".to_string());
+            session.end(now, "chaos: connection dropped", &mut client_state);
+            start_attempt(&network, &args, &mut handshake, &mut client_state);
+        }
+        1 => {
+            let len = 1 + chaos.rng.below(GARBAGE_MAX_BYTES);
+            let bytes: Vec<u8> = (0..len).map(|_| chaos.rng.next_u64() as u8).collect();
+            client_state.push_log(format!("[Chaos]: Injecting {} garbage bytes", len));
+            let garbage = String::from_utf8_lossy(&bytes).to_string();
+            network.received_messages.lock().unwrap().push_back((
+                garbage,
+                client_state.server_addr.clone(),
+                Arrival::new(Instant::now()),
+            ));
+        }
+        2 => {
+            let secs = chaos.range(1.0, 3.0);
+            client_state.push_log(format!("[Chaos]: Duplicating sends for {:.1}s", secs));
+            network.chaos.duplicate.store(true, Ordering::Relaxed);
+            chaos.period_ends = Some(now + secs);
+        }
+        _ => {
+            let secs = chaos.range(0.5, 3.0);
+            client_state.push_log(format!("[Chaos]: Stalling sends for {:.1}s", secs));
+            network.chaos.stall.store(true, Ordering::Relaxed);
+            chaos.period_ends = Some(now + secs);
+        }
+    }
+}
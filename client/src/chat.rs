@@ -0,0 +1,78 @@
+//! Chat field at the bottom of the window. Lines go to the server as
+//! `Chat <text>`; what comes back is the server's moderated relay, shown in
+//! the log like any other message. A server that sets `chat_cooldown_secs`
+//! (see `settings`) holds us to that gap between lines.
+
+use bevy::prelude::*;
+use common::ui::text_input::{TextInput, TextInputSubmitted, spawn_text_input};
+
+use crate::handshake::{Handshake, HandshakeStage};
+use crate::session::Session;
+use crate::settings::NetworkSettings;
+use crate::{ClientState, NetworkState};
+
+#[derive(Component)]
+pub struct ChatInput;
+
+pub fn setup_chat_input(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(35.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            spawn_text_input(
+                parent,
+                TextInput::new("Say something").clear_on_submit(),
+                Val::Px(420.0),
+                ChatInput,
+            );
+        });
+}
+
+pub fn handle_chat_submit(
+    mut submitted: EventReader<TextInputSubmitted>,
+    chat_input: Query<(), With<ChatInput>>,
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    handshake: Res<Handshake>,
+    settings: Res<NetworkSettings>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+    mut last_sent: Local<Option<f32>>,
+) {
+    for event in submitted.read() {
+        if chat_input.get(event.entity).is_err() {
+            continue;
+        }
+        let text = event.value.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if handshake.stage != HandshakeStage::Connected {
+            client_state.push_log("[Error]: Not connected, chat not sent".to_string());
+            continue;
+        }
+        let now = time.elapsed_seconds();
+        if let Some(wait) = last_sent
+            .map(|at| at + settings.chat_cooldown_secs - now)
+            .filter(|wait| *wait > 0.0)
+        {
+            client_state.push_log(format!(
+                "[Error]: Chat cooldown, wait {:.1}s before the next line",
+                wait
+            ));
+            continue;
+        }
+        *last_sent = Some(now);
+        let message = format!("Chat {}", text);
+        network.send_to(message.as_bytes(), client_state.gameplay_addr());
+        session.record_sent("Chat", message.len());
+        client_state.push_log(format!("[Tx]: {}", message));
+    }
+}
@@ -0,0 +1,413 @@
+//! Connect dialog: shown in the middle of the window whenever we are not
+//! connected, prefilled with `--server`.
+//!
+//! The address is checked before anything is sent, the handshake runs with a
+//! visible countdown, and a failed attempt says why: the address is
+//! malformed, the host can't be reached, the server didn't reply, or it
+//! speaks a different protocol version. After a connection ends, the dialog
+//! says why it ended. RETRY connects to whatever is in the
+//! field (Enter does the same); EDIT stops the automatic retries so the
+//! address can be changed. Under the status, each address in the field
+//! says what it is (see `discovery`).
+
+use bevy::prelude::*;
+use std::net::IpAddr;
+
+use common::disconnect::{DisconnectReason, Disconnected};
+use common::error::NetError;
+use common::ui::text_input::{TextInput, TextInputSubmitted, spawn_text_input};
+use common::ui::widgets::{ButtonFlash, ThemedButton, UiTheme};
+
+use crate::discovery::ServerListText;
+use crate::gui::HeaderText;
+use crate::handshake::{Handshake, HandshakeStage, start_attempt};
+use crate::{Args, ClientState, NetworkState};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConnectFailure {
+    InvalidAddress(String),
+    /// The name didn't resolve, or the OS refused to send to it
+    Unreachable(String),
+    NoReply,
+    VersionMismatch {
+        ours: u32,
+        theirs: u32,
+    },
+    /// The server wants a connect ticket we don't have
+    TicketRefused(String),
+    /// The server encodes its channels differently; see `common::codec`
+    CodecMismatch {
+        ours: String,
+        theirs: String,
+    },
+    /// A connection that had been established ended
+    Disconnected(DisconnectReason),
+}
+
+impl ConnectFailure {
+    fn describe(&self) -> String {
+        match self {
+            ConnectFailure::InvalidAddress(reason) => format!("Invalid address: {}", reason),
+            ConnectFailure::Unreachable(reason) => format!("Host unreachable: {}", reason),
+            ConnectFailure::NoReply => "No reply from the server".to_string(),
+            ConnectFailure::VersionMismatch { ours, theirs } => format!(
+                "Version mismatch: the server speaks protocol {}, this client speaks {}",
+                theirs, ours
+            ),
+            ConnectFailure::TicketRefused(reason) => format!("Connect ticket refused: {}", reason),
+            ConnectFailure::CodecMismatch { ours, theirs } => format!(
+                "Codec mismatch: the server uses {}, this client uses {} (see --codec)",
+                theirs, ours
+            ),
+            ConnectFailure::Disconnected(reason) => format!("Disconnected: {}", reason),
+        }
+    }
+}
+
+/// Why the last attempt failed; cleared once connected.
+#[derive(Resource, Default)]
+pub struct ConnectDialog {
+    pub failure: Option<ConnectFailure>,
+}
+
+/// Checks `host:port` syntax without resolving anything, for every address
+/// of a comma-separated list of fallbacks. IPv6 addresses need brackets,
+/// e.g. `[::1]:12345`.
+pub fn validate_address(value: &str) -> Result<(), String> {
+    let endpoints: Vec<&str> = value.split(',').map(str::trim).collect();
+    for endpoint in &endpoints {
+        match validate_endpoint(endpoint) {
+            Err(reason) if endpoints.len() > 1 => return Err(format!("{}: {}", endpoint, reason)),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+fn validate_endpoint(value: &str) -> Result<(), String> {
+    let (host, port) = value
+        .rsplit_once(':')
+        .ok_or_else(|| "expected host:port".to_string())?;
+    let bracketed = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'));
+    let host = bracketed.unwrap_or(host);
+    if host.is_empty() {
+        return Err("missing host".to_string());
+    }
+    if host.contains(':') && bracketed.is_none() {
+        return Err(format!(
+            "IPv6 addresses need brackets, e.g. [{}]:{}",
+            host, port
+        ));
+    }
+    let hostname = host
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if host.parse::<IpAddr>().is_err() && !hostname {
+        return Err(format!("'{}' is not a hostname or IP address", host));
+    }
+    match port.parse::<u16>() {
+        Ok(0) => Err("port 0 can't be connected to".to_string()),
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("'{}' is not a port number", port)),
+    }
+}
+
+#[derive(Component)]
+pub struct ConnectPanel;
+
+#[derive(Component)]
+pub struct ConnectInput;
+
+#[derive(Component)]
+pub struct ConnectStatus;
+
+#[derive(Component)]
+pub struct RetryButton;
+
+#[derive(Component)]
+pub struct EditButton;
+
+fn dialog_button(parent: &mut ChildBuilder, theme: &UiTheme, label: &str, marker: impl Bundle) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(100.0),
+                    height: Val::Px(32.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: theme.button_color.into(),
+                ..default()
+            },
+            ThemedButton,
+            ButtonFlash::default(),
+            marker,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font_size: 18.0,
+                    color: theme.text_color,
+                    ..default()
+                },
+            ));
+        });
+}
+
+pub fn setup_connect_dialog(mut commands: Commands, args: Res<Args>, theme: Res<UiTheme>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            ConnectPanel,
+        ))
+        .with_children(|screen| {
+            screen
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(10.0),
+                        padding: UiRect::all(Val::Px(16.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+                    ..default()
+                })
+                .with_children(|panel| {
+                    panel.spawn(TextBundle::from_section(
+                        "Connect to server",
+                        TextStyle {
+                            font_size: theme.header_font_size,
+                            color: theme.header_color,
+                            ..default()
+                        },
+                    ));
+                    spawn_text_input(
+                        panel,
+                        TextInput::new("host:port").with_value(args.server.clone()),
+                        Val::Px(320.0),
+                        ConnectInput,
+                    );
+                    panel.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font_size: theme.log_font_size,
+                                color: theme.text_color,
+                                ..default()
+                            },
+                        ),
+                        ConnectStatus,
+                    ));
+                    panel.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font_size: theme.log_font_size,
+                                color: theme.text_color,
+                                ..default()
+                            },
+                        ),
+                        ServerListText,
+                    ));
+                    panel
+                        .spawn(NodeBundle {
+                            style: Style {
+                                column_gap: Val::Px(10.0),
+                                ..default()
+                            },
+                            ..default()
+                        })
+                        .with_children(|row| {
+                            dialog_button(row, &theme, "RETRY", RetryButton);
+                            dialog_button(row, &theme, "EDIT", EditButton);
+                        });
+                });
+        });
+}
+
+/// Turns the errors of a connection attempt, or the end of a connection,
+/// into the reason shown.
+pub fn record_connect_failures(
+    mut errors: EventReader<NetError>,
+    mut disconnects: EventReader<Disconnected>,
+    handshake: Res<Handshake>,
+    client_state: Res<ClientState>,
+    mut dialog: ResMut<ConnectDialog>,
+) {
+    for error in errors.read() {
+        let failure = match error {
+            NetError::Resolve { source, .. } | NetError::Connect { source, .. } => {
+                ConnectFailure::Unreachable(source.to_string())
+            }
+            NetError::Send { addr, source }
+                if *addr == client_state.server_addr
+                    && handshake.stage != HandshakeStage::Connected =>
+            {
+                ConnectFailure::Unreachable(source.to_string())
+            }
+            // A timeout after a send error is a symptom, not the reason
+            NetError::HandshakeTimeout { .. }
+                if !matches!(dialog.failure, Some(ConnectFailure::Unreachable(_))) =>
+            {
+                ConnectFailure::NoReply
+            }
+            NetError::VersionMismatch { ours, theirs, .. } => ConnectFailure::VersionMismatch {
+                ours: *ours,
+                theirs: *theirs,
+            },
+            NetError::TicketRefused { reason, .. } => ConnectFailure::TicketRefused(reason.clone()),
+            NetError::CodecMismatch { ours, theirs, .. } => ConnectFailure::CodecMismatch {
+                ours: ours.clone(),
+                theirs: theirs.clone(),
+            },
+            _ => continue,
+        };
+        dialog.failure = Some(failure);
+    }
+    if let Some(disconnect) = disconnects.read().last() {
+        dialog.failure = Some(ConnectFailure::Disconnected(disconnect.reason));
+    }
+}
+
+/// Enter in the field or RETRY connects to the typed address; EDIT stops
+/// retrying and focuses the field.
+pub fn connect_dialog_actions(
+    mut submitted: EventReader<TextInputSubmitted>,
+    retry: Query<&Interaction, (Changed<Interaction>, With<RetryButton>)>,
+    edit: Query<&Interaction, (Changed<Interaction>, With<EditButton>)>,
+    mut inputs: Query<(Entity, &mut TextInput, Has<ConnectInput>)>,
+    mut header: Query<&mut Text, With<HeaderText>>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut dialog: ResMut<ConnectDialog>,
+) {
+    let Some(input) = inputs.iter().find(|(_, _, is_dialog)| *is_dialog) else {
+        return;
+    };
+    let (input_entity, target) = (input.0, input.1.value.trim().to_string());
+    let submitted = submitted
+        .read()
+        .filter(|event| event.entity == input_entity)
+        .count()
+        > 0;
+    let retry = retry
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+
+    if edit
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        handshake.stop();
+        for (_, mut input, is_dialog) in inputs.iter_mut() {
+            input.focused = is_dialog;
+        }
+        return;
+    }
+    if !submitted && !retry {
+        return;
+    }
+
+    if let Err(reason) = validate_address(&target) {
+        handshake.stop();
+        dialog.failure = Some(ConnectFailure::InvalidAddress(reason));
+        return;
+    }
+    dialog.failure = None;
+    handshake.reset_attempts();
+    client_state.set_endpoints(&target);
+    for mut text in header.iter_mut() {
+        text.sections[0].value = format!(
+            "Client connecting to {}{}",
+            client_state.server_target,
+            client_state.endpoint_position()
+        );
+    }
+    start_attempt(&network, &args, &mut handshake, &mut client_state);
+}
+
+pub fn update_connect_dialog(
+    handshake: Res<Handshake>,
+    client_state: Res<ClientState>,
+    mut dialog: ResMut<ConnectDialog>,
+    mut panels: Query<&mut Visibility, With<ConnectPanel>>,
+    mut status: Query<&mut Text, With<ConnectStatus>>,
+) {
+    let connected = handshake.stage == HandshakeStage::Connected;
+    if connected && dialog.failure.is_some() {
+        dialog.failure = None;
+    }
+    for mut visibility in panels.iter_mut() {
+        let wanted = if connected {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+    if connected {
+        return;
+    }
+
+    let reason = dialog.failure.as_ref().map(ConnectFailure::describe);
+    let remaining = handshake.remaining_secs();
+    let line = match (handshake.stage, reason) {
+        (HandshakeStage::Resolving, Some(reason)) => format!(
+            "{}. Resolving {}... {:.1}s",
+            reason, client_state.server_target, remaining
+        ),
+        (HandshakeStage::Resolving, None) => format!(
+            "Resolving {}... {:.1}s",
+            client_state.server_target, remaining
+        ),
+        (HandshakeStage::Waiting, Some(reason)) => format!(
+            "{}. Reconnecting to {} (attempt {})... {:.1}s",
+            reason,
+            client_state.server_target,
+            handshake.attempt_label(),
+            remaining
+        ),
+        (HandshakeStage::Waiting, None) => format!(
+            "Connecting to {} (attempt {})... {:.1}s",
+            client_state.server_target,
+            handshake.attempt_label(),
+            remaining
+        ),
+        (HandshakeStage::Scanning, _) => format!(
+            "Probing ports on {}... {:.1}s",
+            client_state.server_target, remaining
+        ),
+        (HandshakeStage::Failed, Some(reason)) => {
+            format!("{}. Retrying in {:.1}s", reason, remaining)
+        }
+        (HandshakeStage::Failed, None) => format!("Retrying in {:.1}s", remaining),
+        (HandshakeStage::Stopped, Some(reason)) => format!("{}. Edit the address or retry", reason),
+        (HandshakeStage::Stopped, None) => "Edit the address and press Enter".to_string(),
+        (HandshakeStage::Connected, _) => return,
+    };
+    for mut text in status.iter_mut() {
+        if text.sections[0].value != line {
+            text.sections[0].value = line.clone();
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! The `thin` build's stand-in for the window, for tools and CI.
+//!
+//! Every log line is printed to stdout instead of drawn (see
+//! `ClientState::push_log`), and every line typed on stdin is sent to the
+//! server's gameplay address as a message, e.g. `Ping` or `Chat hello`,
+//! picked up at the next input sample.
+//! `/quit` exits, saying Disconnect first like closing the window does.
+//! `--script` works the same as in the full build.
+
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::diagnostic::DiagnosticsPlugin;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::ClientState;
+use crate::outbound::OutboundQueue;
+use crate::pacing::input_tick;
+
+/// How often the app updates without a window to pace it
+const TICK_HZ: f64 = 60.0;
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / TICK_HZ,
+            ))),
+            LogPlugin::default(),
+            DiagnosticsPlugin,
+        ))
+        .insert_resource(ConsoleInput::start())
+        .add_systems(Update, send_console_lines.run_if(input_tick));
+    }
+}
+
+/// Lines read from stdin, waiting for the next frame
+#[derive(Resource)]
+struct ConsoleInput(Arc<Mutex<VecDeque<String>>>);
+
+impl ConsoleInput {
+    fn start() -> Self {
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        let inbox = lines.clone();
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                inbox.lock().unwrap().push_back(line);
+            }
+        });
+        Self(lines)
+    }
+}
+
+fn send_console_lines(
+    time: Res<Time>,
+    input: Res<ConsoleInput>,
+    client_state: Res<ClientState>,
+    mut outbound: ResMut<OutboundQueue>,
+    mut exits: EventWriter<AppExit>,
+) {
+    let lines: Vec<String> = input.0.lock().unwrap().drain(..).collect();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "/quit" {
+            exits.send(AppExit);
+            return;
+        }
+        // The outbound queue logs the send, like a button press
+        outbound.push(line, client_state.gameplay_addr(), time.elapsed_seconds());
+    }
+}
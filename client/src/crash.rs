@@ -0,0 +1,194 @@
+//! Crash reports, with `--crash-report <path>`: what went wrong last run,
+//! sent to the server on the next.
+//!
+//! [`CrashReporter::install`] runs before the app is built. It picks up a
+//! report left at `<path>` by the last run, then writes `<path>.running`
+//! and hooks panics. A panic writes a one-line report to `<path>`; a clean
+//! exit removes `<path>.running`. Finding the marker still there with no
+//! report means the last run ended some other way (killed, a crash in
+//! native code, the power going out), which is reported as
+//! `kind=abnormal_exit`. Running two clients with the same path mixes up
+//! their markers.
+//!
+//! After every Welcome a pending report goes out on the reliable channel
+//! (see `account`), until the server answers `CrashReportOk` or
+//! `CrashReportRefused`; either way the file is removed then. The server
+//! only keeps reports when started with `--crash-reports`. A report holds
+//! the kind, version, OS, uptime and, for a panic, where and why; nothing
+//! from the session itself.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use common::handlers::{NetReceived, Welcome};
+use std::fs;
+use std::panic::{self, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::ClientState;
+use crate::account::AccountLink;
+
+/// Longest panic message sent, in characters; a report must fit one datagram
+const MAX_MESSAGE: usize = 300;
+
+#[derive(Resource)]
+pub struct CrashReporter {
+    path: PathBuf,
+    /// The last run's report, until the server has answered for it
+    pending: Option<String>,
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn marker_path(path: &Path) -> PathBuf {
+    let mut marker = path.as_os_str().to_owned();
+    marker.push(".running");
+    PathBuf::from(marker)
+}
+
+/// The fields every report starts with.
+fn header(kind: &str) -> String {
+    format!(
+        "CrashReport kind={} version={} os={} arch={}",
+        kind,
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+fn panic_report(info: &PanicHookInfo, started: Instant) -> String {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    // Framing is by line and fields by whitespace
+    let message: String = message
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_MESSAGE)
+        .collect();
+    let mut report = format!(
+        "{} uptime_secs={:.1}",
+        header("panic"),
+        started.elapsed().as_secs_f32()
+    );
+    if let Some(location) = info.location() {
+        report.push_str(&format!(
+            " location={}:{}",
+            location.file(),
+            location.line()
+        ));
+    }
+    if !message.is_empty() {
+        report.push_str(&format!(" message={}", message));
+    }
+    report
+}
+
+impl CrashReporter {
+    /// Takes up the last run's report and starts watching this one.
+    pub fn install(path: &Path) -> Self {
+        let marker = marker_path(path);
+        let mut pending = fs::read_to_string(path)
+            .ok()
+            .map(|report| report.trim().to_string())
+            .filter(|report| report.starts_with("CrashReport "));
+        if pending.is_none()
+            && let Ok(started) = fs::read_to_string(&marker)
+        {
+            let report = format!("{} started={}", header("abnormal_exit"), started.trim());
+            // Kept like a panic's, in case this run ends before it's sent
+            if let Err(e) = fs::write(path, &report) {
+                eprintln!("Warning: could not write {}: {}", path.display(), e);
+            }
+            pending = Some(report);
+        }
+        if let Err(e) = fs::write(&marker, unix_secs().to_string()) {
+            eprintln!("Warning: could not write {}: {}", marker.display(), e);
+        }
+
+        let started = Instant::now();
+        let report_path = path.to_path_buf();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = fs::write(&report_path, panic_report(info, started));
+            previous(info);
+        }));
+        Self {
+            path: path.to_path_buf(),
+            pending,
+        }
+    }
+
+    /// Handles the server's answer to a report: the line for the log.
+    pub fn answered(&mut self, reply: &str) -> String {
+        let line = match reply.strip_prefix("CrashReportOk id=") {
+            Some(id) => format!("[Info]: Sent the crash report from the last run (#{})", id),
+            None => {
+                let reason = reply
+                    .strip_prefix("CrashReportRefused reason=")
+                    .unwrap_or("unknown");
+                format!(
+                    "[Info]: The server didn't take the crash report from the last run ({}); discarded it",
+                    reason
+                )
+            }
+        };
+        // Only an unanswered report is worth sending again
+        self.pending = None;
+        if let Err(e) = fs::remove_file(&self.path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Could not remove {}: {}", self.path.display(), e);
+        }
+        line
+    }
+}
+
+/// Queues the pending report on the reliable channel, which
+/// `log_in_after_welcome` has just started afresh.
+pub fn send_crash_report(
+    mut welcomes: EventReader<NetReceived<Welcome>>,
+    reporter: Option<Res<CrashReporter>>,
+    mut link: ResMut<AccountLink>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if welcomes.read().count() == 0 {
+        return;
+    }
+    let Some(report) = reporter
+        .as_ref()
+        .and_then(|reporter| reporter.pending.clone())
+    else {
+        return;
+    };
+    let kind = report
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("kind="))
+        .unwrap_or("unknown")
+        .replace('_', " ");
+    client_state.push_log(format!(
+        "[Info]: Sending a crash report on the last run ({})",
+        kind
+    ));
+    link.push(report);
+}
+
+/// A clean exit is no crash: drops the marker `install` wrote.
+pub fn finish_crash_watch(mut exits: EventReader<AppExit>, reporter: Option<Res<CrashReporter>>) {
+    if exits.read().next().is_none() {
+        return;
+    }
+    if let Some(reporter) = reporter {
+        let _ = fs::remove_file(marker_path(&reporter.path));
+    }
+}
@@ -0,0 +1,102 @@
+//! The server's soft deprecation of our protocol version.
+//!
+//! A server that still supports an older Hello version welcomes it anyway,
+//! and names the features that client goes without in the Welcome's
+//! `deprecated=` (see `common::protocol::FEATURES`). We log the notice, and
+//! the window shows it in a banner along the top until a Welcome without
+//! one. `--protocol-version 1` claims the older version, to try it out.
+//!
+//! A Welcome's `without=` names the protocol subsets (see
+//! `common::protocol::SUBSETS`) the session goes without because one side
+//! was built without them; that is logged too.
+
+use bevy::prelude::*;
+use common::handlers::{NetReceived, Welcome};
+use common::protocol;
+
+use crate::ClientState;
+
+#[derive(Resource, Default)]
+pub struct Deprecation {
+    /// `Please update: ... unavailable`, from the last Welcome
+    pub notice: Option<String>,
+}
+
+pub fn note_deprecation(
+    mut welcomes: EventReader<NetReceived<Welcome>>,
+    mut deprecation: ResMut<Deprecation>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for welcome in welcomes.read() {
+        let features = &welcome.message.deprecated;
+        deprecation.notice = (!features.is_empty()).then(|| {
+            let described: Vec<&str> = features
+                .iter()
+                .map(|feature| protocol::describe_feature(feature))
+                .collect();
+            format!("Please update: {} unavailable", described.join(", "))
+        });
+        if let Some(notice) = &deprecation.notice {
+            client_state.push_log(format!("[Warn]: {}", notice));
+        }
+        // Not something an update fixes, so no banner
+        if !welcome.message.without.is_empty() {
+            client_state.push_log(format!(
+                "[Warn]: Connected without {}: this build or the server's leaves it out",
+                welcome.message.without.join(", ")
+            ));
+        }
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct DeprecationBanner;
+
+#[cfg(not(feature = "thin"))]
+pub fn setup_deprecation_banner(mut commands: Commands) {
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(0.0),
+                left: Val::Px(0.0),
+                width: Val::Percent(100.0),
+                padding: UiRect::all(Val::Px(6.0)),
+                display: Display::None,
+                ..default()
+            },
+            background_color: Color::rgb(0.6, 0.45, 0.1).into(),
+            z_index: ZIndex::Global(50),
+            ..TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+        }
+        .with_text_justify(JustifyText::Center),
+        DeprecationBanner,
+    ));
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn update_deprecation_banner(
+    deprecation: Res<Deprecation>,
+    mut banners: Query<(&mut Text, &mut Style), With<DeprecationBanner>>,
+) {
+    if !deprecation.is_changed() {
+        return;
+    }
+    for (mut text, mut style) in banners.iter_mut() {
+        match &deprecation.notice {
+            Some(notice) => {
+                text.sections[0].value = notice.clone();
+                style.display = Display::Flex;
+            }
+            None => style.display = Display::None,
+        }
+    }
+}
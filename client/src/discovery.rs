@@ -0,0 +1,154 @@
+//! The server list under the connect dialog: what each address in the
+//! dialog's field says it is, before we join it.
+//!
+//! While the dialog is up and its field holds a valid address list, a
+//! thread of its own asks every address with a `ServerInfoRequest` every
+//! [`QUERY_INTERVAL`] (see `common::server_query`). A server answers that
+//! without a handshake and without counting us as a client, so asking
+//! costs it nothing and may go on while the handshake retries. Each line
+//! shows the name, who is on, whether it takes a matchmaker's ticket, its
+//! protocol version, its uptime and the query's round trip, or that it
+//! didn't answer within [`REPLY_TIMEOUT`]. The list follows the field as it
+//! is edited, and stops once connected.
+
+use bevy::prelude::*;
+use common::handlers::ServerInfo;
+use common::server_query::{Querier, QueryResult};
+use common::ui::text_input::TextInput;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::connect::{ConnectInput, validate_address};
+use crate::handshake::{Handshake, HandshakeStage};
+
+/// Time between two rounds of queries
+const QUERY_INTERVAL: Duration = Duration::from_secs(3);
+/// A server that takes longer than this to answer is listed as silent
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the thread looks for addresses while it has none
+const IDLE_POLL: Duration = Duration::from_millis(200);
+
+/// The addresses to ask, from the dialog, and what they last answered.
+#[derive(Resource, Clone, Default)]
+pub struct ServerList {
+    targets: Arc<Mutex<Vec<String>>>,
+    answers: Arc<Mutex<Vec<(String, QueryResult)>>>,
+}
+
+impl ServerList {
+    /// Starts the thread, which asks nothing until it's given addresses.
+    pub fn start() -> Self {
+        let list = Self::default();
+        let shared = list.clone();
+        thread::spawn(move || {
+            let querier = Querier::bind();
+            loop {
+                let targets = shared.targets.lock().unwrap().clone();
+                if targets.is_empty() {
+                    thread::sleep(IDLE_POLL);
+                    continue;
+                }
+                let results = querier.query(&targets, REPLY_TIMEOUT);
+                *shared.answers.lock().unwrap() = targets.into_iter().zip(results).collect();
+                thread::sleep(QUERY_INTERVAL);
+            }
+        });
+        list
+    }
+}
+
+/// Under the connect dialog's status line
+#[derive(Component)]
+pub struct ServerListText;
+
+/// Hands the thread the addresses in the dialog's field while the dialog
+/// is up, and none once connected.
+pub fn update_query_targets(
+    inputs: Query<&TextInput, With<ConnectInput>>,
+    handshake: Res<Handshake>,
+    list: Res<ServerList>,
+) {
+    let value = inputs
+        .get_single()
+        .map_or(String::new(), |input| input.value.trim().to_string());
+    let targets: Vec<String> =
+        if handshake.stage == HandshakeStage::Connected || validate_address(&value).is_err() {
+            Vec::new()
+        } else {
+            value
+                .split(',')
+                .map(|target| target.trim().to_string())
+                .collect()
+        };
+    let mut current = list.targets.lock().unwrap();
+    if *current != targets {
+        *current = targets;
+    }
+}
+
+pub fn update_server_list(
+    list: Res<ServerList>,
+    mut texts: Query<&mut Text, With<ServerListText>>,
+) {
+    let targets = list.targets.lock().unwrap().clone();
+    let answers = list.answers.lock().unwrap();
+    let lines: Vec<String> = targets
+        .iter()
+        .map(
+            |target| match answers.iter().find(|(answered, _)| answered == target) {
+                Some((_, answer)) => describe(target, answer),
+                None => format!("{}  asking...", target),
+            },
+        )
+        .collect();
+    let value = lines.join("\n");
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+fn describe(target: &str, answer: &QueryResult) -> String {
+    match answer {
+        QueryResult::Answered { info, rtt_ms } => describe_info(target, info, *rtt_ms),
+        QueryResult::NoReply => format!("{}  no answer", target),
+        QueryResult::Failed(reason) => format!("{}  can't be asked: {}", target, reason),
+    }
+}
+
+fn describe_info(target: &str, info: &ServerInfo, rtt_ms: f32) -> String {
+    let mut line = format!(
+        "{}  {}  {} player{}",
+        target,
+        info.name,
+        info.players,
+        if info.players == 1 { "" } else { "s" }
+    );
+    if info.spectators > 0 {
+        line.push_str(&format!(", {} watching", info.spectators));
+    }
+    line.push_str(&format!(
+        "  {}  protocol {}  up {}  {:.0} ms",
+        info.mode,
+        info.version,
+        format_uptime(info.uptime_secs),
+        rtt_ms
+    ));
+    line
+}
+
+/// `42s`, `5m`, `1h02m` or `3d04h`
+fn format_uptime(secs: u64) -> String {
+    let (minutes, hours, days) = (secs / 60, secs / 3600, secs / 86400);
+    if days > 0 {
+        format!("{}d{:02}h", days, hours % 24)
+    } else if hours > 0 {
+        format!("{}h{:02}m", hours, minutes % 60)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", secs)
+    }
+}
@@ -0,0 +1,75 @@
+//! F10 flashes every window in the session; see `common::effects`.
+//!
+//! The key sends `Fx <seq> flash` to the server, which fans it out to every
+//! client. Ours comes back too, and our window flashes when it does, not on
+//! the key press, so it flashes when everyone else's does.
+
+use bevy::prelude::*;
+use common::effects::{self, AtMostOnce, Effect, FLASH};
+use common::handlers::NetIncoming;
+use common::ui::screen_flash::FlashScreen;
+
+use crate::handshake::{Handshake, HandshakeStage};
+use crate::session::Session;
+use crate::{ClientState, NetworkState};
+
+#[derive(Resource, Default)]
+pub struct Effects {
+    /// Numbers the events we send
+    next_seq: u32,
+    /// The server's numbers, for the events it fans out
+    seen: AtMostOnce,
+}
+
+pub fn send_flash_on_key(
+    keys: Res<ButtonInput<KeyCode>>,
+    network: Res<NetworkState>,
+    handshake: Res<Handshake>,
+    mut effects: ResMut<Effects>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    if !keys.just_pressed(KeyCode::F10) {
+        return;
+    }
+    if handshake.stage != HandshakeStage::Connected {
+        client_state.push_log("[Error]: Not connected, flash not sent".to_string());
+        return;
+    }
+    effects.next_seq += 1;
+    let message = Effect::new(FLASH).request(effects.next_seq);
+    network.send_to(message.as_bytes(), client_state.gameplay_addr());
+    session.record_sent(effects::KIND, message.len());
+}
+
+/// Shows each event the server fans out at most once; a new session starts
+/// the server's numbering over.
+pub fn show_effects(
+    mut incoming: EventReader<NetIncoming>,
+    client_state: Res<ClientState>,
+    mut effects: ResMut<Effects>,
+    mut flashes: EventWriter<FlashScreen>,
+) {
+    for message in incoming.read() {
+        let from_server = message.from == client_state.server_addr
+            || client_state.data_addr.as_deref() == Some(message.from.as_str());
+        if !from_server {
+            continue;
+        }
+        if message.text.starts_with("Welcome") {
+            effects.seen.reset();
+            continue;
+        }
+        let Some((seq, _origin, effect)) = Effect::parse_relay(&message.text) else {
+            continue;
+        };
+        if !effects.seen.accept(seq) {
+            continue;
+        }
+        if effect.name == FLASH {
+            flashes.send(FlashScreen {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.6),
+            });
+        }
+    }
+}
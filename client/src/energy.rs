@@ -0,0 +1,137 @@
+//! Energy use: how much CPU the client burns and how often its network
+//! worker threads wake up, so the effect of `--net-tick-hz`,
+//! `--heartbeat-interval` and the send mode on a laptop or phone battery
+//! can be measured instead of guessed.
+//!
+//! The workers are the receive thread and the send pacer's thread. Each
+//! counts its wakeups and reads its own CPU time as it wakes (`cpu-time`,
+//! which asks the OS for the thread's clock). Once a second
+//! `measure_energy` turns them into rates:
+//!
+//! - [`PROCESS_CPU`]: CPU time of the whole client, in % of one core
+//! - [`NETWORK_CPU`]: of that, the workers' share, in % of one core
+//! - [`NETWORK_WAKEUPS`]: worker wakeups per second
+//!
+//! The receive thread polls, sleeping 10 ms whenever the socket is empty, so
+//! an idle client still wakes it about 100 times a second; every datagram
+//! read on top of that is one more. The send thread only wakes for a paced
+//! batch (F7), once per datagram in it. Sends in burst mode happen on the
+//! frame and are counted in the process but not the workers. The F5 panel
+//! shows all three.
+
+#[cfg(not(feature = "thin"))]
+use bevy::diagnostic::{Diagnostic, DiagnosticsStore};
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::prelude::*;
+use cpu_time::{ProcessTime, ThreadTime};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::NetworkState;
+
+/// CPU time of the whole process, % of one core
+pub const PROCESS_CPU: DiagnosticPath = DiagnosticPath::const_new("energy/process_cpu");
+/// CPU time of the network worker threads, % of one core
+pub const NETWORK_CPU: DiagnosticPath = DiagnosticPath::const_new("energy/network_cpu");
+/// Network worker wakeups per second
+pub const NETWORK_WAKEUPS: DiagnosticPath = DiagnosticPath::const_new("energy/network_wakeups");
+
+/// How often the rates are taken
+const SAMPLE_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy)]
+pub enum Worker {
+    Receive,
+    Send,
+}
+
+/// Shared with the worker threads.
+#[derive(Default)]
+pub struct WorkerMeters {
+    wakeups: AtomicU64,
+    /// Each worker's CPU time at its last wakeup, in nanoseconds
+    cpu_nanos: [AtomicU64; 2],
+}
+
+impl WorkerMeters {
+    /// Called on `worker`'s own thread each time it wakes.
+    pub fn woke(&self, worker: Worker) {
+        self.wakeups.fetch_add(1, Ordering::Relaxed);
+        // Not every OS has a per-thread clock; then only wakeups are counted
+        if let Ok(cpu) = ThreadTime::try_now() {
+            self.cpu_nanos[worker as usize]
+                .store(cpu.as_duration().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn cpu(&self) -> Duration {
+        let nanos = self
+            .cpu_nanos
+            .iter()
+            .map(|cpu| cpu.load(Ordering::Relaxed))
+            .sum();
+        Duration::from_nanos(nanos)
+    }
+}
+
+/// The counters at the last sample.
+pub struct Sample {
+    at: Instant,
+    process: Option<Duration>,
+    workers: Duration,
+    wakeups: u64,
+}
+
+impl Sample {
+    fn take(meters: &WorkerMeters) -> Self {
+        Self {
+            at: Instant::now(),
+            process: ProcessTime::try_now().ok().map(|cpu| cpu.as_duration()),
+            workers: meters.cpu(),
+            wakeups: meters.wakeups.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub fn measure_energy(
+    network: Res<NetworkState>,
+    mut last: Local<Option<Sample>>,
+    mut diagnostics: Diagnostics,
+) {
+    let Some(previous) = last.as_ref() else {
+        *last = Some(Sample::take(&network.energy));
+        return;
+    };
+    if previous.at.elapsed() < SAMPLE_EVERY {
+        return;
+    }
+    let sample = Sample::take(&network.energy);
+    let wall = (sample.at - previous.at).as_secs_f64();
+    let percent = |cpu: Duration| cpu.as_secs_f64() / wall * 100.0;
+    if let (Some(now), Some(then)) = (sample.process, previous.process) {
+        diagnostics.add_measurement(&PROCESS_CPU, || percent(now.saturating_sub(then)));
+    }
+    let workers = sample.workers.saturating_sub(previous.workers);
+    diagnostics.add_measurement(&NETWORK_CPU, || percent(workers));
+    let wakeups = sample.wakeups - previous.wakeups;
+    diagnostics.add_measurement(&NETWORK_WAKEUPS, || wakeups as f64 / wall);
+    *last = Some(sample);
+}
+
+/// One line per rate, for the F5 panel; `-` until there is a sample.
+#[cfg(not(feature = "thin"))]
+pub fn lines(store: &DiagnosticsStore) -> Vec<String> {
+    [
+        ("process cpu", PROCESS_CPU, "%"),
+        ("network cpu", NETWORK_CPU, "%"),
+        ("net wakeups", NETWORK_WAKEUPS, "/s"),
+    ]
+    .into_iter()
+    .map(
+        |(label, path, unit)| match store.get(&path).and_then(Diagnostic::smoothed) {
+            Some(value) => format!("{:<16} {:>7.1} {}", label, value, unit),
+            None => format!("{:<16} {:>7}", label, "-"),
+        },
+    )
+    .collect()
+}
@@ -0,0 +1,605 @@
+//! The client's window: header, log, PING button and everything drawn
+//! around them. Left out of `thin` builds, which print the log instead; see
+//! `console`.
+//!
+//! F5 opens the pacing panel: the frame rate next to the network tick and
+//! input sample rates (see `pacing`), the RTT and Ping loss raw, smoothed
+//! and over each `--stats-windows` window (see `common::stats`), the
+//! ambient RTT the heartbeats measure the same way, the time messages
+//! spend in each stage inside the client (see `latency`), the CPU use and
+//! network worker wakeups (see `energy`), and the traffic broken down by
+//! message type (see `common::bandwidth`). F6 toggles the send inspector (see
+//! `inspector`). F7 switches between paced and burst sends (see
+//! `send_pacer`); the pacing panel shows which is on. Built with
+//! `--features traceroute`, F8 traces the route to the server (see
+//! `traceroute`). F10 flashes every window in the session (see `effects`).
+//! With `--latency-report`, F11 writes the HTML report of the run so far
+//! (see `latency_report`). The arrow keys move a square that the server
+//! draws too (see `sprite`).
+//!
+//! PING is greyed out while a Ping is in flight, from the press until the
+//! Pong or the ack timeout, so round trips never overlap and each Pong is
+//! matched to the right Ping. A ring above the button drains over the
+//! `--ack-timeout`; a tick on it marks the smoothed round trip, where the
+//! reply is due. Over a slow link the wait is simply longer.
+//!
+//! Once connected, the header shows the server and the smoothed round trip
+//! time, from the heartbeats when nobody pings (see `timeline`).
+
+use bevy::diagnostic::{Diagnostic, DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use bevy::window::PresentMode;
+use common::bandwidth::BandwidthStats;
+use common::message::Message;
+use common::stats::SmoothedStat;
+use common::ui::accessibility::{KeyboardNavigationPlugin, LiveStatus};
+use common::ui::clipboard::ClipboardPlugin;
+use common::ui::fonts::FontsPlugin;
+use common::ui::screen_flash::ScreenFlashPlugin;
+use common::ui::text_input::TextInputPlugin;
+use common::ui::widgets::{
+    ButtonDisabled, ButtonFlash, UiTheme, WidgetsPlugin, header, log_text, spawn_button,
+};
+
+use crate::address::{
+    handle_address_submit, handle_code_submit, invite_button_system, setup_address_entry,
+};
+use crate::chaos::{chaos_button_system, setup_chaos_button};
+#[cfg(feature = "chat")]
+use crate::chat::{handle_chat_submit, setup_chat_input};
+use crate::connect::{
+    ConnectDialog, connect_dialog_actions, record_connect_failures, setup_connect_dialog,
+    update_connect_dialog,
+};
+use crate::deprecation::{setup_deprecation_banner, update_deprecation_banner};
+use crate::discovery::{ServerList, update_query_targets, update_server_list};
+use crate::effects::{Effects, send_flash_on_key, show_effects};
+use crate::energy;
+use crate::handshake::{Handshake, HandshakeStage};
+use crate::inspector::{Inspector, InspectorPlugin};
+use crate::outbound::OutboundQueue;
+use crate::pacing::{INPUT_SAMPLE_RATE, NET_TICK_RATE, input_tick};
+use crate::projectile::{PacketFlight, launch_projectiles, move_projectiles, setup_projectiles};
+use crate::recovery::{RecoveryChart, draw_recovery_chart, sample_recovery, setup_recovery_chart};
+use crate::script::ServerMessage;
+use crate::send_pacer::SendMode;
+use crate::sprite::{
+    PositionSender, move_player_sprite, send_player_position, setup_player_sprite,
+};
+use crate::timeline::{PacketTimeline, draw_timeline, setup_timeline};
+use crate::traffic::{setup_footer, update_footer};
+use crate::transport::{pick_transport, toggle_transport_menu, update_transport_button};
+use crate::{Args, ClientState, NetworkState, handle_network_messages};
+
+pub struct GuiPlugin {
+    /// `--font`
+    pub fonts: Vec<String>,
+    /// Off with `--no-vsync`
+    pub vsync: bool,
+    /// `--inspect-sends`
+    pub inspect_sends: bool,
+    /// `--position-hz`
+    pub position_hz: f32,
+}
+
+impl Plugin for GuiPlugin {
+    fn build(&self, app: &mut App) {
+        let present_mode = if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        };
+        app.add_plugins((
+            DefaultPlugins.set(WindowPlugin {
+                primary_window: Some(Window {
+                    present_mode,
+                    ..default()
+                }),
+                ..default()
+            }),
+            FrameTimeDiagnosticsPlugin,
+            TextInputPlugin,
+            ClipboardPlugin,
+            KeyboardNavigationPlugin,
+            WidgetsPlugin,
+            ScreenFlashPlugin,
+            FontsPlugin {
+                paths: self.fonts.clone(),
+            },
+            InspectorPlugin {
+                enabled: self.inspect_sends,
+            },
+        ))
+        .init_resource::<ConnectDialog>()
+        .insert_resource(ServerList::start())
+        .init_resource::<RecoveryChart>()
+        .init_resource::<Effects>()
+        .insert_resource(PositionSender::new(self.position_hz))
+        .add_event::<PacketFlight>()
+        .add_systems(
+            Startup,
+            (
+                setup_ui,
+                setup_timeline,
+                setup_footer,
+                setup_address_entry,
+                setup_projectiles,
+                setup_chaos_button,
+                setup_connect_dialog,
+                #[cfg(feature = "chat")]
+                setup_chat_input,
+                setup_pacing_panel,
+                setup_recovery_chart,
+                setup_deprecation_banner,
+                setup_player_sprite,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                ping_button_system.run_if(input_tick),
+                update_log_ui,
+                draw_timeline,
+                update_footer,
+                handle_address_submit,
+                handle_code_submit,
+                invite_button_system,
+                launch_projectiles,
+                move_projectiles,
+                chaos_button_system.run_if(input_tick),
+                #[cfg(feature = "chat")]
+                handle_chat_submit,
+                show_pongs.after(handle_network_messages),
+                toggle_pacing_panel,
+                toggle_send_mode,
+                update_pacing_panel,
+                update_deprecation_banner,
+                update_header_latency.after(handle_network_messages),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                record_connect_failures,
+                connect_dialog_actions,
+                update_connect_dialog,
+            )
+                .chain()
+                .after(handle_network_messages),
+        )
+        .add_systems(Update, (update_query_targets, update_server_list))
+        .add_systems(
+            Update,
+            (sample_recovery, draw_recovery_chart.after(sample_recovery)),
+        )
+        .add_systems(Update, (update_ping_cooldown, draw_ping_cooldown))
+        .add_systems(
+            Update,
+            (
+                send_flash_on_key,
+                show_effects.after(handle_network_messages),
+                move_player_sprite,
+                send_player_position.after(move_player_sprite),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                toggle_transport_menu,
+                pick_transport,
+                update_transport_button.after(pick_transport),
+            ),
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct HeaderText;
+
+#[derive(Component)]
+struct LogText;
+
+#[derive(Component)]
+struct PingButton;
+
+fn setup_ui(mut commands: Commands, client_state: Res<ClientState>, theme: Res<UiTheme>) {
+    commands.spawn(Camera2dBundle::default());
+
+    // Status Header
+    commands.spawn((
+        header(
+            &theme,
+            format!(
+                "Client connecting to {}{}",
+                client_state.server_target,
+                client_state.endpoint_position()
+            ),
+        ),
+        HeaderText,
+        LiveStatus,
+    ));
+
+    // Log Area
+    commands.spawn((log_text(&theme, "Ready to ping...\n"), LogText));
+
+    spawn_button(&mut commands, &theme, "PING", 120.0, PingButton);
+}
+
+/// Seconds between changes of the header's latency figure, so a screen
+/// reader announcing the header isn't handed a new number every frame
+const HEADER_REFRESH_SECS: f32 = 1.0;
+
+/// While connected, the header names the server and the live round trip
+/// time; once the connection is gone, or a failover picks another
+/// endpoint, it says what we're connecting to.
+fn update_header_latency(
+    time: Res<Time>,
+    handshake: Res<Handshake>,
+    client_state: Res<ClientState>,
+    timeline: Res<PacketTimeline>,
+    mut header: Query<&mut Text, With<HeaderText>>,
+    mut shown_at: Local<Option<f32>>,
+    mut shown_target: Local<Option<String>>,
+) {
+    let now = time.elapsed_seconds();
+    let moved = shown_target
+        .replace(client_state.server_target.clone())
+        .is_some_and(|previous| previous != client_state.server_target);
+    let position = client_state.endpoint_position();
+    let line = if handshake.stage != HandshakeStage::Connected {
+        if shown_at.take().is_none() && !moved {
+            return;
+        }
+        format!(
+            "Client connecting to {}{}",
+            client_state.server_target, position
+        )
+    } else if shown_at.is_some_and(|at| now - at < HEADER_REFRESH_SECS) {
+        return;
+    } else {
+        *shown_at = Some(now);
+        match timeline.latency() {
+            Some((rtt, source)) => format!(
+                "Client connected to {}{}, RTT {:.0} ms ({})",
+                client_state.server_addr,
+                position,
+                rtt * 1000.0,
+                source.label()
+            ),
+            None => format!(
+                "Client connected to {}{}",
+                client_state.server_addr, position
+            ),
+        }
+    };
+    for mut text in header.iter_mut() {
+        if text.sections[0].value != line {
+            text.sections[0].value = line.clone();
+        }
+    }
+}
+
+/// The server's message of the day and announcements stand out from the
+/// rest of the log
+const ANNOUNCEMENT_COLOR: Color = Color::rgb(1.0, 0.8, 0.35);
+
+/// A section per log entry, so announcements (see `account`) get their own
+/// color
+fn update_log_ui(
+    client_state: Res<ClientState>,
+    theme: Res<UiTheme>,
+    mut query: Query<&mut Text, With<LogText>>,
+) {
+    if !client_state.is_changed() {
+        return;
+    }
+    let style = TextStyle {
+        font_size: theme.log_font_size,
+        color: theme.text_color,
+        ..default()
+    };
+    for mut text in query.iter_mut() {
+        let mut sections: Vec<TextSection> = client_state
+            .log
+            .iter()
+            .map(|entry| {
+                let mut style = style.clone();
+                if entry.starts_with("[MOTD]") || entry.starts_with("[Announcement]") {
+                    style.color = ANNOUNCEMENT_COLOR;
+                }
+                TextSection::new(format!("{}\n", entry), style)
+            })
+            .collect();
+        if sections.is_empty() {
+            sections.push(TextSection::new(String::new(), style.clone()));
+        }
+        text.sections = sections;
+    }
+}
+
+fn ping_button_system(
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<PingButton>)>,
+    time: Res<Time>,
+    client_state: Res<ClientState>,
+    network: Res<NetworkState>,
+    timeline: Res<PacketTimeline>,
+    mut inspector: ResMut<Inspector>,
+    mut outbound: ResMut<OutboundQueue>,
+) {
+    for interaction in interaction_query.iter() {
+        if *interaction != Interaction::Pressed || ping_cooling_down(&timeline, &outbound) {
+            continue;
+        }
+        if inspector.enabled {
+            // One at a time: the panel has to be answered first
+            if !inspector.holding() {
+                inspector.hold(&network, "Ping", client_state.gameplay_addr());
+            }
+        } else {
+            // The outbound queue logs the send and coalesces rapid repeats
+            outbound.push("Ping", client_state.gameplay_addr(), time.elapsed_seconds());
+        }
+    }
+}
+
+/// A Ping is queued or waiting for its Pong.
+fn ping_cooling_down(timeline: &PacketTimeline, outbound: &OutboundQueue) -> bool {
+    timeline.in_flight().is_some() || outbound.queued("Ping")
+}
+
+fn update_ping_cooldown(
+    mut commands: Commands,
+    timeline: Res<PacketTimeline>,
+    outbound: Res<OutboundQueue>,
+    buttons: Query<(Entity, Has<ButtonDisabled>), With<PingButton>>,
+) {
+    let cooling_down = ping_cooling_down(&timeline, &outbound);
+    for (button, disabled) in buttons.iter() {
+        if cooling_down && !disabled {
+            commands.entity(button).insert(ButtonDisabled);
+        } else if !cooling_down && disabled {
+            commands.entity(button).remove::<ButtonDisabled>();
+        }
+    }
+}
+
+/// Ring radius, in pixels
+const COOLDOWN_RADIUS: f32 = 12.0;
+
+fn draw_ping_cooldown(
+    time: Res<Time>,
+    timeline: Res<PacketTimeline>,
+    windows: Query<&Window>,
+    buttons: Query<(&Node, &GlobalTransform), With<PingButton>>,
+    mut gizmos: Gizmos,
+) {
+    let Some(mark) = timeline.in_flight() else {
+        return;
+    };
+    let (Ok(window), Ok((node, transform))) = (windows.get_single(), buttons.get_single()) else {
+        return;
+    };
+    // UI positions count from the top-left corner, the 2D camera from the
+    // middle of the window
+    let button = transform.translation().truncate();
+    let center = Vec2::new(
+        button.x - window.width() / 2.0,
+        window.height() / 2.0 - button.y + node.size().y / 2.0 + COOLDOWN_RADIUS + 6.0,
+    );
+    let waited = (time.elapsed_seconds_f64() - mark.sent_at) as f32;
+    let left = (1.0 - waited / timeline.ack_timeout).clamp(0.0, 1.0);
+    let full = std::f32::consts::TAU;
+
+    gizmos.circle_2d(center, COOLDOWN_RADIUS, Color::rgba(1.0, 1.0, 1.0, 0.15));
+    // Drains clockwise from the top; arcs are centred on their direction
+    let arc = full * left;
+    gizmos.arc_2d(center, arc / 2.0, arc, COOLDOWN_RADIUS, Color::YELLOW);
+    if let Some(rtt) = timeline.smoothed_rtt() {
+        let due = full * (1.0 - rtt / timeline.ack_timeout).clamp(0.0, 1.0);
+        let direction = Vec2::new(due.sin(), due.cos());
+        gizmos.line_2d(
+            center + direction * (COOLDOWN_RADIUS - 4.0),
+            center + direction * (COOLDOWN_RADIUS + 4.0),
+            Color::GREEN,
+        );
+    }
+}
+
+/// Flies a dot back for every Pong that answered a Ping, and flashes the
+/// button: the round trip completed, which is when the click "worked".
+fn show_pongs(
+    mut messages: EventReader<ServerMessage>,
+    mut ping_flash: Query<&mut ButtonFlash, With<PingButton>>,
+    mut flights: EventWriter<PacketFlight>,
+) {
+    for message in messages.read() {
+        if !matches!(Message::parse(&message.text), Some(Message::Pong { .. })) {
+            continue;
+        }
+        if message.rtt.is_some() {
+            flights.send(PacketFlight {
+                toward_server: false,
+            });
+        }
+        for mut flash in ping_flash.iter_mut() {
+            flash.trigger();
+        }
+    }
+}
+
+#[derive(Component)]
+struct PacingPanel;
+
+#[derive(Component)]
+struct PacingText;
+
+fn setup_pacing_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(50.0),
+                    right: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            PacingPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Frame pacing (F5 to close)",
+                TextStyle {
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                PacingText,
+            ));
+        });
+}
+
+fn toggle_pacing_panel(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut panels: Query<&mut Visibility, With<PacingPanel>>,
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+    for mut visibility in panels.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn toggle_send_mode(
+    keys: Res<ButtonInput<KeyCode>>,
+    network: Res<NetworkState>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if !keys.just_pressed(KeyCode::F7) {
+        return;
+    }
+    let mode = match network.pacer.mode() {
+        SendMode::Paced => SendMode::Burst,
+        SendMode::Burst => SendMode::Paced,
+    };
+    network.pacer.set_mode(mode);
+    client_state.push_log(format!("[Info]: Sends {}", mode.label()));
+}
+
+/// Message types listed before the rest are lumped together
+const BANDWIDTH_ROWS: usize = 10;
+
+/// The raw and smoothed value, then one line per window; samples are shown
+/// times `scale`
+fn stat_lines(label: &str, stat: &SmoothedStat, now: f64, scale: f32) -> Vec<String> {
+    let show = |value: Option<f32>| value.map_or("-".to_string(), |v| format!("{:.1}", v * scale));
+    let mut lines = vec![format!(
+        "{:<13} {:>7}     (smoothed {})",
+        label,
+        show(stat.raw()),
+        show(stat.smoothed())
+    )];
+    for (secs, summary) in stat.windows(now) {
+        let window = format!("  last {}s", secs);
+        lines.push(match summary {
+            Some(summary) => format!(
+                "{:<13} {:>7}     ({} to {}, {} samples)",
+                window,
+                show(Some(summary.mean)),
+                show(Some(summary.min)),
+                show(Some(summary.max)),
+                summary.count
+            ),
+            None => format!("{:<13} {:>7}", window, "-"),
+        });
+    }
+    lines
+}
+
+fn update_pacing_panel(
+    args: Res<Args>,
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    timeline: Res<PacketTimeline>,
+    bandwidth: Res<BandwidthStats>,
+    store: Res<DiagnosticsStore>,
+    panels: Query<&Visibility, With<PacingPanel>>,
+    mut texts: Query<&mut Text, With<PacingText>>,
+) {
+    if panels
+        .iter()
+        .all(|visibility| *visibility == Visibility::Hidden)
+    {
+        return;
+    }
+    let configured = |hz: f32| {
+        if hz > 0.0 {
+            format!("{} Hz", hz)
+        } else {
+            "every frame".to_string()
+        }
+    };
+    let rows = [
+        (
+            "render",
+            FrameTimeDiagnosticsPlugin::FPS,
+            if args.no_vsync { "uncapped" } else { "vsync" }.to_string(),
+        ),
+        ("network tick", NET_TICK_RATE, configured(args.net_tick_hz)),
+        ("input", INPUT_SAMPLE_RATE, configured(args.input_hz)),
+    ];
+    let mut lines: Vec<String> = rows
+        .into_iter()
+        .map(|(label, path, configured)| {
+            let rate = store
+                .get(&path)
+                .and_then(Diagnostic::smoothed)
+                .unwrap_or(0.0);
+            format!("{:<13} {:>7.1}/s   ({})", label, rate, configured)
+        })
+        .collect();
+    let batch = network.pacer.last_batch();
+    lines.push(format!(
+        "{:<13} {:>7}     (last batch {}, F7 switches)",
+        "sends",
+        network.pacer.mode().label(),
+        batch
+    ));
+    let now = time.elapsed_seconds_f64();
+    lines.push(String::new());
+    lines.extend(stat_lines("ping rtt ms", &timeline.rtt, now, 1000.0));
+    lines.extend(stat_lines("ambient ms", &timeline.ambient_rtt, now, 1000.0));
+    lines.extend(stat_lines("ping loss %", &timeline.lost, now, 100.0));
+    lines.push(String::new());
+    lines.push("Time inside the client, smoothed".to_string());
+    lines.extend(network.latency.lines());
+    lines.push(String::new());
+    lines.push("Energy, smoothed".to_string());
+    lines.extend(energy::lines(&store));
+    lines.push(String::new());
+    lines.push("Bandwidth by message type, since startup".to_string());
+    lines.extend(bandwidth.table(BANDWIDTH_ROWS));
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
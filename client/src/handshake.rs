@@ -0,0 +1,689 @@
+//! Hello/Welcome handshake, port-scanning fallback and reconnects.
+//!
+//! If the configured server port does not answer within the handshake
+//! timeout and `--scan-ports` is set, a Hello goes to every port in the range
+//! on the same host and the first one to say Welcome becomes our server.
+//!
+//! `--server` (or the address field) may list fallbacks after the primary,
+//! comma-separated. Every failed attempt moves on to the next endpoint,
+//! from the last back to the primary, and a lost connection is first
+//! retried on the endpoint it was on, so a Resume reaches the server that
+//! holds our session. The header says which endpoint is in use.
+//!
+//! The server hostname (`--server`, or whatever was typed into the address
+//! field) is resolved again at the start of every attempt, so a DNS change
+//! (server moved to a new IP) is picked up on the next reconnect without
+//! restarting the client. The lookup runs on a thread of its own, so slow
+//! DNS never stalls a frame; meanwhile the attempt is `Resolving`, and a
+//! lookup that takes longer than `--resolve-timeout` fails it like a name
+//! that doesn't resolve. An IP address needs no lookup.
+//!
+//! A server that ends the connection says why in a Disconnect. The client
+//! retries after a shutdown, and otherwise waits for the connect dialog.
+//! Closing the window sends the server a Disconnect of our own.
+//!
+//! After a lost connection the first attempt presents the token from the
+//! last Welcome in a Resume, so a server still holding our session carries
+//! it on; otherwise the server treats the Resume as a Hello.
+//!
+//! A server handing us off to another instance sends `Handoff addr=..
+//! token=..`. That ends the session, and the next attempt starts at once,
+//! on that address (which joins the endpoints) with that token in its
+//! Resume; no dialog, and no retry delay.
+//!
+//! Both carry our connect ticket, if we have one; see `ticket`. A build
+//! without some protocol subset (`--no-default-features` without `chat`)
+//! names the ones it has; see `common::protocol::SUBSETS`.
+//!
+//! Each attempt sends one Hello (or Resume) and waits `--handshake-timeout`
+//! for the Welcome. A failed attempt is retried after `--reconnect-delay`,
+//! up to `--handshake-attempts` attempts in a row, and not past
+//! `--handshake-deadline` seconds after the first; then the client stops and
+//! leaves it to the connect dialog. Every attempt is logged as
+//! `Connect attempt 2/5`. The count starts over once connected, and when the
+//! user picks an address.
+
+use bevy::prelude::*;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::ops::RangeInclusive;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bevy::app::AppExit;
+use common::disconnect::{DisconnectReason, Disconnected};
+use common::error::NetError;
+use common::handlers::{Disconnect, Handoff, NetReceived, Reject};
+use common::protocol;
+use common::ticket::TicketError;
+
+use crate::session::Session;
+use crate::ticket::refresh_ticket;
+use crate::transport::{Transport, tcp_reachable};
+use crate::{Args, ClientState, NetworkState};
+
+/// `--scan-ports` refuses ranges wider than this; it's a fallback, not nmap.
+const MAX_SCAN_PORTS: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HandshakeStage {
+    /// Looking the server's hostname up, off the main thread
+    Resolving,
+    /// Hello sent to the configured address
+    Waiting,
+    /// Hello sent to every port in the scan range
+    Scanning,
+    Connected,
+    /// Attempt gave up; the timer counts down to the next one
+    Failed,
+    /// No attempt and no retry until the connect dialog starts one
+    Stopped,
+}
+
+#[derive(Resource)]
+pub struct Handshake {
+    pub stage: HandshakeStage,
+    timer: Timer,
+    /// Time we last heard anything from the server
+    pub last_heard: f32,
+    /// Attempts made since the last connection, or since the user picked an
+    /// address
+    attempt: u32,
+    /// `--handshake-attempts`; 0 keeps trying
+    max_attempts: u32,
+    /// Seconds spent on those attempts, including the delays between them
+    trying_secs: f32,
+    /// Datagrams received before the first of those attempts; if the count
+    /// hasn't moved since, UDP may be blocked
+    received_before: u64,
+    /// Whether TCP has been tried for these attempts; see `transport`
+    probed_tcp: bool,
+    /// Where the current attempt's lookup thread leaves its answer
+    resolving: Option<Lookup>,
+}
+
+type Lookup = Arc<Mutex<Option<io::Result<SocketAddr>>>>;
+
+impl Handshake {
+    pub fn new(timeout_secs: f32, max_attempts: u32) -> Self {
+        Self {
+            stage: HandshakeStage::Waiting,
+            timer: Timer::from_seconds(timeout_secs, TimerMode::Once),
+            last_heard: 0.0,
+            attempt: 0,
+            max_attempts,
+            trying_secs: 0.0,
+            received_before: 0,
+            probed_tcp: false,
+            resolving: None,
+        }
+    }
+
+    /// `2/5`, or just `2` with no limit
+    pub fn attempt_label(&self) -> String {
+        if self.max_attempts == 0 {
+            self.attempt.to_string()
+        } else {
+            format!("{}/{}", self.attempt, self.max_attempts)
+        }
+    }
+
+    /// The next attempt counts as the first, with the whole deadline ahead
+    /// of it.
+    pub fn reset_attempts(&mut self) {
+        self.attempt = 0;
+        self.trying_secs = 0.0;
+        self.probed_tcp = false;
+    }
+
+    fn attempts_left(&self) -> bool {
+        self.max_attempts == 0 || self.attempt < self.max_attempts
+    }
+
+    /// Returns false if the handshake had already completed, so duplicate
+    /// Welcomes from a scan can be ignored.
+    pub fn complete(&mut self, now: f32) -> bool {
+        if self.stage == HandshakeStage::Connected {
+            return false;
+        }
+        self.stage = HandshakeStage::Connected;
+        self.last_heard = now;
+        self.reset_attempts();
+        true
+    }
+
+    /// Seconds until the current attempt times out, or until the next retry.
+    #[cfg(not(feature = "thin"))]
+    pub fn remaining_secs(&self) -> f32 {
+        self.timer.remaining_secs()
+    }
+
+    /// Gives up without scheduling a retry.
+    pub fn stop(&mut self) {
+        self.stage = HandshakeStage::Stopped;
+    }
+
+    fn restart_timer(&mut self, secs: f32) {
+        self.timer
+            .set_duration(std::time::Duration::from_secs_f32(secs));
+        self.timer.reset();
+    }
+
+    fn fail(&mut self, reconnect_delay: f32) {
+        self.stage = HandshakeStage::Failed;
+        self.restart_timer(reconnect_delay);
+    }
+}
+
+/// Parses `START-END` for `--scan-ports`.
+pub fn parse_port_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| format!("expected START-END, got '{}'", value))?;
+    let start: u16 = start.trim().parse().map_err(|e| format!("{}", e))?;
+    let end: u16 = end.trim().parse().map_err(|e| format!("{}", e))?;
+    if start > end {
+        return Err(format!("range start {} is after end {}", start, end));
+    }
+    if (end - start) as usize + 1 > MAX_SCAN_PORTS {
+        return Err(format!("range is wider than {} ports", MAX_SCAN_PORTS));
+    }
+    Ok(start..=end)
+}
+
+/// Resolves `host:port`, preferring IPv4 since the socket is bound to 0.0.0.0.
+fn resolve_server(server: &str) -> std::io::Result<SocketAddr> {
+    let mut addrs: Vec<SocketAddr> = server.to_socket_addrs()?.collect();
+    addrs.sort_by_key(|addr| !addr.is_ipv4());
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found"))
+}
+
+/// `role=spectator` asks the server to treat us as a spectator.
+fn role_field(args: &Args) -> &'static str {
+    if args.spectate { " role=spectator" } else { "" }
+}
+
+fn ticket_field(client_state: &ClientState) -> String {
+    client_state
+        .ticket
+        .as_ref()
+        .map_or(String::new(), |ticket| format!(" ticket={}", ticket))
+}
+
+/// The protocol subsets this build has; a server goes without the others
+/// for our session, and names them in the Welcome's `without=`.
+const SUBSETS: &[&str] = &[
+    #[cfg(feature = "chat")]
+    "chat",
+];
+
+fn hello(args: &Args, network: &NetworkState, client_state: &ClientState) -> String {
+    format!(
+        "Hello version={}{}{}{}{}",
+        args.protocol_version,
+        role_field(args),
+        ticket_field(client_state),
+        network.codecs.field(),
+        protocol::subsets_field(SUBSETS)
+    )
+}
+
+/// After `--detect-udp-block` silent UDP attempts, checks once whether TCP
+/// reaches the server, and moves to TCP if it does.
+fn detect_udp_block(
+    network: &NetworkState,
+    args: &Args,
+    handshake: &mut Handshake,
+    client_state: &mut ClientState,
+) {
+    if client_state.transport != Transport::Udp
+        || args.detect_udp_block == 0
+        || handshake.attempt < args.detect_udp_block
+        || handshake.probed_tcp
+    {
+        return;
+    }
+    let received = network.counters.packets_received.load(Ordering::Relaxed);
+    if received != handshake.received_before {
+        return;
+    }
+    let Ok(addr) = client_state.server_addr.parse::<SocketAddr>() else {
+        return;
+    };
+    handshake.probed_tcp = true;
+    let timeout = std::time::Duration::from_secs_f32(args.handshake_timeout.min(1.0));
+    if tcp_reachable(addr, timeout) {
+        client_state.push_log(format!(
+            "[Info]: {} Hellos over UDP got no reply and nothing at all came back, but a TCP \
+             connection to {} works. This network seems to block UDP; switching to TCP",
+            handshake.attempt, addr
+        ));
+        client_state.transport = Transport::Tcp;
+    } else {
+        client_state.push_log(format!(
+            "[Info]: Nothing received over UDP, and TCP to {} fails too; the server looks down \
+             rather than UDP blocked",
+            addr
+        ));
+    }
+}
+
+/// Schedules the next attempt, or stops once `--handshake-attempts` have
+/// all failed.
+fn retry_or_give_up(args: &Args, handshake: &mut Handshake, client_state: &mut ClientState) {
+    if client_state.transport == Transport::Udp
+        && args.tcp_fallback_after > 0
+        && handshake.attempt >= args.tcp_fallback_after
+    {
+        client_state.push_log(format!(
+            "[Info]: No answer over UDP after {} attempts, falling back to TCP",
+            handshake.attempt
+        ));
+        client_state.transport = Transport::Tcp;
+    }
+    if handshake.attempts_left() {
+        if client_state.next_endpoint() {
+            client_state.push_log(format!(
+                "[Info]: Failing over to {}{}",
+                client_state.server_target,
+                client_state.endpoint_position()
+            ));
+        }
+        client_state.push_log(format!("[Info]: Retrying in {}s", args.reconnect_delay));
+        handshake.fail(args.reconnect_delay);
+    } else {
+        client_state.push_log(format!(
+            "[Error]: Giving up on {} after {} attempts",
+            client_state.endpoints.join(", "),
+            handshake.attempt
+        ));
+        handshake.stop();
+    }
+}
+
+/// Resolves the server target afresh and sends it a Hello once that has
+/// answered. Unless it follows a failed attempt, the attempt count starts
+/// over.
+pub fn start_attempt(
+    network: &NetworkState,
+    args: &Args,
+    handshake: &mut Handshake,
+    client_state: &mut ClientState,
+) {
+    if handshake.stage != HandshakeStage::Failed {
+        handshake.reset_attempts();
+    }
+    handshake.attempt += 1;
+    if handshake.attempt == 1 {
+        handshake.received_before = network.counters.packets_received.load(Ordering::Relaxed);
+    }
+    refresh_ticket(args, client_state);
+    let target = client_state.server_target.clone();
+    if let Ok(resolved) = target.parse::<SocketAddr>() {
+        handshake.resolving = None;
+        connect_resolved(network, args, handshake, client_state, resolved);
+        return;
+    }
+    // A lookup left behind by an earlier attempt answers into its own slot
+    let lookup = Lookup::default();
+    let answer = lookup.clone();
+    thread::spawn(move || {
+        let resolved = resolve_server(&target);
+        *answer.lock().unwrap() = Some(resolved);
+    });
+    handshake.resolving = Some(lookup);
+    handshake.stage = HandshakeStage::Resolving;
+    handshake.restart_timer(args.resolve_timeout);
+}
+
+/// Carries the attempt on once its lookup has answered, or fails it after
+/// `--resolve-timeout`.
+fn poll_lookup(
+    time: &Time,
+    network: &NetworkState,
+    args: &Args,
+    handshake: &mut Handshake,
+    client_state: &mut ClientState,
+) {
+    let answer = handshake
+        .resolving
+        .as_ref()
+        .and_then(|lookup| lookup.lock().unwrap().take());
+    let result = match answer {
+        Some(result) => result,
+        None if handshake.timer.tick(time.delta()).just_finished() => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no answer from DNS within {}s", args.resolve_timeout),
+        )),
+        None => return,
+    };
+    handshake.resolving = None;
+    match result {
+        Ok(resolved) => connect_resolved(network, args, handshake, client_state, resolved),
+        Err(source) => {
+            network.errors.push(NetError::Resolve {
+                host: client_state.server_target.clone(),
+                source,
+            });
+            retry_or_give_up(args, handshake, client_state);
+        }
+    }
+}
+
+/// The rest of an attempt, with the server's address known.
+fn connect_resolved(
+    network: &NetworkState,
+    args: &Args,
+    handshake: &mut Handshake,
+    client_state: &mut ClientState,
+    resolved: SocketAddr,
+) {
+    let target = client_state.server_target.clone();
+    let role = if args.spectate { " as a spectator" } else { "" };
+    client_state.push_log(format!(
+        "[Info]: Connect attempt {}: {} ({}) over {}{}",
+        handshake.attempt_label(),
+        target,
+        resolved,
+        client_state.transport.label(),
+        role
+    ));
+    match client_state.transport {
+        Transport::Udp => network.tcp.use_udp(),
+        Transport::Tcp => {
+            let timeout = std::time::Duration::from_secs_f32(args.handshake_timeout);
+            if let Err(source) = network.tcp.open(resolved, timeout, network.inbox()) {
+                network.errors.push(NetError::Connect {
+                    addr: resolved.to_string(),
+                    source,
+                });
+                retry_or_give_up(args, handshake, client_state);
+                return;
+            }
+        }
+    }
+    client_state.server_addr = resolved.to_string();
+    client_state.data_addr = None;
+
+    // The Welcome reply tells us whether gameplay uses a separate port
+    let greeting = match &client_state.resume {
+        Some((addr, token)) if *addr == client_state.server_addr || *addr == target => {
+            format!(
+                "Resume token={} version={}{}{}{}{}",
+                token,
+                args.protocol_version,
+                role_field(args),
+                ticket_field(client_state),
+                network.codecs.field(),
+                protocol::subsets_field(SUBSETS)
+            )
+        }
+        _ => hello(args, network, client_state),
+    };
+    network.send_to(greeting.as_bytes(), resolved);
+    handshake.stage = HandshakeStage::Waiting;
+    handshake.restart_timer(args.handshake_timeout);
+}
+
+pub fn handshake_timeout_system(
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if matches!(
+        handshake.stage,
+        HandshakeStage::Connected | HandshakeStage::Stopped
+    ) {
+        return;
+    }
+    handshake.trying_secs += time.delta_seconds();
+    if args.handshake_deadline > 0.0 && handshake.trying_secs >= args.handshake_deadline {
+        network.errors.push(NetError::HandshakeTimeout {
+            addr: client_state.server_addr.clone(),
+            secs: args.handshake_deadline,
+        });
+        let line = format!(
+            "[Error]: Giving up on {} after {}s ({} attempts)",
+            client_state.server_target, args.handshake_deadline, handshake.attempt
+        );
+        client_state.push_log(line);
+        handshake.stop();
+        return;
+    }
+    if handshake.stage == HandshakeStage::Resolving {
+        poll_lookup(&time, &network, &args, &mut handshake, &mut client_state);
+        return;
+    }
+    // A failed attempt's timer is the retry delay, which `reconnect_system` ticks
+    if handshake.stage == HandshakeStage::Failed
+        || !handshake.timer.tick(time.delta()).just_finished()
+    {
+        return;
+    }
+
+    match (handshake.stage, &args.scan_ports) {
+        // Over TCP there is one connection to one port; nothing to scan
+        (HandshakeStage::Waiting, Some(ports)) if client_state.transport == Transport::Udp => {
+            let line = format!(
+                "[Info]: No Welcome from {}, probing ports {}-{}",
+                client_state.server_addr,
+                ports.start(),
+                ports.end()
+            );
+            client_state.push_log(line);
+            let host = client_state
+                .server_addr
+                .rsplit_once(':')
+                .map_or(client_state.server_addr.clone(), |(host, _)| {
+                    host.to_string()
+                });
+            for port in ports.clone() {
+                let addr = format!("{}:{}", host, port);
+                network.send_to(hello(&args, &network, &client_state).as_bytes(), &addr);
+                client_state.push_log(format!("[Tx]: Hello to {}", addr));
+            }
+            handshake.stage = HandshakeStage::Scanning;
+            handshake.restart_timer(args.handshake_timeout);
+        }
+        _ => {
+            network.errors.push(NetError::HandshakeTimeout {
+                addr: client_state.server_addr.clone(),
+                secs: args.handshake_timeout,
+            });
+            detect_udp_block(&network, &args, &mut handshake, &mut client_state);
+            retry_or_give_up(&args, &mut handshake, &mut client_state);
+        }
+    }
+}
+
+/// A server that doesn't support our protocol version, or uses other codecs,
+/// refused our Hello. Retrying won't change either, so this stops until the connect
+/// dialog starts again.
+/// A refused ticket is retried with a new one if the matchmaker can give us
+/// one.
+pub fn handle_reject(
+    mut rejects: EventReader<NetReceived<Reject>>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for reject in rejects.read() {
+        if handshake.stage == HandshakeStage::Connected {
+            continue;
+        }
+        if let Some(code) = &reject.message.ticket {
+            let reason = TicketError::from_code(code).map_or(code.clone(), |e| e.to_string());
+            client_state.push_log(format!("[Rx]: Reject from {}: {}", reject.from, reason));
+            network.errors.push(NetError::TicketRefused {
+                addr: reject.from.clone(),
+                reason,
+            });
+            client_state.ticket = None;
+            if args.matchmaker.is_some() {
+                retry_or_give_up(&args, &mut handshake, &mut client_state);
+            } else {
+                handshake.stop();
+            }
+            continue;
+        }
+        if let Some(theirs) = &reject.message.codecs {
+            client_state.push_log(format!(
+                "[Rx]: Reject from {}: it uses codecs {}",
+                reject.from, theirs
+            ));
+            network.errors.push(NetError::CodecMismatch {
+                addr: reject.from.clone(),
+                ours: network.codecs.describe(),
+                theirs: theirs.clone(),
+            });
+            handshake.stop();
+            continue;
+        }
+        client_state.push_log(format!(
+            "[Rx]: Reject version={} from {}",
+            reject.message.version, reject.from
+        ));
+        network.errors.push(NetError::VersionMismatch {
+            addr: reject.from.clone(),
+            ours: args.protocol_version,
+            theirs: reject.message.version,
+        });
+        handshake.stop();
+    }
+}
+
+/// The server ended our connection. Retry if it might come back,
+/// otherwise stop until the connect dialog starts again.
+pub fn handle_disconnect(
+    mut disconnects: EventReader<NetReceived<Disconnect>>,
+    time: Res<Time>,
+    args: Res<Args>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+    mut disconnected: EventWriter<Disconnected>,
+) {
+    for disconnect in disconnects.read() {
+        if handshake.stage != HandshakeStage::Connected {
+            continue;
+        }
+        let reason = disconnect.message.reason;
+        client_state.push_log(format!(
+            "[Rx]: Disconnect from {} ({})",
+            disconnect.from, reason
+        ));
+        session.end(
+            time.elapsed_seconds(),
+            &reason.to_string(),
+            &mut client_state,
+        );
+        // The server ended the session on purpose; there's nothing to resume
+        client_state.resume = None;
+        disconnected.send(Disconnected {
+            peer: disconnect.from.clone(),
+            reason,
+        });
+        if reason.retryable() {
+            client_state.push_log(format!("[Info]: Retrying in {}s", args.reconnect_delay));
+            handshake.fail(args.reconnect_delay);
+        } else {
+            handshake.stop();
+        }
+    }
+}
+
+/// The server moved us to another instance. Connect there right away, with
+/// its token.
+pub fn handle_handoff(
+    mut handoffs: EventReader<NetReceived<Handoff>>,
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    for handoff in handoffs.read() {
+        if handshake.stage != HandshakeStage::Connected {
+            continue;
+        }
+        let Handoff { addr, token } = &handoff.message;
+        // The token stays out of the log
+        client_state.push_log(format!("[Rx]: Handoff from {} to {}", handoff.from, addr));
+        session.end(
+            time.elapsed_seconds(),
+            &format!("handed off to {}", addr),
+            &mut client_state,
+        );
+        if !client_state.endpoints.contains(addr) {
+            client_state.endpoints.push(addr.clone());
+        }
+        client_state.server_target = addr.clone();
+        client_state.resume = Some((addr.clone(), token.clone()));
+        start_attempt(&network, &args, &mut handshake, &mut client_state);
+    }
+}
+
+/// Tells the server we're leaving, so it doesn't wait out the timeout.
+pub fn send_disconnect_on_exit(
+    mut exits: EventReader<AppExit>,
+    network: Res<NetworkState>,
+    handshake: Res<Handshake>,
+    client_state: Res<ClientState>,
+) {
+    if exits.read().next().is_some() && handshake.stage == HandshakeStage::Connected {
+        let message = DisconnectReason::UserQuit.message();
+        network.send_to(message.as_bytes(), &client_state.server_addr);
+    }
+}
+
+/// Starts a new attempt after a failed one, or when a connected server has
+/// gone silent for longer than `--server-timeout`.
+pub fn reconnect_system(
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+    mut disconnected: EventWriter<Disconnected>,
+) {
+    match handshake.stage {
+        HandshakeStage::Failed => {
+            if !handshake.timer.tick(time.delta()).just_finished() {
+                return;
+            }
+        }
+        HandshakeStage::Connected => {
+            if time.elapsed_seconds() - handshake.last_heard <= args.server_timeout {
+                return;
+            }
+            client_state.push_log(format!(
+                "[Info]: Server silent for {}s, reconnecting",
+                args.server_timeout
+            ));
+            session.end(
+                time.elapsed_seconds(),
+                &format!("server silent for {}s", args.server_timeout),
+                &mut client_state,
+            );
+            disconnected.send(Disconnected {
+                peer: client_state.server_addr.clone(),
+                reason: DisconnectReason::Timeout,
+            });
+        }
+        HandshakeStage::Resolving
+        | HandshakeStage::Waiting
+        | HandshakeStage::Scanning
+        | HandshakeStage::Stopped => return,
+    }
+
+    start_attempt(&network, &args, &mut handshake, &mut client_state);
+}
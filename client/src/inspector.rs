@@ -0,0 +1,352 @@
+//! Send inspector: with `--inspect-sends` (or F6 to toggle), PING doesn't
+//! send right away. It opens a panel showing the packet as it will go out:
+//! the message's entry in `common::protocol` (kind, direction, channel), its
+//! `key=value` fields, the payload, and the bytes after the middleware
+//! pipeline as a hex dump, with the compression marker and, on TCP, the
+//! length prefix from `common::framing` spelled out. SEND puts exactly those
+//! bytes on the wire; CANCEL drops them. One packet at a time, for
+//! following the stack while learning it.
+//!
+//! The pipeline runs once, for the preview, so stages that count or log
+//! (`--compress`, `--trace-packets`) see the packet once whether it is sent
+//! or not.
+
+use bevy::prelude::*;
+use common::compression::Marker;
+use common::protocol::messages;
+use common::session::message_kind;
+use common::ui::widgets::{ButtonFlash, ThemedButton, UiTheme};
+
+use crate::outbound::OutboundQueue;
+use crate::{ClientState, NetworkState};
+
+/// Bytes per hex dump row
+const ROW: usize = 16;
+
+/// A packet waiting for SEND or CANCEL.
+struct Held {
+    message: String,
+    target: String,
+    /// `None` if a stage dropped it
+    encoded: Option<Vec<u8>>,
+    /// Whether it would go out over TCP, framed
+    tcp: bool,
+}
+
+#[derive(Resource)]
+pub struct Inspector {
+    pub enabled: bool,
+    held: Option<Held>,
+}
+
+impl Inspector {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            held: None,
+        }
+    }
+
+    pub fn holding(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// Encodes `message` for `target` and holds it for the panel.
+    pub fn hold(&mut self, network: &NetworkState, message: &str, target: &str) {
+        self.held = Some(Held {
+            message: message.to_string(),
+            target: target.to_string(),
+            encoded: network.pipeline.outgoing(target, message.as_bytes()),
+            tcp: network.tcp.active(),
+        });
+    }
+}
+
+#[derive(Component)]
+struct InspectorPanel;
+
+#[derive(Component)]
+struct InspectorText;
+
+#[derive(Component)]
+struct SendButton;
+
+#[derive(Component)]
+struct CancelButton;
+
+pub struct InspectorPlugin {
+    /// `--inspect-sends`
+    pub enabled: bool,
+}
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Inspector::new(self.enabled))
+            .add_systems(Startup, setup_inspector)
+            .add_systems(
+                Update,
+                (
+                    toggle_inspector,
+                    inspector_actions,
+                    update_inspector_panel.after(inspector_actions),
+                ),
+            );
+    }
+}
+
+fn panel_button(parent: &mut ChildBuilder, theme: &UiTheme, label: &str, marker: impl Bundle) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(100.0),
+                    height: Val::Px(32.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: theme.button_color.into(),
+                ..default()
+            },
+            ThemedButton,
+            ButtonFlash::default(),
+            marker,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font_size: 18.0,
+                    color: theme.text_color,
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn setup_inspector(mut commands: Commands, theme: Res<UiTheme>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(theme.button_margin * 2.0 + theme.button_height),
+                    right: Val::Px(theme.button_margin),
+                    padding: UiRect::all(Val::Px(12.0)),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.05, 0.05, 0.05, 0.95).into(),
+                z_index: ZIndex::Global(15),
+                ..default()
+            },
+            InspectorPanel,
+        ))
+        .with_children(|panel| {
+            panel.spawn(TextBundle::from_section(
+                "Inspect before sending (F6 to turn off)",
+                TextStyle {
+                    font_size: 18.0,
+                    color: theme.header_color,
+                    ..default()
+                },
+            ));
+            panel.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: theme.text_color,
+                        ..default()
+                    },
+                ),
+                InspectorText,
+            ));
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(10.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    panel_button(row, &theme, "SEND", SendButton);
+                    panel_button(row, &theme, "CANCEL", CancelButton);
+                });
+        });
+}
+
+fn toggle_inspector(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut inspector: ResMut<Inspector>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if !keys.just_pressed(KeyCode::F6) {
+        return;
+    }
+    inspector.enabled = !inspector.enabled;
+    if inspector.enabled {
+        client_state.push_log("[Info]: Inspector on; PING shows the packet first".to_string());
+    } else {
+        // Turning it off doesn't send what it holds
+        inspector.held = None;
+        client_state.push_log("[Info]: Inspector off".to_string());
+    }
+}
+
+fn inspector_actions(
+    send: Query<&Interaction, (Changed<Interaction>, With<SendButton>)>,
+    cancel: Query<&Interaction, (Changed<Interaction>, With<CancelButton>)>,
+    time: Res<Time>,
+    mut inspector: ResMut<Inspector>,
+    mut outbound: ResMut<OutboundQueue>,
+    mut client_state: ResMut<ClientState>,
+) {
+    let pressed = |interaction: &Interaction| *interaction == Interaction::Pressed;
+    if cancel.iter().any(pressed) {
+        if let Some(held) = inspector.held.take() {
+            client_state.push_log(format!(
+                "[Info]: Cancelled {} to {}",
+                held.message, held.target
+            ));
+        }
+        return;
+    }
+    if !send.iter().any(pressed) {
+        return;
+    }
+    let Some(held) = inspector.held.take() else {
+        return;
+    };
+    match held.encoded {
+        Some(encoded) => {
+            outbound.push_encoded(&held.message, encoded, &held.target, time.elapsed_seconds())
+        }
+        None => client_state.push_log(format!(
+            "[Info]: A middleware stage dropped {}; nothing to send",
+            held.message
+        )),
+    }
+}
+
+/// Rows of 16 bytes: offset, hex, and the printable ones as text.
+fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(ROW)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("  {:04x}  {:<47}  {}", row * ROW, hex.join(" "), text)
+        })
+        .collect()
+}
+
+fn describe(held: &Held, network: &NetworkState) -> String {
+    let kind = message_kind(&held.message);
+    let mut lines = Vec::new();
+    match messages().find(|spec| spec.kind == kind) {
+        Some(spec) => {
+            lines.push(format!("Kind       {}  ({})", spec.kind, spec.description));
+            lines.push(format!("Direction  {}", spec.direction));
+            lines.push(format!("Channel    {}", spec.channel));
+            lines.push(format!("Format     {}", spec.format));
+        }
+        None => lines.push(format!("Kind       {}  (not in common::protocol)", kind)),
+    }
+    let transport = if held.tcp { "TCP" } else { "UDP" };
+    lines.push(format!("To         {} over {}", held.target, transport));
+    let fields: Vec<&str> = held
+        .message
+        .split_whitespace()
+        .skip(1)
+        .filter(|field| field.contains('='))
+        .collect();
+    lines.push(format!(
+        "Fields     {}",
+        if fields.is_empty() {
+            "(none)".to_string()
+        } else {
+            fields.join(", ")
+        }
+    ));
+    lines.push(format!(
+        "Payload    {} bytes: {}",
+        held.message.len(),
+        held.message
+    ));
+
+    let stages = network.pipeline.names();
+    lines.push(format!(
+        "Pipeline   {}",
+        if stages.is_empty() {
+            "(no stages)".to_string()
+        } else {
+            stages.join(" > ")
+        }
+    ));
+    let Some(encoded) = &held.encoded else {
+        lines.push("Encoded    dropped by a stage; SEND won't send anything".to_string());
+        return lines.join("\n");
+    };
+    let marker = Marker::of(encoded).map_or(String::new(), |marker| {
+        format!(
+            ", {} (marker 0x{:02x})",
+            marker.description(),
+            marker.byte()
+        )
+    });
+    lines.push(format!(
+        "Encoded    {} bytes{}, limit {}",
+        encoded.len(),
+        marker,
+        network.max_message_size
+    ));
+    if held.tcp {
+        let len = encoded.len() as u16;
+        let prefix = len.to_be_bytes();
+        lines.push(format!(
+            "TCP frame  {:02x} {:02x}  length prefix, big-endian {}",
+            prefix[0], prefix[1], len
+        ));
+    }
+    lines.extend(hex_dump(encoded));
+    lines.join("\n")
+}
+
+fn update_inspector_panel(
+    inspector: Res<Inspector>,
+    network: Res<NetworkState>,
+    mut panels: Query<&mut Style, With<InspectorPanel>>,
+    mut texts: Query<&mut Text, With<InspectorText>>,
+) {
+    if !inspector.is_changed() {
+        return;
+    }
+    let display = if inspector.held.is_some() {
+        Display::Flex
+    } else {
+        Display::None
+    };
+    for mut style in panels.iter_mut() {
+        style.display = display;
+    }
+    if let Some(held) = &inspector.held {
+        let description = describe(held, &network);
+        for mut text in texts.iter_mut() {
+            text.sections[0].value = description.clone();
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! The latency budget: how much of a round trip is spent inside the client.
+//!
+//! A Ping's RTT runs from the frame that sends it to the frame that handles
+//! the Pong, so the client's own threads and queues are in it too. Every
+//! message is stamped at the edges of the stages it passes:
+//!
+//! ```text
+//! send:    send_to ──ECS→worker──▶ pacer ──worker→socket──▶ wire
+//! receive: recv_from ──socket→worker──▶ queue ──worker→ECS──▶ handled ──ECS→UI──▶ frame end
+//! ```
+//!
+//! - ECS→worker: the middleware pipeline on the way out
+//! - worker→socket: waiting in the send pacer or a chaos stall
+//! - socket→worker: the middleware pipeline on the way in. Time in the OS
+//!   buffer before `recv_from` returns isn't seen.
+//! - worker→ECS: waiting in the receive queue, which is taken one message a
+//!   frame
+//! - ECS→UI: from being handled to the end of the frame that draws it
+//!
+//! The F5 panel shows each stage's smoothed time. A Pong's log line splits
+//! its RTT the same way, `[Rx]: Pong (rtt 41.2ms: 37.0 network, 4.2 in the
+//! client)`: the client's part is the Ping's send stages and the Pong's
+//! receive stages up to being handled, and the rest is the network and the
+//! server.
+
+use bevy::prelude::*;
+use common::session::message_kind;
+use common::stats::{DEFAULT_ALPHA, Ewma};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::NetworkState;
+
+#[derive(Clone, Copy)]
+pub enum Stage {
+    EcsToWorker,
+    WorkerToSocket,
+    SocketToWorker,
+    WorkerToEcs,
+    EcsToUi,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::EcsToWorker,
+        Stage::WorkerToSocket,
+        Stage::SocketToWorker,
+        Stage::WorkerToEcs,
+        Stage::EcsToUi,
+    ];
+
+    #[cfg(not(feature = "thin"))]
+    fn label(self) -> &'static str {
+        match self {
+            Stage::EcsToWorker => "tx ECS->worker",
+            Stage::WorkerToSocket => "tx worker->sock",
+            Stage::SocketToWorker => "rx sock->worker",
+            Stage::WorkerToEcs => "rx worker->ECS",
+            Stage::EcsToUi => "rx ECS->UI",
+        }
+    }
+}
+
+/// When a datagram being sent started out and when it left the pipeline.
+#[derive(Clone)]
+pub struct SendStamps {
+    /// Of the message before the pipeline, or empty when that isn't text
+    kind: String,
+    called: Instant,
+    processed: Instant,
+}
+
+impl SendStamps {
+    /// For `message`, whose send started at `called` and is through the
+    /// pipeline now.
+    pub fn new(message: &[u8], called: Instant) -> Self {
+        let kind = std::str::from_utf8(message).map_or("", message_kind);
+        Self {
+            kind: kind.to_string(),
+            called,
+            processed: Instant::now(),
+        }
+    }
+}
+
+/// When a datagram came off the socket and when it was queued for the app.
+#[derive(Clone, Copy)]
+pub struct Arrival {
+    read: Instant,
+    queued: Instant,
+}
+
+impl Arrival {
+    /// Read at `read`, through the pipeline and queued now.
+    pub fn new(read: Instant) -> Self {
+        Self {
+            read,
+            queued: Instant::now(),
+        }
+    }
+}
+
+/// Shared with the receive and sending threads.
+pub struct LatencyBudget {
+    /// Seconds, smoothed, in [`Stage::ALL`] order
+    stages: Mutex<[Ewma; Stage::ALL.len()]>,
+    /// The send stages of the last message of each kind that went out
+    last_sent: Mutex<HashMap<String, Duration>>,
+    /// When each message handled this frame was taken from the queue
+    handled: Mutex<Vec<Instant>>,
+}
+
+impl Default for LatencyBudget {
+    fn default() -> Self {
+        Self {
+            stages: Mutex::new([Ewma::new(DEFAULT_ALPHA); Stage::ALL.len()]),
+            last_sent: Mutex::default(),
+            handled: Mutex::default(),
+        }
+    }
+}
+
+impl LatencyBudget {
+    fn record(&self, stage: Stage, took: Duration) {
+        self.stages.lock().unwrap()[stage as usize].add(took.as_secs_f32());
+    }
+
+    /// Called as a datagram goes on the wire.
+    pub fn sent(&self, stamps: &SendStamps) {
+        let now = Instant::now();
+        self.record(Stage::EcsToWorker, stamps.processed - stamps.called);
+        self.record(Stage::WorkerToSocket, now - stamps.processed);
+        self.last_sent
+            .lock()
+            .unwrap()
+            .insert(stamps.kind.clone(), now - stamps.called);
+    }
+
+    /// Called as a message is taken from the receive queue; returns how long
+    /// it has been in the client.
+    pub fn received(&self, arrival: Arrival) -> Duration {
+        let now = Instant::now();
+        self.record(Stage::SocketToWorker, arrival.queued - arrival.read);
+        self.record(Stage::WorkerToEcs, now - arrival.queued);
+        self.handled.lock().unwrap().push(now);
+        now - arrival.read
+    }
+
+    /// How long the last `kind` took from `send_to` to the wire.
+    pub fn last_sent(&self, kind: &str) -> Option<Duration> {
+        self.last_sent.lock().unwrap().get(kind).copied()
+    }
+
+    /// One line per stage, for the F5 panel.
+    #[cfg(not(feature = "thin"))]
+    pub fn lines(&self) -> Vec<String> {
+        let stages = self.stages.lock().unwrap();
+        Stage::ALL
+            .iter()
+            .map(|&stage| match stages[stage as usize].value() {
+                Some(secs) => format!("{:<16} {:>7.2} ms", stage.label(), secs * 1000.0),
+                None => format!("{:<16} {:>7}", stage.label(), "-"),
+            })
+            .collect()
+    }
+}
+
+/// `Pong (rtt 41.2ms: 37.0 network, 4.2 in the client)`, for an RTT in
+/// seconds of which `in_client` was spent here.
+pub fn split_rtt(message: &str, rtt: f32, in_client: Duration) -> String {
+    let in_client = in_client.as_secs_f32();
+    format!(
+        "{} (rtt {:.1}ms: {:.1} network, {:.1} in the client)",
+        message,
+        rtt * 1000.0,
+        (rtt - in_client).max(0.0) * 1000.0,
+        in_client * 1000.0
+    )
+}
+
+/// Runs last in the frame, after everything that draws what came in.
+pub fn end_latency_frame(network: Res<NetworkState>) {
+    let now = Instant::now();
+    let handled = std::mem::take(&mut *network.latency.handled.lock().unwrap());
+    for taken in handled {
+        network.latency.record(Stage::EcsToUi, now - taken);
+    }
+}
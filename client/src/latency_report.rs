@@ -0,0 +1,499 @@
+//! The latency report, with `--latency-report <file.html>`: the run's RTT,
+//! loss, bandwidth and events as one standalone HTML page, for sharing a
+//! test run with people who never start the client.
+//!
+//! Every [`SAMPLE_SECS`] the client notes the smoothed RTT of Pings and of
+//! heartbeats, the Ping loss over the timeline's window (see `timeline`)
+//! and the bytes a second each way (see `traffic`). RTT and loss are only
+//! noted while connected, so a gap in the chart is time spent without a
+//! server. The events are every handshake stage change, disconnect and
+//! network error. A long run keeps its whole history by halving the
+//! resolution each time it reaches [`MAX_SAMPLES`].
+//!
+//! The page is written when the client closes, and in a window build F11
+//! writes it on demand with the run so far; either overwrites the file. The
+//! charts are inline SVG, so it needs no script, no network and no other
+//! file to show.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use common::disconnect::Disconnected;
+use common::error::NetError;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use crate::handshake::{Handshake, HandshakeStage};
+use crate::session::Session;
+use crate::timeline::PacketTimeline;
+use crate::{ClientState, NetworkState};
+
+/// Seconds between samples at the start of a run
+pub const SAMPLE_SECS: f32 = 1.0;
+/// Samples kept before neighbours are merged
+pub const MAX_SAMPLES: usize = 3600;
+/// Events kept; later ones are only counted
+const MAX_EVENTS: usize = 1000;
+
+const CHART_WIDTH: f32 = 900.0;
+const CHART_HEIGHT: f32 = 160.0;
+/// Room for the axis labels
+const MARGIN: f32 = 48.0;
+
+/// One sample, averaged over its interval
+#[derive(Clone, Copy)]
+struct Sample {
+    /// Seconds since the client started, at the interval's start
+    at: f32,
+    rtt_ms: Option<f32>,
+    ambient_ms: Option<f32>,
+    /// Percent
+    loss: Option<f32>,
+    sent_kbps: f32,
+    received_kbps: f32,
+}
+
+impl Sample {
+    /// One sample for `self` and the one after it
+    fn merge(self, next: Sample) -> Sample {
+        Sample {
+            at: self.at,
+            rtt_ms: mean(self.rtt_ms, next.rtt_ms),
+            ambient_ms: mean(self.ambient_ms, next.ambient_ms),
+            loss: mean(self.loss, next.loss),
+            sent_kbps: (self.sent_kbps + next.sent_kbps) / 2.0,
+            received_kbps: (self.received_kbps + next.received_kbps) / 2.0,
+        }
+    }
+}
+
+fn mean(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a + b) / 2.0),
+        (a, b) => a.or(b),
+    }
+}
+
+#[derive(Resource)]
+pub struct LatencyReport {
+    path: PathBuf,
+    /// Seconds between samples, doubled at every merge
+    interval: f32,
+    next_at: f32,
+    /// The traffic counters at the last sample
+    bytes_sent: u64,
+    bytes_received: u64,
+    samples: Vec<Sample>,
+    /// Seconds since the client started, and what happened
+    events: Vec<(f32, String)>,
+    /// Events past [`MAX_EVENTS`]
+    events_dropped: usize,
+    /// The handshake stage at the last frame, to see it change
+    stage: Option<HandshakeStage>,
+}
+
+impl LatencyReport {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            interval: SAMPLE_SECS,
+            next_at: SAMPLE_SECS,
+            bytes_sent: 0,
+            bytes_received: 0,
+            samples: Vec::new(),
+            events: Vec::new(),
+            events_dropped: 0,
+            stage: None,
+        }
+    }
+
+    fn event(&mut self, now: f32, what: String) {
+        if self.events.len() < MAX_EVENTS {
+            self.events.push((now, what));
+        } else {
+            self.events_dropped += 1;
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        self.samples.push(sample);
+        if self.samples.len() < MAX_SAMPLES {
+            return;
+        }
+        self.samples = self
+            .samples
+            .chunks(2)
+            .map(|pair| match pair {
+                [first, second] => first.merge(*second),
+                _ => pair[0],
+            })
+            .collect();
+        self.interval *= 2.0;
+    }
+}
+
+fn stage_event(stage: HandshakeStage, server: &str) -> String {
+    match stage {
+        HandshakeStage::Resolving => format!("resolving {}", server),
+        HandshakeStage::Waiting => format!("connecting to {}", server),
+        HandshakeStage::Scanning => format!("scanning for {}", server),
+        HandshakeStage::Connected => format!("connected to {}", server),
+        HandshakeStage::Failed => "connect attempt failed".to_string(),
+        HandshakeStage::Stopped => "stopped connecting".to_string(),
+    }
+}
+
+/// Takes a sample when one is due, and notes the frame's events.
+pub fn record_latency_report(
+    time: Res<Time>,
+    handshake: Res<Handshake>,
+    timeline: Res<PacketTimeline>,
+    network: Res<NetworkState>,
+    client_state: Res<ClientState>,
+    mut errors: EventReader<NetError>,
+    mut disconnects: EventReader<Disconnected>,
+    mut report: ResMut<LatencyReport>,
+) {
+    let now = time.elapsed_seconds();
+    if report.stage != Some(handshake.stage) {
+        report.stage = Some(handshake.stage);
+        report.event(
+            now,
+            stage_event(handshake.stage, &client_state.server_target),
+        );
+    }
+    for disconnect in disconnects.read() {
+        report.event(now, format!("disconnected: {}", disconnect.reason));
+    }
+    for error in errors.read() {
+        report.event(now, format!("network error: {}", error));
+    }
+
+    if now < report.next_at {
+        return;
+    }
+    let sent = network.counters.bytes_sent.load(Ordering::Relaxed);
+    let received = network.counters.bytes_received.load(Ordering::Relaxed);
+    let secs = now - (report.next_at - report.interval);
+    let connected = handshake.stage == HandshakeStage::Connected;
+    let sample = Sample {
+        at: report.next_at - report.interval,
+        rtt_ms: timeline
+            .rtt
+            .smoothed()
+            .filter(|_| connected)
+            .map(|rtt| rtt * 1000.0),
+        ambient_ms: timeline
+            .ambient_rtt
+            .smoothed()
+            .filter(|_| connected)
+            .map(|rtt| rtt * 1000.0),
+        loss: timeline
+            .loss()
+            .filter(|_| connected)
+            .map(|loss| loss * 100.0),
+        sent_kbps: (sent - report.bytes_sent) as f32 / 1000.0 / secs,
+        received_kbps: (received - report.bytes_received) as f32 / 1000.0 / secs,
+    };
+    report.bytes_sent = sent;
+    report.bytes_received = received;
+    report.push(sample);
+    report.next_at = now + report.interval;
+}
+
+/// Writes the page when the client closes, or on F11.
+pub fn write_latency_report(
+    time: Res<Time>,
+    #[cfg(not(feature = "thin"))] keys: Res<ButtonInput<KeyCode>>,
+    mut exits: EventReader<AppExit>,
+    network: Res<NetworkState>,
+    session: Res<Session>,
+    mut report: ResMut<LatencyReport>,
+    mut client_state: ResMut<ClientState>,
+) {
+    let now = time.elapsed_seconds();
+    let closing = exits.read().next().is_some();
+    #[cfg(not(feature = "thin"))]
+    let requested = keys.just_pressed(KeyCode::F11);
+    #[cfg(feature = "thin")]
+    let requested = false;
+    if !closing && !requested {
+        return;
+    }
+    if closing {
+        report.event(now, "client closed".to_string());
+    }
+    let session = session
+        .current()
+        .map(|stats| stats.summary(now, "still open"));
+    let page = render(&report, now, &network, &client_state.server_target, session);
+    let line = match fs::write(&report.path, page) {
+        Ok(()) => format!("[Info]: Wrote latency report {}", report.path.display()),
+        Err(e) => format!(
+            "[Error]: Could not write latency report {}: {}",
+            report.path.display(),
+            e
+        ),
+    };
+    info!("{}", line);
+    client_state.push_log(line);
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `m:ss`, or `h:mm:ss` past the hour
+fn clock(secs: f32) -> String {
+    let secs = secs.max(0.0) as u64;
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+/// `mean 41.2, min 30.1, max 80.0 ms`, over the samples that have a value
+fn describe_series(values: impl Iterator<Item = Option<f32>>, unit: &str) -> String {
+    let values: Vec<f32> = values.flatten().collect();
+    if values.is_empty() {
+        return "no data".to_string();
+    }
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(0.0, f32::max);
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    format!("mean {:.1}, min {:.1}, max {:.1} {}", mean, min, max, unit)
+}
+
+/// A line chart for `series`, each a colour, a name and a value per sample,
+/// over `span` seconds. A missing value breaks the line.
+fn chart(title: &str, unit: &str, samples: &[Sample], span: f32, series: &[Series]) -> String {
+    let top = series
+        .iter()
+        .flat_map(|series| samples.iter().filter_map(series.value))
+        .fold(0.0, f32::max)
+        .max(1.0)
+        * 1.1;
+    let x = |at: f32| MARGIN + at / span.max(1.0) * (CHART_WIDTH - MARGIN * 2.0);
+    let y = |value: f32| CHART_HEIGHT - MARGIN / 2.0 - value / top * (CHART_HEIGHT - MARGIN);
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<h2>{}</h2>\n<svg viewBox=\"0 0 {} {}\" width=\"{}\" height=\"{}\">\n",
+        escape(title),
+        CHART_WIDTH,
+        CHART_HEIGHT,
+        CHART_WIDTH,
+        CHART_HEIGHT
+    );
+    for value in [0.0, top / 2.0, top] {
+        let _ = writeln!(
+            svg,
+            "<line class=\"grid\" x1=\"{}\" y1=\"{y:.1}\" x2=\"{}\" y2=\"{y:.1}\"/>\
+             <text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{:.1} {}</text>",
+            MARGIN,
+            CHART_WIDTH - MARGIN,
+            MARGIN - 4.0,
+            y(value) + 4.0,
+            value,
+            escape(unit),
+            y = y(value)
+        );
+    }
+    for at in [0.0, span / 2.0, span] {
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            x(at),
+            CHART_HEIGHT - 2.0,
+            clock(at)
+        );
+    }
+    for series in series {
+        let mut points = Vec::new();
+        // Samples with no value end a run of points
+        for sample in samples {
+            match (series.value)(sample) {
+                Some(value) => points.push(format!("{:.1},{:.1}", x(sample.at), y(value))),
+                None => polyline(&mut svg, series.colour, &mut points),
+            }
+        }
+        polyline(&mut svg, series.colour, &mut points);
+    }
+    svg.push_str("</svg>\n<p class=\"legend\">");
+    for series in series {
+        let _ = write!(
+            svg,
+            "<span style=\"color:{}\">&#9632;</span> {}: {} ",
+            series.colour,
+            escape(series.name),
+            describe_series(samples.iter().map(series.value), unit)
+        );
+    }
+    svg.push_str("</p>\n");
+    svg
+}
+
+struct Series {
+    name: &'static str,
+    colour: &'static str,
+    value: fn(&Sample) -> Option<f32>,
+}
+
+/// Draws `points`, if there are any, and empties it.
+fn polyline(svg: &mut String, colour: &str, points: &mut Vec<String>) {
+    if points.is_empty() {
+        return;
+    }
+    let _ = writeln!(
+        svg,
+        "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>",
+        colour,
+        points.join(" ")
+    );
+    points.clear();
+}
+
+/// The events as ticks on a track, with the time and what happened in each
+/// one's tooltip, then as a table.
+fn event_timeline(report: &LatencyReport, span: f32) -> String {
+    let x = |at: f32| MARGIN + at / span.max(1.0) * (CHART_WIDTH - MARGIN * 2.0);
+    let mut html = format!(
+        "<h2>Events</h2>\n<svg viewBox=\"0 0 {w} 40\" width=\"{w}\" height=\"40\">\n\
+         <line class=\"grid\" x1=\"{}\" y1=\"20\" x2=\"{}\" y2=\"20\"/>\n",
+        MARGIN,
+        CHART_WIDTH - MARGIN,
+        w = CHART_WIDTH
+    );
+    for (at, what) in &report.events {
+        let colour = if what.starts_with("connected") {
+            "#2a2"
+        } else if what.starts_with("disconnected") || what.starts_with("network error") {
+            "#c22"
+        } else {
+            "#888"
+        };
+        let _ = writeln!(
+            html,
+            "<line x1=\"{x:.1}\" y1=\"10\" x2=\"{x:.1}\" y2=\"30\" stroke=\"{}\" \
+             stroke-width=\"2\"><title>{} {}</title></line>",
+            colour,
+            clock(*at),
+            escape(what),
+            x = x(*at)
+        );
+    }
+    html.push_str("</svg>\n<table>\n");
+    for (at, what) in &report.events {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            clock(*at),
+            escape(what)
+        );
+    }
+    html.push_str("</table>\n");
+    if report.events_dropped > 0 {
+        let _ = writeln!(
+            html,
+            "<p>{} later events not kept</p>",
+            report.events_dropped
+        );
+    }
+    html
+}
+
+fn render(
+    report: &LatencyReport,
+    now: f32,
+    network: &NetworkState,
+    server: &str,
+    session: Option<Vec<String>>,
+) -> String {
+    let counters = &network.counters;
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Latency report: {server}</title>\n<style>\n\
+         body {{ font-family: sans-serif; margin: 2em; color: #222; }}\n\
+         svg {{ font-size: 11px; background: #fafafa; }}\n\
+         .grid {{ stroke: #ddd; }}\n\
+         .legend {{ font-size: 13px; }}\n\
+         td {{ padding: 0 1em 0 0; font-family: monospace; }}\n\
+         </style>\n</head>\n<body>\n<h1>Latency report: {server}</h1>\n",
+        server = escape(server)
+    );
+    let _ = writeln!(
+        html,
+        "<p>Client {}, ran {}. Sent {} packets ({} bytes), received {} ({} bytes). \
+         One sample every {:.0}s.</p>",
+        env!("CARGO_PKG_VERSION"),
+        clock(now),
+        counters.packets_sent.load(Ordering::Relaxed),
+        counters.bytes_sent.load(Ordering::Relaxed),
+        counters.packets_received.load(Ordering::Relaxed),
+        counters.bytes_received.load(Ordering::Relaxed),
+        report.interval
+    );
+    let samples = &report.samples;
+    html.push_str(&chart(
+        "Round trip time",
+        "ms",
+        samples,
+        now,
+        &[
+            Series {
+                name: "ping",
+                colour: "#27c",
+                value: |sample| sample.rtt_ms,
+            },
+            Series {
+                name: "heartbeat",
+                colour: "#e80",
+                value: |sample| sample.ambient_ms,
+            },
+        ],
+    ));
+    html.push_str(&chart(
+        "Ping loss",
+        "%",
+        samples,
+        now,
+        &[Series {
+            name: "lost",
+            colour: "#c22",
+            value: |sample| sample.loss,
+        }],
+    ));
+    html.push_str(&chart(
+        "Bandwidth",
+        "kB/s",
+        samples,
+        now,
+        &[
+            Series {
+                name: "sent",
+                colour: "#27c",
+                value: |sample| Some(sample.sent_kbps),
+            },
+            Series {
+                name: "received",
+                colour: "#2a2",
+                value: |sample| Some(sample.received_kbps),
+            },
+        ],
+    ));
+    html.push_str(&event_timeline(report, now));
+    if let Some(lines) = session {
+        html.push_str("<h2>Session</h2>\n<pre>");
+        html.push_str(&escape(&lines.join("\n")));
+        html.push_str("</pre>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
@@ -1,208 +1,1187 @@
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
 use bevy::prelude::*;
-use std::net::UdpSocket;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use bevy_udp_net::memory::MemoryNetwork;
+use bevy_udp_net::{NetworkSocket, Receive, UdpNetworkPlugin};
 use clap::Parser;
+use common::bandwidth::{BandwidthMeter, BandwidthStats, METER_ORDER};
+use common::codec::{CODEC_ORDER, ChannelCodecs, Codecs};
+use common::compression::{
+    COMPRESSION_ORDER, CompressionPlugin, CompressionStats, DictCompression,
+};
+use common::disconnect::Disconnected;
+use common::error::{NetError, NetErrorPlugin, NetErrorSink};
+use common::handlers::{
+    ConfigUpdate, Disconnect, Handoff, Heartbeat, NetHandlerExt, NetHandlersPlugin, NetIncoming,
+    NetMessage, Reject, ReliableAck, ReliableData, Welcome,
+};
+use common::identity::VerifyServer;
+use common::message::{Message, Payload};
+use common::middleware::{PacketTrace, Pipeline, TRACE_ORDER};
+use common::packet_log::{PACKET_LOG_ORDER, PacketLog, Side};
+use common::players::PlayerCount;
+use common::presence::{LoggingPresence, PresencePlugin};
+use common::protocol;
+use common::replicate::{
+    ReplicateResourceExt, ReplicationIncoming, ReplicationPlugin, ReplicationRole,
+};
+use common::self_check::{report, run_self_checks};
+use common::session::message_kind;
+use common::shared_rng::SharedRng;
+use common::smoke_test;
+use common::stats::{DEFAULT_ALPHA, DEFAULT_WINDOWS};
+
+mod account;
+#[cfg(not(feature = "thin"))]
+mod address;
+#[cfg(feature = "bug-report")]
+mod bug_report;
+mod chaos;
+#[cfg(all(feature = "chat", not(feature = "thin")))]
+mod chat;
+#[cfg(not(feature = "thin"))]
+mod connect;
+#[cfg(feature = "thin")]
+mod console;
+mod crash;
+mod deprecation;
+#[cfg(not(feature = "thin"))]
+mod discovery;
+#[cfg(not(feature = "thin"))]
+mod effects;
+mod energy;
+#[cfg(not(feature = "thin"))]
+mod gui;
+mod handshake;
+#[cfg(not(feature = "thin"))]
+mod inspector;
+mod latency;
+mod latency_report;
+mod mock_server;
+mod outbound;
+mod pacing;
+mod ping_sweep;
+#[cfg(not(feature = "thin"))]
+mod playback;
+mod presence;
+#[cfg(not(feature = "thin"))]
+mod projectile;
+mod recovery;
+mod script;
+mod send_pacer;
+mod session;
+mod settings;
+#[cfg(not(feature = "thin"))]
+mod sprite;
+mod suspend;
+mod ticket;
+mod timeline;
+#[cfg(feature = "traceroute")]
+mod traceroute;
+mod traffic;
+mod transport;
+
+#[cfg(not(any(feature = "gui", feature = "thin")))]
+compile_error!("build the client with the `gui` feature (the default) or with `thin`");
+
+use account::{
+    AccountLink, flush_account_link, handle_account_acks, handle_account_replies,
+    log_in_after_welcome,
+};
+#[cfg(feature = "bug-report")]
+use bug_report::{BugReports, capture_bug_reports};
+use chaos::{Chaos, ChaosSwitches, run_chaos};
+#[cfg(not(feature = "thin"))]
+use common::ui::fonts::default_fonts;
+#[cfg(feature = "thin")]
+use console::ConsolePlugin;
+use crash::{CrashReporter, finish_crash_watch, send_crash_report};
+use deprecation::{Deprecation, note_deprecation};
+use energy::{NETWORK_CPU, NETWORK_WAKEUPS, PROCESS_CPU, Worker, WorkerMeters, measure_energy};
+#[cfg(not(feature = "thin"))]
+use gui::GuiPlugin;
+use handshake::{
+    Handshake, HandshakeStage, handle_disconnect, handle_handoff, handle_reject,
+    handshake_timeout_system, parse_port_range, reconnect_system, send_disconnect_on_exit,
+    start_attempt,
+};
+use latency::{Arrival, LatencyBudget, SendStamps, end_latency_frame, split_rtt};
+use latency_report::{LatencyReport, record_latency_report, write_latency_report};
+use mock_server::{LocalNetwork, MockConfig, deliver_local};
+use outbound::{OutboundQueue, flush_outbound};
+use pacing::{INPUT_SAMPLE_RATE, NET_TICK_RATE, Pacing, advance_pacing, net_tick};
+use presence::update_presence;
+use recovery::send_replication_nacks;
+use script::{ClientScript, ServerMessage, run_script, start_script};
+use send_pacer::{Queued, SendMode, SendPacer, Wire, release_paced_sends};
+use session::{Session, end_session_on_exit};
+use settings::{NetworkSettings, apply_config_updates};
+use std::ops::RangeInclusive;
+use suspend::{SuspendWatch, detect_resume};
+use timeline::{PacketTimeline, expire_timeline_marks};
+#[cfg(feature = "traceroute")]
+use traceroute::TraceroutePlugin;
+use traffic::TrafficCounters;
+use transport::{Inbox, TcpLink, Transport};
 
 #[derive(Parser, Resource, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Server address to connect to
+    /// Server address to connect to; a comma-separated list adds fallbacks,
+    /// tried in order when the one before fails
     #[arg(short, long, default_value = "127.0.0.1:12345")]
     server: String,
 
     /// Local port to bind to (0 for random)
     #[arg(short, long, default_value_t = 0)]
     port: u16,
+
+    /// Seconds to wait for a Pong before a Ping is marked as timed out
+    #[arg(long, default_value_t = 2.0)]
+    ack_timeout: f32,
+
+    /// Gain of the smoothed RTT and loss, 0..=1; higher follows changes faster
+    #[arg(long, default_value_t = DEFAULT_ALPHA)]
+    stats_alpha: f32,
+
+    /// Comma-separated seconds to average RTT and loss over, besides the smoothed value
+    #[arg(long, value_delimiter = ',', default_values_t = DEFAULT_WINDOWS)]
+    stats_windows: Vec<f32>,
+
+    /// Seconds between heartbeats that keep the server's view of us alive
+    #[arg(long, default_value_t = 1.0)]
+    heartbeat_interval: f32,
+
+    /// Send every heartbeat, even when other traffic already proves we're alive
+    #[arg(long)]
+    always_heartbeat: bool,
+
+    /// Network ticks per second; queued messages go out on a tick (0: every frame)
+    #[arg(long, default_value_t = 30.0)]
+    net_tick_hz: f32,
+
+    /// Input samples per second (0: every frame)
+    #[arg(long, default_value_t = 60.0)]
+    input_hz: f32,
+
+    /// Spread each frame's datagrams over the tick (paced) or send them at once (burst); F7 switches
+    #[arg(long, value_enum, default_value_t = SendMode::Paced)]
+    send_mode: SendMode,
+
+    /// Render as fast as possible instead of waiting for the display's refresh
+    #[cfg(not(feature = "thin"))]
+    #[arg(long)]
+    no_vsync: bool,
+
+    /// Show each PING's encoded packet and wait for SEND before it goes out (F6 toggles)
+    #[cfg(not(feature = "thin"))]
+    #[arg(long)]
+    inspect_sends: bool,
+
+    /// Seconds to wait for the server's Welcome before falling back or giving up
+    #[arg(long, default_value_t = 2.0)]
+    handshake_timeout: f32,
+
+    /// Seconds to wait for the server's hostname to resolve before the attempt fails
+    #[arg(long, default_value_t = 5.0)]
+    resolve_timeout: f32,
+
+    /// Connection attempts in a row before giving up (0 keeps trying)
+    #[arg(long, default_value_t = 5)]
+    handshake_attempts: u32,
+
+    /// Seconds after the first attempt to give up, however many are left (0 for no deadline)
+    #[arg(long, default_value_t = 0.0)]
+    handshake_deadline: f32,
+
+    /// Join as a spectator: receive everything, but the server ignores our inputs
+    #[arg(long)]
+    spectate: bool,
+
+    /// Protocol version to claim in the Hello; an older one the server still supports gets a
+    /// deprecation banner
+    #[arg(long, default_value_t = protocol::PROTOCOL_VERSION)]
+    protocol_version: u32,
+
+    /// Log in to this account after connecting (created on first use)
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Password for --user. It is sent unencrypted: use a throwaway one, on a trusted network
+    #[arg(long)]
+    password: Option<String>,
+
+    /// Port range (e.g. 12345-12350) to probe on the server host if it doesn't answer
+    #[arg(long, value_parser = parse_port_range)]
+    scan_ports: Option<RangeInclusive<u16>>,
+
+    /// Connect ticket to present, for a server started with --ticket-key
+    #[arg(long)]
+    ticket: Option<String>,
+
+    /// Fingerprint of the server's --identity-key; replies it didn't sign are dropped
+    #[arg(long)]
+    server_key: Option<String>,
+
+    /// Matchmaker (host:port) to get the server address and a connect ticket from
+    #[arg(long, conflicts_with = "ticket")]
+    matchmaker: Option<String>,
+
+    /// Invite code to join by: the matchmaker's ticket is for the server the code was made for
+    #[arg(long, requires = "matchmaker")]
+    join_code: Option<String>,
+
+    /// Transport to start on; the menu by the address field switches at runtime
+    #[arg(long, value_enum, default_value_t = Transport::Udp)]
+    transport: Transport,
+
+    /// Switch to TCP after this many failed UDP attempts in a row (0 never does)
+    #[arg(long, default_value_t = 0)]
+    tcp_fallback_after: u32,
+
+    /// After this many UDP attempts with nothing received, check whether TCP gets through (0 never checks)
+    #[arg(long, default_value_t = 2)]
+    detect_udp_block: u32,
+
+    /// Seconds to wait after a failed handshake before trying again
+    #[arg(long, default_value_t = 3.0)]
+    reconnect_delay: f32,
+
+    /// Seconds of silence from a connected server before reconnecting
+    #[arg(long, default_value_t = 5.0)]
+    server_timeout: f32,
+
+    /// Note a panic or unclean exit in this file and send it to the server on the next start (one client per path)
+    #[arg(long)]
+    crash_report: Option<std::path::PathBuf>,
+
+    /// Seconds the clock may jump between frames before we assume the machine slept and check the connection at once (0 turns it off)
+    #[arg(long, default_value_t = 5.0)]
+    suspend_gap: f32,
+
+    /// Largest datagram we accept, in bytes; bigger ones are dropped and counted
+    #[arg(long, default_value_t = 1024)]
+    recv_buffer: usize,
+
+    /// Largest message we send, in bytes; bigger ones are refused with an error
+    #[arg(long, default_value_t = 1024)]
+    max_message_size: usize,
+
+    /// Connect to a fake server inside this process that answers pings, heartbeats and
+    /// knock-knock jokes in chat (see `mock_server`), instead of a real one
+    #[arg(long, conflicts_with_all = ["server", "matchmaker", "compress", "codecs", "server_key"])]
+    mock_server: bool,
+
+    /// --mock-server over an in-memory network instead of 127.0.0.1, binding no socket at all,
+    /// for machines that don't allow it
+    #[arg(long, conflicts_with_all = ["mock_server", "server", "matchmaker", "compress", "codecs", "server_key", "transport", "ping_sweep", "validate"])]
+    local: bool,
+
+    /// Milliseconds the mock server holds each reply
+    #[arg(long, default_value_t = 40)]
+    mock_delay_ms: u64,
+
+    /// Up to this many milliseconds more, at random
+    #[arg(long, default_value_t = 20)]
+    mock_jitter_ms: u64,
+
+    /// Fraction of the mock server's replies it drops, 0..=1
+    #[arg(long, default_value_t = 0.0)]
+    mock_loss: f32,
+
+    /// Start with chaos mode on: random disconnects, garbage, duplicates and stalls
+    #[arg(long)]
+    chaos: bool,
+
+    /// Log every datagram sent and received
+    #[arg(long)]
+    trace_packets: bool,
+
+    /// Write every datagram sent and received to this file, for `logdiff`
+    #[arg(long)]
+    packet_log: Option<std::path::PathBuf>,
+
+    /// Compress datagrams with the protocol dictionary; needs a server started with --compress
+    #[arg(long)]
+    compress: bool,
+
+    /// How a channel is encoded, e.g. data=json (repeatable; see `common::codec`);
+    /// the server has to use the same
+    #[arg(long = "codec")]
+    codecs: Vec<String>,
+
+    /// Print the wire protocol as JSON and exit
+    #[arg(long)]
+    dump_protocol: bool,
+
+    /// Run the self-checks and a loopback session against a built-in peer,
+    /// print the results as JSON and exit: 0 if all passed, 1 if not
+    #[arg(long)]
+    validate: bool,
+
+    /// Send Heartbeats padded from 1 byte to --sweep-max, print the RTT and loss of each size
+    /// and exit, to find where the path fragments or drops big datagrams (see `ping_sweep`)
+    #[arg(long)]
+    ping_sweep: bool,
+
+    /// Largest payload the ping sweep tries, in bytes
+    #[arg(long, default_value_t = 1600)]
+    sweep_max: usize,
+
+    /// Bytes the ping sweep's payload grows by from one size to the next
+    #[arg(long, default_value_t = 32)]
+    sweep_step: usize,
+
+    /// Probes the ping sweep sends of each size
+    #[arg(long, default_value_t = 3)]
+    sweep_probes: u32,
+
+    /// Identical messages sent within this many milliseconds are coalesced (0 disables)
+    #[arg(long, default_value_t = 250)]
+    debounce_ms: u64,
+
+    /// Rhai script that drives the client (see client/src/script.rs for the API)
+    #[arg(long)]
+    script: Option<String>,
+
+    /// Trace the route to the server after connecting, to compare hop latency with the RTT (F8 traces too)
+    #[cfg(feature = "traceroute")]
+    #[arg(long)]
+    traceroute: bool,
+
+    /// Append a summary of every finished session to this file
+    #[arg(long)]
+    session_log: Option<std::path::PathBuf>,
+
+    /// Write a bug-report zip to this directory on a network error or replication desync (F9 too)
+    #[cfg(feature = "bug-report")]
+    #[arg(long)]
+    bug_reports: Option<std::path::PathBuf>,
+
+    /// Write the run's RTT, loss, bandwidth and events to this HTML file on exit (F11 too)
+    #[arg(long)]
+    latency_report: Option<std::path::PathBuf>,
+
+    /// Times a second to send where the arrow-key square is (see `sprite`)
+    #[cfg(not(feature = "thin"))]
+    #[arg(long, default_value_t = 20.0)]
+    position_hz: f32,
+
+    /// Play back a session the server recorded with --record-sessions instead of connecting
+    #[cfg(not(feature = "thin"))]
+    #[arg(long)]
+    play_replay: Option<std::path::PathBuf>,
+
+    /// Font files for UI text, in fallback order (repeat to add more)
+    #[cfg(not(feature = "thin"))]
+    #[arg(long = "font", default_values_t = default_fonts())]
+    fonts: Vec<String>,
 }
 
 #[derive(Resource)]
 struct NetworkState {
-    received_message: Arc<Mutex<Option<String>>>,
-    socket: Arc<UdpSocket>,
+    /// (Message, SenderAddress, Arrival), oldest first; all of it is handled
+    /// each frame
+    received_messages: Arc<Mutex<VecDeque<(String, String, Arrival)>>>,
+    socket: NetworkSocket,
+    counters: Arc<TrafficCounters>,
+    errors: NetErrorSink,
+    /// `--max-message-size`
+    max_message_size: usize,
+    /// Chaos mode's hold on outgoing datagrams
+    chaos: ChaosSwitches,
+    pipeline: Pipeline,
+    /// Carries everything instead of the socket while we're on TCP
+    tcp: Arc<TcpLink>,
+    /// `--send-mode`
+    pacer: SendPacer,
+    /// `--recv-buffer`
+    recv_buffer: usize,
+    /// `--codec`, which the Hello names
+    codecs: Codecs,
+    latency: Arc<LatencyBudget>,
+    /// The worker threads' wakeups and CPU time
+    energy: Arc<WorkerMeters>,
 }
 
-impl Default for NetworkState {
-    fn default() -> Self {
-        Self {
-            received_message: Arc::new(Mutex::new(None)),
-            // This default is unused since we initialize in setup_network
-            socket: Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap()),
+impl NetworkState {
+    /// For the TCP link's reader
+    fn inbox(&self) -> Inbox {
+        Inbox {
+            received: self.received_messages.clone(),
+            counters: self.counters.clone(),
+            errors: self.errors.clone(),
+            pipeline: self.pipeline.clone(),
+            buf_size: self.recv_buffer,
+        }
+    }
+
+    fn wire(&self) -> Wire {
+        Wire {
+            socket: self.socket.clone(),
+            tcp: self.tcp.clone(),
+            counters: self.counters.clone(),
+            errors: self.errors.clone(),
+            latency: self.latency.clone(),
+            energy: self.energy.clone(),
+        }
+    }
+
+    fn send_to(&self, bytes: &[u8], addr: impl std::net::ToSocketAddrs + std::fmt::Display) {
+        let called = Instant::now();
+        let deadline = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(protocol::ttl)
+            .map(|ttl| called + ttl);
+        if let Some(processed) = self.pipeline.outgoing(&addr.to_string(), bytes) {
+            self.send_queued(Queued {
+                stamps: SendStamps::new(bytes, called),
+                bytes: processed,
+                addr: addr.to_string(),
+                deadline,
+            });
+        }
+    }
+
+    /// Sends bytes that have already been through the middleware pipeline.
+    fn send_processed(&self, bytes: &[u8], addr: impl std::fmt::Display) {
+        self.send_queued(Queued {
+            bytes: bytes.to_vec(),
+            addr: addr.to_string(),
+            deadline: None,
+            stamps: SendStamps::new(bytes, Instant::now()),
+        });
+    }
+
+    /// Hands a datagram that's through the pipeline to chaos mode and the
+    /// pacer, which drops it if it's still queued at its deadline; see
+    /// `send_pacer`.
+    fn send_queued(&self, send: Queued) {
+        if !self
+            .errors
+            .within_max_size(&send.addr, &send.bytes, self.max_message_size)
+        {
+            return;
+        }
+        let wire = self.wire();
+        for _ in 0..self.chaos.intercept(&send) {
+            self.pacer.send(&wire, send.clone());
         }
     }
 }
 
+#[derive(Resource)]
+struct HeartbeatTimer(Timer);
+
+/// Longest a busy connection goes without a heartbeat, and so without a
+/// fresh ambient RTT; see `timeline`
+const AMBIENT_REFRESH_SECS: f32 = 5.0;
+/// Heartbeats actually sent per second, after piggybacking suppression.
+const HEARTBEAT_RATE: DiagnosticPath = DiagnosticPath::const_new("network/heartbeat_rate");
+/// Datagrams from anyone but our server dropped since startup; see
+/// `expected_sender`
+const STRAY_PACKETS: DiagnosticPath = DiagnosticPath::const_new("network/stray_packets");
+/// Real-time messages dropped at send because they outlived their TTL
+/// since startup; see `send_pacer`
+const STALE_DROPPED: DiagnosticPath = DiagnosticPath::const_new("network/stale_dropped");
+
 #[derive(Resource, Default)]
 struct ClientState {
     has_connected: bool,
+    /// What the user asked to connect to: `--server` or the address field.
+    /// Resolved again on every connection attempt.
+    server_target: String,
+    /// Every address `--server` (or the address field) lists, primary
+    /// first; `server_target` is one of them
+    endpoints: Vec<String>,
+    /// The resolved server we talk to; a port scan may move it
+    server_addr: String,
+    /// Set when the server's Welcome advertises a separate data port
+    data_addr: Option<String>,
+    /// (server address, token) from the last Welcome, or the target and
+    /// token of a Handoff. A reconnect to that address presents the token
+    /// in a Resume instead of a Hello.
+    resume: Option<(String, String)>,
+    /// Presented in every Hello and Resume; `--ticket`, or the matchmaker's
+    ticket: Option<String>,
+    /// `--join-code` or the code field; the matchmaker's tickets are for
+    /// its server
+    join_code: Option<String>,
+    /// What the next attempt connects over
+    transport: Transport,
     log: Vec<String>,
+    /// More of the log than the panel shows, for bug reports
+    #[cfg(feature = "bug-report")]
+    log_tail: VecDeque<String>,
 }
 
-fn main() {
-    let args = Args::parse();
+impl ClientState {
+    fn new(servers: &str) -> Self {
+        let mut state = Self::default();
+        state.set_endpoints(servers);
+        state.server_addr = state.server_target.clone();
+        state
+    }
+
+    /// Connects to the first address of a comma-separated list from now on,
+    /// with the rest as its fallbacks
+    fn set_endpoints(&mut self, servers: &str) {
+        self.endpoints = servers
+            .split(',')
+            .map(str::trim)
+            .filter(|server| !server.is_empty())
+            .map(str::to_string)
+            .collect();
+        self.server_target = self.endpoints.first().cloned().unwrap_or_default();
+    }
+
+    /// Moves `server_target` on to the next endpoint, from the last back to
+    /// the primary. False when there are no fallbacks.
+    fn next_endpoint(&mut self) -> bool {
+        if self.endpoints.len() < 2 {
+            return false;
+        }
+        let current = self
+            .endpoints
+            .iter()
+            .position(|endpoint| *endpoint == self.server_target);
+        let next = current.map_or(0, |index| (index + 1) % self.endpoints.len());
+        self.server_target = self.endpoints[next].clone();
+        true
+    }
 
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_resource(args)
-        .init_resource::<ClientState>()
-        .add_systems(Startup, (setup_network, setup_ui))
-        .add_systems(
-            Update,
-            (handle_network_messages, ping_button_system, update_log_ui),
-        )
-        .run();
+    /// ` (endpoint 2 of 3)` for the header while there are fallbacks
+    fn endpoint_position(&self) -> String {
+        let current = self
+            .endpoints
+            .iter()
+            .position(|endpoint| *endpoint == self.server_target);
+        match current {
+            Some(index) if self.endpoints.len() > 1 => {
+                format!(" (endpoint {} of {})", index + 1, self.endpoints.len())
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Where gameplay messages go: the data port if the server has one.
+    fn gameplay_addr(&self) -> &str {
+        self.data_addr.as_deref().unwrap_or(&self.server_addr)
+    }
+
+    fn push_log(&mut self, entry: String) {
+        // A thin build has no log panel; stdout is the log
+        #[cfg(feature = "thin")]
+        println!("{}", entry);
+        #[cfg(feature = "bug-report")]
+        {
+            self.log_tail.push_back(entry.clone());
+            if self.log_tail.len() > bug_report::LOG_LINES {
+                self.log_tail.pop_front();
+            }
+        }
+        self.log.push(entry);
+        if self.log.len() > 20 {
+            self.log.remove(0);
+        }
+    }
 }
 
-fn setup_network(mut commands: Commands, args: Res<Args>) {
-    let bind_addr = format!("0.0.0.0:{}", args.port);
-    let socket = Arc::new(UdpSocket::bind(&bind_addr).expect("Failed to bind socket"));
-    println!("Client bound to {}", bind_addr);
-
-    socket
-        .set_nonblocking(true)
-        .expect("Failed to set non-blocking");
-
-    let received_message = Arc::new(Mutex::new(None));
-    let socket_clone = socket.clone();
-    let received_clone = received_message.clone();
-
-    thread::spawn(move || {
-        let mut buf = [0u8; 1024];
-        loop {
-            match socket_clone.recv_from(&mut buf) {
-                Ok((size, _addr)) => {
-                    let message = String::from_utf8_lossy(&buf[..size]).to_string();
-                    let mut received = received_clone.lock().unwrap();
-                    *received = Some(message);
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(10));
-                }
-                Err(_) => break,
+/// Turns the server's `Welcome data_port=N` into the address of its data socket.
+fn parse_data_addr(welcome: &str, server: &str) -> Option<String> {
+    let port = Welcome::parse(welcome)?.data_port?;
+    let (host, _) = server.rsplit_once(':')?;
+    Some(format!("{}:{}", host, port))
+}
+
+fn main() {
+    let mut args = Args::parse();
+    if args.dump_protocol {
+        println!("{}", protocol::to_json(args.max_message_size));
+        return;
+    }
+    if args.validate {
+        let result = smoke_test::run(Side::Client, &[args.port], args.recv_buffer);
+        println!("{}", result.to_json());
+        std::process::exit(if result.ok() { 0 } else { 1 });
+    }
+    if args.ping_sweep {
+        ping_sweep::run(&args);
+        return;
+    }
+    #[cfg(not(feature = "thin"))]
+    if let Some(path) = &args.play_replay {
+        playback::run(&args, path);
+        return;
+    }
+    let local = args.local.then(MemoryNetwork::default);
+    if args.mock_server || local.is_some() {
+        let config = MockConfig {
+            delay: Duration::from_millis(args.mock_delay_ms),
+            jitter: Duration::from_millis(args.mock_jitter_ms),
+            loss: args.mock_loss,
+        };
+        match mock_server::start(config, local.as_ref()) {
+            Ok(addr) => {
+                let memory = if local.is_some() { " (in memory)" } else { "" };
+                println!("Mock server on {}{}", addr, memory);
+                args.server = addr.to_string();
+            }
+            Err(e) => {
+                eprintln!("Error: could not start the mock server: {}", e);
+                std::process::exit(1);
             }
         }
-    });
+    }
+    let crash_reporter = args.crash_report.as_deref().map(CrashReporter::install);
+    #[cfg(feature = "bug-report")]
+    let bug_reports = args.bug_reports.clone().map(BugReports::new);
+    let latency_report = args.latency_report.clone().map(LatencyReport::new);
+    // Runs before binding, or the port check would find our own socket
+    let mut client_state = ClientState::new(&args.server);
+    client_state.ticket = args.ticket.clone();
+    client_state.join_code = args.join_code.clone();
+    client_state.transport = args.transport;
+    // They bind sockets, which is what `--local` does without
+    if local.is_none() {
+        for line in report(&run_self_checks(&[args.port], args.recv_buffer)) {
+            client_state.push_log(line);
+        }
+    }
+    let errors = NetErrorSink::default();
+    let pipeline = Pipeline::default();
+    if args.trace_packets {
+        pipeline.add(TRACE_ORDER, PacketTrace);
+    }
+    let bandwidth = BandwidthStats::default();
+    pipeline.add(METER_ORDER, BandwidthMeter::new(bandwidth.clone()));
+    if let Some(key) = &args.server_key {
+        VerifyServer::new(key, errors.clone()).install(&pipeline);
+    }
+    let codecs = match Codecs::from_args(&args.codecs) {
+        Ok(codecs) => codecs,
+        Err(e) => {
+            eprintln!("Error: --codec: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !args.codecs.is_empty() {
+        pipeline.add(CODEC_ORDER, ChannelCodecs::new(codecs.clone()));
+    }
+    let compression = CompressionStats::default();
+    if args.compress {
+        match DictCompression::always(args.recv_buffer, compression.clone()) {
+            Ok(stage) => pipeline.add(COMPRESSION_ORDER, stage),
+            Err(e) => {
+                eprintln!("Error: could not set up compression: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let network = match bind_network(
+        &args,
+        errors.clone(),
+        pipeline.clone(),
+        codecs,
+        local.as_ref(),
+    ) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // After binding, for the port `--port 0` got us
+    if let Some(path) = &args.packet_log {
+        let port = network
+            .socket
+            .local_addr()
+            .map_or(args.port, |addr| addr.port());
+        match PacketLog::create(path, Side::Client, port) {
+            Ok(stage) => pipeline.add(PACKET_LOG_ORDER, stage),
+            Err(e) => {
+                eprintln!("Error: could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let timeline = PacketTimeline::new(args.ack_timeout, args.stats_alpha, &args.stats_windows);
+    let handshake = Handshake::new(args.handshake_timeout, args.handshake_attempts);
+    let script = args
+        .script
+        .as_deref()
+        .map(|path| match ClientScript::load(path) {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("Error: could not load script {}", e);
+                std::process::exit(1);
+            }
+        });
+    let session = Session::new(args.session_log.clone());
+    let outbound = OutboundQueue::new(args.debounce_ms as f32 / 1000.0);
+    let heartbeat = HeartbeatTimer(Timer::from_seconds(
+        args.heartbeat_interval,
+        TimerMode::Repeating,
+    ));
 
-    commands.insert_resource(NetworkState {
-        received_message,
-        socket,
+    let chaos = Chaos::new(args.chaos);
+    let pacing = Pacing::new(args.net_tick_hz, args.input_hz);
+    let settings = NetworkSettings::from_args(&args);
+
+    let mut app = App::new();
+    #[cfg(not(feature = "thin"))]
+    app.add_plugins(GuiPlugin {
+        fonts: args.fonts.clone(),
+        vsync: !args.no_vsync,
+        inspect_sends: args.inspect_sends,
+        position_hz: args.position_hz,
     });
+    #[cfg(feature = "thin")]
+    app.add_plugins(ConsolePlugin);
+    if let Some(network) = local {
+        app.insert_resource(LocalNetwork(network))
+            .add_systems(First, deliver_local);
+    }
+    #[cfg(feature = "traceroute")]
+    app.add_plugins(TraceroutePlugin {
+        on_connect: args.traceroute,
+    });
+    let receiver = UdpReceiver {
+        inbox: network.inbox(),
+        energy: network.energy.clone(),
+    };
+    app.add_plugins(UdpNetworkPlugin::with_receiver(
+        network.socket.clone(),
+        receiver,
+    ))
+    .insert_resource(errors)
+    .insert_resource(pipeline)
+    .add_plugins(NetErrorPlugin)
+    .add_plugins(CompressionPlugin { stats: compression })
+    .insert_resource(bandwidth)
+    .add_plugins(ReplicationPlugin {
+        role: ReplicationRole::Client,
+    })
+    .replicate_resource::<PlayerCount>()
+    .add_plugins(PresencePlugin::<LoggingPresence>::default())
+    .add_plugins(NetHandlersPlugin)
+    .add_net_handler::<Reject, _>(handle_reject)
+    .add_net_handler::<Disconnect, _>(handle_disconnect)
+    .add_net_handler::<Handoff, _>(handle_handoff)
+    .add_net_handler::<Welcome, _>(log_in_after_welcome)
+    .add_net_handler::<Welcome, _>(send_crash_report.after(log_in_after_welcome))
+    .add_net_handler::<ReliableData, _>(handle_account_replies)
+    .add_net_handler::<ReliableAck, _>(handle_account_acks)
+    .add_net_handler::<ConfigUpdate, _>(apply_config_updates)
+    .add_net_handler::<Welcome, _>(note_deprecation)
+    .init_resource::<Deprecation>()
+    .insert_resource(settings)
+    .init_resource::<AccountLink>()
+    .add_event::<Disconnected>()
+    .insert_resource(args)
+    .insert_resource(network)
+    .insert_resource(timeline)
+    .insert_resource(heartbeat)
+    .register_diagnostic(Diagnostic::new(HEARTBEAT_RATE))
+    .register_diagnostic(Diagnostic::new(STRAY_PACKETS))
+    .register_diagnostic(Diagnostic::new(STALE_DROPPED))
+    .register_diagnostic(Diagnostic::new(NET_TICK_RATE))
+    .register_diagnostic(Diagnostic::new(INPUT_SAMPLE_RATE))
+    .register_diagnostic(Diagnostic::new(PROCESS_CPU))
+    .register_diagnostic(Diagnostic::new(NETWORK_CPU))
+    .register_diagnostic(Diagnostic::new(NETWORK_WAKEUPS))
+    .insert_resource(pacing)
+    .insert_resource(handshake)
+    .insert_resource(client_state)
+    .insert_resource(outbound)
+    .insert_resource(session)
+    .insert_resource(chaos)
+    .init_resource::<SuspendWatch>()
+    .add_event::<ServerMessage>()
+    .add_systems(Startup, start_handshake)
+    .add_systems(First, advance_pacing)
+    .add_systems(
+        Update,
+        (
+            handle_network_messages,
+            expire_timeline_marks,
+            send_heartbeat,
+            handshake_timeout_system,
+            reconnect_system,
+            detect_resume.before(reconnect_system),
+            report_truncated_packets,
+            flush_outbound.run_if(net_tick),
+            log_net_errors,
+            run_chaos.before(handle_network_messages),
+        ),
+    )
+    .add_systems(Update, update_presence.after(handle_network_messages))
+    .add_systems(
+        Update,
+        (measure_stray_packets, measure_stale_drops, measure_energy).after(handle_network_messages),
+    )
+    .add_systems(Update, flush_account_link.run_if(net_tick))
+    .add_systems(Update, send_replication_nacks)
+    .add_systems(
+        Last,
+        (
+            send_disconnect_on_exit,
+            end_session_on_exit,
+            finish_crash_watch,
+        ),
+    )
+    .add_systems(Last, release_paced_sends.after(send_disconnect_on_exit))
+    .add_systems(Last, end_latency_frame)
+    .add_systems(
+        Startup,
+        start_script
+            .after(start_handshake)
+            .run_if(resource_exists::<ClientScript>),
+    )
+    .add_systems(
+        Update,
+        run_script
+            .after(handle_network_messages)
+            .before(flush_outbound)
+            .run_if(resource_exists::<ClientScript>),
+    );
+    if let Some(script) = script {
+        app.insert_resource(script);
+    }
+    if let Some(crash_reporter) = crash_reporter {
+        app.insert_resource(crash_reporter);
+    }
+    #[cfg(feature = "bug-report")]
+    if let Some(bug_reports) = bug_reports {
+        app.insert_resource(bug_reports)
+            .add_systems(Update, capture_bug_reports.after(handle_network_messages));
+    }
+    if let Some(latency_report) = latency_report {
+        app.insert_resource(latency_report)
+            .add_systems(Update, record_latency_report.after(handle_network_messages))
+            .add_systems(
+                Last,
+                write_latency_report
+                    .after(send_disconnect_on_exit)
+                    .before(end_session_on_exit),
+            );
+    }
+    app.run();
 }
 
-#[derive(Component)]
-struct LogText;
-
-#[derive(Component)]
-struct PingButton;
-
-fn setup_ui(mut commands: Commands, args: Res<Args>) {
-    commands.spawn(Camera2dBundle::default());
-
-    // Status Header
-    commands.spawn(
-        TextBundle::from_section(
-            format!("Client connecting to {}", args.server),
-            TextStyle {
-                font_size: 20.0,
-                color: Color::rgb(0.9, 0.9, 0.9),
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(10.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
+/// Binds the socket, on `local` with `--local`. Runs before the app is
+/// built so a port that's already taken ends the program with a clear error
+/// instead of a panic.
+fn bind_network(
+    args: &Args,
+    errors: NetErrorSink,
+    pipeline: Pipeline,
+    codecs: Codecs,
+    local: Option<&MemoryNetwork>,
+) -> Result<NetworkState, NetError> {
+    let bind_addr = format!("0.0.0.0:{}", args.port);
+    let socket = match local {
+        Some(network) => network.bind(&bind_addr, args.recv_buffer),
+        None => NetworkSocket::bind(&bind_addr, args.recv_buffer),
+    }
+    .map_err(|source| NetError::Bind {
+        addr: bind_addr.clone(),
+        source,
+    })?;
+    let memory = if local.is_some() { " (in memory)" } else { "" };
+    println!("Client bound to {}{}", bind_addr, memory);
+
+    let received_messages = Arc::new(Mutex::new(VecDeque::new()));
+    let counters = Arc::new(TrafficCounters::default());
+    let energy = Arc::new(WorkerMeters::default());
+    let tcp = Arc::new(TcpLink::default());
+    let latency = Arc::new(LatencyBudget::default());
+    let pacer = SendPacer::new(
+        args.send_mode,
+        Wire {
+            socket: socket.clone(),
+            tcp: tcp.clone(),
+            counters: counters.clone(),
+            errors: errors.clone(),
+            latency: latency.clone(),
+            energy: energy.clone(),
+        },
     );
+    Ok(NetworkState {
+        received_messages,
+        socket,
+        counters,
+        errors,
+        max_message_size: args.max_message_size,
+        chaos: ChaosSwitches::default(),
+        pipeline,
+        tcp,
+        pacer,
+        recv_buffer: args.recv_buffer,
+        codecs,
+        latency,
+        energy,
+    })
+}
 
-    // Log Area
-    commands.spawn((
-        TextBundle::from_section(
-            "Ready to ping...\n",
-            TextStyle {
-                font_size: 16.0,
-                color: Color::WHITE,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(50.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
-        LogText,
-    ));
+fn start_handshake(
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+) {
+    start_attempt(&network, &args, &mut handshake, &mut client_state);
+}
 
-    commands
-        .spawn((
-            ButtonBundle {
-                style: Style {
-                    width: Val::Px(120.0),
-                    height: Val::Px(50.0),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    position_type: PositionType::Absolute,
-                    bottom: Val::Px(20.0),
-                    right: Val::Px(20.0),
-                    ..default()
-                },
-                background_color: Color::rgb(0.3, 0.5, 0.9).into(),
-                ..default()
-            },
-            PingButton,
-        ))
-        .with_children(|parent| {
-            parent.spawn(TextBundle::from_section(
-                "PING",
-                TextStyle {
-                    font_size: 24.0,
-                    color: Color::WHITE,
-                    ..default()
-                },
-            ));
-        });
+/// What the receive thread does with a datagram: the pipeline, then the
+/// queue the TCP reader fills too
+struct UdpReceiver {
+    inbox: Inbox,
+    energy: Arc<WorkerMeters>,
+}
+
+impl Receive for UdpReceiver {
+    fn received(&mut self, from: SocketAddr, bytes: &[u8]) {
+        let read = Instant::now();
+        self.inbox.counters.record_received(bytes.len());
+        let from = from.to_string();
+        let Some(bytes) = self.inbox.pipeline.incoming(&from, bytes.to_vec()) else {
+            return;
+        };
+        // Bincode, if the server sent it without the codec stage
+        let message = match Payload::read(&bytes) {
+            Ok(Payload::Message(message)) => message.to_string(),
+            Ok(Payload::Text(text)) => text.into_owned(),
+            Err(e) => {
+                warn!("Dropped a datagram from {}: {}", from, e);
+                return;
+            }
+        };
+        let mut received = self.inbox.received.lock().unwrap();
+        received.push_back((message, from, Arrival::new(read)));
+    }
+
+    fn oversized(&mut self, _from: Option<SocketAddr>, _size: usize) {
+        self.inbox
+            .counters
+            .truncated_packets
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn failed(&mut self, error: std::io::Error) {
+        self.inbox.errors.push(NetError::Recv(error));
+    }
+
+    fn woke(&mut self) {
+        self.energy.woke(Worker::Receive);
+    }
 }
 
-fn handle_network_messages(network: Res<NetworkState>, mut client_state: ResMut<ClientState>) {
-    let mut received = network.received_message.lock().unwrap();
-    if let Some(message) = received.take() {
+fn handle_network_messages(
+    mut commands: Commands,
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut client_state: ResMut<ClientState>,
+    mut timeline: ResMut<PacketTimeline>,
+    mut handshake: ResMut<Handshake>,
+    mut server_messages: EventWriter<ServerMessage>,
+    mut session: ResMut<Session>,
+    mut replication: EventWriter<ReplicationIncoming>,
+    mut incoming: EventWriter<NetIncoming>,
+) {
+    // Everything that arrived since the last frame, so a burst doesn't
+    // queue up behind the frame rate
+    let arrived: Vec<_> = network
+        .received_messages
+        .lock()
+        .unwrap()
+        .drain(..)
+        .collect();
+    for (message, from, arrival) in arrived {
+        // Checked one by one, as a Welcome can move us to another address
+        if !expected_sender(&client_state, &handshake, &from) {
+            network
+                .counters
+                .stray_packets
+                .fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        let in_client = network.latency.received(arrival);
+        let now = time.elapsed_seconds();
+        if from == client_state.server_addr
+            || client_state.data_addr.as_deref() == Some(from.as_str())
+        {
+            handshake.last_heard = now;
+        }
+        session.record_received(message_kind(&message), message.len());
+        incoming.send(NetIncoming {
+            from: from.clone(),
+            text: message.clone(),
+        });
+        if message == "HeartbeatAck" {
+            if let Some(rtt) = timeline.ack_heartbeat(time.elapsed_seconds_f64()) {
+                session.record_ambient_rtt(rtt);
+            }
+            continue;
+        }
+        let kind = message_kind(&message);
+        if kind == Reject::KIND || kind == Disconnect::KIND || kind == Handoff::KIND {
+            // Logged by `handshake::handle_reject`, `handle_disconnect` and `handle_handoff`
+            continue;
+        }
+        if kind == ReliableData::KIND || kind == ReliableAck::KIND {
+            // Login traffic; `account` logs the replies
+            continue;
+        }
+        if message.starts_with("REPL ") {
+            // Replicated state (player count); shown in the footer, not logged
+            replication.send(ReplicationIncoming(message));
+            continue;
+        }
+
         client_state.has_connected = true;
-        let log_entry = format!("[Rx]: {}", message);
-        client_state.log.push(log_entry);
-        if client_state.log.len() > 20 {
-            client_state.log.remove(0);
+        let mut rtt = None;
+        let mut line = format!("[Rx]: {}", message);
+        if let Some(Message::Pong { seq }) = Message::parse(&message) {
+            rtt = timeline.ack(seq, time.elapsed_seconds_f64());
+            if let Some(rtt) = rtt {
+                session.record_rtt(rtt);
+                // The Ping's time in the client, and the Pong's so far
+                if let Some(sent) = network.latency.last_sent("Ping") {
+                    line = format!("[Rx]: {}", split_rtt(&message, rtt, sent + in_client));
+                }
+            }
+        }
+        client_state.push_log(line);
+        server_messages.send(ServerMessage {
+            text: message.clone(),
+            rtt,
+        });
+        if message.starts_with("Welcome") && handshake.complete(now) {
+            if from != client_state.server_addr {
+                client_state.push_log(format!("[Info]: Server found on {}", from));
+            }
+            client_state.data_addr = parse_data_addr(&message, &from);
+            if let Some(welcome) = Welcome::parse(&message) {
+                if welcome.resumed {
+                    client_state.push_log("[Info]: Server continued our session".to_string());
+                }
+                client_state.resume = welcome.token.map(|token| (from.clone(), token));
+                if let Some(seed) = welcome.seed {
+                    commands.insert_resource(SharedRng::new(seed));
+                    client_state.push_log(format!("[Info]: Shared seed {:x}", seed));
+                }
+            }
+            session.start(&from, now);
+            session.record_received("Welcome", message.len());
+            client_state.server_addr = from;
+            if let Some(data_addr) = client_state.data_addr.clone() {
+                client_state.push_log(format!(
+                    "[Info]: Gameplay traffic now goes to {}",
+                    data_addr
+                ));
+            }
         }
     }
 }
 
-fn update_log_ui(client_state: Res<ClientState>, mut query: Query<&mut Text, With<LogText>>) {
-    if client_state.is_changed() {
-        for mut text in query.iter_mut() {
-            text.sections[0].value = client_state.log.join("\n");
-        }
+/// Whether `from` is the server we're talking to: its control or data
+/// address, or, while scanning for its port, any port on its host. Anything
+/// else, from another host or with a forged source, never reaches the log
+/// or the handshake.
+fn expected_sender(client_state: &ClientState, handshake: &Handshake, from: &str) -> bool {
+    if from == client_state.server_addr || client_state.data_addr.as_deref() == Some(from) {
+        return true;
     }
+    let host = |addr: &str| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip());
+    handshake.stage == HandshakeStage::Scanning
+        && host(from).is_some()
+        && host(from) == host(&client_state.server_addr)
+}
+
+fn measure_stray_packets(network: Res<NetworkState>, mut diagnostics: Diagnostics) {
+    let stray = network.counters.stray_packets.load(Ordering::Relaxed);
+    diagnostics.add_measurement(&STRAY_PACKETS, || stray as f64);
+}
+
+fn measure_stale_drops(network: Res<NetworkState>, mut diagnostics: Diagnostics) {
+    let stale = network.counters.stale_dropped.load(Ordering::Relaxed);
+    diagnostics.add_measurement(&STALE_DROPPED, || stale as f64);
 }
 
-fn ping_button_system(
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<PingButton>)>,
+fn log_net_errors(mut errors: EventReader<NetError>, mut client_state: ResMut<ClientState>) {
+    for error in errors.read() {
+        error!("{}", error);
+        client_state.push_log(format!("[Error]: {}", error));
+    }
+}
+
+fn report_truncated_packets(
     network: Res<NetworkState>,
+    mut client_state: ResMut<ClientState>,
+    mut reported: Local<u64>,
+) {
+    let truncated = network.counters.truncated_packets.load(Ordering::Relaxed);
+    if truncated > *reported {
+        client_state.push_log(format!(
+            "[Warn]: {} oversized packet(s) dropped, raise --recv-buffer",
+            truncated - *reported
+        ));
+        *reported = truncated;
+    }
+}
+
+/// Heartbeats carry no gameplay meaning; they let the server tell an idle
+/// client apart from one that has gone away, and their acks give us a round
+/// trip time without the user pinging.
+///
+/// One is skipped when we sent something else during the interval and also
+/// heard from the server: that traffic already showed both sides are alive.
+/// Unless `--always-heartbeat` is set, a busy connection only sends one every
+/// [`AMBIENT_REFRESH_SECS`], so the ambient RTT its acks measure stays current.
+fn send_heartbeat(
+    time: Res<Time>,
     args: Res<Args>,
-    mut client_state: ResMut<ClientState>, // Needs to be mutable to push to log
+    mut timer: ResMut<HeartbeatTimer>,
+    network: Res<NetworkState>,
+    handshake: Res<Handshake>,
+    mut timeline: ResMut<PacketTimeline>,
+    client_state: Res<ClientState>,
+    mut session: ResMut<Session>,
+    mut diagnostics: Diagnostics,
+    mut sent_at_last_tick: Local<u64>,
 ) {
-    for interaction in interaction_query.iter() {
-        if *interaction == Interaction::Pressed {
-            let _ = network.socket.send_to("Ping".as_bytes(), &args.server);
-
-            client_state
-                .log
-                .push(format!("[Tx]: Ping to {}", args.server));
-            if client_state.log.len() > 20 {
-                client_state.log.remove(0);
-            }
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    let interval = timer.0.duration().as_secs_f32();
+    let sent_since = network.counters.packets_sent.load(Ordering::Relaxed) > *sent_at_last_tick;
+    let heard_since = now - handshake.last_heard <= interval;
+    let ambient_current = timeline
+        .ambient_age(time.elapsed_seconds_f64())
+        .is_some_and(|age| age < AMBIENT_REFRESH_SECS);
+
+    let suppress = !args.always_heartbeat && sent_since && heard_since && ambient_current;
+    if !suppress {
+        // RTT and loss ride along for the server's topology view and send rate
+        let heartbeat = Heartbeat {
+            rtt_ms: timeline
+                .latency()
+                .map(|(rtt, _)| (rtt * 10_000.0).round() / 10.0),
+            loss: timeline.loss().map(|loss| (loss * 100.0).round() / 100.0),
         }
+        .message();
+        network.send_to(heartbeat.as_bytes(), &client_state.server_addr);
+        session.record_sent("Heartbeat", heartbeat.len());
+        timeline.record_heartbeat_sent(time.elapsed_seconds_f64());
     }
+    let rate = if suppress { 0.0 } else { 1.0 / interval as f64 };
+    diagnostics.add_measurement(&HEARTBEAT_RATE, || rate);
+    *sent_at_last_tick = network.counters.packets_sent.load(Ordering::Relaxed);
 }
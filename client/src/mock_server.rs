@@ -0,0 +1,308 @@
+//! `--mock-server`: a fake server inside the client, for demos on one
+//! machine with no server running.
+//!
+//! It is a thread with a UDP socket of its own on 127.0.0.1 that the client
+//! connects to like any server, so the handshake, the state machine and
+//! the UI all run their real paths. With `--local` both sockets are on a
+//! `bevy_udp_net` memory network instead, so the demo binds no socket at
+//! all, for machines that don't allow it; [`deliver_local`] moves the
+//! datagrams across once a frame. It answers:
+//!
+//! - Hello and Resume with a Welcome (a Resume with the token it handed out
+//!   is `resumed`), or a Reject for a protocol version it doesn't speak
+//! - Ping with a Pong of the same seq, and Heartbeat with HeartbeatAck
+//! - Chat by relaying the line back, and playing the other side of a
+//!   knock-knock joke: `knock knock` gets `Who's there?`, the name that
+//!   follows gets `<name> who?` and the punchline a laugh
+//!
+//! and takes anything else without a word. Every reply is held
+//! `--mock-delay-ms` plus up to `--mock-jitter-ms` more, and `--mock-loss`
+//! of them are dropped, so the RTT, loss and reconnect handling have
+//! something to show. It speaks plain text only, so it can't be combined
+//! with `--compress`, `--codec` or `--server-key`.
+
+use bevy::prelude::*;
+use bevy_udp_net::memory::MemoryNetwork;
+use bevy_udp_net::{NetworkSocket, Receive};
+use common::handlers::{Heartbeat, NetMessage};
+use common::message::Message;
+use common::protocol::{self, MIN_SUPPORTED_VERSION, PROTOCOL_VERSION};
+use common::session::message_kind;
+use common::shared_rng::SharedRng;
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest the thread waits for a datagram while no reply is due
+const POLL: Duration = Duration::from_millis(50);
+
+/// Largest datagram the mock server takes
+const RECV_BUFFER: usize = 2048;
+
+/// What the mock server holds its replies back by and drops.
+#[derive(Clone, Copy)]
+pub struct MockConfig {
+    pub delay: Duration,
+    pub jitter: Duration,
+    /// Fraction of the replies dropped, 0..=1
+    pub loss: f32,
+}
+
+/// Where a client is in a knock-knock joke told over chat.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Joke {
+    Idle,
+    /// We asked who's there
+    Asked,
+    /// We asked who the name is
+    Named,
+}
+
+struct Reply {
+    due: Instant,
+    text: String,
+    to: SocketAddr,
+}
+
+/// The memory network `--local` runs on.
+#[derive(Resource, Clone)]
+pub struct LocalNetwork(pub MemoryNetwork);
+
+/// Delivers everything in flight on the local network, both ways. A reply
+/// the mock server sends meanwhile arrives the frame after.
+pub fn deliver_local(local: Res<LocalNetwork>) {
+    local.0.deliver_all();
+}
+
+/// Passes what the receive thread gets on to the mock server's thread
+struct Forward(Sender<(SocketAddr, String)>);
+
+impl Receive for Forward {
+    fn received(&mut self, from: SocketAddr, bytes: &[u8]) {
+        let text = String::from_utf8_lossy(bytes).into_owned();
+        let _ = self.0.send((from, text));
+    }
+}
+
+struct MockServer {
+    socket: NetworkSocket,
+    arrived: Receiver<(SocketAddr, String)>,
+    config: MockConfig,
+    rng: SharedRng,
+    /// The token each client was welcomed with
+    tokens: HashMap<SocketAddr, String>,
+    jokes: HashMap<SocketAddr, Joke>,
+    /// Replies waiting for their delay, in no particular order
+    replies: Vec<Reply>,
+}
+
+/// Starts the mock server, on `local` if given and on 127.0.0.1 if not;
+/// returns the address to connect to.
+pub fn start(config: MockConfig, local: Option<&MemoryNetwork>) -> io::Result<SocketAddr> {
+    let addr = (Ipv4Addr::LOCALHOST, 0);
+    let socket = match local {
+        Some(network) => network.bind(addr, RECV_BUFFER)?,
+        None => NetworkSocket::bind(addr, RECV_BUFFER)?,
+    };
+    let addr = socket.local_addr()?;
+    let (forward, arrived) = unbounded();
+    // Runs as long as the process does, like the server thread
+    let _ = socket.receive(Forward(forward));
+    let mut server = MockServer {
+        socket,
+        arrived,
+        config,
+        rng: SharedRng::new(SharedRng::random_seed()),
+        tokens: HashMap::new(),
+        jokes: HashMap::new(),
+        replies: Vec::new(),
+    };
+    thread::spawn(move || {
+        if let Err(e) = server.run() {
+            warn!("Mock server stopped: {}", e);
+        }
+    });
+    Ok(addr)
+}
+
+impl MockServer {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let now = Instant::now();
+            let wait = self
+                .replies
+                .iter()
+                .map(|reply| reply.due.saturating_duration_since(now))
+                .min()
+                .unwrap_or(POLL)
+                .clamp(Duration::from_millis(1), POLL);
+            match self.arrived.recv_timeout(wait) {
+                Ok((from, text)) => {
+                    for reply in self.answer(&text, from) {
+                        self.queue(reply, from);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::other("the receive thread stopped"));
+                }
+            }
+            self.send_due()?;
+        }
+    }
+
+    /// Holds `text` for the configured delay, or drops it as lost.
+    fn queue(&mut self, text: String, to: SocketAddr) {
+        if self.rng.unit() < self.config.loss {
+            return;
+        }
+        let jitter = self.config.jitter.mul_f32(self.rng.unit());
+        self.replies.push(Reply {
+            due: Instant::now() + self.config.delay + jitter,
+            text,
+            to,
+        });
+    }
+
+    fn send_due(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let (mut due, waiting): (Vec<Reply>, Vec<Reply>) = std::mem::take(&mut self.replies)
+            .into_iter()
+            .partition(|reply| reply.due <= now);
+        self.replies = waiting;
+        // Oldest first, as a real link would mostly deliver them
+        due.sort_by_key(|reply| reply.due);
+        for reply in due {
+            self.socket.send_to(&reply.text, reply.to)?;
+        }
+        Ok(())
+    }
+
+    /// What we say back to `text` from `from`, if anything.
+    fn answer(&mut self, text: &str, from: SocketAddr) -> Vec<String> {
+        match message_kind(text) {
+            "Hello" | "Resume" => vec![self.welcome(text, from)],
+            "Ping" => match Message::parse(text) {
+                Some(Message::Ping { seq }) => vec![Message::Pong { seq }.to_string()],
+                _ => Vec::new(),
+            },
+            "Heartbeat" if Heartbeat::parse(text).is_some() => vec!["HeartbeatAck".to_string()],
+            "Chat" => {
+                let line = text.strip_prefix("Chat").unwrap_or("").trim().to_string();
+                if line.is_empty() {
+                    return Vec::new();
+                }
+                let mut replies = vec![format!("Chat {}: {}", from, line)];
+                replies.extend(
+                    self.joke(&line, from)
+                        .map(|part| format!("Chat mock: {}", part)),
+                );
+                replies
+            }
+            "Disconnect" => {
+                self.tokens.remove(&from);
+                self.jokes.remove(&from);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn welcome(&mut self, greeting: &str, from: SocketAddr) -> String {
+        let version = protocol::parse_version(greeting).unwrap_or(PROTOCOL_VERSION);
+        if !(MIN_SUPPORTED_VERSION..=PROTOCOL_VERSION).contains(&version) {
+            return format!("Reject version={}", PROTOCOL_VERSION);
+        }
+        let resumed = message_kind(greeting) == "Resume"
+            && self.tokens.get(&from).map(String::as_str) == protocol::parse_token(greeting);
+        let token = format!("{:016x}", self.rng.next_u64());
+        self.tokens.insert(from, token.clone());
+        self.jokes.insert(from, Joke::Idle);
+        let mut welcome = format!("Welcome seed={:x}", self.rng.next_u64());
+        let missing = protocol::missing_features(version);
+        if !missing.is_empty() {
+            welcome.push_str(&format!(" deprecated={}", missing.join(",")));
+        }
+        welcome.push_str(&format!(" token={}", token));
+        if resumed {
+            welcome.push_str(" resumed");
+        }
+        welcome
+    }
+
+    /// Our next line of the joke `from` is telling, if `line` is theirs.
+    fn joke(&mut self, line: &str, from: SocketAddr) -> Option<String> {
+        let joke = self.jokes.entry(from).or_insert(Joke::Idle);
+        let trimmed = line.trim_end_matches(['!', '.', '?']);
+        let (next, reply) = match *joke {
+            _ if trimmed.eq_ignore_ascii_case("knock knock") => {
+                (Joke::Asked, "Who's there?".to_string())
+            }
+            Joke::Asked => (Joke::Named, format!("{} who?", trimmed)),
+            Joke::Named => (Joke::Idle, "Ha! Good one.".to_string()),
+            Joke::Idle => return None,
+        };
+        *joke = next;
+        Some(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// What the test's client socket heard
+    #[derive(Clone, Default)]
+    struct Heard(Arc<Mutex<Vec<String>>>);
+
+    impl Receive for Heard {
+        fn received(&mut self, _from: SocketAddr, bytes: &[u8]) {
+            let text = String::from_utf8_lossy(bytes).into_owned();
+            self.0.lock().unwrap().push(text);
+        }
+    }
+
+    /// Delivers until `heard` has `count` replies, for up to two seconds
+    fn pump(network: &MemoryNetwork, heard: &Heard, count: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while heard.0.lock().unwrap().len() < count && Instant::now() < deadline {
+            network.deliver_all();
+            thread::sleep(Duration::from_millis(5));
+        }
+        heard.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn answers_over_the_local_network() {
+        let network = MemoryNetwork::default();
+        let config = MockConfig {
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+        };
+        let server = start(config, Some(&network)).unwrap();
+        let client = network.bind("0.0.0.0:0", RECV_BUFFER).unwrap();
+        let heard = Heard::default();
+        let _receiver = client.receive(heard.clone());
+
+        let hello = format!("Hello version={}", PROTOCOL_VERSION);
+        client.send_to(&hello, server).unwrap();
+        let replies = pump(&network, &heard, 1);
+        assert!(replies[0].starts_with("Welcome seed="), "{:?}", replies);
+
+        client.send_to("Ping", server).unwrap();
+        client.send_to("Chat knock knock", server).unwrap();
+        let replies = pump(&network, &heard, 4);
+        assert_eq!(replies[1..].len(), 3, "{:?}", replies);
+        assert!(replies.contains(&"Pong".to_string()));
+        assert!(replies.contains(&"Chat mock: Who's there?".to_string()));
+
+        client.send_to("Ping 7", server).unwrap();
+        let replies = pump(&network, &heard, 5);
+        assert_eq!(replies[4], "Pong 7");
+    }
+}
@@ -0,0 +1,137 @@
+//! Outbound message queue with a debounce policy.
+//!
+//! The first message of a kind goes out on the next flush. Identical
+//! messages to the same target within the debounce window are absorbed and
+//! counted instead of sent, and when the window closes the log shows how
+//! many presses were coalesced ("xN"). UI systems just push; the queue
+//! decides what actually hits the wire. Messages the inspector has already
+//! encoded go out as they are, one per push.
+
+use bevy::prelude::*;
+use common::message::Message;
+use common::session::message_kind;
+
+#[cfg(not(feature = "thin"))]
+use crate::projectile::PacketFlight;
+use crate::session::Session;
+use crate::timeline::PacketTimeline;
+use crate::{ClientState, NetworkState};
+
+struct Pending {
+    message: String,
+    target: String,
+    first_at: f32,
+    count: u32,
+    sent: bool,
+    /// Already through the pipeline; see `inspector`
+    encoded: Option<Vec<u8>>,
+}
+
+#[derive(Resource)]
+pub struct OutboundQueue {
+    pending: Vec<Pending>,
+    /// Seconds during which identical messages are coalesced; 0 disables
+    pub debounce_secs: f32,
+}
+
+impl OutboundQueue {
+    pub fn new(debounce_secs: f32) -> Self {
+        Self {
+            pending: Vec::new(),
+            debounce_secs,
+        }
+    }
+
+    pub fn push(&mut self, message: &str, target: &str, now: f32) {
+        if let Some(pending) = self.pending.iter_mut().find(|pending| {
+            pending.message == message
+                && pending.target == target
+                && now - pending.first_at < self.debounce_secs
+        }) {
+            pending.count += 1;
+            return;
+        }
+
+        self.pending.push(Pending {
+            message: message.to_string(),
+            target: target.to_string(),
+            first_at: now,
+            count: 1,
+            sent: false,
+            encoded: None,
+        });
+    }
+
+    /// Whether `message` is waiting for the next flush.
+    #[cfg(not(feature = "thin"))]
+    pub fn queued(&self, message: &str) -> bool {
+        self.pending
+            .iter()
+            .any(|pending| !pending.sent && pending.message == message)
+    }
+
+    /// Queues bytes the pipeline has already produced for `message`. Never
+    /// coalesced: each one was confirmed on its own.
+    #[cfg(not(feature = "thin"))]
+    pub fn push_encoded(&mut self, message: &str, encoded: Vec<u8>, target: &str, now: f32) {
+        self.pending.push(Pending {
+            message: message.to_string(),
+            target: target.to_string(),
+            first_at: now,
+            count: 1,
+            sent: false,
+            encoded: Some(encoded),
+        });
+    }
+}
+
+pub fn flush_outbound(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut outbound: ResMut<OutboundQueue>,
+    mut client_state: ResMut<ClientState>,
+    mut timeline: ResMut<PacketTimeline>,
+    #[cfg(not(feature = "thin"))] mut flights: EventWriter<PacketFlight>,
+    mut session: ResMut<Session>,
+) {
+    let now = time.elapsed_seconds();
+    let debounce_secs = outbound.debounce_secs;
+
+    for pending in outbound.pending.iter_mut().filter(|pending| !pending.sent) {
+        // Numbered as it goes out, so its Pong can say which Ping it answers
+        let message = match Message::parse(&pending.message) {
+            Some(Message::Ping { .. }) => {
+                #[cfg(not(feature = "thin"))]
+                flights.send(PacketFlight {
+                    toward_server: true,
+                });
+                let seq = timeline.record_sent(time.elapsed_seconds_f64());
+                Message::Ping { seq }.to_string()
+            }
+            _ => pending.message.clone(),
+        };
+        match &pending.encoded {
+            Some(encoded) => network.send_processed(encoded, &pending.target),
+            None => network.send_to(message.as_bytes(), &pending.target),
+        }
+        session.record_sent(message_kind(&message), message.len());
+        client_state.push_log(format!("[Tx]: {} to {}", message, pending.target));
+        pending.sent = true;
+    }
+
+    outbound.pending.retain(|pending| {
+        if now - pending.first_at < debounce_secs {
+            return true;
+        }
+        if pending.count > 1 {
+            client_state.push_log(format!(
+                "[Tx]: {} to {} x{} (sent once, {} coalesced)",
+                pending.message,
+                pending.target,
+                pending.count,
+                pending.count - 1
+            ));
+        }
+        false
+    });
+}
@@ -0,0 +1,101 @@
+//! Frame pacing: rendering, the network tick and input sampling each run at
+//! their own rate.
+//!
+//! - Rendering follows the display (vsync) or runs as fast as it can with
+//!   `--no-vsync`. Every other system runs once per rendered frame.
+//! - The network tick, `--net-tick-hz`, is when queued messages leave:
+//!   `flush_outbound` and the account channel only run on a tick, so
+//!   everything queued since the last one goes out together. Receiving and
+//!   heartbeats are unaffected.
+//! - Input sampling, `--input-hz`, is when the buttons are read. A click
+//!   between samples is still seen on the next one.
+//!
+//! 0 runs a tick on every frame. The rates actually reached are the
+//! [`NET_TICK_RATE`] and [`INPUT_SAMPLE_RATE`] diagnostics, next to Bevy's
+//! frame rate; F5 on the client shows all three.
+
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::prelude::*;
+
+/// Network ticks per second
+pub const NET_TICK_RATE: DiagnosticPath = DiagnosticPath::const_new("network/tick_rate");
+/// Input samples per second
+pub const INPUT_SAMPLE_RATE: DiagnosticPath = DiagnosticPath::const_new("input/sample_rate");
+
+/// A fixed rate that frames run or skip; `due` is set on the frames that
+/// reach the next tick.
+pub struct Tick {
+    /// `None` ticks every frame
+    timer: Option<Timer>,
+    due: bool,
+    last_at: Option<f32>,
+}
+
+impl Tick {
+    fn new(hz: f32) -> Self {
+        Self {
+            timer: (hz > 0.0).then(|| Timer::from_seconds(1.0 / hz, TimerMode::Repeating)),
+            due: false,
+            last_at: None,
+        }
+    }
+
+    /// The rate since the previous tick, on frames that tick
+    fn advance(&mut self, time: &Time) -> Option<f64> {
+        self.due = match &mut self.timer {
+            Some(timer) => timer.tick(time.delta()).just_finished(),
+            None => true,
+        };
+        if !self.due {
+            return None;
+        }
+        let now = time.elapsed_seconds();
+        let rate = self
+            .last_at
+            .map(|last| now - last)
+            .filter(|elapsed| *elapsed > 0.0)
+            .map(|elapsed| 1.0 / elapsed as f64);
+        self.last_at = Some(now);
+        rate
+    }
+}
+
+#[derive(Resource)]
+pub struct Pacing {
+    pub net: Tick,
+    pub input: Tick,
+}
+
+impl Pacing {
+    pub fn new(net_tick_hz: f32, input_hz: f32) -> Self {
+        Self {
+            net: Tick::new(net_tick_hz),
+            input: Tick::new(input_hz),
+        }
+    }
+
+    /// Starts the network tick over at a new rate
+    pub fn set_net_tick_hz(&mut self, hz: f32) {
+        self.net = Tick::new(hz);
+    }
+}
+
+/// Runs in `First`, so every system of the frame agrees on what is due.
+pub fn advance_pacing(time: Res<Time>, mut pacing: ResMut<Pacing>, mut diagnostics: Diagnostics) {
+    if let Some(rate) = pacing.net.advance(&time) {
+        diagnostics.add_measurement(&NET_TICK_RATE, || rate);
+    }
+    if let Some(rate) = pacing.input.advance(&time) {
+        diagnostics.add_measurement(&INPUT_SAMPLE_RATE, || rate);
+    }
+}
+
+/// Run condition for systems that send
+pub fn net_tick(pacing: Res<Pacing>) -> bool {
+    pacing.net.due
+}
+
+/// Run condition for systems that read input
+pub fn input_tick(pacing: Res<Pacing>) -> bool {
+    pacing.input.due
+}
@@ -0,0 +1,269 @@
+//! `--ping-sweep`: RTT and loss by datagram size, to find the size at which
+//! the path to the server starts fragmenting or dropping datagrams.
+//!
+//! No window and no Bevy app: the sweep opens a socket of its own, says
+//! Hello, and then sends Heartbeats padded with `pad=xxx...` to every
+//! payload size from 1 byte to `--sweep-max` in steps of `--sweep-step`.
+//! The server acks every Heartbeat whatever it carries, so any server will
+//! do and nothing on it changes. Each size gets `--sweep-probes` probes,
+//! one at a time: a probe's round trip is the time to its HeartbeatAck,
+//! and one unanswered within [`PROBE_TIMEOUT`] counts as lost. The ack
+//! says nothing about which Heartbeat it answers, so whatever arrived
+//! after a timeout is drained before the next probe goes out.
+//!
+//! The table charts each size's mean RTT, then says which was the largest
+//! size answered every time and where loss started:
+//!
+//! ```text
+//! payload  datagram  lost   rtt ms
+//!    1441      1455   0/3     21.6  ########################################
+//!    1473      1487   3/3        -  lost
+//! ```
+//!
+//! On IPv4 a datagram over 1472 bytes does not fit one 1500-byte Ethernet
+//! frame: it goes in fragments, which shows as a step up in RTT, or as
+//! loss where something on the way drops fragments. The server drops what
+//! doesn't fit its `--recv-buffer` (1024 bytes by default) the same way, so
+//! that has to be raised for a sweep past it to say anything about the
+//! path. Only the Heartbeat grows; the ack coming back is always small.
+
+use common::disconnect::DisconnectReason;
+use common::handlers::{Heartbeat, HeartbeatAck, NetMessage, Reject, Welcome};
+use common::protocol::PROTOCOL_VERSION;
+use common::session::message_kind;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::Args;
+
+/// A probe with no ack by then is lost
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Hellos sent before giving up on a Welcome
+const HELLO_ATTEMPTS: u32 = 3;
+/// Widest RTT bar, for the slowest size
+const BAR_WIDTH: f32 = 40.0;
+/// Big enough for any ack, signed or not
+const RECV_BUFFER: usize = 2048;
+
+/// One size's probes
+struct SizeResult {
+    payload: usize,
+    datagram: usize,
+    sent: u32,
+    /// Round trips of the answered probes, in milliseconds
+    rtts: Vec<f32>,
+    /// Why sending failed, if the OS refused the datagram
+    send_error: Option<String>,
+}
+
+impl SizeResult {
+    fn lost(&self) -> u32 {
+        self.sent - self.rtts.len() as u32
+    }
+
+    fn mean_rtt(&self) -> Option<f32> {
+        if self.rtts.is_empty() {
+            None
+        } else {
+            Some(self.rtts.iter().sum::<f32>() / self.rtts.len() as f32)
+        }
+    }
+}
+
+/// Runs the sweep against `--server` and prints the table. Exits with 1 if
+/// the server couldn't be reached at all.
+pub fn run(args: &Args) {
+    let server = match resolve(&args.server) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let socket = match open(server, args.port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error: could not open a socket for the sweep: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = say_hello(&socket, server) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    let step = args.sweep_step.max(1);
+    println!(
+        "Ping sweep to {}: payloads of 1 to {} bytes in steps of {}, {} probes each",
+        server, args.sweep_max, step, args.sweep_probes
+    );
+    let mut payloads: Vec<usize> = (1..=args.sweep_max).step_by(step).collect();
+    if payloads.last().is_some_and(|last| *last != args.sweep_max) {
+        payloads.push(args.sweep_max);
+    }
+    let results: Vec<SizeResult> = payloads
+        .into_iter()
+        .map(|payload| probe_size(&socket, payload, args.sweep_probes))
+        .collect();
+    let quit = format!("Disconnect reason={}", DisconnectReason::UserQuit.code());
+    let _ = socket.send(quit.as_bytes());
+    print_table(&results);
+}
+
+/// The first address of the first server in the list
+fn resolve(servers: &str) -> Result<SocketAddr, String> {
+    let first = servers.split(',').next().unwrap_or(servers).trim();
+    first
+        .to_socket_addrs()
+        .map_err(|e| format!("could not resolve {}: {}", first, e))?
+        .next()
+        .ok_or_else(|| format!("{} has no address", first))
+}
+
+fn open(server: SocketAddr, port: u16) -> io::Result<UdpSocket> {
+    let socket = if server.is_ipv4() {
+        UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?
+    } else {
+        UdpSocket::bind((Ipv6Addr::UNSPECIFIED, port))?
+    };
+    socket.connect(server)?;
+    Ok(socket)
+}
+
+/// The handshake, so a server with `--validate-protocol` takes our
+/// Heartbeats too.
+fn say_hello(socket: &UdpSocket, server: SocketAddr) -> Result<(), String> {
+    let hello = format!("Hello version={}", PROTOCOL_VERSION);
+    for _ in 0..HELLO_ATTEMPTS {
+        socket
+            .send(hello.as_bytes())
+            .map_err(|e| format!("could not send a Hello to {}: {}", server, e))?;
+        let Some(reply) = wait_for(socket, &[Welcome::KIND, Reject::KIND], PROBE_TIMEOUT) else {
+            continue;
+        };
+        if message_kind(&reply) != Reject::KIND {
+            return Ok(());
+        }
+        let version =
+            Reject::parse(&reply).map_or("?".to_string(), |reject| reject.version.to_string());
+        return Err(format!(
+            "{} rejected the Hello (it speaks version {})",
+            server, version
+        ));
+    }
+    Err(format!(
+        "no Welcome from {} after {} Hellos",
+        server, HELLO_ATTEMPTS
+    ))
+}
+
+/// The next datagram of one of `kinds` within `timeout`, skipping others
+fn wait_for(socket: &UdpSocket, kinds: &[&str], timeout: Duration) -> Option<String> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; RECV_BUFFER];
+    loop {
+        let left = deadline.checked_duration_since(Instant::now())?;
+        // A zero timeout would mean waiting forever
+        socket
+            .set_read_timeout(Some(left.max(Duration::from_millis(1))))
+            .ok()?;
+        // A timeout, or on some systems an ICMP port unreachable; the
+        // deadline decides
+        let Ok(size) = socket.recv(&mut buf) else {
+            continue;
+        };
+        let text = String::from_utf8_lossy(&buf[..size]);
+        if kinds.contains(&message_kind(&text)) {
+            return Some(text.into_owned());
+        }
+    }
+}
+
+/// Throws away whatever has arrived, such as an ack that came too late
+fn drain(socket: &UdpSocket) {
+    let mut buf = [0u8; RECV_BUFFER];
+    if socket.set_nonblocking(true).is_err() {
+        return;
+    }
+    while socket.recv(&mut buf).is_ok() {}
+    let _ = socket.set_nonblocking(false);
+}
+
+fn probe_size(socket: &UdpSocket, payload: usize, probes: u32) -> SizeResult {
+    let probe = format!(
+        "{} pad={}",
+        Heartbeat::default().message(),
+        "x".repeat(payload)
+    );
+    let mut result = SizeResult {
+        payload,
+        datagram: probe.len(),
+        sent: 0,
+        rtts: Vec::new(),
+        send_error: None,
+    };
+    for _ in 0..probes {
+        drain(socket);
+        let sent = Instant::now();
+        if let Err(e) = socket.send(probe.as_bytes()) {
+            // Too big for the OS to send at all; the rest would fail the same
+            result.send_error = Some(e.to_string());
+            result.sent = probes;
+            break;
+        }
+        result.sent += 1;
+        if wait_for(socket, &[HeartbeatAck::KIND], PROBE_TIMEOUT).is_some() {
+            result.rtts.push(sent.elapsed().as_secs_f32() * 1000.0);
+        }
+    }
+    result
+}
+
+fn print_table(results: &[SizeResult]) {
+    let slowest = results
+        .iter()
+        .filter_map(SizeResult::mean_rtt)
+        .fold(0.0, f32::max);
+    println!();
+    println!("payload  datagram  lost   rtt ms");
+    for result in results {
+        let lost = format!("{}/{}", result.lost(), result.sent);
+        let chart = match (result.mean_rtt(), &result.send_error) {
+            (_, Some(e)) => format!("send failed: {}", e),
+            (Some(rtt), None) => {
+                let width = if slowest > 0.0 {
+                    (rtt / slowest * BAR_WIDTH).round().max(1.0) as usize
+                } else {
+                    1
+                };
+                format!("{:>8.1}  {}", rtt, "#".repeat(width))
+            }
+            (None, None) => format!("{:>8}  lost", "-"),
+        };
+        println!(
+            "{:>7}  {:>8}  {:<5} {}",
+            result.payload, result.datagram, lost, chart
+        );
+    }
+    println!();
+    let clean = results
+        .iter()
+        .take_while(|result| result.lost() == 0)
+        .last();
+    match clean {
+        Some(result) => println!(
+            "Largest payload answered every time from the start: {} bytes ({}-byte datagram)",
+            result.payload, result.datagram
+        ),
+        None => println!("Even the smallest probe went unanswered"),
+    }
+    if let Some(result) = results.iter().find(|result| result.lost() > 0) {
+        println!(
+            "Loss starts at a {}-byte payload ({}-byte datagram): {} of {} lost",
+            result.payload,
+            result.datagram,
+            result.lost(),
+            result.sent
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Playing back a session the server recorded (`--record-sessions`), with
+//! `--play-replay <file>`.
+//!
+//! The client doesn't connect in this mode. The recorded replication
+//! messages drive the same `PlayerCount` a live connection would, and the
+//! recorded inputs scroll by in the log. The track at the bottom is the
+//! whole session: inputs are ticks above the line, state changes below it.
+//! Click or drag on it to seek; Space pauses, Left/Right step
+//! [`STEP_SECS`], Home goes back to the start.
+//!
+//! A seek backwards starts again from a fresh resource and applies every
+//! state entry up to the new position, so what is shown at any point is
+//! exactly what the client had then.
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use common::players::PlayerCount;
+use common::recording::{Recording, Track};
+use common::replicate::{
+    ReplicateResourceExt, ReplicationIncoming, ReplicationPlugin, ReplicationRole,
+};
+use common::ui::fonts::FontsPlugin;
+use common::ui::widgets::{UiTheme, WidgetsPlugin, header, log_text};
+use std::path::Path;
+
+use crate::Args;
+
+const STEP_SECS: f32 = 5.0;
+const LOG_LINES: usize = 20;
+const TRACK_LEFT: f32 = -600.0;
+const TRACK_WIDTH: f32 = 1000.0;
+const TRACK_Y: f32 = -150.0;
+const TICK_HEIGHT: f32 = 16.0;
+/// How far above or below the track a click still seeks
+const GRAB_HEIGHT: f32 = 30.0;
+
+#[derive(Resource)]
+struct Playback {
+    recording: Recording,
+    /// Seconds into the recording
+    position: f32,
+    paused: bool,
+    /// Entries before this one have been played
+    next: usize,
+    /// Set by a seek backwards; the resource starts over before replaying
+    rewind: bool,
+}
+
+impl Playback {
+    fn seek(&mut self, to: f32) {
+        let to = to.clamp(0.0, self.recording.duration());
+        if to < self.position {
+            self.next = 0;
+            self.rewind = true;
+        }
+        self.position = to;
+    }
+}
+
+#[derive(Component)]
+struct PlaybackHeader;
+
+#[derive(Component)]
+struct PlaybackLog;
+
+/// Runs the playback app in place of the normal client.
+pub fn run(args: &Args, path: &Path) {
+    let recording = match Recording::load(path) {
+        Ok(recording) => recording,
+        Err(e) => {
+            eprintln!("Error: could not read replay {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    App::new()
+        .add_plugins((
+            DefaultPlugins,
+            WidgetsPlugin,
+            FontsPlugin {
+                paths: args.fonts.clone(),
+            },
+        ))
+        .add_plugins(ReplicationPlugin {
+            role: ReplicationRole::Client,
+        })
+        .replicate_resource::<PlayerCount>()
+        .insert_resource(Playback {
+            recording,
+            position: 0.0,
+            paused: false,
+            next: 0,
+            rewind: false,
+        })
+        .add_systems(Startup, setup_playback)
+        .add_systems(
+            Update,
+            (
+                playback_controls,
+                advance_playback.after(playback_controls),
+                update_playback_ui.after(advance_playback),
+                draw_scrubber.after(advance_playback),
+            ),
+        )
+        .run();
+}
+
+fn setup_playback(mut commands: Commands, theme: Res<UiTheme>) {
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((header(&theme, ""), PlaybackHeader));
+    commands.spawn((log_text(&theme, ""), PlaybackLog));
+}
+
+fn playback_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut playback: ResMut<Playback>,
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keys.just_pressed(KeyCode::ArrowLeft) {
+        let to = playback.position - STEP_SECS;
+        playback.seek(to);
+    }
+    if keys.just_pressed(KeyCode::ArrowRight) {
+        let to = playback.position + STEP_SECS;
+        playback.seek(to);
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        playback.seek(0.0);
+    }
+
+    if !mouse.pressed(MouseButton::Left) {
+        return;
+    }
+    let (Ok(window), Ok((camera, transform))) = (windows.get_single(), cameras.get_single()) else {
+        return;
+    };
+    let Some(cursor) = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world_2d(transform, cursor))
+    else {
+        return;
+    };
+    let along = (cursor.x - TRACK_LEFT) / TRACK_WIDTH;
+    if (cursor.y - TRACK_Y).abs() <= GRAB_HEIGHT && (0.0..=1.0).contains(&along) {
+        let to = along * playback.recording.duration();
+        playback.seek(to);
+    }
+}
+
+fn advance_playback(
+    time: Res<Time>,
+    mut playback: ResMut<Playback>,
+    mut players: ResMut<PlayerCount>,
+    mut incoming: EventWriter<ReplicationIncoming>,
+) {
+    let duration = playback.recording.duration();
+    if !playback.paused {
+        playback.position = (playback.position + time.delta_seconds()).min(duration);
+    }
+    if playback.rewind {
+        playback.rewind = false;
+        *players = PlayerCount::default();
+    }
+    let Playback {
+        recording,
+        position,
+        next,
+        ..
+    } = &mut *playback;
+    while let Some(entry) = recording.entries.get(*next) {
+        if entry.at > *position {
+            break;
+        }
+        if entry.track == Track::State {
+            // Applied by `replicate` in PostUpdate, as if it had just arrived
+            incoming.send(ReplicationIncoming(entry.text.clone()));
+        }
+        *next += 1;
+    }
+}
+
+fn update_playback_ui(
+    playback: Res<Playback>,
+    players: Res<PlayerCount>,
+    mut headers: Query<&mut Text, (With<PlaybackHeader>, Without<PlaybackLog>)>,
+    mut logs: Query<&mut Text, (With<PlaybackLog>, Without<PlaybackHeader>)>,
+) {
+    if !playback.is_changed() && !players.is_changed() {
+        return;
+    }
+    let recording = &playback.recording;
+    let status = format!(
+        "Replay of {}: {:.1}s / {:.1}s, {} players, {} spectators{}",
+        recording.peer,
+        playback.position,
+        recording.duration(),
+        players.connected,
+        players.spectators,
+        if playback.paused { " (paused)" } else { "" }
+    );
+    for mut text in headers.iter_mut() {
+        text.sections[0].value = status.clone();
+    }
+
+    let played = &recording.entries[..playback.next];
+    let mut lines: Vec<String> = played
+        .iter()
+        .rev()
+        .filter(|entry| entry.track == Track::Input)
+        .take(LOG_LINES)
+        .map(|entry| format!("[{:.1}s]: {}", entry.at, entry.text))
+        .collect();
+    lines.reverse();
+    for mut text in logs.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+fn draw_scrubber(playback: Res<Playback>, mut gizmos: Gizmos) {
+    let duration = playback.recording.duration().max(f32::EPSILON);
+    let x_at = |at: f32| TRACK_LEFT + TRACK_WIDTH * (at / duration).clamp(0.0, 1.0);
+
+    gizmos.line_2d(
+        Vec2::new(TRACK_LEFT, TRACK_Y),
+        Vec2::new(TRACK_LEFT + TRACK_WIDTH, TRACK_Y),
+        Color::GRAY,
+    );
+    for entry in &playback.recording.entries {
+        let x = x_at(entry.at);
+        let (from, to, color) = match entry.track {
+            Track::Input => (TRACK_Y, TRACK_Y + TICK_HEIGHT / 2.0, Color::YELLOW),
+            Track::State => (TRACK_Y - TICK_HEIGHT / 2.0, TRACK_Y, Color::CYAN),
+        };
+        gizmos.line_2d(Vec2::new(x, from), Vec2::new(x, to), color);
+    }
+    let x = x_at(playback.position);
+    gizmos.line_2d(
+        Vec2::new(x, TRACK_Y - TICK_HEIGHT),
+        Vec2::new(x, TRACK_Y + TICK_HEIGHT),
+        Color::WHITE,
+    );
+}
@@ -0,0 +1,31 @@
+//! Feeds the handshake state into `common::presence`, so a platform backend
+//! can show "Connected to ..." without touching the networking code.
+
+use bevy::prelude::*;
+use common::presence::{ConnectionPresence, PresenceUpdate};
+
+use crate::ClientState;
+use crate::handshake::{Handshake, HandshakeStage};
+
+pub fn update_presence(
+    handshake: Res<Handshake>,
+    client_state: Res<ClientState>,
+    mut last: Local<Option<ConnectionPresence>>,
+    mut updates: EventWriter<PresenceUpdate>,
+) {
+    let state = match handshake.stage {
+        HandshakeStage::Resolving | HandshakeStage::Waiting | HandshakeStage::Scanning => {
+            ConnectionPresence::Connecting {
+                server: client_state.server_target.clone(),
+            }
+        }
+        HandshakeStage::Connected => ConnectionPresence::Connected {
+            server: client_state.server_addr.clone(),
+        },
+        HandshakeStage::Failed | HandshakeStage::Stopped => ConnectionPresence::Disconnected,
+    };
+    if last.as_ref() != Some(&state) {
+        updates.send(PresenceUpdate::Connection(state.clone()));
+        *last = Some(state);
+    }
+}
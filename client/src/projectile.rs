@@ -0,0 +1,128 @@
+//! Ping visualization: a dot flies from the "Client" icon to the "Server"
+//! icon when a Ping goes out, and back when the Pong arrives.
+//!
+//! Flight time is half the smoothed round trip time, slowed down by
+//! `SLOW_MOTION` so a 2 ms LAN ping is still visible. A slower link means a
+//! visibly slower dot.
+
+use bevy::prelude::*;
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+
+use crate::timeline::PacketTimeline;
+
+const CLIENT_ICON: Vec2 = Vec2::new(-250.0, 40.0);
+const SERVER_ICON: Vec2 = Vec2::new(250.0, 40.0);
+const ICON_RADIUS: f32 = 24.0;
+const PROJECTILE_RADIUS: f32 = 6.0;
+/// Real seconds of latency are shown this many times slower
+const SLOW_MOTION: f32 = 100.0;
+const MIN_FLIGHT_SECS: f32 = 0.15;
+const MAX_FLIGHT_SECS: f32 = 3.0;
+/// Until the first Pong we have no RTT; assume this one
+const DEFAULT_RTT_SECS: f32 = 0.005;
+
+/// A packet worth animating; sent by whoever put it on (or took it off) the wire.
+#[derive(Event)]
+pub struct PacketFlight {
+    pub toward_server: bool,
+}
+
+#[derive(Component)]
+pub struct Projectile {
+    from: Vec2,
+    to: Vec2,
+    elapsed: f32,
+    duration: f32,
+}
+
+#[derive(Resource)]
+pub struct ProjectileAssets {
+    mesh: Mesh2dHandle,
+    material: Handle<ColorMaterial>,
+}
+
+pub fn setup_projectiles(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let icon = meshes.add(Circle::new(ICON_RADIUS));
+    for (label, position, color) in [
+        ("Client", CLIENT_ICON, Color::rgb(0.3, 0.5, 0.9)),
+        ("Server", SERVER_ICON, Color::rgb(0.9, 0.5, 0.3)),
+    ] {
+        commands.spawn(MaterialMesh2dBundle {
+            mesh: icon.clone().into(),
+            material: materials.add(color),
+            transform: Transform::from_translation(position.extend(0.0)),
+            ..default()
+        });
+        commands.spawn(Text2dBundle {
+            text: Text::from_section(
+                label,
+                TextStyle {
+                    font_size: 14.0,
+                    color: Color::GRAY,
+                    ..default()
+                },
+            ),
+            transform: Transform::from_translation(
+                (position - Vec2::Y * (ICON_RADIUS + 12.0)).extend(0.0),
+            ),
+            ..default()
+        });
+    }
+
+    commands.insert_resource(ProjectileAssets {
+        mesh: meshes.add(Circle::new(PROJECTILE_RADIUS)).into(),
+        material: materials.add(Color::rgb(1.0, 0.9, 0.3)),
+    });
+}
+
+pub fn launch_projectiles(
+    mut commands: Commands,
+    mut flights: EventReader<PacketFlight>,
+    timeline: Res<PacketTimeline>,
+    assets: Res<ProjectileAssets>,
+) {
+    let rtt = timeline.smoothed_rtt().unwrap_or(DEFAULT_RTT_SECS);
+    let duration = (rtt / 2.0 * SLOW_MOTION).clamp(MIN_FLIGHT_SECS, MAX_FLIGHT_SECS);
+
+    for flight in flights.read() {
+        let (from, to) = if flight.toward_server {
+            (CLIENT_ICON, SERVER_ICON)
+        } else {
+            (SERVER_ICON, CLIENT_ICON)
+        };
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: assets.mesh.clone(),
+                material: assets.material.clone(),
+                // In front of the icons
+                transform: Transform::from_translation(from.extend(1.0)),
+                ..default()
+            },
+            Projectile {
+                from,
+                to,
+                elapsed: 0.0,
+                duration,
+            },
+        ));
+    }
+}
+
+pub fn move_projectiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut Transform)>,
+) {
+    for (entity, mut projectile, mut transform) in projectiles.iter_mut() {
+        projectile.elapsed += time.delta_seconds();
+        let t = (projectile.elapsed / projectile.duration).min(1.0);
+        transform.translation = projectile.from.lerp(projectile.to, t).extend(1.0);
+        if t >= 1.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
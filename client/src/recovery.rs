@@ -0,0 +1,158 @@
+//! Loss recovery for replicated state.
+//!
+//! The server numbers every replication message it sends us (see
+//! `common::replicate`). When a number is skipped the resource can't be
+//! brought up to date from what arrived, so `replicate` holds its changes
+//! back and raises a `ReplicationNack`, which goes to the server here. The
+//! server answers with a full copy of that resource alone, rather than
+//! everything it replicates.
+//!
+//! Below the packet timeline, a chart shows the last [`CHART_SECS`] seconds:
+//! messages dropped (red) and resources recovered by a full copy (green),
+//! per second.
+
+use bevy::prelude::*;
+use common::replicate::ReplicationNack;
+#[cfg(not(feature = "thin"))]
+use common::replicate::ReplicationStreams;
+#[cfg(not(feature = "thin"))]
+use std::collections::VecDeque;
+
+use crate::session::Session;
+use crate::{ClientState, NetworkState};
+
+#[cfg(not(feature = "thin"))]
+const CHART_SECS: usize = 30;
+#[cfg(not(feature = "thin"))]
+const CHART_LEFT: f32 = -600.0;
+#[cfg(not(feature = "thin"))]
+const CHART_WIDTH: f32 = 1000.0;
+#[cfg(not(feature = "thin"))]
+const CHART_Y: f32 = -250.0;
+#[cfg(not(feature = "thin"))]
+const CHART_HEIGHT: f32 = 40.0;
+
+/// Sent straight away, like heartbeats, rather than on the network tick:
+/// the resource is stale until the answer arrives.
+pub fn send_replication_nacks(
+    network: Res<NetworkState>,
+    mut nacks: EventReader<ReplicationNack>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    for nack in nacks.read() {
+        let text = nack.text();
+        network.send_to(text.as_bytes(), &client_state.server_addr);
+        session.record_sent("ReplNack", text.len());
+        let server_addr = client_state.server_addr.clone();
+        client_state.push_log(format!(
+            "[Tx]: ReplNack {} to {} (lost updates, asking for a full copy)",
+            nack.name, server_addr
+        ));
+    }
+}
+
+/// Dropped and recovered counts per second, oldest first; the last bucket
+/// is the current second.
+#[cfg(not(feature = "thin"))]
+#[derive(Resource, Default)]
+pub struct RecoveryChart {
+    buckets: VecDeque<(u64, u64)>,
+    /// `ReplicationStreams` totals when the current bucket started
+    counted: (u64, u64),
+    bucket_started: f32,
+}
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct RecoveryLabel;
+
+#[cfg(not(feature = "thin"))]
+pub fn setup_recovery_chart(mut commands: Commands) {
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 14.0,
+                    color: Color::GRAY,
+                    ..default()
+                },
+            ),
+            transform: Transform::from_xyz(CHART_LEFT, CHART_Y - 4.0, 0.0),
+            text_anchor: bevy::sprite::Anchor::TopLeft,
+            ..default()
+        },
+        RecoveryLabel,
+    ));
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn sample_recovery(
+    time: Res<Time>,
+    streams: Res<ReplicationStreams>,
+    mut chart: ResMut<RecoveryChart>,
+    mut labels: Query<&mut Text, With<RecoveryLabel>>,
+) {
+    let now = time.elapsed_seconds();
+    let totals = (streams.dropped, streams.recovered);
+    if chart.buckets.is_empty() || now - chart.bucket_started >= 1.0 {
+        chart.counted = totals;
+        chart.bucket_started = now;
+        chart.buckets.push_back((0, 0));
+        while chart.buckets.len() > CHART_SECS {
+            chart.buckets.pop_front();
+        }
+    }
+    let counted = chart.counted;
+    if let Some(current) = chart.buckets.back_mut() {
+        *current = (totals.0 - counted.0, totals.1 - counted.1);
+    }
+
+    let waiting: Vec<&str> = streams.awaiting().collect();
+    let mut label = format!(
+        "Replication: {} dropped, {} recovered",
+        streams.dropped, streams.recovered
+    );
+    if !waiting.is_empty() {
+        label.push_str(&format!("   waiting for {}", waiting.join(", ")));
+    }
+    for mut text in labels.iter_mut() {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.clone();
+        }
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn draw_recovery_chart(chart: Res<RecoveryChart>, mut gizmos: Gizmos) {
+    gizmos.line_2d(
+        Vec2::new(CHART_LEFT, CHART_Y),
+        Vec2::new(CHART_LEFT + CHART_WIDTH, CHART_Y),
+        Color::GRAY,
+    );
+    let peak = chart
+        .buckets
+        .iter()
+        .map(|(dropped, recovered)| (*dropped).max(*recovered))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let slot = CHART_WIDTH / CHART_SECS as f32;
+    // Newest second at the right edge, like the timeline above
+    let first = CHART_SECS - chart.buckets.len();
+    for (i, (dropped, recovered)) in chart.buckets.iter().enumerate() {
+        let x = CHART_LEFT + slot * (first + i) as f32;
+        let bars = [
+            (x + slot * 0.2, *dropped, Color::RED),
+            (x + slot * 0.55, *recovered, Color::GREEN),
+        ];
+        for (x, count, color) in bars {
+            if count == 0 {
+                continue;
+            }
+            let height = CHART_HEIGHT * count as f32 / peak as f32;
+            gizmos.line_2d(Vec2::new(x, CHART_Y), Vec2::new(x, CHART_Y + height), color);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Client automation with Rhai scripts.
+//!
+//! `--script scenario.rhai` runs a script once at startup. The script sets up
+//! callbacks and the client calls them as time passes and messages arrive:
+//!
+//! ```rhai
+//! every(1.0, || ping());
+//! on_pong(|rtt_ms| check(rtt_ms < 100.0, `RTT ${rtt_ms}ms over budget`));
+//! after(30.0, || exit());
+//! ```
+//!
+//! Functions available to scripts:
+//! - `ping()` queues a Ping, exactly like pressing the button
+//! - `send(text)` sends arbitrary text to the server's gameplay address
+//! - `every(secs, fn)` / `after(secs, fn)` call `fn` repeatedly / once
+//! - `on_message(fn(text))` is called for every message from the server
+//! - `on_pong(fn(rtt_ms))` is called for every Pong that answers a Ping
+//! - `check(condition, message)` records a failure when `condition` is false
+//! - `exit()` quits; the exit code is 1 if any check failed, else 0
+//! - `print(text)` writes to the log
+//!
+//! Scripts can't touch sockets or ECS state directly; they queue actions that
+//! `run_script` applies each frame.
+
+use bevy::prelude::*;
+use rhai::{AST, Dynamic, Engine, FnPtr, FuncArgs};
+use std::sync::{Arc, Mutex};
+
+use crate::ClientState;
+use crate::outbound::OutboundQueue;
+
+enum Action {
+    Ping,
+    Send(String),
+    Log(String),
+    Exit,
+}
+
+struct ScheduledCall {
+    due: f32,
+    /// Set for `every`, None for `after`
+    period: Option<f32>,
+    callback: FnPtr,
+}
+
+#[derive(Default)]
+struct ScriptState {
+    /// Elapsed seconds as of the current frame
+    now: f32,
+    actions: Vec<Action>,
+    scheduled: Vec<ScheduledCall>,
+    on_message: Vec<FnPtr>,
+    on_pong: Vec<FnPtr>,
+    failures: u32,
+}
+
+/// A message from the server, for script callbacks. Sent by
+/// `handle_network_messages` whether or not a script is loaded.
+#[derive(Event)]
+pub struct ServerMessage {
+    pub text: String,
+    /// Round trip time in seconds when this was a Pong answering a Ping
+    pub rtt: Option<f32>,
+}
+
+#[derive(Resource)]
+pub struct ClientScript {
+    engine: Engine,
+    ast: AST,
+    state: Arc<Mutex<ScriptState>>,
+}
+
+impl ClientScript {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        let engine = build_engine(&state);
+        let ast = engine
+            .compile(&source)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Self { engine, ast, state })
+    }
+
+    fn call(&self, callback: &FnPtr, args: impl FuncArgs) -> Option<String> {
+        callback
+            .call::<Dynamic>(&self.engine, &self.ast, args)
+            .err()
+            .map(|e| e.to_string())
+    }
+}
+
+fn build_engine(state: &Arc<Mutex<ScriptState>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let s = state.clone();
+    engine.on_print(move |text| {
+        s.lock()
+            .unwrap()
+            .actions
+            .push(Action::Log(text.to_string()))
+    });
+    let s = state.clone();
+    engine.register_fn("ping", move || s.lock().unwrap().actions.push(Action::Ping));
+    let s = state.clone();
+    engine.register_fn("send", move |text: &str| {
+        s.lock()
+            .unwrap()
+            .actions
+            .push(Action::Send(text.to_string()))
+    });
+    let s = state.clone();
+    engine.register_fn("exit", move || s.lock().unwrap().actions.push(Action::Exit));
+    let s = state.clone();
+    engine.register_fn("check", move |condition: bool, message: &str| {
+        if !condition {
+            let mut state = s.lock().unwrap();
+            state.failures += 1;
+            state
+                .actions
+                .push(Action::Log(format!("check failed: {}", message)));
+        }
+    });
+    let s = state.clone();
+    engine.register_fn("on_message", move |callback: FnPtr| {
+        s.lock().unwrap().on_message.push(callback)
+    });
+    let s = state.clone();
+    engine.register_fn("on_pong", move |callback: FnPtr| {
+        s.lock().unwrap().on_pong.push(callback)
+    });
+
+    // Registered for both number types so `every(1, ...)` works as well as `every(1.0, ...)`
+    for (name, repeat) in [("every", true), ("after", false)] {
+        let s = state.clone();
+        engine.register_fn(name, move |secs: f64, callback: FnPtr| {
+            schedule(&s, secs as f32, repeat, callback)
+        });
+        let s = state.clone();
+        engine.register_fn(name, move |secs: i64, callback: FnPtr| {
+            schedule(&s, secs as f32, repeat, callback)
+        });
+    }
+
+    engine
+}
+
+fn schedule(state: &Mutex<ScriptState>, secs: f32, repeat: bool, callback: FnPtr) {
+    let mut state = state.lock().unwrap();
+    let due = state.now + secs;
+    state.scheduled.push(ScheduledCall {
+        due,
+        period: repeat.then_some(secs.max(0.01)),
+        callback,
+    });
+}
+
+pub fn start_script(script: Res<ClientScript>, mut client_state: ResMut<ClientState>) {
+    client_state.push_log("[Script]: started".to_string());
+    if let Err(e) = script.engine.run_ast(&script.ast) {
+        client_state.push_log(format!("[Script error]: {}", e));
+    }
+}
+
+/// Fires due timers and message callbacks, then applies what the script asked
+/// for. Callbacks run with the state unlocked, since they call back into it.
+pub fn run_script(
+    time: Res<Time>,
+    script: Res<ClientScript>,
+    mut messages: EventReader<ServerMessage>,
+    mut outbound: ResMut<OutboundQueue>,
+    mut client_state: ResMut<ClientState>,
+) {
+    let now = time.elapsed_seconds();
+    let mut errors = Vec::new();
+
+    let (due, on_message, on_pong) = {
+        let mut state = script.state.lock().unwrap();
+        state.now = now;
+        let mut due = Vec::new();
+        for call in state.scheduled.iter_mut().filter(|call| call.due <= now) {
+            due.push(call.callback.clone());
+            if let Some(period) = call.period {
+                call.due += period;
+            }
+        }
+        state
+            .scheduled
+            .retain(|call| call.period.is_some() || call.due > now);
+        (due, state.on_message.clone(), state.on_pong.clone())
+    };
+
+    for callback in &due {
+        errors.extend(script.call(callback, ()));
+    }
+    for message in messages.read() {
+        for callback in &on_message {
+            errors.extend(script.call(callback, (message.text.clone(),)));
+        }
+        if let Some(rtt) = message.rtt {
+            for callback in &on_pong {
+                errors.extend(script.call(callback, (rtt as f64 * 1000.0,)));
+            }
+        }
+    }
+
+    let (actions, failures) = {
+        let mut state = script.state.lock().unwrap();
+        (std::mem::take(&mut state.actions), state.failures)
+    };
+    for error in errors {
+        client_state.push_log(format!("[Script error]: {}", error));
+    }
+    for action in actions {
+        match action {
+            Action::Ping => {
+                let target = client_state.gameplay_addr().to_string();
+                outbound.push("Ping", &target, now);
+            }
+            Action::Send(text) => {
+                let target = client_state.gameplay_addr().to_string();
+                outbound.push(&text, &target, now);
+            }
+            Action::Log(text) => client_state.push_log(format!("[Script]: {}", text)),
+            Action::Exit => {
+                let code = if failures > 0 { 1 } else { 0 };
+                println!("Script finished: {} check(s) failed", failures);
+                std::process::exit(code);
+            }
+        }
+    }
+}
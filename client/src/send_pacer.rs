@@ -0,0 +1,209 @@
+//! Send pacing: what a frame sends is spread over the network tick
+//! instead of leaving in one burst.
+//!
+//! On a net tick the outbound queue and the account channel may both have
+//! several messages ready, and a router with a small buffer drops the tail
+//! of a burst like that. With `--send-mode paced` (the default) every
+//! datagram a frame sends, after chaos mode and the size check, waits in
+//! the pacer. At the end of the frame they go to a sending thread that acts
+//! as a token bucket holding one send and refilling N times per tick: the
+//! first goes at once and the rest follow evenly spaced, the last one a
+//! tick interval / N before the next tick. A lone datagram is sent right
+//! away. `--send-mode burst` sends everything the moment it's sent.
+//!
+//! F7 switches between the two at runtime, and the F5 panel shows the mode
+//! and the size of the last batch, for comparing them.
+//!
+//! A real-time message (`protocol::REAL_TIME`: positions, effects) carries
+//! a deadline from the moment it was sent. One still waiting here, or held
+//! by a chaos stall, when its deadline passes is dropped instead of going
+//! out: the server would only throw it away for a newer one already on the
+//! way. The drops are counted in the `network/stale_dropped` diagnostic.
+
+use bevy::prelude::*;
+use bevy_udp_net::NetworkSocket;
+use clap::ValueEnum;
+use common::error::{NetError, NetErrorSink};
+use crossbeam::channel::{Receiver, Sender, unbounded};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::energy::{Worker, WorkerMeters};
+use crate::latency::{LatencyBudget, SendStamps};
+use crate::traffic::TrafficCounters;
+use crate::transport::TcpLink;
+use crate::{Args, NetworkState};
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SendMode {
+    /// Everything a frame sends leaves at once
+    Burst,
+    /// Spread over the tick interval
+    Paced,
+}
+
+impl SendMode {
+    #[cfg(not(feature = "thin"))]
+    pub fn label(self) -> &'static str {
+        match self {
+            SendMode::Burst => "burst",
+            SendMode::Paced => "paced",
+        }
+    }
+}
+
+/// What actually puts a datagram on the wire, shared with the sending
+/// thread.
+#[derive(Clone)]
+pub struct Wire {
+    pub socket: NetworkSocket,
+    /// Carries everything instead of the socket while we're on TCP
+    pub tcp: Arc<TcpLink>,
+    pub counters: Arc<TrafficCounters>,
+    pub errors: NetErrorSink,
+    pub latency: Arc<LatencyBudget>,
+    pub energy: Arc<WorkerMeters>,
+}
+
+impl Wire {
+    pub fn transmit(&self, bytes: &[u8], addr: &str) {
+        let sent = if self.tcp.active() {
+            self.tcp.send(bytes)
+        } else {
+            self.socket.send_bytes_to(bytes, addr)
+        };
+        match sent {
+            Ok(()) => self.counters.record_sent(bytes.len()),
+            Err(source) => self.errors.push(NetError::Send {
+                addr: addr.to_string(),
+                source,
+            }),
+        }
+    }
+
+    /// Transmits `send` unless its deadline has passed, in which case it is
+    /// counted as stale and dropped.
+    fn transmit_fresh(&self, send: &Queued) {
+        if send
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            self.counters.stale_dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.transmit(&send.bytes, &send.addr);
+            self.latency.sent(&send.stamps);
+        }
+    }
+}
+
+/// A datagram waiting to go out.
+#[derive(Clone)]
+pub struct Queued {
+    pub bytes: Vec<u8>,
+    pub addr: String,
+    /// When a real-time message stops being worth sending
+    pub deadline: Option<Instant>,
+    pub stamps: SendStamps,
+}
+
+struct Batch {
+    sends: Vec<Queued>,
+    /// Time to spread them over
+    interval: Duration,
+}
+
+pub struct SendPacer {
+    paced: AtomicBool,
+    /// This frame's datagrams, while paced
+    queued: Mutex<Vec<Queued>>,
+    /// Datagrams this frame, in either mode
+    this_frame: AtomicUsize,
+    /// Datagrams in the last frame that sent any
+    last_batch: AtomicUsize,
+    batches: Sender<Batch>,
+}
+
+impl SendPacer {
+    /// Starts the sending thread.
+    pub fn new(mode: SendMode, wire: Wire) -> Self {
+        let (batches, receiver) = unbounded();
+        thread::spawn(move || send_batches(receiver, wire));
+        Self {
+            paced: AtomicBool::new(mode == SendMode::Paced),
+            queued: Mutex::new(Vec::new()),
+            this_frame: AtomicUsize::new(0),
+            last_batch: AtomicUsize::new(0),
+            batches,
+        }
+    }
+
+    #[cfg(not(feature = "thin"))]
+    pub fn mode(&self) -> SendMode {
+        if self.paced.load(Ordering::Relaxed) {
+            SendMode::Paced
+        } else {
+            SendMode::Burst
+        }
+    }
+
+    #[cfg(not(feature = "thin"))]
+    pub fn set_mode(&self, mode: SendMode) {
+        self.paced.store(mode == SendMode::Paced, Ordering::Relaxed);
+    }
+
+    #[cfg(not(feature = "thin"))]
+    pub fn last_batch(&self) -> usize {
+        self.last_batch.load(Ordering::Relaxed)
+    }
+
+    /// Sends now, or holds the datagram for the end of the frame.
+    pub fn send(&self, wire: &Wire, send: Queued) {
+        self.this_frame.fetch_add(1, Ordering::Relaxed);
+        if self.paced.load(Ordering::Relaxed) {
+            self.queued.lock().unwrap().push(send);
+        } else {
+            wire.transmit_fresh(&send);
+        }
+    }
+
+    fn release(&self, wire: &Wire, interval: Duration) {
+        let sent = self.this_frame.swap(0, Ordering::Relaxed);
+        if sent > 0 {
+            self.last_batch.store(sent, Ordering::Relaxed);
+        }
+        let sends = std::mem::take(&mut *self.queued.lock().unwrap());
+        match sends.len() {
+            0 => {}
+            1 => wire.transmit_fresh(&sends[0]),
+            _ => {
+                let _ = self.batches.send(Batch { sends, interval });
+            }
+        }
+    }
+}
+
+fn send_batches(receiver: Receiver<Batch>, wire: Wire) {
+    for batch in receiver {
+        let spacing = batch.interval / batch.sends.len() as u32;
+        for (index, send) in batch.sends.iter().enumerate() {
+            if index > 0 {
+                thread::sleep(spacing);
+            }
+            // Woken for the batch, then after each sleep
+            wire.energy.woke(Worker::Send);
+            wire.transmit_fresh(send);
+        }
+    }
+}
+
+/// Runs in `Last`, after everything that sends this frame.
+pub fn release_paced_sends(time: Res<Time>, args: Res<Args>, network: Res<NetworkState>) {
+    let interval = if args.net_tick_hz > 0.0 {
+        Duration::from_secs_f32(1.0 / args.net_tick_hz)
+    } else {
+        time.delta()
+    };
+    network.pacer.release(&network.wire(), interval);
+}
@@ -0,0 +1,87 @@
+//! The client's current session with the server; see `common::session`.
+//!
+//! A session starts when the handshake completes and ends when the server
+//! goes silent, the address field points us elsewhere, or the window closes.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use common::session::{SessionStats, append_summary};
+use std::path::PathBuf;
+
+use crate::ClientState;
+
+#[derive(Resource, Default)]
+pub struct Session {
+    current: Option<SessionStats>,
+    /// `--session-log`: where summaries are appended
+    pub log_path: Option<PathBuf>,
+}
+
+impl Session {
+    pub fn new(log_path: Option<PathBuf>) -> Self {
+        Self {
+            current: None,
+            log_path,
+        }
+    }
+
+    pub fn start(&mut self, peer: &str, now: f32) {
+        self.current = Some(SessionStats::new(peer, now));
+    }
+
+    pub fn record_sent(&mut self, kind: &str, size: usize) {
+        if let Some(stats) = &mut self.current {
+            stats.record_sent(kind, size);
+        }
+    }
+
+    pub fn record_received(&mut self, kind: &str, size: usize) {
+        if let Some(stats) = &mut self.current {
+            stats.record_received(kind, size);
+        }
+    }
+
+    pub fn record_rtt(&mut self, secs: f32) {
+        if let Some(stats) = &mut self.current {
+            stats.record_rtt(secs);
+        }
+    }
+
+    pub fn record_ambient_rtt(&mut self, secs: f32) {
+        if let Some(stats) = &mut self.current {
+            stats.record_ambient_rtt(secs);
+        }
+    }
+
+    pub fn current(&self) -> Option<&SessionStats> {
+        self.current.as_ref()
+    }
+
+    /// Closes the session, if one is open, and logs its summary.
+    pub fn end(&mut self, now: f32, reason: &str, client_state: &mut ClientState) {
+        let Some(stats) = self.current.take() else {
+            return;
+        };
+        let lines = stats.summary(now, reason);
+        for line in &lines {
+            info!("{}", line);
+            client_state.push_log(format!("[Session]: {}", line.trim_start()));
+        }
+        if let Some(path) = &self.log_path
+            && let Err(e) = append_summary(path, &lines)
+        {
+            warn!("Could not write session log {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub fn end_session_on_exit(
+    mut exits: EventReader<AppExit>,
+    time: Res<Time>,
+    mut session: ResMut<Session>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if exits.read().next().is_some() {
+        session.end(time.elapsed_seconds(), "client closed", &mut client_state);
+    }
+}
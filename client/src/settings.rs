@@ -0,0 +1,83 @@
+//! Settings the server may change while we stay connected.
+//!
+//! When its operator runs `/config`, the server pushes a `ConfigUpdate`
+//! (see `common::handlers`) over the reliable channel, and it sends the same
+//! after every Welcome with everything changed so far. Each value we know is
+//! applied right away and logged; any other is logged and skipped, so an
+//! older client keeps working with a newer server. Every Welcome first puts
+//! the command line's values back.
+//!
+//! - `net_tick_hz`: the network tick, `--net-tick-hz` (see `pacing`)
+//! - `heartbeat_secs`: seconds between heartbeats, `--heartbeat-interval`
+//! - `chat_cooldown_secs`: the shortest gap between two chat lines, 0 at
+//!   start
+
+use bevy::prelude::*;
+use common::handlers::{ConfigUpdate, NetReceived, Welcome};
+use std::time::Duration;
+
+use crate::pacing::Pacing;
+use crate::{Args, ClientState, HeartbeatTimer};
+
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct NetworkSettings {
+    pub net_tick_hz: f32,
+    pub heartbeat_secs: f32,
+    pub chat_cooldown_secs: f32,
+}
+
+impl NetworkSettings {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            net_tick_hz: args.net_tick_hz,
+            heartbeat_secs: args.heartbeat_interval,
+            chat_cooldown_secs: 0.0,
+        }
+    }
+
+    /// The server checks values before sending them; this only keeps a
+    /// bad one from stopping the heartbeats.
+    fn set(&mut self, key: &str, value: f32) -> Result<(), &'static str> {
+        match key {
+            "net_tick_hz" => self.net_tick_hz = value,
+            "heartbeat_secs" if value <= 0.0 => return Err("must be above 0"),
+            "heartbeat_secs" => self.heartbeat_secs = value,
+            "chat_cooldown_secs" => self.chat_cooldown_secs = value,
+            _ => return Err("unknown setting"),
+        }
+        Ok(())
+    }
+}
+
+pub fn apply_config_updates(
+    args: Res<Args>,
+    mut welcomes: EventReader<NetReceived<Welcome>>,
+    mut updates: EventReader<NetReceived<ConfigUpdate>>,
+    mut settings: ResMut<NetworkSettings>,
+    mut pacing: ResMut<Pacing>,
+    mut heartbeat: ResMut<HeartbeatTimer>,
+    mut client_state: ResMut<ClientState>,
+) {
+    let before = *settings;
+    if welcomes.read().count() > 0 {
+        *settings = NetworkSettings::from_args(&args);
+    }
+    for update in updates.read() {
+        for (key, value) in &update.message.values {
+            let line = match settings.set(key, *value) {
+                Ok(()) => format!("[Config]: Server set {} to {}", key, value),
+                Err(why) => format!("[Config]: Skipped {}={}, {}", key, value, why),
+            };
+            client_state.push_log(line);
+        }
+    }
+
+    if settings.net_tick_hz != before.net_tick_hz {
+        pacing.set_net_tick_hz(settings.net_tick_hz);
+    }
+    if settings.heartbeat_secs != before.heartbeat_secs {
+        heartbeat
+            .0
+            .set_duration(Duration::from_secs_f32(settings.heartbeat_secs));
+    }
+}
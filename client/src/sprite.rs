@@ -0,0 +1,116 @@
+//! A square the arrow keys move, mirrored in the server's window; see the
+//! server's `sprites`.
+//!
+//! While connected, the client sends where the square is as
+//! `Pos <seq> <x> <y>` (see `common::handlers`) `--position-hz` times a
+//! second, moving or not, so a lost update is made good by the next. The
+//! updates are numbered from 1 again with every connection, for the server
+//! to drop any UDP delivers after a newer one. The keys do nothing while a
+//! text field has focus.
+
+use bevy::prelude::*;
+use common::handlers::{NetMessage, POSITION_BOUNDS, POSITION_SPEED, PositionUpdate};
+use common::ui::text_input::TextInput;
+
+use crate::handshake::{Handshake, HandshakeStage};
+use crate::session::Session;
+use crate::{ClientState, NetworkState};
+
+const SIZE: f32 = 24.0;
+
+#[derive(Component)]
+pub struct PlayerSprite;
+
+#[derive(Resource)]
+pub struct PositionSender {
+    timer: Timer,
+    /// The last update's number
+    seq: u32,
+}
+
+impl PositionSender {
+    pub fn new(hz: f32) -> Self {
+        Self {
+            timer: Timer::from_seconds(1.0 / hz.max(1.0), TimerMode::Repeating),
+            seq: 0,
+        }
+    }
+}
+
+pub fn setup_player_sprite(mut commands: Commands) {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.3, 0.6, 1.0),
+                custom_size: Some(Vec2::splat(SIZE)),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            ..default()
+        },
+        PlayerSprite,
+    ));
+}
+
+pub fn move_player_sprite(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    inputs: Query<&TextInput>,
+    mut sprites: Query<&mut Transform, With<PlayerSprite>>,
+) {
+    if inputs.iter().any(|input| input.focused) {
+        return;
+    }
+    let mut direction = Vec2::ZERO;
+    if keys.pressed(KeyCode::ArrowLeft) {
+        direction.x -= 1.0;
+    }
+    if keys.pressed(KeyCode::ArrowRight) {
+        direction.x += 1.0;
+    }
+    if keys.pressed(KeyCode::ArrowDown) {
+        direction.y -= 1.0;
+    }
+    if keys.pressed(KeyCode::ArrowUp) {
+        direction.y += 1.0;
+    }
+    if direction == Vec2::ZERO {
+        return;
+    }
+    let step = direction.normalize() * POSITION_SPEED * time.delta_seconds();
+    for mut transform in sprites.iter_mut() {
+        let position =
+            (transform.translation.truncate() + step).clamp(-POSITION_BOUNDS, POSITION_BOUNDS);
+        transform.translation = position.extend(1.0);
+    }
+}
+
+pub fn send_player_position(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    handshake: Res<Handshake>,
+    client_state: Res<ClientState>,
+    sprites: Query<&Transform, With<PlayerSprite>>,
+    mut sender: ResMut<PositionSender>,
+    mut session: ResMut<Session>,
+) {
+    if handshake.stage != HandshakeStage::Connected {
+        sender.seq = 0;
+        return;
+    }
+    if !sender.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Ok(transform) = sprites.get_single() else {
+        return;
+    };
+    sender.seq += 1;
+    let message = PositionUpdate {
+        seq: sender.seq,
+        x: transform.translation.x,
+        y: transform.translation.y,
+    }
+    .message();
+    network.send_to(message.as_bytes(), client_state.gameplay_addr());
+    session.record_sent(PositionUpdate::KIND, message.len());
+}
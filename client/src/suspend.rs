@@ -0,0 +1,120 @@
+//! Sleep and resume: a laptop that wakes up checks its connection at once
+//! instead of waiting out `--server-timeout`.
+//!
+//! Nothing runs while the machine is suspended, so a resume shows up as one
+//! frame that took far too long. Which clock notices depends on the OS:
+//! Windows' monotonic clock keeps counting through a suspend, so the frame
+//! itself is long; Linux's and macOS's stop, so only the wall clock has
+//! moved on. Either jumping more than `--suspend-gap` seconds (default 5)
+//! counts as a resume.
+//!
+//! When connected, a Heartbeat goes out straight away. If anything comes
+//! back within `--handshake-timeout` seconds the server still has us and
+//! nothing else happens; if not, the client reconnects, presenting its
+//! Resume token so a server that kept the session carries it on. A client
+//! that was between handshake attempts starts the next one now, with the
+//! attempt count reset. A stopped client stays stopped.
+//!
+//! A wall clock set forward, or a frame stalled in a debugger, looks the
+//! same; all it costs is one Heartbeat.
+
+use bevy::prelude::*;
+use common::disconnect::{DisconnectReason, Disconnected};
+use std::time::{Instant, SystemTime};
+
+use crate::handshake::{Handshake, HandshakeStage, start_attempt};
+use crate::session::Session;
+use crate::{Args, ClientState, NetworkState};
+
+#[derive(Resource)]
+pub struct SuspendWatch {
+    last_instant: Instant,
+    last_wall: SystemTime,
+    /// When the Heartbeat after a resume went out
+    probe_sent: Option<f32>,
+}
+
+impl Default for SuspendWatch {
+    fn default() -> Self {
+        Self {
+            last_instant: Instant::now(),
+            last_wall: SystemTime::now(),
+            probe_sent: None,
+        }
+    }
+}
+
+impl SuspendWatch {
+    /// Seconds since the last call, if either clock says the machine slept.
+    fn slept(&mut self, gap: f32) -> Option<f32> {
+        let instant = Instant::now();
+        let wall = SystemTime::now();
+        let frame = (instant - self.last_instant).as_secs_f32();
+        // A wall clock set back gives an error here; that's not a sleep
+        let wall_elapsed = wall
+            .duration_since(self.last_wall)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f32());
+        self.last_instant = instant;
+        self.last_wall = wall;
+        (frame > gap || wall_elapsed - frame > gap).then(|| frame.max(wall_elapsed))
+    }
+}
+
+/// Runs before `reconnect_system`.
+pub fn detect_resume(
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut watch: ResMut<SuspendWatch>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+    mut disconnected: EventWriter<Disconnected>,
+) {
+    let now = time.elapsed_seconds();
+    if args.suspend_gap > 0.0
+        && let Some(slept) = watch.slept(args.suspend_gap)
+    {
+        client_state.push_log(format!(
+            "[Info]: Clock jumped {:.0}s, the machine probably slept",
+            slept
+        ));
+        match handshake.stage {
+            HandshakeStage::Connected => {
+                client_state.push_log("[Info]: Checking the server still has us".to_string());
+                network.send_to(b"Heartbeat", &client_state.server_addr);
+                session.record_sent("Heartbeat", "Heartbeat".len());
+                watch.probe_sent = Some(now);
+            }
+            HandshakeStage::Resolving | HandshakeStage::Waiting | HandshakeStage::Failed => {
+                watch.probe_sent = None;
+                // Counts as a fresh start, not one more failure
+                handshake.stage = HandshakeStage::Waiting;
+                start_attempt(&network, &args, &mut handshake, &mut client_state);
+            }
+            HandshakeStage::Scanning | HandshakeStage::Stopped => {}
+        }
+        return;
+    }
+
+    let Some(sent) = watch.probe_sent else {
+        return;
+    };
+    if handshake.stage != HandshakeStage::Connected {
+        // Reconnected some other way already
+        watch.probe_sent = None;
+    } else if handshake.last_heard > sent {
+        watch.probe_sent = None;
+        client_state
+            .push_log("[Info]: Server answered; the session survived the sleep".to_string());
+    } else if now - sent > args.handshake_timeout {
+        watch.probe_sent = None;
+        client_state.push_log("[Info]: No answer after the sleep, reconnecting".to_string());
+        session.end(now, "no answer after a sleep", &mut client_state);
+        disconnected.send(Disconnected {
+            peer: client_state.server_addr.clone(),
+            reason: DisconnectReason::Timeout,
+        });
+        start_attempt(&network, &args, &mut handshake, &mut client_state);
+    }
+}
@@ -0,0 +1,126 @@
+//! Connect tickets for servers started with `--ticket-key`.
+//!
+//! With `--matchmaker <host:port>` the client asks the matchmaker for a
+//! ticket before each connection attempt whose ticket is missing or about to
+//! expire, and connects to the server the matchmaker names instead of
+//! `--server`. A ticket the server refused is dropped, so the retry asks for
+//! a new one. `--ticket` presents a ticket obtained some other way; it isn't
+//! renewed, so a refusal stops the retries.
+//!
+//! With a join code as well (`--join-code`, or the code field by the
+//! address field), the ticket is asked for with `GET /join/<code>` instead,
+//! and is always for the server the code was made for. The Invite button
+//! gets a code for the server we're on with `GET /invite?server=<addr>`.
+//!
+//! The matchmaker speaks just enough HTTP for these, answered with
+//! `server=<addr> ticket=<ticket>` (or `code=<code>`). The request blocks the
+//! frame, for at most [`TIMEOUT`]; it only happens when connecting, or
+//! inviting.
+
+use common::ticket;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::{Args, ClientState};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// A ticket this close to expiring is renewed first, so it can't run out
+/// while the Hello is on its way
+const RENEW_MARGIN_SECS: u64 = 5;
+
+pub struct Issued {
+    pub server: String,
+    pub ticket: String,
+}
+
+/// `path`'s response body, e.g. `server=... ticket=...`
+fn get(matchmaker: &str, path: &str) -> Result<String, String> {
+    let addr = matchmaker
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| "no addresses found".to_string())?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n",
+        path, matchmaker
+    )
+    .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| "malformed response".to_string())?;
+    let status = head.lines().next().unwrap_or("");
+    if !status.contains(" 200 ") {
+        // The body says why, e.g. that a code is unknown
+        return Err(format!("{}: {}", status, body.trim()));
+    }
+    Ok(body.to_string())
+}
+
+fn field(body: &str, name: &str) -> Result<String, String> {
+    body.split_whitespace()
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+        .map(str::to_string)
+        .ok_or_else(|| format!("no {} in the response", name))
+}
+
+/// A ticket for the next server in turn, or with `join_code` for the
+/// server the code was made for.
+pub fn fetch(matchmaker: &str, join_code: Option<&str>) -> Result<Issued, String> {
+    let path = join_code.map_or("/ticket".to_string(), |code| format!("/join/{}", code));
+    let body = get(matchmaker, &path)?;
+    Ok(Issued {
+        server: field(&body, "server")?,
+        ticket: field(&body, "ticket")?,
+    })
+}
+
+#[cfg(not(feature = "thin"))]
+/// A new invite code for `server`, which has to be one of the matchmaker's.
+pub fn fetch_invite(matchmaker: &str, server: &str) -> Result<String, String> {
+    let body = get(matchmaker, &format!("/invite?server={}", server))?;
+    field(&body, "code")
+}
+
+/// Run at the start of every attempt; does nothing without `--matchmaker`.
+pub fn refresh_ticket(args: &Args, client_state: &mut ClientState) {
+    let Some(matchmaker) = &args.matchmaker else {
+        return;
+    };
+    let fresh = client_state
+        .ticket
+        .as_deref()
+        .and_then(ticket::expires_at)
+        .is_some_and(|expires| expires > ticket::unix_now() + RENEW_MARGIN_SECS);
+    if fresh {
+        return;
+    }
+    match fetch(matchmaker, client_state.join_code.as_deref()) {
+        Ok(issued) => {
+            let by_code = client_state
+                .join_code
+                .as_ref()
+                .map_or(String::new(), |code| format!(" by code {}", code));
+            client_state.push_log(format!(
+                "[Info]: Got a connect ticket for {} from {}{}",
+                issued.server, matchmaker, by_code
+            ));
+            client_state.set_endpoints(&issued.server);
+            client_state.ticket = Some(issued.ticket);
+        }
+        // The attempt goes ahead, and the server says what it thinks
+        Err(reason) => {
+            client_state.push_log(format!("[Error]: Matchmaker {}: {}", matchmaker, reason))
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Packet timeline: a horizontal track where every Ping we send is drawn as a
+//! tick. The tick turns green when the server answers with a Pong and red if
+//! no answer arrives within the ack timeout.
+//!
+//! It also keeps the link statistics the Pings measure, raw and smoothed
+//! (see `common::stats`): the round trip time of each answered Ping, and
+//! for each settled one whether it was lost. Next to those it keeps the
+//! ambient round trip time, from each heartbeat to its HeartbeatAck, which
+//! is measured whether or not the user ever pings, so
+//! [`PacketTimeline::latency`] has a figure from the first heartbeat on.
+
+use bevy::prelude::*;
+use common::stats::SmoothedStat;
+use std::collections::VecDeque;
+
+/// How many seconds of history the track shows.
+const WINDOW_SECS: f32 = 10.0;
+#[cfg(not(feature = "thin"))]
+const TRACK_LEFT: f32 = -600.0;
+#[cfg(not(feature = "thin"))]
+const TRACK_WIDTH: f32 = 1000.0;
+#[cfg(not(feature = "thin"))]
+const TRACK_Y: f32 = -150.0;
+#[cfg(not(feature = "thin"))]
+const TICK_HEIGHT: f32 = 16.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MarkState {
+    Pending,
+    Acked,
+    TimedOut,
+}
+
+/// Which exchange measured a round trip time
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RttSource {
+    /// A Ping the user sent, and its Pong
+    Ping,
+    /// A heartbeat and its HeartbeatAck
+    Ambient,
+}
+
+impl RttSource {
+    #[cfg(not(feature = "thin"))]
+    pub fn label(self) -> &'static str {
+        match self {
+            RttSource::Ping => "ping",
+            RttSource::Ambient => "ambient",
+        }
+    }
+}
+
+pub struct PacketMark {
+    /// The Ping's seq, which its Pong echoes
+    pub seq: u32,
+    pub sent_at: f64,
+    pub state: MarkState,
+}
+
+#[derive(Resource)]
+pub struct PacketTimeline {
+    pub marks: VecDeque<PacketMark>,
+    /// Seconds to wait for a reply before a mark turns red
+    pub ack_timeout: f32,
+    /// Round trip times in seconds
+    pub rtt: SmoothedStat,
+    /// 1 for every Ping that timed out, 0 for every one answered, so the
+    /// averages are loss rates
+    pub lost: SmoothedStat,
+    /// Round trip times of heartbeats in seconds
+    pub ambient_rtt: SmoothedStat,
+    /// When the heartbeat still waiting for its ack went out, and when the
+    /// last ack came
+    heartbeat_sent_at: Option<f64>,
+    ambient_at: Option<f64>,
+    /// What measured the newest round trip time
+    newest: Option<RttSource>,
+    /// The last Ping's seq; they are numbered from 1
+    last_seq: u32,
+}
+
+impl PacketTimeline {
+    /// `alpha` and `windows` are the `SmoothedStat` settings for both stats.
+    pub fn new(ack_timeout: f32, alpha: f32, windows: &[f32]) -> Self {
+        Self {
+            marks: VecDeque::new(),
+            ack_timeout,
+            rtt: SmoothedStat::new(alpha, windows),
+            lost: SmoothedStat::new(alpha, windows),
+            ambient_rtt: SmoothedStat::new(alpha, windows),
+            heartbeat_sent_at: None,
+            ambient_at: None,
+            newest: None,
+            last_seq: 0,
+        }
+    }
+
+    /// Exponentially smoothed round trip time, once a Pong has arrived
+    #[cfg(any(not(feature = "thin"), feature = "bug-report", feature = "traceroute"))]
+    pub fn smoothed_rtt(&self) -> Option<f32> {
+        self.rtt.smoothed()
+    }
+
+    /// Returns the seq to send the Ping with.
+    pub fn record_sent(&mut self, now: f64) -> u32 {
+        self.last_seq = self.last_seq.wrapping_add(1).max(1);
+        self.marks.push_back(PacketMark {
+            seq: self.last_seq,
+            sent_at: now,
+            state: MarkState::Pending,
+        });
+        self.last_seq
+    }
+
+    /// A Pong acknowledges the Ping with its seq, if that one is still
+    /// waiting; an unnumbered one (seq 0, from an older server) the oldest
+    /// packet still waiting. Returns the round trip time in seconds and
+    /// records it in `rtt`.
+    pub fn ack(&mut self, seq: u32, now: f64) -> Option<f32> {
+        let mark = self
+            .marks
+            .iter_mut()
+            .find(|mark| mark.state == MarkState::Pending && (seq == 0 || mark.seq == seq))?;
+        mark.state = MarkState::Acked;
+        let rtt = (now - mark.sent_at) as f32;
+        self.rtt.record(now, rtt);
+        self.lost.record(now, 0.0);
+        self.newest = Some(RttSource::Ping);
+        Some(rtt)
+    }
+
+    /// Heartbeats aren't numbered either; a lost ack just means the next
+    /// heartbeat's time replaces this one's.
+    pub fn record_heartbeat_sent(&mut self, now: f64) {
+        self.heartbeat_sent_at = Some(now);
+    }
+
+    /// Records a HeartbeatAck in `ambient_rtt`; returns the round trip time
+    /// in seconds, `None` for an ack we sent no heartbeat for.
+    pub fn ack_heartbeat(&mut self, now: f64) -> Option<f32> {
+        let rtt = (now - self.heartbeat_sent_at.take()?) as f32;
+        self.ambient_rtt.record(now, rtt);
+        self.ambient_at = Some(now);
+        self.newest = Some(RttSource::Ambient);
+        Some(rtt)
+    }
+
+    /// Seconds since the last HeartbeatAck, `None` before the first one.
+    pub fn ambient_age(&self, now: f64) -> Option<f32> {
+        self.ambient_at.map(|at| (now - at) as f32)
+    }
+
+    /// The current smoothed round trip time, from whichever exchange
+    /// measured one last, with which one that was.
+    pub fn latency(&self) -> Option<(f32, RttSource)> {
+        let source = self.newest?;
+        let stat = match source {
+            RttSource::Ping => &self.rtt,
+            RttSource::Ambient => &self.ambient_rtt,
+        };
+        Some((stat.smoothed()?, source))
+    }
+
+    /// The oldest Ping still waiting for its Pong, which is the one the
+    /// next Pong will answer.
+    #[cfg(not(feature = "thin"))]
+    pub fn in_flight(&self) -> Option<&PacketMark> {
+        self.marks
+            .iter()
+            .find(|mark| mark.state == MarkState::Pending)
+    }
+
+    /// Fraction of the Pings in the window that got no Pong in time, once
+    /// any have been answered or given up on.
+    pub fn loss(&self) -> Option<f32> {
+        let settled = self
+            .marks
+            .iter()
+            .filter(|mark| mark.state != MarkState::Pending);
+        let (total, lost) = settled.fold((0, 0), |(total, lost), mark| {
+            (total + 1, lost + (mark.state == MarkState::TimedOut) as u32)
+        });
+        (total > 0).then(|| lost as f32 / total as f32)
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn setup_timeline(mut commands: Commands) {
+    commands.spawn(Text2dBundle {
+        text: Text::from_section(
+            "Server",
+            TextStyle {
+                font_size: 14.0,
+                color: Color::GRAY,
+                ..default()
+            },
+        ),
+        transform: Transform::from_xyz(TRACK_LEFT, TRACK_Y + TICK_HEIGHT, 0.0),
+        text_anchor: bevy::sprite::Anchor::BottomLeft,
+        ..default()
+    });
+}
+
+pub fn expire_timeline_marks(time: Res<Time>, mut timeline: ResMut<PacketTimeline>) {
+    let now = time.elapsed_seconds_f64();
+    let ack_timeout = f64::from(timeline.ack_timeout);
+
+    let mut timed_out = 0;
+    for mark in timeline.marks.iter_mut() {
+        if mark.state == MarkState::Pending && now - mark.sent_at > ack_timeout {
+            mark.state = MarkState::TimedOut;
+            timed_out += 1;
+        }
+    }
+    for _ in 0..timed_out {
+        timeline.lost.record(now, 1.0);
+    }
+
+    while let Some(mark) = timeline.marks.front() {
+        if now - mark.sent_at > f64::from(WINDOW_SECS) {
+            timeline.marks.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn draw_timeline(time: Res<Time>, timeline: Res<PacketTimeline>, mut gizmos: Gizmos) {
+    let now = time.elapsed_seconds_f64();
+
+    gizmos.line_2d(
+        Vec2::new(TRACK_LEFT, TRACK_Y),
+        Vec2::new(TRACK_LEFT + TRACK_WIDTH, TRACK_Y),
+        Color::GRAY,
+    );
+
+    for mark in timeline.marks.iter() {
+        // Newest packets sit at the right edge and scroll left as they age
+        let age = (now - mark.sent_at) as f32;
+        let x = TRACK_LEFT + TRACK_WIDTH * (1.0 - age / WINDOW_SECS);
+        let color = match mark.state {
+            MarkState::Pending => Color::YELLOW,
+            MarkState::Acked => Color::GREEN,
+            MarkState::TimedOut => Color::RED,
+        };
+        gizmos.line_2d(
+            Vec2::new(x, TRACK_Y - TICK_HEIGHT / 2.0),
+            Vec2::new(x, TRACK_Y + TICK_HEIGHT / 2.0),
+            color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline() -> PacketTimeline {
+        PacketTimeline::new(2.0, 0.5, &[10.0])
+    }
+
+    #[test]
+    fn pings_are_numbered_from_one() {
+        let mut timeline = timeline();
+        assert_eq!(timeline.record_sent(0.0), 1);
+        assert_eq!(timeline.record_sent(0.1), 2);
+    }
+
+    #[test]
+    fn a_pong_acks_the_ping_it_answers() {
+        let mut timeline = timeline();
+        timeline.record_sent(0.0);
+        let second = timeline.record_sent(0.5);
+        let rtt = timeline.ack(second, 0.75).unwrap();
+        assert!((rtt - 0.25).abs() < 1e-6);
+        assert!(timeline.marks[0].state == MarkState::Pending);
+        assert!(timeline.marks[1].state == MarkState::Acked);
+        // Answered already
+        assert_eq!(timeline.ack(second, 0.8), None);
+        assert_eq!(timeline.ack(9, 0.8), None);
+    }
+
+    #[test]
+    fn an_unnumbered_pong_acks_the_oldest() {
+        let mut timeline = timeline();
+        timeline.record_sent(0.0);
+        timeline.record_sent(0.5);
+        assert!(timeline.ack(0, 1.0).is_some());
+        assert!(timeline.marks[0].state == MarkState::Acked);
+        assert!(timeline.marks[1].state == MarkState::Pending);
+    }
+}
@@ -0,0 +1,372 @@
+//! Traceroute-lite, with `--features traceroute`: hop-by-hop latency toward
+//! the server, to tell the last mile from the server itself.
+//!
+//! The RTT the timeline shows covers the whole trip: every router on the
+//! way, then however long the server takes to get to our Ping in its next
+//! frame. A trace runs the system's `traceroute -n` (`tracert -d` on
+//! Windows) on a thread, so no raw socket or extra privileges are needed,
+//! and lists each hop's round trip as it comes in. The last hop is the
+//! server's host answering from its kernel; what's left of the RTT after
+//! that is time spent in the server app.
+//!
+//! F8 starts a trace and shows the panel. `--traceroute` runs one after
+//! every connect to a new address, for the `thin` build too, which only
+//! logs the summary.
+
+use bevy::prelude::*;
+use crossbeam::channel::{Receiver, unbounded};
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::ClientState;
+use crate::handshake::{Handshake, HandshakeStage};
+use crate::timeline::PacketTimeline;
+
+/// Hops tried before giving up on reaching the server
+const MAX_HOPS: u32 = 30;
+
+#[derive(Clone, Debug)]
+pub struct Hop {
+    pub ttl: u32,
+    /// `None` when the hop didn't answer
+    pub addr: Option<IpAddr>,
+    /// Fastest of its probes
+    pub ms: Option<f32>,
+}
+
+enum TraceEvent {
+    Hop(Hop),
+    Done,
+    Failed(String),
+}
+
+#[derive(Resource, Default)]
+pub struct Traceroute {
+    /// The server's host, as traced
+    target: Option<IpAddr>,
+    running: Option<Receiver<TraceEvent>>,
+    hops: Vec<Hop>,
+    /// Why the last trace didn't finish
+    error: Option<String>,
+}
+
+impl Traceroute {
+    pub fn running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Starts tracing `server`, unless a trace is still running.
+    pub fn start(&mut self, server: &str) -> Result<(), String> {
+        if self.running() {
+            return Err("a trace is already running".to_string());
+        }
+        let host = server
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .map_err(|_| format!("{} is not resolved yet", server))?;
+        let (sender, receiver) = unbounded();
+        thread::spawn(move || {
+            let event = match trace(host, |hop| {
+                let _ = sender.send(TraceEvent::Hop(hop));
+            }) {
+                Ok(()) => TraceEvent::Done,
+                Err(e) => TraceEvent::Failed(e),
+            };
+            let _ = sender.send(event);
+        });
+        self.target = Some(host);
+        self.running = Some(receiver);
+        self.hops.clear();
+        self.error = None;
+        Ok(())
+    }
+
+    /// The last hop to answer, if it is the server's host.
+    fn server_hop(&self) -> Option<&Hop> {
+        self.hops
+            .iter()
+            .rev()
+            .find(|hop| hop.addr.is_some())
+            .filter(|hop| hop.addr == self.target)
+    }
+
+    /// One line comparing the trace to the round trip the app measures.
+    pub fn summary(&self, rtt: Option<f32>) -> String {
+        let Some(target) = self.target else {
+            return "No trace yet".to_string();
+        };
+        if let Some(error) = &self.error {
+            return format!("Trace to {} failed: {}", target, error);
+        }
+        if self.running() {
+            return format!("Tracing {}, {} hops so far", target, self.hops.len());
+        }
+        let Some(hop) = self.server_hop() else {
+            let last = self.hops.iter().rev().find(|hop| hop.ms.is_some());
+            return match last {
+                Some(last) => format!(
+                    "{} didn't answer; hop {} ({}) is the last that did, at {:.1} ms",
+                    target,
+                    last.ttl,
+                    last.addr.map_or("?".to_string(), |addr| addr.to_string()),
+                    last.ms.unwrap_or_default()
+                ),
+                None => format!("No hop toward {} answered", target),
+            };
+        };
+        let network = hop.ms.unwrap_or_default();
+        match rtt {
+            Some(rtt) => {
+                let rtt = rtt * 1000.0;
+                format!(
+                    "Network {:.1} ms over {} hops; RTT {:.1} ms, so about {:.1} ms in the server",
+                    network,
+                    hop.ttl,
+                    rtt,
+                    (rtt - network).max(0.0)
+                )
+            }
+            None => format!(
+                "Network {:.1} ms over {} hops; no RTT to compare yet",
+                network, hop.ttl
+            ),
+        }
+    }
+}
+
+fn command(host: IpAddr) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("tracert");
+        command.args(["-d", "-w", "1000", "-h", &MAX_HOPS.to_string()]);
+        command.arg(host.to_string());
+        command
+    } else {
+        let mut command = Command::new("traceroute");
+        command.args(["-n", "-q", "1", "-w", "1", "-m", &MAX_HOPS.to_string()]);
+        command.arg(host.to_string());
+        command
+    }
+}
+
+/// Runs the system trace, handing over each hop as its line comes in.
+fn trace(host: IpAddr, mut on_hop: impl FnMut(Hop)) -> Result<(), String> {
+    let mut command = command(host);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if let Some(hop) = parse_hop(&line) {
+                on_hop(hop);
+            }
+        }
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", program, status))
+    }
+}
+
+/// A hop line from either tool, e.g. ` 3  10.0.0.1  4.213 ms` or
+/// `  3    <1 ms     2 ms     1 ms  10.0.0.1`. Headers don't start with a
+/// hop number and give `None`.
+fn parse_hop(line: &str) -> Option<Hop> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let ttl = words.first()?.parse::<u32>().ok()?;
+    let addr = words[1..].iter().find_map(|word| {
+        word.trim_matches(|c| c == '(' || c == ')' || c == '[' || c == ']')
+            .parse::<IpAddr>()
+            .ok()
+    });
+    let ms = words
+        .windows(2)
+        .filter(|pair| pair[1] == "ms")
+        .filter_map(|pair| pair[0].trim_start_matches('<').parse::<f32>().ok())
+        .reduce(f32::min);
+    Some(Hop { ttl, addr, ms })
+}
+
+pub struct TraceroutePlugin {
+    /// `--traceroute`
+    pub on_connect: bool,
+}
+
+impl Plugin for TraceroutePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Traceroute>()
+            .add_systems(Update, collect_trace);
+        if self.on_connect {
+            app.add_systems(Update, trace_on_connect);
+        }
+        #[cfg(not(feature = "thin"))]
+        app.add_systems(Startup, setup_trace_panel).add_systems(
+            Update,
+            (start_trace_on_key, update_trace_panel.after(collect_trace)),
+        );
+    }
+}
+
+/// Once per server address, when a connection is made.
+fn trace_on_connect(
+    handshake: Res<Handshake>,
+    mut traceroute: ResMut<Traceroute>,
+    mut client_state: ResMut<ClientState>,
+    mut traced: Local<Option<String>>,
+) {
+    if handshake.stage != HandshakeStage::Connected
+        || traced.as_deref() == Some(client_state.server_addr.as_str())
+    {
+        return;
+    }
+    *traced = Some(client_state.server_addr.clone());
+    let server = client_state.server_addr.clone();
+    match traceroute.start(&server) {
+        Ok(()) => client_state.push_log(format!("[Info]: Tracing the route to {}", server)),
+        Err(e) => client_state.push_log(format!("[Error]: No trace: {}", e)),
+    }
+}
+
+fn collect_trace(
+    timeline: Res<PacketTimeline>,
+    mut traceroute: ResMut<Traceroute>,
+    mut client_state: ResMut<ClientState>,
+) {
+    let Some(receiver) = traceroute.running.clone() else {
+        return;
+    };
+    for event in receiver.try_iter() {
+        match event {
+            TraceEvent::Hop(hop) => traceroute.hops.push(hop),
+            TraceEvent::Done => traceroute.running = None,
+            TraceEvent::Failed(e) => {
+                traceroute.running = None;
+                traceroute.error = Some(e);
+            }
+        }
+    }
+    if !traceroute.running() {
+        client_state.push_log(format!(
+            "[Info]: {}",
+            traceroute.summary(timeline.smoothed_rtt())
+        ));
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+struct TracePanel;
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+struct TraceText;
+
+#[cfg(not(feature = "thin"))]
+fn setup_trace_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(50.0),
+                    left: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            TracePanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Route to the server (F8 to close)",
+                TextStyle {
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                TraceText,
+            ));
+        });
+}
+
+/// Opening the panel starts a trace; closing it leaves one running.
+#[cfg(not(feature = "thin"))]
+fn start_trace_on_key(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut panels: Query<&mut Visibility, With<TracePanel>>,
+    mut traceroute: ResMut<Traceroute>,
+    mut client_state: ResMut<ClientState>,
+) {
+    if !keys.just_pressed(KeyCode::F8) {
+        return;
+    }
+    for mut visibility in panels.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => {
+                let server = client_state.server_addr.clone();
+                if let Err(e) = traceroute.start(&server) {
+                    client_state.push_log(format!("[Error]: No trace: {}", e));
+                }
+                Visibility::Visible
+            }
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+fn update_trace_panel(
+    timeline: Res<PacketTimeline>,
+    traceroute: Res<Traceroute>,
+    mut texts: Query<&mut Text, With<TraceText>>,
+) {
+    if !traceroute.is_changed() && !timeline.is_changed() {
+        return;
+    }
+    let mut lines = Vec::new();
+    let mut previous = 0.0;
+    for hop in &traceroute.hops {
+        let addr = hop.addr.map_or("*".to_string(), |addr| addr.to_string());
+        match hop.ms {
+            Some(ms) => {
+                lines.push(format!(
+                    "{:>2}  {:<40} {:>7.1} ms  ({:+.1})",
+                    hop.ttl,
+                    addr,
+                    ms,
+                    ms - previous
+                ));
+                previous = ms;
+            }
+            None => lines.push(format!("{:>2}  {:<40}       -", hop.ttl, addr)),
+        }
+    }
+    lines.push(traceroute.summary(timeline.smoothed_rtt()));
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,97 @@
+//! Session-wide traffic counters, bumped by the socket threads and the send
+//! helpers on `NetworkState`, and shown in a footer line so it's obvious
+//! whether traffic is flowing before any log entry is rendered.
+
+#[cfg(not(feature = "thin"))]
+use bevy::prelude::*;
+#[cfg(not(feature = "thin"))]
+use common::compression::CompressionStats;
+#[cfg(not(feature = "thin"))]
+use common::players::PlayerCount;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(not(feature = "thin"))]
+use crate::NetworkState;
+
+#[derive(Default)]
+pub struct TrafficCounters {
+    pub packets_sent: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub packets_received: AtomicU64,
+    pub bytes_received: AtomicU64,
+    /// Datagrams dropped because they didn't fit the receive buffer
+    pub truncated_packets: AtomicU64,
+    /// Datagrams dropped unread because they came from somewhere other than
+    /// our server: another host, or a forged source address
+    pub stray_packets: AtomicU64,
+    /// Real-time messages dropped unsent because they outlived their
+    /// time-to-live while queued; see `send_pacer`
+    pub stale_dropped: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub fn record_sent(&self, bytes: usize) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct FooterText;
+
+#[cfg(not(feature = "thin"))]
+pub fn setup_footer(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 14.0,
+                color: Color::rgb(0.7, 0.7, 0.7),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        FooterText,
+    ));
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn update_footer(
+    network: Res<NetworkState>,
+    players: Res<PlayerCount>,
+    compression: Res<CompressionStats>,
+    mut query: Query<&mut Text, With<FooterText>>,
+) {
+    let counters = &network.counters;
+    let mut footer = format!(
+        "Sent: {} packets / {} bytes   Received: {} packets / {} bytes   Dropped oversized: {}   Players online: {}   Spectators: {}",
+        counters.packets_sent.load(Ordering::Relaxed),
+        counters.bytes_sent.load(Ordering::Relaxed),
+        counters.packets_received.load(Ordering::Relaxed),
+        counters.bytes_received.load(Ordering::Relaxed),
+        counters.truncated_packets.load(Ordering::Relaxed),
+        players.connected,
+        players.spectators,
+    );
+    if let Some(summary) = compression.summary() {
+        footer.push_str("   ");
+        footer.push_str(&summary);
+    }
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != footer {
+            text.sections[0].value = footer.clone();
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Switching between UDP and TCP at runtime.
+//!
+//! The client normally talks UDP. On a network that blocks it, the same
+//! server can be reached over TCP if it was started with `--tcp`: the same
+//! host and port, the same messages, each framed with its length (see
+//! `common::framing`). `--transport tcp` starts on TCP, the menu next to the
+//! address field switches either way, and with `--tcp-fallback-after N` the
+//! client switches to TCP by itself after N failed UDP attempts in a row.
+//!
+//! A switch drops the current connection and starts a new attempt over the
+//! other transport. It presents the resume token like any reconnect, so a
+//! server that still holds our session carries it on.
+//!
+//! Without either, the client still notices the usual culprit, a network
+//! that drops UDP: after `--detect-udp-block` attempts (default 2) during
+//! which not a single datagram arrived, it tries a TCP connection to the
+//! server. If that gets through, the server is up and UDP is what's missing,
+//! so it says so in the log and moves to TCP. If TCP fails too, the server is
+//! more likely down, and it stays on UDP.
+
+use bevy::prelude::*;
+use clap::ValueEnum;
+use common::error::{NetError, NetErrorSink};
+use common::framing::{self, Frame};
+use common::middleware::Pipeline;
+use std::collections::VecDeque;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::latency::Arrival;
+use crate::traffic::TrafficCounters;
+
+#[cfg(not(feature = "thin"))]
+use common::ui::widgets::{ButtonFlash, ThemedButton, UiTheme};
+
+#[cfg(not(feature = "thin"))]
+use crate::handshake::{Handshake, start_attempt};
+#[cfg(not(feature = "thin"))]
+use crate::session::Session;
+#[cfg(not(feature = "thin"))]
+use crate::{Args, ClientState, NetworkState};
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Transport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+impl Transport {
+    pub fn label(self) -> &'static str {
+        match self {
+            Transport::Udp => "UDP",
+            Transport::Tcp => "TCP",
+        }
+    }
+}
+
+/// Whether a TCP connection to `addr` can be made, for telling a blocked
+/// UDP from a server that's down. Blocks the frame for up to `timeout`.
+pub fn tcp_reachable(addr: SocketAddr, timeout: Duration) -> bool {
+    TcpStream::connect_timeout(&addr, timeout)
+        .map(|stream| stream.shutdown(Shutdown::Both))
+        .is_ok()
+}
+
+/// Where the TCP reader puts what it receives; the same queue the UDP
+/// receive thread fills.
+#[derive(Clone)]
+pub struct Inbox {
+    pub received: Arc<Mutex<VecDeque<(String, String, Arrival)>>>,
+    pub counters: Arc<TrafficCounters>,
+    pub errors: NetErrorSink,
+    pub pipeline: Pipeline,
+    /// `--recv-buffer`
+    pub buf_size: usize,
+}
+
+struct Connection {
+    stream: TcpStream,
+    /// Set when we close it ourselves, so the reader doesn't report it
+    closed: Arc<AtomicBool>,
+}
+
+/// The connection to the server while we are on TCP. Sends go over it
+/// from the first `open` until `use_udp`, even while it isn't connected, so
+/// nothing leaks out over UDP between attempts.
+#[derive(Default)]
+pub struct TcpLink {
+    active: AtomicBool,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl TcpLink {
+    pub fn active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Connects to `addr` and starts a thread reading from it, replacing any
+    /// connection we had. Blocks the frame for up to `timeout`.
+    pub fn open(&self, addr: SocketAddr, timeout: Duration, inbox: Inbox) -> std::io::Result<()> {
+        self.active.store(true, Ordering::Relaxed);
+        self.close();
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        // Heartbeats and pings are small; don't hold them back to batch
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let reader_closed = closed.clone();
+        let peer = addr.to_string();
+
+        thread::spawn(move || {
+            loop {
+                match framing::read_frame(&mut reader, inbox.buf_size) {
+                    Ok(Frame::Message(bytes)) => {
+                        let read = Instant::now();
+                        inbox.counters.record_received(bytes.len());
+                        let Some(bytes) = inbox.pipeline.incoming(&peer, bytes) else {
+                            continue;
+                        };
+                        let message = String::from_utf8_lossy(&bytes).to_string();
+                        let mut received = inbox.received.lock().unwrap();
+                        received.push_back((message, peer.clone(), Arrival::new(read)));
+                    }
+                    Ok(Frame::Oversized(size)) => {
+                        inbox
+                            .counters
+                            .truncated_packets
+                            .fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            "Dropped {}-byte message from {} larger than the {}-byte receive buffer",
+                            size, peer, inbox.buf_size
+                        );
+                    }
+                    Ok(Frame::Closed) => {
+                        if !reader_closed.load(Ordering::Relaxed) {
+                            inbox.errors.push(NetError::Recv(std::io::Error::new(
+                                std::io::ErrorKind::ConnectionAborted,
+                                format!("{} closed the TCP connection", peer),
+                            )));
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        if !reader_closed.load(Ordering::Relaxed) {
+                            inbox.errors.push(NetError::Recv(e));
+                        }
+                        break;
+                    }
+                }
+            }
+        });
+        *self.connection.lock().unwrap() = Some(Connection { stream, closed });
+        Ok(())
+    }
+
+    /// Closes the connection and goes back to sending over UDP.
+    pub fn use_udp(&self) {
+        self.active.store(false, Ordering::Relaxed);
+        self.close();
+    }
+
+    fn close(&self) {
+        if let Some(connection) = self.connection.lock().unwrap().take() {
+            connection.closed.store(true, Ordering::Relaxed);
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn send(&self, bytes: &[u8]) -> std::io::Result<()> {
+        match self.connection.lock().unwrap().as_mut() {
+            Some(connection) => framing::write_frame(&mut connection.stream, bytes),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "no TCP connection",
+            )),
+        }
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct TransportButton;
+
+/// The list under the button, open while choosing
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct TransportMenu;
+
+#[cfg(not(feature = "thin"))]
+#[derive(Component)]
+pub struct TransportOption(pub Transport);
+
+#[cfg(not(feature = "thin"))]
+fn menu_item(parent: &mut ChildBuilder, theme: &UiTheme, label: &str, marker: impl Bundle) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(64.0),
+                    height: Val::Px(28.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: theme.button_color.into(),
+                ..default()
+            },
+            ThemedButton,
+            ButtonFlash::default(),
+            marker,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font_size: 16.0,
+                    color: theme.text_color,
+                    ..default()
+                },
+            ));
+        });
+}
+
+#[cfg(not(feature = "thin"))]
+fn button_label(transport: Transport) -> String {
+    format!("{} v", transport.label())
+}
+
+#[cfg(not(feature = "thin"))]
+/// The dropdown: a button showing the current transport, which opens the
+/// list of transports below it.
+pub fn spawn_transport_menu(parent: &mut ChildBuilder, theme: &UiTheme, current: Transport) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|menu| {
+            menu_item(menu, theme, &button_label(current), TransportButton);
+            menu.spawn((
+                NodeBundle {
+                    style: Style {
+                        display: Display::None,
+                        position_type: PositionType::Absolute,
+                        top: Val::Px(30.0),
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(2.0),
+                        ..default()
+                    },
+                    z_index: ZIndex::Global(20),
+                    ..default()
+                },
+                TransportMenu,
+            ))
+            .with_children(|list| {
+                for transport in Transport::value_variants() {
+                    menu_item(list, theme, transport.label(), TransportOption(*transport));
+                }
+            });
+        });
+}
+
+#[cfg(not(feature = "thin"))]
+pub fn toggle_transport_menu(
+    buttons: Query<&Interaction, (Changed<Interaction>, With<TransportButton>)>,
+    mut menus: Query<&mut Style, With<TransportMenu>>,
+) {
+    if !buttons
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        return;
+    }
+    for mut style in menus.iter_mut() {
+        style.display = match style.display {
+            Display::None => Display::Flex,
+            _ => Display::None,
+        };
+    }
+}
+
+#[cfg(not(feature = "thin"))]
+/// Picking the other transport from the list drops the connection and
+/// reconnects over it.
+pub fn pick_transport(
+    options: Query<(&Interaction, &TransportOption), Changed<Interaction>>,
+    mut menus: Query<&mut Style, With<TransportMenu>>,
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut handshake: ResMut<Handshake>,
+    mut client_state: ResMut<ClientState>,
+    mut session: ResMut<Session>,
+) {
+    let Some(transport) = options
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+        .map(|(_, option)| option.0)
+    else {
+        return;
+    };
+    for mut style in menus.iter_mut() {
+        style.display = Display::None;
+    }
+    if transport == client_state.transport {
+        return;
+    }
+    let line = format!(
+        "[Info]: Switching from {} to {}",
+        client_state.transport.label(),
+        transport.label()
+    );
+    client_state.push_log(line);
+    session.end(
+        time.elapsed_seconds(),
+        &format!("switched to {}", transport.label()),
+        &mut client_state,
+    );
+    client_state.transport = transport;
+    handshake.reset_attempts();
+    start_attempt(&network, &args, &mut handshake, &mut client_state);
+}
+
+#[cfg(not(feature = "thin"))]
+/// Keeps the button's label on the transport in use, which a fallback may
+/// have changed.
+pub fn update_transport_button(
+    client_state: Res<ClientState>,
+    buttons: Query<&Children, With<TransportButton>>,
+    mut texts: Query<&mut Text>,
+) {
+    if !client_state.is_changed() {
+        return;
+    }
+    let label = button_label(client_state.transport);
+    for children in buttons.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child)
+                && text.sections[0].value != label
+            {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Where the bandwidth goes, message type by message type.
+//!
+//! [`BandwidthMeter`] is a `middleware` stage that both binaries add at
+//! [`METER_ORDER`], below signing and compression, so it sees every
+//! datagram as the app wrote it or will read it. Each one is counted under
+//! its message kind (the first word, see `session::message_kind`); a
+//! reliable payload counts under the kind it carries, so snapshot chunks
+//! show up as `SNAP`, not `REL`. Sizes are of the plain message, so the
+//! footer's wire totals come out higher when replies are signed and lower
+//! when they are compressed.
+//!
+//! [`BandwidthStats::table`] renders the counts sorted by bytes, biggest
+//! first, for the diagnostics panels.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::middleware::Middleware;
+use crate::reliable::ReliableMessage;
+use crate::session::message_kind;
+
+/// Below signing and compression, above the packet trace (0)
+pub const METER_ORDER: i32 = 1;
+/// Kinds come from whatever peers send; past this many, new ones count as
+/// `(other)`
+const MAX_KINDS: usize = 64;
+
+#[derive(Clone, Copy, Default)]
+pub struct KindTraffic {
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub received_packets: u64,
+    pub received_bytes: u64,
+}
+
+impl KindTraffic {
+    pub fn total_bytes(&self) -> u64 {
+        self.sent_bytes + self.received_bytes
+    }
+}
+
+/// Counts per message kind since startup, shared between the stage and the
+/// app.
+#[derive(Resource, Clone, Default)]
+pub struct BandwidthStats(Arc<Mutex<HashMap<String, KindTraffic>>>);
+
+impl BandwidthStats {
+    fn record(&self, bytes: &[u8], sent: bool) {
+        let text = String::from_utf8_lossy(bytes);
+        let kind = traffic_kind(&text);
+        let mut kinds = self.0.lock().unwrap();
+        let kind = if kinds.contains_key(kind) || kinds.len() < MAX_KINDS {
+            kind
+        } else {
+            "(other)"
+        };
+        let traffic = kinds.entry(kind.to_string()).or_default();
+        if sent {
+            traffic.sent_packets += 1;
+            traffic.sent_bytes += bytes.len() as u64;
+        } else {
+            traffic.received_packets += 1;
+            traffic.received_bytes += bytes.len() as u64;
+        }
+    }
+
+    /// Every kind seen so far, most bytes in both directions first
+    pub fn sorted(&self) -> Vec<(String, KindTraffic)> {
+        let kinds = self.0.lock().unwrap();
+        let mut sorted: Vec<(String, KindTraffic)> = kinds
+            .iter()
+            .map(|(kind, traffic)| (kind.clone(), *traffic))
+            .collect();
+        sorted.sort_by(|a, b| {
+            b.1.total_bytes()
+                .cmp(&a.1.total_bytes())
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        sorted
+    }
+
+    /// A header and one line per kind, the top `limit` kinds, with the rest
+    /// folded into a last line
+    pub fn table(&self, limit: usize) -> Vec<String> {
+        let sorted = self.sorted();
+        let total: u64 = sorted
+            .iter()
+            .map(|(_, traffic)| traffic.total_bytes())
+            .sum();
+        let mut lines = vec![format!(
+            "{:<14} {:>7} {:>10}   {:>7} {:>10}   {:>5}",
+            "message", "sent", "bytes", "recv", "bytes", "share"
+        )];
+        if sorted.is_empty() {
+            lines.push("(nothing yet)".to_string());
+            return lines;
+        }
+        let row = |kind: &str, traffic: &KindTraffic| {
+            format!(
+                "{:<14} {:>7} {:>10}   {:>7} {:>10}   {:>4.0}%",
+                kind,
+                traffic.sent_packets,
+                traffic.sent_bytes,
+                traffic.received_packets,
+                traffic.received_bytes,
+                traffic.total_bytes() as f64 * 100.0 / total.max(1) as f64
+            )
+        };
+        for (kind, traffic) in sorted.iter().take(limit) {
+            lines.push(row(kind, traffic));
+        }
+        if sorted.len() > limit {
+            let rest = sorted[limit..]
+                .iter()
+                .fold(KindTraffic::default(), |sum, (_, traffic)| KindTraffic {
+                    sent_packets: sum.sent_packets + traffic.sent_packets,
+                    sent_bytes: sum.sent_bytes + traffic.sent_bytes,
+                    received_packets: sum.received_packets + traffic.received_packets,
+                    received_bytes: sum.received_bytes + traffic.received_bytes,
+                });
+            lines.push(row(&format!("({} more)", sorted.len() - limit), &rest));
+        }
+        lines
+    }
+}
+
+/// `REL 7 Chat hi` counts as `Chat`; anything else by its first word.
+fn traffic_kind(text: &str) -> &str {
+    match ReliableMessage::parse(text) {
+        Some(ReliableMessage::Data { payload, .. }) if !payload.is_empty() => message_kind(payload),
+        _ => message_kind(text),
+    }
+}
+
+/// Counts every datagram into its [`BandwidthStats`] and passes it on.
+pub struct BandwidthMeter {
+    stats: BandwidthStats,
+}
+
+impl BandwidthMeter {
+    pub fn new(stats: BandwidthStats) -> Self {
+        Self { stats }
+    }
+}
+
+impl Middleware for BandwidthMeter {
+    fn name(&self) -> &'static str {
+        "bandwidth-meter"
+    }
+
+    fn outgoing(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.stats.record(&bytes, true);
+        Some(bytes)
+    }
+
+    fn incoming(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.stats.record(&bytes, false);
+        Some(bytes)
+    }
+}
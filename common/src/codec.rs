@@ -0,0 +1,400 @@
+//! How each channel's messages are encoded on the wire.
+//!
+//! Everything above the sockets deals in text messages (`Chat hello`). A
+//! [`Codec`] turns one into the bytes a datagram carries and back, and
+//! [`Codecs`] says which codec each channel of `protocol::CHANNELS` uses;
+//! a channel without one is sent as plain UTF-8 text, as it always was.
+//! A message's channel is the one its `protocol::MessageSpec` names, so a
+//! reliable message is encoded whole, `REL` header and all, by the
+//! `reliable` channel's codec.
+//!
+//! Codecs are registered before the app starts, on the command line with
+//! `--codec <channel>=<codec>` or in code:
+//!
+//! ```ignore
+//! let mut codecs = Codecs::default();
+//! codecs.register("data", JsonCodec)?;
+//! codecs.register("reliable", MyPackedSnapshots)?;
+//! pipeline.add(CODEC_ORDER, ChannelCodecs::new(codecs.clone()));
+//! ```
+//!
+//! Built in are [`TextCodec`] (`text`), [`JsonCodec`] (`json`), which
+//! writes `{"kind": "Chat", "body": "hello"}` for tools and peers in other
+//! languages, and [`BincodeCodec`] (`bincode`), which sends the typed
+//! `message::Message`s as bincode; anything else, a hand-packed binary
+//! format for instance, implements [`Codec`]. The `control` channel carries the handshake, which
+//! has to be readable before the peers know each other's codecs, so it
+//! always stays text.
+//!
+//! Both sides have to agree: the Hello (and Resume) names every channel
+//! that isn't text with `codecs=<channel>:<codec>,...`, and a server whose
+//! codecs differ answers with `Reject version=<u32> codecs=<its codecs>`
+//! instead of a Welcome.
+
+use bevy::prelude::*;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::message::{Message, is_encoded};
+use crate::middleware::Middleware;
+use crate::protocol;
+use crate::protocol::json_string;
+use crate::session::message_kind;
+
+/// Where [`ChannelCodecs`] goes in the `middleware::Pipeline`: above the
+/// stages that read messages as text (the handshake half of signing, the
+/// validator, the packet log), below compression, which works on whatever bytes it's given.
+pub const CODEC_ORDER: i32 = 8;
+
+/// What a `codecs=` field says when every channel is text
+pub const ALL_TEXT: &str = "text";
+
+pub trait Codec: Send + Sync + 'static {
+    /// As named in `--codec` and in `codecs=`
+    fn name(&self) -> &'static str;
+
+    fn encode(&self, message: &str) -> Vec<u8>;
+
+    /// `None` when `bytes` aren't in this codec's format, so another codec
+    /// (or plain text) can have them
+    fn decode(&self, bytes: &[u8]) -> Option<String>;
+}
+
+/// The message as UTF-8, unchanged.
+pub struct TextCodec;
+
+impl Codec for TextCodec {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn encode(&self, message: &str) -> Vec<u8> {
+        message.as_bytes().to_vec()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+/// `{"kind": "<first word>", "body": "<the rest>"}`; `body` is left out of
+/// a message that is only a kind.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode(&self, message: &str) -> Vec<u8> {
+        let json = match message.split_once(' ') {
+            Some((kind, body)) => format!(
+                "{{\"kind\": {}, \"body\": {}}}",
+                json_string(kind),
+                json_string(body)
+            ),
+            None => format!("{{\"kind\": {}}}", json_string(message)),
+        };
+        json.into_bytes()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        let text = std::str::from_utf8(bytes).ok()?;
+        let members = JsonReader::new(text).object()?;
+        let member = |name: &str| {
+            members
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let kind = member("kind")?;
+        Some(match member("body") {
+            Some(body) => format!("{} {}", kind, body),
+            None => kind.to_string(),
+        })
+    }
+}
+
+/// Ping, Pong, Chat and Pos as an encoded [`Message`]. Anything else on
+/// the channel, such as a History line, goes as text, which another peer's `BincodeCodec` passes on as it is.
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn encode(&self, message: &str) -> Vec<u8> {
+        match Message::parse(message) {
+            Some(message) => message.encode(),
+            None => message.as_bytes().to_vec(),
+        }
+    }
+
+    /// A malformed message is left as it came, for the receiver to log
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        if !is_encoded(bytes) {
+            return None;
+        }
+        Message::decode(bytes)
+            .ok()
+            .map(|message| message.to_string())
+    }
+}
+
+/// Just enough JSON for [`JsonCodec`]: one object whose members are all
+/// strings.
+struct JsonReader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> JsonReader<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn eat(&mut self, expected: char) -> Option<()> {
+        self.skip_whitespace();
+        self.chars.next_if_eq(&expected).map(|_| ())
+    }
+
+    fn object(mut self) -> Option<Vec<(String, String)>> {
+        self.eat('{')?;
+        let mut members = Vec::new();
+        if self.eat('}').is_none() {
+            loop {
+                let key = self.string()?;
+                self.eat(':')?;
+                members.push((key, self.string()?));
+                if self.eat(',').is_none() {
+                    self.eat('}')?;
+                    break;
+                }
+            }
+        }
+        self.skip_whitespace();
+        // Nothing may follow the object
+        self.chars.peek().is_none().then_some(members)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.eat('"')?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(value),
+                '\\' => {
+                    let escaped = match self.chars.next()? {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => self.unicode_escape()?,
+                        c @ ('"' | '\\' | '/') => c,
+                        _ => return None,
+                    };
+                    value.push(escaped);
+                }
+                c if c.is_control() => return None,
+                c => value.push(c),
+            }
+        }
+    }
+
+    /// What follows `\u`; outside the basic plane that is a surrogate pair,
+    /// e.g. `\ud83d\ude00`
+    fn unicode_escape(&mut self) -> Option<char> {
+        let first = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&first) {
+            return char::from_u32(first);
+        }
+        self.chars.next_if_eq(&'\\')?;
+        self.chars.next_if_eq(&'u')?;
+        let second = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&second) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00))
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        (0..4).try_fold(0, |value, _| {
+            Some(value * 16 + self.chars.next()?.to_digit(16)?)
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("no channel called {0}")]
+    UnknownChannel(String),
+    #[error("the control channel carries the handshake, so it stays text")]
+    Control,
+    #[error("no codec called {0}; there are text, json and bincode")]
+    UnknownCodec(String),
+    #[error("expected <channel>=<codec>, got {0}")]
+    Malformed(String),
+}
+
+/// Which codec each channel uses; a channel without one is text. Cheap to
+/// clone: the codecs themselves are shared.
+#[derive(Resource, Clone, Default)]
+pub struct Codecs {
+    channels: Vec<(&'static str, Arc<dyn Codec>)>,
+}
+
+impl Codecs {
+    /// From `--codec` values, each `<channel>=<codec>` with a built-in
+    /// codec.
+    pub fn from_args(values: &[String]) -> Result<Self, CodecError> {
+        let mut codecs = Codecs::default();
+        for value in values {
+            let (channel, codec) = value
+                .split_once('=')
+                .ok_or_else(|| CodecError::Malformed(value.clone()))?;
+            match codec {
+                "text" => codecs.register(channel, TextCodec)?,
+                "json" => codecs.register(channel, JsonCodec)?,
+                "bincode" => codecs.register(channel, BincodeCodec)?,
+                _ => return Err(CodecError::UnknownCodec(codec.to_string())),
+            }
+        }
+        Ok(codecs)
+    }
+
+    /// Sends `channel` with `codec` from now on, replacing the codec it had.
+    pub fn register(&mut self, channel: &str, codec: impl Codec) -> Result<(), CodecError> {
+        let spec = protocol::channel(channel)
+            .ok_or_else(|| CodecError::UnknownChannel(channel.to_string()))?;
+        if spec.name == "control" {
+            return Err(CodecError::Control);
+        }
+        info!("Channel {} uses codec {}", spec.name, codec.name());
+        self.channels.retain(|(name, _)| *name != spec.name);
+        self.channels.push((spec.name, Arc::new(codec)));
+        Ok(())
+    }
+
+    /// The codec for `message`'s channel; `None` for text.
+    fn for_message(&self, message: &str) -> Option<&dyn Codec> {
+        let kind = message_kind(message);
+        let spec = protocol::messages().find(|spec| spec.kind == kind)?;
+        self.channels
+            .iter()
+            .find(|(channel, _)| *channel == spec.channel)
+            .map(|(_, codec)| codec.as_ref())
+    }
+
+    /// What `codecs=` carries: `<channel>:<codec>` for every channel that
+    /// isn't text, sorted, or [`ALL_TEXT`].
+    pub fn describe(&self) -> String {
+        let mut channels: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|(_, codec)| codec.name() != "text")
+            .map(|(channel, codec)| format!("{}:{}", channel, codec.name()))
+            .collect();
+        if channels.is_empty() {
+            return ALL_TEXT.to_string();
+        }
+        channels.sort();
+        channels.join(",")
+    }
+
+    /// Whether a Hello or Resume uses the same codecs we do; one without
+    /// `codecs=` uses text throughout.
+    pub fn agree(&self, greeting: &str) -> bool {
+        let theirs = protocol::parse_codecs(greeting).unwrap_or(ALL_TEXT);
+        let mut theirs: Vec<&str> = theirs.split(',').collect();
+        theirs.sort_unstable();
+        theirs.join(",") == self.describe()
+    }
+
+    /// ` codecs=...` for a Hello or Resume, empty when everything is text,
+    /// so a server from before codecs still understands it.
+    pub fn field(&self) -> String {
+        let codecs = self.describe();
+        if codecs == ALL_TEXT {
+            String::new()
+        } else {
+            format!(" codecs={}", codecs)
+        }
+    }
+}
+
+/// Encodes outgoing messages with their channel's codec and decodes
+/// incoming ones, at [`CODEC_ORDER`].
+pub struct ChannelCodecs {
+    codecs: Codecs,
+}
+
+impl ChannelCodecs {
+    pub fn new(codecs: Codecs) -> Self {
+        Self { codecs }
+    }
+}
+
+impl Middleware for ChannelCodecs {
+    fn name(&self) -> &'static str {
+        "codecs"
+    }
+
+    fn outgoing(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let encoded = std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|message| Some(self.codecs.for_message(message)?.encode(message)));
+        Some(encoded.unwrap_or(bytes))
+    }
+
+    /// The incoming bytes don't say their channel, so every codec in use
+    /// other than text is tried; text is what's left
+    fn incoming(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let decoded = self
+            .codecs
+            .channels
+            .iter()
+            .filter(|(_, codec)| codec.name() != "text")
+            .find_map(|(_, codec)| codec.decode(&bytes));
+        Some(decoded.map_or(bytes, String::into_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bincode_sends_typed_messages_encoded() {
+        for text in ["Ping 3", "Pong", "Chat hello there", "Pos 2 1.5 -4.0"] {
+            let bytes = BincodeCodec.encode(text);
+            assert!(is_encoded(&bytes), "{}", text);
+            assert_eq!(BincodeCodec.decode(&bytes).as_deref(), Some(text));
+        }
+    }
+
+    #[test]
+    fn bincode_leaves_other_messages_as_text() {
+        for text in ["History 1.2.3.4:5: hi", "Pong sig=00ff", "Fx 1 flash"] {
+            let bytes = BincodeCodec.encode(text);
+            assert_eq!(bytes, text.as_bytes());
+            assert_eq!(BincodeCodec.decode(&bytes), None);
+        }
+        // Malformed, and passed on for the receiver to refuse
+        assert_eq!(BincodeCodec.decode(b"\0\0\0\xff"), None);
+    }
+
+    #[test]
+    fn bincode_is_a_built_in_codec() {
+        let codecs = Codecs::from_args(&["data=bincode".to_string()]).unwrap();
+        assert_eq!(codecs.describe(), "data:bincode");
+        assert!(codecs.agree("Hello version=2 codecs=data:bincode"));
+        assert!(!codecs.agree("Hello version=2"));
+    }
+}
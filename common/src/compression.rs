@@ -0,0 +1,405 @@
+//! Datagram compression with a dictionary both sides carry.
+//!
+//! Most of our datagrams are a few dozen bytes of text (`Heartbeat
+//! rtt_ms=12.5`, `REPL players @12 connected=3`). zstd on its own can't do
+//! much with so little input: a packet has no history to find repeats in,
+//! and the frame header eats what it saves. With [`DICTIONARY`], a sample of
+//! real protocol traffic compiled into every binary, each packet is
+//! compressed as if it followed the sample, so even a lone `Pong` finds its
+//! words in there.
+//!
+//! [`DictCompression`] is a `middleware` stage. A compressed datagram goes
+//! out as [`COMPRESSED`] + the zstd frame, and one that wouldn't shrink as
+//! [`STORED`] + the original bytes. Incoming datagrams without either
+//! marker pass through untouched, so a peer that doesn't compress still
+//! works. To such a peer nothing is compressed: a stage only compresses
+//! for peers it has had a marked datagram from, unless it was created with
+//! [`DictCompression::always`] (which a client does, to announce itself).
+//!
+//! [`CompressionStats`] counts bytes before and after, and also what zstd
+//! without the dictionary would have produced, so the gain is visible.
+//! [`CompressionPlugin`] reports both as the [`COMPRESSION_RATIO`] and
+//! [`COMPRESSION_RATIO_NO_DICT`] diagnostics.
+//!
+//! The dictionary is plain sample content, which zstd accepts as it is. A
+//! dictionary trained with `zstd --train` on captured datagrams (one file
+//! each) can replace `dict/protocol.dict`, but both sides have to be built
+//! with the same one.
+
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use zstd::bulk::{Compressor, Decompressor};
+
+use crate::middleware::Middleware;
+
+/// Where both binaries add [`DictCompression`]: above every stage that
+/// reads messages, so they all see them uncompressed
+pub const COMPRESSION_ORDER: i32 = 10;
+
+pub const DICTIONARY: &[u8] = include_bytes!("../dict/protocol.dict");
+
+/// First byte of a compressed datagram. Text never starts with it.
+pub const COMPRESSED: u8 = 0x01;
+/// First byte of a datagram sent as it was because compressing it didn't
+/// make it smaller
+pub const STORED: u8 = 0x02;
+
+/// The marker a framed datagram starts with. These two bytes are the whole
+/// header the stage adds; anything else passes through as it is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Marker {
+    Compressed,
+    Stored,
+}
+
+impl Marker {
+    /// `None` for a datagram the stage didn't frame
+    pub fn of(bytes: &[u8]) -> Option<Self> {
+        match *bytes.first()? {
+            COMPRESSED => Some(Self::Compressed),
+            STORED => Some(Self::Stored),
+            _ => None,
+        }
+    }
+
+    pub const fn byte(self) -> u8 {
+        match self {
+            Self::Compressed => COMPRESSED,
+            Self::Stored => STORED,
+        }
+    }
+
+    /// For the send inspector
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Compressed => "compressed",
+            Self::Stored => "stored uncompressed",
+        }
+    }
+}
+
+/// Levels above this cost time and gain nothing on packets this small
+const LEVEL: i32 = 3;
+
+/// Bytes on the wire per byte of payload, for what we sent this frame
+pub const COMPRESSION_RATIO: DiagnosticPath = DiagnosticPath::const_new("compression/ratio");
+/// The same ratio had the packets been compressed without the dictionary
+pub const COMPRESSION_RATIO_NO_DICT: DiagnosticPath =
+    DiagnosticPath::const_new("compression/ratio_no_dict");
+
+#[derive(Default)]
+struct Counters {
+    plain_bytes: AtomicU64,
+    wire_bytes: AtomicU64,
+    no_dict_bytes: AtomicU64,
+}
+
+/// Outgoing byte counts since startup, shared between the stage and the
+/// app.
+#[derive(Resource, Clone, Default)]
+pub struct CompressionStats(Arc<Counters>);
+
+impl CompressionStats {
+    fn record(&self, plain: usize, wire: usize, no_dict: usize) {
+        self.0
+            .plain_bytes
+            .fetch_add(plain as u64, Ordering::Relaxed);
+        self.0.wire_bytes.fetch_add(wire as u64, Ordering::Relaxed);
+        self.0
+            .no_dict_bytes
+            .fetch_add(no_dict as u64, Ordering::Relaxed);
+    }
+
+    /// (plain, on the wire, without the dictionary)
+    fn totals(&self) -> (u64, u64, u64) {
+        (
+            self.0.plain_bytes.load(Ordering::Relaxed),
+            self.0.wire_bytes.load(Ordering::Relaxed),
+            self.0.no_dict_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// (with the dictionary, without), over everything sent so far
+    pub fn ratios(&self) -> Option<(f64, f64)> {
+        let (plain, wire, no_dict) = self.totals();
+        (plain > 0).then(|| (wire as f64 / plain as f64, no_dict as f64 / plain as f64))
+    }
+
+    /// For a footer: `Compressed: 48% (71% without dictionary)`, once
+    /// anything has been compressed
+    pub fn summary(&self) -> Option<String> {
+        let (wire, no_dict) = self.ratios()?;
+        Some(format!(
+            "Compressed: {:.0}% ({:.0}% without dictionary)",
+            wire * 100.0,
+            no_dict * 100.0
+        ))
+    }
+}
+
+pub struct DictCompression {
+    compressor: Mutex<Compressor<'static>>,
+    /// Only for the stats
+    baseline: Mutex<Compressor<'static>>,
+    decompressor: Mutex<Decompressor<'static>>,
+    /// Largest datagram a decompressed one may grow to
+    max_size: usize,
+    always: bool,
+    /// Peers that have sent us a marked datagram
+    speakers: Mutex<HashSet<String>>,
+    stats: CompressionStats,
+}
+
+impl DictCompression {
+    /// Compresses for peers once they have shown they can decompress.
+    pub fn new(max_size: usize, stats: CompressionStats) -> std::io::Result<Self> {
+        let mut compressor = Compressor::with_dictionary(LEVEL, DICTIONARY)?;
+        // Both sides know which dictionary it is
+        compressor.include_dictid(false)?;
+        compressor.include_checksum(false)?;
+        Ok(Self {
+            compressor: Mutex::new(compressor),
+            baseline: Mutex::new(Compressor::new(LEVEL)?),
+            decompressor: Mutex::new(Decompressor::with_dictionary(DICTIONARY)?),
+            max_size,
+            always: false,
+            speakers: Mutex::new(HashSet::new()),
+            stats,
+        })
+    }
+
+    /// Compresses for every peer from the first datagram.
+    pub fn always(max_size: usize, stats: CompressionStats) -> std::io::Result<Self> {
+        Ok(Self {
+            always: true,
+            ..Self::new(max_size, stats)?
+        })
+    }
+}
+
+impl Middleware for DictCompression {
+    fn name(&self) -> &'static str {
+        "dict-compression"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        if !self.always && !self.speakers.lock().unwrap().contains(peer) {
+            return Some(bytes);
+        }
+        let compressed = match self.compressor.lock().unwrap().compress(&bytes) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                warn!(
+                    "Compressing {} bytes for {} failed: {}",
+                    bytes.len(),
+                    peer,
+                    e
+                );
+                Vec::new()
+            }
+        };
+        let no_dict = self
+            .baseline
+            .lock()
+            .unwrap()
+            .compress(&bytes)
+            .map_or(bytes.len(), |baseline| baseline.len().min(bytes.len()))
+            + 1;
+        let mut framed = Vec::with_capacity(bytes.len() + 1);
+        if !compressed.is_empty() && compressed.len() < bytes.len() {
+            framed.push(Marker::Compressed.byte());
+            framed.extend_from_slice(&compressed);
+        } else {
+            framed.push(Marker::Stored.byte());
+            framed.extend_from_slice(&bytes);
+        }
+        self.stats.record(bytes.len(), framed.len(), no_dict);
+        Some(framed)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let Some(marker) = Marker::of(&bytes) else {
+            return Some(bytes);
+        };
+        if !self.always {
+            let mut speakers = self.speakers.lock().unwrap();
+            if !speakers.contains(peer) {
+                speakers.insert(peer.to_string());
+            }
+        }
+        if marker == Marker::Stored {
+            return Some(bytes[1..].to_vec());
+        }
+        // zstd reads nothing as nothing, but no sender frames an empty packet
+        if bytes.len() == 1 {
+            warn!("Dropping an empty compressed datagram from {}", peer);
+            return None;
+        }
+        match self
+            .decompressor
+            .lock()
+            .unwrap()
+            .decompress(&bytes[1..], self.max_size)
+        {
+            Ok(plain) => Some(plain),
+            Err(e) => {
+                warn!(
+                    "Dropping undecodable compressed datagram from {}: {}",
+                    peer, e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Registers the ratio diagnostics and measures them every frame. Add it
+/// with the same stats the pipeline's stage was given.
+pub struct CompressionPlugin {
+    pub stats: CompressionStats,
+}
+
+impl Plugin for CompressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.stats.clone())
+            .register_diagnostic(Diagnostic::new(COMPRESSION_RATIO))
+            .register_diagnostic(Diagnostic::new(COMPRESSION_RATIO_NO_DICT))
+            .add_systems(Last, measure_compression);
+    }
+}
+
+fn measure_compression(
+    stats: Res<CompressionStats>,
+    mut last: Local<(u64, u64, u64)>,
+    mut diagnostics: Diagnostics,
+) {
+    let totals = stats.totals();
+    let plain = totals.0 - last.0;
+    if plain > 0 {
+        let wire = (totals.1 - last.1) as f64 / plain as f64;
+        let no_dict = (totals.2 - last.2) as f64 / plain as f64;
+        diagnostics.add_measurement(&COMPRESSION_RATIO, || wire);
+        diagnostics.add_measurement(&COMPRESSION_RATIO_NO_DICT, || no_dict);
+    }
+    *last = totals;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::NetErrorSink;
+    use crate::middleware::Pipeline;
+    use crate::shared_rng::SharedRng;
+
+    const MAX_SIZE: usize = 1024;
+
+    fn stage(always: bool) -> DictCompression {
+        let stats = CompressionStats::default();
+        if always {
+            DictCompression::always(MAX_SIZE, stats).unwrap()
+        } else {
+            DictCompression::new(MAX_SIZE, stats).unwrap()
+        }
+    }
+
+    #[test]
+    fn markers_round_trip_through_their_byte() {
+        for marker in [Marker::Compressed, Marker::Stored] {
+            assert_eq!(Marker::of(&[marker.byte()]), Some(marker));
+            assert_eq!(Marker::of(&[marker.byte(), b'x']), Some(marker));
+        }
+        assert_eq!(Marker::Compressed.byte(), 0x01);
+        assert_eq!(Marker::Stored.byte(), 0x02);
+        assert_eq!(Marker::Compressed.description(), "compressed");
+        assert_eq!(Marker::Stored.description(), "stored uncompressed");
+    }
+
+    #[test]
+    fn text_and_empty_datagrams_have_no_marker() {
+        assert_eq!(Marker::of(b""), None);
+        for byte in (0u8..=255).filter(|byte| ![COMPRESSED, STORED].contains(byte)) {
+            assert_eq!(Marker::of(&[byte]), None, "{:#04x}", byte);
+        }
+    }
+
+    #[test]
+    fn frames_only_for_peers_that_frame() {
+        let client = stage(true);
+        let server = stage(false);
+        let heartbeat = b"Heartbeat rtt_ms=12.5 loss=0".to_vec();
+
+        // The server hasn't heard a marker from us yet
+        assert_eq!(
+            server.outgoing("client", heartbeat.clone()),
+            Some(heartbeat.clone())
+        );
+
+        let framed = client.outgoing("server", heartbeat.clone()).unwrap();
+        assert_eq!(Marker::of(&framed), Some(Marker::Compressed));
+        assert!(framed.len() < heartbeat.len());
+        assert_eq!(server.incoming("client", framed), Some(heartbeat.clone()));
+
+        let reply = server.outgoing("client", b"HeartbeatAck".to_vec()).unwrap();
+        assert!(Marker::of(&reply).is_some());
+        assert_eq!(
+            client.incoming("server", reply),
+            Some(b"HeartbeatAck".to_vec())
+        );
+    }
+
+    #[test]
+    fn stores_what_would_not_shrink() {
+        let client = stage(true);
+        let noise: Vec<u8> = (0..64u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let framed = client.outgoing("server", noise.clone()).unwrap();
+        assert_eq!(framed[0], STORED);
+        assert_eq!(framed[1..], noise[..]);
+        assert_eq!(stage(false).incoming("client", framed), Some(noise));
+    }
+
+    #[test]
+    fn can_grow_a_datagram_at_the_limit_past_it() {
+        let pipeline = Pipeline::default();
+        pipeline.add(COMPRESSION_ORDER, stage(true));
+        let mut rng = SharedRng::new(7);
+        let noise: Vec<u8> = (0..MAX_SIZE).map(|_| rng.next_u64() as u8).collect();
+        let errors = NetErrorSink::default();
+        assert!(errors.within_max_size("server", &noise, MAX_SIZE));
+        // Stored, behind its marker
+        let framed = pipeline.outgoing("server", &noise).unwrap();
+        assert_eq!(framed.len(), MAX_SIZE + 1);
+        assert!(!errors.within_max_size("server", &framed, MAX_SIZE));
+    }
+
+    #[test]
+    fn drops_malformed_frames() {
+        let server = stage(false);
+        // A marker and nothing a decompressor can read
+        assert_eq!(server.incoming("client", vec![COMPRESSED]), None);
+        assert_eq!(
+            server.incoming("client", vec![COMPRESSED, 0xde, 0xad, 0xbe, 0xef]),
+            None
+        );
+        // A stored frame may be empty
+        assert_eq!(server.incoming("client", vec![STORED]), Some(Vec::new()));
+        // Text passes as it is
+        assert_eq!(
+            server.incoming("client", b"Pong".to_vec()),
+            Some(b"Pong".to_vec())
+        );
+    }
+
+    #[test]
+    fn drops_frames_that_grow_past_the_limit() {
+        let client = stage(true);
+        let big = vec![b'a'; MAX_SIZE * 4];
+        let framed = client.outgoing("server", big).unwrap();
+        assert_eq!(Marker::of(&framed), Some(Marker::Compressed));
+        assert_eq!(stage(false).incoming("client", framed), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Why a connection ended.
+//!
+//! Whichever side ends a connection on purpose says why in a
+//! `Disconnect reason=<code>` message; a connection that just goes quiet
+//! ends with [`DisconnectReason::Timeout`]. Both binaries send a
+//! [`Disconnected`] event whenever a connection ends, with the reason.
+
+use bevy::prelude::*;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisconnectReason {
+    /// Nothing heard for longer than the timeout
+    Timeout,
+    /// Removed by the server operator
+    Kicked,
+    ServerShutdown,
+    /// The two sides speak different protocol versions
+    VersionMismatch,
+    /// Credentials were refused
+    AuthFailed,
+    /// The player closed the client
+    UserQuit,
+    /// Moved to another server instance with a `Handoff`
+    HandedOff,
+}
+
+impl DisconnectReason {
+    /// The `reason=` value on the wire
+    pub fn code(self) -> &'static str {
+        match self {
+            DisconnectReason::Timeout => "timeout",
+            DisconnectReason::Kicked => "kicked",
+            DisconnectReason::ServerShutdown => "server_shutdown",
+            DisconnectReason::VersionMismatch => "version_mismatch",
+            DisconnectReason::AuthFailed => "auth_failed",
+            DisconnectReason::UserQuit => "user_quit",
+            DisconnectReason::HandedOff => "handed_off",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Some(match code {
+            "timeout" => DisconnectReason::Timeout,
+            "kicked" => DisconnectReason::Kicked,
+            "server_shutdown" => DisconnectReason::ServerShutdown,
+            "version_mismatch" => DisconnectReason::VersionMismatch,
+            "auth_failed" => DisconnectReason::AuthFailed,
+            "user_quit" => DisconnectReason::UserQuit,
+            "handed_off" => DisconnectReason::HandedOff,
+            _ => return None,
+        })
+    }
+
+    /// Whether reconnecting could help. A server that shut down may come
+    /// back and a timeout may have been a blip; a kick or refusal will just
+    /// happen again.
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            DisconnectReason::Timeout | DisconnectReason::ServerShutdown
+        )
+    }
+
+    /// `Disconnect reason=<code>`
+    pub fn message(self) -> String {
+        format!("Disconnect reason={}", self.code())
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisconnectReason::Timeout => "connection timed out",
+            DisconnectReason::Kicked => "kicked by the server",
+            DisconnectReason::ServerShutdown => "the server shut down",
+            DisconnectReason::VersionMismatch => "protocol version mismatch",
+            DisconnectReason::AuthFailed => "authentication failed",
+            DisconnectReason::UserQuit => "the player quit",
+            DisconnectReason::HandedOff => "handed off to another server",
+        })
+    }
+}
+
+/// A connection ended. On the server `peer` is the client's address, on the
+/// client it is the server's.
+#[derive(Event, Clone, Debug)]
+pub struct Disconnected {
+    pub peer: String,
+    pub reason: DisconnectReason,
+}
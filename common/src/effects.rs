@@ -0,0 +1,108 @@
+//! Cosmetic events: a hit flash, a sound cue, an emoji reaction.
+//!
+//! They travel on their own `events` channel (see `protocol`) with
+//! at-most-once delivery: nothing is acked or resent, and a duplicate or an
+//! event older than one already shown is dropped. An effect that arrives
+//! late is worse than one that never arrives, and none of them changes any
+//! state, so a lost one costs nothing.
+//!
+//! A client sends `Fx <seq> <name>[ <detail>]`, numbering its own events.
+//! The server drops repeats with an [`AtMostOnce`] per sender and fans the
+//! rest out to every connected client, the sender included, as
+//! `Fx <seq> <origin> <name>[ <detail>]`, numbered again in the order it
+//! sends them. Each client keeps one [`AtMostOnce`] for the server's
+//! numbers, started over when a new session begins.
+
+pub const KIND: &str = "Fx";
+
+/// The demo effect: every window flashes.
+pub const FLASH: &str = "flash";
+
+/// An effect as its sender describes it; receivers decide how to show it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Effect {
+    /// One word, such as `flash`, `sound` or `emoji`
+    pub name: String,
+    /// The rest of the line: which sound, which emoji
+    pub detail: Option<String>,
+}
+
+impl Effect {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            detail: None,
+        }
+    }
+
+    /// `Fx <seq> <name>[ <detail>]`, from a client.
+    pub fn request(&self, seq: u32) -> String {
+        format!("{} {} {}", KIND, seq, self.describe())
+    }
+
+    pub fn parse_request(message: &str) -> Option<(u32, Effect)> {
+        let rest = message.strip_prefix(KIND)?.strip_prefix(' ')?;
+        let (seq, rest) = rest.split_once(' ')?;
+        Some((seq.parse().ok()?, Effect::parse_words(rest)?))
+    }
+
+    /// `Fx <seq> <origin> <name>[ <detail>]`, fanned out by the server.
+    pub fn relay(&self, seq: u32, origin: &str) -> String {
+        format!("{} {} {} {}", KIND, seq, origin, self.describe())
+    }
+
+    /// (seq, origin, effect)
+    pub fn parse_relay(message: &str) -> Option<(u32, String, Effect)> {
+        let rest = message.strip_prefix(KIND)?.strip_prefix(' ')?;
+        let (seq, rest) = rest.split_once(' ')?;
+        let (origin, rest) = rest.split_once(' ')?;
+        Some((
+            seq.parse().ok()?,
+            origin.to_string(),
+            Effect::parse_words(rest)?,
+        ))
+    }
+
+    /// `name` or `name detail`, as logged and sent
+    pub fn describe(&self) -> String {
+        match &self.detail {
+            Some(detail) => format!("{} {}", self.name, detail),
+            None => self.name.clone(),
+        }
+    }
+
+    fn parse_words(text: &str) -> Option<Effect> {
+        let text = text.trim();
+        let (name, detail) = match text.split_once(' ') {
+            Some((name, detail)) => (name, Some(detail.trim().to_string())),
+            None => (text, None),
+        };
+        (!name.is_empty()).then(|| Effect {
+            name: name.to_string(),
+            detail: detail.filter(|detail| !detail.is_empty()),
+        })
+    }
+}
+
+/// Lets each sequence number through once, and never one below the newest
+/// seen.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AtMostOnce {
+    newest: Option<u32>,
+}
+
+impl AtMostOnce {
+    /// Whether `seq` is new; if it is, it becomes the newest.
+    pub fn accept(&mut self, seq: u32) -> bool {
+        if self.newest.is_some_and(|newest| seq <= newest) {
+            return false;
+        }
+        self.newest = Some(seq);
+        true
+    }
+
+    /// Forgets what was seen, for when the sender starts counting again
+    pub fn reset(&mut self) {
+        self.newest = None;
+    }
+}
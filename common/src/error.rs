@@ -0,0 +1,149 @@
+//! Networking errors.
+//!
+//! Anything that goes wrong on a socket becomes a [`NetError`] instead of a
+//! panic or a silently ignored `Result`. Errors raised inside Bevy systems
+//! are sent as events directly; errors from the receive threads go through a
+//! [`NetErrorSink`], which [`NetErrorPlugin`] drains into the same event
+//! stream every frame. Apps read `EventReader<NetError>` to show them.
+
+use bevy::prelude::*;
+use std::io;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use crate::validator::Violation;
+
+#[derive(Debug, Error, Event)]
+pub enum NetError {
+    #[error("could not bind {addr}: {source}")]
+    Bind {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("could not configure socket: {0}")]
+    Socket(#[source] io::Error),
+    #[error("could not resolve {host}: {source}")]
+    Resolve {
+        host: String,
+        #[source]
+        source: io::Error,
+    },
+    /// Connecting over TCP, when the client is on that transport
+    #[error("could not connect to {addr} over TCP: {source}")]
+    Connect {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("send to {addr} failed: {source}")]
+    Send {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("receive failed, socket closed: {0}")]
+    Recv(#[source] io::Error),
+    #[error("no Welcome from {addr} within {secs}s")]
+    HandshakeTimeout { addr: String, secs: f32 },
+    #[error("{addr} speaks protocol version {theirs}, we speak {ours}")]
+    VersionMismatch {
+        addr: String,
+        ours: u32,
+        theirs: u32,
+    },
+    /// `ours` and `theirs` as in a `codecs=` field; see `codec`
+    #[error("{addr} encodes its channels as {theirs}, we encode them as {ours}")]
+    CodecMismatch {
+        addr: String,
+        ours: String,
+        theirs: String,
+    },
+    /// `reason` is the server's reason code
+    #[error("{addr} refused our connect ticket ({reason})")]
+    TicketRefused { addr: String, reason: String },
+    /// A reply the pinned server key didn't sign; see `identity`
+    #[error("dropped a message from {addr} that the server's key didn't sign ({reason})")]
+    Unverified { addr: String, reason: String },
+    /// A message the protocol doesn't allow in the connection's state; see
+    /// `validator`
+    #[error("{addr} sent {violation}; dropped")]
+    ProtocolViolation { addr: String, violation: Violation },
+    /// Refused before sending, rather than letting the peer's receive buffer
+    /// cut it short
+    #[error("message to {addr} is {size} bytes, over the {max}-byte limit")]
+    MessageTooLarge {
+        addr: String,
+        size: usize,
+        max: usize,
+    },
+}
+
+/// Collects errors from outside the ECS (socket threads, `&self` send
+/// helpers) until the next frame turns them into events.
+#[derive(Resource, Clone, Default)]
+pub struct NetErrorSink(Arc<Mutex<Vec<NetError>>>);
+
+impl NetErrorSink {
+    pub fn push(&self, error: NetError) {
+        self.0.lock().unwrap().push(error);
+    }
+
+    /// Whether a datagram for `addr`, as it goes out after the pipeline,
+    /// is at most `max` bytes. One that isn't is raised as
+    /// [`NetError::MessageTooLarge`] and must not be sent.
+    pub fn within_max_size(&self, addr: &str, datagram: &[u8], max: usize) -> bool {
+        if datagram.len() <= max {
+            return true;
+        }
+        self.push(NetError::MessageTooLarge {
+            addr: addr.to_string(),
+            size: datagram.len(),
+            max,
+        });
+        false
+    }
+}
+
+/// Registers the [`NetError`] event and forwards the [`NetErrorSink`] into it.
+/// Insert your own sink first to share it with threads spawned before the app
+/// starts.
+pub struct NetErrorPlugin;
+
+impl Plugin for NetErrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<NetError>()
+            .init_resource::<NetErrorSink>()
+            .add_systems(PreUpdate, forward_sink_errors);
+    }
+}
+
+fn forward_sink_errors(sink: Res<NetErrorSink>, mut errors: EventWriter<NetError>) {
+    let drained: Vec<NetError> = sink.0.lock().unwrap().drain(..).collect();
+    errors.send_batch(drained);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: usize = 1024;
+
+    #[test]
+    fn sends_a_datagram_exactly_at_the_limit() {
+        let errors = NetErrorSink::default();
+        assert!(errors.within_max_size("peer", &[b'a'; MAX], MAX));
+        assert!(errors.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_a_datagram_one_byte_over() {
+        let errors = NetErrorSink::default();
+        assert!(!errors.within_max_size("peer", &[b'a'; MAX + 1], MAX));
+        let raised = errors.0.lock().unwrap();
+        assert!(matches!(
+            raised.as_slice(),
+            [NetError::MessageTooLarge { addr, size: 1025, max: MAX }] if addr == "peer"
+        ));
+    }
+}
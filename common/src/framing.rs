@@ -0,0 +1,144 @@
+//! Message framing for TCP, for networks that block UDP.
+//!
+//! A datagram carries exactly one message; a TCP stream is just bytes. On
+//! TCP every message, after the middleware pipeline, is preceded by its
+//! length as a 16-bit big-endian (network order) integer, which caps a
+//! message at [`MAX_FRAME`] bytes. Everything above the socket is the same
+//! for both transports.
+
+use std::io::{self, Read, Write};
+
+pub const MAX_FRAME: usize = u16::MAX as usize;
+
+/// One read from a stream.
+pub enum Frame {
+    Message(Vec<u8>),
+    /// A message larger than the receive buffer, skipped (its length is
+    /// given) so the next one is read from the right place
+    Oversized(usize),
+    /// The peer closed the connection between messages
+    Closed,
+}
+
+pub fn write_frame(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(bytes.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes don't fit one frame", bytes.len()),
+        )
+    })?;
+    // One write, so small messages go out in one segment
+    let mut frame = Vec::with_capacity(bytes.len() + 2);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(bytes);
+    writer.write_all(&frame)
+}
+
+/// Blocks until a whole message has arrived. Messages over `max` bytes are
+/// skipped rather than read into memory.
+pub fn read_frame(reader: &mut impl Read, max: usize) -> io::Result<Frame> {
+    let mut len = [0u8; 2];
+    match reader.read_exact(&mut len[..1]) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Frame::Closed),
+        Err(e) => return Err(e),
+    }
+    // Closing halfway through a length is a broken stream, not the end of one
+    reader.read_exact(&mut len[1..])?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > max {
+        let skipped = io::copy(&mut reader.by_ref().take(len as u64), &mut io::sink())?;
+        if skipped < len as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        return Ok(Frame::Oversized(len));
+    }
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(Frame::Message(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(bytes: &[u8]) -> Vec<u8> {
+        let mut framed = Vec::new();
+        write_frame(&mut framed, bytes).unwrap();
+        framed
+    }
+
+    /// The length goes first, most significant byte first, whatever the
+    /// host's own order. 258 is 0x0102, so a little-endian prefix would
+    /// show up as `02 01`.
+    #[test]
+    fn writes_the_length_in_network_order() {
+        assert_eq!(frame(b"Pong"), b"\x00\x04Pong");
+        let framed = frame(&[b'x'; 258]);
+        assert_eq!(framed[..2], [0x01, 0x02]);
+        assert_eq!(framed.len(), 260);
+        let framed = frame(&vec![b'x'; MAX_FRAME]);
+        assert_eq!(framed[..2], [0xff, 0xff]);
+    }
+
+    /// A capture from a big-endian writer reads the same here
+    #[test]
+    fn reads_captured_frames() {
+        let captured = b"\x00\x09Heartbeat\x00\x0cHeartbeatAck";
+        let mut stream = &captured[..];
+        for expected in [&b"Heartbeat"[..], b"HeartbeatAck"] {
+            match read_frame(&mut stream, MAX_FRAME).unwrap() {
+                Frame::Message(bytes) => assert_eq!(bytes, expected),
+                _ => panic!("expected {:?}", expected),
+            }
+        }
+        assert!(matches!(
+            read_frame(&mut stream, MAX_FRAME).unwrap(),
+            Frame::Closed
+        ));
+    }
+
+    #[test]
+    fn skips_oversized_frames_to_the_next_header() {
+        let mut stream: Vec<u8> = frame(&[b'x'; 300]);
+        stream.extend(frame(b"Pong"));
+        let mut stream = &stream[..];
+        assert!(matches!(
+            read_frame(&mut stream, 256).unwrap(),
+            Frame::Oversized(300)
+        ));
+        assert!(matches!(
+            read_frame(&mut stream, 256).unwrap(),
+            Frame::Message(bytes) if bytes == b"Pong"
+        ));
+    }
+
+    #[test]
+    fn a_cut_header_or_body_is_an_error() {
+        for broken in [&b"\x00"[..], b"\x00\x05Pon", b"\x01\x00"] {
+            let error = read_frame(&mut &broken[..], MAX_FRAME).err();
+            assert_eq!(
+                error.map(|e| e.kind()),
+                Some(io::ErrorKind::UnexpectedEof),
+                "{:?}",
+                broken
+            );
+        }
+        // Nothing at all is a clean close
+        assert!(matches!(
+            read_frame(&mut &b""[..], MAX_FRAME).unwrap(),
+            Frame::Closed
+        ));
+        // An oversized frame that's cut short is too
+        let error = read_frame(&mut &b"\x01\x00xx"[..], 16).err();
+        assert_eq!(error.map(|e| e.kind()), Some(io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn refuses_messages_over_a_frame() {
+        let mut sink = Vec::new();
+        let error = write_frame(&mut sink, &vec![0; MAX_FRAME + 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(sink.is_empty());
+    }
+}
@@ -0,0 +1,443 @@
+//! Typed handlers for received messages.
+//!
+//! Instead of every consumer matching on raw text in one receive system, a
+//! message type implements [`NetMessage`] and a system reads it as an event:
+//!
+//! ```ignore
+//! app.add_net_handler::<Reject, _>(handle_reject);
+//!
+//! fn handle_reject(mut rejects: EventReader<NetReceived<Reject>>) { ... }
+//! ```
+//!
+//! The app forwards everything it receives as [`NetIncoming`]. In
+//! `PostUpdate`, each registered type picks out the messages whose first
+//! word is its [`NetMessage::KIND`], parses them and sends them on as
+//! [`NetReceived`], and its handlers run right after. Like the replication
+//! module, this never touches a socket.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::disconnect::DisconnectReason;
+use crate::protocol::{
+    is_spectator, parse_codecs, parse_seed, parse_ticket, parse_token, parse_version,
+};
+use crate::reliable::ReliableMessage;
+use crate::session::message_kind;
+
+/// A message that can be routed to its own handlers.
+pub trait NetMessage: Send + Sync + Sized + 'static {
+    /// The first word of the message on the wire
+    const KIND: &'static str;
+
+    /// `None` when the text has the right kind but is malformed.
+    fn parse(text: &str) -> Option<Self>;
+}
+
+/// Every received message, as text.
+#[derive(Event, Clone)]
+pub struct NetIncoming {
+    pub from: String,
+    pub text: String,
+}
+
+/// One parsed message of type `M`.
+#[derive(Event)]
+pub struct NetReceived<M: NetMessage> {
+    pub from: String,
+    pub message: M,
+}
+
+pub struct NetHandlersPlugin;
+
+impl Plugin for NetHandlersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<NetIncoming>();
+    }
+}
+
+pub trait NetHandlerExt {
+    /// Runs `handler` in `PostUpdate` after this frame's `M` messages have
+    /// been routed. Add [`NetHandlersPlugin`] first. Registering several
+    /// handlers for one type is fine; each sees every message.
+    fn add_net_handler<M: NetMessage, Marker>(
+        &mut self,
+        handler: impl IntoSystemConfigs<Marker>,
+    ) -> &mut Self;
+
+    /// Adds the `NetReceived<M>` event and routes `M` into it in
+    /// `PostUpdate`, with no handler of its own; `add_net_handler` does this
+    /// first. Calling it again does nothing.
+    fn add_net_message<M: NetMessage>(&mut self) -> &mut Self;
+}
+
+impl NetHandlerExt for App {
+    fn add_net_handler<M: NetMessage, Marker>(
+        &mut self,
+        handler: impl IntoSystemConfigs<Marker>,
+    ) -> &mut Self {
+        self.add_net_message::<M>()
+            .add_systems(PostUpdate, handler.after(route::<M>))
+    }
+
+    fn add_net_message<M: NetMessage>(&mut self) -> &mut Self {
+        if !self.world.contains_resource::<Events<NetReceived<M>>>() {
+            self.add_event::<NetReceived<M>>()
+                .add_systems(PostUpdate, route::<M>);
+        }
+        self
+    }
+}
+
+fn route<M: NetMessage>(
+    mut incoming: EventReader<NetIncoming>,
+    mut received: EventWriter<NetReceived<M>>,
+) {
+    for message in incoming.read() {
+        if message_kind(&message.text) != M::KIND {
+            continue;
+        }
+        match M::parse(&message.text) {
+            Some(parsed) => {
+                received.send(NetReceived {
+                    from: message.from.clone(),
+                    message: parsed,
+                });
+            }
+            None => warn!(
+                "Malformed {} from {}: {}",
+                M::KIND,
+                message.from,
+                message.text
+            ),
+        }
+    }
+}
+
+/// `Hello[ version=<u32>][ role=spectator]`
+pub struct Hello {
+    /// `None` for a Hello from before versioning
+    pub version: Option<u32>,
+    pub spectator: bool,
+}
+
+impl NetMessage for Hello {
+    const KIND: &'static str = "Hello";
+
+    fn parse(text: &str) -> Option<Self> {
+        Some(Hello {
+            version: parse_version(text),
+            spectator: is_spectator(text),
+        })
+    }
+}
+
+/// `Welcome[ data_port=<u16>] seed=<hex>[ deprecated=<feature>,...][ without=<subset>,...]
+/// token=<hex>[ resumed]`
+pub struct Welcome {
+    pub data_port: Option<u16>,
+    /// `None` from a server older than `shared_rng`
+    pub seed: Option<u64>,
+    /// Features we go without because our Hello's version is older than
+    /// the server's; see `protocol::FEATURES`
+    pub deprecated: Vec<String>,
+    /// Protocol subsets the session goes without because we or the server
+    /// were built without them; see `protocol::SUBSETS`
+    pub without: Vec<String>,
+    /// `None` from a server without session resumption
+    pub token: Option<String>,
+    /// The Welcome answers a Resume that continued the old session
+    pub resumed: bool,
+}
+
+impl NetMessage for Welcome {
+    const KIND: &'static str = "Welcome";
+
+    fn parse(text: &str) -> Option<Self> {
+        let data_port = match text
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("data_port="))
+        {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        Some(Welcome {
+            data_port,
+            seed: parse_seed(text),
+            deprecated: text
+                .split_whitespace()
+                .find_map(|field| field.strip_prefix("deprecated="))
+                .map_or(Vec::new(), |features| {
+                    features.split(',').map(str::to_string).collect()
+                }),
+            without: text
+                .split_whitespace()
+                .find_map(|field| field.strip_prefix("without="))
+                .map_or(Vec::new(), |subsets| {
+                    subsets.split(',').map(str::to_string).collect()
+                }),
+            token: parse_token(text).map(str::to_string),
+            resumed: text.split_whitespace().any(|field| field == "resumed"),
+        })
+    }
+}
+
+/// `Reject version=<u32>[ ticket=<code>][ codecs=<codecs>]`, the server's
+/// version and, for a refused connect ticket, why, or for a Hello with other
+/// codecs, the server's; see `codec`
+pub struct Reject {
+    pub version: u32,
+    pub ticket: Option<String>,
+    pub codecs: Option<String>,
+}
+
+impl NetMessage for Reject {
+    const KIND: &'static str = "Reject";
+
+    fn parse(text: &str) -> Option<Self> {
+        Some(Reject {
+            version: parse_version(text)?,
+            ticket: parse_ticket(text).map(str::to_string),
+            codecs: parse_codecs(text).map(str::to_string),
+        })
+    }
+}
+
+/// `Disconnect reason=<code>`; see [`DisconnectReason`]
+pub struct Disconnect {
+    pub reason: DisconnectReason,
+}
+
+impl NetMessage for Disconnect {
+    const KIND: &'static str = "Disconnect";
+
+    fn parse(text: &str) -> Option<Self> {
+        let code = text
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("reason="))?;
+        Some(Disconnect {
+            reason: DisconnectReason::from_code(code)?,
+        })
+    }
+}
+
+/// `Handoff addr=<host:port> token=<token>`: the server is moving us to
+/// another instance, which honours the token in a Resume
+pub struct Handoff {
+    pub addr: String,
+    pub token: String,
+}
+
+impl NetMessage for Handoff {
+    const KIND: &'static str = "Handoff";
+
+    fn parse(text: &str) -> Option<Self> {
+        let addr = text
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("addr="))?;
+        Some(Handoff {
+            addr: addr.to_string(),
+            token: parse_token(text)?.to_string(),
+        })
+    }
+}
+
+crate::define_protocol! {
+    /// The keepalive exchange; declared with
+    /// [`define_protocol!`](crate::define_protocol), see `wire`
+    pub enum KeepAlive {
+        /// Keepalive; skipped when other traffic already proves liveness. rtt_ms is the
+        /// client's smoothed RTT once it has one, loss the fraction of recent Pings that
+        /// went unanswered
+        #[message(kind = "Heartbeat", direction = "client->server", channel = "control")]
+        Heartbeat {
+            #[optional] rtt_ms: f32,
+            #[optional] loss: f32,
+        },
+        /// Reply to every Heartbeat
+        #[message(kind = "HeartbeatAck", direction = "server->client", channel = "control")]
+        HeartbeatAck {},
+    }
+}
+
+crate::define_protocol! {
+    /// The connectionless query, answered before any handshake, so a
+    /// server list or a matchmaker can see what a server is up to without
+    /// joining it
+    pub enum ServerQuery {
+        /// What is this server? Needs no session, and starts none
+        #[message(kind = "ServerInfoRequest", direction = "client->server", channel = "control")]
+        ServerInfoRequest {},
+        /// The answer. name is the server's `--name`, version its protocol version, players
+        /// and spectators who is connected, mode `open`, or `tickets` when joining takes a
+        /// matchmaker's ticket, and uptime_secs how long it has been running
+        #[message(kind = "ServerInfo", direction = "server->client", channel = "control")]
+        ServerInfo {
+            name: String,
+            version: u32,
+            players: u32,
+            spectators: u32,
+            mode: String,
+            uptime_secs: u64,
+        },
+    }
+}
+
+/// `REL <seq> <payload>` on the reliable channel; see `reliable`
+pub struct ReliableData {
+    pub seq: u32,
+    pub payload: String,
+}
+
+impl NetMessage for ReliableData {
+    const KIND: &'static str = "REL";
+
+    fn parse(text: &str) -> Option<Self> {
+        match ReliableMessage::parse(text)? {
+            ReliableMessage::Data { seq, payload } => Some(ReliableData {
+                seq,
+                payload: payload.to_string(),
+            }),
+            ReliableMessage::Ack { .. } => None,
+        }
+    }
+}
+
+/// `REL_ACK <seq>`
+pub struct ReliableAck {
+    pub seq: u32,
+}
+
+impl NetMessage for ReliableAck {
+    const KIND: &'static str = "REL_ACK";
+
+    fn parse(text: &str) -> Option<Self> {
+        match ReliableMessage::parse(text)? {
+            ReliableMessage::Ack { seq } => Some(ReliableAck { seq }),
+            ReliableMessage::Data { .. } => None,
+        }
+    }
+}
+
+/// The settings a [`ConfigUpdate`] may carry
+pub const CONFIG_KEYS: &[&str] = &["net_tick_hz", "heartbeat_secs", "chat_cooldown_secs"];
+
+/// `ConfigUpdate <key>=<value>...`, client settings the server changes at
+/// runtime. Sent as a payload on the reliable channel, so it isn't lost.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigUpdate {
+    /// In the order sent; keys outside [`CONFIG_KEYS`] are kept, for the
+    /// receiver to skip
+    pub values: Vec<(String, f32)>,
+}
+
+impl ConfigUpdate {
+    pub fn message(&self) -> String {
+        let mut message = Self::KIND.to_string();
+        for (key, value) in &self.values {
+            message.push_str(&format!(" {}={}", key, value));
+        }
+        message
+    }
+}
+
+impl NetMessage for ConfigUpdate {
+    const KIND: &'static str = "ConfigUpdate";
+
+    fn parse(text: &str) -> Option<Self> {
+        let values = text
+            .split_whitespace()
+            .skip(1)
+            .map(|field| {
+                let (key, value) = field.split_once('=')?;
+                let value: f32 = value.parse().ok()?;
+                value.is_finite().then(|| (key.to_string(), value))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(ConfigUpdate { values })
+    }
+}
+
+/// Whether an [`Announcement`] is part of the message of the day or a
+/// scheduled one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnouncementKind {
+    Motd,
+    Notice,
+}
+
+impl AnnouncementKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            AnnouncementKind::Motd => "motd",
+            AnnouncementKind::Notice => "notice",
+        }
+    }
+}
+
+/// `Announce motd|notice <text>`, server-initiated text for the client's
+/// log. Sent as a payload on the reliable channel, like [`ConfigUpdate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Announcement {
+    pub kind: AnnouncementKind,
+    pub text: String,
+}
+
+impl Announcement {
+    pub fn message(&self) -> String {
+        format!("{} {} {}", Self::KIND, self.kind.name(), self.text)
+    }
+}
+
+impl NetMessage for Announcement {
+    const KIND: &'static str = "Announce";
+
+    fn parse(text: &str) -> Option<Self> {
+        let rest = text.strip_prefix("Announce ")?;
+        let (kind, text) = rest.split_once(' ')?;
+        let kind = match kind {
+            "motd" => AnnouncementKind::Motd,
+            "notice" => AnnouncementKind::Notice,
+            _ => return None,
+        };
+        Some(Announcement {
+            kind,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// `Pos <seq> <x> <y>`, where a client's sprite is, in world units. The
+/// client numbers every update, so a receiver can drop one that arrives
+/// after a newer one; see `effects::AtMostOnce`. Also a typed
+/// `message::Message`, for bincode.
+/// How fast a client's square moves, in world units per second; the server
+/// drops updates that would have it go faster.
+pub const POSITION_SPEED: f32 = 240.0;
+/// A client's square stays within this far of the middle of the window.
+pub const POSITION_BOUNDS: Vec2 = Vec2::new(600.0, 320.0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PositionUpdate {
+    pub seq: u32,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl PositionUpdate {
+    pub fn message(&self) -> String {
+        format!("{} {} {:.1} {:.1}", Self::KIND, self.seq, self.x, self.y)
+    }
+}
+
+impl NetMessage for PositionUpdate {
+    const KIND: &'static str = "Pos";
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut fields = text.split_whitespace().skip(1);
+        let seq = fields.next()?.parse().ok()?;
+        let x: f32 = fields.next()?.parse().ok()?;
+        let y: f32 = fields.next()?.parse().ok()?;
+        (x.is_finite() && y.is_finite()).then_some(PositionUpdate { seq, x, y })
+    }
+}
@@ -0,0 +1,720 @@
+//! Signed server identity, so a host on the LAN can't answer in the
+//! server's place.
+//!
+//! UDP has no connection to hijack: anyone who sees a Hello go by can send
+//! the client a Welcome (or a Reject, or a Disconnect) from the server's
+//! address, and the client has no way to tell. With an identity the server
+//! holds an Ed25519 key (`--identity-key <file>`, created on first use) and
+//! prints its fingerprint, the SHA-256 of the public key in hex. A client
+//! that pins it with `--server-key <fingerprint>` only believes replies the
+//! key signed:
+//!
+//! - every Hello and Resume carries a fresh random `nonce=<hex>`
+//! - the server's [`SignReplies`] sends its Welcome or Reject as
+//!   [`SIGNED_WITH_KEY`], a flags byte, its public key and a signature over
+//!   all that, the datagram and the nonce, so an old Welcome can't be
+//!   replayed
+//! - the client's [`VerifyServer`] checks the key against the pin and the
+//!   signature against the key, and drops the reply otherwise, with a
+//!   [`NetError::Unverified`]
+//!
+//! With `--sign-all` the server signs everything it sends to the client,
+//! as [`SIGNED`] and a signature over the datagram and the Welcome's nonce,
+//! the Welcome's flags say so, and the client drops anything unsigned for
+//! the rest of the session. Within a session a spoofer can still replay a
+//! datagram the server really sent; only the handshake is bound to a nonce
+//! of its own.
+//!
+//! The signature covers a datagram's bytes as they go on the wire, whether
+//! they're text, bincode (see `codec`) or compressed: each side's stage has
+//! a half at [`SIGN_ORDER`], outside every other stage, that adds or checks
+//! and strips the signature. The other half, at [`HANDSHAKE_ORDER`], reads
+//! the handshake as text: the Hello's nonce, and whether a datagram is a
+//! Welcome or Reject, which has to be signed with the key. The two halves
+//! see the same datagram one right after the other on one thread, and pass
+//! what they found on in a thread-local. A third stage clears it at both
+//! ends of the pipeline, so whatever a pass found can't leak into the next
+//! one when a stage in between drops the datagram. A server only signs for
+//! clients whose Hello had a nonce, so clients that don't pin a key see
+//! exactly what they always did.
+//!
+//! The server keeps the nonces of at most [`MAX_PINNED`] clients, dropping
+//! the oldest past that and a client's own once it's archived.
+
+use bevy::prelude::*;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{NetError, NetErrorSink};
+use crate::middleware::{Middleware, Pipeline};
+
+/// Where the half of each stage that signs or checks bytes goes in the
+/// `middleware::Pipeline`: outside every other stage, compression and
+/// `legacy` included, so it sees datagrams exactly as they go on the wire.
+pub const SIGN_ORDER: i32 = 30;
+/// Where the half that reads the handshake goes: below the codecs and
+/// compression, where the Hello and the Welcome are text.
+pub const HANDSHAKE_ORDER: i32 = 2;
+
+/// First byte of a datagram signed over the session's nonce: then the
+/// signature and the datagram.
+pub const SIGNED: u8 = 0x03;
+/// First byte of a signed Welcome or Reject: then a flags byte, the public
+/// key, the signature and the datagram.
+pub const SIGNED_WITH_KEY: u8 = 0x04;
+/// In the flags byte: the server signs everything for the rest of the session
+const ALL_SIGNED: u8 = 0x01;
+const KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// Nonces of recent Hellos any reply may answer; a port scan sends a burst
+const RECENT_NONCES: usize = 16;
+/// Clients the server keeps a nonce for; a flood of Hellos from spoofed
+/// addresses pushes out the oldest, whose client just says Hello again
+pub const MAX_PINNED: usize = 4096;
+
+/// What one half found out about a datagram, for the other.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mark {
+    Unsigned,
+    /// Over the session's nonce
+    Signed,
+    /// A Welcome or Reject, with the key, over a Hello's nonce
+    Handshake,
+}
+
+thread_local! {
+    /// The mark of the datagram going through the pipeline on this thread
+    static MARK: Cell<Mark> = const { Cell::new(Mark::Unsigned) };
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// SHA-256 of a public key, in hex: what `--server-key` pins.
+pub fn fingerprint(key: &VerifyingKey) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn field<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// What a signature covers: the header before it, the datagram after it,
+/// and the nonce.
+fn signed_bytes(header: &[u8], datagram: &[u8], nonce: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(header.len() + datagram.len() + 1 + nonce.len());
+    bytes.extend_from_slice(header);
+    bytes.extend_from_slice(datagram);
+    bytes.push(b'\n');
+    bytes.extend_from_slice(nonce.as_bytes());
+    bytes
+}
+
+fn split_signature(bytes: &[u8]) -> Result<(Signature, &[u8]), String> {
+    let (signature, datagram) = bytes
+        .split_at_checked(SIGNATURE_LEN)
+        .ok_or("truncated signature")?;
+    let signature = Signature::from_bytes(signature.try_into().unwrap());
+    Ok((signature, datagram))
+}
+
+fn is_handshake_reply(message: &str) -> bool {
+    matches!(
+        message.split_whitespace().next(),
+        Some("Welcome" | "Reject")
+    )
+}
+
+fn is_hello(message: &str) -> bool {
+    matches!(message.split_whitespace().next(), Some("Hello" | "Resume"))
+}
+
+pub struct ServerIdentity {
+    key: SigningKey,
+}
+
+impl ServerIdentity {
+    /// Reads the key from `path`, a hex seed, or creates a new one there.
+    /// Returns whether it was created.
+    pub fn load_or_create(path: &Path) -> io::Result<(Self, bool)> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let seed = unhex::<32>(text.trim()).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} does not hold a 64-digit hex key", path.display()),
+                    )
+                })?;
+                Ok((
+                    Self {
+                        key: SigningKey::from_bytes(&seed),
+                    },
+                    false,
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut seed = [0u8; 32];
+                getrandom::getrandom(&mut seed).map_err(io::Error::other)?;
+                write_private(path, &format!("{}\n", hex(&seed)))?;
+                Ok((
+                    Self {
+                        key: SigningKey::from_bytes(&seed),
+                    },
+                    true,
+                ))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.key.verifying_key())
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Clears the mark at both ends of the pipeline; see the module docs.
+struct ClearMark;
+
+impl Middleware for ClearMark {
+    fn name(&self) -> &'static str {
+        "clear-mark"
+    }
+
+    fn outgoing(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        MARK.set(Mark::Unsigned);
+        Some(bytes)
+    }
+
+    fn incoming(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        MARK.set(Mark::Unsigned);
+        Some(bytes)
+    }
+}
+
+fn install_clear_mark(pipeline: &Pipeline) {
+    pipeline.add(i32::MIN, ClearMark);
+    pipeline.add(i32::MAX, ClearMark);
+}
+
+struct Signing {
+    identity: ServerIdentity,
+    /// `--sign-all`
+    sign_all: bool,
+    /// Latest Hello nonce by peer, and when it came, counted in Hellos
+    nonces: Mutex<HashMap<String, (String, u64)>>,
+    hellos: AtomicU64,
+}
+
+/// The server's stage: remembers each client's nonce and signs replies.
+/// Add it with [`SignReplies::install`], which adds both halves.
+pub struct SignReplies(Arc<Signing>);
+
+/// The half of [`SignReplies`] at [`SIGN_ORDER`].
+struct SignDatagrams(Arc<Signing>);
+
+impl SignReplies {
+    pub fn new(identity: ServerIdentity, sign_all: bool) -> Self {
+        Self(Arc::new(Signing {
+            identity,
+            sign_all,
+            nonces: Mutex::new(HashMap::new()),
+            hellos: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn install(self, pipeline: &Pipeline) {
+        install_clear_mark(pipeline);
+        pipeline.add(SIGN_ORDER, SignDatagrams(self.0.clone()));
+        pipeline.add(HANDSHAKE_ORDER, self);
+    }
+}
+
+impl Middleware for SignReplies {
+    fn name(&self) -> &'static str {
+        "sign-replies"
+    }
+
+    /// Decides what [`SignDatagrams`] does with the datagram.
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let pinned = self.0.nonces.lock().unwrap().contains_key(peer);
+        let mark = match std::str::from_utf8(&bytes) {
+            _ if !pinned => Mark::Unsigned,
+            Ok(message) if is_handshake_reply(message) => Mark::Handshake,
+            _ if self.0.sign_all => Mark::Signed,
+            _ => Mark::Unsigned,
+        };
+        MARK.set(mark);
+        Some(bytes)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        if let Ok(message) = std::str::from_utf8(&bytes)
+            && is_hello(message)
+        {
+            let mut nonces = self.0.nonces.lock().unwrap();
+            match field(message, "nonce") {
+                Some(nonce) => {
+                    if nonces.len() >= MAX_PINNED && !nonces.contains_key(peer) {
+                        let oldest = nonces
+                            .iter()
+                            .min_by_key(|(_, (_, at))| *at)
+                            .map(|(peer, _)| peer.clone());
+                        if let Some(oldest) = oldest {
+                            nonces.remove(&oldest);
+                        }
+                    }
+                    let at = self.0.hellos.fetch_add(1, Ordering::Relaxed);
+                    nonces.insert(peer.to_string(), (nonce.to_string(), at));
+                }
+                // A client that stopped pinning gets plain replies again
+                None => {
+                    nonces.remove(peer);
+                }
+            }
+        }
+        Some(bytes)
+    }
+
+    fn forget(&self, peer: &str) {
+        self.0.nonces.lock().unwrap().remove(peer);
+    }
+
+    fn heap_bytes(&self) -> usize {
+        let nonces = self.0.nonces.lock().unwrap();
+        nonces.capacity() * std::mem::size_of::<(String, (String, u64))>()
+            + nonces
+                .iter()
+                .map(|(peer, (nonce, _))| peer.capacity() + nonce.capacity())
+                .sum::<usize>()
+    }
+}
+
+impl Middleware for SignDatagrams {
+    fn name(&self) -> &'static str {
+        "sign-datagrams"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let mark = MARK.replace(Mark::Unsigned);
+        if mark == Mark::Unsigned {
+            return Some(bytes);
+        }
+        let Some((nonce, _)) = self.0.nonces.lock().unwrap().get(peer).cloned() else {
+            return Some(bytes);
+        };
+        let key = &self.0.identity.key;
+        let mut header = Vec::with_capacity(2 + KEY_LEN);
+        if mark == Mark::Handshake {
+            header.push(SIGNED_WITH_KEY);
+            header.push(if self.0.sign_all { ALL_SIGNED } else { 0 });
+            header.extend_from_slice(key.verifying_key().as_bytes());
+        } else {
+            header.push(SIGNED);
+        }
+        let signature = key.sign(&signed_bytes(&header, &bytes, &nonce));
+        let mut signed = header;
+        signed.extend_from_slice(&signature.to_bytes());
+        signed.extend_from_slice(&bytes);
+        Some(signed)
+    }
+}
+
+struct Verifying {
+    /// `--server-key`, lowercase
+    pinned: String,
+    errors: NetErrorSink,
+    /// Newest last
+    nonces: Mutex<VecDeque<String>>,
+    /// The verified key and the nonce its Welcome answered, for the rest of
+    /// the session
+    session: Mutex<Option<(VerifyingKey, String)>>,
+    /// The Welcome's flags said everything is signed
+    strict: AtomicBool,
+}
+
+impl Verifying {
+    /// The datagram without its signature, or why it isn't believed.
+    fn verify(&self, bytes: &[u8]) -> Result<(Mark, Vec<u8>), String> {
+        match bytes.first() {
+            Some(&SIGNED_WITH_KEY) => {
+                let (header, rest) = bytes.split_at_checked(2 + KEY_LEN).ok_or("truncated key")?;
+                let key = VerifyingKey::from_bytes(header[2..].try_into().unwrap())
+                    .map_err(|_| "no key")?;
+                if fingerprint(&key) != self.pinned {
+                    return Err(format!(
+                        "signed by key {}, not --server-key",
+                        fingerprint(&key)
+                    ));
+                }
+                let (signature, datagram) = split_signature(rest)?;
+                let nonces = self.nonces.lock().unwrap();
+                let nonce = nonces
+                    .iter()
+                    .rev()
+                    .find(|nonce| {
+                        key.verify(&signed_bytes(header, datagram, nonce), &signature)
+                            .is_ok()
+                    })
+                    .ok_or("bad signature, or not an answer to our Hello")?;
+                self.strict
+                    .store(header[1] & ALL_SIGNED != 0, Ordering::Relaxed);
+                *self.session.lock().unwrap() = Some((key, nonce.clone()));
+                Ok((Mark::Handshake, datagram.to_vec()))
+            }
+            Some(&SIGNED) => {
+                let (header, rest) = bytes.split_at(1);
+                let (signature, datagram) = split_signature(rest)?;
+                let session = self.session.lock().unwrap();
+                let (key, nonce) = session.as_ref().ok_or("signed, but before a Welcome")?;
+                key.verify(&signed_bytes(header, datagram, nonce), &signature)
+                    .map_err(|_| "bad signature")?;
+                Ok((Mark::Signed, datagram.to_vec()))
+            }
+            _ if self.strict.load(Ordering::Relaxed) => Err("unsigned".to_string()),
+            _ => Ok((Mark::Unsigned, bytes.to_vec())),
+        }
+    }
+
+    fn refuse(&self, peer: &str, reason: String) {
+        self.errors.push(NetError::Unverified {
+            addr: peer.to_string(),
+            reason,
+        });
+    }
+}
+
+/// The client's stage: adds nonces to Hellos and drops replies the pinned
+/// key didn't sign. Add it with [`VerifyServer::install`], which adds both
+/// halves.
+pub struct VerifyServer(Arc<Verifying>);
+
+/// The half of [`VerifyServer`] at [`SIGN_ORDER`].
+struct VerifyDatagrams(Arc<Verifying>);
+
+impl VerifyServer {
+    pub fn new(pinned: &str, errors: NetErrorSink) -> Self {
+        Self(Arc::new(Verifying {
+            pinned: pinned.trim().to_ascii_lowercase(),
+            errors,
+            nonces: Mutex::new(VecDeque::new()),
+            session: Mutex::new(None),
+            strict: AtomicBool::new(false),
+        }))
+    }
+
+    pub fn install(self, pipeline: &Pipeline) {
+        install_clear_mark(pipeline);
+        pipeline.add(SIGN_ORDER, VerifyDatagrams(self.0.clone()));
+        pipeline.add(HANDSHAKE_ORDER, self);
+    }
+}
+
+impl Middleware for VerifyServer {
+    fn name(&self) -> &'static str {
+        "verify-server"
+    }
+
+    fn outgoing(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let Ok(message) = std::str::from_utf8(&bytes) else {
+            return Some(bytes);
+        };
+        if !is_hello(message) {
+            return Some(bytes);
+        }
+        let mut nonce = [0u8; 16];
+        if let Err(e) = getrandom::getrandom(&mut nonce) {
+            warn!("No randomness for a Hello nonce: {}", e);
+            return Some(bytes);
+        }
+        let nonce = hex(&nonce);
+        let mut nonces = self.0.nonces.lock().unwrap();
+        nonces.push_back(nonce.clone());
+        while nonces.len() > RECENT_NONCES {
+            nonces.pop_front();
+        }
+        Some(format!("{} nonce={}", message, nonce).into_bytes())
+    }
+
+    /// Drops a Welcome or Reject that [`VerifyDatagrams`] didn't find
+    /// signed with the key.
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        if let Ok(message) = std::str::from_utf8(&bytes)
+            && is_handshake_reply(message)
+            && MARK.get() != Mark::Handshake
+        {
+            self.0.refuse(peer, "unsigned".to_string());
+            return None;
+        }
+        Some(bytes)
+    }
+}
+
+impl Middleware for VerifyDatagrams {
+    fn name(&self) -> &'static str {
+        "verify-datagrams"
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        match self.0.verify(&bytes) {
+            Ok((mark, datagram)) => {
+                MARK.set(mark);
+                Some(datagram)
+            }
+            Err(reason) => {
+                MARK.set(Mark::Unsigned);
+                self.0.refuse(peer, reason);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: &str = "127.0.0.1:7000";
+    const CLIENT: &str = "127.0.0.1:50000";
+
+    fn identity(seed: u8) -> ServerIdentity {
+        ServerIdentity {
+            key: SigningKey::from_bytes(&[seed; 32]),
+        }
+    }
+
+    /// A server and a client pipeline that pins the server's key, after a
+    /// Hello and its Welcome.
+    fn connected(sign_all: bool) -> (Pipeline, Pipeline) {
+        let server = Pipeline::default();
+        let client = Pipeline::default();
+        let server_identity = identity(7);
+        let pinned = server_identity.fingerprint();
+        SignReplies::new(server_identity, sign_all).install(&server);
+        VerifyServer::new(&pinned, NetErrorSink::default()).install(&client);
+        #[cfg(feature = "compression")]
+        {
+            use crate::compression::{COMPRESSION_ORDER, CompressionStats, DictCompression};
+            let compression = |always| {
+                let stats = CompressionStats::default();
+                if always {
+                    DictCompression::always(1200, stats).unwrap()
+                } else {
+                    DictCompression::new(1200, stats).unwrap()
+                }
+            };
+            server.add(COMPRESSION_ORDER, compression(false));
+            client.add(COMPRESSION_ORDER, compression(true));
+        }
+
+        let hello = client.outgoing(SERVER, b"Hello version=1").unwrap();
+        let hello = server.incoming(CLIENT, hello).unwrap();
+        assert!(field(std::str::from_utf8(&hello).unwrap(), "nonce").is_some());
+        let welcome = server.outgoing(CLIENT, b"Welcome seed=2a").unwrap();
+        assert_eq!(welcome[0], SIGNED_WITH_KEY);
+        assert_eq!(
+            client.incoming(SERVER, welcome).as_deref(),
+            Some(&b"Welcome seed=2a"[..])
+        );
+        (server, client)
+    }
+
+    #[test]
+    fn a_signature_can_take_a_reply_past_the_size_limit() {
+        const MAX: usize = 1024;
+        let (server, _) = connected(true);
+        // Noise, so compression (if it's in) can only add its marker
+        let mut rng = crate::shared_rng::SharedRng::new(7);
+        let reply: Vec<u8> = (0..MAX - 1).map(|_| rng.next_u64() as u8).collect();
+        let errors = NetErrorSink::default();
+        assert!(errors.within_max_size(CLIENT, &reply, MAX));
+        let signed = server.outgoing(CLIENT, &reply).unwrap();
+        assert_eq!(signed[0], SIGNED);
+        assert!(!errors.within_max_size(CLIENT, &signed, MAX));
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let (server, client) = connected(true);
+        let signed = server.outgoing(CLIENT, b"Chat hello there").unwrap();
+        assert_eq!(signed[0], SIGNED);
+        assert_eq!(
+            client.incoming(SERVER, signed).as_deref(),
+            Some(&b"Chat hello there"[..])
+        );
+    }
+
+    #[test]
+    fn binary_round_trips() {
+        let (server, client) = connected(true);
+        // An encoded `message::Message` starts with a zero; not UTF-8 either
+        let bincode = [0u8, 3, 0, 0, 0, 0xff, 0xfe];
+        let signed = server.outgoing(CLIENT, &bincode).unwrap();
+        assert_eq!(signed[0], SIGNED);
+        assert_eq!(
+            client.incoming(SERVER, signed).as_deref(),
+            Some(&bincode[..])
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn signs_the_compressed_bytes() {
+        use crate::compression::Marker;
+
+        let (server, client) = connected(true);
+        let signed = server.outgoing(CLIENT, b"Heartbeat rtt_ms=12.5").unwrap();
+        let (_, framed) = signed.split_at(1 + SIGNATURE_LEN);
+        assert!(Marker::of(framed).is_some());
+        assert_eq!(
+            client.incoming(SERVER, signed).as_deref(),
+            Some(&b"Heartbeat rtt_ms=12.5"[..])
+        );
+    }
+
+    /// Records the mark each outgoing pass starts with, and drops what it's
+    /// told to.
+    struct Probe(Arc<Mutex<Vec<Mark>>>, &'static str);
+
+    impl Middleware for Probe {
+        fn name(&self) -> &'static str {
+            "probe"
+        }
+
+        fn outgoing(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+            self.0.lock().unwrap().push(MARK.get());
+            (!bytes.starts_with(self.1.as_bytes())).then_some(bytes)
+        }
+    }
+
+    #[test]
+    fn a_dropped_datagram_leaves_no_mark() {
+        let (server, _) = connected(false);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        // Between the halves, as compression or the codecs are
+        server.add(HANDSHAKE_ORDER + 1, Probe(seen.clone(), "Welcome"));
+        server.add(HANDSHAKE_ORDER - 1, Probe(seen.clone(), "-"));
+        assert_eq!(server.outgoing(CLIENT, b"Welcome seed=2a"), None);
+        assert_eq!(MARK.get(), Mark::Handshake);
+        server.outgoing(CLIENT, b"Chat hi").unwrap();
+        let seen = seen.lock().unwrap();
+        // The Welcome's mark was cleared before the next pass reached anything
+        assert_eq!(
+            seen[..],
+            [
+                Mark::Unsigned,
+                Mark::Handshake,
+                Mark::Unsigned,
+                Mark::Unsigned
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_at_most_max_pinned_nonces() {
+        let server = Pipeline::default();
+        SignReplies::new(identity(7), false).install(&server);
+        let peer = |number: usize| format!("10.{}.{}.1:5000", number / 256, number % 256);
+        for number in 0..=MAX_PINNED {
+            server.incoming(&peer(number), b"Hello version=1 nonce=00".to_vec());
+        }
+        // The oldest went to make room
+        let first = server.outgoing(&peer(0), b"Welcome seed=2a").unwrap();
+        assert_eq!(first, b"Welcome seed=2a");
+        let last = server
+            .outgoing(&peer(MAX_PINNED), b"Welcome seed=2a")
+            .unwrap();
+        assert_eq!(last[0], SIGNED_WITH_KEY);
+    }
+
+    #[test]
+    fn forgets_an_archived_peer() {
+        let (server, _) = connected(true);
+        let kept = server.heap_bytes();
+        server.forget(CLIENT);
+        assert!(server.heap_bytes() < kept);
+        // Unsigned from now on, as for a client that never pinned the key
+        let sent = server.outgoing(CLIENT, b"Chat hi").unwrap();
+        assert_ne!(sent[0], SIGNED);
+    }
+
+    #[test]
+    fn drops_a_tampered_datagram() {
+        let (server, client) = connected(true);
+        let mut signed = server.outgoing(CLIENT, &[0u8, 1, 2, 3]).unwrap();
+        *signed.last_mut().unwrap() ^= 1;
+        assert_eq!(client.incoming(SERVER, signed), None);
+    }
+
+    #[test]
+    fn drops_a_tampered_welcome() {
+        let (server, client) = connected(false);
+        let hello = client.outgoing(SERVER, b"Hello version=1").unwrap();
+        server.incoming(CLIENT, hello);
+        let mut welcome = server.outgoing(CLIENT, b"Welcome seed=2a").unwrap();
+        // Claiming the server signs everything is covered too
+        welcome[1] ^= ALL_SIGNED;
+        assert_eq!(client.incoming(SERVER, welcome), None);
+    }
+
+    #[test]
+    fn drops_replies_it_cant_believe() {
+        let (_, client) = connected(false);
+        // A spoofer without the key
+        assert_eq!(client.incoming(SERVER, b"Welcome seed=00".to_vec()), None);
+        let spoofer = Pipeline::default();
+        SignReplies::new(identity(9), false).install(&spoofer);
+        spoofer.incoming(CLIENT, b"Hello version=1 nonce=00".to_vec());
+        let welcome = spoofer.outgoing(CLIENT, b"Welcome seed=00").unwrap();
+        assert_eq!(client.incoming(SERVER, welcome), None);
+        // Without --sign-all the rest of the session may be unsigned
+        assert_eq!(
+            client.incoming(SERVER, b"Chat hi".to_vec()).as_deref(),
+            Some(&b"Chat hi"[..])
+        );
+    }
+
+    #[test]
+    fn drops_unsigned_datagrams_once_everything_is_signed() {
+        let (_, client) = connected(true);
+        assert_eq!(client.incoming(SERVER, b"Chat hi".to_vec()), None);
+    }
+}
@@ -0,0 +1,68 @@
+//! Plain-text lines from before the current protocol.
+//!
+//! The first clients, and anyone poking a server with netcat, send a bare
+//! line such as `Ping`, often as `ping` or with the newline `echo` adds,
+//! and never a Hello. Today's datagrams carry a message's exact text, or
+//! start with a marker byte when a stage framed them (see `compression`).
+//!
+//! [`LegacyText`] sits outside every other `middleware` stage, at
+//! [`LEGACY_ORDER`], so it sees datagrams as they came off the wire. One
+//! without a marker whose trimmed text is one of its legacy lines, in any
+//! case, is rewritten to the line itself, and from there on it is parsed
+//! and handled like any other message. Everything else passes through
+//! untouched. Nothing outgoing changes: compression only frames for peers
+//! that framed something first, so a reply to a legacy peer goes out as
+//! plain text it can read.
+//!
+//! ```ignore
+//! pipeline.add(LEGACY_ORDER, LegacyText::new(&["Ping"]));
+//! ```
+
+use bevy::prelude::*;
+
+use crate::middleware::Middleware;
+
+/// Where [`LegacyText`] goes in the `middleware::Pipeline`: above
+/// compression, the outermost stage that frames datagrams.
+pub const LEGACY_ORDER: i32 = 20;
+
+pub struct LegacyText {
+    lines: &'static [&'static str],
+}
+
+impl LegacyText {
+    /// `lines` in the form the app expects them, e.g. `KNOCK KNOCK`
+    pub fn new(lines: &'static [&'static str]) -> Self {
+        Self { lines }
+    }
+
+    fn translate(&self, bytes: &[u8]) -> Option<&'static str> {
+        // A framed datagram starts with a marker; text doesn't
+        if bytes.first().is_none_or(|first| first.is_ascii_control()) {
+            return None;
+        }
+        let text = std::str::from_utf8(bytes).ok()?.trim();
+        let line = self
+            .lines
+            .iter()
+            .find(|line| line.eq_ignore_ascii_case(text))?;
+        // Already what the app expects
+        (line.as_bytes() != bytes).then_some(*line)
+    }
+}
+
+impl Middleware for LegacyText {
+    fn name(&self) -> &'static str {
+        "legacy-text"
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        match self.translate(&bytes) {
+            Some(line) => {
+                debug!("Legacy line from {} read as {}", peer, line);
+                Some(line.as_bytes().to_vec())
+            }
+            None => Some(bytes),
+        }
+    }
+}
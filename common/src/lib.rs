@@ -0,0 +1,56 @@
+//! Code shared by the server, client and knock_knock binaries.
+//!
+//! With `--no-default-features` only the Bevy ECS/app core is pulled in, so
+//! the networking pieces build for headless servers and wasm without winit or
+//! wgpu. `gui` adds the UI widgets, `native-net` the socket-based helpers,
+//! `storage` the persistent history and accounts, `tickets` the signed
+//! connect tickets, `compression` the dictionary compression stage,
+//! `identity` the signed server replies, `offload` UDP segmentation offload.
+//! `native-net` also has the TCP framing and the server query client.
+//! `message` is the typed, bincode-encoded gameplay messages every build has.
+
+pub mod bandwidth;
+pub mod codec;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod disconnect;
+pub mod effects;
+pub mod error;
+#[cfg(feature = "native-net")]
+pub mod framing;
+pub mod handlers;
+#[cfg(feature = "identity")]
+pub mod identity;
+pub mod legacy;
+pub mod log_sink;
+pub mod message;
+pub mod middleware;
+#[cfg(feature = "offload")]
+pub mod offload;
+pub mod packet_log;
+pub mod persist;
+pub mod players;
+pub mod presence;
+pub mod protocol;
+pub mod recording;
+pub mod reliable;
+pub mod replicate;
+#[cfg(feature = "native-net")]
+pub mod self_check;
+pub mod sequence_diagram;
+#[cfg(feature = "native-net")]
+pub mod server_query;
+pub mod session;
+pub mod shared_rng;
+#[cfg(feature = "native-net")]
+pub mod smoke_test;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "storage")]
+pub mod storage;
+#[cfg(feature = "tickets")]
+pub mod ticket;
+#[cfg(feature = "gui")]
+pub mod ui;
+pub mod validator;
+pub mod wire;
@@ -0,0 +1,147 @@
+//! Where log lines go.
+//!
+//! The on-screen logs are lines like `[Info]: 127.0.0.1:50211 is now idle`.
+//! Each one pushed to [`LogSinks`] becomes a [`LogEntry`] and is handed to
+//! every registered [`LogSink`], in the order they were added: the log
+//! panel ([`RecentLines`]), the terminal ([`StdoutSink`]), a file, an
+//! observer connection, or anything else that implements the trait.
+//!
+//! Like the middleware [`Pipeline`](crate::middleware::Pipeline), the sinks
+//! are a resource that can be added to at any time:
+//!
+//! ```ignore
+//! app.world.resource::<LogSinks>().add(MySink);
+//! ```
+//!
+//! Sinks run on whichever thread pushed the line, usually a system in the
+//! middle of a frame, so one that does I/O should hand the work to a thread
+//! of its own.
+
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct LogEntry {
+    /// Unix time, in seconds
+    pub at: f64,
+    /// `Info` for `[Info]: ...`; empty for a line without a tag, like a
+    /// console echo
+    pub tag: String,
+    pub text: String,
+}
+
+impl LogEntry {
+    pub fn parse(line: &str, at: f64) -> Self {
+        let tagged = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once("]: "));
+        let (tag, text) = tagged.unwrap_or(("", line));
+        Self {
+            at,
+            tag: tag.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    /// The line as it was pushed
+    pub fn line(&self) -> String {
+        if self.tag.is_empty() {
+            self.text.clone()
+        } else {
+            format!("[{}]: {}", self.tag, self.text)
+        }
+    }
+}
+
+pub trait LogSink: Send + Sync + 'static {
+    /// Shown in logs
+    fn name(&self) -> &'static str;
+
+    fn write(&self, entry: &LogEntry);
+}
+
+/// Cheap to clone; every clone pushes to the same sinks.
+#[derive(Resource, Clone, Default)]
+pub struct LogSinks(Arc<RwLock<Vec<Box<dyn LogSink>>>>);
+
+impl LogSinks {
+    pub fn add(&self, sink: impl LogSink) {
+        info!("Log sink {} added", sink.name());
+        self.0.write().unwrap().push(Box::new(sink));
+    }
+
+    pub fn push(&self, line: String) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let entry = LogEntry::parse(&line, at);
+        for sink in self.0.read().unwrap().iter() {
+            sink.write(&entry);
+        }
+    }
+}
+
+/// Prints every line to stdout, for when there's no window to show them.
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        println!("{}", entry.line());
+    }
+}
+
+/// The newest lines, for a log panel to show. Clones share the lines, so
+/// one goes to [`LogSinks::add`] and one to whatever draws them.
+#[derive(Clone)]
+pub struct RecentLines {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+    /// Bumped by every change, so a panel knows when to redraw
+    version: Arc<AtomicU64>,
+}
+
+impl RecentLines {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: default(),
+            capacity,
+            version: default(),
+        }
+    }
+
+    /// Oldest first
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lines.lock().unwrap().clear();
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+}
+
+impl LogSink for RecentLines {
+    fn name(&self) -> &'static str {
+        "panel"
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        let mut lines = self.lines.lock().unwrap();
+        lines.push_back(entry.line());
+        while lines.len() > self.capacity {
+            lines.pop_front();
+        }
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,429 @@
+//! Typed gameplay messages, serialized with serde and bincode.
+//!
+//! [`Message`] is every message the two demos play with: the Ping/Pong
+//! data channel (Ping, Pong, Chat and Pos) and the knock knock jokes and
+//! quiz. [`Message::encode`] writes one with bincode, in network byte order
+//! with fixed-size integers, and [`Message::decode`] reads it back. Anything
+//! malformed is a [`DecodeError`], never a panic, so a receiver logs the
+//! datagram and carries on.
+//!
+//! An encoded message starts with its variant's index as a big-endian u32,
+//! so its first byte is 0, which no text message starts with. That is how
+//! [`Payload::read`] tells the two apart on a socket that carries both: the
+//! handshake, `REL` and `REPL` stay text, as `protocol` describes them, and
+//! knock_knock sends its jokes and quiz as bincode next to them. The
+//! Ping/Pong pair sends text unless the data channel has the `bincode`
+//! codec (`--codec data=bincode`, see `codec`); either way its receivers
+//! handle the typed message.
+//!
+//! The index is a variant's place in the enum, so new messages go at the
+//! end. The tests hold every variant's bytes, which must not change.
+//!
+//! ```
+//! # use common::message::Message;
+//! let ping = Message::Ping { seq: 7 };
+//! assert_eq!(ping.encode(), [0, 0, 0, 0, 0, 0, 0, 7]);
+//! assert_eq!(Message::decode(&ping.encode()).unwrap(), ping);
+//! assert_eq!(ping.to_string(), "Ping 7");
+//! assert_eq!(Message::parse("Ping 7"), Some(ping));
+//! assert!(Message::decode(b"\0\0\0\x63").is_err());
+//! ```
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use thiserror::Error;
+
+use crate::handlers::{NetMessage, PositionUpdate};
+use crate::session::message_kind;
+
+/// The most [`Message::decode`] reads, so a length field in a malformed
+/// datagram can't ask for more than a datagram can hold
+const MAX_ENCODED_LEN: u64 = 65_536;
+
+/// How a quiz answer was judged
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Verdict {
+    Correct,
+    Wrong,
+    /// The question had already closed, or was never asked
+    Late,
+}
+
+impl Verdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Correct => "CORRECT",
+            Verdict::Wrong => "WRONG",
+            Verdict::Late => "LATE",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    /// Numbered by the client from 1, so a Pong says which Ping it
+    /// answers; 0 is a Ping from a client that doesn't number them
+    Ping {
+        seq: u32,
+    },
+    /// The `seq` of the Ping it answers, or 0 for one sent unasked
+    Pong {
+        seq: u32,
+    },
+    /// A chat line; relayed by the server as `<addr>: <text>`
+    Chat {
+        text: String,
+    },
+    Pos(PositionUpdate),
+    /// `KNOCK KNOCK`, which starts a joke
+    Knock,
+    /// `WHO IS THERE?`, the server's answer to a knock
+    WhoIsThere,
+    /// The client's answer to that, the name the joke is about
+    Name {
+        name: String,
+    },
+    /// `<name> WHO?`, the server's answer to a name
+    NameWho {
+        name: String,
+    },
+    /// A quiz question, to answer within `secs`
+    Question {
+        id: u32,
+        secs: f32,
+        text: String,
+    },
+    Answer {
+        id: u32,
+        text: String,
+    },
+    Verdict {
+        id: u32,
+        verdict: Verdict,
+    },
+    /// The question's deadline passed; `answer` was right
+    Closed {
+        id: u32,
+        answer: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("malformed message: {0}")]
+    Malformed(#[from] bincode::Error),
+    /// Text carries no NaN or infinity either; see `wire::WireValue`
+    #[error("a position that isn't a number")]
+    NotFinite,
+}
+
+/// Big-endian, unlike bincode's default, as `protocol` asks of binary
+/// fields; varints would make the bytes depend on the values.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .with_limit(MAX_ENCODED_LEN)
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        options()
+            .serialize(self)
+            .expect("every message is within the limit")
+    }
+
+    /// Refuses trailing bytes, an unknown variant, text that isn't UTF-8
+    /// and positions that aren't finite.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let message: Message = options().deserialize(bytes)?;
+        if let Message::Pos(update) = &message
+            && !(update.x.is_finite() && update.y.is_finite())
+        {
+            return Err(DecodeError::NotFinite);
+        }
+        Ok(message)
+    }
+
+    /// Reads the text form of Ping, Pong, Chat and Pos, as in
+    /// `protocol::MESSAGES`; `None` for anything else. The knock knock
+    /// messages only travel encoded: a name has no kind word to tell it by.
+    pub fn parse(text: &str) -> Option<Self> {
+        // `Ping` or `Ping <seq>`, and nothing after
+        let numbered = || -> Option<u32> {
+            let mut fields = text.split_whitespace().skip(1);
+            let seq = fields.next().map_or(Some(0), |seq| seq.parse().ok())?;
+            fields.next().is_none().then_some(seq)
+        };
+        match message_kind(text) {
+            "Ping" => Some(Message::Ping { seq: numbered()? }),
+            "Pong" => Some(Message::Pong { seq: numbered()? }),
+            "Chat" => Some(Message::Chat {
+                text: text.trim_start().strip_prefix("Chat")?.trim().to_string(),
+            }),
+            PositionUpdate::KIND => PositionUpdate::parse(text).map(Message::Pos),
+            _ => None,
+        }
+    }
+}
+
+/// The text form: what [`Message::parse`] reads, and what logs show
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Ping { seq: 0 } => write!(f, "Ping"),
+            Message::Ping { seq } => write!(f, "Ping {}", seq),
+            Message::Pong { seq: 0 } => write!(f, "Pong"),
+            Message::Pong { seq } => write!(f, "Pong {}", seq),
+            Message::Chat { text } if text.is_empty() => write!(f, "Chat"),
+            Message::Chat { text } => write!(f, "Chat {}", text),
+            Message::Pos(update) => write!(f, "{}", update.message()),
+            Message::Knock => write!(f, "KNOCK KNOCK"),
+            Message::WhoIsThere => write!(f, "WHO IS THERE?"),
+            Message::Name { name } => write!(f, "{}", name),
+            Message::NameWho { name } => write!(f, "{} WHO?", name),
+            Message::Question { id, secs, text } => {
+                write!(f, "QUESTION {} {:.1} {}", id, secs, text)
+            }
+            Message::Answer { id, text } => write!(f, "ANSWER {} {}", id, text),
+            Message::Verdict { id, verdict } => write!(f, "VERDICT {} {}", id, verdict.as_str()),
+            Message::Closed { id, answer } => write!(f, "CLOSED {} {}", id, answer),
+        }
+    }
+}
+
+/// What a datagram carries.
+#[derive(Debug, PartialEq)]
+pub enum Payload<'a> {
+    /// A [`Message`], encoded
+    Message(Message),
+    /// Anything else: text, with what isn't UTF-8 replaced
+    Text(Cow<'a, str>),
+}
+
+impl<'a> Payload<'a> {
+    /// An error only for a datagram that starts like an encoded message
+    /// but isn't one.
+    pub fn read(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        if is_encoded(bytes) {
+            Message::decode(bytes).map(Payload::Message)
+        } else {
+            Ok(Payload::Text(String::from_utf8_lossy(bytes)))
+        }
+    }
+}
+
+/// Whether `bytes` is meant as an encoded [`Message`] rather than text.
+pub fn is_encoded(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every variant as it goes on the wire, byte for byte. If one of these
+    /// changes, the wire format did: old peers will misread the new bytes,
+    /// so add a variant at the end rather than edit the fixture to match.
+    fn fixtures() -> Vec<(Message, Vec<u8>)> {
+        vec![
+            (Message::Ping { seq: 7 }, vec![0, 0, 0, 0, 0, 0, 0, 7]),
+            (
+                Message::Pong { seq: 0x0102_0304 },
+                vec![0, 0, 0, 1, 1, 2, 3, 4],
+            ),
+            (
+                Message::Chat {
+                    text: "hi".to_string(),
+                },
+                vec![0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2, b'h', b'i'],
+            ),
+            (
+                Message::Pos(PositionUpdate {
+                    seq: 3,
+                    x: 1.5,
+                    y: -2.0,
+                }),
+                vec![0, 0, 0, 3, 0, 0, 0, 3, 0x3f, 0xc0, 0, 0, 0xc0, 0, 0, 0],
+            ),
+            (Message::Knock, vec![0, 0, 0, 4]),
+            (Message::WhoIsThere, vec![0, 0, 0, 5]),
+            (
+                Message::Name {
+                    name: "Boo".to_string(),
+                },
+                vec![0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 3, b'B', b'o', b'o'],
+            ),
+            (
+                Message::NameWho {
+                    name: "Boo".to_string(),
+                },
+                vec![0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 3, b'B', b'o', b'o'],
+            ),
+            (
+                Message::Question {
+                    id: 1,
+                    secs: 8.0,
+                    text: "?".to_string(),
+                },
+                vec![
+                    0, 0, 0, 8, 0, 0, 0, 1, 0x41, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, b'?',
+                ],
+            ),
+            (
+                Message::Answer {
+                    id: 1,
+                    text: "42".to_string(),
+                },
+                vec![0, 0, 0, 9, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, b'4', b'2'],
+            ),
+            (
+                Message::Verdict {
+                    id: 1,
+                    verdict: Verdict::Late,
+                },
+                vec![0, 0, 0, 10, 0, 0, 0, 1, 0, 0, 0, 2],
+            ),
+            (
+                Message::Closed {
+                    id: 2,
+                    answer: "8".to_string(),
+                },
+                vec![0, 0, 0, 11, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, b'8'],
+            ),
+        ]
+    }
+
+    #[test]
+    fn every_variant_matches_its_fixture() {
+        for (message, bytes) in fixtures() {
+            assert_eq!(message.encode(), bytes, "{:?}", message);
+            assert_eq!(Message::decode(&bytes).unwrap(), message);
+            assert_eq!(Payload::read(&bytes).unwrap(), Payload::Message(message));
+        }
+    }
+
+    #[test]
+    fn every_variant_has_a_fixture() {
+        // The last variant's index, plus one
+        let count = fixtures().len();
+        let next = [0, 0, 0, count as u8];
+        assert!(Message::decode(&next).is_err());
+        let mut tags: Vec<u8> = fixtures().iter().map(|(_, bytes)| bytes[3]).collect();
+        tags.dedup();
+        assert_eq!(tags, (0..count as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn refuses_malformed_datagrams() {
+        for bytes in [
+            &b""[..],
+            b"\0",
+            b"\0\0\0",
+            // No such variant
+            b"\0\0\0\xff",
+            // A Ping cut short, and one with a byte too many
+            b"\0\0\0\0\0\0\0",
+            b"\0\0\0\0\0\0\0\x07\0",
+            // A Chat that claims more text than there is
+            b"\0\0\0\x02\0\0\0\0\0\0\0\x05hi",
+            // One that claims more than a datagram holds
+            b"\0\0\0\x02\xff\xff\xff\xff\xff\xff\xff\xffhi",
+            // Text that isn't UTF-8
+            b"\0\0\0\x02\0\0\0\0\0\0\0\x01\xff",
+            // A verdict that doesn't exist
+            b"\0\0\0\x0a\0\0\0\x01\0\0\0\x03",
+        ] {
+            assert!(Message::decode(bytes).is_err(), "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn refuses_positions_that_are_not_numbers() {
+        for x in [f32::NAN, f32::INFINITY] {
+            let bytes = Message::Pos(PositionUpdate { seq: 1, x, y: 0.0 }).encode();
+            assert!(matches!(
+                Message::decode(&bytes),
+                Err(DecodeError::NotFinite)
+            ));
+        }
+    }
+
+    #[test]
+    fn text_is_never_read_as_encoded() {
+        for text in ["Ping", "Hello version=2", "REL 1 Login", " KNOCK KNOCK", ""] {
+            assert_eq!(
+                Payload::read(text.as_bytes()).unwrap(),
+                Payload::Text(text.into())
+            );
+        }
+        assert!(Payload::read(b"\0\0\0\xff").is_err());
+    }
+
+    #[test]
+    fn text_forms_round_trip() {
+        for message in [
+            Message::Ping { seq: 0 },
+            Message::Ping { seq: 12 },
+            Message::Pong { seq: 0 },
+            Message::Pong { seq: u32::MAX },
+            Message::Chat {
+                text: "hello there".to_string(),
+            },
+            Message::Chat {
+                text: String::new(),
+            },
+            Message::Pos(PositionUpdate {
+                seq: 4,
+                x: 10.5,
+                y: -3.0,
+            }),
+        ] {
+            assert_eq!(Message::parse(&message.to_string()), Some(message));
+        }
+    }
+
+    #[test]
+    fn parses_the_text_older_peers_send() {
+        assert_eq!(Message::parse("Ping"), Some(Message::Ping { seq: 0 }));
+        assert_eq!(Message::parse("Pong"), Some(Message::Pong { seq: 0 }));
+        assert_eq!(
+            Message::parse("Chat  hi "),
+            Some(Message::Chat {
+                text: "hi".to_string()
+            })
+        );
+        for text in [
+            "Ping x",
+            "Ping 1 2",
+            "Ping -1",
+            "Pingx",
+            "Chatter",
+            "Pos 1 nan 2",
+            "KNOCK KNOCK",
+            "Hello",
+            "",
+        ] {
+            assert_eq!(Message::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn knock_knock_text_is_what_the_jokes_always_read() {
+        let name = "Lettuce".to_string();
+        assert_eq!(Message::Knock.to_string(), "KNOCK KNOCK");
+        assert_eq!(Message::WhoIsThere.to_string(), "WHO IS THERE?");
+        assert_eq!(Message::Name { name: name.clone() }.to_string(), "Lettuce");
+        assert_eq!(Message::NameWho { name }.to_string(), "Lettuce WHO?");
+        assert_eq!(
+            Message::Verdict {
+                id: 3,
+                verdict: Verdict::Correct
+            }
+            .to_string(),
+            "VERDICT 3 CORRECT"
+        );
+    }
+}
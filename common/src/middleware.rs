@@ -0,0 +1,142 @@
+//! Ordered hooks on the send and receive paths.
+//!
+//! A [`Middleware`] sees every datagram on its way out and on its way in,
+//! and can inspect it, rewrite it or drop it. The binaries put one
+//! [`Pipeline`] between their sockets and the rest of the app: outgoing
+//! bytes pass through it before the size check and the socket, incoming
+//! bytes right after the receive thread has them (and, on the server, after
+//! the allow/deny check).
+//!
+//! Stages run in ascending `order` on the way out and descending on the way
+//! in, so a layer that transforms the bytes (compression, encryption) wraps
+//! everything after it symmetrically. Stages with the same order run in the
+//! order they were added.
+//!
+//! The pipeline is a resource, and it is also shared with the receive
+//! threads, so stages can be added at any time:
+//!
+//! ```ignore
+//! app.world.resource::<Pipeline>().add(10, MyCompression);
+//! ```
+
+use bevy::prelude::*;
+use std::sync::{Arc, RwLock};
+
+/// One stage of the pipeline. Both hooks pass bytes through unchanged unless
+/// overridden; return `None` to drop the datagram.
+pub trait Middleware: Send + Sync + 'static {
+    /// Shown in logs
+    fn name(&self) -> &'static str;
+
+    fn outgoing(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        Some(bytes)
+    }
+
+    fn incoming(&self, _peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        Some(bytes)
+    }
+
+    /// Drops whatever the stage keeps for `peer`; the server calls it when
+    /// it archives the peer.
+    fn forget(&self, _peer: &str) {}
+
+    /// Roughly what the stage keeps per peer on the heap.
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+struct Stage {
+    order: i32,
+    middleware: Box<dyn Middleware>,
+}
+
+#[derive(Resource, Clone, Default)]
+pub struct Pipeline(Arc<RwLock<Vec<Stage>>>);
+
+impl Pipeline {
+    pub fn add(&self, order: i32, middleware: impl Middleware) {
+        info!("Middleware {} added at order {}", middleware.name(), order);
+        let mut stages = self.0.write().unwrap();
+        stages.push(Stage {
+            order,
+            middleware: Box::new(middleware),
+        });
+        // Stable, so equal orders keep insertion order
+        stages.sort_by_key(|stage| stage.order);
+    }
+
+    /// Stage names, in outgoing order.
+    pub fn names(&self) -> Vec<&'static str> {
+        let stages = self.0.read().unwrap();
+        stages.iter().map(|stage| stage.middleware.name()).collect()
+    }
+
+    /// `None` if a stage dropped the datagram.
+    pub fn outgoing(&self, peer: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+        let stages = self.0.read().unwrap();
+        stages.iter().try_fold(bytes.to_vec(), |bytes, stage| {
+            stage.middleware.outgoing(peer, bytes)
+        })
+    }
+
+    /// `None` if a stage dropped the datagram.
+    pub fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let stages = self.0.read().unwrap();
+        stages
+            .iter()
+            .rev()
+            .try_fold(bytes, |bytes, stage| stage.middleware.incoming(peer, bytes))
+    }
+
+    /// Has every stage drop what it keeps for `peer`.
+    pub fn forget(&self, peer: &str) {
+        let stages = self.0.read().unwrap();
+        for stage in stages.iter() {
+            stage.middleware.forget(peer);
+        }
+    }
+
+    /// What the stages keep per peer, together.
+    pub fn heap_bytes(&self) -> usize {
+        let stages = self.0.read().unwrap();
+        stages
+            .iter()
+            .map(|stage| stage.middleware.heap_bytes())
+            .sum()
+    }
+}
+
+/// Where [`PacketTrace`] goes: below everything, so it logs the bytes as
+/// they are on the wire
+pub const TRACE_ORDER: i32 = 0;
+
+/// Logs every datagram in both directions. `--trace-packets`
+/// on the server and client adds it at [`TRACE_ORDER`].
+pub struct PacketTrace;
+
+impl Middleware for PacketTrace {
+    fn name(&self) -> &'static str {
+        "packet-trace"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        info!(
+            "-> {} {} bytes: {}",
+            peer,
+            bytes.len(),
+            String::from_utf8_lossy(&bytes)
+        );
+        Some(bytes)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        info!(
+            "<- {} {} bytes: {}",
+            peer,
+            bytes.len(),
+            String::from_utf8_lossy(&bytes)
+        );
+        Some(bytes)
+    }
+}
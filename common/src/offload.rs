@@ -0,0 +1,135 @@
+//! UDP segmentation offload, for bursts of datagrams to one peer.
+//!
+//! With generic segmentation offload (GSO), a run of equal-sized datagrams
+//! to one address goes to the kernel as one buffer in one `sendmsg`, and
+//! the kernel (or the NIC) cuts it into ordinary datagrams. Generic receive
+//! offload (GRO) is the reverse: one read returns several datagrams from
+//! one sender back to back, and [`UdpOffload::recv`] gives their stride so
+//! the caller can cut them apart. Nothing changes on the wire, so the peer
+//! can't tell. Linux has both, GSO since 4.18 and GRO since 5.0; elsewhere
+//! every call carries one datagram, as without this module.
+//!
+//! `quinn-udp` makes the system calls. If the kernel refuses a GSO send
+//! (EIO, from a NIC that can't checksum segments), GSO stays off on that
+//! socket from then on, and [`UdpOffload::send_batch`] sends that run one
+//! datagram at a time. `quinn-udp` also sets don't-fragment on the socket
+//! where it can, so a datagram over the path MTU fails to send instead of
+//! going out in fragments; see `self_check`'s payload-size warning.
+
+use quinn_udp::{RecvMeta, Transmit, UdpSockRef, UdpSocketState};
+use std::io::{self, IoSliceMut};
+use std::net::{SocketAddr, UdpSocket};
+
+/// Most payload bytes a single send can carry, the UDP limit
+const MAX_GSO_BYTES: usize = 65_507;
+
+/// Enough for any read GRO can return
+pub const GRO_BUFFER: usize = 65_535;
+
+pub struct UdpOffload {
+    state: UdpSocketState,
+}
+
+impl UdpOffload {
+    /// Turns on whatever offload the system has for `socket`.
+    pub fn new(socket: &UdpSocket) -> io::Result<Self> {
+        Ok(Self {
+            state: UdpSocketState::new(UdpSockRef::from(socket))?,
+        })
+    }
+
+    /// Datagrams one send can carry; 1 without GSO, or after it failed
+    pub fn gso_segments(&self) -> usize {
+        self.state.max_gso_segments()
+    }
+
+    /// Datagrams one read can return; 1 without GRO
+    pub fn gro_segments(&self) -> usize {
+        self.state.gro_segments()
+    }
+
+    /// `GSO up to 64 datagrams a send, GRO up to 64 a read`, or `no GSO`
+    /// and `no GRO`.
+    pub fn describe(&self) -> String {
+        let gso = match self.gso_segments() {
+            1 => "no GSO".to_string(),
+            segments => format!("GSO up to {} datagrams a send", segments),
+        };
+        let gro = match self.gro_segments() {
+            1 => "no GRO".to_string(),
+            segments => format!("GRO up to {} a read", segments),
+        };
+        format!("{}, {}", gso, gro)
+    }
+
+    /// Sends `datagrams` to `addr` in order. Each run of equal sizes, with
+    /// a shorter one to end it, goes in one send where GSO allows.
+    pub fn send_batch(
+        &self,
+        socket: &UdpSocket,
+        addr: SocketAddr,
+        datagrams: &[Vec<u8>],
+    ) -> io::Result<()> {
+        let mut rest = datagrams;
+        while let Some(first) = rest.first() {
+            let size = first.len();
+            let limit = (MAX_GSO_BYTES / size.max(1))
+                .min(self.gso_segments())
+                .min(rest.len())
+                .max(1);
+            let mut run = rest[..limit]
+                .iter()
+                .take_while(|datagram| datagram.len() == size)
+                .count();
+            // The kernel lets the last segment be shorter than the rest
+            if run < limit && rest[run].len() < size {
+                run += 1;
+            }
+            let (batch, remaining) = rest.split_at(run);
+            rest = remaining;
+            if batch.len() > 1 && size > 0 && self.send_segments(socket, addr, batch).is_ok() {
+                continue;
+            }
+            for datagram in batch {
+                socket.send_to(datagram, addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_segments(
+        &self,
+        socket: &UdpSocket,
+        addr: SocketAddr,
+        batch: &[Vec<u8>],
+    ) -> io::Result<()> {
+        let contents = batch.concat();
+        let transmit = Transmit {
+            destination: addr,
+            ecn: None,
+            contents: &contents,
+            segment_size: Some(batch[0].len()),
+            src_ip: None,
+        };
+        self.state.try_send(UdpSockRef::from(socket), &transmit)
+    }
+
+    /// Reads one datagram, or with GRO several from one sender, into `buf`,
+    /// which should hold [`GRO_BUFFER`] bytes. Returns the bytes read, the
+    /// sender and the stride: every datagram is that long but the last,
+    /// which may be shorter.
+    pub fn recv(
+        &self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, usize)> {
+        let mut meta = [RecvMeta::default()];
+        self.state.recv(
+            UdpSockRef::from(socket),
+            &mut [IoSliceMut::new(buf)],
+            &mut meta,
+        )?;
+        let [meta] = meta;
+        Ok((meta.len, meta.addr, meta.stride))
+    }
+}
@@ -0,0 +1,205 @@
+//! Packet logs: every datagram a binary sends or receives, one line each,
+//! for lining the two sides of a session up with `logdiff`.
+//!
+//! `--packet-log <path>` on the server or client adds [`PacketLog`] to the
+//! pipeline at [`PACKET_LOG_ORDER`]: after `--trace-packets` and before compression, so
+//! the log has the plain text either way. A file looks like this, with wall
+//! clock times so two machines' logs can be compared:
+//!
+//! ```text
+//! # packets side=client port=50211
+//! 1767225600.125310 1 tx 127.0.0.1:12345 Hello version=2
+//! 1767225600.127902 2 rx 127.0.0.1:12345 Welcome token=3f2a
+//! ```
+//!
+//! The second column numbers the lines of one log. The protocol carries no
+//! sequence numbers, so the other side can't know them; `logdiff` pairs a
+//! sent datagram with the received one of the same text closest in time
+//! instead. `port` is the local port, which is how the server's log (with
+//! every client in it) is narrowed to the client being compared.
+//!
+//! A datagram refused after the pipeline (too large, or held by the
+//! client's chaos mode) is still logged as sent.
+
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::middleware::Middleware;
+
+/// Above the packet trace and the validator, below codecs and compression
+pub const PACKET_LOG_ORDER: i32 = 5;
+
+const HEADER: &str = "# packets ";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Server,
+    Client,
+}
+
+impl Side {
+    pub fn label(self) -> &'static str {
+        match self {
+            Side::Server => "server",
+            Side::Client => "client",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Sent => "tx",
+            Direction::Received => "rx",
+        }
+    }
+}
+
+struct Writer {
+    file: LineWriter<File>,
+    seq: u64,
+}
+
+/// Writes the log from both hooks; passes every datagram on unchanged.
+pub struct PacketLog {
+    writer: Mutex<Writer>,
+}
+
+impl PacketLog {
+    /// Truncates `path` and writes the header.
+    pub fn create(path: &Path, side: Side, port: u16) -> io::Result<Self> {
+        let mut file = LineWriter::new(File::create(path)?);
+        writeln!(file, "{}side={} port={}", HEADER, side.label(), port)?;
+        Ok(Self {
+            writer: Mutex::new(Writer { file, seq: 0 }),
+        })
+    }
+
+    fn write(&self, direction: Direction, peer: &str, bytes: &[u8]) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        // One datagram per line, whatever it holds
+        let text = String::from_utf8_lossy(bytes).replace(['\n', '\r'], " ");
+        let mut writer = self.writer.lock().unwrap();
+        writer.seq += 1;
+        let seq = writer.seq;
+        if let Err(e) = writeln!(
+            writer.file,
+            "{:.6} {} {} {} {}",
+            at,
+            seq,
+            direction.label(),
+            peer,
+            text
+        ) {
+            warn!("Could not write the packet log: {}", e);
+        }
+    }
+}
+
+impl Middleware for PacketLog {
+    fn name(&self) -> &'static str {
+        "packet-log"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.write(Direction::Sent, peer, &bytes);
+        Some(bytes)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.write(Direction::Received, peer, &bytes);
+        Some(bytes)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggedPacket {
+    /// Unix time, in seconds
+    pub at: f64,
+    pub seq: u64,
+    pub direction: Direction,
+    pub peer: String,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct PacketLogFile {
+    pub side: Side,
+    pub port: u16,
+    /// In the order they were written
+    pub packets: Vec<LoggedPacket>,
+}
+
+impl PacketLogFile {
+    /// Reads a packet log; lines that don't parse are skipped. Fails if the
+    /// header is missing, since the side can't be told then.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut header = None;
+        let mut packets = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if let Some(rest) = line.strip_prefix(HEADER) {
+                header = parse_header(rest);
+                continue;
+            }
+            let mut words = line.splitn(5, ' ');
+            let (Some(at), Some(seq), Some(direction), Some(peer)) =
+                (words.next(), words.next(), words.next(), words.next())
+            else {
+                continue;
+            };
+            let (Ok(at), Ok(seq)) = (at.parse::<f64>(), seq.parse::<u64>()) else {
+                continue;
+            };
+            let direction = match direction {
+                "tx" => Direction::Sent,
+                "rx" => Direction::Received,
+                _ => continue,
+            };
+            packets.push(LoggedPacket {
+                at,
+                seq,
+                direction,
+                peer: peer.to_string(),
+                text: words.next().unwrap_or("").to_string(),
+            });
+        }
+        let Some((side, port)) = header else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a packet log (no header)", path.display()),
+            ));
+        };
+        Ok(Self {
+            side,
+            port,
+            packets,
+        })
+    }
+}
+
+fn parse_header(rest: &str) -> Option<(Side, u16)> {
+    let mut side = None;
+    let mut port = None;
+    for field in rest.split_whitespace() {
+        match field.split_once('=') {
+            Some(("side", "server")) => side = Some(Side::Server),
+            Some(("side", "client")) => side = Some(Side::Client),
+            Some(("port", value)) => port = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((side?, port?))
+}
@@ -0,0 +1,150 @@
+//! Server side: the replicated world on disk, so a stateful server picks up
+//! where it left off after a restart.
+//!
+//! With a [`WorldSnapshots`] resource inserted, `app.persist_resource::<R>()`
+//! keeps the latest full copy of a [`Replicated`] resource and writes every
+//! persisted resource to one file, at most once per interval and only when
+//! something changed, plus once more on exit. A snapshot is one line per
+//! resource, exactly what a newcomer is sent:
+//!
+//! ```text
+//! REPL scoreboard * 127.0.0.1:50122=3 127.0.0.1:50140=1
+//! ```
+//!
+//! The file is written as `<path>.tmp` and renamed over the old one, so a
+//! crash mid-write leaves the previous snapshot rather than half of one. A
+//! server started from [`WorldSnapshots::resume`] applies each resource's
+//! line as it registers it, before the first frame, so its first clients
+//! are sent the restored state as if it had never stopped.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::replicate::{Replicated, apply_message, full_copy, message_name};
+
+#[derive(Resource)]
+pub struct WorldSnapshots {
+    path: PathBuf,
+    timer: Timer,
+    /// Each persisted resource's latest full copy, by name
+    lines: BTreeMap<&'static str, String>,
+    /// Something changed since the last write
+    dirty: bool,
+    /// Read by [`Self::resume`], until each resource takes its own line
+    loaded: BTreeMap<String, String>,
+}
+
+impl WorldSnapshots {
+    pub fn new(path: impl Into<PathBuf>, interval_secs: f32) -> Self {
+        Self {
+            path: path.into(),
+            timer: Timer::from_seconds(interval_secs, TimerMode::Repeating),
+            lines: BTreeMap::new(),
+            dirty: false,
+            loaded: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the last snapshot for resources to start from. A missing file
+    /// is a first run and resumes nothing.
+    pub fn resume(mut self) -> io::Result<Self> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self),
+            Err(e) => return Err(e),
+        };
+        for line in text.lines() {
+            if let Some(name) = message_name(line) {
+                self.loaded.insert(name.to_string(), line.to_string());
+            }
+        }
+        Ok(self)
+    }
+
+    /// Resources the snapshot read by [`Self::resume`] has, by name
+    pub fn resumable(&self) -> impl Iterator<Item = &str> {
+        self.loaded.keys().map(String::as_str)
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        let mut text = self.lines.values().cloned().collect::<Vec<_>>().join("\n");
+        text.push('\n');
+        let mut staging = self.path.clone().into_os_string();
+        staging.push(".tmp");
+        fs::write(&staging, text)?;
+        fs::rename(&staging, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+pub trait PersistResourceExt {
+    /// Adds `R` to the world snapshot, restoring it first if the snapshot
+    /// was resumed. Insert [`WorldSnapshots`] first; `R` is usually also
+    /// registered with `replicate_resource`.
+    fn persist_resource<R: Replicated>(&mut self) -> &mut Self;
+}
+
+impl PersistResourceExt for App {
+    fn persist_resource<R: Replicated>(&mut self) -> &mut Self {
+        self.init_resource::<R>();
+        let (line, first) = {
+            let mut snapshots = self
+                .world
+                .get_resource_mut::<WorldSnapshots>()
+                .expect("insert WorldSnapshots before persist_resource");
+            (snapshots.loaded.remove(R::NAME), snapshots.lines.is_empty())
+        };
+        let snapshot = {
+            let mut resource = self.world.resource_mut::<R>();
+            if let Some(line) = line {
+                apply_message(&mut *resource, &line);
+            }
+            full_copy(&*resource)
+        };
+        self.world
+            .resource_mut::<WorldSnapshots>()
+            .lines
+            .insert(R::NAME, snapshot);
+        self.add_systems(PostUpdate, record_snapshot::<R>);
+        if first {
+            self.add_systems(Last, write_snapshots);
+        }
+        self
+    }
+}
+
+fn record_snapshot<R: Replicated>(resource: Res<R>, mut snapshots: ResMut<WorldSnapshots>) {
+    if !resource.is_changed() {
+        return;
+    }
+    let line = full_copy(&*resource);
+    if snapshots.lines.get(R::NAME) != Some(&line) {
+        snapshots.lines.insert(R::NAME, line);
+        snapshots.dirty = true;
+    }
+}
+
+fn write_snapshots(
+    time: Res<Time>,
+    mut exits: EventReader<AppExit>,
+    mut snapshots: ResMut<WorldSnapshots>,
+) {
+    let due = snapshots.timer.tick(time.delta()).just_finished();
+    let exiting = exits.read().count() > 0;
+    if !snapshots.dirty || !(due || exiting) {
+        return;
+    }
+    // Left dirty on failure, so the next interval tries again
+    if let Err(e) = snapshots.write() {
+        warn!(
+            "Could not write the world snapshot {}: {}",
+            snapshots.path.display(),
+            e
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Resources the Ping/Pong server replicates to its clients.
+
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::replicate::Replicated;
+
+/// Clients the server currently counts as connected (active or idle).
+#[derive(Resource, Default, Debug)]
+pub struct PlayerCount {
+    /// Players, not counting spectators
+    pub connected: usize,
+    pub spectators: usize,
+}
+
+impl Replicated for PlayerCount {
+    const NAME: &'static str = "players";
+
+    fn to_fields(&self) -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("connected".to_string(), self.connected.to_string()),
+            ("spectators".to_string(), self.spectators.to_string()),
+        ])
+    }
+
+    fn apply_field(&mut self, key: &str, value: Option<&str>) {
+        let count = value.and_then(|value| value.parse().ok()).unwrap_or(0);
+        match key {
+            "connected" => self.connected = count,
+            "spectators" => self.spectators = count,
+            _ => {}
+        }
+    }
+}
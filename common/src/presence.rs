@@ -0,0 +1,88 @@
+//! Rich presence hook for platform integrations.
+//!
+//! Steam, Discord and friends all want to hear the same things: are we
+//! connecting, who are we connected to, which room are we in. Apps send
+//! [`PresenceUpdate`] events when those change; [`PresencePlugin`] hands each
+//! one to a [`PresenceBackend`]. The SDK glue for a platform is a type
+//! implementing that trait, added with
+//! `PresencePlugin::<SteamPresence>::default()`; nothing else in the app needs
+//! to know it exists. [`LoggingPresence`] is the default and only logs.
+
+use bevy::prelude::*;
+use std::marker::PhantomData;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionPresence {
+    Connecting { server: String },
+    Connected { server: String },
+    Disconnected,
+}
+
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub enum PresenceUpdate {
+    Connection(ConnectionPresence),
+    /// `None` when we leave a room without joining another
+    Room(Option<String>),
+}
+
+/// Receives presence changes. Both methods default to doing nothing, so a
+/// backend only implements what its platform can show.
+pub trait PresenceBackend: Default + Send + Sync + 'static {
+    fn connection_changed(&mut self, _state: &ConnectionPresence) {}
+
+    fn room_changed(&mut self, _room: Option<&str>) {}
+}
+
+/// Writes presence changes to the Bevy log.
+#[derive(Default)]
+pub struct LoggingPresence;
+
+impl PresenceBackend for LoggingPresence {
+    fn connection_changed(&mut self, state: &ConnectionPresence) {
+        match state {
+            ConnectionPresence::Connecting { server } => {
+                info!("Presence: connecting to {}", server)
+            }
+            ConnectionPresence::Connected { server } => info!("Presence: connected to {}", server),
+            ConnectionPresence::Disconnected => info!("Presence: disconnected"),
+        }
+    }
+
+    fn room_changed(&mut self, room: Option<&str>) {
+        match room {
+            Some(room) => info!("Presence: in room {}", room),
+            None => info!("Presence: not in a room"),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct Presence<B: PresenceBackend>(B);
+
+pub struct PresencePlugin<B: PresenceBackend = LoggingPresence>(PhantomData<B>);
+
+impl<B: PresenceBackend> Default for PresencePlugin<B> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<B: PresenceBackend> Plugin for PresencePlugin<B> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PresenceUpdate>()
+            .init_resource::<Presence<B>>()
+            .add_systems(PostUpdate, dispatch_presence::<B>);
+    }
+}
+
+fn dispatch_presence<B: PresenceBackend>(
+    mut updates: EventReader<PresenceUpdate>,
+    mut presence: ResMut<Presence<B>>,
+) {
+    for update in updates.read() {
+        match update {
+            PresenceUpdate::Connection(state) => presence.0.connection_changed(state),
+            PresenceUpdate::Room(room) => presence.0.room_changed(room.as_deref()),
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! Machine-readable description of the wire protocol.
+//!
+//! [`MESSAGES`] and [`CHANNELS`] list every message the server and client
+//! exchange and the sockets they travel on. `--dump-protocol` on either
+//! binary prints them as JSON via [`to_json`], so tools and non-Rust clients
+//! can be generated from this one table. A new message kind should be added
+//! here alongside the code that sends it, unless it's declared with
+//! [`define_protocol!`](crate::define_protocol), whose `SPECS` are chained
+//! on in [`messages`]. [`SUBSETS`] are the parts a build can leave out; the
+//! messages of one whose feature is off aren't listed, the others carry a
+//! `subset` in the JSON. [`REAL_TIME`] gives the messages that are dropped
+//! rather than sent late a time-to-live, `ttl_ms` in the JSON.
+//!
+//! There is no binary header to get the byte order of wrong: a message is
+//! UTF-8 text and every number in it is written in decimal (or hex, for
+//! tokens and tickets), so it decodes the same on little- and big-endian
+//! hosts. Anything that adds binary fields to the wire has to pick a byte
+//! order explicitly (network order, `to_be_bytes`, as `storage` does for its
+//! keys) and say so in the header description from [`to_json`]. The framed
+//! datagrams of `compression` only add a single marker byte, and TCP (the
+//! `tcp` channel) puts a big-endian length in front of each message; see
+//! `framing`. A channel with the `bincode` codec sends the messages of
+//! `message::Message` in network order too, and the header's `binary` says
+//! how.
+
+use std::time::Duration;
+
+use crate::handlers::{KeepAlive, ServerQuery};
+use crate::session::message_kind;
+
+/// Sent in every Hello. The server rejects a Hello carrying a version
+/// outside [`MIN_SUPPORTED_VERSION`]..=[`PROTOCOL_VERSION`].
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest version the server still welcomes. Such a client goes without
+/// the [`FEATURES`] added since, and its Welcome names them with
+/// `deprecated=`, so it can ask its user to update.
+pub const MIN_SUPPORTED_VERSION: u32 = 1;
+
+pub struct FeatureSpec {
+    /// As named in a Welcome's `deprecated=`
+    pub name: &'static str,
+    /// The protocol version that added it
+    pub since: u32,
+    pub description: &'static str,
+}
+
+pub const FEATURES: &[FeatureSpec] = &[
+    FeatureSpec {
+        name: "effects",
+        since: 2,
+        description: "cosmetic events (Fx)",
+    },
+    FeatureSpec {
+        name: "config_push",
+        since: 2,
+        description: "live client settings (ConfigUpdate)",
+    },
+    FeatureSpec {
+        name: "announcements",
+        since: 2,
+        description: "the message of the day and server announcements (Announce)",
+    },
+];
+
+/// The features a client speaking `version` goes without.
+pub fn missing_features(version: u32) -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|feature| feature.since > version)
+        .map(|feature| feature.name)
+        .collect()
+}
+
+/// What a user is told a feature is, e.g. in the client's update banner;
+/// a name this build doesn't know is shown as it is.
+pub fn describe_feature(name: &str) -> &str {
+    FEATURES
+        .iter()
+        .find(|feature| feature.name == name)
+        .map_or(name, |feature| feature.description)
+}
+
+/// A part of the protocol a build leaves out when it's compiled without the
+/// cargo feature of the same name, messages, handlers and all. Each side
+/// names the subsets it has in its greeting, and a session goes without
+/// whichever subset either side lacks.
+pub struct SubsetSpec {
+    /// As named in a Hello's `subsets=` and a Welcome's `without=`
+    pub name: &'static str,
+    /// The messages that go with it
+    pub kinds: &'static [&'static str],
+    pub description: &'static str,
+}
+
+pub const SUBSETS: &[SubsetSpec] = &[SubsetSpec {
+    name: "chat",
+    kinds: &["Chat", "History"],
+    description: "chat lines, their moderation and the history sent on join",
+}];
+
+/// The ` subsets=<subset>,...` a build with only the `built` subsets adds to
+/// its Hello or Resume; empty when it has them all, as every build before
+/// subsets had.
+pub fn subsets_field(built: &[&str]) -> String {
+    if SUBSETS.iter().all(|subset| built.contains(&subset.name)) {
+        return String::new();
+    }
+    format!(" subsets={}", built.join(","))
+}
+
+/// The subsets a session goes without: those missing from `built`, this
+/// build's, or from the `subsets=` of the peer's Hello or Resume.
+pub fn missing_subsets(built: &[&str], greeting: &str) -> Vec<&'static str> {
+    let theirs = greeting
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("subsets="));
+    SUBSETS
+        .iter()
+        .map(|subset| subset.name)
+        .filter(|name| {
+            !built.contains(name)
+                || theirs.is_some_and(|theirs| !theirs.split(',').any(|theirs| theirs == *name))
+        })
+        .collect()
+}
+
+/// The `version=<u32>` field of a Hello or Reject, if it has one.
+pub fn parse_version(message: &str) -> Option<u32> {
+    message
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("version="))
+        .and_then(|version| version.parse().ok())
+}
+
+/// Whether a Hello or Resume asks for a spectator connection
+/// (`role=spectator`).
+pub fn is_spectator(message: &str) -> bool {
+    message
+        .split_whitespace()
+        .any(|field| field == "role=spectator")
+}
+
+/// The `token=` field of a Welcome, Resume or Handoff, if it has one.
+pub fn parse_token(message: &str) -> Option<&str> {
+    message
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("token="))
+}
+
+/// The `seed=<hex>` field of a Welcome, the seed of the session's
+/// `shared_rng::SharedRng`.
+pub fn parse_seed(message: &str) -> Option<u64> {
+    message
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("seed="))
+        .and_then(|seed| u64::from_str_radix(seed, 16).ok())
+}
+
+/// The `codecs=<channel>:<codec>,...` field of a Hello, Resume or Reject,
+/// if it has one; see `codec`.
+pub fn parse_codecs(message: &str) -> Option<&str> {
+    message
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("codecs="))
+}
+
+/// The `ticket=<ticket>` field of a Hello or Resume (the connect ticket from
+/// a matchmaker), or of a Reject (why the ticket was refused).
+pub fn parse_ticket(message: &str) -> Option<&str> {
+    message
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("ticket="))
+}
+
+/// The payload of a Login on the reliable channel, as the client sends it:
+/// `Login user=<name> password=<len>:<password>`. The password goes last
+/// with its length in bytes in front, so it may hold spaces, `=` or
+/// anything else; usernames are checked by the server and have neither.
+pub fn login_payload(user: &str, password: &str) -> String {
+    format!(
+        "Login user={} password={}:{}",
+        user,
+        password.len(),
+        password
+    )
+}
+
+/// The user and password of a [`login_payload`], or `None` when the
+/// password's length doesn't match what follows it.
+pub fn parse_login(payload: &str) -> Option<(&str, &str)> {
+    let (user, password) = payload
+        .strip_prefix("Login user=")?
+        .split_once(" password=")?;
+    let (len, password) = password.split_once(':')?;
+    let len: usize = len.parse().ok()?;
+    (password.len() == len && !user.contains(' ')).then_some((user, password))
+}
+
+pub struct ChannelSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub reliable: bool,
+    pub ordered: bool,
+}
+
+pub struct MessageSpec {
+    /// The first word of the message, which is how receivers tell them apart
+    pub kind: &'static str,
+    pub direction: &'static str,
+    pub channel: &'static str,
+    /// Full text layout; `<name>` is a field, `[...]` is optional
+    pub format: &'static str,
+    pub description: &'static str,
+}
+
+pub const CHANNELS: &[ChannelSpec] = &[
+    ChannelSpec {
+        name: "control",
+        description: "The server's main port (--port): handshake, heartbeats, replication",
+        reliable: false,
+        ordered: false,
+    },
+    ChannelSpec {
+        name: "data",
+        description: "The server's --data-port when given, otherwise the control port: gameplay",
+        reliable: false,
+        ordered: false,
+    },
+    ChannelSpec {
+        name: "reliable",
+        description: "REL/REL_ACK framing over either port: resent until acked, delivered in order",
+        reliable: true,
+        ordered: true,
+    },
+    ChannelSpec {
+        name: "events",
+        description: "Cosmetic events over the data channel, at most once: never resent, and duplicates or events older than the newest seen are dropped",
+        reliable: false,
+        ordered: false,
+    },
+    ChannelSpec {
+        name: "tcp",
+        description: "TCP on the control port when the server has --tcp: every message, each after its length as a big-endian u16",
+        reliable: true,
+        ordered: true,
+    },
+];
+
+/// A message only worth delivering while it's fresh, because the next one
+/// overtakes it. Senders drop one that waited in a queue (pacing, a stall)
+/// for longer than `ttl_ms` instead of sending it late.
+pub struct TtlSpec {
+    pub kind: &'static str,
+    pub ttl_ms: u32,
+}
+
+pub const REAL_TIME: &[TtlSpec] = &[
+    TtlSpec {
+        kind: "Pos",
+        ttl_ms: 250,
+    },
+    TtlSpec {
+        kind: "Fx",
+        ttl_ms: 500,
+    },
+];
+
+/// How long `message` stays worth sending; `None` when it has to arrive
+/// however late.
+pub fn ttl(message: &str) -> Option<Duration> {
+    let kind = message_kind(message);
+    REAL_TIME
+        .iter()
+        .find(|spec| spec.kind == kind)
+        .map(|spec| Duration::from_millis(spec.ttl_ms.into()))
+}
+
+/// [`MESSAGES`], then the messages declared with
+/// [`define_protocol!`](crate::define_protocol).
+pub fn messages() -> impl Iterator<Item = &'static MessageSpec> {
+    MESSAGES
+        .iter()
+        .chain(KeepAlive::SPECS)
+        .chain(ServerQuery::SPECS)
+}
+
+/// The channel called `name`, if there is one.
+pub fn channel(name: &str) -> Option<&'static ChannelSpec> {
+    CHANNELS.iter().find(|channel| channel.name == name)
+}
+
+/// Whether [`CHANNELS`] has one called `name`; checked when a
+/// [`define_protocol!`](crate::define_protocol) message is compiled.
+pub const fn is_channel(name: &str) -> bool {
+    let mut i = 0;
+    while i < CHANNELS.len() {
+        if same(CHANNELS[i].name, name) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether `direction` is one a [`MessageSpec`] may have.
+pub const fn is_direction(direction: &str) -> bool {
+    same(direction, "client->server")
+        || same(direction, "server->client")
+        || same(direction, "either")
+}
+
+/// `==`, which isn't usable in a const
+const fn same(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+pub const MESSAGES: &[MessageSpec] = &[
+    MessageSpec {
+        kind: "Hello",
+        direction: "client->server",
+        channel: "control",
+        format: "Hello[ version=<u32>][ role=spectator][ ticket=<ticket>][ nonce=<hex>][ codecs=<channel>:<codec>,...][ subsets=<subset>,...]",
+        description: "Starts the handshake; a Hello without a version is accepted as the current one. A spectator gets all replication but its inputs are ignored. A server started with --ticket-key needs a connect ticket from the matchmaker. nonce is sent by a client pinning the server key, to be signed into the reply. codecs names every channel the client doesn't send as text, which has to match the server's. subsets lists the protocol subsets the client was built with, when it lacks some",
+    },
+    MessageSpec {
+        kind: "Resume",
+        direction: "client->server",
+        channel: "control",
+        format: "Resume token=<hex>|<ticket> version=<u32>[ role=spectator][ ticket=<ticket>][ nonce=<hex>][ codecs=<channel>:<codec>,...][ subsets=<subset>,...]",
+        description: "Sent instead of a Hello after a lost connection, to continue the session the token came with, or after a Handoff with its token; an unknown or expired token is treated as a Hello",
+    },
+    MessageSpec {
+        kind: "Welcome",
+        direction: "server->client",
+        channel: "control",
+        format: "Welcome[ data_port=<u16>] seed=<hex>[ deprecated=<feature>,...][ without=<subset>,...][ token=<hex>][ resumed]",
+        description: "Completes the handshake; data_port is present when gameplay uses a separate port, seed is the server's SharedRng seed for randomness every peer draws the same, deprecated lists the features an older but supported Hello version goes without, without the protocol subsets the client or the server was built without, token resumes this session later, resumed answers a Resume that continued the session. A server with --identity-key answers a nonce with it framed as identity::SIGNED_WITH_KEY: its public key and an Ed25519 signature over the datagram and the nonce, and a flag for --sign-all, after which every datagram comes as identity::SIGNED",
+    },
+    MessageSpec {
+        kind: "Reject",
+        direction: "server->client",
+        channel: "control",
+        format: "Reject version=<u32>[ ticket=missing|malformed|bad_signature|expired|reused][ codecs=<channel>:<codec>,...|text]",
+        description: "Handshake refused because the Hello's version is newer than the server's, which is given, or older than it still supports, or because of its connect ticket when ticket= is present, or because its codecs differ from the server's, given in codecs=. Signed like a Welcome",
+    },
+    MessageSpec {
+        kind: "Disconnect",
+        direction: "either",
+        channel: "control",
+        format: "Disconnect reason=timeout|kicked|server_shutdown|version_mismatch|auth_failed|user_quit|handed_off",
+        description: "The sender is ending the connection on purpose, and says why",
+    },
+    MessageSpec {
+        kind: "Handoff",
+        direction: "server->client",
+        channel: "control",
+        format: "Handoff addr=<host>:<port> token=<ticket>",
+        description: "Ends the session and moves the client to another server instance, e.g. to rebalance a fleet or restart for an update: the client connects to addr at once and presents token in a Resume. A server started with the same --handoff-key continues the session there as resumed, once, for 30 seconds",
+    },
+    MessageSpec {
+        kind: "Ping",
+        direction: "client->server",
+        channel: "data",
+        format: "Ping[ <u32 seq>]",
+        description: "Gameplay message; the RTT is measured to the matching Pong. seq numbers the client's Pings from 1, so the Pong can say which it answers; a Ping without one is seq 0",
+    },
+    MessageSpec {
+        kind: "Pong",
+        direction: "server->client",
+        channel: "data",
+        format: "Pong[ <u32 seq>]",
+        description: "Gameplay reply, with the seq of the Ping it answers; an unnumbered Ping, or none, gets a bare Pong",
+    },
+    #[cfg(feature = "chat")]
+    MessageSpec {
+        kind: "Chat",
+        direction: "either",
+        channel: "data",
+        format: "Chat <text> (to the server), Chat <addr>: <text> (relayed)",
+        description: "A chat line; the server filters banned words, enforces mutes and rate limits, and answers Chat server: <notice> when it drops one",
+    },
+    MessageSpec {
+        kind: "Fx",
+        direction: "either",
+        channel: "events",
+        format: "Fx <u32 seq> <name>[ <detail>] (to the server), Fx <u32 seq> <origin addr> <name>[ <detail>] (fanned out)",
+        description: "A cosmetic event such as flash; the server fans it out to every connected client, the sender included, numbering the copies it sends. Each side drops a seq at or below the newest it has seen from that sender",
+    },
+    MessageSpec {
+        kind: "Pos",
+        direction: "client->server",
+        channel: "data",
+        format: "Pos <u32 seq> <f32 x> <f32 y>",
+        description: "Where the client's sprite is, in world units, sent --position-hz times a second while connected; the server moves that client's sprite toward it, and drops an update whose seq is at or below the newest it has",
+    },
+    #[cfg(feature = "chat")]
+    MessageSpec {
+        kind: "History",
+        direction: "server->client",
+        channel: "data",
+        format: "History <addr>: <text>",
+        description: "One earlier chat line, oldest first, sent to a client as it joins",
+    },
+    MessageSpec {
+        kind: "REPL",
+        direction: "server->client",
+        channel: "control",
+        format: "REPL <name> [@<u32 seq>] [*] <key>=<value>... -<key>...",
+        description: "Replicated resource change; seq counts each resource's messages to this client, * means a full copy, values escape % space newline as %25 %20 %0A",
+    },
+    MessageSpec {
+        kind: "ReplNack",
+        direction: "client->server",
+        channel: "control",
+        format: "ReplNack <name> [<u32 last seq>]",
+        description: "A gap in a resource's REPL seq; the server answers with a full copy of that resource alone",
+    },
+    MessageSpec {
+        kind: "REL",
+        direction: "either",
+        channel: "reliable",
+        format: "REL <u32 seq> <payload>",
+        description: "Reliable payload; resent until acked. Carries knock_knock's joke chunks and the Ping/Pong Login, LoginOk and LoginFailed",
+    },
+    MessageSpec {
+        kind: "REL_ACK",
+        direction: "either",
+        channel: "reliable",
+        format: "REL_ACK <u32 seq>",
+        description: "Acknowledges one REL",
+    },
+    MessageSpec {
+        kind: "ConfigUpdate",
+        direction: "server->client",
+        channel: "reliable",
+        format: "ConfigUpdate <key>=<f32>...",
+        description: "Client settings changed at runtime, carried as a REL payload: net_tick_hz, heartbeat_secs, chat_cooldown_secs. Sent after every Welcome with everything changed so far, and to every client on each change; a client logs and skips keys it doesn't know",
+    },
+    MessageSpec {
+        kind: "Announce",
+        direction: "server->client",
+        channel: "reliable",
+        format: "Announce motd|notice <text>",
+        description: "Text for the client log, carried as a REL payload: the server's message of the day, one line each, after every Welcome, and announcements on the server's schedule or from its /announce",
+    },
+    MessageSpec {
+        kind: "SNAP",
+        direction: "server->client",
+        channel: "reliable",
+        format: "SNAP <name> <index> <total> <essential> <records separated by newline>",
+        description: "One chunk of a snapshot, carried as a REL payload",
+    },
+];
+
+/// Escapes `value` as a JSON string, quotes included.
+pub fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The whole protocol as pretty-printed JSON. `max_message_size` is the
+/// sender's configured limit.
+pub fn to_json(max_message_size: usize) -> String {
+    let channels: Vec<String> = CHANNELS
+        .iter()
+        .map(|channel| {
+            format!(
+                "    {{\"name\": {}, \"reliable\": {}, \"ordered\": {}, \"description\": {}}}",
+                json_string(channel.name),
+                channel.reliable,
+                channel.ordered,
+                json_string(channel.description)
+            )
+        })
+        .collect();
+    let messages: Vec<String> = messages()
+        .map(|message| {
+            let ttl = REAL_TIME
+                .iter()
+                .find(|spec| spec.kind == message.kind)
+                .map_or(String::new(), |spec| format!(", \"ttl_ms\": {}", spec.ttl_ms));
+            let subset = SUBSETS
+                .iter()
+                .find(|subset| subset.kinds.contains(&message.kind))
+                .map_or(String::new(), |subset| {
+                    format!(", \"subset\": {}", json_string(subset.name))
+                });
+            format!(
+                "    {{\"kind\": {}, \"direction\": {}, \"channel\": {}, \"format\": {}, \"description\": {}{}{}}}",
+                json_string(message.kind),
+                json_string(message.direction),
+                json_string(message.channel),
+                json_string(message.format),
+                json_string(message.description),
+                ttl,
+                subset
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\n  \"version\": {},\n  \"protocol_version\": {},\n  \"min_protocol_version\": {},\n  \"header\": {{\"transport\": \"udp\", \"encoding\": \"utf-8\", \"byte_order\": \"none, numbers are decimal text\", \"framing\": \"one message per datagram\", \"kind\": \"first space-separated word\", \"binary\": \"with codec bincode: Ping, Pong, Chat and Pos as bincode, a big-endian u32 variant index (so a 0 first byte) then the fields as big-endian fixed-size numbers, strings after a big-endian u64 length\", \"max_message_size\": {}}},\n  \"channels\": [\n{}\n  ],\n  \"messages\": [\n{}\n  ]\n}}",
+        json_string(env!("CARGO_PKG_VERSION")),
+        PROTOCOL_VERSION,
+        MIN_SUPPORTED_VERSION,
+        max_message_size,
+        channels.join(",\n"),
+        messages.join(",\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_passwords_keep_spaces() {
+        for password in [
+            "",
+            "hunter2",
+            "correct horse battery staple",
+            "a=b c=d",
+            "pässwörd ",
+        ] {
+            let payload = login_payload("alice", password);
+            assert_eq!(parse_login(&payload), Some(("alice", password)));
+        }
+    }
+
+    #[test]
+    fn login_lengths_have_to_match() {
+        assert_eq!(parse_login("Login user=alice password=hunter2"), None);
+        assert_eq!(parse_login("Login user=alice password=3:hunter2"), None);
+        assert_eq!(parse_login("Login user=alice password=9:hunter2"), None);
+        assert_eq!(parse_login("Login user=al ice password=1:x"), None);
+        assert_eq!(parse_login("Login password=1:x"), None);
+    }
+
+    #[test]
+    fn the_current_version_misses_nothing() {
+        assert!(missing_features(PROTOCOL_VERSION).is_empty());
+    }
+
+    #[test]
+    fn an_older_version_misses_what_came_after_it() {
+        assert_eq!(
+            missing_features(MIN_SUPPORTED_VERSION),
+            ["effects", "config_push", "announcements"]
+        );
+        for version in MIN_SUPPORTED_VERSION..=PROTOCOL_VERSION {
+            for feature in FEATURES {
+                let missing = missing_features(version).contains(&feature.name);
+                assert_eq!(
+                    missing,
+                    feature.since > version,
+                    "{} at {}",
+                    feature.name,
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn features_fit_the_supported_versions_and_the_welcome() {
+        for (index, feature) in FEATURES.iter().enumerate() {
+            // A feature of the oldest supported version is never missing
+            assert!(
+                (MIN_SUPPORTED_VERSION + 1..=PROTOCOL_VERSION).contains(&feature.since),
+                "{}",
+                feature.name
+            );
+            // Joined with commas into one whitespace-split field
+            assert!(!feature.name.contains([',', ' ']), "{}", feature.name);
+            assert!(
+                FEATURES[..index]
+                    .iter()
+                    .all(|other| other.name != feature.name)
+            );
+        }
+    }
+
+    #[test]
+    fn features_are_described_by_name() {
+        assert_eq!(describe_feature("effects"), "cosmetic events (Fx)");
+        assert_eq!(describe_feature("teleport"), "teleport");
+    }
+
+    #[test]
+    fn versions_parse_from_hellos_and_rejects() {
+        assert_eq!(parse_version("Hello version=1 role=spectator"), Some(1));
+        assert_eq!(parse_version("Reject version=2 codecs=text"), Some(2));
+        assert_eq!(parse_version("Hello"), None);
+        assert_eq!(parse_version("Hello version=two"), None);
+    }
+
+    #[test]
+    fn a_welcome_names_what_an_old_client_goes_without() {
+        use crate::handlers::{NetMessage, Welcome};
+
+        let missing = missing_features(MIN_SUPPORTED_VERSION).join(",");
+        let text = format!("Welcome seed=2a deprecated={} token=00ff", missing);
+        let welcome = Welcome::parse(&text).unwrap();
+        assert_eq!(
+            welcome.deprecated,
+            ["effects", "config_push", "announcements"]
+        );
+        assert!(
+            Welcome::parse("Welcome seed=2a token=00ff")
+                .unwrap()
+                .deprecated
+                .is_empty()
+        );
+    }
+}
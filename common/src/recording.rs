@@ -0,0 +1,127 @@
+//! Session recordings: what one client sent during a session and every
+//! replicated state change the server sent it, in a replay file.
+//!
+//! The server writes one file per session with `--record-sessions`, and the
+//! client's `--play-replay` mode plays it back. A file is plain text, one
+//! entry per line, with times in seconds since the session started:
+//!
+//! ```text
+//! # replay peer=127.0.0.1:50211
+//! 0.000 in Hello v=3
+//! 0.016 state REPL players * connected=1 spectators=0
+//! 2.412 in Ping
+//! ```
+//!
+//! State entries are replication messages (see `replicate`). Applying them
+//! in order to a fresh resource gives back exactly what the client saw at
+//! any point, however often playback seeks.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "# replay peer=";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Track {
+    /// A message the client sent
+    Input,
+    /// A replication message the server sent the client
+    State,
+}
+
+impl Track {
+    fn label(self) -> &'static str {
+        match self {
+            Track::Input => "in",
+            Track::State => "state",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub at: f32,
+    pub track: Track,
+    pub text: String,
+}
+
+/// A replay file being written; every entry is on disk once `record`
+/// returns, so a crash loses nothing.
+pub struct Recorder {
+    file: LineWriter<File>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Starts `<dir>/<peer>-<unix time>.replay`.
+    pub fn create(dir: &Path, peer: &str) -> io::Result<Self> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let name = format!("{}-{}.replay", peer.replace([':', '[', ']'], "_"), started);
+        let path = dir.join(name);
+        let mut file = LineWriter::new(File::create(&path)?);
+        writeln!(file, "{}{}", HEADER, peer)?;
+        Ok(Self { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `at` is seconds since the session started.
+    pub fn record(&mut self, at: f32, track: Track, text: &str) -> io::Result<()> {
+        // One entry per line, whatever the client put in its datagram
+        let text = text.replace(['\n', '\r'], " ");
+        writeln!(self.file, "{:.3} {} {}", at, track.label(), text)
+    }
+}
+
+#[derive(Debug)]
+pub struct Recording {
+    pub peer: String,
+    /// In time order
+    pub entries: Vec<Entry>,
+}
+
+impl Recording {
+    /// Reads a replay file; lines that don't parse are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut peer = String::new();
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if let Some(rest) = line.strip_prefix(HEADER) {
+                peer = rest.trim().to_string();
+                continue;
+            }
+            let mut words = line.splitn(3, ' ');
+            let (Some(at), Some(track), text) = (words.next(), words.next(), words.next()) else {
+                continue;
+            };
+            let Ok(at) = at.parse::<f32>() else {
+                continue;
+            };
+            let track = match track {
+                "in" => Track::Input,
+                "state" => Track::State,
+                _ => continue,
+            };
+            entries.push(Entry {
+                at,
+                track,
+                text: text.unwrap_or("").to_string(),
+            });
+        }
+        // Recorded in order, but a hand-edited file might not be
+        entries.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Ok(Self { peer, entries })
+    }
+
+    /// Time of the last entry
+    pub fn duration(&self) -> f32 {
+        self.entries.last().map_or(0.0, |entry| entry.at)
+    }
+}
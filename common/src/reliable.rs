@@ -0,0 +1,293 @@
+//! A minimal reliable, ordered channel over the demo's text datagrams.
+//!
+//! The sender numbers each payload and keeps it until the receiver acks that
+//! number, resending it if no ack arrives within `resend_after` seconds. The
+//! receiver acks everything it gets, drops duplicates, and delivers payloads
+//! strictly in order, holding back any that arrive early.
+//!
+//! The sender never has more than `window` payloads unacked. Payloads pushed
+//! beyond that wait in a queue until acks open the window. So a stalled
+//! receiver costs at most `window` resends per timeout, not one per pushed
+//! payload.
+//!
+//! The receiver only holds back payloads less than [`RECEIVE_WINDOW`] past
+//! the first one it is missing. Anything further ahead is dropped without an
+//! ack, so the sender resends it once the gap has closed; a peer sending
+//! made-up sequence numbers can't make it hold more than that. On the wire:
+//!
+//! ```text
+//! REL <seq> <payload>   sender -> receiver
+//! REL_ACK <seq>         receiver -> sender
+//! ```
+//!
+//! Neither side owns a socket; the app sends the lines these types return.
+
+use std::collections::{BTreeMap, VecDeque};
+
+/// Most payloads a receiver holds back for a gap, counted from the first
+/// one it is missing
+pub const RECEIVE_WINDOW: u32 = 256;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReliableMessage<'a> {
+    Data { seq: u32, payload: &'a str },
+    Ack { seq: u32 },
+}
+
+impl<'a> ReliableMessage<'a> {
+    /// `None` for anything that isn't reliable-channel traffic.
+    pub fn parse(message: &'a str) -> Option<Self> {
+        if let Some(seq) = message.strip_prefix("REL_ACK ") {
+            return Some(ReliableMessage::Ack {
+                seq: seq.trim().parse().ok()?,
+            });
+        }
+        let rest = message.strip_prefix("REL ")?;
+        let (seq, payload) = rest.split_once(' ').unwrap_or((rest, ""));
+        Some(ReliableMessage::Data {
+            seq: seq.parse().ok()?,
+            payload,
+        })
+    }
+}
+
+struct InFlight {
+    payload: String,
+    sent_at: f32,
+}
+
+pub struct ReliableSender {
+    next_seq: u32,
+    /// Pushed but not sent yet
+    queued: VecDeque<(u32, String)>,
+    in_flight: BTreeMap<u32, InFlight>,
+    /// Most payloads unacked at once
+    window: usize,
+    resend_after: f32,
+    /// Payloads sent again because their ack was late
+    pub resends: u64,
+}
+
+impl ReliableSender {
+    pub fn new(window: usize, resend_after: f32) -> Self {
+        Self {
+            next_seq: 0,
+            queued: VecDeque::new(),
+            in_flight: BTreeMap::new(),
+            window: window.max(1),
+            resend_after,
+            resends: 0,
+        }
+    }
+
+    pub fn push(&mut self, payload: impl Into<String>) -> u32 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queued.push_back((seq, payload.into()));
+        seq
+    }
+
+    pub fn ack(&mut self, seq: u32) {
+        self.in_flight.remove(&seq);
+    }
+
+    /// Unacked payloads, at most the window size.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Roughly what the queued and unacked payloads hold on the heap.
+    pub fn heap_bytes(&self) -> usize {
+        let queued: usize = self
+            .queued
+            .iter()
+            .map(|(_, payload)| payload.capacity() + std::mem::size_of::<(u32, String)>())
+            .sum();
+        let in_flight: usize = self
+            .in_flight
+            .values()
+            .map(|flight| flight.payload.capacity() + std::mem::size_of::<(u32, InFlight)>())
+            .sum();
+        queued + in_flight
+    }
+
+    /// True once everything pushed has been acked.
+    pub fn is_idle(&self) -> bool {
+        self.queued.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Lines to send now: queued payloads while the window has room, then
+    /// any whose ack is overdue.
+    pub fn poll(&mut self, now: f32) -> Vec<String> {
+        let mut lines = Vec::new();
+        while self.in_flight.len() < self.window {
+            let Some((seq, payload)) = self.queued.pop_front() else {
+                break;
+            };
+            lines.push(format!("REL {} {}", seq, payload));
+            self.in_flight.insert(
+                seq,
+                InFlight {
+                    payload,
+                    sent_at: now,
+                },
+            );
+        }
+        for (seq, flight) in self.in_flight.iter_mut() {
+            if now - flight.sent_at >= self.resend_after {
+                lines.push(format!("REL {} {}", seq, flight.payload));
+                flight.sent_at = now;
+                self.resends += 1;
+            }
+        }
+        lines
+    }
+}
+
+#[derive(Default)]
+pub struct ReliableReceiver {
+    next_expected: u32,
+    /// Arrived ahead of a gap
+    early: BTreeMap<u32, String>,
+}
+
+impl ReliableReceiver {
+    /// Roughly what the payloads held back for a gap take on the heap.
+    pub fn heap_bytes(&self) -> usize {
+        self.early
+            .values()
+            .map(|payload| payload.capacity() + std::mem::size_of::<(u32, String)>())
+            .sum()
+    }
+
+    /// Returns the ack line to send back, and the payloads that are now
+    /// deliverable in order (possibly none, possibly several). There is no
+    /// ack for a payload beyond the [`RECEIVE_WINDOW`]; it comes again.
+    pub fn receive(&mut self, seq: u32, payload: &str) -> (Option<String>, Vec<String>) {
+        let ack = format!("REL_ACK {}", seq);
+        if seq < self.next_expected {
+            // A resend of something we already have; the ack was lost
+            return (Some(ack), Vec::new());
+        }
+        if seq - self.next_expected >= RECEIVE_WINDOW {
+            return (None, Vec::new());
+        }
+        self.early.entry(seq).or_insert_with(|| payload.to_string());
+
+        let mut delivered = Vec::new();
+        while let Some(payload) = self.early.remove(&self.next_expected) {
+            delivered.push(payload);
+            self.next_expected += 1;
+        }
+        (Some(ack), delivered)
+    }
+
+    /// Payloads held back for a gap, at most [`RECEIVE_WINDOW`].
+    pub fn held(&self) -> usize {
+        self.early.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_in_order_whatever_the_arrival_order() {
+        let mut receiver = ReliableReceiver::default();
+        let (ack, delivered) = receiver.receive(2, "c");
+        assert_eq!(ack.as_deref(), Some("REL_ACK 2"));
+        assert!(delivered.is_empty());
+        let (_, delivered) = receiver.receive(1, "b");
+        assert!(delivered.is_empty());
+        assert_eq!(receiver.held(), 2);
+
+        let (ack, delivered) = receiver.receive(0, "a");
+        assert_eq!(ack.as_deref(), Some("REL_ACK 0"));
+        assert_eq!(delivered, ["a", "b", "c"]);
+        assert_eq!(receiver.held(), 0);
+    }
+
+    #[test]
+    fn acks_duplicates_but_delivers_them_once() {
+        let mut receiver = ReliableReceiver::default();
+        receiver.receive(1, "b");
+        // Held back, then resent before the gap closed
+        let (ack, delivered) = receiver.receive(1, "b");
+        assert_eq!(ack.as_deref(), Some("REL_ACK 1"));
+        assert!(delivered.is_empty());
+        assert_eq!(receiver.held(), 1);
+
+        assert_eq!(receiver.receive(0, "a").1, ["a", "b"]);
+        // Already delivered; the ack was lost
+        let (ack, delivered) = receiver.receive(0, "a");
+        assert_eq!(ack.as_deref(), Some("REL_ACK 0"));
+        assert!(delivered.is_empty());
+    }
+
+    #[test]
+    fn drops_payloads_beyond_the_window_unacked() {
+        let mut receiver = ReliableReceiver::default();
+        let (ack, _) = receiver.receive(RECEIVE_WINDOW - 1, "last");
+        assert!(ack.is_some());
+        for seq in [RECEIVE_WINDOW, RECEIVE_WINDOW + 1, u32::MAX] {
+            let (ack, delivered) = receiver.receive(seq, "too far");
+            assert!(ack.is_none());
+            assert!(delivered.is_empty());
+        }
+        assert_eq!(receiver.held(), 1);
+
+        // A flood of made-up sequence numbers can't grow what is held
+        for seq in 1..RECEIVE_WINDOW * 4 {
+            receiver.receive(seq, "x");
+        }
+        assert_eq!(receiver.held(), RECEIVE_WINDOW as usize - 1);
+
+        // Closing the gap moves the window, so the resend is taken
+        assert_eq!(
+            receiver.receive(0, "first").1.len(),
+            RECEIVE_WINDOW as usize
+        );
+        let (ack, delivered) = receiver.receive(RECEIVE_WINDOW, "resent");
+        assert!(ack.is_some());
+        assert_eq!(delivered, ["resent"]);
+    }
+
+    #[test]
+    fn sender_resends_what_the_receiver_dropped() {
+        let mut sender = ReliableSender::new(RECEIVE_WINDOW as usize + 1, 1.0);
+        let mut receiver = ReliableReceiver::default();
+        for i in 0..=RECEIVE_WINDOW {
+            sender.push(i.to_string());
+        }
+        let mut delivered = Vec::new();
+        let mut now = 0.0;
+        // The first payload is lost once, so the last is beyond the window
+        let mut lost_first = false;
+        while !sender.is_idle() && now < 10.0 {
+            for line in sender.poll(now) {
+                let Some(ReliableMessage::Data { seq, payload }) = ReliableMessage::parse(&line)
+                else {
+                    continue;
+                };
+                if seq == 0 && !lost_first {
+                    lost_first = true;
+                    continue;
+                }
+                let (ack, payloads) = receiver.receive(seq, payload);
+                if ack.is_some() {
+                    sender.ack(seq);
+                }
+                delivered.extend(payloads);
+            }
+            now += 1.0;
+        }
+        let expected: Vec<String> = (0..=RECEIVE_WINDOW).map(|i| i.to_string()).collect();
+        assert_eq!(delivered, expected);
+        assert!(sender.resends > 1);
+    }
+}
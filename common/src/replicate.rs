@@ -0,0 +1,630 @@
+//! Server-to-client resource replication.
+//!
+//! A resource that implements [`Replicated`] and is registered with
+//! `app.replicate_resource::<R>()` is kept in sync from the server to every
+//! client. On the server, whenever the resource changes its fields are
+//! diffed against what was last sent, and only the changed ones go out as a
+//! [`ReplicationOutgoing`] message. Clients that have just joined get the full
+//! resource. On the client, each [`ReplicationIncoming`] message is applied to
+//! the local copy.
+//!
+//! This module never touches a socket. The app sends the outgoing messages
+//! over its own connection, to a single peer or to everyone. It also turns
+//! the matching received text back into incoming events. On the wire a
+//! message is one line:
+//!
+//! ```text
+//! REPL <name> [@<seq>] [*] <key>=<value> ... -<key> ...
+//! ```
+//!
+//! `*` marks a full copy: the client resets the resource first, which drops
+//! any fields left over from an earlier connection. `-<key>` removes a
+//! field. Values are escaped so that they contain no spaces.
+//!
+//! An app that sends to some peers less often than the resource changes can
+//! merge what queued up in between with [`coalesce`].
+//!
+//! A message is applied on top of the one before it, so over an unreliable
+//! socket a lost message would leave the client wrong until the field next
+//! changes. Apps that number each peer's messages with
+//! [`ReplicationSequence`] get loss recovery: a client that sees a gap in a
+//! resource's `@<seq>` skips that resource's changes and sends a
+//! [`ReplicationNack`], and the server answers with a full copy of that one
+//! resource ([`ReplicationResync`]), which the client applies from scratch.
+//! [`ReplicationStreams`] counts what was dropped and recovered. Messages
+//! without a number are applied as they come.
+
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::prelude::*;
+// std's Instant panics on wasm
+use bevy::utils::Instant;
+use std::collections::BTreeMap;
+
+const PREFIX: &str = "REPL ";
+const NACK_KIND: &str = "ReplNack";
+/// How long a client waits for the full copy a NACK asked for before
+/// asking again; the NACK or the copy may have been lost too.
+const NACK_RETRY_SECS: f32 = 0.5;
+
+/// Milliseconds spent diffing a replicated resource and building its
+/// messages, on the frames it changed or someone joined. Only recorded if
+/// the app registers the diagnostic.
+pub const REPLICATION_BUILD: DiagnosticPath = DiagnosticPath::const_new("replication/build_ms");
+
+/// A resource the server owns and clients mirror.
+///
+/// It is flattened to string fields. Keys must not contain spaces or `=`,
+/// and must not start with `-` or `@`.
+pub trait Replicated: Resource + Default {
+    /// Names the resource on the wire; it must be unique among the app's
+    /// replicated resources.
+    const NAME: &'static str;
+
+    fn to_fields(&self) -> BTreeMap<String, String>;
+
+    /// `None` removes the field.
+    fn apply_field(&mut self, key: &str, value: Option<&str>);
+}
+
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplicationRole {
+    Server,
+    Client,
+}
+
+/// Server side: ask for full copies of every replicated resource for `peer`.
+#[derive(Event)]
+pub struct ReplicationJoin {
+    pub peer: String,
+}
+
+/// Server side: ask for a full copy of one resource for `peer`, in answer to
+/// its [`ReplicationNack`].
+#[derive(Event)]
+pub struct ReplicationResync {
+    pub peer: String,
+    pub name: String,
+}
+
+/// Server side: text to send, to one peer or (`None`) to every client.
+#[derive(Event)]
+pub struct ReplicationOutgoing {
+    pub to: Option<String>,
+    pub text: String,
+}
+
+/// Client side: a received message, as text. Anything that doesn't start
+/// with `REPL ` is ignored, so apps can forward every message.
+#[derive(Event)]
+pub struct ReplicationIncoming(pub String);
+
+/// Client side: a resource's messages have a gap, so its copy can't be
+/// trusted until a full one arrives. The app sends [`Self::text`] to the
+/// server.
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub struct ReplicationNack {
+    pub name: String,
+    /// The last message that arrived, if any; for the server's log
+    pub last_seq: Option<u32>,
+}
+
+impl ReplicationNack {
+    /// `ReplNack <name> [<last seq>]`
+    pub fn text(&self) -> String {
+        match self.last_seq {
+            Some(seq) => format!("{} {} {}", NACK_KIND, self.name, seq),
+            None => format!("{} {}", NACK_KIND, self.name),
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace();
+        if words.next() != Some(NACK_KIND) {
+            return None;
+        }
+        let name = words.next()?.to_string();
+        let last_seq = words.next().and_then(|seq| seq.parse().ok());
+        Some(Self { name, last_seq })
+    }
+}
+
+/// Server side: numbers one peer's replication messages, counting each
+/// resource separately. Keep one per peer and stamp messages as they are
+/// sent, after any [`coalesce`].
+#[derive(Default)]
+pub struct ReplicationSequence {
+    next: BTreeMap<String, u32>,
+}
+
+impl ReplicationSequence {
+    /// Adds `@<seq>` after the resource name. Other text is returned as is.
+    pub fn stamp(&mut self, message: String) -> String {
+        let Some(rest) = message.strip_prefix(PREFIX) else {
+            return message;
+        };
+        let (name, fields) = rest.split_once(' ').unwrap_or((rest, ""));
+        let next = self.next.entry(name.to_string()).or_default();
+        let seq = *next;
+        *next += 1;
+        format!("{}{} @{} {}", PREFIX, name, seq, fields)
+            .trim_end()
+            .to_string()
+    }
+}
+
+/// What a received numbered message means for the resource.
+enum Received {
+    Apply,
+    /// A duplicate, a late arrival, or a change that can't be applied until
+    /// the full copy arrives
+    Skip,
+    /// A gap: skip it and ask for a full copy
+    Nack(Option<u32>),
+}
+
+/// Client side: how each resource's numbered messages have been arriving.
+#[derive(Resource, Default)]
+pub struct ReplicationStreams {
+    last_seq: BTreeMap<String, u32>,
+    /// Resources waiting for a full copy, with when it was last asked for
+    awaiting: BTreeMap<String, f32>,
+    /// Messages lost in the gaps
+    pub dropped: u64,
+    /// Full copies that arrived while a resource was waiting for one
+    pub recovered: u64,
+}
+
+impl ReplicationStreams {
+    fn receive(&mut self, name: &str, seq: u32, full: bool, now: f32) -> Received {
+        if full {
+            // A full copy needs no baseline; it is one
+            self.last_seq.insert(name.to_string(), seq);
+            if self.awaiting.remove(name).is_some() {
+                self.recovered += 1;
+            }
+            return Received::Apply;
+        }
+        let last = self.last_seq.get(name).copied();
+        match last {
+            Some(last) if seq <= last => return Received::Skip,
+            Some(last) if seq == last + 1 && !self.awaiting.contains_key(name) => {
+                self.last_seq.insert(name.to_string(), seq);
+                return Received::Apply;
+            }
+            Some(last) if seq > last + 1 => self.dropped += (seq - last - 1) as u64,
+            Some(_) => {}
+            // Even the first full copy went missing
+            None => self.dropped += 1,
+        }
+        self.last_seq.insert(name.to_string(), seq);
+        match self.awaiting.get(name) {
+            Some(asked) if now - asked < NACK_RETRY_SECS => Received::Skip,
+            _ => {
+                self.awaiting.insert(name.to_string(), now);
+                Received::Nack(last)
+            }
+        }
+    }
+
+    /// Resources whose copy is stale until a full one arrives
+    pub fn awaiting(&self) -> impl Iterator<Item = &str> {
+        self.awaiting.keys().map(String::as_str)
+    }
+}
+
+/// Senders run in `PostUpdate`, after the frame's changes. The app forwards
+/// their messages whenever it next reads the events.
+pub struct ReplicationPlugin {
+    pub role: ReplicationRole,
+}
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.role)
+            .init_resource::<ReplicationStreams>()
+            .add_event::<ReplicationJoin>()
+            .add_event::<ReplicationResync>()
+            .add_event::<ReplicationOutgoing>()
+            .add_event::<ReplicationIncoming>()
+            .add_event::<ReplicationNack>();
+    }
+}
+
+pub trait ReplicateResourceExt {
+    /// Registers `R` for replication. Add [`ReplicationPlugin`] first, since
+    /// the role decides whether this app sends or applies changes.
+    fn replicate_resource<R: Replicated>(&mut self) -> &mut Self;
+}
+
+impl ReplicateResourceExt for App {
+    fn replicate_resource<R: Replicated>(&mut self) -> &mut Self {
+        let role = *self
+            .world
+            .get_resource::<ReplicationRole>()
+            .expect("add ReplicationPlugin before replicate_resource");
+        self.init_resource::<R>();
+        match role {
+            ReplicationRole::Server => self.add_systems(PostUpdate, send_changes::<R>),
+            ReplicationRole::Client => self.add_systems(PostUpdate, apply_changes::<R>),
+        }
+    }
+}
+
+fn send_changes<R: Replicated>(
+    resource: Res<R>,
+    mut last_sent: Local<BTreeMap<String, String>>,
+    mut joins: EventReader<ReplicationJoin>,
+    mut resyncs: EventReader<ReplicationResync>,
+    mut outgoing: EventWriter<ReplicationOutgoing>,
+    mut diagnostics: Diagnostics,
+) {
+    let mut joined: Vec<String> = joins.read().map(|join| join.peer.clone()).collect();
+    for resync in resyncs.read() {
+        if resync.name == R::NAME && !joined.contains(&resync.peer) {
+            joined.push(resync.peer.clone());
+        }
+    }
+    if !resource.is_changed() && joined.is_empty() {
+        return;
+    }
+    let started = Instant::now();
+    let fields = resource.to_fields();
+
+    if resource.is_changed() {
+        let mut changes = Vec::new();
+        for (key, value) in &fields {
+            if last_sent.get(key) != Some(value) {
+                changes.push(format!("{}={}", key, escape(value)));
+            }
+        }
+        for key in last_sent.keys() {
+            if !fields.contains_key(key) {
+                changes.push(format!("-{}", key));
+            }
+        }
+        if !changes.is_empty() {
+            outgoing.send(ReplicationOutgoing {
+                to: None,
+                text: format!("{}{} {}", PREFIX, R::NAME, changes.join(" ")),
+            });
+        }
+    }
+
+    // A newcomer has nothing yet, and a resync can't trust what it has, so
+    // both get every field
+    if !joined.is_empty() {
+        let text = full_copy_of::<R>(&fields);
+        for peer in joined {
+            outgoing.send(ReplicationOutgoing {
+                to: Some(peer),
+                text: text.clone(),
+            });
+        }
+    }
+
+    *last_sent = fields;
+    diagnostics.add_measurement(&REPLICATION_BUILD, || {
+        started.elapsed().as_secs_f64() * 1000.0
+    });
+}
+
+fn apply_changes<R: Replicated>(
+    time: Res<Time>,
+    mut incoming: EventReader<ReplicationIncoming>,
+    mut resource: ResMut<R>,
+    mut streams: ResMut<ReplicationStreams>,
+    mut nacks: EventWriter<ReplicationNack>,
+) {
+    for message in incoming.read() {
+        let Some(rest) = message.0.trim().strip_prefix(PREFIX) else {
+            continue;
+        };
+        let mut words = rest.split_whitespace().peekable();
+        if words.next() != Some(R::NAME) {
+            continue;
+        }
+        let seq = words
+            .peek()
+            .and_then(|word| word.strip_prefix('@'))
+            .and_then(|seq| seq.parse::<u32>().ok());
+        if let Some(seq) = seq {
+            words.next();
+            let full = words.peek() == Some(&"*");
+            match streams.receive(R::NAME, seq, full, time.elapsed_seconds()) {
+                Received::Apply => {}
+                Received::Skip => continue,
+                Received::Nack(last_seq) => {
+                    nacks.send(ReplicationNack {
+                        name: R::NAME.to_string(),
+                        last_seq,
+                    });
+                    continue;
+                }
+            }
+        }
+        apply_words(&mut *resource, words);
+    }
+}
+
+fn apply_words<'a, R: Replicated>(resource: &mut R, words: impl Iterator<Item = &'a str>) {
+    for word in words {
+        if word == "*" {
+            *resource = R::default();
+        } else if let Some(key) = word.strip_prefix('-') {
+            resource.apply_field(key, None);
+        } else if let Some((key, value)) = word.split_once('=') {
+            resource.apply_field(key, Some(&unescape(value)));
+        }
+    }
+}
+
+fn full_copy_of<R: Replicated>(fields: &BTreeMap<String, String>) -> String {
+    let all: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, escape(value)))
+        .collect();
+    format!("{}{} * {}", PREFIX, R::NAME, all.join(" "))
+        .trim_end()
+        .to_string()
+}
+
+/// `resource` as the unnumbered full copy a newcomer gets.
+pub fn full_copy<R: Replicated>(resource: &R) -> String {
+    full_copy_of::<R>(&resource.to_fields())
+}
+
+/// The resource a message is for, if it is a replication message.
+pub fn message_name(message: &str) -> Option<&str> {
+    message
+        .trim()
+        .strip_prefix(PREFIX)?
+        .split_whitespace()
+        .next()
+}
+
+/// Applies one of `R`'s messages straight to `resource`, ignoring any
+/// number; for a full copy read back from somewhere other than the socket.
+/// Messages for other resources leave it alone.
+pub fn apply_message<R: Replicated>(resource: &mut R, message: &str) {
+    if message_name(message) != Some(R::NAME) {
+        return;
+    }
+    let words = message
+        .split_whitespace()
+        .skip(2)
+        .filter(|word| !word.starts_with('@'));
+    apply_words(resource, words);
+}
+
+/// Merges replication messages queued for one peer into one message per
+/// resource, so a peer can be sent to less often without missing anything:
+/// later values win and a full copy drops whatever came before it. Other
+/// text passes through, ahead of the merged messages.
+pub fn coalesce(messages: impl IntoIterator<Item = String>) -> Vec<String> {
+    struct Merged {
+        name: String,
+        full: bool,
+        /// `None` for a removed field
+        fields: BTreeMap<String, Option<String>>,
+    }
+
+    let mut out = Vec::new();
+    let mut merged: Vec<Merged> = Vec::new();
+    for message in messages {
+        let Some(rest) = message.trim().strip_prefix(PREFIX) else {
+            out.push(message);
+            continue;
+        };
+        let mut words = rest.split_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let index = match merged.iter().position(|entry| entry.name == name) {
+            Some(index) => index,
+            None => {
+                merged.push(Merged {
+                    name: name.to_string(),
+                    full: false,
+                    fields: BTreeMap::new(),
+                });
+                merged.len() - 1
+            }
+        };
+        let entry = &mut merged[index];
+        for word in words {
+            if word.starts_with('@') {
+                // Numbered again when sent
+                continue;
+            } else if word == "*" {
+                entry.full = true;
+                entry.fields.clear();
+            } else if let Some(key) = word.strip_prefix('-') {
+                entry.fields.insert(key.to_string(), None);
+            } else if let Some((key, value)) = word.split_once('=') {
+                entry
+                    .fields
+                    .insert(key.to_string(), Some(value.to_string()));
+            }
+        }
+    }
+
+    for entry in merged {
+        let fields: Vec<String> = entry
+            .fields
+            .iter()
+            .filter_map(|(key, value)| match value {
+                Some(value) => Some(format!("{}={}", key, value)),
+                // A full copy starts from defaults anyway
+                None if entry.full => None,
+                None => Some(format!("-{}", key)),
+            })
+            .collect();
+        if entry.full {
+            out.push(
+                format!("{}{} * {}", PREFIX, entry.name, fields.join(" "))
+                    .trim_end()
+                    .to_string(),
+            );
+        } else if !fields.is_empty() {
+            out.push(format!("{}{} {}", PREFIX, entry.name, fields.join(" ")));
+        }
+    }
+    out
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('\n', "%0A")
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("%0A", "\n")
+        .replace("%20", " ")
+        .replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Resource, Default)]
+    struct Score(BTreeMap<String, String>);
+
+    impl Replicated for Score {
+        const NAME: &'static str = "score";
+
+        fn to_fields(&self) -> BTreeMap<String, String> {
+            self.0.clone()
+        }
+
+        fn apply_field(&mut self, key: &str, value: Option<&str>) {
+            match value {
+                Some(value) => self.0.insert(key.to_string(), value.to_string()),
+                None => self.0.remove(key),
+            };
+        }
+    }
+
+    fn client() -> App {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .add_plugins(ReplicationPlugin {
+                role: ReplicationRole::Client,
+            })
+            .replicate_resource::<Score>();
+        app
+    }
+
+    /// Delivers `messages` and runs a frame; returns the NACKs it sent.
+    fn deliver(app: &mut App, messages: &[&str]) -> Vec<ReplicationNack> {
+        for message in messages {
+            app.world
+                .send_event(ReplicationIncoming(message.to_string()));
+        }
+        app.update();
+        app.world
+            .resource_mut::<Events<ReplicationNack>>()
+            .drain()
+            .collect()
+    }
+
+    fn field(app: &App, key: &str) -> Option<String> {
+        app.world.resource::<Score>().0.get(key).cloned()
+    }
+
+    #[test]
+    fn numbers_each_resource_on_its_own() {
+        let mut sequence = ReplicationSequence::default();
+        assert_eq!(sequence.stamp("REPL a x=1".into()), "REPL a @0 x=1");
+        assert_eq!(sequence.stamp("REPL a *".into()), "REPL a @1 *");
+        assert_eq!(sequence.stamp("REPL b x=1".into()), "REPL b @0 x=1");
+        assert_eq!(sequence.stamp("Pong".into()), "Pong");
+    }
+
+    #[test]
+    fn a_nack_round_trips() {
+        for last_seq in [None, Some(41)] {
+            let nack = ReplicationNack {
+                name: "score".into(),
+                last_seq,
+            };
+            assert_eq!(ReplicationNack::parse(&nack.text()), Some(nack));
+        }
+        assert_eq!(ReplicationNack::parse("REPL score"), None);
+    }
+
+    #[test]
+    fn a_gap_is_counted_and_asked_about_once_per_retry() {
+        let mut streams = ReplicationStreams::default();
+        assert!(matches!(
+            streams.receive("s", 0, true, 0.0),
+            Received::Apply
+        ));
+        assert!(matches!(
+            streams.receive("s", 1, false, 0.0),
+            Received::Apply
+        ));
+        assert!(matches!(
+            streams.receive("s", 1, false, 0.0),
+            Received::Skip
+        ));
+        assert!(matches!(
+            streams.receive("s", 4, false, 0.0),
+            Received::Nack(Some(1))
+        ));
+        assert_eq!(streams.dropped, 2);
+        // In order again, but still missing what the gap held
+        assert!(matches!(
+            streams.receive("s", 5, false, 0.1),
+            Received::Skip
+        ));
+        assert!(matches!(
+            streams.receive("s", 6, false, NACK_RETRY_SECS),
+            Received::Nack(Some(5))
+        ));
+        assert_eq!(streams.awaiting().collect::<Vec<_>>(), ["s"]);
+    }
+
+    #[test]
+    fn a_lost_first_copy_is_asked_for() {
+        let mut streams = ReplicationStreams::default();
+        assert!(matches!(
+            streams.receive("s", 3, false, 0.0),
+            Received::Nack(None)
+        ));
+        assert_eq!(streams.dropped, 1);
+    }
+
+    #[test]
+    fn resyncs_from_a_full_copy_after_a_dropped_snapshot() {
+        let mut app = client();
+        assert!(deliver(&mut app, &["REPL score @0 * a=1", "REPL score @1 a=2"]).is_empty());
+        assert_eq!(field(&app, "a").as_deref(), Some("2"));
+
+        // @2 was lost: @3 can't be applied on top of what we have
+        let nacks = deliver(&mut app, &["REPL score @3 b=9"]);
+        assert_eq!(
+            nacks,
+            [ReplicationNack {
+                name: "score".into(),
+                last_seq: Some(1),
+            }]
+        );
+        assert_eq!(field(&app, "b"), None);
+
+        // The server's answer replaces everything
+        assert!(deliver(&mut app, &["REPL score @4 * a=5 c=7"]).is_empty());
+        assert_eq!(field(&app, "a").as_deref(), Some("5"));
+        assert_eq!(field(&app, "c").as_deref(), Some("7"));
+        let streams = app.world.resource::<ReplicationStreams>();
+        assert_eq!((streams.dropped, streams.recovered), (1, 1));
+        assert_eq!(streams.awaiting().count(), 0);
+
+        // And changes apply on top of it again
+        deliver(&mut app, &["REPL score @5 -c"]);
+        assert_eq!(field(&app, "c"), None);
+    }
+}
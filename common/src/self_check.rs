@@ -0,0 +1,176 @@
+//! Startup self-diagnostics.
+//!
+//! A UDP program that can't receive usually just sits there. These checks
+//! run before the sockets are bound and turn the common causes (port taken,
+//! loopback broken, a firewall eating traffic on the LAN address, payloads
+//! too big for one Ethernet frame) into a short report for the log and the
+//! terminal.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Largest UDP payload that fits a 1500-byte Ethernet frame without IP
+/// fragmentation (1500 - 20 IPv4 header - 8 UDP header).
+pub const ETHERNET_SAFE_PAYLOAD: usize = 1472;
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+const PROBE: &[u8] = b"self-check";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+        }
+    }
+}
+
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    /// One log line, e.g. `[Check ok]: loopback - 127.0.0.1 echo received`.
+    pub fn describe(&self) -> String {
+        format!(
+            "[Check {}]: {} - {}",
+            self.status.label(),
+            self.name,
+            self.detail
+        )
+    }
+}
+
+/// Runs every check. `ports` are the ports the program is about to bind (0
+/// means "any" and is skipped); `recv_buffer` is the `--recv-buffer` size.
+pub fn run_self_checks(ports: &[u16], recv_buffer: usize) -> Vec<CheckResult> {
+    let mut results: Vec<CheckResult> = ports
+        .iter()
+        .filter(|port| **port != 0)
+        .map(|port| check_port_free(*port))
+        .collect();
+    results.push(check_echo("loopback", IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    results.push(match lan_address() {
+        Some(lan) => check_echo("LAN address", lan),
+        None => CheckResult::new(
+            "LAN address",
+            CheckStatus::Warn,
+            "no non-loopback IPv4 route found; other machines can't reach us",
+        ),
+    });
+    results.push(check_payload_size(recv_buffer));
+    results
+}
+
+/// Prints the report to the terminal and returns its lines for the UI log.
+pub fn report(results: &[CheckResult]) -> Vec<String> {
+    let lines: Vec<String> = results.iter().map(CheckResult::describe).collect();
+    for line in &lines {
+        println!("{}", line);
+    }
+    lines
+}
+
+fn check_port_free(port: u16) -> CheckResult {
+    match UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(_) => CheckResult::new("bind", CheckStatus::Ok, format!("port {} is free", port)),
+        Err(e) => CheckResult::new(
+            "bind",
+            CheckStatus::Fail,
+            format!(
+                "port {} can't be bound ({}); is another instance running?",
+                port, e
+            ),
+        ),
+    }
+}
+
+/// Sends a datagram to ourselves on `ip`; true if it came back.
+fn echo_round_trip(ip: IpAddr) -> std::io::Result<bool> {
+    let receiver = UdpSocket::bind(SocketAddr::new(ip, 0))?;
+    receiver.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    let sender = UdpSocket::bind(SocketAddr::new(ip, 0))?;
+    sender.send_to(PROBE, receiver.local_addr()?)?;
+    let mut buf = [0u8; 16];
+    match receiver.recv_from(&mut buf) {
+        Ok((size, _)) => Ok(&buf[..size] == PROBE),
+        // The timeout shows up as WouldBlock on Unix and TimedOut on Windows
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn check_echo(name: &'static str, ip: IpAddr) -> CheckResult {
+    let result = echo_round_trip(ip);
+    match result {
+        Ok(true) => CheckResult::new(name, CheckStatus::Ok, format!("{} echo received", ip)),
+        Ok(false) => CheckResult::new(
+            name,
+            CheckStatus::Warn,
+            format!(
+                "nothing came back on {} within {}ms; a firewall is likely dropping UDP",
+                ip,
+                PROBE_TIMEOUT.as_millis()
+            ),
+        ),
+        Err(e) => CheckResult::new(name, CheckStatus::Fail, format!("{}: {}", ip, e)),
+    }
+}
+
+/// The address other machines would use to reach us. Connecting a UDP socket
+/// sends nothing; it only asks the OS which interface routes there.
+fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+/// UDP can't discover the path MTU by itself, so assume Ethernet and warn
+/// when the receive buffer invites payloads that would be fragmented.
+fn check_payload_size(recv_buffer: usize) -> CheckResult {
+    if recv_buffer > ETHERNET_SAFE_PAYLOAD {
+        CheckResult::new(
+            "MTU",
+            CheckStatus::Warn,
+            format!(
+                "--recv-buffer {} exceeds {} bytes; bigger datagrams get fragmented and are more likely to be lost",
+                recv_buffer, ETHERNET_SAFE_PAYLOAD
+            ),
+        )
+    } else {
+        CheckResult::new(
+            "MTU",
+            CheckStatus::Ok,
+            format!(
+                "assuming a 1500-byte MTU, payloads up to {} bytes travel unfragmented",
+                ETHERNET_SAFE_PAYLOAD
+            ),
+        )
+    }
+}
@@ -0,0 +1,105 @@
+//! Sequence diagrams of a session, from a packet log.
+//!
+//! [`render`] draws the datagrams of a `--packet-log` file (see
+//! `packet_log`) as a Mermaid or PlantUML sequence diagram: one lifeline
+//! for the side that wrote the log and one per peer, and an arrow per
+//! datagram labelled with its time since the first one and its kind:
+//!
+//! ```text
+//! sequenceDiagram
+//!     participant S as server
+//!     participant P1 as 127.0.0.1:50211
+//!     P1->>S: +0.000s Hello
+//!     S->>P1: +0.001s Welcome
+//! ```
+//!
+//! Handy for documenting a protocol flow as it happened in a real run; the
+//! server's console writes one with `/diagram`.
+
+use crate::packet_log::{Direction, PacketLogFile};
+use crate::session::message_kind;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiagramFormat {
+    Mermaid,
+    PlantUml,
+}
+
+impl DiagramFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mermaid" => Some(DiagramFormat::Mermaid),
+            "plantuml" => Some(DiagramFormat::PlantUml),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension
+    pub fn extension(self) -> &'static str {
+        match self {
+            DiagramFormat::Mermaid => "mmd",
+            DiagramFormat::PlantUml => "puml",
+        }
+    }
+}
+
+/// The first `limit` datagrams of `log`, or only those exchanged with `peer`
+/// when given. Returns the diagram and how many arrows it has.
+pub fn render(
+    log: &PacketLogFile,
+    peer: Option<&str>,
+    format: DiagramFormat,
+    limit: usize,
+) -> (String, usize) {
+    let packets: Vec<_> = log
+        .packets
+        .iter()
+        .filter(|packet| peer.is_none_or(|peer| packet.peer == peer))
+        .take(limit)
+        .collect();
+    // Lifelines in order of appearance, ours first
+    let mut peers: Vec<&str> = Vec::new();
+    for packet in &packets {
+        if !peers.contains(&packet.peer.as_str()) {
+            peers.push(&packet.peer);
+        }
+    }
+    let id = |peer: &str| {
+        let index = peers.iter().position(|known| *known == peer).unwrap_or(0);
+        format!("P{}", index + 1)
+    };
+
+    let mut lines = Vec::new();
+    if format == DiagramFormat::PlantUml {
+        lines.push("@startuml".to_string());
+    } else {
+        lines.push("sequenceDiagram".to_string());
+    }
+    let mut participant = |id: &str, name: &str| {
+        lines.push(match format {
+            DiagramFormat::Mermaid => format!("    participant {} as {}", id, name),
+            DiagramFormat::PlantUml => format!("participant \"{}\" as {}", name, id),
+        });
+    };
+    participant("S", log.side.label());
+    for peer in &peers {
+        participant(&id(peer), peer);
+    }
+
+    let start = packets.first().map_or(0.0, |packet| packet.at);
+    for packet in &packets {
+        let (from, to) = match packet.direction {
+            Direction::Sent => ("S".to_string(), id(&packet.peer)),
+            Direction::Received => (id(&packet.peer), "S".to_string()),
+        };
+        let label = format!("+{:.3}s {}", packet.at - start, message_kind(&packet.text));
+        lines.push(match format {
+            DiagramFormat::Mermaid => format!("    {}->>{}: {}", from, to, label),
+            DiagramFormat::PlantUml => format!("{} -> {} : {}", from, to, label),
+        });
+    }
+    if format == DiagramFormat::PlantUml {
+        lines.push("@enduml".to_string());
+    }
+    (lines.join("\n") + "\n", packets.len())
+}
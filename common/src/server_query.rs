@@ -0,0 +1,123 @@
+//! Asking servers what they are, with the connectionless
+//! `ServerInfoRequest` (see `handlers::ServerQuery`), for server lists and
+//! matchmakers.
+//!
+//! A [`ServerQuery`](crate::handlers::ServerQuery) needs no session, so a
+//! [`Querier`] just sends every address a request from its own sockets, one
+//! per address family, and collects the `ServerInfo`s that come back within
+//! the timeout. Answers are matched to addresses by where they came from,
+//! so a server has to answer from the address it was asked at. It blocks
+//! for up to the timeout: run it on a thread of its own.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::handlers::{NetMessage, ServerInfo, ServerInfoRequest};
+
+/// Big enough for any answer, signed or not
+const RECV_BUFFER: usize = 2048;
+
+/// What one address said
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryResult {
+    Answered {
+        info: ServerInfo,
+        rtt_ms: f32,
+    },
+    /// Nothing within the timeout
+    NoReply,
+    /// It couldn't be asked: it didn't resolve, or the send failed
+    Failed(String),
+}
+
+pub struct Querier {
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+}
+
+impl Querier {
+    /// Binds an ephemeral port for each address family this host has.
+    pub fn bind() -> Self {
+        let v4 = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok();
+        let v6 = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).ok();
+        for socket in v4.iter().chain(v6.iter()) {
+            let _ = socket.set_nonblocking(true);
+        }
+        Self { v4, v6 }
+    }
+
+    /// Asks every `host:port` in `targets` at once; the results are in the
+    /// same order.
+    pub fn query(&self, targets: &[String], timeout: Duration) -> Vec<QueryResult> {
+        self.drain();
+        let request = ServerInfoRequest::default().message();
+        let mut results = Vec::new();
+        // Asked and not answered yet: where, which target, and when
+        let mut waiting: Vec<(SocketAddr, usize, Instant)> = Vec::new();
+        for target in targets {
+            let result = match self.send(target, request.as_bytes()) {
+                Ok(addr) => {
+                    waiting.push((addr, results.len(), Instant::now()));
+                    QueryResult::NoReply
+                }
+                Err(reason) => QueryResult::Failed(reason),
+            };
+            results.push(result);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; RECV_BUFFER];
+        while !waiting.is_empty() && Instant::now() < deadline {
+            let mut received = false;
+            for socket in self.sockets() {
+                while let Ok((size, from)) = socket.recv_from(&mut buf) {
+                    received = true;
+                    let text = String::from_utf8_lossy(&buf[..size]);
+                    let Some(info) = ServerInfo::parse(&text) else {
+                        continue;
+                    };
+                    let Some(index) = waiting.iter().position(|(addr, ..)| *addr == from) else {
+                        continue;
+                    };
+                    let (_, target, sent) = waiting.swap_remove(index);
+                    let rtt_ms = sent.elapsed().as_secs_f32() * 1000.0;
+                    results[target] = QueryResult::Answered { info, rtt_ms };
+                }
+            }
+            if !received {
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+        results
+    }
+
+    /// The address `request` went to
+    fn send(&self, target: &str, request: &[u8]) -> Result<SocketAddr, String> {
+        let addr = target
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| "no address".to_string())?;
+        let socket = if addr.is_ipv4() { &self.v4 } else { &self.v6 };
+        let Some(socket) = socket else {
+            return Err("no socket for its address family".to_string());
+        };
+        socket
+            .send_to(request, addr)
+            .map(|_| addr)
+            .map_err(|e| e.to_string())
+    }
+
+    fn sockets(&self) -> impl Iterator<Item = &UdpSocket> {
+        self.v4.iter().chain(self.v6.iter())
+    }
+
+    /// Late answers to the last round would be taken for answers to this one
+    fn drain(&self) {
+        let mut buf = [0u8; RECV_BUFFER];
+        for socket in self.sockets() {
+            while socket.recv_from(&mut buf).is_ok() {}
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Per-connection session statistics.
+//!
+//! A [`SessionStats`] is opened when a handshake completes and closed when the
+//! connection ends, for whatever reason. Closing it produces a short summary
+//! (duration, messages by type, bytes, RTT percentiles of the user's Pings
+//! and of the heartbeats measured alongside, why it ended) for the
+//! log, and optionally appends the same lines to a file so every test run
+//! leaves a record.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What's left of a session once it has closed: enough to say how long it
+/// was and how much went through it, without the per-type counts and RTTs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SessionTotals {
+    pub secs: f32,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+pub struct SessionStats {
+    pub peer: String,
+    /// Elapsed app seconds when the session started
+    pub started_at: f32,
+    sent: BTreeMap<String, u64>,
+    received: BTreeMap<String, u64>,
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// Round trip times of Pings in seconds
+    rtts: Vec<f32>,
+    /// Round trip times of heartbeats, measured whether or not anyone pings
+    ambient_rtts: Vec<f32>,
+}
+
+impl SessionStats {
+    pub fn new(peer: impl Into<String>, now: f32) -> Self {
+        Self {
+            peer: peer.into(),
+            started_at: now,
+            sent: BTreeMap::new(),
+            received: BTreeMap::new(),
+            bytes_sent: 0,
+            bytes_received: 0,
+            rtts: Vec::new(),
+            ambient_rtts: Vec::new(),
+        }
+    }
+
+    pub fn record_sent(&mut self, kind: &str, size: usize) {
+        *self.sent.entry(kind.to_string()).or_default() += 1;
+        self.bytes_sent += size as u64;
+    }
+
+    pub fn record_received(&mut self, kind: &str, size: usize) {
+        *self.received.entry(kind.to_string()).or_default() += 1;
+        self.bytes_received += size as u64;
+    }
+
+    /// Messages of `kind` received so far
+    pub fn received(&self, kind: &str) -> u64 {
+        self.received.get(kind).copied().unwrap_or(0)
+    }
+
+    pub fn record_rtt(&mut self, secs: f32) {
+        self.rtts.push(secs);
+    }
+
+    pub fn record_ambient_rtt(&mut self, secs: f32) {
+        self.ambient_rtts.push(secs);
+    }
+
+    /// For a session ending at `now`.
+    pub fn totals(&self, now: f32) -> SessionTotals {
+        SessionTotals {
+            secs: now - self.started_at,
+            messages_sent: self.sent.values().sum(),
+            messages_received: self.received.values().sum(),
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+        }
+    }
+
+    /// Roughly what the stats hold on the heap: the peer, the counts by
+    /// type and every RTT sample.
+    pub fn heap_bytes(&self) -> usize {
+        let counts = |messages: &BTreeMap<String, u64>| {
+            messages
+                .keys()
+                .map(|kind| kind.capacity() + std::mem::size_of::<(String, u64)>())
+                .sum::<usize>()
+        };
+        self.peer.capacity()
+            + counts(&self.sent)
+            + counts(&self.received)
+            + (self.rtts.capacity() + self.ambient_rtts.capacity()) * std::mem::size_of::<f32>()
+    }
+
+    /// The summary lines for a session ending at `now` because of `reason`.
+    pub fn summary(&self, now: f32, reason: &str) -> Vec<String> {
+        let counts = |messages: &BTreeMap<String, u64>| {
+            if messages.is_empty() {
+                return "none".to_string();
+            }
+            messages
+                .iter()
+                .map(|(kind, count)| format!("{} x{}", kind, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut lines = vec![
+            format!(
+                "Session with {} ended after {:.1}s: {}",
+                self.peer,
+                now - self.started_at,
+                reason
+            ),
+            format!("  sent: {}", counts(&self.sent)),
+            format!("  received: {}", counts(&self.received)),
+            format!(
+                "  bytes: {} sent, {} received",
+                self.bytes_sent, self.bytes_received
+            ),
+        ];
+        lines.extend(rtt_line("RTT", &self.rtts));
+        lines.extend(rtt_line("ambient RTT", &self.ambient_rtts));
+        lines
+    }
+}
+
+/// `  <label>: avg .., p95 ..`, when there are samples
+fn rtt_line(label: &str, rtts: &[f32]) -> Option<String> {
+    if rtts.is_empty() {
+        return None;
+    }
+    let mut sorted = rtts.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let average = sorted.iter().sum::<f32>() / sorted.len() as f32;
+    let p95 = sorted[((sorted.len() - 1) as f32 * 0.95).round() as usize];
+    Some(format!(
+        "  {}: avg {:.1} ms, p95 {:.1} ms ({} samples)",
+        label,
+        average * 1000.0,
+        p95 * 1000.0,
+        sorted.len()
+    ))
+}
+
+/// First word of a message, so `Welcome data_port=12346` counts as `Welcome`.
+pub fn message_kind(message: &str) -> &str {
+    message.split_whitespace().next().unwrap_or("(empty)")
+}
+
+/// Appends a summary to `path`, preceded by a unix timestamp line.
+pub fn append_summary(path: &Path, lines: &[String]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    writeln!(file, "# {}", now)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Randomness every peer draws the same.
+//!
+//! The server picks a seed (`--seed`, or one from the clock) and hands it
+//! to every client as it joins: in the Welcome as `seed=<hex>`, or, in
+//! knock_knock, as the first payload of the join stream. A [`SharedRng`]
+//! made from that seed draws the same numbers on every peer, so a demo can
+//! pick a joke or a spawn angle on each side without a message saying
+//! which.
+//!
+//! It is SplitMix64 run as a counter: the `n`th number is a hash of the
+//! seed and `n`, so [`SharedRng::at`] can look up any draw without making
+//! the ones before it. That matters when peers draw at different moments,
+//! or when one joins late: tie a draw to something both sides know (round
+//! 7, question 12) and they agree however many numbers each drew before.
+//! It is not meant for anything secret.
+
+use bevy::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SharedRng {
+    seed: u64,
+    /// Numbers drawn with [`SharedRng::next_u64`] so far
+    draws: u64,
+}
+
+impl SharedRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, draws: 0 }
+    }
+
+    /// A seed for the server to hand out, from the clock
+    pub fn random_seed() -> u64 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        mix(nanos)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The `index`th number, whether or not it has been drawn
+    pub fn at(&self, index: u64) -> u64 {
+        mix(self
+            .seed
+            .wrapping_add(index.wrapping_add(1).wrapping_mul(GAMMA)))
+    }
+
+    /// The `index`th number, reduced to `0..count`. `count` must not be 0.
+    pub fn index_at(&self, index: u64, count: usize) -> usize {
+        (self.at(index) % count as u64) as usize
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let value = self.at(self.draws);
+        self.draws += 1;
+        value
+    }
+
+    /// Uniform in `0..count`; `count` must not be 0
+    pub fn below(&mut self, count: usize) -> usize {
+        (self.next_u64() % count as u64) as usize
+    }
+
+    /// Uniform in 0..1
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Fisher-Yates, so equal seeds give equal orders
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for last in (1..items.len()).rev() {
+            items.swap(last, self.below(last + 1));
+        }
+    }
+}
+
+/// The SplitMix64 finalizer
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,371 @@
+//! `--validate`: a smoke test of the networking on this machine.
+//!
+//! For packagers and CI boxes without a display: the binary runs the
+//! startup self-checks, then a short session over loopback against a
+//! built-in peer playing the other side, prints the outcome as one JSON
+//! object on stdout and exits with 0 when nothing failed, 1 otherwise. No
+//! window, no Bevy app, and nothing leaves the machine.
+//!
+//! The session is the real exchange, in the real message formats:
+//!
+//! - `bind`: a socket on 127.0.0.1
+//! - `handshake`: Hello, answered by a Welcome with a seed and a token
+//! - `ping`: [`PINGS`] Pings, each answered by a Pong
+//! - `heartbeat`: a Heartbeat with the measured RTT, answered by a
+//!   HeartbeatAck
+//! - `disconnect`: a Disconnect the other side recognizes
+//!
+//! The client's binary sends these and the built-in peer answers; the
+//! server's answers them and the built-in peer sends. Each check waits
+//! [`STEP_TIMEOUT`] at most, so a broken stack fails in seconds.
+//!
+//! ```text
+//! {"binary": "client", "version": "0.1.0", "protocol_version": 2, "ok": true, "checks": [
+//!   {"name": "bind", "status": "ok", "ms": 0.1, "detail": "127.0.0.1:50211"}, ...]}
+//! ```
+
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::disconnect::DisconnectReason;
+use crate::handlers::{Heartbeat, NetMessage, Welcome};
+use crate::packet_log::Side;
+use crate::protocol::{PROTOCOL_VERSION, json_string};
+use crate::self_check::{CheckStatus, run_self_checks};
+use crate::session::message_kind;
+
+pub const PINGS: usize = 5;
+pub const STEP_TIMEOUT: Duration = Duration::from_secs(1);
+/// The answering side gives up once it has heard nothing for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    /// How long the step took, for the steps that are timed
+    pub millis: Option<f64>,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            millis: None,
+            detail: detail.into(),
+        }
+    }
+
+    fn timed(mut self, started: Instant) -> Self {
+        self.millis = Some(started.elapsed().as_secs_f64() * 1000.0);
+        self
+    }
+
+    fn to_json(&self) -> String {
+        let millis = self
+            .millis
+            .map_or(String::new(), |ms| format!(", \"ms\": {:.3}", ms));
+        format!(
+            "{{\"name\": {}, \"status\": {}{}, \"detail\": {}}}",
+            json_string(&self.name),
+            json_string(&self.status.label().to_ascii_lowercase()),
+            millis,
+            json_string(&self.detail)
+        )
+    }
+}
+
+pub struct Report {
+    pub side: Side,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Warnings, e.g. no LAN address, don't count against it
+    pub fn ok(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Fail)
+    }
+
+    pub fn to_json(&self) -> String {
+        let checks: Vec<String> = self
+            .checks
+            .iter()
+            .map(|check| format!("    {}", check.to_json()))
+            .collect();
+        let fields = [
+            ("binary", json_string(self.side.label())),
+            ("version", json_string(env!("CARGO_PKG_VERSION"))),
+            ("protocol_version", PROTOCOL_VERSION.to_string()),
+            ("ok", self.ok().to_string()),
+            ("checks", format!("[\n{}\n  ]", checks.join(",\n"))),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(name, value)| format!("  \"{}\": {}", name, value))
+            .collect();
+        format!("{{\n{}\n}}", fields.join(",\n"))
+    }
+}
+
+/// Runs the self-checks for `ports` and `recv_buffer` (as at startup), then
+/// the loopback session with us as `side`.
+pub fn run(side: Side, ports: &[u16], recv_buffer: usize) -> Report {
+    let mut checks: Vec<Check> = run_self_checks(ports, recv_buffer)
+        .into_iter()
+        .map(|result| Check::new(result.name, result.status, result.detail))
+        .collect();
+
+    let started = Instant::now();
+    let sockets = UdpSocket::bind("127.0.0.1:0").and_then(|ours| {
+        let peer = UdpSocket::bind("127.0.0.1:0")?;
+        for socket in [&ours, &peer] {
+            socket.set_read_timeout(Some(STEP_TIMEOUT))?;
+        }
+        Ok((ours, peer))
+    });
+    let (ours, peer) = match sockets {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            checks.push(Check::new("bind", CheckStatus::Fail, e.to_string()).timed(started));
+            return Report { side, checks };
+        }
+    };
+    let (Ok(our_addr), Ok(peer_addr)) = (ours.local_addr(), peer.local_addr()) else {
+        checks.push(Check::new(
+            "bind",
+            CheckStatus::Fail,
+            "bound, but the address is unknown",
+        ));
+        return Report { side, checks };
+    };
+    checks.push(Check::new("bind", CheckStatus::Ok, our_addr.to_string()).timed(started));
+
+    match side {
+        Side::Client => {
+            let server = thread::spawn(move || answer(&peer));
+            checks.extend(exercise(&ours, peer_addr));
+            // The built-in server's own failures show up as the client's
+            let _ = server.join();
+        }
+        Side::Server => {
+            let client = thread::spawn(move || exercise(&peer, our_addr));
+            let answered = answer(&ours);
+            match client.join() {
+                Ok(client_checks) => checks.extend(client_checks),
+                Err(_) => checks.push(Check::new(
+                    "session",
+                    CheckStatus::Fail,
+                    "the built-in client panicked",
+                )),
+            }
+            checks.push(match answered {
+                Ok(count) => Check::new(
+                    "answered",
+                    CheckStatus::Ok,
+                    format!("{} messages answered", count),
+                ),
+                Err(reason) => Check::new("answered", CheckStatus::Fail, reason),
+            });
+        }
+    }
+    Report { side, checks }
+}
+
+/// The next datagram from `from`, as text; `None` on a timeout. Anything
+/// from another address is skipped.
+fn receive(socket: &UdpSocket, from: SocketAddr) -> std::io::Result<Option<String>> {
+    let mut buf = [0u8; 1024];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((size, sender)) if sender == from => {
+                return Ok(Some(String::from_utf8_lossy(&buf[..size]).into_owned()));
+            }
+            Ok(_) => continue,
+            // WouldBlock on Unix and TimedOut on Windows, as in `self_check`
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Sends `message` and waits for the reply of kind `expected`.
+fn request(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    message: &str,
+    expected: &str,
+) -> Result<String, String> {
+    socket
+        .send_to(message.as_bytes(), server)
+        .map_err(|e| format!("send failed: {}", e))?;
+    let deadline = Instant::now() + STEP_TIMEOUT;
+    while Instant::now() < deadline {
+        match receive(socket, server) {
+            Ok(Some(reply)) if message_kind(&reply) == expected => return Ok(reply),
+            Ok(Some(_)) => continue,
+            Ok(None) => break,
+            Err(e) => return Err(format!("receive failed: {}", e)),
+        }
+    }
+    Err(format!(
+        "no {} within {}ms",
+        expected,
+        STEP_TIMEOUT.as_millis()
+    ))
+}
+
+/// The client's half: sends every step and checks the replies.
+fn exercise(socket: &UdpSocket, server: SocketAddr) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let started = Instant::now();
+    let hello = format!("Hello version={}", PROTOCOL_VERSION);
+    let welcome = request(socket, server, &hello, Welcome::KIND)
+        .and_then(|text| Welcome::parse(&text).ok_or_else(|| format!("malformed: {}", text)));
+    match welcome {
+        Ok(Welcome {
+            seed: Some(seed),
+            token: Some(_),
+            ..
+        }) => checks.push(
+            Check::new(
+                "handshake",
+                CheckStatus::Ok,
+                format!("welcomed, seed {:016x}", seed),
+            )
+            .timed(started),
+        ),
+        Ok(_) => {
+            checks.push(
+                Check::new(
+                    "handshake",
+                    CheckStatus::Fail,
+                    "Welcome without a seed or token",
+                )
+                .timed(started),
+            );
+            return checks;
+        }
+        Err(reason) => {
+            checks.push(Check::new("handshake", CheckStatus::Fail, reason).timed(started));
+            return checks;
+        }
+    }
+
+    let started = Instant::now();
+    let mut rtts = Vec::new();
+    let mut failure = None;
+    for _ in 0..PINGS {
+        let sent = Instant::now();
+        match request(socket, server, "Ping", "Pong") {
+            Ok(_) => rtts.push(sent.elapsed().as_secs_f64() * 1000.0),
+            Err(reason) => failure = Some(reason),
+        }
+    }
+    let rtt = (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64);
+    let lost = PINGS - rtts.len();
+    checks.push(
+        match (rtt, failure) {
+            (Some(rtt), None) => Check::new(
+                "ping",
+                CheckStatus::Ok,
+                format!("{} Pongs, mean RTT {:.3}ms", PINGS, rtt),
+            ),
+            (Some(rtt), Some(reason)) => Check::new(
+                "ping",
+                CheckStatus::Warn,
+                format!(
+                    "{} of {} Pings lost on loopback ({}), mean RTT {:.3}ms",
+                    lost, PINGS, reason, rtt
+                ),
+            ),
+            (None, reason) => Check::new(
+                "ping",
+                CheckStatus::Fail,
+                reason.unwrap_or_else(|| "no Pongs".to_string()),
+            ),
+        }
+        .timed(started),
+    );
+
+    let started = Instant::now();
+    let heartbeat = Heartbeat {
+        rtt_ms: rtt.map(|rtt| rtt as f32),
+        loss: Some(lost as f32 / PINGS as f32),
+    };
+    checks.push(
+        match request(socket, server, &heartbeat.message(), "HeartbeatAck") {
+            Ok(_) => Check::new("heartbeat", CheckStatus::Ok, "acked"),
+            Err(reason) => Check::new("heartbeat", CheckStatus::Fail, reason),
+        }
+        .timed(started),
+    );
+
+    let started = Instant::now();
+    let disconnect = format!("Disconnect reason={}", DisconnectReason::UserQuit.code());
+    checks.push(
+        match socket.send_to(disconnect.as_bytes(), server) {
+            Ok(_) => Check::new("disconnect", CheckStatus::Ok, "sent"),
+            Err(e) => Check::new("disconnect", CheckStatus::Fail, e.to_string()),
+        }
+        .timed(started),
+    );
+    checks
+}
+
+/// The server's half: answers until a Disconnect, or until nothing has
+/// come for [`IDLE_TIMEOUT`]. Returns how many messages it answered.
+fn answer(socket: &UdpSocket) -> Result<u32, String> {
+    let mut buf = [0u8; 1024];
+    let mut answered = 0;
+    let mut last_heard = Instant::now();
+    while last_heard.elapsed() < IDLE_TIMEOUT {
+        let (size, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(format!("receive failed: {}", e)),
+        };
+        last_heard = Instant::now();
+        let text = String::from_utf8_lossy(&buf[..size]);
+        let reply = match message_kind(&text) {
+            "Hello" => welcome(),
+            "Ping" => "Pong".to_string(),
+            "Heartbeat" if Heartbeat::parse(&text).is_some() => "HeartbeatAck".to_string(),
+            "Disconnect" => return Ok(answered),
+            _ => return Err(format!("unexpected message: {}", text)),
+        };
+        socket
+            .send_to(reply.as_bytes(), from)
+            .map_err(|e| format!("send failed: {}", e))?;
+        answered += 1;
+    }
+    Err(format!(
+        "nothing heard for {}s, and no Disconnect",
+        IDLE_TIMEOUT.as_secs()
+    ))
+}
+
+fn welcome() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    format!("Welcome seed={:016x} token={:016x}", nanos, !nanos)
+}
@@ -0,0 +1,119 @@
+//! Chunked snapshots: a large initial state split into datagram-sized pieces.
+//!
+//! The sender flattens its state to records, one per line, and
+//! [`split_into_chunks`] packs whole records into chunks no bigger than
+//! `max_bytes`. Each chunk goes over the reliable channel (see `reliable`),
+//! so they arrive complete and in order. The first chunks are marked
+//! essential: once those have arrived the receiver has enough to start
+//! interacting, and the rest can keep streaming in behind. On the wire each
+//! chunk is one reliable payload:
+//!
+//! ```text
+//! SNAP <name> <index> <total> <essential> <records...>
+//! ```
+//!
+//! The records after the header are separated by newlines.
+
+/// Leaves room for the `REL`/`SNAP` headers inside a 1024-byte receive buffer.
+pub const DEFAULT_CHUNK_BYTES: usize = 800;
+
+/// Packs `records` into chunk payloads. Chunks holding any of the first
+/// `essential_records` records are essential. A record longer than
+/// `max_bytes` gets a chunk to itself.
+pub fn split_into_chunks(
+    name: &str,
+    records: &[String],
+    max_bytes: usize,
+    essential_records: usize,
+) -> Vec<String> {
+    let mut groups: Vec<Vec<&str>> = Vec::new();
+    let mut size = 0;
+    let mut essential = 0;
+    for (index, record) in records.iter().enumerate() {
+        if groups.is_empty() || size + record.len() + 1 > max_bytes {
+            groups.push(Vec::new());
+            size = 0;
+        }
+        groups.last_mut().unwrap().push(record);
+        size += record.len() + 1;
+        if index < essential_records {
+            essential = groups.len();
+        }
+    }
+
+    let total = groups.len();
+    groups
+        .iter()
+        .enumerate()
+        .map(|(index, group)| {
+            format!(
+                "SNAP {} {} {} {} {}",
+                name,
+                index,
+                total,
+                essential,
+                group.join("\n")
+            )
+        })
+        .collect()
+}
+
+pub struct SnapshotChunk<'a> {
+    pub name: &'a str,
+    pub index: usize,
+    pub total: usize,
+    pub essential: usize,
+    pub records: &'a str,
+}
+
+impl<'a> SnapshotChunk<'a> {
+    pub fn parse(payload: &'a str) -> Option<Self> {
+        let mut parts = payload.strip_prefix("SNAP ")?.splitn(5, ' ');
+        Some(Self {
+            name: parts.next()?,
+            index: parts.next()?.parse().ok()?,
+            total: parts.next()?.parse().ok()?,
+            essential: parts.next()?.parse().ok()?,
+            records: parts.next().unwrap_or(""),
+        })
+    }
+}
+
+/// Receiver-side progress through one named snapshot.
+#[derive(Default)]
+pub struct SnapshotProgress {
+    pub received: usize,
+    /// Unknown until the first chunk arrives
+    pub total: Option<usize>,
+    pub essential: usize,
+}
+
+impl SnapshotProgress {
+    /// Records the chunk and returns its records.
+    pub fn accept<'a>(&mut self, chunk: &SnapshotChunk<'a>) -> impl Iterator<Item = &'a str> {
+        self.received = self.received.max(chunk.index + 1);
+        self.total = Some(chunk.total);
+        self.essential = chunk.essential;
+        chunk
+            .records
+            .split('\n')
+            .filter(|record| !record.is_empty())
+    }
+
+    /// 0..=1, for a progress bar
+    pub fn fraction(&self) -> f32 {
+        match self.total {
+            Some(0) => 1.0,
+            Some(total) => self.received as f32 / total as f32,
+            None => 0.0,
+        }
+    }
+
+    pub fn essential_ready(&self) -> bool {
+        self.total.is_some() && self.received >= self.essential
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total.is_some_and(|total| self.received >= total)
+    }
+}
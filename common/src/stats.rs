@@ -0,0 +1,221 @@
+//! Raw and smoothed network statistics.
+//!
+//! A single RTT sample says little: one Pong waits behind a slow frame and
+//! the next doesn't. Anything that acts on one, such as a quality score or
+//! an adaptive rate, wants a steadier input. A [`SmoothedStat`] keeps the
+//! last raw sample and an exponentially weighted moving average of all of
+//! them ([`Ewma`]; an alpha of 1/8 is TCP's RTT gain). It also keeps the
+//! recent samples for a few windows (1, 10 and 60 seconds by default),
+//! summarized as mean, min and max.
+//!
+//! Time is whatever clock the caller uses, in seconds as an `f64`: an
+//! `f32` clock is down to 2ms steps after four and a half hours, which
+//! would blur the windows of a long session. Samples passed in must not go
+//! back in time.
+
+use std::collections::VecDeque;
+
+/// TCP's RTT gain
+pub const DEFAULT_ALPHA: f32 = 0.125;
+pub const DEFAULT_WINDOWS: [f32; 3] = [1.0, 10.0, 60.0];
+
+/// Exponentially weighted moving average. The first sample is taken as
+/// it is; each later one moves the average `alpha` of the way to it.
+#[derive(Clone, Copy, Debug)]
+pub struct Ewma {
+    alpha: f32,
+    value: Option<f32>,
+}
+
+impl Ewma {
+    /// `alpha` is clamped to 0..=1; 1 follows the raw samples.
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    pub fn add(&mut self, sample: f32) -> f32 {
+        let value = match self.value {
+            Some(value) => value + (sample - value) * self.alpha,
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn value(&self) -> Option<f32> {
+        self.value
+    }
+}
+
+/// The samples of one window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowSummary {
+    pub secs: f32,
+    pub count: usize,
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+pub struct SmoothedStat {
+    ewma: Ewma,
+    last: Option<f32>,
+    /// Seconds, in the order given
+    windows: Vec<f32>,
+    /// (when, sample), oldest first, back to the longest window
+    samples: VecDeque<(f64, f32)>,
+}
+
+impl SmoothedStat {
+    /// Windows of 0 seconds or less are left out.
+    pub fn new(alpha: f32, windows: &[f32]) -> Self {
+        Self {
+            ewma: Ewma::new(alpha),
+            last: None,
+            windows: windows.iter().copied().filter(|secs| *secs > 0.0).collect(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, now: f64, sample: f32) {
+        self.last = Some(sample);
+        self.ewma.add(sample);
+        self.samples.push_back((now, sample));
+        let longest = f64::from(self.windows.iter().copied().fold(0.0, f32::max));
+        while let Some((at, _)) = self.samples.front() {
+            if now - at > longest {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// The last sample
+    pub fn raw(&self) -> Option<f32> {
+        self.last
+    }
+
+    pub fn smoothed(&self) -> Option<f32> {
+        self.ewma.value()
+    }
+
+    /// The samples of the last `secs` seconds, `None` if there were none.
+    /// Only goes back as far as the longest configured window.
+    pub fn window(&self, now: f64, secs: f32) -> Option<WindowSummary> {
+        let recent = self
+            .samples
+            .iter()
+            .rev()
+            .take_while(|(at, _)| now - at <= f64::from(secs))
+            .map(|(_, sample)| *sample);
+        let (count, sum, min, max) = recent.fold(
+            (0, 0.0, f32::INFINITY, f32::NEG_INFINITY),
+            |(count, sum, min, max), sample| {
+                (count + 1, sum + sample, min.min(sample), max.max(sample))
+            },
+        );
+        (count > 0).then(|| WindowSummary {
+            secs,
+            count,
+            mean: sum / count as f32,
+            min,
+            max,
+        })
+    }
+
+    /// One entry per configured window, in their order
+    pub fn windows(&self, now: f64) -> Vec<(f32, Option<WindowSummary>)> {
+        self.windows
+            .iter()
+            .map(|secs| (*secs, self.window(now, *secs)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ewma_takes_the_first_sample_as_it_is() {
+        let mut ewma = Ewma::new(DEFAULT_ALPHA);
+        assert_eq!(ewma.value(), None);
+        assert_eq!(ewma.add(40.0), 40.0);
+        assert_eq!(ewma.add(48.0), 41.0);
+        assert_eq!(ewma.value(), Some(41.0));
+    }
+
+    #[test]
+    fn ewma_clamps_alpha() {
+        let mut follows = Ewma::new(3.0);
+        follows.add(10.0);
+        assert_eq!(follows.add(20.0), 20.0);
+        let mut stuck = Ewma::new(-1.0);
+        stuck.add(10.0);
+        assert_eq!(stuck.add(20.0), 10.0);
+    }
+
+    #[test]
+    fn summarizes_a_window() {
+        let mut stat = SmoothedStat::new(DEFAULT_ALPHA, &DEFAULT_WINDOWS);
+        for (now, sample) in [(0.0, 30.0), (0.5, 10.0), (1.0, 20.0)] {
+            stat.record(now, sample);
+        }
+        assert_eq!(stat.raw(), Some(20.0));
+        assert_eq!(
+            stat.window(1.0, 10.0),
+            Some(WindowSummary {
+                secs: 10.0,
+                count: 3,
+                mean: 20.0,
+                min: 10.0,
+                max: 30.0,
+            })
+        );
+        let last = stat.window(1.0, 0.6).unwrap();
+        assert_eq!((last.count, last.min, last.max), (2, 10.0, 20.0));
+    }
+
+    #[test]
+    fn an_empty_window_has_no_summary() {
+        let mut stat = SmoothedStat::new(DEFAULT_ALPHA, &DEFAULT_WINDOWS);
+        assert_eq!(stat.window(0.0, 1.0), None);
+        stat.record(0.0, 5.0);
+        assert_eq!(stat.window(2.0, 1.0), None);
+        assert!(stat.window(2.0, 10.0).is_some());
+        let windows = stat.windows(2.0);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0], (1.0, None));
+    }
+
+    #[test]
+    fn keeps_samples_back_to_the_longest_window() {
+        // The 0-second window is left out, so 5 seconds is the longest
+        let mut stat = SmoothedStat::new(DEFAULT_ALPHA, &[0.0, 2.0, 5.0]);
+        assert_eq!(stat.windows(0.0).len(), 2);
+        for second in 0..10 {
+            stat.record(f64::from(second), 1.0);
+        }
+        assert_eq!(stat.samples.len(), 6);
+        assert_eq!(stat.samples.front().map(|(at, _)| *at), Some(4.0));
+        // A question about longer than that only sees what was kept
+        assert_eq!(stat.window(9.0, 60.0).map(|summary| summary.count), Some(6));
+    }
+
+    #[test]
+    fn windows_stay_sharp_late_in_a_session() {
+        // A day in, an f32 clock only has 8ms steps
+        let day = 86_400.0;
+        let mut stat = SmoothedStat::new(DEFAULT_ALPHA, &[1.0]);
+        stat.record(day, 1.0);
+        stat.record(day + 0.999, 2.0);
+        assert_eq!(
+            stat.window(day + 1.0005, 1.0).map(|summary| summary.count),
+            Some(1)
+        );
+    }
+}
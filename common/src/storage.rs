@@ -0,0 +1,439 @@
+//! State that survives restarts, behind a pluggable [`Storage`].
+//!
+//! [`open`] gives the app one [`Database`] of the `--storage` [`Backend`]:
+//! an embedded sled database (the default), one SQLite file (with the
+//! `sqlite` feature), or memory only. sled and SQLite live at
+//! `--database <path>`; without one, [`choose`] keeps everything in memory.
+//! Each store keeps its own [`Table`] in it:
+//!
+//! - [`History`] keeps the last [`HISTORY_LINES`] lines in memory for
+//!   newcomers and appends every line to the `history` table. On startup
+//!   the newest lines come back, so a restarted server greets its first
+//!   client with what was said before. The table keeps at most
+//!   [`KEPT_LINES`]; older lines are dropped as new ones arrive. Keys are a
+//!   big-endian sequence number, so key order is arrival order.
+//! - [`Accounts`] keeps one record per username in the `accounts` table: a
+//!   salted argon2id password hash and the account's running
+//!   [`AccountStats`]. A record that doesn't parse is an error, never a
+//!   free username, so a damaged row can't be taken over by the next login.
+//! - [`CrashReports`] keeps the reports clients send about their last run
+//!   in the `crash_reports` table, newest [`KEPT_REPORTS`] only, keyed like
+//!   the history.
+//! - [`Bans`] keeps the address ranges banned at runtime in the `bans`
+//!   table, keyed by the range as written.
+//!
+//! The stores only see the [`Table`] trait, so they behave the same on
+//! every backend. Pick by what the deployment needs: sled needs nothing
+//! outside the binary, an SQLite file opens in the `sqlite3` shell and in
+//! backup tools, and memory leaves nothing behind for throwaway servers.
+
+mod backend;
+
+pub use backend::{Backend, Entry, Result, Storage, StorageError, Table, choose, open};
+
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lines served to a newly connecting client
+pub const HISTORY_LINES: usize = 50;
+/// Lines kept in the database
+pub const KEPT_LINES: usize = 1000;
+/// Crash reports kept in the database
+pub const KEPT_REPORTS: usize = 200;
+/// Random bytes in a password salt
+const SALT_BYTES: usize = 16;
+
+pub type Database = Box<dyn Storage>;
+
+#[derive(Resource)]
+pub struct History {
+    table: Box<dyn Table>,
+    /// Lines in the table, tracked here because counting may walk it
+    stored: usize,
+    next_seq: u64,
+    recent: VecDeque<String>,
+}
+
+impl History {
+    /// Opens the history table and loads its newest lines.
+    pub fn open(database: &dyn Storage) -> Result<Self> {
+        let table = database.table("history")?;
+        let newest = table.newest(HISTORY_LINES)?;
+        let next_seq = newest.first().map_or(0, |(key, _)| seq_of(key) + 1);
+        let recent = newest
+            .iter()
+            .rev()
+            .map(|(_, line)| String::from_utf8_lossy(line).to_string())
+            .collect();
+        Ok(History {
+            stored: table.count()?,
+            table,
+            next_seq,
+            recent,
+        })
+    }
+
+    pub fn append(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        match self
+            .table
+            .insert(&self.next_seq.to_be_bytes(), line.as_bytes())
+        {
+            Ok(_) => {
+                self.next_seq += 1;
+                self.stored += 1;
+            }
+            Err(e) => warn!("Could not store history line: {}", e),
+        }
+        while self.stored > KEPT_LINES {
+            match self.table.pop_min() {
+                Ok(Some(_)) => self.stored -= 1,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Could not trim history: {}", e);
+                    break;
+                }
+            }
+        }
+        self.recent.push_back(line);
+        while self.recent.len() > HISTORY_LINES {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Oldest first
+    pub fn recent(&self) -> impl Iterator<Item = &str> {
+        self.recent.iter().map(String::as_str)
+    }
+}
+
+fn seq_of(key: &[u8]) -> u64 {
+    key.try_into().map_or(0, u64::from_be_bytes)
+}
+
+/// Totals over every session an account has logged in to.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct AccountStats {
+    pub logins: u64,
+    pub pings: u64,
+    pub chat_lines: u64,
+}
+
+pub enum Login {
+    /// `created` when the username was free and now belongs to this password
+    LoggedIn {
+        stats: AccountStats,
+        created: bool,
+    },
+    BadPassword,
+}
+
+/// An account's argon2id password hash in the PHC string format, salt and
+/// parameters included: `$argon2id$v=19$m=...$<salt>$<hash>`
+struct PasswordHashed(String);
+
+impl PasswordHashed {
+    /// An argon2id hash of `password` under a fresh salt from the OS.
+    fn new(password: &str) -> Result<Self> {
+        let mut salt = [0u8; SALT_BYTES];
+        getrandom::getrandom(&mut salt).map_err(|e| StorageError::Hash(e.to_string()))?;
+        let salt = SaltString::encode_b64(&salt).map_err(|e| StorageError::Hash(e.to_string()))?;
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| StorageError::Hash(e.to_string()))?;
+        Ok(Self(hash.to_string()))
+    }
+
+    /// argon2 compares the hashes in constant time.
+    fn verify(&self, password: &str) -> bool {
+        PasswordHash::new(&self.0).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+}
+
+struct AccountRecord {
+    password: PasswordHashed,
+    stats: AccountStats,
+}
+
+impl AccountRecord {
+    /// `<phc> <logins> <pings> <chat_lines>`
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(bytes).ok()?;
+        let mut fields = text.split_whitespace();
+        let phc = fields.next().filter(|phc| phc.starts_with("$argon2id$"))?;
+        let mut count = || fields.next()?.parse().ok();
+        let stats = AccountStats {
+            logins: count()?,
+            pings: count()?,
+            chat_lines: count()?,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            password: PasswordHashed(phc.to_string()),
+            stats,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        format!(
+            "{} {} {} {}",
+            self.password.0, self.stats.logins, self.stats.pings, self.stats.chat_lines
+        )
+        .into_bytes()
+    }
+}
+
+/// Cheap to clone, so a login can be checked off the main thread.
+#[derive(Resource, Clone)]
+pub struct Accounts {
+    table: Arc<dyn Table>,
+}
+
+impl Accounts {
+    pub fn open(database: &dyn Storage) -> Result<Self> {
+        Ok(Self {
+            table: Arc::from(database.table("accounts")?),
+        })
+    }
+
+    /// The record stored for `user`, if there is one.
+    fn record(&self, user: &str) -> Result<Option<AccountRecord>> {
+        match self.table.get(user.as_bytes())? {
+            Some(bytes) => AccountRecord::parse(&bytes)
+                .map(Some)
+                .ok_or_else(|| StorageError::Corrupt(format!("accounts/{}", user))),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks the password, creating the account on its first login, and
+    /// counts the login. The argon2 hash takes tens of milliseconds on
+    /// purpose, so call it off the main thread.
+    pub fn login(&self, user: &str, password: &str) -> Result<Login> {
+        let (mut record, created) = match self.record(user)? {
+            Some(record) if !record.password.verify(password) => {
+                return Ok(Login::BadPassword);
+            }
+            Some(record) => (record, false),
+            None => {
+                let record = AccountRecord {
+                    password: PasswordHashed::new(password)?,
+                    stats: AccountStats::default(),
+                };
+                (record, true)
+            }
+        };
+        record.stats.logins += 1;
+        self.table.insert(user.as_bytes(), &record.to_bytes())?;
+        Ok(Login::LoggedIn {
+            stats: record.stats,
+            created,
+        })
+    }
+
+    /// Adds a session's new activity to the account's totals.
+    pub fn add_activity(
+        &self,
+        user: &str,
+        pings: u64,
+        chat_lines: u64,
+    ) -> Result<Option<AccountStats>> {
+        let Some(mut record) = self.record(user)? else {
+            return Ok(None);
+        };
+        record.stats.pings += pings;
+        record.stats.chat_lines += chat_lines;
+        self.table.insert(user.as_bytes(), &record.to_bytes())?;
+        Ok(Some(record.stats))
+    }
+}
+
+/// One report as filed.
+pub struct CrashReport {
+    pub id: u64,
+    /// Unix seconds when it arrived
+    pub received_at: u64,
+    pub peer: String,
+    /// The client's fields, as sent
+    pub report: String,
+}
+
+impl CrashReport {
+    /// `<received_at> <peer> <report>`
+    fn parse(id: u64, bytes: &[u8]) -> Option<Self> {
+        let text = String::from_utf8_lossy(bytes);
+        let (received_at, rest) = text.split_once(' ')?;
+        let (peer, report) = rest.split_once(' ')?;
+        Some(Self {
+            id,
+            received_at: received_at.parse().ok()?,
+            peer: peer.to_string(),
+            report: report.to_string(),
+        })
+    }
+}
+
+#[derive(Resource)]
+pub struct CrashReports {
+    table: Box<dyn Table>,
+    stored: usize,
+    next_id: u64,
+}
+
+impl CrashReports {
+    pub fn open(database: &dyn Storage) -> Result<Self> {
+        let table = database.table("crash_reports")?;
+        let next_id = table
+            .newest(1)?
+            .first()
+            .map_or(0, |(key, _)| seq_of(key) + 1);
+        Ok(Self {
+            stored: table.count()?,
+            table,
+            next_id,
+        })
+    }
+
+    /// Stores `report` from `peer` and returns its id.
+    pub fn file(&mut self, peer: &str, report: &str) -> Result<u64> {
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let id = self.next_id;
+        self.table.insert(
+            &id.to_be_bytes(),
+            format!("{} {} {}", received_at, peer, report).as_bytes(),
+        )?;
+        self.next_id += 1;
+        self.stored += 1;
+        while self.stored > KEPT_REPORTS {
+            match self.table.pop_min()? {
+                Some(_) => self.stored -= 1,
+                None => break,
+            }
+        }
+        Ok(id)
+    }
+
+    /// Newest first
+    pub fn recent(&self, count: usize) -> Vec<CrashReport> {
+        self.table
+            .newest(count)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(key, value)| CrashReport::parse(seq_of(&key), &value))
+            .collect()
+    }
+}
+
+/// Address ranges banned at runtime, as written (`10.0.0.0/8`). The store
+/// doesn't parse them; the server does.
+pub struct Bans {
+    table: Box<dyn Table>,
+}
+
+impl Bans {
+    pub fn open(database: &dyn Storage) -> Result<Self> {
+        Ok(Self {
+            table: database.table("bans")?,
+        })
+    }
+
+    /// In key order
+    pub fn all(&self) -> Result<Vec<String>> {
+        let mut ranges: Vec<String> = self
+            .table
+            .newest(usize::MAX)?
+            .into_iter()
+            .map(|(range, _)| String::from_utf8_lossy(&range).to_string())
+            .collect();
+        ranges.reverse();
+        Ok(ranges)
+    }
+
+    pub fn add(&self, range: &str) -> Result<()> {
+        self.table.insert(range.as_bytes(), &[])
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        while self.table.pop_min()?.is_some() {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> Accounts {
+        let database = open(Backend::Memory, None).unwrap();
+        Accounts::open(database.as_ref()).unwrap()
+    }
+
+    fn logged_in(login: Login) -> Option<(AccountStats, bool)> {
+        match login {
+            Login::LoggedIn { stats, created } => Some((stats, created)),
+            Login::BadPassword => None,
+        }
+    }
+
+    #[test]
+    fn first_login_creates_the_account() {
+        let accounts = accounts();
+        let (stats, created) = logged_in(accounts.login("alice", "a b c").unwrap()).unwrap();
+        assert!(created);
+        assert_eq!(stats.logins, 1);
+        let (stats, created) = logged_in(accounts.login("alice", "a b c").unwrap()).unwrap();
+        assert!(!created);
+        assert_eq!(stats.logins, 2);
+        assert!(logged_in(accounts.login("alice", "a b").unwrap()).is_none());
+    }
+
+    #[test]
+    fn stores_argon2id_under_a_random_salt() {
+        let accounts = accounts();
+        accounts.login("alice", "secret").unwrap();
+        accounts.login("bob", "secret").unwrap();
+        let stored = |user: &str| {
+            let bytes = accounts.table.get(user.as_bytes()).unwrap().unwrap();
+            String::from_utf8(bytes).unwrap()
+        };
+        let (alice, bob) = (stored("alice"), stored("bob"));
+        assert!(alice.starts_with("$argon2id$"));
+        assert!(!alice.contains("secret"));
+        assert_ne!(alice, bob);
+    }
+
+    #[test]
+    fn refuses_to_overwrite_a_corrupt_record() {
+        let accounts = accounts();
+        for damaged in [
+            &b"00112233 deadbeef 4 5 6"[..],
+            b"$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA 4 5",
+            b"$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA 4 5 six",
+            b"\xff\xfe",
+        ] {
+            accounts.table.insert(b"alice", damaged).unwrap();
+            assert!(matches!(
+                accounts.login("alice", "secret"),
+                Err(StorageError::Corrupt(_))
+            ));
+            assert!(matches!(
+                accounts.add_activity("alice", 1, 1),
+                Err(StorageError::Corrupt(_))
+            ));
+            // Still there for an operator to look at
+            assert_eq!(accounts.table.get(b"alice").unwrap().unwrap(), damaged);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Where the stores keep their bytes. Each store asks the [`Storage`] for a
+//! [`Table`] of its own by name: an ordered map of byte keys to byte values,
+//! ordered like `memcmp`, which every backend agrees on.
+
+#[cfg(feature = "sqlite")]
+use rusqlite::OptionalExtension;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    /// No randomness for a salt, or argon2 refused its input
+    #[error("password hashing failed: {0}")]
+    Hash(String),
+    /// A stored record that doesn't parse, by `<table>/<key>`
+    #[error("unreadable record {0}")]
+    Corrupt(String),
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+/// A key and its value.
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+pub trait Table: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Replaces any value the key had
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<()>;
+    fn remove(&self, key: &[u8]) -> Result<()>;
+    /// Removes the entry with the smallest key and returns it
+    fn pop_min(&self) -> Result<Option<Entry>>;
+    /// The `count` entries with the largest keys, largest first
+    fn newest(&self, count: usize) -> Result<Vec<Entry>>;
+    /// Entries in the table
+    fn count(&self) -> Result<usize>;
+}
+
+pub trait Storage: Send + Sync {
+    /// The table called `name`, created empty the first time
+    fn table(&self, name: &str) -> Result<Box<dyn Table>>;
+}
+
+/// `--storage`: which [`Storage`] [`open`] gives.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// An embedded sled database, a directory
+    #[default]
+    Sled,
+    /// One SQLite file, which the `sqlite3` shell can read
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    /// Nothing on disk; everything is gone on exit
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, String> {
+        match name {
+            "sled" => Ok(Backend::Sled),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(Backend::Sqlite),
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => Err("built without the sqlite feature".to_string()),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!(
+                "no storage backend called {}; there are sled, sqlite and memory",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Sled => "sled",
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => "sqlite",
+            Backend::Memory => "memory",
+        })
+    }
+}
+
+/// The backend for `--storage` and `--database`, and a warning to print if
+/// it isn't the one asked for. Without `--storage` it's sled for a path and
+/// memory otherwise. sled or SQLite without a path would only be a
+/// temporary database that looks like it persists, so that is memory too,
+/// with a warning. Memory with a path is an error.
+pub fn choose(
+    storage: Option<Backend>,
+    path: Option<&Path>,
+) -> std::result::Result<(Backend, Option<String>), String> {
+    match (storage, path) {
+        (Some(Backend::Memory), Some(_)) => {
+            Err("--storage memory keeps nothing on disk, so it takes no --database".to_string())
+        }
+        (Some(backend), Some(_)) => Ok((backend, None)),
+        (None, Some(_)) => Ok((Backend::Sled, None)),
+        (Some(Backend::Memory) | None, None) => Ok((Backend::Memory, None)),
+        (Some(backend), None) => Ok((
+            Backend::Memory,
+            Some(format!(
+                "--storage {} needs a --database to keep anything; keeping it in memory",
+                backend
+            )),
+        )),
+    }
+}
+
+/// `backend` at `path`; see [`choose`]. sled and SQLite without a path
+/// are kept in memory.
+pub fn open(backend: Backend, path: Option<&Path>) -> Result<Box<dyn Storage>> {
+    Ok(match (backend, path) {
+        (Backend::Sled, Some(path)) => Box::new(sled::open(path)?),
+        #[cfg(feature = "sqlite")]
+        (Backend::Sqlite, Some(path)) => Box::new(SqliteStorage::open(path)?),
+        _ => Box::new(MemoryStorage::default()),
+    })
+}
+
+impl Storage for sled::Db {
+    fn table(&self, name: &str) -> Result<Box<dyn Table>> {
+        Ok(Box::new(self.open_tree(name)?))
+    }
+}
+
+impl Table for sled::Tree {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(sled::Tree::get(self, key)?.map(|value| value.to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        sled::Tree::insert(self, key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<()> {
+        sled::Tree::remove(self, key)?;
+        Ok(())
+    }
+
+    fn pop_min(&self) -> Result<Option<Entry>> {
+        Ok(sled::Tree::pop_min(self)?.map(|(key, value)| (key.to_vec(), value.to_vec())))
+    }
+
+    fn newest(&self, count: usize) -> Result<Vec<Entry>> {
+        self.iter()
+            .rev()
+            .take(count)
+            .map(|entry| -> Result<Entry> {
+                let (key, value) = entry?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Walks the whole tree; sled keeps no count
+    fn count(&self) -> Result<usize> {
+        Ok(sled::Tree::len(self))
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<HashMap<String, MemoryTable>>,
+}
+
+impl Storage for MemoryStorage {
+    fn table(&self, name: &str) -> Result<Box<dyn Table>> {
+        let mut tables = self.tables.lock().unwrap();
+        Ok(Box::new(
+            tables.entry(name.to_string()).or_default().clone(),
+        ))
+    }
+}
+
+/// Shared by every handle to the same table
+#[derive(Clone, Default)]
+pub struct MemoryTable(Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>);
+
+impl Table for MemoryTable {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.lock().unwrap().get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.0.lock().unwrap().insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<()> {
+        self.0.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn pop_min(&self) -> Result<Option<Entry>> {
+        Ok(self.0.lock().unwrap().pop_first())
+    }
+
+    fn newest(&self, count: usize) -> Result<Vec<Entry>> {
+        let map = self.0.lock().unwrap();
+        Ok(map
+            .iter()
+            .rev()
+            .take(count)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn count(&self) -> Result<usize> {
+        Ok(self.0.lock().unwrap().len())
+    }
+}
+
+/// Every table is an SQL table of the same name with a `key` and a `value`
+/// BLOB column; SQLite compares BLOBs with `memcmp`, as the trait wants.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStorage {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+    fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            connection: Arc::new(Mutex::new(rusqlite::Connection::open(path)?)),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for SqliteStorage {
+    fn table(&self, name: &str) -> Result<Box<dyn Table>> {
+        // Table names are the stores' own constants, never user input
+        self.connection.lock().unwrap().execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS \"{}\" \
+                 (key BLOB PRIMARY KEY, value BLOB NOT NULL) WITHOUT ROWID",
+                name
+            ),
+            [],
+        )?;
+        Ok(Box::new(SqliteTable {
+            connection: self.connection.clone(),
+            name: name.to_string(),
+        }))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct SqliteTable {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+    name: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl Table for SqliteTable {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let sql = format!("SELECT value FROM \"{}\" WHERE key = ?1", self.name);
+        let connection = self.connection.lock().unwrap();
+        Ok(connection
+            .query_row(&sql, [key], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let sql = format!(
+            "INSERT OR REPLACE INTO \"{}\" (key, value) VALUES (?1, ?2)",
+            self.name
+        );
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(&sql, [key, value])?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<()> {
+        let sql = format!("DELETE FROM \"{}\" WHERE key = ?1", self.name);
+        self.connection.lock().unwrap().execute(&sql, [key])?;
+        Ok(())
+    }
+
+    fn pop_min(&self) -> Result<Option<Entry>> {
+        let select = format!(
+            "SELECT key, value FROM \"{}\" ORDER BY key LIMIT 1",
+            self.name
+        );
+        // Holding the lock makes the select and delete one step
+        let connection = self.connection.lock().unwrap();
+        let first: Option<Entry> = connection
+            .query_row(&select, [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        if let Some((key, _)) = &first {
+            let delete = format!("DELETE FROM \"{}\" WHERE key = ?1", self.name);
+            connection.execute(&delete, [key])?;
+        }
+        Ok(first)
+    }
+
+    fn newest(&self, count: usize) -> Result<Vec<Entry>> {
+        let sql = format!(
+            "SELECT key, value FROM \"{}\" ORDER BY key DESC LIMIT ?1",
+            self.name
+        );
+        // A negative limit is no limit
+        let limit = i64::try_from(count).unwrap_or(-1);
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&sql)?;
+        let rows = statement.query_map([limit], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<Entry>>>()?)
+    }
+
+    fn count(&self) -> Result<usize> {
+        let sql = format!("SELECT COUNT(*) FROM \"{}\"", self.name);
+        let count: i64 = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(&sql, [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_backends_without_a_path_fall_back_to_memory() {
+        let path = Some(Path::new("server.db"));
+        assert_eq!(choose(None, None), Ok((Backend::Memory, None)));
+        assert_eq!(choose(None, path), Ok((Backend::Sled, None)));
+        assert_eq!(choose(Some(Backend::Sled), path), Ok((Backend::Sled, None)));
+        assert_eq!(
+            choose(Some(Backend::Memory), None),
+            Ok((Backend::Memory, None))
+        );
+        assert!(choose(Some(Backend::Memory), path).is_err());
+        let (backend, warning) = choose(Some(Backend::Sled), None).unwrap();
+        assert_eq!(backend, Backend::Memory);
+        assert!(
+            warning
+                .unwrap()
+                .contains("--storage sled needs a --database")
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Signed, short-lived connect tickets, in the style of netcode.io's connect
+//! tokens.
+//!
+//! A matchmaker that shares a secret key with the game server hands each
+//! player a ticket. The client presents it in its Hello (`ticket=<ticket>`)
+//! and the server only starts a session for a ticket it can verify, so a
+//! random host that finds the port can't connect. A ticket is
+//!
+//! ```text
+//! <expires>.<client id>.<signature>
+//! ```
+//!
+//! with the expiry in Unix seconds, a random client id in hex, and an
+//! HMAC-SHA256 over the first two fields as the signature. Nothing in it is
+//! secret; the client never needs the key, only [`expires_at`] to know when
+//! to ask for a new one.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// How long a ticket is good for, unless the matchmaker says otherwise.
+/// Only the handshake needs it; a session outlives its ticket.
+pub const DEFAULT_TTL_SECS: u64 = 30;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A verified ticket.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ticket {
+    pub client_id: u64,
+    /// Unix seconds
+    pub expires: u64,
+}
+
+/// Why a ticket was refused. [`Self::code`] is what a Reject carries.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum TicketError {
+    #[error("no ticket")]
+    Missing,
+    #[error("malformed ticket")]
+    Malformed,
+    #[error("ticket not signed by our matchmaker")]
+    BadSignature,
+    #[error("ticket expired")]
+    Expired,
+    /// Already used by another address
+    #[error("ticket already used")]
+    Reused,
+}
+
+impl TicketError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TicketError::Missing => "missing",
+            TicketError::Malformed => "malformed",
+            TicketError::BadSignature => "bad_signature",
+            TicketError::Expired => "expired",
+            TicketError::Reused => "reused",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Some(match code {
+            "missing" => TicketError::Missing,
+            "malformed" => TicketError::Malformed,
+            "bad_signature" => TicketError::BadSignature,
+            "expired" => TicketError::Expired,
+            "reused" => TicketError::Reused,
+            _ => return None,
+        })
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+pub fn issue(key: &[u8], client_id: u64, expires: u64) -> String {
+    let fields = format!("{}.{:016x}", expires, client_id);
+    let signature = mac(key, &fields).finalize().into_bytes();
+    format!("{}.{}", fields, hex(&signature))
+}
+
+pub fn verify(key: &[u8], ticket: &str, now: u64) -> Result<Ticket, TicketError> {
+    let (fields, signature) = ticket.rsplit_once('.').ok_or(TicketError::Malformed)?;
+    let (expires, client_id) = fields.split_once('.').ok_or(TicketError::Malformed)?;
+    let expires: u64 = expires.parse().map_err(|_| TicketError::Malformed)?;
+    let client_id = u64::from_str_radix(client_id, 16).map_err(|_| TicketError::Malformed)?;
+    let signature = unhex(signature).ok_or(TicketError::BadSignature)?;
+    // verify_slice compares in constant time, so timing doesn't give away
+    // how much matched
+    mac(key, fields)
+        .verify_slice(&signature)
+        .map_err(|_| TicketError::BadSignature)?;
+    if expires <= now {
+        return Err(TicketError::Expired);
+    }
+    Ok(Ticket { client_id, expires })
+}
+
+/// When a ticket runs out, read without checking the signature.
+pub fn expires_at(ticket: &str) -> Option<u64> {
+    ticket.split('.').next()?.parse().ok()
+}
+
+fn mac(key: &[u8], fields: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(fields.as_bytes());
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"matchmaker secret";
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        let signature = mac(b"Jefe", "what do ya want for nothing?")
+            .finalize()
+            .into_bytes();
+        assert_eq!(
+            hex(&signature),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn verifies_what_it_issued() {
+        let ticket = issue(KEY, 0xfeed, 100);
+        assert_eq!(
+            verify(KEY, &ticket, 99),
+            Ok(Ticket {
+                client_id: 0xfeed,
+                expires: 100
+            })
+        );
+        assert_eq!(expires_at(&ticket), Some(100));
+        assert_eq!(verify(KEY, &ticket, 100), Err(TicketError::Expired));
+    }
+
+    #[test]
+    fn refuses_other_keys_and_edits() {
+        let ticket = issue(KEY, 0xfeed, 100);
+        assert_eq!(
+            verify(b"another key", &ticket, 0),
+            Err(TicketError::BadSignature)
+        );
+        let later = ticket.replacen("100.", "200.", 1);
+        assert_eq!(verify(KEY, &later, 0), Err(TicketError::BadSignature));
+        let (fields, _) = ticket.rsplit_once('.').unwrap();
+        for signature in ["", "abc", "zz", &"00".repeat(32)] {
+            let forged = format!("{}.{}", fields, signature);
+            assert_eq!(verify(KEY, &forged, 0), Err(TicketError::BadSignature));
+        }
+        assert_eq!(verify(KEY, "100", 0), Err(TicketError::Malformed));
+        assert_eq!(verify(KEY, "x.feed.00", 0), Err(TicketError::Malformed));
+    }
+}
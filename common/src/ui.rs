@@ -0,0 +1,9 @@
+//! UI building blocks Bevy doesn't ship with.
+
+pub mod accessibility;
+pub mod clipboard;
+pub mod fonts;
+pub mod screen_flash;
+pub mod slider;
+pub mod text_input;
+pub mod widgets;
@@ -0,0 +1,248 @@
+//! Keyboard-only operation and screen-reader labels.
+//!
+//! [`KeyboardNavigationPlugin`] makes every visible button and
+//! [`TextInput`] reachable without a mouse:
+//!
+//! - Tab moves the focus to the next one in reading order (top to bottom,
+//!   then left to right), Shift+Tab to the previous one
+//! - Enter or Space on a focused button presses it, exactly as a click
+//!   would, so every button system works unchanged
+//! - A focused text input takes typing and Enter as usual
+//!
+//! The focus is Bevy's [`Focus`] resource, which is also what AccessKit
+//! reports as focused, and it follows clicks too. The focused widget gets
+//! an outline.
+//!
+//! Buttons already get a screen-reader name from their label (Bevy's UI
+//! does that). On top of that, a text input is exposed as one, named after
+//! its placeholder and carrying its value, and a text marked
+//! [`LiveStatus`] is exposed as a status that is announced whenever it
+//! changes, e.g. a connection status header.
+
+use bevy::a11y::accesskit::{Live, NodeBuilder, Role};
+use bevy::a11y::{AccessibilityNode, Focus};
+use bevy::prelude::*;
+use bevy::ui::UiSystem;
+
+use crate::ui::text_input::TextInput;
+
+const FOCUS_RING: Color = Color::rgb(1.0, 0.85, 0.3);
+/// Widgets whose centers are closer than this vertically are on one row
+/// for Tab's order
+const ROW_HEIGHT: f32 = 20.0;
+
+pub struct KeyboardNavigationPlugin;
+
+impl Plugin for KeyboardNavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Focus>()
+            .init_resource::<KeyPresses>()
+            .add_systems(
+                PreUpdate,
+                (
+                    release_key_presses.before(UiSystem::Focus),
+                    press_focused_button.after(UiSystem::Focus),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    follow_clicks,
+                    move_focus,
+                    show_focus,
+                    describe_text_inputs,
+                    describe_live_status,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A text a screen reader announces whenever it changes.
+#[derive(Component)]
+pub struct LiveStatus;
+
+/// Buttons pressed from the keyboard this frame, released at the start of
+/// the next one like a click is
+#[derive(Resource, Default)]
+struct KeyPresses(Vec<Entity>);
+
+fn release_key_presses(
+    mut presses: ResMut<KeyPresses>,
+    mut buttons: Query<&mut Interaction, With<Button>>,
+) {
+    for entity in presses.0.drain(..) {
+        if let Ok(mut interaction) = buttons.get_mut(entity) {
+            interaction.set_if_neq(Interaction::None);
+        }
+    }
+}
+
+fn press_focused_button(
+    keys: Res<ButtonInput<KeyCode>>,
+    focus: Res<Focus>,
+    mut presses: ResMut<KeyPresses>,
+    mut buttons: Query<&mut Interaction, With<Button>>,
+) {
+    if !keys.any_just_pressed([KeyCode::Enter, KeyCode::NumpadEnter, KeyCode::Space]) {
+        return;
+    }
+    let Some(entity) = focus.0 else {
+        return;
+    };
+    if let Ok(mut interaction) = buttons.get_mut(entity) {
+        *interaction = Interaction::Pressed;
+        presses.0.push(entity);
+    }
+}
+
+type ClickedButtons<'w, 's> =
+    Query<'w, 's, (Entity, &'static Interaction), (Changed<Interaction>, With<Button>)>;
+
+/// A clicked button or text input takes the focus.
+fn follow_clicks(
+    mut focus: ResMut<Focus>,
+    buttons: ClickedButtons,
+    inputs: Query<(Entity, &TextInput), Changed<TextInput>>,
+) {
+    let clicked = buttons
+        .iter()
+        .find(|(_, interaction)| **interaction == Interaction::Pressed)
+        .map(|(entity, _)| entity);
+    let typing = inputs
+        .iter()
+        .find(|(_, input)| input.focused)
+        .map(|(entity, _)| entity);
+    if let Some(entity) = typing.or(clicked).filter(|entity| focus.0 != Some(*entity)) {
+        focus.0 = Some(entity);
+    }
+}
+
+/// Everything Tab moves the focus between
+type Focusable<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Node,
+        &'static GlobalTransform,
+        &'static InheritedVisibility,
+    ),
+    Or<(With<Button>, With<TextInput>)>,
+>;
+
+fn move_focus(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut focus: ResMut<Focus>,
+    widgets: Focusable,
+    mut inputs: Query<(Entity, &mut TextInput)>,
+) {
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    // A hidden menu's items have no size
+    let mut order: Vec<(Entity, Vec2)> = widgets
+        .iter()
+        .filter(|(_, node, _, visibility)| visibility.get() && node.size() != Vec2::ZERO)
+        .map(|(entity, _, transform, _)| (entity, transform.translation().truncate()))
+        .collect();
+    if order.is_empty() {
+        return;
+    }
+    // UI y grows downward
+    order.sort_by(|(_, a), (_, b)| {
+        let row = |at: &Vec2| (at.y / ROW_HEIGHT).round();
+        row(a).total_cmp(&row(b)).then(a.x.total_cmp(&b.x))
+    });
+
+    let back = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let current = focus
+        .0
+        .and_then(|focused| order.iter().position(|(entity, _)| *entity == focused));
+    let next = match (current, back) {
+        (Some(index), false) => (index + 1) % order.len(),
+        (Some(index), true) => (index + order.len() - 1) % order.len(),
+        (None, false) => 0,
+        (None, true) => order.len() - 1,
+    };
+    let (entity, _) = order[next];
+    focus.0 = Some(entity);
+    for (input_entity, mut input) in inputs.iter_mut() {
+        let focused = input_entity == entity;
+        if input.focused != focused {
+            input.focused = focused;
+        }
+    }
+}
+
+fn show_focus(
+    mut commands: Commands,
+    focus: Res<Focus>,
+    nodes: Query<(), With<Node>>,
+    mut shown: Local<Option<Entity>>,
+) {
+    if focus.0 == *shown {
+        return;
+    }
+    // Either may have been despawned, e.g. with a closed dialog
+    if let Some(previous) = shown.filter(|entity| nodes.contains(*entity)) {
+        commands.entity(previous).remove::<Outline>();
+    }
+    *shown = focus.0;
+    if let Some(focused) = focus.0.filter(|entity| nodes.contains(*entity)) {
+        commands
+            .entity(focused)
+            .insert(Outline::new(Val::Px(2.0), Val::Px(2.0), FOCUS_RING));
+    }
+}
+
+fn describe_text_inputs(
+    mut commands: Commands,
+    mut inputs: Query<(Entity, &TextInput, Option<&mut AccessibilityNode>), Changed<TextInput>>,
+) {
+    for (entity, input, node) in inputs.iter_mut() {
+        match node {
+            Some(mut node) => node.set_value(input.value.clone()),
+            None => {
+                let mut node = NodeBuilder::new(Role::TextInput);
+                node.set_name(input.placeholder.clone());
+                node.set_value(input.value.clone());
+                commands
+                    .entity(entity)
+                    .insert(AccessibilityNode::from(node));
+            }
+        }
+    }
+}
+
+type ChangedStatuses<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Text,
+        Option<&'static mut AccessibilityNode>,
+    ),
+    (With<LiveStatus>, Changed<Text>),
+>;
+
+fn describe_live_status(mut commands: Commands, mut statuses: ChangedStatuses) {
+    for (entity, text, node) in statuses.iter_mut() {
+        let shown: String = text
+            .sections
+            .iter()
+            .map(|section| section.value.as_str())
+            .collect();
+        match node {
+            Some(mut node) => node.set_name(shown),
+            None => {
+                let mut node = NodeBuilder::new(Role::Status);
+                node.set_live(Live::Polite);
+                node.set_name(shown);
+                commands
+                    .entity(entity)
+                    .insert(AccessibilityNode::from(node));
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! The OS clipboard, for Ctrl+V in text inputs.
+//!
+//! [`ClipboardPlugin`] answers every [`TextInputPasteRequested`] with the
+//! clipboard's text, read with the system's own tool rather than a
+//! windowing library:
+//!
+//! - Windows: `powershell Get-Clipboard`
+//! - macOS: `pbpaste`
+//! - Linux and the BSDs: `wl-paste` on Wayland, then `xclip`, then `xsel`
+//!
+//! The tool runs on a thread of its own, so one that is slow to answer
+//! never stalls a frame; the text lands in the field a frame or two after
+//! the keypress. When none of the tools works, the paste is dropped with a
+//! warning naming what was tried.
+
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::ui::text_input::{TextInputPaste, TextInputPasteRequested};
+
+pub struct ClipboardPlugin;
+
+impl Plugin for ClipboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Pastes>()
+            .add_systems(Update, (read_for_paste_requests, deliver_pastes));
+    }
+}
+
+/// Clipboard reads that have finished, waiting for the next frame
+#[derive(Resource, Default)]
+struct Pastes(Arc<Mutex<VecDeque<TextInputPaste>>>);
+
+fn read_for_paste_requests(
+    mut requests: EventReader<TextInputPasteRequested>,
+    pastes: Res<Pastes>,
+) {
+    for request in requests.read() {
+        let entity = request.entity;
+        let pastes = pastes.0.clone();
+        thread::spawn(move || match read() {
+            Ok(text) => pastes
+                .lock()
+                .unwrap()
+                .push_back(TextInputPaste { entity, text }),
+            Err(reason) => warn!("Paste failed: {}", reason),
+        });
+    }
+}
+
+fn deliver_pastes(pastes: Res<Pastes>, mut delivered: EventWriter<TextInputPaste>) {
+    delivered.send_batch(pastes.0.lock().unwrap().drain(..));
+}
+
+/// The tools to try, in order
+fn commands() -> Vec<(&'static str, &'static [&'static str])> {
+    let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+    if cfg!(windows) {
+        commands.push(("powershell", &["-NoProfile", "-Command", "Get-Clipboard"]));
+    } else if cfg!(target_os = "macos") {
+        commands.push(("pbpaste", &[]));
+    } else {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(("wl-paste", &["--no-newline"]));
+        }
+        commands.push(("xclip", &["-o", "-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--output"]));
+    }
+    commands
+}
+
+/// The clipboard's text, from the first tool that works. Blocks until the
+/// tool exits.
+pub fn read() -> Result<String, String> {
+    let mut failures = Vec::new();
+    for (program, args) in commands() {
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+            }
+            Ok(output) => failures.push(format!("{} exited with {}", program, output.status)),
+            Err(e) => failures.push(format!("could not run {}: {}", program, e)),
+        }
+    }
+    Err(failures.join(", "))
+}
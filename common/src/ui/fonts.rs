@@ -0,0 +1,116 @@
+//! Font fallback chain.
+//!
+//! Bevy's built-in font only covers Latin text, so anything else a peer sends
+//! (CJK, emoji) renders as boxes. [`FontsPlugin`] loads a list of font files
+//! and, whenever a text section changes, switches it to the first font in the
+//! chain that has a glyph for every character in it. Bevy 0.13 renders a
+//! section with a single font, so a line mixing scripts gets the font that
+//! covers the most of it.
+//!
+//! Only sections using the default font (or a font from the chain) are
+//! touched; text that picked a font explicitly keeps it.
+
+use ab_glyph::Font as _;
+use bevy::prelude::*;
+
+/// The fonts shipped in `assets/fonts`: Latin, then CJK, then emoji.
+pub fn default_fonts() -> Vec<String> {
+    [
+        "assets/fonts/NotoSans-Regular.ttf",
+        "assets/fonts/NotoSansSC-Regular.otf",
+        "assets/fonts/NotoEmoji-Regular.ttf",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Font files to try, in order; missing or unreadable ones are skipped with a
+/// warning.
+pub struct FontsPlugin {
+    pub paths: Vec<String>,
+}
+
+/// Loaded fonts in fallback order, ending with Bevy's default font.
+#[derive(Resource, Default)]
+pub struct FontChain {
+    paths: Vec<String>,
+    pub fonts: Vec<Handle<Font>>,
+}
+
+impl Plugin for FontsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FontChain {
+            paths: self.paths.clone(),
+            fonts: Vec::new(),
+        })
+        .add_systems(Startup, load_font_chain)
+        .add_systems(PostUpdate, apply_font_fallback);
+    }
+}
+
+fn load_font_chain(mut chain: ResMut<FontChain>, mut fonts: ResMut<Assets<Font>>) {
+    for path in chain.paths.clone() {
+        let font = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| Font::try_from_bytes(bytes).map_err(|e| e.to_string()));
+        match font {
+            Ok(font) => {
+                info!("Loaded font {}", path);
+                let handle = fonts.add(font);
+                chain.fonts.push(handle);
+            }
+            Err(e) => warn!("Skipping font {}: {}", path, e),
+        }
+    }
+    chain.fonts.push(Handle::default());
+}
+
+/// Picks the first font covering every character in `text`, or failing that
+/// the one covering the most.
+fn pick_font<'a>(
+    chain: &'a FontChain,
+    fonts: &Assets<Font>,
+    text: &str,
+) -> Option<&'a Handle<Font>> {
+    let mut best: Option<(&Handle<Font>, usize)> = None;
+    let needed = text.chars().filter(|c| !c.is_whitespace()).count();
+    for handle in &chain.fonts {
+        let Some(font) = fonts.get(handle) else {
+            continue;
+        };
+        let covered = text
+            .chars()
+            .filter(|c| !c.is_whitespace() && font.font.glyph_id(*c).0 != 0)
+            .count();
+        if covered == needed {
+            return Some(handle);
+        }
+        if best.is_none_or(|(_, most)| covered > most) {
+            best = Some((handle, covered));
+        }
+    }
+    best.map(|(handle, _)| handle)
+}
+
+fn apply_font_fallback(
+    chain: Res<FontChain>,
+    fonts: Res<Assets<Font>>,
+    mut texts: Query<&mut Text, Changed<Text>>,
+) {
+    for mut text in texts.iter_mut() {
+        for index in 0..text.sections.len() {
+            let section = &text.sections[index];
+            if !chain.fonts.contains(&section.style.font) {
+                continue;
+            }
+            let Some(font) = pick_font(&chain, &fonts, &section.value) else {
+                continue;
+            };
+            // Only write when it changes, or Changed<Text> fires every frame
+            if *font != section.style.font {
+                let font = font.clone();
+                text.sections[index].style.font = font;
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! A flash over the whole window.
+//!
+//! Send a [`FlashScreen`] and the window is tinted with its color, fading
+//! out over [`FLASH_SECS`]; a flash during a flash starts it over. The
+//! overlay sits above all other UI and lets clicks through.
+
+use bevy::prelude::*;
+
+pub const FLASH_SECS: f32 = 0.4;
+
+#[derive(Event, Clone, Copy)]
+pub struct FlashScreen {
+    /// The alpha is how strong the flash starts
+    pub color: Color,
+}
+
+#[derive(Component)]
+struct FlashOverlay {
+    color: Color,
+    /// Seconds left in the current flash
+    remaining: f32,
+}
+
+pub struct ScreenFlashPlugin;
+
+impl Plugin for ScreenFlashPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FlashScreen>()
+            .add_systems(Startup, spawn_overlay)
+            .add_systems(Update, update_overlay);
+    }
+}
+
+fn spawn_overlay(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            background_color: Color::NONE.into(),
+            z_index: ZIndex::Global(100),
+            ..default()
+        },
+        FlashOverlay {
+            color: Color::NONE,
+            remaining: 0.0,
+        },
+    ));
+}
+
+fn update_overlay(
+    time: Res<Time>,
+    mut flashes: EventReader<FlashScreen>,
+    mut overlays: Query<(&mut FlashOverlay, &mut BackgroundColor)>,
+) {
+    let flash = flashes.read().last().copied();
+    for (mut overlay, mut background) in overlays.iter_mut() {
+        if let Some(flash) = flash {
+            overlay.color = flash.color;
+            overlay.remaining = FLASH_SECS;
+        } else if overlay.remaining <= 0.0 {
+            continue;
+        }
+        overlay.remaining = (overlay.remaining - time.delta_seconds()).max(0.0);
+        let strength = overlay.remaining / FLASH_SECS;
+        *background = overlay.color.with_a(overlay.color.a() * strength).into();
+    }
+}
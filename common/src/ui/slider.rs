@@ -0,0 +1,210 @@
+//! Horizontal slider widget.
+//!
+//! Bevy UI has no slider either: press on the track and drag to pick a
+//! value between [`Slider::min`] and [`Slider::max`], snapped to
+//! [`Slider::step`]. The label above the track shows the value. Each change
+//! made by dragging sends a [`SliderChanged`] event; setting
+//! [`Slider::value`] from code moves the fill without one.
+
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+
+const TRACK: Color = Color::rgb(0.12, 0.12, 0.12);
+const BORDER: Color = Color::rgb(0.4, 0.4, 0.4);
+const FILL: Color = Color::rgb(0.3, 0.5, 0.9);
+
+#[derive(Component)]
+pub struct Slider {
+    pub label: String,
+    pub min: f32,
+    pub max: f32,
+    /// Values are `min` plus a multiple of this
+    pub step: f32,
+    pub value: f32,
+}
+
+impl Slider {
+    pub fn new(label: impl Into<String>, min: f32, max: f32, step: f32) -> Self {
+        Self {
+            label: label.into(),
+            min,
+            max,
+            step,
+            value: min,
+        }
+    }
+
+    pub fn with_value(mut self, value: f32) -> Self {
+        self.value = self.snap(value);
+        self
+    }
+
+    fn snap(&self, value: f32) -> f32 {
+        let steps = ((value - self.min) / self.step).round();
+        (self.min + steps * self.step).clamp(self.min, self.max)
+    }
+
+    /// How far along the track the value is, 0..=1
+    fn fraction(&self) -> f32 {
+        if self.max > self.min {
+            (self.value - self.min) / (self.max - self.min)
+        } else {
+            0.0
+        }
+    }
+
+    fn text(&self) -> String {
+        if self.step.fract() == 0.0 {
+            format!("{}: {:.0}", self.label, self.value)
+        } else {
+            format!("{}: {:.2}", self.label, self.value)
+        }
+    }
+}
+
+/// The clickable bar of a [`Slider`]; a child of it.
+#[derive(Component)]
+pub struct SliderTrack;
+
+/// The filled part of a [`SliderTrack`].
+#[derive(Component)]
+pub struct SliderFill;
+
+/// Shows a [`Slider`]'s label and value.
+#[derive(Component)]
+pub struct SliderLabel;
+
+#[derive(Event)]
+pub struct SliderChanged {
+    pub entity: Entity,
+    pub value: f32,
+}
+
+pub struct SliderPlugin;
+
+impl Plugin for SliderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SliderChanged>()
+            .add_systems(Update, (drag_sliders, render_sliders).chain());
+    }
+}
+
+/// Spawns a slider as a child of `parent`, with `extra` (usually a marker
+/// component) on the slider entity, and returns that entity.
+pub fn spawn_slider(
+    parent: &mut ChildBuilder,
+    slider: Slider,
+    width: Val,
+    extra: impl Bundle,
+) -> Entity {
+    let label = slider.text();
+    let fraction = slider.fraction();
+    parent
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width,
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            },
+            slider,
+            extra,
+        ))
+        .with_children(|column| {
+            column.spawn((
+                TextBundle::from_section(
+                    label,
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                SliderLabel,
+            ));
+            column
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.0),
+                            height: Val::Px(14.0),
+                            border: UiRect::all(Val::Px(1.0)),
+                            ..default()
+                        },
+                        background_color: TRACK.into(),
+                        border_color: BORDER.into(),
+                        ..default()
+                    },
+                    Interaction::default(),
+                    RelativeCursorPosition::default(),
+                    SliderTrack,
+                ))
+                .with_children(|track| {
+                    track.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(fraction * 100.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: FILL.into(),
+                            ..default()
+                        },
+                        SliderFill,
+                    ));
+                });
+        })
+        .id()
+}
+
+/// While the track is held, the value follows the cursor, even past
+/// either end.
+fn drag_sliders(
+    tracks: Query<(&Interaction, &RelativeCursorPosition, &Parent), With<SliderTrack>>,
+    mut sliders: Query<(Entity, &mut Slider)>,
+    mut changed: EventWriter<SliderChanged>,
+) {
+    for (interaction, cursor, parent) in tracks.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(position) = cursor.normalized else {
+            continue;
+        };
+        let Ok((entity, mut slider)) = sliders.get_mut(parent.get()) else {
+            continue;
+        };
+        let picked = slider.min + position.x.clamp(0.0, 1.0) * (slider.max - slider.min);
+        let value = slider.snap(picked);
+        if value != slider.value {
+            slider.value = value;
+            changed.send(SliderChanged { entity, value });
+        }
+    }
+}
+
+fn render_sliders(
+    sliders: Query<(&Slider, &Children), Changed<Slider>>,
+    mut labels: Query<&mut Text, With<SliderLabel>>,
+    tracks: Query<&Children, With<SliderTrack>>,
+    mut fills: Query<&mut Style, With<SliderFill>>,
+) {
+    for (slider, children) in sliders.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = labels.get_mut(*child) {
+                text.sections[0].value = slider.text();
+            }
+            let Ok(track) = tracks.get(*child) else {
+                continue;
+            };
+            for fill in track.iter() {
+                if let Ok(mut style) = fills.get_mut(*fill) {
+                    style.width = Val::Percent(slider.fraction() * 100.0);
+                }
+            }
+        }
+    }
+}
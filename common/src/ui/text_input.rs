@@ -0,0 +1,287 @@
+//! Single-line text input widget.
+//!
+//! Bevy UI has no text field, so this is a small one: click to focus, type,
+//! move the cursor with the arrow keys/Home/End, delete with Backspace and
+//! Delete, and press Enter to submit. Submitting sends a
+//! [`TextInputSubmitted`] event; the app decides what the text means.
+//!
+//! Ctrl+V sends a [`TextInputPasteRequested`] event. Whatever owns the
+//! clipboard answers with [`TextInputPaste`], which inserts the text at the
+//! cursor; the widget itself never touches the OS. Add
+//! [`ClipboardPlugin`](crate::ui::clipboard::ClipboardPlugin) for the system
+//! clipboard.
+
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+
+const BACKGROUND: Color = Color::rgb(0.12, 0.12, 0.12);
+const BORDER_IDLE: Color = Color::rgb(0.4, 0.4, 0.4);
+const BORDER_FOCUSED: Color = Color::rgb(0.3, 0.5, 0.9);
+const PLACEHOLDER: Color = Color::rgb(0.5, 0.5, 0.5);
+
+#[derive(Component, Default)]
+pub struct TextInput {
+    pub value: String,
+    /// Cursor position in characters, not bytes
+    pub cursor: usize,
+    pub focused: bool,
+    pub placeholder: String,
+    /// Clear the field after Enter (chat, console) or keep it (address entry)
+    pub clear_on_submit: bool,
+}
+
+impl TextInput {
+    pub fn new(placeholder: impl Into<String>) -> Self {
+        Self {
+            placeholder: placeholder.into(),
+            ..default()
+        }
+    }
+
+    pub fn with_value(mut self, value: impl Into<String>) -> Self {
+        self.value = value.into();
+        self.cursor = self.value.chars().count();
+        self
+    }
+
+    pub fn clear_on_submit(mut self) -> Self {
+        self.clear_on_submit = true;
+        self
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.value
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.value.len(), |(index, _)| index)
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        let index = self.byte_index(self.cursor);
+        self.value.insert_str(index, text);
+        self.cursor += text.chars().count();
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let index = self.byte_index(self.cursor);
+            self.value.remove(index);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.value.chars().count() {
+            let index = self.byte_index(self.cursor);
+            self.value.remove(index);
+        }
+    }
+}
+
+/// Marks the text entity that displays a [`TextInput`]'s contents.
+#[derive(Component)]
+pub struct TextInputText;
+
+#[derive(Event)]
+pub struct TextInputSubmitted {
+    pub entity: Entity,
+    pub value: String,
+}
+
+#[derive(Event)]
+pub struct TextInputPasteRequested {
+    pub entity: Entity,
+}
+
+#[derive(Event)]
+pub struct TextInputPaste {
+    pub entity: Entity,
+    pub text: String,
+}
+
+pub struct TextInputPlugin;
+
+impl Plugin for TextInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TextInputSubmitted>()
+            .add_event::<TextInputPasteRequested>()
+            .add_event::<TextInputPaste>()
+            .add_systems(
+                Update,
+                (
+                    focus_text_inputs,
+                    type_into_text_inputs,
+                    paste_into_text_inputs,
+                    render_text_inputs,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Spawns a text input as a child of `parent`, with `extra` (usually a
+/// marker component) on the field entity, and returns that entity.
+pub fn spawn_text_input(
+    parent: &mut ChildBuilder,
+    input: TextInput,
+    width: Val,
+    extra: impl Bundle,
+) -> Entity {
+    parent
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width,
+                    height: Val::Px(28.0),
+                    padding: UiRect::horizontal(Val::Px(6.0)),
+                    border: UiRect::all(Val::Px(2.0)),
+                    align_items: AlignItems::Center,
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: BACKGROUND.into(),
+                border_color: BORDER_IDLE.into(),
+                ..default()
+            },
+            Interaction::default(),
+            input,
+            extra,
+        ))
+        .with_children(|field| {
+            field.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                TextInputText,
+            ));
+        })
+        .id()
+}
+
+/// A click focuses the field under the cursor and unfocuses every other one.
+fn focus_text_inputs(
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut inputs: Query<(&Interaction, &mut TextInput)>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    for (interaction, mut input) in inputs.iter_mut() {
+        let focused = *interaction == Interaction::Pressed;
+        if input.focused != focused {
+            input.focused = focused;
+        }
+    }
+}
+
+fn type_into_text_inputs(
+    mut key_events: EventReader<KeyboardInput>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut inputs: Query<(Entity, &mut TextInput)>,
+    mut submitted: EventWriter<TextInputSubmitted>,
+    mut paste_requests: EventWriter<TextInputPasteRequested>,
+) {
+    let control = keys.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::SuperLeft,
+        KeyCode::SuperRight,
+    ]);
+
+    // KeyboardInput includes OS key repeats, so holding Backspace works
+    for event in key_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        let Some((entity, mut input)) = inputs.iter_mut().find(|(_, input)| input.focused) else {
+            continue;
+        };
+
+        match &event.logical_key {
+            Key::Enter => {
+                submitted.send(TextInputSubmitted {
+                    entity,
+                    value: input.value.clone(),
+                });
+                if input.clear_on_submit {
+                    input.value.clear();
+                    input.cursor = 0;
+                }
+            }
+            Key::Backspace => input.backspace(),
+            Key::Delete => input.delete(),
+            Key::ArrowLeft => input.cursor = input.cursor.saturating_sub(1),
+            Key::ArrowRight => {
+                input.cursor = (input.cursor + 1).min(input.value.chars().count());
+            }
+            Key::Home => input.cursor = 0,
+            Key::End => input.cursor = input.value.chars().count(),
+            Key::Escape => input.focused = false,
+            // Some platforms report Ctrl+V as the raw SYN control character
+            Key::Character(text)
+                if control && (text.eq_ignore_ascii_case("v") || text.as_str() == "\u{16}") =>
+            {
+                paste_requests.send(TextInputPasteRequested { entity });
+            }
+            Key::Character(_) if control => {}
+            Key::Character(text) if !text.chars().any(char::is_control) => input.insert(text),
+            Key::Space => input.insert(" "),
+            _ => {}
+        }
+    }
+}
+
+fn paste_into_text_inputs(
+    mut pastes: EventReader<TextInputPaste>,
+    mut inputs: Query<&mut TextInput>,
+) {
+    for paste in pastes.read() {
+        if let Ok(mut input) = inputs.get_mut(paste.entity) {
+            // Single-line field: newlines from the clipboard become spaces
+            let text: String = paste
+                .text
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c })
+                .collect();
+            input.insert(text.trim_end());
+        }
+    }
+}
+
+fn render_text_inputs(
+    mut inputs: Query<(&TextInput, &Children, &mut BorderColor), Changed<TextInput>>,
+    mut texts: Query<&mut Text, With<TextInputText>>,
+) {
+    for (input, children, mut border) in inputs.iter_mut() {
+        *border = if input.focused {
+            BORDER_FOCUSED
+        } else {
+            BORDER_IDLE
+        }
+        .into();
+
+        for child in children.iter() {
+            let Ok(mut text) = texts.get_mut(*child) else {
+                continue;
+            };
+            let section = &mut text.sections[0];
+            if input.value.is_empty() && !input.focused {
+                section.value = input.placeholder.clone();
+                section.style.color = PLACEHOLDER;
+            } else {
+                let mut shown = input.value.clone();
+                if input.focused {
+                    shown.insert(input.byte_index(input.cursor), '|');
+                }
+                section.value = shown;
+                section.style.color = Color::WHITE;
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Header, log and button widgets shared by every binary's `setup_ui`, styled
+//! from a [`UiTheme`] resource.
+//!
+//! Buttons spawned with [`spawn_button`] get hover and pressed colors from the
+//! theme; nothing else needs to be wired up beyond adding [`WidgetsPlugin`].
+//! Call [`ButtonFlash::trigger`] when the click actually did something (the
+//! packet went out, the reply came back) and the button flashes and fades
+//! back to its normal color. A button with [`ButtonDisabled`] is drawn in
+//! the theme's disabled color and ignores hovering; the app still has to
+//! ignore its presses.
+
+use bevy::prelude::*;
+
+/// Colors, font sizes and spacing for the shared widgets. Insert your own
+/// before adding [`WidgetsPlugin`] to restyle an app.
+#[derive(Resource, Clone)]
+pub struct UiTheme {
+    pub header_color: Color,
+    pub text_color: Color,
+    pub button_color: Color,
+    pub button_hovered: Color,
+    pub button_pressed: Color,
+    /// Background of a button with [`ButtonDisabled`]
+    pub button_disabled: Color,
+    /// Color a button flashes to on [`ButtonFlash::trigger`]
+    pub button_flash: Color,
+    /// Seconds the flash takes to fade out
+    pub flash_secs: f32,
+    pub header_font_size: f32,
+    pub log_font_size: f32,
+    pub button_font_size: f32,
+    /// Distance from the window edge to the header and log
+    pub padding: f32,
+    /// Distance from the window edge to the action button
+    pub button_margin: f32,
+    pub button_height: f32,
+}
+
+impl Default for UiTheme {
+    fn default() -> Self {
+        Self {
+            header_color: Color::rgb(0.9, 0.9, 0.9),
+            text_color: Color::WHITE,
+            button_color: Color::rgb(0.3, 0.5, 0.9),
+            button_hovered: Color::rgb(0.4, 0.6, 1.0),
+            button_pressed: Color::rgb(0.2, 0.35, 0.7),
+            button_disabled: Color::rgb(0.3, 0.3, 0.35),
+            button_flash: Color::rgb(0.3, 0.85, 0.45),
+            flash_secs: 0.3,
+            header_font_size: 20.0,
+            log_font_size: 16.0,
+            button_font_size: 24.0,
+            padding: 10.0,
+            button_margin: 20.0,
+            button_height: 50.0,
+        }
+    }
+}
+
+/// Marks buttons whose background follows the theme's hover/pressed colors.
+#[derive(Component)]
+pub struct ThemedButton;
+
+/// Greys a themed button out until removed.
+#[derive(Component)]
+pub struct ButtonDisabled;
+
+/// Success flash on a themed button.
+#[derive(Component, Default)]
+pub struct ButtonFlash {
+    triggered: bool,
+    /// Seconds left in the current flash
+    remaining: f32,
+}
+
+impl ButtonFlash {
+    /// Flashes the button, restarting any flash in progress.
+    pub fn trigger(&mut self) {
+        self.triggered = true;
+    }
+}
+
+pub struct WidgetsPlugin;
+
+impl Plugin for WidgetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UiTheme>()
+            .add_systems(Update, update_button_colors);
+    }
+}
+
+/// The status line in the top-left corner.
+pub fn header(theme: &UiTheme, text: impl Into<String>) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font_size: theme.header_font_size,
+            color: theme.header_color,
+            ..default()
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Px(theme.padding),
+        left: Val::Px(theme.padding),
+        ..default()
+    })
+}
+
+/// The message log under the header.
+pub fn log_text(theme: &UiTheme, text: impl Into<String>) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font_size: theme.log_font_size,
+            color: theme.text_color,
+            ..default()
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Px(theme.padding + 40.0),
+        left: Val::Px(theme.padding),
+        ..default()
+    })
+}
+
+/// Spawns the action button in the bottom-right corner with `extra` (usually a
+/// marker component) on the button entity, and returns that entity.
+pub fn spawn_button(
+    commands: &mut Commands,
+    theme: &UiTheme,
+    label: &str,
+    width: f32,
+    extra: impl Bundle,
+) -> Entity {
+    commands
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(width),
+                    height: Val::Px(theme.button_height),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(theme.button_margin),
+                    right: Val::Px(theme.button_margin),
+                    ..default()
+                },
+                background_color: theme.button_color.into(),
+                ..default()
+            },
+            ThemedButton,
+            ButtonFlash::default(),
+            extra,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font_size: theme.button_font_size,
+                    color: theme.text_color,
+                    ..default()
+                },
+            ));
+        })
+        .id()
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    Color::rgba(
+        from.r() + (to.r() - from.r()) * t,
+        from.g() + (to.g() - from.g()) * t,
+        from.b() + (to.b() - from.b()) * t,
+        from.a() + (to.a() - from.a()) * t,
+    )
+}
+
+type ThemedButtons<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        Ref<'static, Interaction>,
+        &'static mut ButtonFlash,
+        &'static mut BackgroundColor,
+        Option<Ref<'static, ButtonDisabled>>,
+    ),
+    With<ThemedButton>,
+>;
+
+fn update_button_colors(
+    time: Res<Time>,
+    theme: Res<UiTheme>,
+    mut buttons: ThemedButtons,
+    mut enabled: RemovedComponents<ButtonDisabled>,
+) {
+    let enabled: Vec<Entity> = enabled.read().collect();
+    for (entity, interaction, mut flash, mut background, disabled) in buttons.iter_mut() {
+        if flash.triggered {
+            flash.triggered = false;
+            flash.remaining = theme.flash_secs;
+        }
+        let flashing = flash.remaining > 0.0;
+        let disabled_now = disabled
+            .as_ref()
+            .is_some_and(|disabled| disabled.is_added());
+        if !flashing && !interaction.is_changed() && !disabled_now && !enabled.contains(&entity) {
+            continue;
+        }
+
+        let base = match *interaction {
+            _ if disabled.is_some() => theme.button_disabled,
+            Interaction::Pressed => theme.button_pressed,
+            Interaction::Hovered => theme.button_hovered,
+            Interaction::None => theme.button_color,
+        };
+        if !flashing {
+            *background = base.into();
+            continue;
+        }
+
+        flash.remaining -= time.delta_seconds();
+        let faded = 1.0 - (flash.remaining / theme.flash_secs).max(0.0);
+        *background = lerp_color(theme.button_flash, base, faded).into();
+    }
+}
@@ -0,0 +1,265 @@
+//! Per-connection protocol state, checked where messages come in.
+//!
+//! A [`ProtocolRules`] says which messages a peer may send in each state of
+//! its connection and how what either side sends moves that state on. A
+//! [`ProtocolValidator`] keeps one state per peer address and holds every
+//! incoming message to the rules, so a Chat before the handshake or a reply
+//! to a question nobody asked is refused at the boundary instead of
+//! reaching code that assumes it can't happen.
+//!
+//! As a [`Middleware`] the validator drops what the rules refuse, logs it,
+//! and with [`ProtocolValidator::reporting_to`] raises a
+//! [`NetError::ProtocolViolation`] the app shows like any other error. It
+//! sees the app's own sends on the way out, so a server's Welcome is what
+//! opens a connection, and it should sit at an order where messages are
+//! plain text both ways (below compression). Apps without a pipeline call
+//! [`check`](ProtocolValidator::check) and
+//! [`note_sent`](ProtocolValidator::note_sent) themselves.
+//!
+//! A peer back in the default state is forgotten, so addresses that never
+//! get past it (scans, spoofed garbage) cost nothing to track.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{NetError, NetErrorSink};
+use crate::middleware::Middleware;
+use crate::session::message_kind;
+
+/// Where the server adds its validator: above the handshake half of
+/// signing, below the packet log, codecs and compression
+pub const VALIDATOR_ORDER: i32 = 3;
+
+pub trait ProtocolRules: Send + Sync + 'static {
+    /// Where a connection is; `Default` is a peer we know nothing about.
+    type State: Copy + Default + PartialEq + Send;
+
+    /// The state after the peer sent `message` in `state`, or why it may
+    /// not send that there.
+    fn received(&self, state: Self::State, message: &str) -> Result<Self::State, &'static str>;
+
+    /// The state after we sent `message` in `state`.
+    fn sent(&self, state: Self::State, _message: &str) -> Self::State {
+        state
+    }
+
+    /// Shown in violations, e.g. `handshaking`
+    fn label(&self, state: Self::State) -> &'static str;
+}
+
+/// A message the rules refused.
+#[derive(Debug)]
+pub struct Violation {
+    /// The first word of the message
+    pub kind: String,
+    pub state: &'static str,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} while {}: {}", self.kind, self.state, self.reason)
+    }
+}
+
+pub struct ProtocolValidator<R: ProtocolRules> {
+    rules: R,
+    states: Mutex<HashMap<String, R::State>>,
+    violations: AtomicU64,
+    errors: Option<NetErrorSink>,
+}
+
+impl<R: ProtocolRules> ProtocolValidator<R> {
+    pub fn new(rules: R) -> Self {
+        Self {
+            rules,
+            states: Mutex::new(HashMap::new()),
+            violations: AtomicU64::new(0),
+            errors: None,
+        }
+    }
+
+    /// Also raise each violation as a [`NetError`] through `errors`.
+    pub fn reporting_to(mut self, errors: NetErrorSink) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    /// Moves `peer` on past `message`, unless the rules refuse it there; a
+    /// refused message leaves the state as it was.
+    pub fn check(&self, peer: &str, message: &str) -> Result<(), Violation> {
+        let mut states = self.states.lock().unwrap();
+        let state = states.get(peer).copied().unwrap_or_default();
+        match self.rules.received(state, message) {
+            Ok(next) => {
+                set_state(&mut states, peer, next);
+                Ok(())
+            }
+            Err(reason) => {
+                self.violations.fetch_add(1, Ordering::Relaxed);
+                Err(Violation {
+                    kind: message_kind(message).to_string(),
+                    state: self.rules.label(state),
+                    reason,
+                })
+            }
+        }
+    }
+
+    pub fn note_sent(&self, peer: &str, message: &str) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.get(peer).copied().unwrap_or_default();
+        let next = self.rules.sent(state, message);
+        set_state(&mut states, peer, next);
+    }
+
+    /// Messages refused so far, from every peer
+    pub fn violations(&self) -> u64 {
+        self.violations.load(Ordering::Relaxed)
+    }
+}
+
+fn set_state<S: Default + PartialEq>(states: &mut HashMap<String, S>, peer: &str, state: S) {
+    if state == S::default() {
+        states.remove(peer);
+    } else if let Some(current) = states.get_mut(peer) {
+        *current = state;
+    } else {
+        states.insert(peer.to_string(), state);
+    }
+}
+
+impl<R: ProtocolRules> Middleware for ProtocolValidator<R> {
+    fn name(&self) -> &'static str {
+        "validator"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.note_sent(peer, &String::from_utf8_lossy(&bytes));
+        Some(bytes)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let Err(violation) = self.check(peer, &String::from_utf8_lossy(&bytes)) else {
+            return Some(bytes);
+        };
+        warn!("{} sent {}; dropped", peer, violation);
+        if let Some(errors) = &self.errors {
+            errors.push(NetError::ProtocolViolation {
+                addr: peer.to_string(),
+                violation,
+            });
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "127.0.0.1:50211";
+
+    /// Hello, then nothing until we Welcome, then anything until Bye.
+    struct Door;
+
+    #[derive(Clone, Copy, Default, PartialEq, Debug)]
+    enum Step {
+        #[default]
+        Outside,
+        Knocked,
+        Inside,
+    }
+
+    impl ProtocolRules for Door {
+        type State = Step;
+
+        fn received(&self, step: Step, message: &str) -> Result<Step, &'static str> {
+            match (step, message_kind(message)) {
+                (_, "Hello") => Ok(Step::Knocked),
+                (Step::Inside, "Bye") => Ok(Step::Outside),
+                (Step::Inside, _) => Ok(Step::Inside),
+                (Step::Knocked, _) => Err("not let in yet"),
+                (Step::Outside, _) => Err("knock first"),
+            }
+        }
+
+        fn sent(&self, step: Step, message: &str) -> Step {
+            match message_kind(message) {
+                "Welcome" => Step::Inside,
+                _ => step,
+            }
+        }
+
+        fn label(&self, step: Step) -> &'static str {
+            match step {
+                Step::Outside => "outside",
+                Step::Knocked => "knocked",
+                Step::Inside => "inside",
+            }
+        }
+    }
+
+    fn step(validator: &ProtocolValidator<Door>, peer: &str) -> Step {
+        let states = validator.states.lock().unwrap();
+        states.get(peer).copied().unwrap_or_default()
+    }
+
+    #[test]
+    fn accepts_what_the_rules_allow() {
+        let validator = ProtocolValidator::new(Door);
+        assert!(validator.check(PEER, "Hello version=2").is_ok());
+        validator.note_sent(PEER, "Welcome seed=1");
+        assert!(validator.check(PEER, "Chat hi").is_ok());
+        assert_eq!(step(&validator, PEER), Step::Inside);
+        assert_eq!(validator.violations(), 0);
+    }
+
+    #[test]
+    fn rejects_without_moving_the_peer_on() {
+        let validator = ProtocolValidator::new(Door);
+        let violation = validator.check(PEER, "Chat hi").unwrap_err();
+        assert_eq!(
+            (violation.kind.as_str(), violation.state, violation.reason),
+            ("Chat", "outside", "knock first")
+        );
+        validator.check(PEER, "Hello").unwrap();
+        let violation = validator.check(PEER, "Chat hi").unwrap_err();
+        assert_eq!(violation.state, "knocked");
+        assert_eq!(step(&validator, PEER), Step::Knocked);
+        assert_eq!(validator.violations(), 2);
+    }
+
+    #[test]
+    fn recovers_once_the_peer_follows_the_rules() {
+        let validator = ProtocolValidator::new(Door);
+        assert!(validator.check(PEER, "Chat hi").is_err());
+        validator.check(PEER, "Hello").unwrap();
+        validator.note_sent(PEER, "Welcome");
+        assert!(validator.check(PEER, "Chat hi").is_ok());
+        // Another peer is held to the rules on its own
+        assert!(validator.check("127.0.0.1:50212", "Chat hi").is_err());
+        // And one that leaves is forgotten
+        validator.check(PEER, "Bye").unwrap();
+        assert!(validator.states.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn drops_refused_datagrams_as_a_stage() {
+        let validator = ProtocolValidator::new(Door).reporting_to(NetErrorSink::default());
+        assert_eq!(validator.incoming(PEER, b"Chat hi".to_vec()), None);
+        assert!(validator.incoming(PEER, b"Hello".to_vec()).is_some());
+        assert_eq!(
+            validator.outgoing(PEER, b"Welcome".to_vec()),
+            Some(b"Welcome".to_vec())
+        );
+        assert_eq!(
+            validator.incoming(PEER, b"Chat hi".to_vec()),
+            Some(b"Chat hi".to_vec())
+        );
+        assert_eq!(validator.violations(), 1);
+    }
+}
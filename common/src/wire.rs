@@ -0,0 +1,492 @@
+//! Messages declared once, with [`define_protocol!`](crate::define_protocol).
+//!
+//! A hand-written message needs a struct, a `message()` that writes it, a
+//! [`NetMessage`](crate::handlers::NetMessage) impl that parses it, a row in
+//! `protocol::MESSAGES` and an `add_net_handler` or `add_event` call, and
+//! they all have to agree on the text. For a message made of `key=value`
+//! fields the macro writes all of that from one declaration:
+//!
+//! ```
+//! # use common::define_protocol;
+//! define_protocol! {
+//!     /// What a lobby says
+//!     pub enum LobbyMessage {
+//!         /// A player is ready to start
+//!         #[message(kind = "Ready", direction = "client->server", channel = "data")]
+//!         Ready { slot: u8, #[optional] team: String },
+//!         /// Sent when every player is ready
+//!         #[message(kind = "Start", direction = "server->client", channel = "reliable")]
+//!         Start {},
+//!     }
+//! }
+//!
+//! let ready = Ready { slot: 2, team: None };
+//! assert_eq!(ready.message(), "Ready slot=2");
+//! assert_eq!(LobbyMessage::parse("Ready team=red slot=2").map(|m| m.kind()), Some("Ready"));
+//! ```
+//!
+//! That declares a struct per message (`Ready { slot, team: Option<String> }`,
+//! written `Ready slot=<u8>[ team=<String>]`) with a `message()` and a
+//! `NetMessage` impl, so it can go straight to `add_net_handler`, and an enum
+//! of them with:
+//!
+//! - `parse`, which picks the message by its first word, and `message`
+//! - `kind` and `channel`, and `reliable`, which follows from the channel
+//! - `SPECS`, the messages' rows for the protocol description
+//! - `register`, which adds a `NetReceived<M>` event for every message, for
+//!   apps that read them without a handler
+//!
+//! The channel has to be one of `protocol::CHANNELS` and the direction one
+//! of `client->server`, `server->client` or `either`; anything else fails to
+//! compile. The `///` lines of a message become its description. Fields are
+//! read in any order and unknown ones are skipped, so a newer sender can add
+//! fields; a value that doesn't parse makes the message malformed. Values
+//! are the types with a [`WireValue`] impl; a `String` must not contain
+//! whitespace.
+//!
+//! Messages with positional fields (`Pos <seq> <x> <y>`) or free text
+//! (`Chat <text>`) stay hand-written, like those in `handlers`.
+
+/// A single value, as written after `name=`.
+pub trait WireValue: Sized {
+    fn from_wire(text: &str) -> Option<Self>;
+
+    fn to_wire(&self) -> String;
+}
+
+/// For the types whose `FromStr` and `Display` already agree
+macro_rules! plain_values {
+    ($($ty:ty),*) => {$(
+        impl WireValue for $ty {
+            fn from_wire(text: &str) -> Option<Self> {
+                text.parse().ok()
+            }
+
+            fn to_wire(&self) -> String {
+                self.to_string()
+            }
+        }
+    )*};
+}
+
+plain_values!(u8, u16, u32, u64, i32, i64, bool, String);
+
+/// NaN and infinity don't parse, as in `ConfigUpdate`
+impl WireValue for f32 {
+    fn from_wire(text: &str) -> Option<Self> {
+        text.parse().ok().filter(|value: &f32| value.is_finite())
+    }
+
+    fn to_wire(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl WireValue for f64 {
+    fn from_wire(text: &str) -> Option<Self> {
+        text.parse().ok().filter(|value: &f64| value.is_finite())
+    }
+
+    fn to_wire(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// A field of a generated message: a [`WireValue`], which must be there,
+/// or, for an `#[optional]` field, an `Option` of one.
+pub trait WireField: Sized {
+    /// `value` is what follows `name=`, `None` when the field is missing;
+    /// returns `None` when the message is malformed
+    fn read(value: Option<&str>) -> Option<Self>;
+
+    /// `None` leaves the field out
+    fn write(&self) -> Option<String>;
+}
+
+impl<T: WireValue> WireField for T {
+    fn read(value: Option<&str>) -> Option<Self> {
+        T::from_wire(value?)
+    }
+
+    fn write(&self) -> Option<String> {
+        Some(self.to_wire())
+    }
+}
+
+impl<T: WireValue> WireField for Option<T> {
+    fn read(value: Option<&str>) -> Option<Self> {
+        match value {
+            Some(value) => T::from_wire(value).map(Some),
+            None => Some(None),
+        }
+    }
+
+    fn write(&self) -> Option<String> {
+        self.as_ref().map(WireValue::to_wire)
+    }
+}
+
+/// What follows `name=` in `message`, if the field is there
+#[doc(hidden)]
+pub fn field<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message
+        .split_whitespace()
+        .skip(1)
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// `kind` followed by every field that isn't left out
+#[doc(hidden)]
+pub fn message(kind: &str, fields: &[(&str, Option<String>)]) -> String {
+    let mut message = kind.to_string();
+    for (name, value) in fields {
+        if let Some(value) = value {
+            message.push_str(&format!(" {}={}", name, value));
+        }
+    }
+    message
+}
+
+/// See the [module docs](crate::wire).
+#[macro_export]
+macro_rules! define_protocol {
+    (@type [optional] $ty:ty) => { ::std::option::Option<$ty> };
+    (@type [] $ty:ty) => { $ty };
+    (@field [optional] $field:ident $ty:ty) => {
+        concat!("[ ", stringify!($field), "=<", stringify!($ty), ">]")
+    };
+    (@field [] $field:ident $ty:ty) => {
+        concat!(" ", stringify!($field), "=<", stringify!($ty), ">")
+    };
+    (
+        $(#[doc = $enum_doc:literal])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[doc = $doc:literal])*
+                #[message(
+                    kind = $kind:literal,
+                    direction = $direction:literal,
+                    channel = $channel:literal $(,)?
+                )]
+                $variant:ident {
+                    $( $(#[$optional:ident])? $field:ident : $ty:ty ),* $(,)?
+                }
+            ),* $(,)?
+        }
+    ) => {
+        $(
+            $(#[doc = $doc])*
+            #[doc = ""]
+            #[doc = concat!(
+                "`", $kind $(, $crate::define_protocol!(@field [$($optional)?] $field $ty))*,
+                "` on the `", $channel, "` channel"
+            )]
+            #[derive(Clone, Debug, Default, PartialEq)]
+            $vis struct $variant {
+                $( pub $field: $crate::define_protocol!(@type [$($optional)?] $ty), )*
+            }
+
+            const _: () = assert!(
+                $crate::protocol::is_channel($channel),
+                concat!($kind, ": no such channel: ", $channel)
+            );
+            const _: () = assert!(
+                $crate::protocol::is_direction($direction),
+                concat!($kind, ": no such direction: ", $direction)
+            );
+
+            impl $variant {
+                pub fn message(&self) -> String {
+                    $crate::wire::message(
+                        $kind,
+                        &[$((
+                            stringify!($field),
+                            $crate::wire::WireField::write(&self.$field),
+                        )),*],
+                    )
+                }
+            }
+
+            impl $crate::handlers::NetMessage for $variant {
+                const KIND: &'static str = $kind;
+
+                fn parse(text: &str) -> Option<Self> {
+                    if $crate::session::message_kind(text) != $kind {
+                        return None;
+                    }
+                    Some($variant {
+                        $( $field: $crate::wire::WireField::read(
+                            $crate::wire::field(text, stringify!($field)),
+                        )?, )*
+                    })
+                }
+            }
+
+            impl From<$variant> for $name {
+                fn from(message: $variant) -> Self {
+                    $name::$variant(message)
+                }
+            }
+        )*
+
+        $(#[doc = $enum_doc])*
+        #[derive(Clone, Debug, PartialEq)]
+        $vis enum $name {
+            $( $variant($variant), )*
+        }
+
+        impl $name {
+            /// Every message's row, as in `protocol::MESSAGES`
+            pub const SPECS: &'static [$crate::protocol::MessageSpec] = &[$(
+                $crate::protocol::MessageSpec {
+                    kind: $kind,
+                    direction: $direction,
+                    channel: $channel,
+                    format: concat!(
+                        $kind $(, $crate::define_protocol!(@field [$($optional)?] $field $ty))*
+                    ),
+                    description: concat!($($doc),*).trim_ascii(),
+                },
+            )*];
+
+            /// `None` for another kind of message, or a malformed one
+            pub fn parse(text: &str) -> Option<Self> {
+                match $crate::session::message_kind(text) {
+                    $( $kind => <$variant as $crate::handlers::NetMessage>::parse(text)
+                        .map($name::$variant), )*
+                    _ => None,
+                }
+            }
+
+            pub fn message(&self) -> String {
+                match self {
+                    $( $name::$variant(message) => message.message(), )*
+                }
+            }
+
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    $( $name::$variant(_) => $kind, )*
+                }
+            }
+
+            pub fn channel(&self) -> &'static str {
+                match self {
+                    $( $name::$variant(_) => $channel, )*
+                }
+            }
+
+            /// Whether the channel resends it until it's acked
+            pub fn reliable(&self) -> bool {
+                $crate::protocol::channel(self.channel()).is_some_and(|channel| channel.reliable)
+            }
+
+            /// Adds a `NetReceived<M>` event, routed in `PostUpdate`, for
+            /// every message; see `handlers`
+            pub fn register(app: &mut ::bevy::prelude::App) {
+                use $crate::handlers::NetHandlerExt;
+                $( app.add_net_message::<$variant>(); )*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::{
+        Heartbeat, HeartbeatAck, KeepAlive, ServerInfo, ServerInfoRequest, ServerQuery,
+    };
+
+    /// Every `define_protocol!` message as it goes on the wire, byte for
+    /// byte. If one of these changes, the wire format did: old peers will
+    /// misread the new bytes, so bump `protocol::PROTOCOL_VERSION` rather
+    /// than edit the fixture to match.
+    fn keep_alive() -> Vec<(KeepAlive, &'static [u8])> {
+        vec![
+            (
+                Heartbeat {
+                    rtt_ms: Some(12.5),
+                    loss: Some(0.25),
+                }
+                .into(),
+                b"Heartbeat rtt_ms=12.5 loss=0.25",
+            ),
+            (
+                Heartbeat {
+                    rtt_ms: Some(40.0),
+                    loss: None,
+                }
+                .into(),
+                b"Heartbeat rtt_ms=40",
+            ),
+            (
+                Heartbeat {
+                    rtt_ms: None,
+                    loss: None,
+                }
+                .into(),
+                b"Heartbeat",
+            ),
+            (HeartbeatAck {}.into(), b"HeartbeatAck"),
+        ]
+    }
+
+    fn server_query() -> Vec<(ServerQuery, &'static [u8])> {
+        vec![
+            (ServerInfoRequest {}.into(), b"ServerInfoRequest"),
+            (
+                ServerInfo {
+                    name: "lobby-1".to_string(),
+                    version: 2,
+                    players: 3,
+                    spectators: 1,
+                    mode: "tickets".to_string(),
+                    uptime_secs: 3600,
+                }
+                .into(),
+                b"ServerInfo name=lobby-1 version=2 players=3 spectators=1 mode=tickets uptime_secs=3600",
+            ),
+        ]
+    }
+
+    #[test]
+    fn keep_alive_matches_its_fixtures() {
+        for (message, bytes) in keep_alive() {
+            assert_eq!(message.message().as_bytes(), bytes, "{:?}", message);
+            let text = std::str::from_utf8(bytes).unwrap();
+            assert_eq!(KeepAlive::parse(text), Some(message));
+        }
+    }
+
+    #[test]
+    fn server_query_matches_its_fixtures() {
+        for (message, bytes) in server_query() {
+            assert_eq!(message.message().as_bytes(), bytes, "{:?}", message);
+            let text = std::str::from_utf8(bytes).unwrap();
+            assert_eq!(ServerQuery::parse(text), Some(message));
+        }
+    }
+
+    #[test]
+    fn every_message_has_a_fixture() {
+        let covered: Vec<&str> = keep_alive()
+            .iter()
+            .map(|(message, _)| message.kind())
+            .chain(server_query().iter().map(|(message, _)| message.kind()))
+            .collect();
+        for spec in KeepAlive::SPECS.iter().chain(ServerQuery::SPECS) {
+            assert!(
+                covered.contains(&spec.kind),
+                "{} has no fixture in wire::tests",
+                spec.kind
+            );
+        }
+    }
+
+    #[test]
+    fn specs_match_their_fixtures() {
+        let formats: Vec<(&str, &str, &str)> = KeepAlive::SPECS
+            .iter()
+            .chain(ServerQuery::SPECS)
+            .map(|spec| (spec.kind, spec.channel, spec.format))
+            .collect();
+        assert_eq!(
+            formats,
+            [
+                (
+                    "Heartbeat",
+                    "control",
+                    "Heartbeat[ rtt_ms=<f32>][ loss=<f32>]"
+                ),
+                ("HeartbeatAck", "control", "HeartbeatAck"),
+                ("ServerInfoRequest", "control", "ServerInfoRequest"),
+                (
+                    "ServerInfo",
+                    "control",
+                    "ServerInfo name=<String> version=<u32> players=<u32> spectators=<u32> mode=<String> uptime_secs=<u64>"
+                ),
+            ]
+        );
+    }
+
+    /// Captured datagrams and the exact values they decode to. Text has no
+    /// byte order, so these hold on big- and little-endian hosts alike;
+    /// floats are compared by their bits, so a host that rounded
+    /// differently would fail too.
+    #[test]
+    fn decodes_captured_fixtures_to_the_same_bits_on_every_host() {
+        let heartbeat = |bytes: &[u8]| match KeepAlive::parse(std::str::from_utf8(bytes).unwrap()) {
+            Some(KeepAlive::Heartbeat(heartbeat)) => heartbeat,
+            other => panic!("not a Heartbeat: {:?}", other),
+        };
+        let captured = heartbeat(b"Heartbeat rtt_ms=0.1 loss=0.33333334");
+        assert_eq!(captured.rtt_ms.map(f32::to_bits), Some(0x3dcc_cccd));
+        assert_eq!(captured.loss.map(f32::to_bits), Some(0x3eaa_aaab));
+        let tiny = heartbeat(b"Heartbeat rtt_ms=0.000000000000000000000000000000000000000000001");
+        assert_eq!(tiny.rtt_ms.map(f32::to_bits), Some(1));
+
+        let info = b"ServerInfo name=a version=4294967295 players=0 spectators=0 mode=open uptime_secs=18446744073709551615";
+        match ServerQuery::parse(std::str::from_utf8(info).unwrap()) {
+            Some(ServerQuery::ServerInfo(info)) => {
+                assert_eq!(info.version, u32::MAX);
+                assert_eq!(info.uptime_secs, u64::MAX);
+            }
+            other => panic!("not a ServerInfo: {:?}", other),
+        }
+    }
+
+    /// Encoding writes the shortest text that reads back to the same bits,
+    /// so a value survives a trip through any peer.
+    #[test]
+    fn floats_round_trip_bit_for_bit() {
+        for bits in [
+            0x3dcc_cccd,
+            0x3eaa_aaab,
+            0x4b7f_ffff,
+            0x0000_0001,
+            0x7f7f_ffff,
+            0x8000_0000,
+        ] {
+            let heartbeat = Heartbeat {
+                rtt_ms: Some(f32::from_bits(bits)),
+                loss: None,
+            };
+            let message = heartbeat.message();
+            let decoded = match KeepAlive::parse(&message) {
+                Some(KeepAlive::Heartbeat(decoded)) => decoded.rtt_ms.map(f32::to_bits),
+                other => panic!("{} read back as {:?}", message, other),
+            };
+            assert_eq!(decoded, Some(bits), "{}", message);
+        }
+    }
+
+    #[test]
+    fn reads_fields_in_any_order_and_skips_unknown_ones() {
+        assert_eq!(
+            KeepAlive::parse("Heartbeat loss=0.5 jitter_ms=3 rtt_ms=20"),
+            Some(
+                Heartbeat {
+                    rtt_ms: Some(20.0),
+                    loss: Some(0.5),
+                }
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_values() {
+        for text in [
+            "Heartbeat rtt_ms=fast",
+            "Heartbeat rtt_ms=NaN",
+            "Heartbeat loss=inf",
+            "ServerInfo name=lobby-1 version=2 players=3 spectators=1 mode=open",
+            "ServerInfo name=lobby-1 version=-2 players=3 spectators=1 mode=open uptime_secs=1",
+            "Heartbeatx",
+            "",
+        ] {
+            assert_eq!(KeepAlive::parse(text), None, "{}", text);
+            assert_eq!(ServerQuery::parse(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,781 @@
+//! conformance: checks a server against the protocol, for reimplementations
+//! Usage: cargo run -p conformance -- --server 127.0.0.1:12345
+//!
+//! Talks to the server at `--server` over UDP the way a client would, with
+//! a fresh socket, and so a fresh session, for each group of checks, and
+//! prints a row per check:
+//!
+//! ```text
+//! area           check          result       ms  detail
+//! handshake      hello          pass        0.4  seed 3f2a91c07d5e6b18, token issued
+//! version        too-new        pass        0.3  Reject version=2
+//! reliability    resend         FAIL     3000.1  REL 0 wasn't sent again within 3000ms
+//! ```
+//!
+//! - `handshake`: Hello, Ping, Heartbeat, and a Resume with a token the
+//!   server never issued, each answered as `common::protocol` says. Pongs
+//!   are left to the server's operator, so a Ping passes when the session
+//!   still acks a Heartbeat after it
+//! - `version`: a Hello without a version, one at the oldest supported
+//!   version (whose Welcome has to name every newer feature in
+//!   `deprecated=`), versions no server speaks, and codecs no server has
+//! - `reliability`: the `common::reliable` channel, through Logins: acks,
+//!   delivery, resends while unacked and none once acked, a duplicate
+//!   acked but not delivered again, and a payload held back behind a gap
+//! - `fragmentation`: there is none, every message is one datagram. A
+//!   long Chat is relayed whole to a second session, an oversized one is
+//!   dropped rather than handled in part, and nothing the server sent
+//!   during the run is bigger than `--max-message-size`
+//!
+//! A check whose setup failed is skipped. The exit status is 1 when any
+//! check failed. The Logins create an account called `conformance_<hex>`
+//! on the server, and a server started with `--ticket-key` turns away
+//! every Hello without a ticket, so only the rejections pass there.
+
+use clap::Parser;
+use common::disconnect::DisconnectReason;
+use common::handlers::{Heartbeat, NetMessage, Welcome};
+use common::protocol::{self, MIN_SUPPORTED_VERSION, PROTOCOL_VERSION};
+use common::reliable::ReliableMessage;
+use common::session::message_kind;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a receive blocks before a wait looks at the clock again
+const POLL: Duration = Duration::from_millis(20);
+/// Bigger than any datagram UDP can carry
+const RECEIVE_BYTES: usize = 65536;
+/// Bigger than a server's receive buffer should be
+const OVERSIZED_BYTES: usize = 8192;
+/// A Chat whose relay still fits a 1024-byte datagram
+const LONG_CHAT_BYTES: usize = 800;
+/// Left for resends already on the way when we ack
+const ACK_SETTLE: Duration = Duration::from_millis(200);
+/// Asks whether a session is still up: every Heartbeat gets an ack
+const PROBE: Heartbeat = Heartbeat {
+    rtt_ms: None,
+    loss: None,
+};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Server to check: the address of its main (control) port
+    #[arg(short, long, default_value = "127.0.0.1:12345")]
+    server: String,
+
+    /// Milliseconds a reply may take
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    /// Seconds to wait for an unacked reliable message to be sent again
+    #[arg(long, default_value_t = 3.0)]
+    resend_wait_secs: f32,
+
+    /// Largest datagram the server may send, in bytes (the server's --max-message-size)
+    #[arg(long, default_value_t = 1024)]
+    max_message_size: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl Outcome {
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "skip",
+        }
+    }
+}
+
+/// A check's outcome and what it saw; `Err` is a failure
+type Verdict = Result<String, String>;
+
+struct Check {
+    area: &'static str,
+    name: &'static str,
+    outcome: Outcome,
+    millis: f64,
+    detail: String,
+}
+
+#[derive(Default)]
+struct Matrix {
+    checks: Vec<Check>,
+}
+
+impl Matrix {
+    fn record(
+        &mut self,
+        area: &'static str,
+        name: &'static str,
+        started: Instant,
+        verdict: Verdict,
+    ) {
+        let (outcome, detail) = match verdict {
+            Ok(detail) => (Outcome::Pass, detail),
+            Err(detail) => (Outcome::Fail, detail),
+        };
+        self.checks.push(Check {
+            area,
+            name,
+            outcome,
+            millis: started.elapsed().as_secs_f64() * 1000.0,
+            detail,
+        });
+    }
+
+    fn skip(&mut self, area: &'static str, names: &[&'static str], reason: &str) {
+        for name in names {
+            self.checks.push(Check {
+                area,
+                name,
+                outcome: Outcome::Skip,
+                millis: 0.0,
+                detail: reason.to_string(),
+            });
+        }
+    }
+
+    fn count(&self, area: Option<&str>, outcome: Outcome) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| area.is_none_or(|area| check.area == area))
+            .filter(|check| check.outcome == outcome)
+            .count()
+    }
+
+    fn print(&self) {
+        println!(
+            "{:<14} {:<14} {:<6} {:>8}  detail",
+            "area", "check", "result", "ms"
+        );
+        for check in &self.checks {
+            println!(
+                "{:<14} {:<14} {:<6} {:>8.1}  {}",
+                check.area,
+                check.name,
+                check.outcome.label(),
+                check.millis,
+                check.detail
+            );
+        }
+        let mut areas: Vec<&str> = Vec::new();
+        for check in &self.checks {
+            if !areas.contains(&check.area) {
+                areas.push(check.area);
+            }
+        }
+        let summary: Vec<String> = areas
+            .iter()
+            .map(|&area| {
+                let passed = self.count(Some(area), Outcome::Pass);
+                let failed = self.count(Some(area), Outcome::Fail);
+                format!("{} {}/{}", area, passed, passed + failed)
+            })
+            .collect();
+        println!();
+        println!("{}", summary.join("  "));
+        println!(
+            "{} passed, {} failed, {} skipped",
+            self.count(None, Outcome::Pass),
+            self.count(None, Outcome::Fail),
+            self.count(None, Outcome::Skip)
+        );
+    }
+}
+
+/// What every session of the run shares.
+struct Link {
+    server: SocketAddr,
+    timeout: Duration,
+    /// Largest datagram heard from the server so far
+    largest: Cell<usize>,
+}
+
+impl Link {
+    /// A session of our own, from a fresh socket.
+    fn peer(&self) -> Result<Peer<'_>, String> {
+        let local = if self.server.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| format!("bind failed: {}", e))?;
+        socket
+            .set_read_timeout(Some(POLL))
+            .map_err(|e| format!("bind failed: {}", e))?;
+        Ok(Peer { link: self, socket })
+    }
+}
+
+struct Peer<'a> {
+    link: &'a Link,
+    socket: UdpSocket,
+}
+
+impl Peer<'_> {
+    fn send(&self, message: &str) -> Result<(), String> {
+        self.send_bytes(message.as_bytes())
+    }
+
+    fn send_bytes(&self, bytes: &[u8]) -> Result<(), String> {
+        self.socket
+            .send_to(bytes, self.link.server)
+            .map(|_| ())
+            .map_err(|e| format!("send failed: {}", e))
+    }
+
+    /// The next datagram from the server's host before `deadline`, as
+    /// text. Replies may come from its data port, so any port will do.
+    fn receive(&self, deadline: Instant) -> Result<Option<String>, String> {
+        let mut buf = vec![0u8; RECEIVE_BYTES];
+        while Instant::now() < deadline {
+            match self.socket.recv_from(&mut buf) {
+                Ok((size, from)) if from.ip() == self.link.server.ip() => {
+                    self.link.largest.set(self.link.largest.get().max(size));
+                    return Ok(Some(String::from_utf8_lossy(&buf[..size]).into_owned()));
+                }
+                Ok(_) => continue,
+                // WouldBlock on Unix and TimedOut on Windows
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => return Err(format!("receive failed: {}", e)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for a message `wanted` accepts, skipping the rest; `what`
+    /// names it in the error.
+    fn wait_for(
+        &self,
+        what: &str,
+        within: Duration,
+        wanted: impl Fn(&str) -> bool,
+    ) -> Result<String, String> {
+        let deadline = Instant::now() + within;
+        while let Some(message) = self.receive(deadline)? {
+            if wanted(&message) {
+                return Ok(message);
+            }
+        }
+        Err(format!("no {} within {}ms", what, within.as_millis()))
+    }
+
+    /// Sends `message` and waits for a reply of kind `kind`.
+    fn request(&self, message: &str, kind: &str) -> Result<String, String> {
+        self.send(message)?;
+        self.wait_for(kind, self.link.timeout, |reply| message_kind(reply) == kind)
+    }
+
+    /// Sends a Hello or Resume, and returns the Welcome or Reject it got.
+    fn greet(&self, greeting: &str) -> Result<String, String> {
+        self.send(greeting)?;
+        self.wait_for("Welcome or Reject", self.link.timeout, |reply| {
+            matches!(message_kind(reply), "Welcome" | "Reject")
+        })
+    }
+
+    fn welcome(&self, greeting: &str) -> Result<Welcome, String> {
+        let reply = self.greet(greeting)?;
+        Welcome::parse(&reply).ok_or_else(|| format!("expected a Welcome, got {}", reply))
+    }
+
+    /// A session at our protocol version.
+    fn join(&self) -> Result<Welcome, String> {
+        self.welcome(&format!("Hello version={}", PROTOCOL_VERSION))
+    }
+}
+
+impl Drop for Peer<'_> {
+    /// Ends the session, so the server doesn't hold it until it times out
+    fn drop(&mut self) {
+        let disconnect = format!("Disconnect reason={}", DisconnectReason::UserQuit.code());
+        let _ = self.send(&disconnect);
+    }
+}
+
+/// Set for each run, so the accounts and chat lines of two runs differ
+fn nonce() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// A Welcome has to carry both, whatever else it has
+fn welcomed(welcome: &Welcome) -> Verdict {
+    match (welcome.seed, &welcome.token) {
+        (Some(seed), Some(_)) => Ok(format!("seed {:016x}, token issued", seed)),
+        _ => Err("Welcome without a seed or token".to_string()),
+    }
+}
+
+/// The reply, if it's a Reject saying which version the server speaks
+fn rejected(reply: Result<String, String>) -> Verdict {
+    let reply = reply?;
+    if message_kind(&reply) != "Reject" {
+        return Err(format!("expected a Reject, got {}", reply));
+    }
+    if protocol::parse_version(&reply).is_none() {
+        return Err(format!("Reject without version=: {}", reply));
+    }
+    Ok(reply)
+}
+
+fn check_handshake(link: &Link, matrix: &mut Matrix) {
+    const AREA: &str = "handshake";
+    let started = Instant::now();
+    let session = link.peer().and_then(|peer| {
+        let welcome = peer.join()?;
+        Ok((peer, welcome))
+    });
+    let peer = match session {
+        Ok((peer, welcome)) => {
+            matrix.record(AREA, "hello", started, welcomed(&welcome));
+            peer
+        }
+        Err(reason) => {
+            matrix.record(AREA, "hello", started, Err(reason));
+            matrix.skip(AREA, &["ping", "heartbeat"], "no session");
+            return;
+        }
+    };
+
+    // The server leaves Pongs to its operator, so a Heartbeat right behind
+    // the Ping shows it was taken without ending the session
+    let started = Instant::now();
+    let answer = peer.send("Ping").and_then(|_| {
+        peer.request(&PROBE.message(), "HeartbeatAck")
+            .map_err(|_| "no HeartbeatAck after the Ping".to_string())
+    });
+    let rtt_ms = started.elapsed().as_secs_f32() * 1000.0;
+    matrix.record(
+        AREA,
+        "ping",
+        started,
+        answer.map(|_| "accepted, the session still answers".to_string()),
+    );
+
+    let started = Instant::now();
+    let heartbeat = Heartbeat {
+        rtt_ms: Some(rtt_ms),
+        loss: Some(0.0),
+    };
+    let ack = peer.request(&heartbeat.message(), "HeartbeatAck");
+    matrix.record(
+        AREA,
+        "heartbeat",
+        started,
+        ack.map(|_| "HeartbeatAck".to_string()),
+    );
+
+    // A token nobody was issued starts a new session
+    let started = Instant::now();
+    let resume = format!("Resume token={:016x} version={}", nonce(), PROTOCOL_VERSION);
+    let resumed = link
+        .peer()
+        .and_then(|peer| peer.welcome(&resume))
+        .and_then(|welcome| {
+            if welcome.resumed {
+                return Err("an unknown token resumed a session".to_string());
+            }
+            welcomed(&welcome).map(|_| "new session".to_string())
+        });
+    matrix.record(AREA, "resume", started, resumed);
+}
+
+fn check_versions(link: &Link, matrix: &mut Matrix) {
+    const AREA: &str = "version";
+    let greet = |greeting: &str| link.peer().and_then(|peer| peer.greet(greeting));
+    let welcome = |greeting: &str| link.peer().and_then(|peer| peer.welcome(greeting));
+
+    // A bare Hello predates versioning and speaks the current protocol
+    let started = Instant::now();
+    let verdict = welcome("Hello").and_then(|welcome| {
+        if welcome.deprecated.is_empty() {
+            welcomed(&welcome)
+        } else {
+            Err(format!("deprecated={}", welcome.deprecated.join(",")))
+        }
+    });
+    matrix.record(AREA, "unversioned", started, verdict);
+
+    let started = Instant::now();
+    let verdict =
+        welcome(&format!("Hello version={}", MIN_SUPPORTED_VERSION)).and_then(|welcome| {
+            let expected: BTreeSet<&str> = protocol::missing_features(MIN_SUPPORTED_VERSION)
+                .into_iter()
+                .collect();
+            let named: BTreeSet<&str> = welcome.deprecated.iter().map(String::as_str).collect();
+            if named != expected {
+                return Err(format!(
+                    "deprecated={}, expected {}",
+                    welcome.deprecated.join(","),
+                    expected.into_iter().collect::<Vec<_>>().join(",")
+                ));
+            }
+            welcomed(&welcome).map(|_| {
+                format!(
+                    "version {}, deprecated={}",
+                    MIN_SUPPORTED_VERSION,
+                    welcome.deprecated.join(",")
+                )
+            })
+        });
+    matrix.record(AREA, "oldest", started, verdict);
+
+    let started = Instant::now();
+    matrix.record(AREA, "too-old", started, rejected(greet("Hello version=0")));
+
+    let started = Instant::now();
+    let hello = format!("Hello version={}", u32::MAX);
+    matrix.record(AREA, "too-new", started, rejected(greet(&hello)));
+
+    // The server's codecs can't include one that doesn't exist
+    let started = Instant::now();
+    let hello = format!("Hello version={} codecs=data:conformance", PROTOCOL_VERSION);
+    let verdict = rejected(greet(&hello)).and_then(|reject| {
+        if protocol::parse_codecs(&reject).is_some() {
+            Ok(reject)
+        } else {
+            Err(format!("Reject without codecs=: {}", reject))
+        }
+    });
+    matrix.record(AREA, "codecs", started, verdict);
+}
+
+/// One session's reliable channel, as seen from our side.
+struct Channel<'a> {
+    peer: Peer<'a>,
+    /// Every `REL` the server sent, resends included, as (seq, payload)
+    data: Vec<(u32, String)>,
+    /// Every `REL_ACK` it sent
+    acks: Vec<u32>,
+    user: String,
+}
+
+impl Channel<'_> {
+    fn login(&self, seq: u32) -> Result<(), String> {
+        let login = format!(
+            "REL {} {}",
+            seq,
+            protocol::login_payload(&self.user, "conformance")
+        );
+        self.peer.send(&login)
+    }
+
+    /// Reads until `done` holds; `false` when `within` passed first
+    fn pump(&mut self, within: Duration, done: impl Fn(&Self) -> bool) -> Result<bool, String> {
+        let deadline = Instant::now() + within;
+        while !done(self) {
+            let Some(message) = self.peer.receive(deadline)? else {
+                return Ok(false);
+            };
+            match ReliableMessage::parse(&message) {
+                Some(ReliableMessage::Data { seq, payload }) => {
+                    self.data.push((seq, payload.to_string()))
+                }
+                Some(ReliableMessage::Ack { seq }) => self.acks.push(seq),
+                None => {}
+            }
+        }
+        Ok(true)
+    }
+
+    fn acked(&self, seq: u32) -> usize {
+        self.acks.iter().filter(|acked| **acked == seq).count()
+    }
+
+    /// Distinct answers to our Logins
+    fn replies(&self) -> usize {
+        let seqs: BTreeSet<u32> = self
+            .data
+            .iter()
+            .filter(|(_, payload)| matches!(message_kind(payload), "LoginOk" | "LoginFailed"))
+            .map(|(seq, _)| *seq)
+            .collect();
+        seqs.len()
+    }
+
+    /// Acks everything the server has sent so far.
+    fn ack_all(&self) -> Result<(), String> {
+        let seqs: BTreeSet<u32> = self.data.iter().map(|(seq, _)| *seq).collect();
+        for seq in seqs {
+            self.peer.send(&format!("REL_ACK {}", seq))?;
+        }
+        Ok(())
+    }
+}
+
+fn check_reliability(link: &Link, resend_wait: Duration, matrix: &mut Matrix) {
+    const AREA: &str = "reliability";
+    const CHECKS: &[&str] = &[
+        "ack",
+        "deliver",
+        "resend",
+        "acked",
+        "duplicate",
+        "hold-back",
+    ];
+    let timeout = link.timeout;
+    let started = Instant::now();
+    let session = link.peer().and_then(|peer| {
+        peer.join()?;
+        Ok(peer)
+    });
+    let peer = match session {
+        Ok(peer) => peer,
+        Err(reason) => {
+            matrix.skip(AREA, CHECKS, &format!("no session: {}", reason));
+            return;
+        }
+    };
+    let mut channel = Channel {
+        peer,
+        data: Vec::new(),
+        acks: Vec::new(),
+        user: format!("conformance_{:08x}", nonce() as u32),
+    };
+
+    let verdict = channel
+        .login(0)
+        .and_then(|_| channel.pump(timeout, |channel| channel.acked(0) > 0))
+        .and_then(|acked| {
+            if acked {
+                Ok("REL_ACK 0".to_string())
+            } else {
+                Err(format!("no REL_ACK 0 within {}ms", timeout.as_millis()))
+            }
+        });
+    matrix.record(AREA, "ack", started, verdict);
+
+    let started = Instant::now();
+    let verdict = channel
+        .pump(timeout, |channel| channel.replies() > 0)
+        .and_then(|answered| {
+            if answered {
+                Ok(format!(
+                    "{} messages, the Login answered",
+                    channel.data.len()
+                ))
+            } else {
+                Err(format!(
+                    "no answer to the Login within {}ms",
+                    timeout.as_millis()
+                ))
+            }
+        });
+    matrix.record(AREA, "deliver", started, verdict);
+    let Some((seq, payload)) = channel.data.first().cloned() else {
+        matrix.skip(AREA, &CHECKS[2..], "the server sent nothing reliably");
+        return;
+    };
+
+    // Nothing is acked yet, so the first message has to come again
+    let started = Instant::now();
+    let verdict = channel
+        .pump(resend_wait, |channel| {
+            channel
+                .data
+                .iter()
+                .filter(|(resent, _)| *resent == seq)
+                .count()
+                > 1
+        })
+        .and_then(|resent| {
+            let copy = channel
+                .data
+                .iter()
+                .skip(1)
+                .find(|(resent, _)| *resent == seq);
+            match (resent, copy) {
+                (true, Some((_, copy))) if *copy == payload => {
+                    Ok(format!("REL {} sent again", seq))
+                }
+                (true, _) => Err(format!("REL {} sent again with another payload", seq)),
+                (false, _) => Err(format!(
+                    "REL {} wasn't sent again within {}ms",
+                    seq,
+                    resend_wait.as_millis()
+                )),
+            }
+        });
+    matrix.record(AREA, "resend", started, verdict);
+
+    let started = Instant::now();
+    let verdict = channel
+        .ack_all()
+        .and_then(|_| channel.pump(ACK_SETTLE, |_| false))
+        .and_then(|_| {
+            let before = channel.data.len();
+            channel.pump(resend_wait, |channel| channel.data.len() > before)?;
+            let acked: BTreeSet<u32> = channel.data[..before].iter().map(|(seq, _)| *seq).collect();
+            match channel.data[before..]
+                .iter()
+                .find(|(seq, _)| acked.contains(seq))
+            {
+                Some((seq, _)) => Err(format!("REL {} sent again after its ack", seq)),
+                None => Ok(format!("{} acked, none sent again", acked.len())),
+            }
+        });
+    matrix.record(AREA, "acked", started, verdict);
+
+    // The same seq again is acked and dropped
+    let started = Instant::now();
+    let verdict = channel.ack_all().and_then(|_| {
+        let (acks, replies) = (channel.acked(0), channel.replies());
+        channel.login(0)?;
+        channel.pump(timeout, |channel| channel.acked(0) > acks)?;
+        channel.pump(timeout, |channel| channel.replies() > replies)?;
+        match (channel.acked(0) > acks, channel.replies() > replies) {
+            (true, false) => Ok("acked again, not delivered again".to_string()),
+            (false, _) => Err("the duplicate REL 0 wasn't acked".to_string()),
+            (true, true) => Err("the duplicate REL 0 was delivered again".to_string()),
+        }
+    });
+    matrix.record(AREA, "duplicate", started, verdict);
+
+    // 2 arrives before 1, so it waits for 1 and both are answered after it
+    let started = Instant::now();
+    let verdict = channel.ack_all().and_then(|_| {
+        let replies = channel.replies();
+        channel.login(2)?;
+        if !channel.pump(timeout, |channel| channel.acked(2) > 0)? {
+            return Err("REL 2 wasn't acked".to_string());
+        }
+        if channel.pump(timeout, |channel| channel.replies() > replies)? {
+            return Err("REL 2 was delivered before REL 1".to_string());
+        }
+        channel.login(1)?;
+        if !channel.pump(timeout, |channel| channel.replies() >= replies + 2)? {
+            return Err("REL 1 and 2 weren't both delivered after REL 1".to_string());
+        }
+        Ok("REL 2 held back until REL 1".to_string())
+    });
+    matrix.record(AREA, "hold-back", started, verdict);
+    let _ = channel.ack_all();
+}
+
+fn check_fragmentation(link: &Link, max_message_size: usize, matrix: &mut Matrix) {
+    const AREA: &str = "fragmentation";
+    let timeout = link.timeout;
+    let sessions = link.peer().and_then(|sender| {
+        sender.join()?;
+        let receiver = link.peer()?;
+        receiver.join()?;
+        Ok((sender, receiver))
+    });
+    match sessions {
+        Ok((sender, receiver)) => {
+            // Chat is relayed to every other session, as `Chat <addr>: <text>`
+            let started = Instant::now();
+            let marker = format!("conformance-{:08x}", nonce() as u32);
+            let text = format!("{} {}", marker, "x".repeat(LONG_CHAT_BYTES));
+            let relayed = sender.send(&format!("Chat {}", text)).and_then(|_| {
+                receiver.wait_for("relayed Chat", timeout, |message| {
+                    message_kind(message) == "Chat" && message.contains(&marker)
+                })
+            });
+            let verdict = relayed.and_then(|relay| {
+                if relay.ends_with(&text) {
+                    Ok(format!("{} bytes relayed whole", relay.len()))
+                } else {
+                    Err(format!("relayed in part, {} bytes", relay.len()))
+                }
+            });
+            let whole = verdict.is_ok();
+            matrix.record(AREA, "whole", started, verdict);
+
+            if whole {
+                let started = Instant::now();
+                let marker = format!("{}-oversized", marker);
+                let text = format!("Chat {} {}", marker, "x".repeat(OVERSIZED_BYTES));
+                let verdict = sender.send_bytes(text.as_bytes()).and_then(|_| {
+                    let handled =
+                        receiver.wait_for("relay", timeout, |message| message.contains(&marker));
+                    if handled.is_ok() {
+                        return Err(format!("{} bytes were handled", text.len()));
+                    }
+                    sender.request(&PROBE.message(), "HeartbeatAck")?;
+                    Ok(format!(
+                        "{} bytes dropped, the session still answers",
+                        text.len()
+                    ))
+                });
+                matrix.record(AREA, "oversized", started, verdict);
+            } else {
+                matrix.skip(AREA, &["oversized"], "needs a relayed Chat to compare with");
+            }
+        }
+        Err(reason) => {
+            matrix.skip(
+                AREA,
+                &["whole", "oversized"],
+                &format!("no sessions: {}", reason),
+            );
+        }
+    }
+
+    // Last, so it covers everything the run heard
+    let started = Instant::now();
+    let largest = link.largest.get();
+    let verdict = if largest <= max_message_size {
+        Ok(format!("largest {} bytes", largest))
+    } else {
+        Err(format!(
+            "a {}-byte datagram, over {}",
+            largest, max_message_size
+        ))
+    };
+    matrix.record(AREA, "reply-size", started, verdict);
+}
+
+fn main() {
+    let args = Args::parse();
+    let server = match args.server.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(server)) => server,
+        Ok(None) => {
+            eprintln!("Error: {} has no address", args.server);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: could not resolve {}: {}", args.server, e);
+            std::process::exit(1);
+        }
+    };
+    let link = Link {
+        server,
+        timeout: Duration::from_millis(args.timeout_ms),
+        largest: Cell::new(0),
+    };
+    println!(
+        "Checking {} against protocol version {} (oldest {})\n",
+        server, PROTOCOL_VERSION, MIN_SUPPORTED_VERSION
+    );
+
+    let mut matrix = Matrix::default();
+    check_handshake(&link, &mut matrix);
+    check_versions(&link, &mut matrix);
+    let resend_wait = Duration::from_secs_f32(args.resend_wait_secs.max(0.0));
+    check_reliability(&link, resend_wait, &mut matrix);
+    check_fragmentation(&link, args.max_message_size, &mut matrix);
+    matrix.print();
+    if matrix.count(None, Outcome::Fail) > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,123 @@
+//! Cursor Presence Client
+//! Usage: cargo run --bin cursor_client -- --server 127.0.0.1:12500
+//!
+//! Streams the pointer to the server `SEND_HZ` times a second while it is
+//! over the window, and draws every other client's pointer as a dot in
+//! that client's colour. Run two side by side and move the mouse in one.
+
+use bevy::prelude::*;
+use bevy_udp_net::{NetworkMessageReceived, NetworkSocket, UdpNetworkPlugin};
+use clap::Parser;
+use std::collections::HashMap;
+
+use cursors::{CursorMessage, RECV_BUFFER, SEND_HZ, TIMEOUT_SECS, draw_dot, pointer_fraction};
+
+#[derive(Parser, Resource, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Server address to stream to
+    #[arg(short, long, default_value = "127.0.0.1:12500")]
+    server: String,
+}
+
+#[derive(Resource)]
+struct SendTimer(Timer);
+
+/// Other clients' cursors by id: (position, when last heard)
+#[derive(Resource, Default)]
+struct Others(HashMap<u32, (Vec2, f32)>);
+
+fn main() {
+    let args = Args::parse();
+
+    let socket = match NetworkSocket::bind("0.0.0.0:0", RECV_BUFFER) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error: could not bind a socket: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Ok(addr) = socket.local_addr() {
+        println!("Cursor Client bound to {}", addr);
+    }
+
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                title: "Cursor Client".to_string(),
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugins(UdpNetworkPlugin::new(socket))
+        .insert_resource(args)
+        .insert_resource(SendTimer(Timer::from_seconds(
+            1.0 / SEND_HZ,
+            TimerMode::Repeating,
+        )))
+        .init_resource::<Others>()
+        .add_systems(Startup, setup)
+        .add_systems(
+            Update,
+            (stream_pointer, receive_cursors, draw_cursors).chain(),
+        )
+        .run();
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+fn stream_pointer(
+    time: Res<Time>,
+    args: Res<Args>,
+    socket: Res<NetworkSocket>,
+    windows: Query<&Window>,
+    mut timer: ResMut<SendTimer>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Some(pointer) = windows.get_single().ok().and_then(pointer_fraction) else {
+        return;
+    };
+    let message = CursorMessage::Moved {
+        x: pointer.x,
+        y: pointer.y,
+    };
+    if let Err(e) = socket.send_to(&message.to_string(), args.server.as_str()) {
+        warn!("Failed to send cursor to {}: {}", args.server, e);
+    }
+}
+
+fn receive_cursors(
+    time: Res<Time>,
+    mut received: EventReader<NetworkMessageReceived>,
+    mut others: ResMut<Others>,
+) {
+    let now = time.elapsed_seconds();
+    for message in received.read() {
+        match CursorMessage::parse(&message.text()) {
+            Some(CursorMessage::Relayed { id, x, y }) => {
+                others.0.insert(id, (Vec2::new(x, y), now));
+            }
+            Some(CursorMessage::Left { id }) => {
+                others.0.remove(&id);
+            }
+            _ => {}
+        }
+    }
+    // In case the LEFT was lost
+    others
+        .0
+        .retain(|_, (_, last_heard)| now - *last_heard <= TIMEOUT_SECS);
+}
+
+fn draw_cursors(mut gizmos: Gizmos, windows: Query<&Window>, others: Res<Others>) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    for (id, (position, _)) in &others.0 {
+        draw_dot(&mut gizmos, window, *position, *id);
+    }
+}
@@ -0,0 +1,101 @@
+//! Cursor presence: the wire format and drawing shared by cursor_server and
+//! cursor_client.
+//!
+//! ```text
+//! CURSOR <x> <y>        client -> server, SEND_HZ times a second while the pointer is over the window
+//! CURSOR <id> <x> <y>   server -> every other client
+//! LEFT <id>             server -> every other client, once <id> has been quiet for TIMEOUT_SECS
+//! ```
+//!
+//! Positions are fractions of the window, `0 0` top left and `1 1` bottom
+//! right, so windows of different sizes agree on where a pointer is.
+//! Nothing is acked or resent: a lost update is overtaken by the next one
+//! 33ms later, which is what the unreliable channel is for. A lost LEFT is
+//! covered by each side timing quiet cursors out on its own.
+//!
+//! Both binaries send and receive through `bevy_udp_net`, with a
+//! [`RECV_BUFFER`] far bigger than any message here.
+
+#[cfg(feature = "gui")]
+use bevy::prelude::*;
+use std::fmt;
+
+/// Pointer updates a client sends per second
+pub const SEND_HZ: f32 = 30.0;
+/// Seconds without an update before a cursor is dropped
+pub const TIMEOUT_SECS: f32 = 2.0;
+/// Largest datagram accepted, in bytes; a bigger one is dropped and
+/// counted, never parsed cut short
+pub const RECV_BUFFER: usize = 1024;
+#[cfg(feature = "gui")]
+pub const DOT_RADIUS: f32 = 8.0;
+
+pub enum CursorMessage {
+    Moved { x: f32, y: f32 },
+    Relayed { id: u32, x: f32, y: f32 },
+    Left { id: u32 },
+}
+
+/// A coordinate, clamped to the window.
+fn fraction(word: &str) -> Option<f32> {
+    let value: f32 = word.parse().ok()?;
+    value.is_finite().then(|| value.clamp(0.0, 1.0))
+}
+
+impl CursorMessage {
+    pub fn parse(text: &str) -> Option<Self> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            ["CURSOR", x, y] => Some(Self::Moved {
+                x: fraction(x)?,
+                y: fraction(y)?,
+            }),
+            ["CURSOR", id, x, y] => Some(Self::Relayed {
+                id: id.parse().ok()?,
+                x: fraction(x)?,
+                y: fraction(y)?,
+            }),
+            ["LEFT", id] => Some(Self::Left {
+                id: id.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CursorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Moved { x, y } => write!(f, "CURSOR {:.4} {:.4}", x, y),
+            Self::Relayed { id, x, y } => write!(f, "CURSOR {} {:.4} {:.4}", id, x, y),
+            Self::Left { id } => write!(f, "LEFT {}", id),
+        }
+    }
+}
+
+/// The colour of client `id`'s dot; consecutive ids get far-apart hues.
+#[cfg(feature = "gui")]
+pub fn color(id: u32) -> Color {
+    Color::hsl((id as f32 * 137.5) % 360.0, 0.8, 0.6)
+}
+
+/// Where the pointer is over `window`, as a fraction of it.
+#[cfg(feature = "gui")]
+pub fn pointer_fraction(window: &Window) -> Option<Vec2> {
+    let position = window.cursor_position()?;
+    Some(position / Vec2::new(window.width(), window.height()))
+}
+
+/// Draws client `id`'s cursor at `fraction` of `window`, under a default
+/// `Camera2dBundle` (origin in the middle, y up).
+#[cfg(feature = "gui")]
+pub fn draw_dot(gizmos: &mut Gizmos, window: &Window, fraction: Vec2, id: u32) {
+    let center = Vec2::new(
+        (fraction.x - 0.5) * window.width(),
+        (0.5 - fraction.y) * window.height(),
+    );
+    // Gizmos only draw outlines; enough rings close together read as a dot
+    for ring in 1..=4 {
+        gizmos.circle_2d(center, DOT_RADIUS * ring as f32 / 4.0, color(id));
+    }
+}
@@ -0,0 +1,198 @@
+//! Cursor Presence Server
+//! Usage: cargo run --bin cursor_server -- --port 12500
+//!
+//! Each cursor_client streams its pointer here; the server gives every
+//! client a number and a colour, relays its pointer to all the others and
+//! draws every pointer as a dot. A client that goes quiet for
+//! `TIMEOUT_SECS` (closed, or pointer outside its window) is dropped and
+//! the others are told. See `lib.rs` for the messages.
+//!
+//! Built without the `gui` feature it opens no window and only relays.
+
+#[cfg(not(feature = "gui"))]
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::prelude::*;
+use bevy_udp_net::{NetworkMessageReceived, NetworkSocket, UdpNetworkPlugin};
+use clap::Parser;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+#[cfg(not(feature = "gui"))]
+use std::time::Duration;
+
+#[cfg(not(feature = "gui"))]
+use cursors::SEND_HZ;
+#[cfg(feature = "gui")]
+use cursors::draw_dot;
+use cursors::{CursorMessage, RECV_BUFFER, TIMEOUT_SECS};
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Port to listen on
+    #[arg(short, long, default_value_t = 12500)]
+    port: u16,
+}
+
+struct Peer {
+    id: u32,
+    position: Vec2,
+    last_heard: f32,
+}
+
+#[derive(Resource, Default)]
+struct Peers {
+    by_addr: HashMap<SocketAddr, Peer>,
+    next_id: u32,
+}
+
+impl Peers {
+    /// Sends `message` to every client but `from`.
+    fn relay(&self, socket: &NetworkSocket, from: Option<SocketAddr>, message: &CursorMessage) {
+        let text = message.to_string();
+        for addr in self.by_addr.keys().filter(|addr| Some(**addr) != from) {
+            let _ = socket.send_to(&text, addr);
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+struct StatusText;
+
+fn main() {
+    let args = Args::parse();
+
+    let addr = format!("0.0.0.0:{}", args.port);
+    let socket = match NetworkSocket::bind(&addr, RECV_BUFFER) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error: could not bind port {}: {}", args.port, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Cursor Server listening on {}", addr);
+
+    let mut app = App::new();
+    #[cfg(feature = "gui")]
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Cursor Server".to_string(),
+            ..default()
+        }),
+        ..default()
+    }))
+    .add_systems(Startup, setup_ui)
+    .add_systems(
+        Update,
+        (draw_cursors, update_status).after(drop_quiet_peers),
+    );
+    // Relaying at the clients' send rate keeps up with every update
+    #[cfg(not(feature = "gui"))]
+    app.add_plugins(
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f32(
+            1.0 / SEND_HZ,
+        ))),
+    );
+    app.add_plugins(UdpNetworkPlugin::new(socket))
+        .init_resource::<Peers>()
+        .add_systems(Update, (relay_cursors, drop_quiet_peers).chain())
+        .run();
+}
+
+#[cfg(feature = "gui")]
+fn setup_ui(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 18.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        StatusText,
+    ));
+}
+
+fn relay_cursors(
+    time: Res<Time>,
+    socket: Res<NetworkSocket>,
+    mut received: EventReader<NetworkMessageReceived>,
+    mut peers: ResMut<Peers>,
+) {
+    let now = time.elapsed_seconds();
+    for message in received.read() {
+        let addr = message.from;
+        let Some(CursorMessage::Moved { x, y }) = CursorMessage::parse(&message.text()) else {
+            continue;
+        };
+        let position = Vec2::new(x, y);
+        let id = match peers.by_addr.get_mut(&addr) {
+            Some(peer) => {
+                peer.position = position;
+                peer.last_heard = now;
+                peer.id
+            }
+            None => {
+                let id = peers.next_id;
+                peers.next_id += 1;
+                println!("Cursor #{} joined from {}", id, addr);
+                peers.by_addr.insert(
+                    addr,
+                    Peer {
+                        id,
+                        position,
+                        last_heard: now,
+                    },
+                );
+                id
+            }
+        };
+        peers.relay(&socket, Some(addr), &CursorMessage::Relayed { id, x, y });
+    }
+}
+
+fn drop_quiet_peers(time: Res<Time>, socket: Res<NetworkSocket>, mut peers: ResMut<Peers>) {
+    let now = time.elapsed_seconds();
+    let mut left = Vec::new();
+    peers.by_addr.retain(|addr, peer| {
+        let quiet = now - peer.last_heard > TIMEOUT_SECS;
+        if quiet {
+            println!("Cursor #{} from {} went quiet", peer.id, addr);
+            left.push(peer.id);
+        }
+        !quiet
+    });
+    for id in left {
+        peers.relay(&socket, None, &CursorMessage::Left { id });
+    }
+}
+
+#[cfg(feature = "gui")]
+fn draw_cursors(mut gizmos: Gizmos, windows: Query<&Window>, peers: Res<Peers>) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    for peer in peers.by_addr.values() {
+        draw_dot(&mut gizmos, window, peer.position, peer.id);
+    }
+}
+
+#[cfg(feature = "gui")]
+fn update_status(peers: Res<Peers>, mut texts: Query<&mut Text, With<StatusText>>) {
+    if !peers.is_changed() {
+        return;
+    }
+    for mut text in &mut texts {
+        text.sections[0].value = match peers.by_addr.len() {
+            0 => "Waiting for cursor_client windows...".to_string(),
+            count => format!("{} cursors", count),
+        };
+    }
+}
@@ -0,0 +1,324 @@
+//! Fleet supervisor
+//! Usage: cargo run -p fleet -- --instances 4 --base-port 12345 [-- <server args>]
+//!
+//! Launches several `--headless` servers on consecutive ports, one shard or
+//! room each, and keeps them running: an instance that exits is started
+//! again after `--restart-delay`, and one that keeps crashing right after
+//! starting waits twice as long each time. Every instance prints a status
+//! line (see the server's `headless` module); the fleet adds them up and
+//! prints the totals. Everything else an instance prints is passed through,
+//! prefixed with its port.
+//!
+//! Instances also run with `--shard-link`, so the fleet can route messages
+//! between them: a `forward` line from one instance is written to the
+//! target's stdin as `deliver`, or bounced back as `undeliverable`. See the
+//! server's `shards` module for the line format.
+
+use clap::Parser;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// An instance that runs at least this long before exiting is treated as
+/// having been healthy, and restarts after the normal delay again
+const STABLE_SECS: u64 = 30;
+/// Longest wait before restarting an instance that keeps crashing
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Server instances to run
+    #[arg(short = 'n', long, default_value_t = 3, value_parser = clap::value_parser!(u16).range(1..))]
+    instances: u16,
+
+    /// Port of the first instance; the others follow on consecutive ports
+    #[arg(long, default_value_t = 12345)]
+    base_port: u16,
+
+    /// Server binary (default: bevy-networking-server next to this one)
+    #[arg(long)]
+    server: Option<PathBuf>,
+
+    /// Seconds to wait before restarting an instance that exited
+    #[arg(long, default_value_t = 2.0)]
+    restart_delay: f32,
+
+    /// Seconds between fleet totals
+    #[arg(long, default_value_t = 5.0)]
+    report_secs: f32,
+
+    /// Passed to every instance, after `--`; don't repeat `--port` here
+    #[arg(last = true)]
+    server_args: Vec<String>,
+}
+
+/// The last status line an instance printed.
+#[derive(Clone, Copy, Default, Debug)]
+struct Status {
+    players: u64,
+    spectators: u64,
+    packets_sent: u64,
+    packets_received: u64,
+}
+
+impl Status {
+    /// `status port=.. players=.. ...`; unknown fields are ignored
+    fn parse(line: &str) -> Option<Self> {
+        let fields = line.strip_prefix("status ")?;
+        let mut status = Status::default();
+        for field in fields.split_whitespace() {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            let Ok(value) = value.parse() else {
+                continue;
+            };
+            match key {
+                "players" => status.players = value,
+                "spectators" => status.spectators = value,
+                "packets_sent" => status.packets_sent = value,
+                "packets_received" => status.packets_received = value,
+                _ => {}
+            }
+        }
+        Some(status)
+    }
+}
+
+/// The stdin of every running instance, by port.
+#[derive(Clone, Default)]
+struct Router {
+    links: Arc<Mutex<HashMap<u16, ChildStdin>>>,
+}
+
+impl Router {
+    fn connect(&self, port: u16, stdin: ChildStdin) {
+        self.links.lock().unwrap().insert(port, stdin);
+    }
+
+    fn disconnect(&self, port: u16) {
+        self.links.lock().unwrap().remove(&port);
+    }
+
+    /// Whether the line reached `port`. A lost pipe means the instance is
+    /// gone, so it is dropped until the instance restarts.
+    fn send(&self, port: u16, line: &str) -> bool {
+        let mut links = self.links.lock().unwrap();
+        let Some(stdin) = links.get_mut(&port) else {
+            return false;
+        };
+        if writeln!(stdin, "{}", line).is_err() {
+            links.remove(&port);
+            return false;
+        }
+        true
+    }
+
+    /// `<to_port> <sender> <text>`, the rest of a `forward` line from `from`
+    fn forward(&self, from: u16, rest: &str) {
+        let mut words = rest.splitn(3, ' ');
+        let (Some(to), Some(sender), Some(text)) = (words.next(), words.next(), words.next())
+        else {
+            eprintln!("[fleet] malformed forward from port {}: {}", from, rest);
+            return;
+        };
+        let delivered = to
+            .parse::<u16>()
+            .is_ok_and(|to| self.send(to, &format!("deliver {} {} {}", from, sender, text)));
+        if !delivered {
+            self.send(from, &format!("undeliverable {} {}", to, sender));
+        }
+    }
+}
+
+struct Instance {
+    port: u16,
+    child: Option<Child>,
+    started: Instant,
+    /// When a stopped instance is due to start again
+    restart_at: Instant,
+    restart_delay: Duration,
+    restarts: u32,
+    status: Arc<Mutex<Option<Status>>>,
+}
+
+impl Instance {
+    fn new(port: u16, restart_delay: Duration) -> Self {
+        Self {
+            port,
+            child: None,
+            started: Instant::now(),
+            restart_at: Instant::now(),
+            restart_delay,
+            restarts: 0,
+            status: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn start(&mut self, args: &Args, server: &Path, router: &Router) -> std::io::Result<()> {
+        let mut child = Command::new(server)
+            .arg("--headless")
+            .arg("--shard-link")
+            .arg("--port")
+            .arg(self.port.to_string())
+            .args(&args.server_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        router.connect(self.port, child.stdin.take().expect("stdin is piped"));
+        let router = router.clone();
+        let status = self.status.clone();
+        let port = self.port;
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if let Some(rest) = line.strip_prefix("forward ") {
+                    router.forward(port, rest);
+                    continue;
+                }
+                match Status::parse(&line) {
+                    Some(parsed) => *status.lock().unwrap() = Some(parsed),
+                    None => println!("[{}] {}", port, line),
+                }
+            }
+        });
+        self.child = Some(child);
+        self.started = Instant::now();
+        Ok(())
+    }
+
+    /// Notices an instance that exited, and starts it again once its delay
+    /// has passed.
+    fn supervise(&mut self, args: &Args, server: &Path, router: &Router) {
+        let normal_delay = Duration::from_secs_f32(args.restart_delay.max(0.0));
+        if let Some(child) = &mut self.child {
+            match child.try_wait() {
+                Ok(None) => return,
+                Ok(Some(exit)) => {
+                    self.restart_delay = if self.started.elapsed().as_secs() >= STABLE_SECS {
+                        normal_delay
+                    } else {
+                        (self.restart_delay * 2).clamp(normal_delay, MAX_RESTART_DELAY)
+                    };
+                    println!(
+                        "[fleet] instance on port {} exited ({}), restarting in {:.1}s",
+                        self.port,
+                        exit,
+                        self.restart_delay.as_secs_f32()
+                    );
+                }
+                Err(e) => {
+                    eprintln!("[fleet] could not check port {}: {}", self.port, e);
+                    return;
+                }
+            }
+            self.child = None;
+            router.disconnect(self.port);
+            *self.status.lock().unwrap() = None;
+            self.restart_at = Instant::now() + self.restart_delay;
+            return;
+        }
+        if Instant::now() < self.restart_at {
+            return;
+        }
+        self.restarts += 1;
+        if let Err(e) = self.start(args, server, router) {
+            eprintln!("[fleet] could not restart port {}: {}", self.port, e);
+            self.restart_at = Instant::now() + self.restart_delay;
+        }
+    }
+}
+
+fn default_server_path() -> std::io::Result<PathBuf> {
+    let name = format!("bevy-networking-server{}", std::env::consts::EXE_SUFFIX);
+    Ok(std::env::current_exe()?.with_file_name(name))
+}
+
+fn report(instances: &[Instance]) {
+    let mut running = 0;
+    let mut total = Status::default();
+    for instance in instances {
+        let Some(status) = *instance.status.lock().unwrap() else {
+            continue;
+        };
+        running += 1;
+        total.players += status.players;
+        total.spectators += status.spectators;
+        total.packets_sent += status.packets_sent;
+        total.packets_received += status.packets_received;
+    }
+    let restarts: u32 = instances.iter().map(|instance| instance.restarts).sum();
+    println!(
+        "[fleet] {}/{} reporting, {} players, {} spectators, {} packets sent, {} received, {} restarts",
+        running,
+        instances.len(),
+        total.players,
+        total.spectators,
+        total.packets_sent,
+        total.packets_received,
+        restarts
+    );
+}
+
+fn main() {
+    let args = Args::parse();
+    let server = match args.server.clone().map_or_else(default_server_path, Ok) {
+        Ok(server) if server.exists() => server,
+        Ok(server) => {
+            eprintln!(
+                "Error: no server binary at {} (build it first, or pass --server)",
+                server.display()
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: could not locate the server binary: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let Some(last_port) = args.base_port.checked_add(args.instances - 1) else {
+        eprintln!(
+            "Error: {} instances don't fit above port {}",
+            args.instances, args.base_port
+        );
+        std::process::exit(1);
+    };
+    let restart_delay = Duration::from_secs_f32(args.restart_delay.max(0.0));
+    let router = Router::default();
+    let mut instances: Vec<Instance> = (args.base_port..=last_port)
+        .map(|port| Instance::new(port, restart_delay))
+        .collect();
+    for instance in &mut instances {
+        if let Err(e) = instance.start(&args, &server, &router) {
+            eprintln!("Error: could not start {}: {}", server.display(), e);
+            std::process::exit(1);
+        }
+    }
+    println!(
+        "[fleet] {} instances on ports {}-{}",
+        instances.len(),
+        args.base_port,
+        last_port
+    );
+
+    let report_interval = Duration::from_secs_f32(args.report_secs.max(1.0));
+    let mut next_report = Instant::now() + report_interval;
+    loop {
+        for instance in &mut instances {
+            instance.supervise(&args, &server, &router);
+        }
+        if Instant::now() >= next_report {
+            report(&instances);
+            next_report += report_interval;
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
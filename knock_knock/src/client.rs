@@ -1,14 +1,48 @@
 //! Knock Knock Client
 //! Usage: cargo run --bin knock_client -- --server 127.0.0.1:50051
 //!
-//! Sends "KNOCK KNOCK" to server and waits for "WHO IS THERE?" reply.
+//! Sends "KNOCK KNOCK" to server and waits for "WHO IS THERE?" reply, both
+//! `common::message` messages, encoded with bincode like the server's.
+//!
+//! The server streams its joke database on the first knock; a progress bar
+//! shows how far it has got. Once the first few jokes are in, "WHO IS THERE?"
+//! is answered with a joke's name and "<Name> WHO?" with its punchline, while
+//! the rest keeps loading. Which joke comes next is drawn from the seed the
+//! stream starts with (see `common::shared_rng`), the same for every client.
+//!
+//! If the server runs with `--quiz`, knocking joins its quiz: questions show
+//! up with a countdown, the answer field sends answers, and the scoreboard
+//! lists every player.
+//!
+//! The joke field sends a joke of our own, `Name|Punchline`, over a
+//! reliable stream to the server (see `contribute`). Once the server's
+//! operator approves it, every client tells it too.
 
 use bevy::prelude::*;
+use bevy_udp_net::{NetworkMessageReceived, NetworkSocket, SendNetworkMessage, UdpNetworkPlugin};
 use clap::Parser;
-use std::net::UdpSocket;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use common::message::{Message, Payload, Verdict};
+use common::reliable::{ReliableMessage, ReliableReceiver, ReliableSender};
+use common::replicate::{
+    ReplicateResourceExt, ReplicationIncoming, ReplicationPlugin, ReplicationRole,
+};
+use common::shared_rng::SharedRng;
+use common::snapshot::{SnapshotChunk, SnapshotProgress};
+use common::ui::accessibility::KeyboardNavigationPlugin;
+use common::ui::clipboard::ClipboardPlugin;
+use common::ui::text_input::{TextInput, TextInputPlugin, TextInputSubmitted, spawn_text_input};
+use common::ui::widgets::{ButtonFlash, UiTheme, WidgetsPlugin, header, log_text, spawn_button};
+
+mod contribute;
+mod quiz;
+
+use contribute::{Contribution, check_joke};
+use quiz::Scoreboard;
+
+/// Submissions in flight (sent but unacked) at most
+const SUBMIT_WINDOW: usize = 4;
+/// Resend a submission if it isn't acked within this many seconds
+const SUBMIT_RESEND_SECS: f32 = 0.5;
 
 #[derive(Parser, Resource, Debug, Clone)]
 #[command(version, about, long_about = None)]
@@ -16,12 +50,10 @@ struct Args {
     /// Server address to connect to
     #[arg(short, long, default_value = "127.0.0.1:50051")]
     server: String,
-}
 
-#[derive(Resource)]
-struct NetworkState {
-    received_message: Arc<Mutex<Option<String>>>,
-    socket: Arc<UdpSocket>,
+    /// Largest datagram we accept, in bytes; bigger ones are dropped and counted
+    #[arg(long, default_value_t = 1024)]
+    recv_buffer: usize,
 }
 
 #[derive(Resource, Default)]
@@ -35,134 +67,495 @@ struct LogText;
 #[derive(Component)]
 struct KnockButton;
 
+#[derive(Component)]
+struct AnswerInput;
+
+#[derive(Component)]
+struct JokeInput;
+
+#[derive(Component)]
+struct QuizText;
+
+#[derive(Component)]
+struct StreamProgressBar;
+
+#[derive(Component)]
+struct StreamProgressFill;
+
+#[derive(Component)]
+struct StreamProgressText;
+
+/// Jokes streamed from the server, usable before the stream completes.
+#[derive(Resource, Default)]
+struct JokeBook {
+    stream: ReliableReceiver,
+    progress: SnapshotProgress,
+    /// (name, punchline)
+    jokes: Vec<(String, String)>,
+    /// From the server's `Seed`, which comes before the jokes
+    rng: Option<SharedRng>,
+    /// Jokes told so far
+    next: usize,
+    /// The joke we're in the middle of, waiting for "<Name> WHO?"
+    telling: Option<usize>,
+}
+
+/// Jokes of our own on their way to the server.
+#[derive(Resource)]
+struct Submissions(ReliableSender);
+
+impl Default for Submissions {
+    fn default() -> Self {
+        Self(ReliableSender::new(SUBMIT_WINDOW, SUBMIT_RESEND_SECS))
+    }
+}
+
+/// The server's quiz as last heard; empty until it asks something.
+#[derive(Resource, Default)]
+struct QuizView {
+    /// (id, text, elapsed seconds when it closes)
+    question: Option<(u32, String, f32)>,
+}
+
 fn main() {
     let args = Args::parse();
+    let socket = match NetworkSocket::bind("0.0.0.0:0", args.recv_buffer) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error: could not bind a socket: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Ok(addr) = socket.local_addr() {
+        println!("Knock Knock Client bound to {}", addr);
+    }
 
     App::new()
-        .add_plugins(DefaultPlugins)
+        .add_plugins((
+            DefaultPlugins,
+            WidgetsPlugin,
+            TextInputPlugin,
+            ClipboardPlugin,
+            KeyboardNavigationPlugin,
+        ))
+        .add_plugins(UdpNetworkPlugin::new(socket))
+        .insert_resource(knock_knock_theme())
         .insert_resource(args)
         .init_resource::<ClientState>()
-        .add_systems(Startup, (setup_network, setup_ui))
+        .init_resource::<QuizView>()
+        .init_resource::<JokeBook>()
+        .init_resource::<Submissions>()
+        .add_plugins(ReplicationPlugin {
+            role: ReplicationRole::Client,
+        })
+        .replicate_resource::<Scoreboard>()
+        .add_systems(Startup, setup_ui)
         .add_systems(
             Update,
-            (handle_network_messages, knock_button_system, update_log_ui),
+            (
+                handle_network_messages,
+                knock_button_system,
+                update_log_ui,
+                report_truncated_packets,
+                submit_answers,
+                update_quiz_ui,
+                update_stream_progress,
+                submit_jokes,
+                flush_submissions.after(submit_jokes),
+            ),
         )
         .run();
 }
 
-fn setup_network(mut commands: Commands) {
-    let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").expect("Failed to bind socket"));
-    println!(
-        "Knock Knock Client bound to {}",
-        socket.local_addr().unwrap()
-    );
-
-    socket
-        .set_nonblocking(true)
-        .expect("Failed to set non-blocking");
-
-    let received_message = Arc::new(Mutex::new(None));
-    let socket_clone = socket.clone();
-    let received_clone = received_message.clone();
-
-    thread::spawn(move || {
-        let mut buf = [0u8; 1024];
-        loop {
-            match socket_clone.recv_from(&mut buf) {
-                Ok((size, _addr)) => {
-                    let message = String::from_utf8_lossy(&buf[..size]).to_string();
-                    let mut received = received_clone.lock().unwrap();
-                    *received = Some(message);
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(10));
-                }
-                Err(_) => break,
-            }
-        }
-    });
-
-    commands.insert_resource(NetworkState {
-        received_message,
-        socket,
-    });
+/// Knock Knock uses bigger header and log text than the Ping/Pong demo.
+fn knock_knock_theme() -> UiTheme {
+    UiTheme {
+        header_font_size: 24.0,
+        log_font_size: 18.0,
+        button_font_size: 20.0,
+        ..default()
+    }
 }
 
-fn setup_ui(mut commands: Commands, args: Res<Args>) {
+fn setup_ui(mut commands: Commands, args: Res<Args>, theme: Res<UiTheme>) {
     commands.spawn(Camera2dBundle::default());
 
-    commands.spawn(
-        TextBundle::from_section(
-            format!("Knock Knock Client -> {}", args.server),
-            TextStyle {
-                font_size: 24.0,
-                color: Color::rgb(0.9, 0.9, 0.9),
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(10.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
-    );
+    commands.spawn(header(
+        &theme,
+        format!("Knock Knock Client -> {}", args.server),
+    ));
 
     commands.spawn((
-        TextBundle::from_section(
-            "Click KNOCK to send message...\n",
-            TextStyle {
-                font_size: 18.0,
-                color: Color::WHITE,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(50.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
+        log_text(&theme, "Click KNOCK to send message...\n"),
         LogText,
     ));
 
+    spawn_button(&mut commands, &theme, "KNOCK KNOCK", 150.0, KnockButton);
+
     commands
         .spawn((
-            ButtonBundle {
+            NodeBundle {
                 style: Style {
-                    width: Val::Px(150.0),
-                    height: Val::Px(50.0),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
                     position_type: PositionType::Absolute,
-                    bottom: Val::Px(20.0),
-                    right: Val::Px(20.0),
+                    bottom: Val::Px(theme.padding),
+                    left: Val::Px(theme.padding),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(4.0),
                     ..default()
                 },
-                background_color: Color::rgb(0.3, 0.5, 0.9).into(),
+                visibility: Visibility::Hidden,
                 ..default()
             },
-            KnockButton,
+            StreamProgressBar,
         ))
         .with_children(|parent| {
-            parent.spawn(TextBundle::from_section(
-                "KNOCK KNOCK",
-                TextStyle {
-                    font_size: 20.0,
-                    color: Color::WHITE,
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: theme.text_color,
+                        ..default()
+                    },
+                ),
+                StreamProgressText,
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(300.0),
+                        height: Val::Px(12.0),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.2, 0.2, 0.2).into(),
                     ..default()
-                },
+                })
+                .with_children(|track| {
+                    track.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(0.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: theme.button_color.into(),
+                            ..default()
+                        },
+                        StreamProgressFill,
+                    ));
+                });
+        });
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: theme.log_font_size,
+                        color: theme.text_color,
+                        ..default()
+                    },
+                ),
+                QuizText,
             ));
+            spawn_text_input(
+                parent,
+                TextInput::new("Answer (Enter to send)").clear_on_submit(),
+                Val::Px(300.0),
+                AnswerInput,
+            );
+            spawn_text_input(
+                parent,
+                TextInput::new("Your joke: Name|Punchline (Enter to send)").clear_on_submit(),
+                Val::Px(300.0),
+                JokeInput,
+            );
         });
 }
 
-fn handle_network_messages(network: Res<NetworkState>, mut client_state: ResMut<ClientState>) {
-    let mut received = network.received_message.lock().unwrap();
-    if let Some(message) = received.take() {
-        client_state.log.push(format!("[Rx]: {}", message.trim()));
+fn handle_network_messages(
+    time: Res<Time>,
+    mut received: EventReader<NetworkMessageReceived>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+    args: Res<Args>,
+    mut client_state: ResMut<ClientState>,
+    mut quiz: ResMut<QuizView>,
+    mut jokes: ResMut<JokeBook>,
+    mut submissions: ResMut<Submissions>,
+    mut replication: EventWriter<ReplicationIncoming>,
+) {
+    for event in received.read() {
+        let message = match Payload::read(&event.bytes) {
+            Ok(Payload::Message(message)) => message,
+            Ok(Payload::Text(text)) => {
+                receive_text(
+                    &text,
+                    &mut outgoing,
+                    &args,
+                    &mut client_state,
+                    &mut jokes,
+                    &mut submissions,
+                    &mut replication,
+                );
+                continue;
+            }
+            Err(e) => {
+                client_state.log.push(format!(
+                    "[Warn]: dropped a datagram from {}: {}",
+                    event.from, e
+                ));
+                continue;
+            }
+        };
+        match &message {
+            Message::Question { id, secs, text } => {
+                quiz.question = Some((*id, text.clone(), time.elapsed_seconds() + secs));
+            }
+            Message::Closed { id, .. }
+                if quiz.question.as_ref().is_some_and(|(open, ..)| open == id) =>
+            {
+                quiz.question = None;
+            }
+            Message::Verdict {
+                verdict: Verdict::Correct,
+                ..
+            } => {
+                quiz.question = None;
+            }
+            _ => {}
+        }
+        client_state.log.push(format!("[Rx]: {}", message));
+        tell_joke(
+            &message,
+            &mut outgoing,
+            &args,
+            &mut jokes,
+            &mut client_state,
+        );
+        while client_state.log.len() > 20 {
+            client_state.log.remove(0);
+        }
+    }
+}
+
+/// What comes as text: replication, and the reliable stream of jokes,
+/// history and news of our submissions, with its acks for theirs.
+fn receive_text(
+    text: &str,
+    outgoing: &mut EventWriter<SendNetworkMessage>,
+    args: &Args,
+    client_state: &mut ClientState,
+    jokes: &mut JokeBook,
+    submissions: &mut Submissions,
+    replication: &mut EventWriter<ReplicationIncoming>,
+) {
+    if text.starts_with("REPL ") {
+        // The scoreboard is refreshed on screen; no need to log it
+        replication.send(ReplicationIncoming(text.to_string()));
+        return;
+    }
+    match ReliableMessage::parse(text) {
+        Some(ReliableMessage::Data { seq, payload }) => {
+            let (ack, delivered) = jokes.stream.receive(seq, payload);
+            if let Some(ack) = ack {
+                outgoing.send(SendNetworkMessage::new(args.server.as_str(), ack));
+            }
+            for payload in delivered {
+                if let Some(seed) = payload.strip_prefix("Seed ") {
+                    jokes.rng = u64::from_str_radix(seed, 16).ok().map(SharedRng::new);
+                } else if let Some(line) = payload.strip_prefix("History ") {
+                    receive_history_line(line, client_state);
+                } else if let Some(contribution) = Contribution::parse(&payload) {
+                    receive_contribution(contribution, jokes, client_state);
+                } else {
+                    receive_joke_chunk(jokes, &payload, client_state);
+                }
+            }
+        }
+        // For our submissions
+        Some(ReliableMessage::Ack { seq }) => submissions.0.ack(seq),
+        None => {
+            client_state.log.push(format!(
+                "[Warn]: text that isn't a message: {}",
+                text.trim()
+            ));
+            while client_state.log.len() > 20 {
+                client_state.log.remove(0);
+            }
+        }
+    }
+}
+
+/// An earlier exchange, streamed after the jokes when we first knock.
+fn receive_history_line(line: &str, client_state: &mut ClientState) {
+    client_state.log.push(format!("[History]: {}", line));
+    while client_state.log.len() > 20 {
+        client_state.log.remove(0);
+    }
+}
+
+/// What became of a joke we sent, or a joke someone's got approved.
+fn receive_contribution(
+    contribution: Contribution,
+    jokes: &mut JokeBook,
+    client_state: &mut ClientState,
+) {
+    let line = match contribution {
+        Contribution::Pending { id, name } => format!(
+            "[Info]: {} is #{}, waiting for the server's operator",
+            name, id
+        ),
+        Contribution::Refused { reason } => format!("[Info]: Joke refused: {}", reason),
+        Contribution::Approved { id, name } => format!("[Info]: {} (#{}) was approved", name, id),
+        Contribution::Declined { id, name } => format!("[Info]: {} (#{}) was declined", name, id),
+        Contribution::Joke { joke } => {
+            let Some((name, punchline)) = joke.split_once('|') else {
+                return;
+            };
+            jokes.jokes.push((name.to_string(), punchline.to_string()));
+            format!("[Info]: New joke: {}, {} in all", name, jokes.jokes.len())
+        }
+        // Only we send these
+        Contribution::Submit { .. } => return,
+    };
+    client_state.log.push(line);
+    while client_state.log.len() > 20 {
+        client_state.log.remove(0);
+    }
+}
+
+fn receive_joke_chunk(jokes: &mut JokeBook, payload: &str, client_state: &mut ClientState) {
+    let Some(chunk) = SnapshotChunk::parse(payload) else {
+        return;
+    };
+    if chunk.name != "jokes" {
+        return;
+    }
+    let was_ready = jokes.progress.essential_ready();
+    let records: Vec<(String, String)> = jokes
+        .progress
+        .accept(&chunk)
+        .filter_map(|record| record.split_once('|'))
+        .map(|(name, punchline)| (name.to_string(), punchline.to_string()))
+        .collect();
+    jokes.jokes.extend(records);
+
+    if !was_ready && jokes.progress.essential_ready() {
+        client_state.log.push(format!(
+            "[Info]: {} jokes loaded, ready to tell them",
+            jokes.jokes.len()
+        ));
+    }
+    if jokes.progress.is_complete() {
+        client_state
+            .log
+            .push(format!("[Info]: All {} jokes loaded", jokes.jokes.len()));
+    }
+}
+
+/// Plays our side of the joke: a name for "WHO IS THERE?", the punchline
+/// for "<Name> WHO?".
+fn tell_joke(
+    message: &Message,
+    outgoing: &mut EventWriter<SendNetworkMessage>,
+    args: &Args,
+    jokes: &mut JokeBook,
+    client_state: &mut ClientState,
+) {
+    match message {
+        Message::WhoIsThere => {
+            if !jokes.progress.essential_ready() || jokes.jokes.is_empty() {
+                client_state
+                    .log
+                    .push("[Info]: Still loading jokes...".to_string());
+                return;
+            }
+            // A server that sends no seed gets its jokes in order
+            let index = match &jokes.rng {
+                Some(rng) => rng.index_at(jokes.next as u64, jokes.jokes.len()),
+                None => jokes.next % jokes.jokes.len(),
+            };
+            jokes.next += 1;
+            jokes.telling = Some(index);
+            let name = Message::Name {
+                name: jokes.jokes[index].0.clone(),
+            };
+            outgoing.send(SendNetworkMessage::new(args.server.as_str(), name.encode()));
+            client_state.log.push(format!("[Tx]: {}", name));
+        }
+        Message::NameWho { name: asked } => {
+            let Some(index) = jokes.telling else {
+                return;
+            };
+            let (name, punchline) = &jokes.jokes[index];
+            if asked.eq_ignore_ascii_case(name) {
+                client_state.log.push(format!("[Joke]: {}", punchline));
+                jokes.telling = None;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Shows the joke stream's progress until it completes.
+fn update_stream_progress(
+    jokes: Res<JokeBook>,
+    mut bar: Query<&mut Visibility, With<StreamProgressBar>>,
+    mut fill: Query<&mut Style, With<StreamProgressFill>>,
+    mut text: Query<&mut Text, With<StreamProgressText>>,
+) {
+    if !jokes.is_changed() {
+        return;
+    }
+    let progress = &jokes.progress;
+    let visible = progress.total.is_some() && !progress.is_complete();
+    for mut visibility in bar.iter_mut() {
+        *visibility = if visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+    for mut style in fill.iter_mut() {
+        style.width = Val::Percent(progress.fraction() * 100.0);
+    }
+    for mut text in text.iter_mut() {
+        text.sections[0].value = format!(
+            "Loading jokes: {}/{} chunks ({} jokes)",
+            progress.received,
+            progress.total.unwrap_or(0),
+            jokes.jokes.len()
+        );
+    }
+}
+
+fn report_truncated_packets(
+    network: Res<NetworkSocket>,
+    mut client_state: ResMut<ClientState>,
+    mut reported: Local<u64>,
+) {
+    let truncated = network.truncated_packets();
+    if truncated > *reported {
+        client_state.log.push(format!(
+            "[Warn]: {} oversized packet(s) dropped, raise --recv-buffer",
+            truncated - *reported
+        ));
         if client_state.log.len() > 20 {
             client_state.log.remove(0);
         }
+        *reported = truncated;
     }
 }
 
@@ -174,16 +567,136 @@ fn update_log_ui(client_state: Res<ClientState>, mut query: Query<&mut Text, Wit
     }
 }
 
+/// Sends whatever was typed in the answer field as the answer to the open
+/// question.
+fn submit_answers(
+    mut submitted: EventReader<TextInputSubmitted>,
+    answer_input: Query<(), With<AnswerInput>>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+    args: Res<Args>,
+    quiz: Res<QuizView>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for event in submitted.read() {
+        if answer_input.get(event.entity).is_err() || event.value.trim().is_empty() {
+            continue;
+        }
+        let Some((id, ..)) = &quiz.question else {
+            client_state
+                .log
+                .push("[Info]: No open question".to_string());
+            continue;
+        };
+        let answer = Message::Answer {
+            id: *id,
+            text: event.value.trim().to_string(),
+        };
+        client_state.log.push(format!("[Tx]: {}", answer));
+        outgoing.send(SendNetworkMessage::new(
+            args.server.as_str(),
+            answer.encode(),
+        ));
+        if client_state.log.len() > 20 {
+            client_state.log.remove(0);
+        }
+    }
+}
+
+/// Sends whatever was typed in the joke field to the server, if it looks
+/// like a joke.
+fn submit_jokes(
+    mut submitted: EventReader<TextInputSubmitted>,
+    joke_input: Query<(), With<JokeInput>>,
+    mut submissions: ResMut<Submissions>,
+    mut client_state: ResMut<ClientState>,
+) {
+    for event in submitted.read() {
+        if joke_input.get(event.entity).is_err() || event.value.trim().is_empty() {
+            continue;
+        }
+        let line = match check_joke(&event.value) {
+            Ok((name, punchline)) => {
+                let submit = Contribution::Submit {
+                    joke: format!("{}|{}", name, punchline),
+                }
+                .to_string();
+                submissions.0.push(submit.clone());
+                format!("[Tx]: {}", submit)
+            }
+            Err(reason) => format!("[Info]: Not sent: {}", reason),
+        };
+        client_state.log.push(line);
+        if client_state.log.len() > 20 {
+            client_state.log.remove(0);
+        }
+    }
+}
+
+/// Sends submissions as the window allows, and resends any whose ack is
+/// overdue.
+fn flush_submissions(
+    time: Res<Time>,
+    args: Res<Args>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+    mut submissions: ResMut<Submissions>,
+) {
+    if submissions.0.is_idle() {
+        return;
+    }
+    for line in submissions.0.poll(time.elapsed_seconds()) {
+        outgoing.send(SendNetworkMessage::new(args.server.as_str(), line));
+    }
+}
+
+fn update_quiz_ui(
+    time: Res<Time>,
+    quiz: Res<QuizView>,
+    scoreboard: Res<Scoreboard>,
+    mut query: Query<&mut Text, With<QuizText>>,
+) {
+    if quiz.question.is_none() && !quiz.is_changed() && !scoreboard.is_changed() {
+        return;
+    }
+    let mut lines = Vec::new();
+    if let Some((id, text, closes_at)) = &quiz.question {
+        let left = (closes_at - time.elapsed_seconds()).max(0.0);
+        lines.push(format!("Q{} ({:.0}s left): {}", id, left.ceil(), text));
+    }
+    if !scoreboard.scores.is_empty() {
+        lines.push("Scoreboard:".to_string());
+        let mut scores: Vec<(&String, &u32)> = scoreboard.scores.iter().collect();
+        scores.sort_by(|a, b| b.1.cmp(a.1));
+        for (addr, score) in scores {
+            lines.push(format!("  {:>3}  {}", score, addr));
+        }
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+type KnockPresses<'w, 's> = Query<
+    'w,
+    's,
+    (&'static Interaction, &'static mut ButtonFlash),
+    (Changed<Interaction>, With<KnockButton>),
+>;
+
 fn knock_button_system(
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<KnockButton>)>,
-    network: Res<NetworkState>,
+    mut interaction_query: KnockPresses,
+    network: Res<NetworkSocket>,
     args: Res<Args>,
     mut client_state: ResMut<ClientState>,
 ) {
-    for interaction in interaction_query.iter() {
+    for (interaction, mut flash) in interaction_query.iter_mut() {
         if *interaction == Interaction::Pressed {
-            let message = b"KNOCK KNOCK";
-            let _ = network.socket.send_to(message, &args.server);
+            // Sent right away: the button only flashes if it went out
+            if network
+                .send_bytes_to(&Message::Knock.encode(), &args.server)
+                .is_ok()
+            {
+                flash.trigger();
+            }
             client_state
                 .log
                 .push(format!("[Tx]: KNOCK KNOCK -> {}", args.server));
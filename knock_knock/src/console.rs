@@ -0,0 +1,83 @@
+//! The operator's console for knock_server: a field under the log in the
+//! window, or stdin with `--headless` and in a build without `gui`. Either way each line lands in
+//! [`ConsoleLines`] for the server to run at the next frame.
+
+use bevy::prelude::*;
+#[cfg(feature = "gui")]
+use common::ui::text_input::{TextInput, TextInputSubmitted, spawn_text_input};
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ConsoleInput;
+
+/// Lines typed, waiting for the next frame
+#[derive(Resource, Default)]
+pub struct ConsoleLines(Arc<Mutex<VecDeque<String>>>);
+
+impl ConsoleLines {
+    /// Reads lines from stdin on a thread of their own, as they are typed.
+    pub fn from_stdin() -> Self {
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        let inbox = lines.clone();
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                inbox.lock().unwrap().push_back(line);
+            }
+        });
+        Self(lines)
+    }
+
+    /// Every line typed since the last call, trimmed, without blank ones
+    pub fn drain(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(feature = "gui")]
+pub fn setup_console(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            spawn_text_input(
+                parent,
+                TextInput::new("Type /help for console commands").clear_on_submit(),
+                Val::Px(420.0),
+                ConsoleInput,
+            );
+        });
+}
+
+/// What was typed into the console field, for the next frame.
+#[cfg(feature = "gui")]
+pub fn queue_typed_lines(
+    mut submitted: EventReader<TextInputSubmitted>,
+    console: Query<(), With<ConsoleInput>>,
+    lines: Res<ConsoleLines>,
+) {
+    for event in submitted.read() {
+        if console.get(event.entity).is_ok() {
+            lines.0.lock().unwrap().push_back(event.value.clone());
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Joke contributions wire format, shared by knock_server and knock_client.
+//!
+//! A client sends a joke of its own as a payload on a reliable stream of
+//! its own (see `common::reliable`), so it arrives however lossy the link.
+//! The server checks it with [`check_joke`], holds it for the operator and
+//! answers on the stream it sends jokes on. Once the operator approves it,
+//! every client is sent it as a `JOKE` and tells it from then on, and
+//! clients that knock later get it with the rest.
+//!
+//! ```text
+//! SUBMIT <name>|<punchline>      client -> server
+//! PENDING <id> <name>            server -> client, waiting for the operator
+//! REFUSED <reason>               server -> client, not held
+//! APPROVED <id> <name>           server -> client, in the rotation
+//! DECLINED <id> <name>           server -> client
+//! JOKE <name>|<punchline>        server -> every client, a new joke
+//! ```
+
+use std::fmt;
+
+/// Longest name a contributed joke may have, in characters
+pub const MAX_NAME_CHARS: usize = 32;
+/// Longest punchline, in characters
+pub const MAX_PUNCHLINE_CHARS: usize = 120;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Contribution {
+    Submit { joke: String },
+    Pending { id: u32, name: String },
+    Refused { reason: String },
+    Approved { id: u32, name: String },
+    Declined { id: u32, name: String },
+    Joke { joke: String },
+}
+
+impl Contribution {
+    /// `None` for any other payload, e.g. a joke chunk.
+    pub fn parse(payload: &str) -> Option<Self> {
+        let (command, rest) = payload.split_once(' ')?;
+        let id_and_name = || -> Option<(u32, String)> {
+            let (id, name) = rest.split_once(' ')?;
+            Some((id.parse().ok()?, name.to_string()))
+        };
+        match command {
+            "SUBMIT" => Some(Contribution::Submit {
+                joke: rest.to_string(),
+            }),
+            "PENDING" => id_and_name().map(|(id, name)| Contribution::Pending { id, name }),
+            "REFUSED" => Some(Contribution::Refused {
+                reason: rest.to_string(),
+            }),
+            "APPROVED" => id_and_name().map(|(id, name)| Contribution::Approved { id, name }),
+            "DECLINED" => id_and_name().map(|(id, name)| Contribution::Declined { id, name }),
+            "JOKE" => Some(Contribution::Joke {
+                joke: rest.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Contribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Contribution::Submit { joke } => write!(f, "SUBMIT {}", joke),
+            Contribution::Pending { id, name } => write!(f, "PENDING {} {}", id, name),
+            Contribution::Refused { reason } => write!(f, "REFUSED {}", reason),
+            Contribution::Approved { id, name } => write!(f, "APPROVED {} {}", id, name),
+            Contribution::Declined { id, name } => write!(f, "DECLINED {} {}", id, name),
+            Contribution::Joke { joke } => write!(f, "JOKE {}", joke),
+        }
+    }
+}
+
+/// `Name|Punchline`, trimmed, or why it can't be a joke. The client checks
+/// before sending; the server checks again, since anyone can send anything.
+pub fn check_joke(joke: &str) -> Result<(&str, &str), &'static str> {
+    let Some((name, punchline)) = joke.split_once('|') else {
+        return Err("expected Name|Punchline");
+    };
+    let (name, punchline) = (name.trim(), punchline.trim());
+    if name.is_empty() || punchline.is_empty() {
+        return Err("both the name and the punchline are needed");
+    }
+    if punchline.contains('|') {
+        return Err("only one | please");
+    }
+    if name.chars().count() > MAX_NAME_CHARS {
+        return Err("the name is too long");
+    }
+    if punchline.chars().count() > MAX_PUNCHLINE_CHARS {
+        return Err("the punchline is too long");
+    }
+    if joke.chars().any(char::is_control) {
+        return Err("no control characters");
+    }
+    if name.eq_ignore_ascii_case("KNOCK KNOCK") {
+        return Err("that name is the knock itself");
+    }
+    Ok((name, punchline))
+}
@@ -0,0 +1,71 @@
+//! The server's joke database, streamed to every client that knocks.
+//!
+//! One joke per line, `Name|Punchline`: the client answers "WHO IS THERE?"
+//! with the name, and the server's "<Name> WHO?" with the punchline.
+//! `--jokes <file>` adds more from a file in the same format.
+
+const BUILT_IN: &str = "\
+Lettuce|Lettuce in, it's cold out here!
+Boo|Don't cry, it's only a joke.
+Cow says|No, a cow says mooooo!
+Olive|Olive you and I don't care who knows it!
+Tank|You're welcome!
+Atch|Bless you!
+Interrupting cow|MOO!
+Nobel|No bell, that's why I knocked.
+Harry|Harry up, it's cold out here!
+Wooden shoe|Wooden shoe like to hear another joke?
+Water|Water you doing? Let me in!
+Orange|Orange you glad I didn't say banana?
+Banana|Knock knock. (Did you expect a different answer?)
+Dishes|Dishes the police, open up!
+Honeydew|Honeydew you want to hear another one?
+Annie|Annie thing you can do, I can do better.
+Hawaii|I'm fine, Hawaii you?
+Justin|Justin time for dinner.
+Ice cream|Ice cream if you don't let me in!
+Figs|Figs the doorbell, it's broken!
+Police|Police let me in, it's cold out here!
+Luke|Luke through the keyhole and you'll see.
+Tuna|Tuna piano and it sounds much better.
+Alpaca|Alpaca the trunk, you pack the suitcase.
+Canoe|Canoe help me with my homework?
+Doris|Doris locked, that's why I'm knocking.
+Ketchup|Ketchup with me and I'll tell you.
+Says|Says me, that's who!
+Yukon|Yukon say that again.
+Iva|Iva sore hand from knocking!
+Needle|Needle little help getting in the door.
+Scold|Scold outside, let me in!
+Amish|Really? You don't look like a shoe.
+Broccoli|Broccoli doesn't have a last name, silly.
+Cash|No thanks, I prefer peanuts.
+Lena|Lena little closer and I'll tell you.
+Mikey|Mikey doesn't fit in the keyhole!
+Noah|Noah good place we can get something to eat?
+Radio|Radio not, here I come!
+Spell|W-H-O.
+Voodoo|Voodoo you think you are, asking me so many questions?
+Wendy|Wendy bell works again, I won't have to knock anymore.
+Zany|Zany body home?
+Abby|Abby birthday to you!
+Candice|Candice door open, or what?
+Howard|Howard I know? I forgot my keys.";
+
+/// How many jokes must arrive before a client can start telling them.
+pub const ESSENTIAL_JOKES: usize = 5;
+
+/// Built-in jokes, then any from `extra` (malformed lines are skipped).
+pub fn load(extra: Option<&str>) -> std::io::Result<Vec<String>> {
+    let mut jokes: Vec<String> = BUILT_IN.lines().map(str::to_string).collect();
+    if let Some(path) = extra {
+        let text = std::fs::read_to_string(path)?;
+        jokes.extend(
+            text.lines()
+                .map(str::trim)
+                .filter(|line| line.contains('|'))
+                .map(str::to_string),
+        );
+    }
+    Ok(jokes)
+}
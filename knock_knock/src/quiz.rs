@@ -0,0 +1,48 @@
+//! Quiz mode, shared by knock_server and knock_client.
+//!
+//! With `--quiz`, the server asks every client it has heard from a question
+//! with a deadline and judges the answers. The [`Scoreboard`] is a
+//! replicated resource (see `common::replicate`), so every client's copy
+//! follows the server's. The questions and answers are `common::message`
+//! messages, encoded like the jokes; as text, in the log, they read:
+//!
+//! ```text
+//! QUESTION <id> <secs> <text>     server -> client, answer within secs
+//! ANSWER <id> <text>              client -> server
+//! VERDICT <id> CORRECT|WRONG|LATE server -> client
+//! CLOSED <id> <answer>            server -> client, deadline passed
+//! REPL scoreboard <addr>=<n> ...  server -> client, as text
+//! ```
+
+use bevy::prelude::*;
+use common::replicate::Replicated;
+use std::collections::BTreeMap;
+
+/// Points per player, keyed by address. Everyone who knocks is listed, even
+/// with no points.
+#[derive(Resource, Default)]
+pub struct Scoreboard {
+    pub scores: BTreeMap<String, u32>,
+}
+
+impl Replicated for Scoreboard {
+    const NAME: &'static str = "scoreboard";
+
+    fn to_fields(&self) -> BTreeMap<String, String> {
+        self.scores
+            .iter()
+            .map(|(addr, score)| (addr.clone(), score.to_string()))
+            .collect()
+    }
+
+    fn apply_field(&mut self, key: &str, value: Option<&str>) {
+        match value.and_then(|value| value.parse().ok()) {
+            Some(score) => {
+                self.scores.insert(key.to_string(), score);
+            }
+            None => {
+                self.scores.remove(key);
+            }
+        }
+    }
+}
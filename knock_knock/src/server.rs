@@ -1,147 +1,891 @@
 //! Knock Knock Server
 //! Usage: cargo run --bin knock_server -- --port 50051
 //!
-//! Listens for "KNOCK KNOCK" messages and replies "WHO IS THERE?"
+//! Listens for "KNOCK KNOCK" messages and replies "WHO IS THERE?", then
+//! "<Name> WHO?" to whatever name comes back. These, and the quiz, are
+//! `common::message` messages, encoded with bincode; a datagram that
+//! doesn't decode is logged and dropped. The reliable stream and
+//! replication stay text, as in the Ping/Pong demo.
+//!
+//! Every new client is sent the joke database as a chunked snapshot over the
+//! reliable channel (see `jokes` and `common::snapshot`). The stream opens
+//! with `Seed <hex>`, the seed of a `common::shared_rng::SharedRng` that
+//! picks which joke a client tells next, so every client that has all the
+//! jokes tells them in the same order. The quiz asks its questions in an
+//! order drawn from the same seed; `--seed` repeats a run.
+//!
+//! With `--quiz` it also quizzes every client it has heard from; see `quiz`.
+//! With `--validate-protocol` a client's lines must come in joke order; see
+//! `validation`.
+//!
+//! Knock exchanges go into a `common::storage::History` (kept on disk with
+//! `--database`), and a new client is streamed the recent ones after the
+//! jokes, as `History <line>` payloads.
+//!
+//! Clients can send jokes of their own (see `contribute`). Each is checked
+//! and held in the database until the operator runs `/approve <id>` or
+//! `/decline <id>` in the console; approved ones join the rotation (see
+//! `submissions`).
+//!
+//! With `--snapshot <file>` the scoreboard is saved every `--snapshot-secs`
+//! (see `common::persist`), and `--resume` starts from the saved one, so
+//! quiz scores survive a restart.
+//!
+//! With `--headless` it opens no window: only Bevy's minimal plugins run,
+//! there is no UI, every log line is printed to stdout instead and console
+//! commands are read from stdin. Built without the `gui` feature it always
+//! runs that way.
 
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::diagnostic::{
+    Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsPlugin, RegisterDiagnostic,
+};
+use bevy::log::LogPlugin;
 use bevy::prelude::*;
+use bevy_udp_net::{NetworkMessageReceived, NetworkSocket, SendNetworkMessage, UdpNetworkPlugin};
 use clap::Parser;
-use std::net::UdpSocket;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use common::message::{Message, Payload};
+use common::persist::{PersistResourceExt, WorldSnapshots};
+use common::reliable::{ReliableMessage, ReliableReceiver, ReliableSender};
+use common::replicate::{
+    ReplicateResourceExt, ReplicationJoin, ReplicationOutgoing, ReplicationPlugin, ReplicationRole,
+};
+use common::shared_rng::SharedRng;
+use common::snapshot::{DEFAULT_CHUNK_BYTES, split_into_chunks};
+use common::storage::{self, Backend, History};
+#[cfg(feature = "gui")]
+use common::ui::text_input::TextInputPlugin;
+#[cfg(feature = "gui")]
+use common::ui::widgets::{UiTheme, WidgetsPlugin, header, log_text};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::time::Duration;
 
+mod console;
+mod contribute;
+mod jokes;
+mod quiz;
+mod submissions;
+mod validation;
+
+use common::message::Verdict;
+use console::ConsoleLines;
+#[cfg(feature = "gui")]
+use console::{queue_typed_lines, setup_console};
+use contribute::Contribution;
+use quiz::Scoreboard;
+use submissions::Submissions;
+use validation::Validation;
+
+/// (question, answer); answers are compared ignoring case and surrounding space
+const QUESTIONS: &[(&str, &str)] = &[
+    ("What transport protocol does this demo use?", "udp"),
+    ("How many bits are in a byte?", "8"),
+    (
+        "What port does the knock knock server listen on by default?",
+        "50051",
+    ),
+    ("What is 6 x 7?", "42"),
+    ("Which Bevy version does this demo use?", "0.13"),
+    ("What does RTT stand for?", "round trip time"),
+];
+
 #[derive(Parser, Resource, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Args {
     /// Port to listen on
     #[arg(short, long, default_value_t = 50051)]
     port: u16,
-}
 
-#[derive(Resource)]
-struct NetworkState {
-    /// (Message, SenderAddress)
-    received_message: Arc<Mutex<Option<(String, String)>>>,
-    socket: Arc<UdpSocket>,
+    /// Largest datagram we accept, in bytes; bigger ones are dropped and counted
+    #[arg(long, default_value_t = 1024)]
+    recv_buffer: usize,
+
+    /// Quiz every client that knocks, with a shared scoreboard
+    #[arg(long)]
+    quiz: bool,
+
+    /// Seconds between the end of one question and the next
+    #[arg(long, default_value_t = 10.0)]
+    question_interval: f32,
+
+    /// Seconds a client has to answer
+    #[arg(long, default_value_t = 8.0)]
+    answer_secs: f32,
+
+    /// Extra jokes, one `Name|Punchline` per line
+    #[arg(long)]
+    jokes: Option<String>,
+
+    /// Resend a joke chunk if it isn't acked within this many seconds
+    #[arg(long, default_value_t = 0.5)]
+    resend_secs: f32,
+
+    /// Most joke chunks in flight (sent but unacked) per client
+    #[arg(long, default_value_t = 4)]
+    window: usize,
+
+    /// Keep knock history in this database so it survives restarts
+    #[arg(long)]
+    database: Option<String>,
+
+    /// What keeps the history and joke submissions in the --database: sled (the default), or
+    /// memory for nothing on disk. Without --database everything is kept in memory
+    #[arg(long)]
+    storage: Option<Backend>,
+
+    /// Refuse lines out of joke order, such as a name before WHO IS THERE?
+    #[arg(long)]
+    validate_protocol: bool,
+
+    /// Save the replicated world (the scoreboard) to this file
+    #[arg(long)]
+    snapshot: Option<String>,
+
+    /// Seconds between snapshots, when something changed
+    #[arg(long, default_value_t = 10.0)]
+    snapshot_secs: f32,
+
+    /// Start from the `--snapshot` file instead of an empty scoreboard
+    #[arg(long, requires = "snapshot")]
+    resume: bool,
+
+    /// Seed for joke and question order, sent to every client (default: from the clock)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Run without a window, printing the log to stdout. Always on in a build without `gui`
+    #[arg(long)]
+    headless: bool,
 }
 
+/// Frames per second with `--headless`, when no window paces the loop
+const HEADLESS_TICK_HZ: f64 = 60.0;
+
+/// Submissions `/submissions` lists; the log keeps 20 lines
+const LISTED_SUBMISSIONS: usize = 15;
+
 #[derive(Resource, Default)]
 struct ServerState {
     log: Vec<String>,
+    /// `--headless`: stdout is the log
+    print: bool,
 }
 
+impl ServerState {
+    fn push_log(&mut self, entry: String) {
+        if self.print {
+            println!("{}", entry);
+        }
+        self.log.push(entry);
+        if self.log.len() > 20 {
+            self.log.remove(0);
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
 #[derive(Component)]
 struct LogText;
 
+/// Unacked chunks across every client's stream, as a share of their
+/// combined windows (0..=1).
+const STREAM_WINDOW_OCCUPANCY: DiagnosticPath =
+    DiagnosticPath::const_new("network/stream_window_occupancy");
+
+/// The joke database, and the same already split into snapshot chunks.
+#[derive(Resource)]
+struct JokeChunks {
+    jokes: Vec<String>,
+    chunks: Vec<String>,
+}
+
+impl JokeChunks {
+    fn new(jokes: Vec<String>) -> Self {
+        let chunks =
+            split_into_chunks("jokes", &jokes, DEFAULT_CHUNK_BYTES, jokes::ESSENTIAL_JOKES);
+        Self { jokes, chunks }
+    }
+
+    /// Adds `joke` at the end, for the clients that knock from now on.
+    fn add(&mut self, joke: String) {
+        let mut jokes = std::mem::take(&mut self.jokes);
+        jokes.push(joke);
+        *self = Self::new(jokes);
+    }
+}
+
+/// One reliable stream per client that has knocked.
+#[derive(Resource, Default)]
+struct Streams(HashMap<String, ReliableSender>);
+
+/// Each client's reliable stream to us, which carries its joke submissions.
+#[derive(Resource, Default)]
+struct Uploads(HashMap<String, ReliableReceiver>);
+
+struct OpenQuestion {
+    id: u32,
+    /// Index into QUESTIONS
+    index: usize,
+    closes_at: f32,
+    /// Clients that already answered; one attempt each
+    answered: HashSet<String>,
+}
+
+#[derive(Resource)]
+struct Quiz {
+    next_question: Timer,
+    current: Option<OpenQuestion>,
+    next_id: u32,
+    /// Indices into QUESTIONS, in the order they are asked, over and over
+    order: Vec<usize>,
+}
+
+impl Quiz {
+    fn new(interval: f32, mut rng: SharedRng) -> Self {
+        let mut order: Vec<usize> = (0..QUESTIONS.len()).collect();
+        rng.shuffle(&mut order);
+        Self {
+            next_question: Timer::from_seconds(interval, TimerMode::Once),
+            current: None,
+            next_id: 1,
+            order,
+        }
+    }
+
+    fn judge(&mut self, scoreboard: &mut Scoreboard, addr: &str, id: u32, text: &str) -> Verdict {
+        let Some(question) = self.current.as_mut().filter(|question| question.id == id) else {
+            return Verdict::Late;
+        };
+        if !question.answered.insert(addr.to_string()) {
+            return Verdict::Late;
+        }
+        let (_, answer) = QUESTIONS[question.index];
+        if text.trim().eq_ignore_ascii_case(answer) {
+            *scoreboard.scores.entry(addr.to_string()).or_default() += 1;
+            Verdict::Correct
+        } else {
+            Verdict::Wrong
+        }
+    }
+}
+
 fn main() {
     let args = Args::parse();
+    let quiz_enabled = args.quiz;
+    let mut jokes = match jokes::load(args.jokes.as_deref()) {
+        Ok(jokes) => jokes,
+        Err(e) => {
+            eprintln!("Error: could not read jokes file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let path = args.database.as_deref().map(Path::new);
+    let backend = match storage::choose(args.storage, path) {
+        Ok((backend, warning)) => {
+            if let Some(warning) = warning {
+                eprintln!("Warning: {}", warning);
+            }
+            backend
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stores = storage::open(backend, path).and_then(|database| {
+        let history = History::open(database.as_ref())?;
+        let submissions = Submissions::open(database.as_ref())?;
+        let approved = submissions.approved()?;
+        Ok((history, submissions, approved))
+    });
+    let (history, submissions, approved) = match stores {
+        Ok(stores) => stores,
+        Err(e) => {
+            eprintln!("Error: could not open the database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Contributed jokes go last, in the order they were approved
+    jokes.extend(approved);
+    let jokes = JokeChunks::new(jokes);
+    println!(
+        "Serving {} jokes in {} chunks, {} submissions waiting for approval",
+        jokes.jokes.len(),
+        jokes.chunks.len(),
+        submissions.pending().len()
+    );
 
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_resource(args)
-        .init_resource::<ServerState>()
-        .add_systems(Startup, (setup_network, setup_ui))
-        .add_systems(Update, (handle_network_messages, update_log_ui))
-        .run();
-}
-
-fn setup_network(mut commands: Commands, args: Res<Args>) {
-    let bind_addr = format!("0.0.0.0:{}", args.port);
-    let socket = Arc::new(UdpSocket::bind(&bind_addr).expect("Failed to bind socket"));
-    println!("Knock Knock Server listening on {}", bind_addr);
-
-    socket
-        .set_nonblocking(true)
-        .expect("Failed to set non-blocking");
-
-    let received_message = Arc::new(Mutex::new(None));
-    let socket_clone = socket.clone();
-    let received_clone = received_message.clone();
-
-    thread::spawn(move || {
-        let mut buf = [0u8; 1024];
-        loop {
-            match socket_clone.recv_from(&mut buf) {
-                Ok((size, addr)) => {
-                    let message = String::from_utf8_lossy(&buf[..size]).to_string();
-                    let mut received = received_clone.lock().unwrap();
-                    *received = Some((message, addr.to_string()));
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(10));
+    let snapshots = args.snapshot.as_ref().map(|path| {
+        let snapshots = WorldSnapshots::new(path, args.snapshot_secs);
+        if !args.resume {
+            return snapshots;
+        }
+        match snapshots.resume() {
+            Ok(snapshots) => {
+                let resumed: Vec<&str> = snapshots.resumable().collect();
+                if resumed.is_empty() {
+                    println!("No snapshot at {} yet; starting fresh", path);
+                } else {
+                    println!("Resuming {} from {}", resumed.join(", "), path);
                 }
-                Err(_) => break,
+                snapshots
+            }
+            Err(e) => {
+                eprintln!("Error: could not read the snapshot {}: {}", path, e);
+                std::process::exit(1);
             }
         }
     });
 
-    commands.insert_resource(NetworkState {
-        received_message,
-        socket,
-    });
-}
+    let shared_rng = SharedRng::new(args.seed.unwrap_or_else(SharedRng::random_seed));
+    println!("Shared seed {:x}", shared_rng.seed());
 
-fn setup_ui(mut commands: Commands, args: Res<Args>) {
-    commands.spawn(Camera2dBundle::default());
+    let socket = match NetworkSocket::bind(("0.0.0.0", args.port), args.recv_buffer) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error: could not bind port {}: {}", args.port, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Knock Knock Server listening on 0.0.0.0:{}", args.port);
 
-    commands.spawn(
-        TextBundle::from_section(
-            format!("Knock Knock Server - Listening on port {}", args.port),
-            TextStyle {
-                font_size: 24.0,
-                color: Color::rgb(0.9, 0.9, 0.9),
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(10.0),
-            left: Val::Px(10.0),
+    let validate_protocol = args.validate_protocol;
+    let question_interval = args.question_interval;
+    // Without a window to open, there's only headless
+    let headless = args.headless || cfg!(not(feature = "gui"));
+    let mut app = App::new();
+    if headless {
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / HEADLESS_TICK_HZ,
+            ))),
+            LogPlugin::default(),
+            DiagnosticsPlugin,
+        ))
+        .insert_resource(ConsoleLines::from_stdin());
+    }
+    #[cfg(feature = "gui")]
+    if !headless {
+        app.add_plugins((DefaultPlugins, WidgetsPlugin, TextInputPlugin))
+            .insert_resource(knock_knock_theme())
+            .init_resource::<ConsoleLines>()
+            .add_systems(Startup, (setup_ui, setup_console))
+            .add_systems(Update, (update_log_ui, queue_typed_lines));
+    }
+    app.add_plugins(UdpNetworkPlugin::new(socket))
+        .insert_resource(args)
+        .insert_resource(ServerState {
+            print: headless,
             ..default()
-        }),
-    );
+        })
+        .insert_resource(Quiz::new(question_interval, shared_rng))
+        .insert_resource(shared_rng)
+        .insert_resource(jokes)
+        .init_resource::<Streams>()
+        .init_resource::<Uploads>()
+        .insert_resource(history)
+        .insert_resource(submissions)
+        .register_diagnostic(Diagnostic::new(STREAM_WINDOW_OCCUPANCY))
+        .add_plugins(ReplicationPlugin {
+            role: ReplicationRole::Server,
+        })
+        .replicate_resource::<Scoreboard>()
+        .add_systems(
+            Update,
+            (
+                handle_network_messages,
+                report_truncated_packets,
+                run_quiz
+                    .after(handle_network_messages)
+                    .run_if(move || quiz_enabled),
+                send_replication,
+                flush_streams.after(handle_network_messages),
+                handle_console_commands.before(flush_streams),
+            ),
+        );
+    if validate_protocol {
+        app.init_resource::<Validation>();
+    }
+    if let Some(snapshots) = snapshots {
+        app.insert_resource(snapshots)
+            .persist_resource::<Scoreboard>();
+    }
+    app.run();
+}
 
-    commands.spawn((
-        TextBundle::from_section(
-            "Waiting for KNOCK KNOCK...\n",
-            TextStyle {
-                font_size: 18.0,
-                color: Color::WHITE,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(50.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
-        LogText,
+/// Knock Knock uses bigger header and log text than the Ping/Pong demo.
+#[cfg(feature = "gui")]
+fn knock_knock_theme() -> UiTheme {
+    UiTheme {
+        header_font_size: 24.0,
+        log_font_size: 18.0,
+        button_font_size: 20.0,
+        ..default()
+    }
+}
+
+#[cfg(feature = "gui")]
+fn setup_ui(mut commands: Commands, args: Res<Args>, theme: Res<UiTheme>) {
+    commands.spawn(Camera2dBundle::default());
+
+    commands.spawn(header(
+        &theme,
+        format!("Knock Knock Server - Listening on port {}", args.port),
     ));
+
+    commands.spawn((log_text(&theme, "Waiting for KNOCK KNOCK...\n"), LogText));
 }
 
-fn handle_network_messages(network: Res<NetworkState>, mut server_state: ResMut<ServerState>) {
-    let mut received = network.received_message.lock().unwrap();
-    if let Some((message, client_addr)) = received.take() {
+fn handle_network_messages(
+    mut received: EventReader<NetworkMessageReceived>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+    args: Res<Args>,
+    mut quiz: ResMut<Quiz>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut joins: EventWriter<ReplicationJoin>,
+    jokes: Res<JokeChunks>,
+    shared_rng: Res<SharedRng>,
+    mut streams: ResMut<Streams>,
+    mut uploads: ResMut<Uploads>,
+    mut submissions: ResMut<Submissions>,
+    mut history: ResMut<History>,
+    validation: Option<Res<Validation>>,
+    mut server_state: ResMut<ServerState>,
+) {
+    for event in received.read() {
+        let client_addr = event.from.to_string();
+        let message = match Payload::read(&event.bytes) {
+            Ok(Payload::Message(message)) => message,
+            Ok(Payload::Text(text)) => {
+                receive_reliable(
+                    &text,
+                    &client_addr,
+                    &mut outgoing,
+                    &args,
+                    &jokes,
+                    &shared_rng,
+                    &history,
+                    &mut streams,
+                    &mut uploads,
+                    &mut submissions,
+                    &mut server_state,
+                );
+                continue;
+            }
+            Err(e) => {
+                server_state.push_log(format!(
+                    "[Warn]: dropped a datagram from {}: {}",
+                    client_addr, e
+                ));
+                continue;
+            }
+        };
+        let text = message.to_string();
+
+        if let Some(Err(violation)) = validation
+            .as_ref()
+            .map(|validation| validation.0.check(&client_addr, &text))
+        {
+            server_state.push_log(format!("[Rejected from {}]: {}", client_addr, violation));
+            continue;
+        }
+
+        stream_to(
+            &mut streams,
+            &client_addr,
+            &args,
+            &jokes,
+            &shared_rng,
+            &history,
+            &mut server_state,
+        );
+
         // Log what we received
-        server_state
-            .log
-            .push(format!("[Rx from {}]: {}", client_addr, message.trim()));
+        server_state.push_log(format!("[Rx from {}]: {}", client_addr, text));
+
+        if let Message::Answer { id, text } = &message
+            && args.quiz
+        {
+            let verdict = quiz.judge(&mut scoreboard, &client_addr, *id, text);
+            let reply = Message::Verdict { id: *id, verdict };
+            send_reply(&mut outgoing, validation.as_deref(), &client_addr, &reply);
+            server_state.push_log(format!("[Tx to {}]: {}", client_addr, reply));
+            continue;
+        }
+        let Some(reply) = knock_reply(&message) else {
+            continue;
+        };
+        send_reply(&mut outgoing, validation.as_deref(), &client_addr, &reply);
+        record_exchange(&mut history, &client_addr, &text, &reply.to_string());
+        if !args.quiz {
+            server_state.push_log(format!("[Tx to {}]: {}", client_addr, reply));
+        } else if !scoreboard.scores.contains_key(&client_addr) {
+            // Any other message (a knock) joins the quiz
+            scoreboard.scores.insert(client_addr.clone(), 0);
+            server_state.push_log(format!("[Quiz]: {} joined", client_addr));
+            joins.send(ReplicationJoin { peer: client_addr });
+        }
+    }
+}
+
+/// What comes as text: `REL_ACK`s for our stream to the client, and on
+/// its own reliable stream, the jokes it sends in.
+fn receive_reliable(
+    text: &str,
+    client_addr: &str,
+    outgoing: &mut EventWriter<SendNetworkMessage>,
+    args: &Args,
+    jokes: &JokeChunks,
+    shared_rng: &SharedRng,
+    history: &History,
+    streams: &mut Streams,
+    uploads: &mut Uploads,
+    submissions: &mut Submissions,
+    server_state: &mut ServerState,
+) {
+    match ReliableMessage::parse(text) {
+        Some(ReliableMessage::Ack { seq }) => {
+            if let Some(stream) = streams.0.get_mut(client_addr) {
+                stream.ack(seq);
+            }
+        }
+        // All a client sends reliably is jokes of its own
+        Some(ReliableMessage::Data { seq, payload }) => {
+            let upload = uploads.0.entry(client_addr.to_string()).or_default();
+            let (ack, delivered) = upload.receive(seq, payload);
+            if let Some(ack) = ack {
+                outgoing.send(SendNetworkMessage::new(client_addr, ack));
+            }
+            for payload in delivered {
+                let Some(Contribution::Submit { joke }) = Contribution::parse(&payload) else {
+                    continue;
+                };
+                let reply = match submissions.submit(client_addr, &joke, &jokes.jokes) {
+                    Ok(submission) => {
+                        server_state.push_log(format!(
+                            "[Submission #{} from {}]: {} (/approve {} or /decline {})",
+                            submission.id,
+                            client_addr,
+                            submission.joke,
+                            submission.id,
+                            submission.id
+                        ));
+                        Contribution::Pending {
+                            id: submission.id,
+                            name: submission.name().to_string(),
+                        }
+                    }
+                    Err(reason) => {
+                        server_state.push_log(format!(
+                            "[Refused from {}]: {} ({})",
+                            client_addr, joke, reason
+                        ));
+                        Contribution::Refused { reason }
+                    }
+                };
+                let stream = stream_to(
+                    streams,
+                    client_addr,
+                    args,
+                    jokes,
+                    shared_rng,
+                    history,
+                    server_state,
+                );
+                stream.push(reply.to_string());
+            }
+        }
+        None => server_state.push_log(format!(
+            "[Warn]: {} sent text that isn't a message: {}",
+            client_addr,
+            text.trim()
+        )),
+    }
+}
+
+/// The stream to `addr`, opened with the seed, the jokes and the recent
+/// history the first time we hear from it.
+fn stream_to<'a>(
+    streams: &'a mut Streams,
+    addr: &str,
+    args: &Args,
+    jokes: &JokeChunks,
+    shared_rng: &SharedRng,
+    history: &History,
+    server_state: &mut ServerState,
+) -> &'a mut ReliableSender {
+    streams.0.entry(addr.to_string()).or_insert_with(|| {
+        let mut stream = ReliableSender::new(args.window, args.resend_secs);
+        stream.push(format!("Seed {:x}", shared_rng.seed()));
+        for chunk in &jokes.chunks {
+            stream.push(chunk.clone());
+        }
+        let mut lines = 0;
+        for line in history.recent() {
+            stream.push(format!("History {}", line));
+            lines += 1;
+        }
+        server_state.push_log(format!(
+            "[Stream]: Sending {} joke chunks and {} history lines to {}",
+            jokes.chunks.len(),
+            lines,
+            addr
+        ));
+        stream
+    })
+}
 
-        // Reply: "WHO IS THERE?"
-        let reply = b"WHO IS THERE?";
-        let _ = network.socket.send_to(reply, &client_addr);
-        server_state
-            .log
-            .push(format!("[Tx to {}]: WHO IS THERE?", client_addr));
+/// Runs the operator's console commands, which look after the jokes
+/// clients have sent in.
+fn handle_console_commands(
+    lines: Res<ConsoleLines>,
+    mut submissions: ResMut<Submissions>,
+    mut jokes: ResMut<JokeChunks>,
+    mut streams: ResMut<Streams>,
+    mut server_state: ResMut<ServerState>,
+) {
+    for line in lines.drain() {
+        server_state.push_log(format!("> {}", line));
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let id = words
+            .next()
+            .and_then(|id| id.trim_start_matches('#').parse().ok());
+        let output = match (command, id) {
+            ("/help", _) => vec![
+                "/help            this list".to_string(),
+                "/submissions     jokes sent in and waiting for approval".to_string(),
+                "/approve <id>    add a joke to the rotation".to_string(),
+                "/decline <id>    drop a joke".to_string(),
+            ],
+            ("/submissions", _) => list_submissions(&submissions),
+            ("/approve", Some(id)) => match submissions.approve(id) {
+                Ok(submission) => {
+                    let joke = Contribution::Joke {
+                        joke: submission.joke.clone(),
+                    };
+                    // Clients that already have the jokes hear of it now,
+                    // the rest get it with the others when they knock
+                    for stream in streams.0.values_mut() {
+                        stream.push(joke.to_string());
+                    }
+                    if let Some(stream) = streams.0.get_mut(&submission.from) {
+                        let approved = Contribution::Approved {
+                            id,
+                            name: submission.name().to_string(),
+                        };
+                        stream.push(approved.to_string());
+                    }
+                    jokes.add(submission.joke.clone());
+                    vec![format!(
+                        "[Approved]: #{} {}, now {} jokes",
+                        id,
+                        submission.name(),
+                        jokes.jokes.len()
+                    )]
+                }
+                Err(e) => vec![format!("[Error]: {}", e)],
+            },
+            ("/decline", Some(id)) => match submissions.decline(id) {
+                Ok(submission) => {
+                    if let Some(stream) = streams.0.get_mut(&submission.from) {
+                        let declined = Contribution::Declined {
+                            id,
+                            name: submission.name().to_string(),
+                        };
+                        stream.push(declined.to_string());
+                    }
+                    vec![format!("[Declined]: #{} {}", id, submission.name())]
+                }
+                Err(e) => vec![format!("[Error]: {}", e)],
+            },
+            ("/approve" | "/decline", None) => {
+                vec![format!("[Error]: expected {} <id>", command)]
+            }
+            _ => vec![format!("[Error]: unknown command {}; try /help", command)],
+        };
+        for line in output {
+            server_state.push_log(line);
+        }
+    }
+}
 
-        // Keep log length manageable
-        if server_state.log.len() > 20 {
-            server_state.log.remove(0);
+/// What `/submissions` prints: the waiting jokes, oldest first, as many as
+/// the log has room for.
+fn list_submissions(submissions: &Submissions) -> Vec<String> {
+    let pending = submissions.pending();
+    if pending.is_empty() {
+        return vec!["[Info]: no jokes are waiting for approval".to_string()];
+    }
+    let mut lines: Vec<String> = pending
+        .iter()
+        .take(LISTED_SUBMISSIONS)
+        .map(|submission| {
+            format!(
+                "#{} from {}: {}",
+                submission.id, submission.from, submission.joke
+            )
+        })
+        .collect();
+    if pending.len() > LISTED_SUBMISSIONS {
+        lines.push(format!(
+            "... and {} more",
+            pending.len() - LISTED_SUBMISSIONS
+        ));
+    }
+    lines
+}
+
+/// Replies to a client, telling the validator where that leaves the joke.
+fn send_reply(
+    outgoing: &mut EventWriter<SendNetworkMessage>,
+    validation: Option<&Validation>,
+    addr: &str,
+    reply: &Message,
+) {
+    outgoing.send(SendNetworkMessage::new(addr, reply.encode()));
+    if let Some(validation) = validation {
+        validation.0.note_sent(addr, &reply.to_string());
+    }
+}
+
+fn record_exchange(history: &mut History, client_addr: &str, message: &str, reply: &str) {
+    history.append(format!("{}: {}", client_addr, message));
+    history.append(format!("server to {}: {}", client_addr, reply));
+}
+
+/// "WHO IS THERE?" to a knock, "<Name> WHO?" to a name; nothing to
+/// anything else.
+fn knock_reply(message: &Message) -> Option<Message> {
+    match message {
+        Message::Knock => Some(Message::WhoIsThere),
+        Message::Name { name } => Some(Message::NameWho { name: name.clone() }),
+        _ => None,
+    }
+}
+
+/// Sends joke chunks as each stream's window allows, and resends any whose
+/// ack is overdue.
+fn flush_streams(
+    time: Res<Time>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+    mut streams: ResMut<Streams>,
+    mut diagnostics: Diagnostics,
+) {
+    let now = time.elapsed_seconds();
+    let (mut in_flight, mut capacity) = (0, 0);
+    for (addr, stream) in streams.0.iter_mut() {
+        if stream.is_idle() {
+            continue;
+        }
+        for line in stream.poll(now) {
+            outgoing.send(SendNetworkMessage::new(addr.as_str(), line));
+        }
+        in_flight += stream.in_flight();
+        capacity += stream.window();
+    }
+    let occupancy = if capacity == 0 {
+        0.0
+    } else {
+        in_flight as f64 / capacity as f64
+    };
+    diagnostics.add_measurement(&STREAM_WINDOW_OCCUPANCY, || occupancy);
+}
+
+/// Sends `bytes` to every client in the quiz.
+fn broadcast(
+    outgoing: &mut EventWriter<SendNetworkMessage>,
+    scoreboard: &Scoreboard,
+    bytes: &[u8],
+) {
+    for addr in scoreboard.scores.keys() {
+        outgoing.send(SendNetworkMessage::new(addr.as_str(), bytes));
+    }
+}
+
+/// Sends scoreboard changes to one player or to all of them.
+fn send_replication(
+    scoreboard: Res<Scoreboard>,
+    mut replication: EventReader<ReplicationOutgoing>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+) {
+    for message in replication.read() {
+        match &message.to {
+            Some(peer) => {
+                outgoing.send(SendNetworkMessage::new(
+                    peer.as_str(),
+                    message.text.as_str(),
+                ));
+            }
+            None => broadcast(&mut outgoing, &scoreboard, message.text.as_bytes()),
         }
     }
 }
 
+/// Asks a new question once the interval has passed, and closes the open one
+/// at its deadline.
+fn run_quiz(
+    time: Res<Time>,
+    args: Res<Args>,
+    mut outgoing: EventWriter<SendNetworkMessage>,
+    mut quiz: ResMut<Quiz>,
+    scoreboard: Res<Scoreboard>,
+    mut server_state: ResMut<ServerState>,
+) {
+    let now = time.elapsed_seconds();
+    let quiz = &mut *quiz;
+
+    if let Some(question) = &quiz.current {
+        if now < question.closes_at {
+            return;
+        }
+        let (_, answer) = QUESTIONS[question.index];
+        let closed = Message::Closed {
+            id: question.id,
+            answer: answer.to_string(),
+        };
+        server_state.push_log(format!("[Quiz]: {}", closed));
+        broadcast(&mut outgoing, &scoreboard, &closed.encode());
+        quiz.current = None;
+        quiz.next_question.reset();
+    } else if quiz.next_question.tick(time.delta()).finished() && !scoreboard.scores.is_empty() {
+        let id = quiz.next_id;
+        quiz.next_id += 1;
+        let index = quiz.order[(id as usize - 1) % quiz.order.len()];
+        let (text, _) = QUESTIONS[index];
+        let question = Message::Question {
+            id,
+            secs: args.answer_secs,
+            text: text.to_string(),
+        };
+        server_state.push_log(format!("[Quiz]: {}", question));
+        broadcast(&mut outgoing, &scoreboard, &question.encode());
+        quiz.current = Some(OpenQuestion {
+            id,
+            index,
+            closes_at: now + args.answer_secs,
+            answered: HashSet::new(),
+        });
+    }
+}
+
+fn report_truncated_packets(
+    network: Res<NetworkSocket>,
+    mut server_state: ResMut<ServerState>,
+    mut reported: Local<u64>,
+) {
+    let truncated = network.truncated_packets();
+    if truncated > *reported {
+        server_state.push_log(format!(
+            "[Warn]: {} oversized packet(s) dropped, raise --recv-buffer",
+            truncated - *reported
+        ));
+        *reported = truncated;
+    }
+}
+
+#[cfg(feature = "gui")]
 fn update_log_ui(server_state: Res<ServerState>, mut query: Query<&mut Text, With<LogText>>) {
     if server_state.is_changed() {
         for mut text in query.iter_mut() {
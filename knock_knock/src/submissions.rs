@@ -0,0 +1,196 @@
+//! Jokes contributed by clients, waiting for the operator or approved.
+//!
+//! Both live in the server's database (see `common::storage`), next to the
+//! knock history, so a restart loses neither: pending submissions in the
+//! `joke_submissions` table by id, approved jokes in `contributed_jokes` in
+//! the order they were approved, which is the order they join the rotation.
+//! On startup the approved ones are added after the built-in jokes and any
+//! `--jokes` file.
+//!
+//! A submission is held only if it passes `contribute::check_joke`, no joke
+//! or pending submission has its name already, its client has fewer than
+//! [`MAX_PENDING_PER_CLIENT`] waiting and the queue isn't full. Ids name
+//! pending submissions; after a restart an id whose submission is gone may
+//! be given out again.
+
+use bevy::prelude::*;
+use common::storage::{Result, Storage, Table};
+
+use crate::contribute::check_joke;
+
+/// Submissions one client may have waiting for the operator
+const MAX_PENDING_PER_CLIENT: usize = 3;
+/// Submissions waiting across every client
+const MAX_PENDING: usize = 100;
+
+pub struct Submission {
+    pub id: u32,
+    /// The client that sent it
+    pub from: String,
+    /// `Name|Punchline`, trimmed
+    pub joke: String,
+}
+
+impl Submission {
+    pub fn name(&self) -> &str {
+        self.joke
+            .split_once('|')
+            .map_or(&self.joke, |(name, _)| name)
+    }
+
+    /// Stored as `<from>\n<joke>`; neither has a newline
+    fn encode(&self) -> Vec<u8> {
+        format!("{}\n{}", self.from, self.joke).into_bytes()
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Option<Self> {
+        let id = u32::from_be_bytes(key.try_into().ok()?);
+        let value = String::from_utf8_lossy(value);
+        let (from, joke) = value.split_once('\n')?;
+        Some(Submission {
+            id,
+            from: from.to_string(),
+            joke: joke.to_string(),
+        })
+    }
+}
+
+#[derive(Resource)]
+pub struct Submissions {
+    pending_table: Box<dyn Table>,
+    approved_table: Box<dyn Table>,
+    /// Oldest first, as in the table
+    pending: Vec<Submission>,
+    next_id: u32,
+    next_approved: u64,
+}
+
+impl Submissions {
+    /// Opens both tables; [`Submissions::approved`] has the approved jokes.
+    pub fn open(database: &dyn Storage) -> Result<Self> {
+        let pending_table = database.table("joke_submissions")?;
+        let approved_table = database.table("contributed_jokes")?;
+        let pending: Vec<Submission> = pending_table
+            .newest(MAX_PENDING)?
+            .iter()
+            .rev()
+            .filter_map(|(key, value)| Submission::decode(key, value))
+            .collect();
+        let next_approved = approved_table
+            .newest(1)?
+            .first()
+            .and_then(|(key, _)| key.as_slice().try_into().ok())
+            .map_or(0, |key| u64::from_be_bytes(key) + 1);
+        Ok(Self {
+            next_id: pending.last().map_or(1, |submission| submission.id + 1),
+            pending,
+            pending_table,
+            approved_table,
+            next_approved,
+        })
+    }
+
+    /// The approved jokes, in the order they were approved.
+    pub fn approved(&self) -> Result<Vec<String>> {
+        let count = self.approved_table.count()?;
+        Ok(self
+            .approved_table
+            .newest(count)?
+            .iter()
+            .rev()
+            .map(|(_, joke)| String::from_utf8_lossy(joke).to_string())
+            .collect())
+    }
+
+    /// Oldest first
+    pub fn pending(&self) -> &[Submission] {
+        &self.pending
+    }
+
+    /// Holds `joke` from `from` for the operator, unless it can't be held:
+    /// then why. `jokes` is every joke in the rotation.
+    pub fn submit(
+        &mut self,
+        from: &str,
+        joke: &str,
+        jokes: &[String],
+    ) -> std::result::Result<&Submission, String> {
+        let (name, punchline) = check_joke(joke).map_err(str::to_string)?;
+        let taken = |other: &str| {
+            other
+                .split_once('|')
+                .is_some_and(|(other, _)| other.trim().eq_ignore_ascii_case(name))
+        };
+        if jokes.iter().any(|joke| taken(joke)) {
+            return Err(format!("there is already a joke called {}", name));
+        }
+        if self
+            .pending
+            .iter()
+            .any(|submission| taken(&submission.joke))
+        {
+            return Err(format!("{} is already waiting for approval", name));
+        }
+        let waiting = self
+            .pending
+            .iter()
+            .filter(|submission| submission.from == from)
+            .count();
+        if waiting >= MAX_PENDING_PER_CLIENT {
+            return Err(format!(
+                "you already have {} jokes waiting for approval",
+                waiting
+            ));
+        }
+        if self.pending.len() >= MAX_PENDING {
+            return Err("too many jokes are waiting for approval".to_string());
+        }
+        let submission = Submission {
+            id: self.next_id,
+            from: from.to_string(),
+            joke: format!("{}|{}", name, punchline),
+        };
+        self.pending_table
+            .insert(&submission.id.to_be_bytes(), &submission.encode())
+            .map_err(|e| {
+                warn!("Could not store a joke submission: {}", e);
+                "the server could not store it".to_string()
+            })?;
+        self.next_id += 1;
+        self.pending.push(submission);
+        Ok(self.pending.last().unwrap())
+    }
+
+    /// Takes submission `id` out of the queue and stores its joke with the
+    /// approved ones. The submission, or why not.
+    pub fn approve(&mut self, id: u32) -> std::result::Result<Submission, String> {
+        let index = self.index_of(id)?;
+        let key = self.next_approved.to_be_bytes();
+        self.approved_table
+            .insert(&key, self.pending[index].joke.as_bytes())
+            .map_err(|e| format!("could not store joke #{}: {}", id, e))?;
+        self.next_approved += 1;
+        Ok(self.take(index))
+    }
+
+    /// Takes submission `id` out of the queue for good.
+    pub fn decline(&mut self, id: u32) -> std::result::Result<Submission, String> {
+        let index = self.index_of(id)?;
+        Ok(self.take(index))
+    }
+
+    fn index_of(&self, id: u32) -> std::result::Result<usize, String> {
+        self.pending
+            .iter()
+            .position(|submission| submission.id == id)
+            .ok_or_else(|| format!("no submission #{} is waiting", id))
+    }
+
+    fn take(&mut self, index: usize) -> Submission {
+        let submission = self.pending.remove(index);
+        if let Err(e) = self.pending_table.remove(&submission.id.to_be_bytes()) {
+            warn!("Could not remove joke submission #{}: {}", submission.id, e);
+        }
+        submission
+    }
+}
@@ -0,0 +1,69 @@
+//! `--validate-protocol` for knock_server: a joke's lines in order, per
+//! client, checked with `common::validator` on each message's text form
+//! (see `common::message`).
+//!
+//! ```text
+//! idle ──KNOCK KNOCK──▶ knocked ──(we send WHO IS THERE?)──▶ asked ──<name>──▶ idle
+//! ```
+//!
+//! A name is only an answer to "WHO IS THERE?"; sent before it, it is
+//! refused and gets no "<Name> WHO?". A knock starts over from anywhere.
+//! Quiz answers are judged on their own deadlines and pass in any state.
+
+use bevy::prelude::*;
+use common::message::Message;
+use common::session::message_kind;
+use common::validator::{ProtocolRules, ProtocolValidator};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum JokeStage {
+    #[default]
+    Idle,
+    Knocked,
+    /// We asked "WHO IS THERE?" and wait for a name
+    Asked,
+}
+
+pub struct KnockRules;
+
+impl ProtocolRules for KnockRules {
+    type State = JokeStage;
+
+    fn received(&self, stage: JokeStage, message: &str) -> Result<JokeStage, &'static str> {
+        if message == Message::Knock.to_string() {
+            return Ok(JokeStage::Knocked);
+        }
+        if message_kind(message) == "ANSWER" {
+            return Ok(stage);
+        }
+        match stage {
+            JokeStage::Asked => Ok(JokeStage::Idle),
+            _ => Err("nobody asked WHO IS THERE? yet"),
+        }
+    }
+
+    fn sent(&self, stage: JokeStage, message: &str) -> JokeStage {
+        if message == Message::WhoIsThere.to_string() {
+            JokeStage::Asked
+        } else {
+            stage
+        }
+    }
+
+    fn label(&self, stage: JokeStage) -> &'static str {
+        match stage {
+            JokeStage::Idle => "idle",
+            JokeStage::Knocked => "knocked",
+            JokeStage::Asked => "asked who",
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Validation(pub ProtocolValidator<KnockRules>);
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self(ProtocolValidator::new(KnockRules))
+    }
+}
@@ -0,0 +1,350 @@
+//! logdiff: lines up the packet logs of both sides of a session
+//! Usage: cargo run -p logdiff -- client.log server.log [--lost-only]
+//!
+//! Takes two `--packet-log` files (see `common::packet_log`), one from the
+//! client and one from the server, in either order, and pairs every
+//! datagram one side sent with the one the other side received. What was
+//! sent and never arrived is marked LOST:
+//!
+//! ```text
+//!   +1.204  client -> server  Ping            #12 -> #40     2.1 ms
+//!   +1.500  client -> server  Ping            #13 -> LOST
+//!   +1.802  server -> client  Pong            ??? -> #15     not sent (duplicate, or before the log)
+//! ```
+//!
+//! The protocol has no sequence numbers, so a sent datagram is paired with
+//! the unpaired received one of the same text closest in time, within
+//! `--window` seconds. Times are compared after taking out the difference
+//! between the two machines' clocks. Unless `--offset` gives it, that is
+//! estimated the way NTP does: half the difference between the median
+//! one-way delays measured in each direction. Datagrams sent before the
+//! other log starts or after it ends aren't counted as lost.
+//!
+//! The server's log has every client in it; only the peer on the client
+//! log's port is compared, unless `--peer` names one (a TCP client's
+//! connection has a port of its own).
+
+use clap::Parser;
+use common::packet_log::{Direction, LoggedPacket, PacketLogFile, Side};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Largest clock difference the estimate looks for, in seconds
+const MAX_SKEW: f64 = 60.0;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Packet log of one side (--packet-log)
+    first: PathBuf,
+
+    /// Packet log of the other side
+    second: PathBuf,
+
+    /// Client address as the server logged it; by default the client log's port picks it
+    #[arg(long)]
+    peer: Option<String>,
+
+    /// Seconds the server's clock is ahead of the client's (estimated when not given)
+    #[arg(long, allow_hyphen_values = true)]
+    offset: Option<f64>,
+
+    /// Seconds a datagram may take, either way of the clock offset, to count as the same one
+    #[arg(long, default_value_t = 2.0)]
+    window: f64,
+
+    /// Only print what was lost
+    #[arg(long)]
+    lost_only: bool,
+}
+
+/// The other side's copy of a sent datagram.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Fate {
+    /// Index into the receiver's packets
+    Received(usize),
+    Lost,
+    /// Sent outside the time the other log covers
+    Unlogged,
+}
+
+/// Pairs each of `sent` with one of `received`. `shift` turns a sender
+/// time into a receiver time. Returns each send's fate and which receives
+/// were paired.
+fn pair(
+    sent: &[&LoggedPacket],
+    received: &[&LoggedPacket],
+    covered: (f64, f64),
+    shift: f64,
+    window: f64,
+) -> (Vec<Fate>, Vec<bool>) {
+    let mut by_text: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, packet) in received.iter().enumerate() {
+        by_text.entry(packet.text.as_str()).or_default().push(index);
+    }
+    let mut paired = vec![false; received.len()];
+    let fates = sent
+        .iter()
+        .map(|packet| {
+            let at = packet.at + shift;
+            let best = by_text
+                .get(packet.text.as_str())
+                .into_iter()
+                .flatten()
+                .filter(|&&index| !paired[index])
+                .map(|&index| (index, (received[index].at - at).abs()))
+                .filter(|&(_, distance)| distance <= window)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            match best {
+                Some((index, _)) => {
+                    paired[index] = true;
+                    Fate::Received(index)
+                }
+                None if at < covered.0 - window || at > covered.1 + window => Fate::Unlogged,
+                None => Fate::Lost,
+            }
+        })
+        .collect();
+    (fates, paired)
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    Some(values[values.len() / 2])
+}
+
+/// Median of receive minus send time, pairing each send with the nearest
+/// receive of the same text: one-way delay plus the clock difference.
+fn one_way(sent: &[&LoggedPacket], received: &[&LoggedPacket]) -> Option<f64> {
+    let deltas = sent
+        .iter()
+        .filter_map(|packet| {
+            received
+                .iter()
+                .filter(|other| other.text == packet.text)
+                .map(|other| other.at - packet.at)
+                .filter(|delta| delta.abs() <= MAX_SKEW)
+                .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+        })
+        .collect();
+    median(deltas)
+}
+
+fn split<'a>(packets: &[&'a LoggedPacket]) -> (Vec<&'a LoggedPacket>, Vec<&'a LoggedPacket>) {
+    packets
+        .iter()
+        .copied()
+        .partition(|packet| packet.direction == Direction::Sent)
+}
+
+fn span(packets: &[&LoggedPacket]) -> (f64, f64) {
+    packets
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(first, last), packet| {
+            (first.min(packet.at), last.max(packet.at))
+        })
+}
+
+struct Row {
+    /// Client clock
+    at: f64,
+    line: String,
+    lost: bool,
+}
+
+/// One direction: the sends, what became of them, and receives nobody sent.
+struct Flow<'a> {
+    label: &'static str,
+    sent: Vec<&'a LoggedPacket>,
+    received: Vec<&'a LoggedPacket>,
+    fates: Vec<Fate>,
+    paired: Vec<bool>,
+}
+
+impl Flow<'_> {
+    fn count(&self, fate: fn(&Fate) -> bool) -> usize {
+        self.fates.iter().filter(|f| fate(f)).count()
+    }
+
+    /// `to_client` turns a time on this flow's sending side into client
+    /// time, `shift` a sender time into a receiver time.
+    fn rows(&self, to_client: f64, shift: f64) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for (packet, fate) in self.sent.iter().zip(&self.fates) {
+            let (to, note) = match fate {
+                Fate::Received(index) => {
+                    let delay = self.received[*index].at - packet.at - shift;
+                    (
+                        format!("#{}", self.received[*index].seq),
+                        format!("{:6.1} ms", delay * 1000.0),
+                    )
+                }
+                Fate::Lost => ("LOST".to_string(), String::new()),
+                Fate::Unlogged => ("-".to_string(), "outside the other log".to_string()),
+            };
+            rows.push(Row {
+                at: packet.at + to_client,
+                line: format!(
+                    "{}  {:<24}  #{} -> {:<6}  {}",
+                    self.label, packet.text, packet.seq, to, note
+                ),
+                lost: *fate == Fate::Lost,
+            });
+        }
+        for (packet, paired) in self.received.iter().zip(&self.paired) {
+            if *paired {
+                continue;
+            }
+            rows.push(Row {
+                at: packet.at + to_client - shift,
+                line: format!(
+                    "{}  {:<24}  ??? -> #{:<5}  not sent (duplicate, or before the log)",
+                    self.label, packet.text, packet.seq
+                ),
+                lost: false,
+            });
+        }
+        rows
+    }
+
+    fn summary(&self) -> String {
+        let lost = self.count(|fate| *fate == Fate::Lost);
+        let received = self.count(|fate| matches!(fate, Fate::Received(_)));
+        let counted = lost + received;
+        let mut summary = format!(
+            "{}: {} sent, {} received, {} lost",
+            self.label, counted, received, lost
+        );
+        if counted > 0 {
+            summary += &format!(" ({:.1}%)", lost as f64 * 100.0 / counted as f64);
+        }
+        let unlogged = self.count(|fate| *fate == Fate::Unlogged);
+        if unlogged > 0 {
+            summary += &format!(", {} outside the other log", unlogged);
+        }
+        summary
+    }
+}
+
+/// Addresses in the server log, for when none matched the client's port.
+fn peers(log: &PacketLogFile) -> String {
+    let peers: BTreeSet<&str> = log
+        .packets
+        .iter()
+        .map(|packet| packet.peer.as_str())
+        .collect();
+    peers.into_iter().collect::<Vec<_>>().join(", ")
+}
+
+fn load(path: &Path) -> PacketLogFile {
+    match PacketLogFile::load(path) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Error: could not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let first = load(&args.first);
+    let second = load(&args.second);
+    let (client, server) = match (first.side, second.side) {
+        (Side::Client, Side::Server) => (first, second),
+        (Side::Server, Side::Client) => (second, first),
+        (side, _) => {
+            eprintln!(
+                "Error: both logs are from the {}; give one client and one server log",
+                side.label()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let client_packets: Vec<&LoggedPacket> = client.packets.iter().collect();
+    let server_packets: Vec<&LoggedPacket> = server
+        .packets
+        .iter()
+        .filter(|packet| match &args.peer {
+            Some(peer) => packet.peer == *peer,
+            None => packet
+                .peer
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port == client.port.to_string()),
+        })
+        .collect();
+    if server_packets.is_empty() {
+        eprintln!(
+            "Error: the server log has nothing from {}; pick one with --peer: {}",
+            args.peer
+                .clone()
+                .unwrap_or_else(|| format!("port {}", client.port)),
+            peers(&server)
+        );
+        std::process::exit(1);
+    }
+
+    let (client_sent, client_received) = split(&client_packets);
+    let (server_sent, server_received) = split(&server_packets);
+    let (offset, how) = match args.offset {
+        Some(offset) => (offset, "given"),
+        None => {
+            let up = one_way(&client_sent, &server_received);
+            let down = one_way(&server_sent, &client_received);
+            match (up, down) {
+                (Some(up), Some(down)) => ((up - down) / 2.0, "estimated"),
+                (Some(delta), None) | (None, Some(delta)) => (delta, "estimated one way"),
+                (None, None) => (0.0, "assumed"),
+            }
+        }
+    };
+
+    let (fates, paired) = pair(
+        &client_sent,
+        &server_received,
+        span(&server_packets),
+        offset,
+        args.window,
+    );
+    let up = Flow {
+        label: "client -> server",
+        sent: client_sent,
+        received: server_received,
+        fates,
+        paired,
+    };
+    let (fates, paired) = pair(
+        &server_sent,
+        &client_received,
+        span(&client_packets),
+        -offset,
+        args.window,
+    );
+    let down = Flow {
+        label: "server -> client",
+        sent: server_sent,
+        received: client_received,
+        fates,
+        paired,
+    };
+
+    let peer = server_packets[0].peer.clone();
+    println!(
+        "Client on port {} vs server port {} (as {}), server clock {:+.3}s ({})",
+        client.port, server.port, peer, offset, how
+    );
+    let mut rows = up.rows(0.0, offset);
+    rows.extend(down.rows(-offset, -offset));
+    rows.sort_by(|a, b| a.at.total_cmp(&b.at));
+    let start = rows.first().map_or(0.0, |row| row.at);
+    for row in rows.iter().filter(|row| row.lost || !args.lost_only) {
+        println!("{:+9.3}  {}", row.at - start, row.line);
+    }
+    println!();
+    println!("{}", up.summary());
+    println!("{}", down.summary());
+}
@@ -0,0 +1,578 @@
+//! Matchmaker: hands out connect tickets
+//! Usage: cargo run -p matchmaker -- --key <secret> [--server 127.0.0.1:12345 ...]
+//!
+//! Answers `GET /ticket` with a game server's address and a ticket for it,
+//! signed with `--key`; see `common::ticket`. Servers started with the same
+//! `--ticket-key` only start sessions for clients that present one, so the
+//! matchmaker decides who gets in, and a host that just finds the port
+//! doesn't. With several `--server`s (say, a fleet's instances), each
+//! request gets the next one in turn.
+//!
+//! ```text
+//! GET /ticket HTTP/1.0
+//!
+//! HTTP/1.0 200 OK
+//!
+//! server=127.0.0.1:12345 ticket=1767225600.3f2a...e1 expires=1767225600
+//! ```
+//!
+//! Two players can also meet on one server by invite code, without either
+//! seeing the other's address. `GET /invite` picks a server the same way and
+//! answers with a short code for it (`GET /invite?server=<addr>` asks for a
+//! code for a server of ours the asker is already on); `GET /join/<code>`
+//! answers like `/ticket`, but always with the code's server:
+//!
+//! ```text
+//! GET /invite HTTP/1.0
+//!
+//! code=K7QF2M server=127.0.0.1:12345 expires=1767229200
+//! ```
+//!
+//! Codes are six characters without look-alikes (no 0/O or 1/I/L), drawn
+//! from the OS's random source, are matched ignoring case, and expire after
+//! `--code-ttl` seconds. An address gets [`JOIN_ATTEMPTS`] tries at
+//! `/join/` per [`JOIN_WINDOW`], so codes can't be found by trying them all.
+//!
+//! Each request is answered on a thread of its own, at most
+//! [`MAX_CONNECTIONS`] at once; past that a request is turned away with a
+//! 503 instead of starting another. A request line and headers longer than
+//! [`MAX_REQUEST_BYTES`] get a 431, whether or not they ever end.
+//!
+//! Every `--heartbeat-secs` the matchmaker asks each server what it is,
+//! with the connectionless `ServerInfoRequest` (see `common::server_query`),
+//! and logs a server that stops or starts answering. One that didn't
+//! answer the last heartbeat is skipped when taking turns, unless none
+//! did, so players aren't sent to a server that is down. `GET /servers`
+//! lists what each said:
+//!
+//! ```text
+//! server=127.0.0.1:12345 name=bevy-server players=3 spectators=0 mode=tickets version=2 uptime_secs=600 rtt_ms=0.4
+//! server=127.0.0.1:12346 down
+//! ```
+//!
+//! A real matchmaker would authenticate the player first and speak HTTPS.
+//! This one gives a ticket to anyone who asks, which is enough to show the
+//! handshake side.
+
+use clap::Parser;
+use common::handlers::ServerInfo;
+use common::server_query::{Querier, QueryResult};
+use common::ticket::{self, DEFAULT_TTL_SECS};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Take, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A client that takes longer than this to send its request is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The request line and headers together; a longer request gets a 431
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+/// Invite codes are made of these, which can't be mistaken for each other
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+const CODE_LEN: usize = 6;
+const USAGE: &str = "GET /ticket for a ticket, /invite for an invite code, /join/<code> to use \
+                     one, /servers for the servers' last heartbeat\n";
+/// A server that takes longer than this to answer a heartbeat is down
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Requests answered at once, each on its own thread
+const MAX_CONNECTIONS: usize = 64;
+/// `/join/` requests one address may make per [`JOIN_WINDOW`]
+const JOIN_ATTEMPTS: usize = 10;
+const JOIN_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Address to listen on for ticket requests
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Secret shared with the servers' --ticket-key
+    #[arg(long)]
+    key: String,
+
+    /// Game server to send players to (repeat to take turns between several)
+    #[arg(long = "server", default_values_t = ["127.0.0.1:12345".to_string()])]
+    servers: Vec<String>,
+
+    /// Seconds a ticket is good for; it only has to last until the handshake
+    #[arg(long, default_value_t = DEFAULT_TTL_SECS)]
+    ttl: u64,
+
+    /// Seconds an invite code is good for
+    #[arg(long, default_value_t = 3600)]
+    code_ttl: u64,
+
+    /// Seconds between two queries of every server, to find the ones that are down (0 disables)
+    #[arg(long, default_value_t = 5.0, value_parser = parse_heartbeat_secs)]
+    heartbeat_secs: f32,
+}
+
+/// `--heartbeat-secs`: 0, or a number of seconds a `Duration` can hold
+fn parse_heartbeat_secs(text: &str) -> Result<f32, String> {
+    let secs: f32 = text.parse().map_err(|e| format!("{}", e))?;
+    if Duration::try_from_secs_f32(secs).is_err() {
+        return Err(format!("{} is not a number of seconds", text));
+    }
+    Ok(secs)
+}
+
+struct Invite {
+    server: String,
+    expires: u64,
+}
+
+/// When each address last tried `/join/`, inside [`JOIN_WINDOW`]
+#[derive(Default)]
+struct JoinAttempts(HashMap<IpAddr, VecDeque<Instant>>);
+
+impl JoinAttempts {
+    /// Counts an attempt from `ip`; false if it is one too many.
+    fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        self.0.retain(|_, attempts| {
+            while attempts
+                .front()
+                .is_some_and(|at| now.duration_since(*at) >= JOIN_WINDOW)
+            {
+                attempts.pop_front();
+            }
+            !attempts.is_empty()
+        });
+        let attempts = self.0.entry(ip).or_default();
+        if attempts.len() >= JOIN_ATTEMPTS {
+            return false;
+        }
+        attempts.push_back(now);
+        true
+    }
+}
+
+/// One of the [`MAX_CONNECTIONS`]; gives its place back when dropped
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn open(open: &Arc<AtomicUsize>) -> Option<Self> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            (count < MAX_CONNECTIONS).then_some(count + 1)
+        })
+        .ok()
+        .map(|_| Self(open.clone()))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+struct Matchmaker {
+    args: Args,
+    issued: AtomicU64,
+    /// Requests that were given the next server
+    turns: AtomicU64,
+    /// Randomly keyed per process, so client ids can't be predicted
+    keys: RandomState,
+    /// By code
+    invites: Mutex<HashMap<String, Invite>>,
+    joins: Mutex<JoinAttempts>,
+    /// Each server's answer to the last heartbeat, in `--server` order;
+    /// `None` before the first
+    health: Mutex<Vec<Option<QueryResult>>>,
+}
+
+impl Matchmaker {
+    fn new(args: Args) -> Self {
+        let servers = args.servers.len();
+        Self {
+            args,
+            issued: AtomicU64::new(0),
+            turns: AtomicU64::new(0),
+            keys: RandomState::new(),
+            invites: Mutex::default(),
+            joins: Mutex::default(),
+            health: Mutex::new(vec![None; servers]),
+        }
+    }
+
+    fn client_id(&self, issued: u64) -> u64 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let mut hasher = self.keys.build_hasher();
+        hasher.write_u128(nanos);
+        hasher.write_u64(issued);
+        hasher.finish()
+    }
+
+    /// The next server in turn that answered the last heartbeat, or just
+    /// the next one if none did
+    fn next_server(&self) -> &str {
+        let health = self.health.lock().unwrap();
+        let count = self.args.servers.len();
+        let mut first = None;
+        for _ in 0..count {
+            let turn = self.turns.fetch_add(1, Ordering::Relaxed) as usize % count;
+            let down = matches!(
+                health[turn],
+                Some(QueryResult::NoReply | QueryResult::Failed(_))
+            );
+            if !down {
+                return &self.args.servers[turn];
+            }
+            first.get_or_insert(turn);
+        }
+        &self.args.servers[first.unwrap_or(0)]
+    }
+
+    /// Asks every server what it is, every `interval`, for as long as we run.
+    fn watch_servers(&self, interval: Duration) {
+        let querier = Querier::bind();
+        loop {
+            let results = querier.query(&self.args.servers, HEARTBEAT_TIMEOUT);
+            let mut health = self.health.lock().unwrap();
+            for ((server, last), result) in
+                self.args.servers.iter().zip(health.iter_mut()).zip(results)
+            {
+                report_change(server, last.as_ref(), &result);
+                *last = Some(result);
+            }
+            drop(health);
+            thread::sleep(interval);
+        }
+    }
+
+    /// The body for `GET /servers`
+    fn list_servers(&self) -> String {
+        let health = self.health.lock().unwrap();
+        let mut body = String::new();
+        for (server, last) in self.args.servers.iter().zip(health.iter()) {
+            let state = match last {
+                Some(QueryResult::Answered { info, rtt_ms }) => describe(info, *rtt_ms),
+                Some(QueryResult::NoReply | QueryResult::Failed(_)) => "down".to_string(),
+                None => "unknown".to_string(),
+            };
+            body.push_str(&format!("server={} {}\n", server, state));
+        }
+        body
+    }
+
+    /// The response body for one ticket request to `server`
+    fn issue(&self, server: &str) -> String {
+        let issued = self.issued.fetch_add(1, Ordering::Relaxed);
+        let expires = ticket::unix_now() + self.args.ttl;
+        let ticket = ticket::issue(self.args.key.as_bytes(), self.client_id(issued), expires);
+        format!("server={} ticket={} expires={}\n", server, ticket, expires)
+    }
+
+    /// A new code for `server`, and the response body; `None` when the OS
+    /// has no randomness to give
+    fn invite(&self, server: &str) -> Option<(String, String)> {
+        let now = ticket::unix_now();
+        let expires = now + self.args.code_ttl;
+        let mut invites = self.invites.lock().unwrap();
+        invites.retain(|_, invite| invite.expires > now);
+        let code = loop {
+            let mut bytes = [0u8; 8];
+            if let Err(e) = getrandom::getrandom(&mut bytes) {
+                eprintln!("Error: no randomness for an invite code: {}", e);
+                return None;
+            }
+            let mut bits = u64::from_le_bytes(bytes);
+            let code: String = (0..CODE_LEN)
+                .map(|_| {
+                    let index = (bits % CODE_ALPHABET.len() as u64) as usize;
+                    bits /= CODE_ALPHABET.len() as u64;
+                    CODE_ALPHABET[index] as char
+                })
+                .collect();
+            if !invites.contains_key(&code) {
+                break code;
+            }
+        };
+        invites.insert(
+            code.clone(),
+            Invite {
+                server: server.to_string(),
+                expires,
+            },
+        );
+        let body = format!("code={} server={} expires={}\n", code, server, expires);
+        Some((code, body))
+    }
+
+    /// The response to `GET /invite` for `server`
+    fn answer_invite(&self, server: &str, peer: impl std::fmt::Display) -> (&'static str, String) {
+        match self.invite(server) {
+            Some((code, body)) => {
+                println!("Invite {} for {} issued to {}", code, server, peer);
+                ("200 OK", body)
+            }
+            None => (
+                "503 Service Unavailable",
+                "no invite codes right now\n".to_string(),
+            ),
+        }
+    }
+
+    /// The server an unexpired `code` is for
+    fn redeem(&self, code: &str) -> Option<String> {
+        let invites = self.invites.lock().unwrap();
+        let invite = invites.get(&code.to_ascii_uppercase())?;
+        (invite.expires > ticket::unix_now()).then(|| invite.server.clone())
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let peer = stream.peer_addr()?;
+        let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+        let (status, body) = match read_request(&mut reader)? {
+            Some(request) => self.answer(&request, peer),
+            None => (
+                "431 Request Header Fields Too Large",
+                format!("a request may be at most {} bytes\n", MAX_REQUEST_BYTES),
+            ),
+        };
+        write!(
+            stream,
+            "HTTP/1.0 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    /// The status and body for `request`, the request line
+    fn answer(&self, request: &str, peer: SocketAddr) -> (&'static str, String) {
+        let mut words = request.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("GET"), Some("/ticket")) => {
+                let server = self.next_server();
+                println!("Ticket for {} issued to {}", server, peer);
+                ("200 OK", self.issue(server))
+            }
+            (Some("GET"), Some("/servers")) => ("200 OK", self.list_servers()),
+            (Some("GET"), Some("/invite")) => self.answer_invite(self.next_server(), peer),
+            (Some("GET"), Some(path)) if path.starts_with("/invite?server=") => {
+                let server = &path["/invite?server=".len()..];
+                if self.args.servers.iter().any(|ours| ours == server) {
+                    self.answer_invite(server, peer)
+                } else {
+                    (
+                        "404 Not Found",
+                        format!("{} is not one of our servers\n", server),
+                    )
+                }
+            }
+            (Some("GET"), Some(path)) if path.starts_with("/join/") => {
+                let code = &path["/join/".len()..];
+                // Counted before the code is even looked at, so a client
+                // over the limit learns nothing about it
+                if !self.joins.lock().unwrap().allow(peer.ip(), Instant::now()) {
+                    return (
+                        "429 Too Many Requests",
+                        "too many codes tried, wait a minute\n".to_string(),
+                    );
+                }
+                match self.redeem(code) {
+                    Some(server) => {
+                        println!("Ticket for {} issued to {} by code {}", server, peer, code);
+                        ("200 OK", self.issue(&server))
+                    }
+                    None => ("404 Not Found", format!("no invite with code {}\n", code)),
+                }
+            }
+            (Some("GET"), Some(_)) => ("404 Not Found", USAGE.to_string()),
+            _ => ("405 Method Not Allowed", USAGE.to_string()),
+        }
+    }
+}
+
+/// Reads the request line, and the headers up to the blank line after
+/// them, which are ignored. `None` if they run past the reader's limit.
+fn read_request<R: Read>(reader: &mut BufReader<Take<R>>) -> io::Result<Option<String>> {
+    let mut request = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            if reader.get_ref().limit() == 0 {
+                return Ok(None);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "request ended early",
+            ));
+        }
+        if request.is_empty() {
+            request = line.clone();
+        } else if line.trim_end().is_empty() {
+            return Ok(Some(request));
+        }
+    }
+}
+
+/// `name=... players=...`, the fields of a heartbeat's answer
+fn describe(info: &ServerInfo, rtt_ms: f32) -> String {
+    format!(
+        "name={} players={} spectators={} mode={} version={} uptime_secs={} rtt_ms={:.1}",
+        info.name, info.players, info.spectators, info.mode, info.version, info.uptime_secs, rtt_ms
+    )
+}
+
+/// Logs a server that started or stopped answering, and warns about one
+/// that lets players in without a ticket when it comes up.
+fn report_change(server: &str, last: Option<&QueryResult>, result: &QueryResult) {
+    let was_up = matches!(last, Some(QueryResult::Answered { .. }));
+    match result {
+        QueryResult::Answered { info, .. } if !was_up => {
+            println!(
+                "{} is up: {}, {} players, protocol {}",
+                server, info.name, info.players, info.version
+            );
+            if info.mode != "tickets" {
+                println!(
+                    "Warning: {} lets players in without a ticket (start it with --ticket-key)",
+                    server
+                );
+            }
+        }
+        QueryResult::NoReply if last.is_none() || was_up => {
+            println!("{} is down: no answer to the heartbeat", server);
+        }
+        QueryResult::Failed(reason) if last.is_none() || was_up => {
+            println!("{} is down: {}", server, reason);
+        }
+        _ => {}
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    if args.key.is_empty() {
+        eprintln!("Error: --key must not be empty");
+        std::process::exit(1);
+    }
+    let listener = match TcpListener::bind(&args.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: could not bind {}: {}", args.listen, e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Matchmaker on http://{}/ticket, sending players to {}",
+        args.listen,
+        args.servers.join(", ")
+    );
+
+    let heartbeat_secs = args.heartbeat_secs;
+    let matchmaker = Arc::new(Matchmaker::new(args));
+    if heartbeat_secs > 0.0 {
+        let matchmaker = matchmaker.clone();
+        let interval = Duration::from_secs_f32(heartbeat_secs);
+        thread::spawn(move || matchmaker.watch_servers(interval));
+    }
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error: accept failed: {}", e);
+                continue;
+            }
+        };
+        let Some(connection) = Connection::open(&open) else {
+            // Best effort: the reply fits the socket's send buffer
+            let _ =
+                stream.write_all(b"HTTP/1.0 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n");
+            continue;
+        };
+        let matchmaker = matchmaker.clone();
+        thread::spawn(move || {
+            let _connection = connection;
+            if let Err(e) = matchmaker.handle(stream) {
+                eprintln!("Error: request failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read(request: &str) -> io::Result<Option<String>> {
+        let mut reader = BufReader::new(Cursor::new(request.as_bytes()).take(64));
+        read_request(&mut reader)
+    }
+
+    #[test]
+    fn reads_a_request_line_and_skips_the_headers() {
+        let request = read("GET /ticket HTTP/1.0\r\nHost: here\r\n\r\n").unwrap();
+        assert_eq!(request.as_deref(), Some("GET /ticket HTTP/1.0\r\n"));
+    }
+
+    #[test]
+    fn stops_reading_a_request_past_the_limit() {
+        // One endless line, and endless headers
+        assert_eq!(read(&"A".repeat(100)).unwrap(), None);
+        let headers = format!("GET / HTTP/1.0\r\n{}", "X: y\r\n".repeat(20));
+        assert_eq!(read(&headers).unwrap(), None);
+        assert!(read("GET / HTTP/1.0\r\nX: y").is_err());
+    }
+
+    #[test]
+    fn turns_away_a_limited_join_before_looking_at_the_code() {
+        let matchmaker = Matchmaker::new(Args::parse_from(["matchmaker", "--key", "k"]));
+        let (code, _) = matchmaker.invite("127.0.0.1:12345").unwrap();
+        let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let request = format!("GET /join/{} HTTP/1.0", code);
+        for _ in 0..JOIN_ATTEMPTS - 1 {
+            matchmaker.answer("GET /join/NOSUCH HTTP/1.0", peer);
+        }
+        assert_eq!(matchmaker.answer(&request, peer).0, "200 OK");
+        assert_eq!(matchmaker.answer(&request, peer).0, "429 Too Many Requests");
+    }
+
+    #[test]
+    fn turns_away_an_address_that_tries_too_many_codes() {
+        let mut joins = JoinAttempts::default();
+        let (ip, other): (IpAddr, IpAddr) =
+            ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+        for _ in 0..JOIN_ATTEMPTS {
+            assert!(joins.allow(ip, start));
+        }
+        assert!(!joins.allow(ip, start));
+        assert!(joins.allow(other, start));
+        assert!(joins.allow(ip, start + JOIN_WINDOW));
+    }
+
+    #[test]
+    fn takes_only_heartbeats_a_duration_can_hold() {
+        assert_eq!(parse_heartbeat_secs("2.5"), Ok(2.5));
+        assert_eq!(parse_heartbeat_secs("0"), Ok(0.0));
+        for bad in ["NaN", "inf", "-1", "1e30", "soon"] {
+            assert!(parse_heartbeat_secs(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn gives_back_its_place_when_a_connection_closes() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let connections: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| Connection::open(&open).unwrap())
+            .collect();
+        assert!(Connection::open(&open).is_none());
+        drop(connections);
+        assert!(Connection::open(&open).is_some());
+    }
+}
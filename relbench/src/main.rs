@@ -0,0 +1,341 @@
+//! relbench: the same workload through several reliability settings
+//! Usage: cargo run -p relbench -- --loss 0.1 --latency-ms 50 --jitter-ms 20
+//!
+//! Runs `common::reliable`'s sender and receiver against each other over a
+//! simulated link, once per setting, with no sockets and no waiting: time is
+//! a counter stepped by `--tick-ms`. Every run pushes the same `--messages`
+//! payloads and draws its losses and delays from the same `--seed`, so the
+//! settings are what differs. A setting is
+//! `window=<n>,resend=<secs>,rate=<payloads/sec>`, and a part left out
+//! takes the default. One row per setting:
+//!
+//! ```text
+//! setting                      delivered  p50 ms  p95 ms  max ms resends wire KB overhead  done s
+//! window=4 resend=0.2 rate=50    200/200    62.3   341.8   598.1      57    29.1     +49%     4.6
+//! ```
+//!
+//! Latency runs from the push to delivery in order, so it includes time
+//! queued behind a full window and time held behind a lost payload.
+//! Overhead is every byte on the wire, both ways, resends and acks
+//! included, over the payload bytes pushed.
+
+use clap::Parser;
+use common::reliable::{ReliableMessage, ReliableReceiver, ReliableSender};
+use common::shared_rng::SharedRng;
+use std::fmt;
+
+const DEFAULT_WINDOW: usize = 4;
+const DEFAULT_RESEND_SECS: f32 = 0.2;
+const DEFAULT_RATE: f32 = 50.0;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// `window=<n>,resend=<secs>,rate=<payloads/sec>`, once per setting to compare (default: a
+    /// spread of windows, resend times and rates)
+    #[arg(long = "setting", value_parser = parse_setting)]
+    settings: Vec<Setting>,
+
+    /// Payloads each run pushes
+    #[arg(long, default_value_t = 200)]
+    messages: usize,
+
+    /// Bytes in each payload
+    #[arg(long, default_value_t = 100)]
+    payload_bytes: usize,
+
+    /// Share of datagrams lost, each way (0 to 1)
+    #[arg(long, default_value_t = 0.1)]
+    loss: f32,
+
+    /// One-way delay in milliseconds
+    #[arg(long, default_value_t = 50.0)]
+    latency_ms: f32,
+
+    /// Up to this many more milliseconds, at random, so datagrams can overtake each other
+    #[arg(long, default_value_t = 20.0)]
+    jitter_ms: f32,
+
+    /// Seed for the losses and delays, the same for every setting
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// Simulated milliseconds per step
+    #[arg(long, default_value_t = 1.0)]
+    tick_ms: f32,
+
+    /// Stop a run that hasn't delivered everything after this many simulated seconds
+    #[arg(long, default_value_t = 120.0)]
+    max_secs: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Setting {
+    /// Most payloads unacked at once
+    window: usize,
+    /// Seconds before an unacked payload is sent again
+    resend_secs: f32,
+    /// Payloads pushed per second
+    rate: f32,
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "window={} resend={} rate={}",
+            self.window, self.resend_secs, self.rate
+        )
+    }
+}
+
+fn parse_setting(text: &str) -> Result<Setting, String> {
+    let mut setting = Setting {
+        window: DEFAULT_WINDOW,
+        resend_secs: DEFAULT_RESEND_SECS,
+        rate: DEFAULT_RATE,
+    };
+    for part in text.split(',').filter(|part| !part.is_empty()) {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("{} is not key=value", part))?;
+        let bad = || format!("bad {}: {}", key, value);
+        match key {
+            "window" => {
+                setting.window = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(bad)?;
+            }
+            "resend" => {
+                setting.resend_secs = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|&s| s >= 0.0)
+                    .ok_or_else(bad)?;
+            }
+            "rate" => {
+                setting.rate = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|&r| r > 0.0)
+                    .ok_or_else(bad)?;
+            }
+            _ => return Err(format!("{} is not window, resend or rate", key)),
+        }
+    }
+    Ok(setting)
+}
+
+/// Compared when no `--setting` is given: the window, the resend time and
+/// the rate each moved away from the defaults on their own.
+fn default_settings() -> Vec<Setting> {
+    let base = Setting {
+        window: DEFAULT_WINDOW,
+        resend_secs: DEFAULT_RESEND_SECS,
+        rate: DEFAULT_RATE,
+    };
+    vec![
+        Setting { window: 1, ..base },
+        base,
+        Setting { window: 16, ..base },
+        Setting {
+            resend_secs: 0.1,
+            ..base
+        },
+        Setting {
+            resend_secs: 0.5,
+            ..base
+        },
+        Setting {
+            rate: 200.0,
+            ..base
+        },
+    ]
+}
+
+/// One direction of the simulated link.
+struct Link {
+    rng: SharedRng,
+    loss: f32,
+    latency: f32,
+    jitter: f32,
+    /// (arrives at, line)
+    in_transit: Vec<(f32, String)>,
+    /// Everything sent, lost or not
+    bytes: usize,
+}
+
+impl Link {
+    fn new(args: &Args, seed: u64) -> Self {
+        Self {
+            rng: SharedRng::new(seed),
+            loss: args.loss,
+            latency: args.latency_ms / 1000.0,
+            jitter: args.jitter_ms / 1000.0,
+            in_transit: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    fn send(&mut self, now: f32, line: String) {
+        self.bytes += line.len();
+        if self.rng.unit() < self.loss {
+            return;
+        }
+        let arrives = now + self.latency + self.jitter * self.rng.unit();
+        self.in_transit.push((arrives, line));
+    }
+
+    /// Lines that have arrived by `now`, in the order they arrived.
+    fn arrived(&mut self, now: f32) -> Vec<String> {
+        let (mut arrived, waiting): (Vec<_>, Vec<_>) = self
+            .in_transit
+            .drain(..)
+            .partition(|(arrives, _)| *arrives <= now);
+        self.in_transit = waiting;
+        arrived.sort_by(|a, b| a.0.total_cmp(&b.0));
+        arrived.into_iter().map(|(_, line)| line).collect()
+    }
+}
+
+struct Outcome {
+    /// Seconds from push to delivery, one per delivered payload
+    latencies: Vec<f32>,
+    resends: u64,
+    wire_bytes: usize,
+    /// When the last ack came back, or when the run was stopped
+    done_at: f32,
+}
+
+/// `<index> xxx...`, `payload_bytes` long unless the index alone is longer.
+fn payload(index: usize, payload_bytes: usize) -> String {
+    let mut payload = format!("{} ", index);
+    let padding = payload_bytes.saturating_sub(payload.len());
+    payload.push_str(&"x".repeat(padding));
+    payload
+}
+
+fn run(setting: Setting, args: &Args) -> Outcome {
+    let mut sender = ReliableSender::new(setting.window, setting.resend_secs);
+    let mut receiver = ReliableReceiver::default();
+    let mut forward = Link::new(args, args.seed);
+    let mut back = Link::new(args, args.seed.wrapping_add(1));
+    let tick = args.tick_ms / 1000.0;
+    let mut pushed_at = Vec::with_capacity(args.messages);
+    let mut latencies = Vec::with_capacity(args.messages);
+
+    let mut step = 0u64;
+    let done_at = loop {
+        // Counted in steps, so long runs don't pile up rounding
+        let now = step as f32 * tick;
+        if now > args.max_secs {
+            break now;
+        }
+        while pushed_at.len() < args.messages && pushed_at.len() as f32 / setting.rate <= now {
+            sender.push(payload(pushed_at.len(), args.payload_bytes));
+            pushed_at.push(now);
+        }
+        for line in sender.poll(now) {
+            forward.send(now, line);
+        }
+        for line in forward.arrived(now) {
+            let Some(ReliableMessage::Data { seq, payload }) = ReliableMessage::parse(&line) else {
+                continue;
+            };
+            let (ack, delivered) = receiver.receive(seq, payload);
+            if let Some(ack) = ack {
+                back.send(now, ack);
+            }
+            for payload in delivered {
+                let index = payload
+                    .split(' ')
+                    .next()
+                    .and_then(|i| i.parse::<usize>().ok());
+                if let Some(at) = index.and_then(|index| pushed_at.get(index)) {
+                    latencies.push(now - at);
+                }
+            }
+        }
+        for line in back.arrived(now) {
+            if let Some(ReliableMessage::Ack { seq }) = ReliableMessage::parse(&line) {
+                sender.ack(seq);
+            }
+        }
+        if pushed_at.len() == args.messages && sender.is_idle() {
+            break now;
+        }
+        step += 1;
+    };
+
+    Outcome {
+        latencies,
+        resends: sender.resends,
+        wire_bytes: forward.bytes + back.bytes,
+        done_at,
+    }
+}
+
+/// The value `share` of the way through `sorted`, in milliseconds
+fn percentile_ms(sorted: &[f32], share: f32) -> f32 {
+    if sorted.is_empty() {
+        return f32::NAN;
+    }
+    let index = ((sorted.len() - 1) as f32 * share).round() as usize;
+    sorted[index] * 1000.0
+}
+
+fn main() {
+    let args = Args::parse();
+    if !(0.0..=1.0).contains(&args.loss) || args.tick_ms <= 0.0 {
+        eprintln!("Error: --loss must be 0 to 1 and --tick-ms above 0");
+        std::process::exit(1);
+    }
+    let settings = if args.settings.is_empty() {
+        default_settings()
+    } else {
+        args.settings.clone()
+    };
+
+    println!(
+        "{} payloads of {} bytes; {}% lost each way, {} ms + up to {} ms delay; seed {}",
+        args.messages,
+        args.payload_bytes,
+        args.loss * 100.0,
+        args.latency_ms,
+        args.jitter_ms,
+        args.seed
+    );
+    println!();
+    println!(
+        "{:<28} {:>9} {:>7} {:>7} {:>7} {:>7} {:>7} {:>8} {:>7}",
+        "setting",
+        "delivered",
+        "p50 ms",
+        "p95 ms",
+        "max ms",
+        "resends",
+        "wire KB",
+        "overhead",
+        "done s"
+    );
+    let payload_bytes = (args.messages * args.payload_bytes).max(1) as f32;
+    for setting in settings {
+        let mut outcome = run(setting, &args);
+        outcome.latencies.sort_by(f32::total_cmp);
+        let overhead = (outcome.wire_bytes as f32 / payload_bytes - 1.0) * 100.0;
+        println!(
+            "{:<28} {:>9} {:>7.1} {:>7.1} {:>7.1} {:>7} {:>7.1} {:>+7.0}% {:>7.1}",
+            setting.to_string(),
+            format!("{}/{}", outcome.latencies.len(), args.messages),
+            percentile_ms(&outcome.latencies, 0.5),
+            percentile_ms(&outcome.latencies, 0.95),
+            percentile_ms(&outcome.latencies, 1.0),
+            outcome.resends,
+            outcome.wire_bytes as f32 / 1024.0,
+            overhead,
+            outcome.done_at
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! The fighting mini-game. Everything is integers and stepped one frame at
+//! a time from both players' inputs, so the same inputs from the same state
+//! give the same state on every machine, which is all rollback needs.
+//!
+//! Two fighters on a flat stage walk left and right and punch. A punch
+//! takes [`ATTACK_FRAMES`]; it can only land during [`ACTIVE_FRAMES`] of
+//! it, which gives late inputs something to get wrong. A hit knocks the
+//! other fighter back and stuns them; at 0 HP the round is over and the
+//! next starts after a pause.
+
+use std::ops::RangeInclusive;
+
+pub const STAGE_WIDTH: i32 = 1000;
+pub const BODY_WIDTH: i32 = 60;
+pub const MAX_HP: i32 = 100;
+/// How far past the body a punch reaches
+pub const REACH: i32 = 70;
+pub const ATTACK_FRAMES: u8 = 24;
+/// The frames of a punch, counted from 1, that can hit
+pub const ACTIVE_FRAMES: RangeInclusive<u8> = 6..=10;
+
+const START_X: [i32; 2] = [300, 700];
+const WALK_SPEED: i32 = 6;
+const DAMAGE: i32 = 10;
+const KNOCKBACK: i32 = 50;
+const HITSTUN_FRAMES: u8 = 15;
+/// Frames between a knockout and the next round
+const ROUND_PAUSE: u16 = 120;
+
+/// One player's buttons for one frame.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Input(pub u8);
+
+impl Input {
+    pub const LEFT: u8 = 1;
+    pub const RIGHT: u8 = 2;
+    pub const ATTACK: u8 = 4;
+
+    pub fn has(self, button: u8) -> bool {
+        self.0 & button != 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fighter {
+    /// Middle of the body, 0..STAGE_WIDTH
+    pub x: i32,
+    pub hp: i32,
+    /// Frames into the current punch, 0 when not punching
+    pub attack: u8,
+    /// This punch already hit; one hit per punch
+    pub landed: bool,
+    /// Frames left unable to act after being hit
+    pub hitstun: u8,
+}
+
+impl Fighter {
+    fn new(x: i32) -> Self {
+        Self {
+            x,
+            hp: MAX_HP,
+            attack: 0,
+            landed: false,
+            hitstun: 0,
+        }
+    }
+
+    pub fn punching(&self) -> bool {
+        ACTIVE_FRAMES.contains(&self.attack)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GameState {
+    /// Frames stepped so far
+    pub frame: u32,
+    pub fighters: [Fighter; 2],
+    pub wins: [u32; 2],
+    /// Frames left before the next round, after a knockout
+    pub pause: u16,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            fighters: START_X.map(Fighter::new),
+            wins: [0, 0],
+            pause: 0,
+        }
+    }
+}
+
+impl GameState {
+    /// 1 if `player` faces right, -1 if left; fighters always face each other.
+    pub fn facing(&self, player: usize) -> i32 {
+        let (me, other) = (self.fighters[player].x, self.fighters[1 - player].x);
+        match other.cmp(&me) {
+            std::cmp::Ordering::Greater => 1,
+            std::cmp::Ordering::Less => -1,
+            // Pushed apart next frame; player 1 is the one on the left
+            std::cmp::Ordering::Equal if player == 0 => 1,
+            std::cmp::Ordering::Equal => -1,
+        }
+    }
+
+    pub fn step(&mut self, inputs: [Input; 2]) {
+        self.frame += 1;
+        if self.pause > 0 {
+            self.pause -= 1;
+            if self.pause == 0 {
+                self.fighters = START_X.map(Fighter::new);
+            }
+            return;
+        }
+
+        for (fighter, input) in self.fighters.iter_mut().zip(inputs) {
+            if fighter.hitstun > 0 {
+                fighter.hitstun -= 1;
+            } else if fighter.attack > 0 {
+                // Committed to the punch until it's over
+                fighter.attack += 1;
+                if fighter.attack > ATTACK_FRAMES {
+                    fighter.attack = 0;
+                }
+            } else if input.has(Input::ATTACK) {
+                fighter.attack = 1;
+                fighter.landed = false;
+            } else {
+                let direction = input.has(Input::RIGHT) as i32 - input.has(Input::LEFT) as i32;
+                fighter.x = clamp_to_stage(fighter.x + direction * WALK_SPEED);
+            }
+        }
+        self.separate();
+
+        // Both checked before either lands, so a trade hits both ways
+        let hits = [0, 1].map(|player| self.hits(player));
+        for (player, hit) in hits.into_iter().enumerate() {
+            if !hit {
+                continue;
+            }
+            let facing = self.facing(player);
+            self.fighters[player].landed = true;
+            let target = &mut self.fighters[1 - player];
+            target.hp = (target.hp - DAMAGE).max(0);
+            target.attack = 0;
+            target.hitstun = HITSTUN_FRAMES;
+            target.x = clamp_to_stage(target.x + facing * KNOCKBACK);
+        }
+        self.separate();
+
+        let knocked_out = self.fighters.map(|fighter| fighter.hp == 0);
+        if knocked_out[0] || knocked_out[1] {
+            // A double knockout is nobody's win
+            for (player, out) in knocked_out.into_iter().enumerate() {
+                if !out && knocked_out[1 - player] {
+                    self.wins[player] += 1;
+                }
+            }
+            self.pause = ROUND_PAUSE;
+        }
+    }
+
+    fn hits(&self, player: usize) -> bool {
+        let (fighter, target) = (self.fighters[player], self.fighters[1 - player]);
+        fighter.punching()
+            && !fighter.landed
+            && target.hp > 0
+            && (target.x - fighter.x).abs() <= BODY_WIDTH + REACH
+    }
+
+    /// Bodies don't overlap; both are pushed apart evenly.
+    fn separate(&mut self) {
+        let gap = (self.fighters[1].x - self.fighters[0].x).abs();
+        if gap >= BODY_WIDTH {
+            return;
+        }
+        let push = (BODY_WIDTH - gap + 1) / 2;
+        let left = if self.facing(0) > 0 { 0 } else { 1 };
+        self.fighters[left].x = clamp_to_stage(self.fighters[left].x - push);
+        self.fighters[1 - left].x = clamp_to_stage(self.fighters[1 - left].x + push);
+    }
+
+    /// FNV-1a over the whole state, for the two sides to compare.
+    pub fn checksum(&self) -> u32 {
+        let mut words = vec![self.frame as i32, self.pause as i32];
+        words.extend(self.wins.map(|wins| wins as i32));
+        for fighter in &self.fighters {
+            words.extend([
+                fighter.x,
+                fighter.hp,
+                fighter.attack as i32,
+                fighter.landed as i32,
+                fighter.hitstun as i32,
+            ]);
+        }
+        let mut hash: u32 = 0x811c9dc5;
+        for byte in words.iter().flat_map(|word| word.to_le_bytes()) {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+        hash
+    }
+}
+
+fn clamp_to_stage(x: i32) -> i32 {
+    x.clamp(BODY_WIDTH / 2, STAGE_WIDTH - BODY_WIDTH / 2)
+}
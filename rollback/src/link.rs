@@ -0,0 +1,228 @@
+//! The UDP link to the other player, and what goes over it:
+//!
+//! ```text
+//! INPUTS <ack> <first> <inputs>   every tick: our inputs from <first>, one hex digit each
+//! CHECK <frame> <checksum>        every CHECK_INTERVAL confirmed frames
+//! ```
+//!
+//! `<ack>` is the first frame we still need the peer's input for. Each
+//! side sends every input the other hasn't acked, so a lost packet is
+//! covered by the next one and nothing is ever resent on its own.
+//!
+//! The socket and its receive thread are `bevy_udp_net`'s; the link keeps
+//! what comes from the peer and ignores everyone else.
+//!
+//! On localhost inputs arrive well within a frame and there is nothing to
+//! roll back, so what we send can be held back by `--lag-ms` plus up to
+//! `--jitter-ms`, and dropped with probability `--loss`.
+
+use bevy::prelude::*;
+use bevy_udp_net::{NetworkSocket, Receive};
+use common::shared_rng::SharedRng;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::game::Input;
+use crate::session::MAX_SENT_INPUTS;
+
+/// Largest datagram accepted, in bytes: a full `INPUTS` is well under it
+const RECV_BUFFER: usize = 1024;
+
+pub enum PeerMessage {
+    Inputs {
+        ack: u32,
+        first: u32,
+        inputs: Vec<Input>,
+    },
+    Check {
+        frame: u32,
+        checksum: u32,
+    },
+}
+
+impl PeerMessage {
+    pub fn parse(text: &str) -> Option<Self> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            ["INPUTS", ack, first, inputs] => {
+                let first: u32 = first.parse().ok()?;
+                let inputs: Vec<Input> = inputs
+                    .chars()
+                    .map(|digit| digit.to_digit(16).map(|bits| Input(bits as u8)))
+                    .collect::<Option<_>>()?;
+                // More than the peer ever sends, or past the last frame
+                if inputs.len() > MAX_SENT_INPUTS {
+                    return None;
+                }
+                first.checked_add(inputs.len() as u32)?;
+                Some(Self::Inputs {
+                    ack: ack.parse().ok()?,
+                    first,
+                    inputs,
+                })
+            }
+            // Nothing to send yet
+            ["INPUTS", ack, first] => Some(Self::Inputs {
+                ack: ack.parse().ok()?,
+                first: first.parse().ok()?,
+                inputs: Vec::new(),
+            }),
+            ["CHECK", frame, checksum] => Some(Self::Check {
+                frame: frame.parse().ok()?,
+                checksum: u32::from_str_radix(checksum, 16).ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PeerMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inputs { ack, first, inputs } => {
+                write!(f, "INPUTS {} {} ", ack, first)?;
+                for input in inputs {
+                    write!(f, "{:x}", input.0)?;
+                }
+                Ok(())
+            }
+            Self::Check { frame, checksum } => write!(f, "CHECK {} {:08x}", frame, checksum),
+        }
+    }
+}
+
+/// Oldest first
+type Inbox = Arc<Mutex<VecDeque<String>>>;
+
+/// Keeps the peer's datagrams, as text
+struct FromPeer {
+    peer: SocketAddr,
+    inbox: Inbox,
+}
+
+impl Receive for FromPeer {
+    fn received(&mut self, from: SocketAddr, bytes: &[u8]) {
+        if from == self.peer {
+            let message = String::from_utf8_lossy(bytes).to_string();
+            self.inbox.lock().unwrap().push_back(message);
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Link {
+    socket: NetworkSocket,
+    pub peer: SocketAddr,
+    inbox: Inbox,
+    pub lag_ms: u32,
+    pub jitter_ms: u32,
+    /// Share of packets dropped, 0..1
+    pub loss: f32,
+    /// Sent, but held back by the simulated lag: (when it goes, text)
+    held: Vec<(Instant, String)>,
+    /// For the simulated lag and loss only
+    rng: SharedRng,
+}
+
+impl Link {
+    /// Binds `bind` and listens to `peer` only.
+    pub fn open(bind: &str, peer: &str) -> io::Result<Self> {
+        let peer = peer
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+        let socket = NetworkSocket::bind(bind, RECV_BUFFER)?;
+        let inbox = Inbox::default();
+        socket.receive(FromPeer {
+            peer,
+            inbox: inbox.clone(),
+        });
+
+        Ok(Self {
+            socket,
+            peer,
+            inbox,
+            lag_ms: 0,
+            jitter_ms: 0,
+            loss: 0.0,
+            held: Vec::new(),
+            rng: SharedRng::new(SharedRng::random_seed()),
+        })
+    }
+
+    pub fn send(&mut self, message: &PeerMessage) {
+        if self.loss > 0.0 && self.rng.unit() < self.loss {
+            return;
+        }
+        let delay_ms = self.lag_ms as f32 + self.rng.unit() * self.jitter_ms as f32;
+        let due = Instant::now() + Duration::from_secs_f32(delay_ms / 1000.0);
+        self.held.push((due, message.to_string()));
+        self.flush();
+    }
+
+    /// Sends whatever the simulated lag has held long enough. With jitter,
+    /// packets can overtake each other, as they do on real networks.
+    pub fn flush(&mut self) {
+        let now = Instant::now();
+        let (due, held): (Vec<_>, Vec<_>) = self.held.drain(..).partition(|(at, _)| *at <= now);
+        self.held = held;
+        for (_, text) in due {
+            // The peer may not be up yet; its inputs get resent anyway
+            let _ = self.socket.send_to(&text, self.peer);
+        }
+    }
+
+    pub fn receive(&self) -> Vec<PeerMessage> {
+        let received: Vec<String> = self.inbox.lock().unwrap().drain(..).collect();
+        received
+            .iter()
+            .filter_map(|text| PeerMessage::parse(text))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_round_trip() {
+        let sent = PeerMessage::Inputs {
+            ack: 3,
+            first: 7,
+            inputs: vec![Input(0), Input(5), Input(15)],
+        };
+        let Some(PeerMessage::Inputs { ack, first, inputs }) =
+            PeerMessage::parse(&sent.to_string())
+        else {
+            panic!("didn't parse");
+        };
+        assert_eq!((ack, first), (3, 7));
+        assert_eq!(inputs, vec![Input(0), Input(5), Input(15)]);
+    }
+
+    #[test]
+    fn rejects_inputs_past_the_last_frame() {
+        assert!(PeerMessage::parse("INPUTS 0 4294967295 0").is_none());
+        assert!(PeerMessage::parse("INPUTS 0 4294967294 0").is_some());
+        let too_many = "0".repeat(MAX_SENT_INPUTS + 1);
+        assert!(PeerMessage::parse(&format!("INPUTS 0 0 {}", too_many)).is_none());
+    }
+
+    #[test]
+    fn keeps_only_the_peers_datagrams() {
+        let peer: SocketAddr = "127.0.0.1:7000".parse().unwrap();
+        let mut receiver = FromPeer {
+            peer,
+            inbox: Inbox::default(),
+        };
+        receiver.received(peer, b"CHECK 6 0000002a");
+        receiver.received("127.0.0.1:7001".parse().unwrap(), b"CHECK 6 00000000");
+        let inbox = receiver.inbox.lock().unwrap();
+        assert_eq!(inbox.iter().collect::<Vec<_>>(), ["CHECK 6 0000002a"]);
+    }
+}
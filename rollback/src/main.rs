@@ -0,0 +1,504 @@
+//! Rollback Netcode Demo
+//! Usage: cargo run -p rollback -- --player 1
+//!        cargo run -p rollback -- --player 2
+//!
+//! A two-player fighting mini-game played peer to peer, GGPO style: each
+//! side sends its inputs straight to the other, runs the game ahead on a
+//! prediction of the remote player's inputs, and rolls back and
+//! resimulates when the real ones arrive different (see `session`). The
+//! game is deterministic (see `game`); the two sides checksum the frames
+//! they agree on and show it if they ever drift apart.
+//!
+//! Player 1 listens on 7001 and player 2 on 7002, each sending to the
+//! other, unless `--port` and `--peer` say otherwise. Add `--lag-ms 80`
+//! to see rollbacks on one machine. The chart at the bottom shows each
+//! tick's rollback depth.
+//!
+//! Sliders on the right change the input delay and the rollback limit
+//! while playing, next to what they cost over the last second: how long
+//! your own presses take to show, how often the game corrects itself and
+//! by how much, and how often it freezes waiting for the peer.
+
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy::window::WindowResolution;
+use clap::Parser;
+use common::ui::slider::{Slider, SliderChanged, SliderPlugin, spawn_slider};
+
+mod game;
+mod link;
+mod session;
+
+use game::{BODY_WIDTH, Input, MAX_HP, REACH, STAGE_WIDTH};
+use link::{Link, PeerMessage};
+use session::{RollbackSession, TICK_HISTORY};
+
+/// Game frames per second, on both sides
+const FRAME_HZ: f64 = 60.0;
+const WINDOW_HEIGHT: f32 = 520.0;
+/// Feet of the fighters, in world space
+const GROUND_Y: f32 = -60.0;
+const BODY_HEIGHT: f32 = 140.0;
+/// Bottom of the rollback depth chart, in world space
+const CHART_Y: f32 = -220.0;
+/// Chart height of one frame of rollback, in pixels
+const CHART_FRAME: f32 = 6.0;
+/// Highest input delay and rollback limit the sliders offer
+const MAX_DELAY_SLIDER: u32 = 10;
+const MAX_ROLLBACK_SLIDER: u32 = 20;
+const PLAYER_COLORS: [Color; 2] = [Color::rgb(0.3, 0.5, 1.0), Color::rgb(1.0, 0.35, 0.3)];
+
+#[derive(Parser, Resource, Debug, Clone)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// 1 starts on the left, 2 on the right
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=2))]
+    player: u8,
+
+    /// Port to listen on [default: 7000 + player]
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// The other player's address [default: 127.0.0.1:7000 + their player]
+    #[arg(long)]
+    peer: Option<String>,
+
+    /// Frames between pressing a key and the game acting on it
+    #[arg(long, default_value_t = 2)]
+    input_delay: u32,
+
+    /// Most frames the game runs ahead of the other player's inputs
+    #[arg(long, default_value_t = 8)]
+    max_rollback: u32,
+
+    /// Hold back everything we send by this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    lag_ms: u32,
+
+    /// Up to this many milliseconds more, at random
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u32,
+
+    /// Share of packets to drop, 0 to 1
+    #[arg(long, default_value_t = 0.0)]
+    loss: f32,
+}
+
+#[derive(Resource)]
+struct Rollback(RollbackSession);
+
+#[derive(Component)]
+struct Body(usize);
+
+#[derive(Component)]
+struct Fist(usize);
+
+#[derive(Component)]
+struct HealthBar(usize);
+
+#[derive(Component)]
+struct HudText;
+
+#[derive(Component)]
+struct DelaySlider;
+
+#[derive(Component)]
+struct RollbackSlider;
+
+/// What the current settings cost, under the sliders
+#[derive(Component)]
+struct TradeoffText;
+
+fn main() {
+    let args = Args::parse();
+    let local = args.player as usize - 1;
+    let port = args.port.unwrap_or(7000 + args.player as u16);
+    let peer = args
+        .peer
+        .clone()
+        .unwrap_or_else(|| format!("127.0.0.1:{}", 7000 + (2 - local as u16)));
+    let mut link = match Link::open(&format!("0.0.0.0:{}", port), &peer) {
+        Ok(link) => link,
+        Err(e) => {
+            eprintln!("Error: could not open port {} to {}: {}", port, peer, e);
+            std::process::exit(1);
+        }
+    };
+    link.lag_ms = args.lag_ms;
+    link.jitter_ms = args.jitter_ms;
+    link.loss = args.loss;
+    println!(
+        "Player {} on port {}, playing against {}",
+        args.player, port, link.peer
+    );
+    let session = RollbackSession::new(local, args.input_delay, args.max_rollback);
+
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: format!("Rollback - Player {}", args.player),
+                    resolution: WindowResolution::new(STAGE_WIDTH as f32, WINDOW_HEIGHT),
+                    ..default()
+                }),
+                ..default()
+            }),
+            SliderPlugin,
+        ))
+        .insert_resource(Time::<Fixed>::from_hz(FRAME_HZ))
+        .insert_resource(args)
+        .insert_resource(link)
+        .insert_resource(Rollback(session))
+        .add_systems(Startup, setup)
+        .add_systems(FixedUpdate, run_frame)
+        .add_systems(
+            Update,
+            (
+                flush_link,
+                apply_sliders,
+                draw_fighters,
+                draw_depth_chart,
+                update_hud,
+                update_tradeoffs,
+            ),
+        )
+        .run();
+}
+
+fn setup(mut commands: Commands, args: Res<Args>) {
+    commands.spawn(Camera2dBundle::default());
+    for (player, color) in PLAYER_COLORS.into_iter().enumerate() {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::new(BODY_WIDTH as f32, BODY_HEIGHT)),
+                    anchor: Anchor::BottomCenter,
+                    ..default()
+                },
+                ..default()
+            },
+            Body(player),
+        ));
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::new(REACH as f32, 16.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            Fist(player),
+        ));
+        let (x, anchor) = if player == 0 {
+            (-STAGE_WIDTH as f32 / 2.0 + 20.0, Anchor::CenterLeft)
+        } else {
+            (STAGE_WIDTH as f32 / 2.0 - 20.0, Anchor::CenterRight)
+        };
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    anchor,
+                    ..default()
+                },
+                transform: Transform::from_xyz(x, WINDOW_HEIGHT / 2.0 - 20.0, 0.0),
+                ..default()
+            },
+            HealthBar(player),
+        ));
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 16.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(40.0),
+            left: Val::Px(20.0),
+            ..default()
+        }),
+        HudText,
+    ));
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                right: Val::Px(20.0),
+                width: Val::Px(260.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|panel| {
+            let most_delay = MAX_DELAY_SLIDER.max(args.input_delay);
+            spawn_slider(
+                panel,
+                Slider::new("Input delay (frames)", 0.0, most_delay as f32, 1.0)
+                    .with_value(args.input_delay as f32),
+                Val::Percent(100.0),
+                DelaySlider,
+            );
+            let most_rollback = MAX_ROLLBACK_SLIDER.max(args.max_rollback);
+            spawn_slider(
+                panel,
+                Slider::new("Max rollback (frames)", 0.0, most_rollback as f32, 1.0)
+                    .with_value(args.max_rollback as f32),
+                Val::Percent(100.0),
+                RollbackSlider,
+            );
+            panel.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        ..default()
+                    },
+                ),
+                TradeoffText,
+            ));
+        });
+}
+
+/// The settings only change this side: our input delay is how early the
+/// peer gets our inputs, and our rollback limit how far we guess theirs.
+fn apply_sliders(
+    mut changes: EventReader<SliderChanged>,
+    delay_sliders: Query<(), With<DelaySlider>>,
+    rollback_sliders: Query<(), With<RollbackSlider>>,
+    mut rollback: ResMut<Rollback>,
+) {
+    for change in changes.read() {
+        let frames = change.value as u32;
+        if delay_sliders.contains(change.entity) {
+            rollback.0.input_delay = frames;
+        } else if rollback_sliders.contains(change.entity) {
+            rollback.0.max_rollback = frames;
+        }
+    }
+}
+
+fn local_input(keys: &ButtonInput<KeyCode>) -> Input {
+    let mut bits = 0;
+    if keys.any_pressed([KeyCode::KeyA, KeyCode::ArrowLeft]) {
+        bits |= Input::LEFT;
+    }
+    if keys.any_pressed([KeyCode::KeyD, KeyCode::ArrowRight]) {
+        bits |= Input::RIGHT;
+    }
+    if keys.any_pressed([KeyCode::Space, KeyCode::KeyJ]) {
+        bits |= Input::ATTACK;
+    }
+    Input(bits)
+}
+
+/// One game tick: take what the peer sent, add our input, advance (rolling
+/// back first if a guess was wrong), and send the peer what it lacks.
+fn run_frame(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut rollback: ResMut<Rollback>,
+    mut link: ResMut<Link>,
+) {
+    let session = &mut rollback.0;
+    for message in link.receive() {
+        match message {
+            PeerMessage::Inputs { ack, first, inputs } => {
+                session.peer_acked(ack);
+                session.add_remote_inputs(first, &inputs);
+            }
+            PeerMessage::Check { frame, checksum } => session.add_peer_check(frame, checksum),
+        }
+    }
+
+    session.add_local_input(local_input(&keys));
+    session.advance();
+
+    let (first, inputs) = session.unacked_inputs();
+    link.send(&PeerMessage::Inputs {
+        ack: session.remote_confirmed(),
+        first,
+        inputs,
+    });
+    for (frame, checksum) in session.outgoing_checks.drain(..) {
+        link.send(&PeerMessage::Check { frame, checksum });
+    }
+}
+
+/// Lagged packets go out on time even between game ticks.
+fn flush_link(mut link: ResMut<Link>) {
+    link.flush();
+}
+
+/// Stage x (0..STAGE_WIDTH) in world space, where the middle is 0.
+fn world_x(x: i32) -> f32 {
+    (x - STAGE_WIDTH / 2) as f32
+}
+
+/// The bodies, fists and health bars, which share `Transform` and `Sprite`
+type FighterSprites<'w, 's> = ParamSet<
+    'w,
+    's,
+    (
+        Query<'static, 'static, (&'static Body, &'static mut Transform, &'static mut Sprite)>,
+        Query<
+            'static,
+            'static,
+            (
+                &'static Fist,
+                &'static mut Transform,
+                &'static mut Visibility,
+            ),
+        >,
+        Query<'static, 'static, (&'static HealthBar, &'static mut Sprite)>,
+    ),
+>;
+
+fn draw_fighters(rollback: Res<Rollback>, mut sprites: FighterSprites) {
+    let state = rollback.0.state();
+    for (body, mut transform, mut sprite) in &mut sprites.p0() {
+        let fighter = state.fighters[body.0];
+        transform.translation = Vec3::new(world_x(fighter.x), GROUND_Y, 0.0);
+        // Flash white while stunned
+        sprite.color = if fighter.hitstun > 0 {
+            Color::WHITE
+        } else {
+            PLAYER_COLORS[body.0]
+        };
+    }
+    for (fist, mut transform, mut visibility) in &mut sprites.p1() {
+        let fighter = state.fighters[fist.0];
+        let facing = state.facing(fist.0) as f32;
+        // Out to full reach while it can hit, half way while winding up or back
+        let reach = if fighter.punching() { 1.0 } else { 0.5 };
+        let x = world_x(fighter.x) + facing * (BODY_WIDTH as f32 + REACH as f32 * reach) / 2.0;
+        transform.translation = Vec3::new(x, GROUND_Y + BODY_HEIGHT * 0.7, 1.0);
+        *visibility = if fighter.attack > 0 {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+    for (bar, mut sprite) in &mut sprites.p2() {
+        let hp = state.fighters[bar.0].hp;
+        sprite.custom_size = Some(Vec2::new(400.0 * hp as f32 / MAX_HP as f32, 16.0));
+    }
+}
+
+fn draw_depth_chart(mut gizmos: Gizmos, rollback: Res<Rollback>) {
+    let session = &rollback.0;
+    let left = -STAGE_WIDTH as f32 / 2.0 + 20.0;
+    let step = (STAGE_WIDTH as f32 - 40.0) / TICK_HISTORY as f32;
+    let right = left + step * TICK_HISTORY as f32;
+    gizmos.line_2d(
+        Vec2::new(left, CHART_Y),
+        Vec2::new(right, CHART_Y),
+        Color::GRAY,
+    );
+    // Deeper than this the session stalls instead
+    let limit = CHART_Y + session.max_rollback as f32 * CHART_FRAME;
+    gizmos.line_2d(Vec2::new(left, limit), Vec2::new(right, limit), Color::RED);
+    for (i, tick) in session.stats.ticks.iter().enumerate() {
+        let x = left + step * i as f32;
+        if tick.depth > 0 {
+            gizmos.line_2d(
+                Vec2::new(x, CHART_Y),
+                Vec2::new(x, CHART_Y + tick.depth as f32 * CHART_FRAME),
+                Color::YELLOW,
+            );
+        }
+        // Stalls hang below the line
+        if tick.stalled {
+            gizmos.line_2d(
+                Vec2::new(x, CHART_Y),
+                Vec2::new(x, CHART_Y - 2.0 * CHART_FRAME),
+                Color::ORANGE,
+            );
+        }
+    }
+}
+
+fn update_hud(
+    args: Res<Args>,
+    rollback: Res<Rollback>,
+    link: Res<Link>,
+    mut texts: Query<&mut Text, With<HudText>>,
+) {
+    let session = &rollback.0;
+    let state = session.state();
+    let stats = &session.stats;
+    let ahead = session.frames_ahead();
+    let waiting = if session.waiting() {
+        format!(", waiting for {}", link.peer)
+    } else {
+        String::new()
+    };
+    let sync = match (session.desync_at, session.synced_through) {
+        (Some(frame), _) => format!("DESYNC at frame {}", frame),
+        (None, Some(frame)) => format!("in sync through frame {}", frame),
+        (None, None) => "not checked yet".to_string(),
+    };
+    let lines = [
+        format!(
+            "You are player {} ({}). A/D or arrows walk, Space or J punches.",
+            args.player,
+            if session.local == 0 { "blue" } else { "red" }
+        ),
+        format!("Wins: {} - {}", state.wins[0], state.wins[1]),
+        format!(
+            "Frame {}, {} ahead of the last confirmed input{}",
+            state.frame, ahead, waiting
+        ),
+        format!(
+            "Rollbacks: {} ({} frames resimulated), deepest {}, {} stalled ticks",
+            stats.rollbacks, stats.frames_resimulated, stats.deepest, stats.stalls
+        ),
+        format!(
+            "Sending with {}ms lag, +{}ms jitter, {:.0}% loss",
+            link.lag_ms,
+            link.jitter_ms,
+            link.loss * 100.0
+        ),
+        format!("Checksums: {}", sync),
+    ];
+    for mut text in &mut texts {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+fn update_tradeoffs(rollback: Res<Rollback>, mut texts: Query<&mut Text, With<TradeoffText>>) {
+    let session = &rollback.0;
+    let frame_ms = 1000.0 / FRAME_HZ as f32;
+    let recent = session.stats.recent(FRAME_HZ as usize);
+    let lateness = match session.stats.average_lateness() {
+        Some(frames) if frames > 0.0 => {
+            format!("{:.1} frames ({:.0} ms) late", frames, frames * frame_ms)
+        }
+        Some(_) => "in time".to_string(),
+        None => "none yet".to_string(),
+    };
+    let lines = [
+        "Over the last second:".to_string(),
+        format!(
+            "Your presses show after {:.0} ms",
+            session.input_delay as f32 * frame_ms
+        ),
+        format!("Their inputs arrive {}", lateness),
+        format!(
+            "Corrections: {} ({:.1} frames deep)",
+            recent.rollbacks, recent.average_depth
+        ),
+        format!("Frozen waiting: {:.0}% of ticks", recent.stalled * 100.0),
+    ];
+    for mut text in &mut texts {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,435 @@
+//! The rollback session, GGPO style: run the game ahead on a guess of the
+//! remote player's input, and rewind when the real one turns out different.
+//!
+//! Each frame the local input is recorded for `frame + input_delay` and
+//! sent to the peer. A frame runs with the remote input for it if that has
+//! arrived, or else a prediction: the last remote input we have, held.
+//! When a remote input arrives for a frame that already ran on a wrong
+//! guess, the session restores the state saved before that frame and runs
+//! every frame since again with what it now knows: a rollback, as deep as
+//! the frames it replayed.
+//!
+//! The session runs at most `max_rollback` frames past the last frame it
+//! has every remote input for; past that it stalls, waiting for the peer,
+//! rather than guess further. States are only kept back to that frame,
+//! since nothing earlier can change any more, and every
+//! [`CHECK_INTERVAL`]th of those final states is checksummed for the peer
+//! to compare: two sides that disagree have desynced.
+//!
+//! The peer sends its inputs from the first one we haven't acked, at most
+//! [`MAX_SENT_INPUTS`] of them, so a remote input or checksum for a frame
+//! further ahead than that can't be from an honest peer and is ignored;
+//! what the session keeps for the peer stays bounded either way.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::game::{GameState, Input};
+
+/// Frames between checksums of confirmed states
+pub const CHECK_INTERVAL: u32 = 60;
+/// Ticks kept for the chart and the recent figures
+pub const TICK_HISTORY: usize = 180;
+/// Remote inputs whose lateness is averaged
+const LATENESS_HISTORY: usize = 60;
+/// Most inputs one packet carries
+pub const MAX_SENT_INPUTS: usize = 64;
+
+#[derive(Clone, Copy, Default)]
+pub struct Tick {
+    /// Frames replayed by this tick's rollback; 0 if there was none
+    pub depth: u32,
+    /// No frame ran, waiting for the peer
+    pub stalled: bool,
+}
+
+/// How the last few ticks went.
+pub struct RecentStats {
+    pub rollbacks: usize,
+    /// Average depth of those rollbacks
+    pub average_depth: f32,
+    /// Share of ticks that stalled, 0..=1
+    pub stalled: f32,
+}
+
+#[derive(Default)]
+pub struct RollbackStats {
+    /// Oldest first
+    pub ticks: VecDeque<Tick>,
+    /// Frames after it was needed that each recent remote input arrived;
+    /// negative if it came before its frame ran
+    pub lateness: VecDeque<i64>,
+    pub rollbacks: u64,
+    pub frames_resimulated: u64,
+    /// Ticks spent waiting for the peer
+    pub stalls: u64,
+    pub deepest: u32,
+}
+
+impl RollbackStats {
+    /// Over the last `count` ticks
+    pub fn recent(&self, count: usize) -> RecentStats {
+        let ticks: Vec<Tick> = self.ticks.iter().rev().take(count).copied().collect();
+        let depths: Vec<u32> = ticks
+            .iter()
+            .map(|tick| tick.depth)
+            .filter(|depth| *depth > 0)
+            .collect();
+        let stalled = ticks.iter().filter(|tick| tick.stalled).count();
+        RecentStats {
+            rollbacks: depths.len(),
+            average_depth: if depths.is_empty() {
+                0.0
+            } else {
+                depths.iter().sum::<u32>() as f32 / depths.len() as f32
+            },
+            stalled: if ticks.is_empty() {
+                0.0
+            } else {
+                stalled as f32 / ticks.len() as f32
+            },
+        }
+    }
+
+    /// Average frames late, over the recent remote inputs
+    pub fn average_lateness(&self) -> Option<f32> {
+        if self.lateness.is_empty() {
+            return None;
+        }
+        Some(self.lateness.iter().sum::<i64>() as f32 / self.lateness.len() as f32)
+    }
+}
+
+pub struct RollbackSession {
+    /// Which fighter we are, 0 or 1
+    pub local: usize,
+    pub input_delay: u32,
+    pub max_rollback: u32,
+    state: GameState,
+    /// The state at the start of each frame that may still be rolled back
+    saved: BTreeMap<u32, GameState>,
+    local_inputs: BTreeMap<u32, Input>,
+    /// Local inputs are recorded up to here; a frame is never recorded twice
+    next_local: u32,
+    remote_inputs: BTreeMap<u32, Input>,
+    /// Every remote input before this frame has arrived
+    remote_confirmed: u32,
+    /// The newest one of those, which predictions hold
+    last_remote: Input,
+    /// What frames that ran without the remote input were run with
+    predicted: BTreeMap<u32, Input>,
+    /// The earliest frame that ran on a wrong guess
+    rollback_from: Option<u32>,
+    /// The peer has every local input before this frame
+    peer_confirmed: u32,
+    local_checks: BTreeMap<u32, u32>,
+    peer_checks: BTreeMap<u32, u32>,
+    /// Checksums to send, as (frame, checksum)
+    pub outgoing_checks: Vec<(u32, u32)>,
+    /// The newest frame both sides checked and agreed on
+    pub synced_through: Option<u32>,
+    /// The first frame the two sides disagreed on
+    pub desync_at: Option<u32>,
+    pub stats: RollbackStats,
+}
+
+impl RollbackSession {
+    pub fn new(local: usize, input_delay: u32, max_rollback: u32) -> Self {
+        Self {
+            local,
+            input_delay,
+            max_rollback,
+            state: GameState::default(),
+            saved: BTreeMap::new(),
+            // Nobody pressed anything before the first delayed input
+            local_inputs: (0..input_delay).map(|frame| (frame, Input(0))).collect(),
+            next_local: input_delay,
+            remote_inputs: BTreeMap::new(),
+            remote_confirmed: 0,
+            last_remote: Input(0),
+            predicted: BTreeMap::new(),
+            rollback_from: None,
+            peer_confirmed: 0,
+            local_checks: BTreeMap::new(),
+            peer_checks: BTreeMap::new(),
+            outgoing_checks: Vec::new(),
+            synced_through: None,
+            desync_at: None,
+            stats: RollbackStats::default(),
+        }
+    }
+
+    /// The present, including whatever was predicted to get here
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    /// Frames run past the last one with every remote input
+    pub fn frames_ahead(&self) -> u32 {
+        self.state.frame.saturating_sub(self.remote_confirmed)
+    }
+
+    /// Running the next frame would guess further than `max_rollback`
+    /// allows; with 0, every frame waits for the remote input, as in lockstep.
+    pub fn waiting(&self) -> bool {
+        self.state.frame >= self.remote_confirmed.saturating_add(self.max_rollback)
+    }
+
+    /// The first frame we still need a remote input for; sent as our ack
+    pub fn remote_confirmed(&self) -> u32 {
+        self.remote_confirmed
+    }
+
+    /// Records this tick's input for `input_delay` frames from now. After
+    /// the delay goes up, the gap repeats the last input; after it goes
+    /// down, inputs are dropped until the present catches up.
+    pub fn add_local_input(&mut self, input: Input) {
+        let target = self.state.frame + self.input_delay;
+        if target < self.next_local {
+            return;
+        }
+        let held = self
+            .local_inputs
+            .get(&(self.next_local.wrapping_sub(1)))
+            .copied()
+            .unwrap_or_default();
+        for frame in self.next_local..target {
+            self.local_inputs.insert(frame, held);
+        }
+        self.local_inputs.insert(target, input);
+        self.next_local = target + 1;
+    }
+
+    /// The local inputs the peer hasn't acked: (first frame, inputs).
+    pub fn unacked_inputs(&self) -> (u32, Vec<Input>) {
+        let inputs = self
+            .local_inputs
+            .range(self.peer_confirmed..)
+            .take(MAX_SENT_INPUTS)
+            .map(|(_, input)| *input)
+            .collect();
+        (self.peer_confirmed, inputs)
+    }
+
+    /// The peer has every input before `ack`.
+    pub fn peer_acked(&mut self, ack: u32) {
+        if ack <= self.peer_confirmed {
+            return;
+        }
+        self.peer_confirmed = ack.min(self.next_local);
+    }
+
+    /// The first frame too far ahead for the peer to send anything about
+    fn horizon(&self) -> u32 {
+        self.remote_confirmed.saturating_add(MAX_SENT_INPUTS as u32)
+    }
+
+    /// Remote inputs for `first` onwards, as they arrived; repeats are fine.
+    pub fn add_remote_inputs(&mut self, first: u32, inputs: &[Input]) {
+        let horizon = self.horizon();
+        for (offset, input) in inputs.iter().copied().enumerate() {
+            let Some(frame) = first.checked_add(offset as u32) else {
+                break;
+            };
+            if frame >= horizon {
+                break;
+            }
+            if frame < self.remote_confirmed || self.remote_inputs.contains_key(&frame) {
+                continue;
+            }
+            self.remote_inputs.insert(frame, input);
+            self.stats
+                .lateness
+                .push_back(self.state.frame as i64 - frame as i64);
+            if self.stats.lateness.len() > LATENESS_HISTORY {
+                self.stats.lateness.pop_front();
+            }
+            if self
+                .predicted
+                .remove(&frame)
+                .is_some_and(|guess| guess != input)
+            {
+                self.rollback_from = Some(self.rollback_from.map_or(frame, |from| from.min(frame)));
+            }
+        }
+        while let Some(input) = self.remote_inputs.get(&self.remote_confirmed) {
+            self.last_remote = *input;
+            self.remote_confirmed += 1;
+        }
+    }
+
+    /// A checksum the peer sent for one of its confirmed frames.
+    pub fn add_peer_check(&mut self, frame: u32, checksum: u32) {
+        let settled = self.remote_confirmed.min(self.state.frame);
+        // Ours for a settled frame is gone once compared, and a frame that
+        // isn't a multiple of CHECK_INTERVAL never gets one
+        let compared = frame < settled && !self.local_checks.contains_key(&frame);
+        if compared || !frame.is_multiple_of(CHECK_INTERVAL) || frame >= self.horizon() {
+            return;
+        }
+        self.peer_checks.insert(frame, checksum);
+        self.compare_checks();
+    }
+
+    /// Runs one tick: any rollback that's due, then the next frame, unless
+    /// that would run too far past the remote inputs.
+    pub fn advance(&mut self) {
+        let mut depth = 0;
+        if let Some(from) = self.rollback_from.take() {
+            let present = self.state.frame;
+            if let Some(saved) = self.saved.get(&from) {
+                self.state = *saved;
+                while self.state.frame < present {
+                    self.run_frame();
+                }
+                depth = present - from;
+                self.stats.rollbacks += 1;
+                self.stats.frames_resimulated += depth as u64;
+                self.stats.deepest = self.stats.deepest.max(depth);
+            }
+        }
+        let stalled = self.waiting();
+        if stalled {
+            self.stats.stalls += 1;
+        } else {
+            self.run_frame();
+        }
+        self.stats.ticks.push_back(Tick { depth, stalled });
+        while self.stats.ticks.len() > TICK_HISTORY {
+            self.stats.ticks.pop_front();
+        }
+        self.forget_confirmed();
+    }
+
+    fn run_frame(&mut self) {
+        let frame = self.state.frame;
+        self.saved.insert(frame, self.state);
+        let local = self.local_inputs.get(&frame).copied().unwrap_or_default();
+        let remote = match self.remote_inputs.get(&frame) {
+            Some(input) => *input,
+            None => {
+                self.predicted.insert(frame, self.last_remote);
+                self.last_remote
+            }
+        };
+        let mut inputs = [remote; 2];
+        inputs[self.local] = local;
+        self.state.step(inputs);
+    }
+
+    /// Drops what can no longer be rolled back to, checksumming on the way.
+    fn forget_confirmed(&mut self) {
+        let settled = self.remote_confirmed.min(self.state.frame);
+        let kept = self.saved.split_off(&settled);
+        for (frame, state) in std::mem::replace(&mut self.saved, kept) {
+            if frame % CHECK_INTERVAL == 0 {
+                let checksum = state.checksum();
+                self.local_checks.insert(frame, checksum);
+                self.outgoing_checks.push((frame, checksum));
+            }
+        }
+        // Our own inputs are needed to resimulate as well as to resend
+        self.local_inputs = self
+            .local_inputs
+            .split_off(&settled.min(self.peer_confirmed));
+        self.remote_inputs = self.remote_inputs.split_off(&settled);
+        self.predicted = self.predicted.split_off(&settled);
+        self.compare_checks();
+    }
+
+    fn compare_checks(&mut self) {
+        let compared: Vec<(u32, bool)> = self
+            .local_checks
+            .iter()
+            .filter_map(|(frame, checksum)| {
+                Some((*frame, self.peer_checks.get(frame)? == checksum))
+            })
+            .collect();
+        for (frame, matched) in compared {
+            self.local_checks.remove(&frame);
+            self.peer_checks.remove(&frame);
+            if !matched {
+                self.desync_at.get_or_insert(frame);
+            } else if self.desync_at.is_none() {
+                self.synced_through = Some(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Player 1, no input delay, up to 8 frames of prediction
+    fn session() -> RollbackSession {
+        RollbackSession::new(0, 0, 8)
+    }
+
+    #[test]
+    fn inputs_at_the_last_frames_dont_overflow() {
+        let mut session = session();
+        session.add_remote_inputs(u32::MAX, &[Input(1); 4]);
+        session.add_remote_inputs(u32::MAX - 1, &[Input(1); 4]);
+        assert!(session.remote_inputs.is_empty());
+        assert_eq!(session.remote_confirmed(), 0);
+    }
+
+    #[test]
+    fn far_future_inputs_are_ignored() {
+        let mut session = session();
+        session.add_remote_inputs(MAX_SENT_INPUTS as u32, &[Input(1)]);
+        session.add_remote_inputs(1_000_000, &[Input(1); MAX_SENT_INPUTS]);
+        assert!(session.remote_inputs.is_empty());
+        // Only the part of a packet inside the window is kept
+        session.add_remote_inputs(1, &[Input(1); MAX_SENT_INPUTS]);
+        assert_eq!(session.remote_inputs.len(), MAX_SENT_INPUTS - 1);
+    }
+
+    #[test]
+    fn a_wrong_guess_rolls_back() {
+        let mut session = session();
+        session.add_remote_inputs(0, &[Input(0)]);
+        for _ in 0..4 {
+            session.advance();
+        }
+        // Frames 1 to 3 ran on a guess of no input
+        session.add_remote_inputs(1, &[Input(0), Input(1), Input(1)]);
+        session.advance();
+        assert_eq!(session.stats.rollbacks, 1);
+        assert_eq!(session.stats.deepest, 2);
+        assert_eq!(session.remote_confirmed(), 4);
+    }
+
+    #[test]
+    fn compared_checks_are_forgotten() {
+        let mut session = session();
+        session.add_remote_inputs(0, &[Input(0); 4]);
+        for _ in 0..4 {
+            session.advance();
+        }
+        let (frame, checksum) = session.outgoing_checks[0];
+        assert_eq!(frame, 0);
+        session.add_peer_check(frame, checksum);
+        assert_eq!(session.synced_through, Some(0));
+        assert!(session.peer_checks.is_empty());
+        // A repeat, or a check for a frame that never gets one, is dropped
+        session.add_peer_check(frame, checksum);
+        session.add_peer_check(1, 0);
+        session.add_peer_check(CHECK_INTERVAL * 1000, 0);
+        assert!(session.peer_checks.is_empty());
+        assert_eq!(session.desync_at, None);
+    }
+
+    #[test]
+    fn a_different_checksum_is_a_desync() {
+        let mut session = session();
+        session.add_remote_inputs(0, &[Input(0); 4]);
+        for _ in 0..4 {
+            session.advance();
+        }
+        let (frame, checksum) = session.outgoing_checks[0];
+        session.add_peer_check(frame, checksum ^ 1);
+        assert_eq!(session.desync_at, Some(0));
+        assert_eq!(session.synced_through, None);
+    }
+}
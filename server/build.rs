@@ -0,0 +1,24 @@
+fn main() {
+    // Only the gRPC API has generated code
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/admin.proto");
+        println!("cargo:rerun-if-env-changed=PROTOC");
+        let mut config = prost_build::Config::new();
+        if std::env::var_os("PROTOC").is_none() {
+            match protoc_bin_vendored::protoc_bin_path() {
+                Ok(protoc) => {
+                    config.protoc_executable(protoc);
+                }
+                Err(e) => panic!("no $PROTOC and no bundled protoc for this platform: {}", e),
+            }
+        }
+        if let Err(e) = tonic_build::configure().compile_protos_with_config(
+            config,
+            &["proto/admin.proto"],
+            &["proto"],
+        ) {
+            panic!("could not compile proto/admin.proto: {}", e);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! IP allowlist/denylist.
+//!
+//! Checked on the receive thread before a packet is even queued, so traffic
+//! from a blocked range costs no decoding work. A deny match always wins; if
+//! any allow ranges are configured, everything outside them is rejected.
+//! The console can change both lists while the server runs. Ranges it
+//! denies are bans: they go in the database's `bans` table
+//! (`common::storage::Bans`) and are denied again after a restart.
+//! `--deny-cidr` ranges are configuration and aren't stored.
+
+use bevy::prelude::*;
+use common::storage::{self, Bans};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+/// An address range like `10.0.0.0/8` or `::1/128`. A bare address means a
+/// single host. Host bits past the prefix are dropped, so `10.1.2.3/8` is
+/// `10.0.0.0/8`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// An IPv4 range also holds the IPv4-mapped IPv6 form of its addresses
+    /// (`::ffff:10.0.0.1`), which is how a dual-stack socket reports IPv4
+    /// peers, and the other way around.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = v4_mask(self.prefix);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = v6_mask(self.prefix);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(ip)) => self.contains(IpAddr::V6(ip.to_ipv6_mapped())),
+        }
+    }
+}
+
+fn v4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Parses `ADDR/PREFIX` or a bare `ADDR`, for `--allow-cidr`/`--deny-cidr`.
+pub fn parse_cidr(value: &str) -> Result<Cidr, String> {
+    let (addr, prefix) = match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (value, None),
+    };
+    let network: IpAddr = addr
+        .trim()
+        .parse()
+        .map_err(|e| format!("'{}': {}", addr, e))?;
+    let max = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .map_err(|e| format!("'{}': {}", prefix, e))?,
+        None => max,
+    };
+    if prefix > max {
+        return Err(format!("prefix /{} is longer than {} bits", prefix, max));
+    }
+    // So the same range compares and displays the same however it's written
+    let network = match network {
+        IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) & v4_mask(prefix)).into()),
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & v6_mask(prefix)).into()),
+    };
+    Ok(Cidr { network, prefix })
+}
+
+#[derive(Default)]
+pub struct AccessList {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    /// The rejection reason for `ip`, if it isn't let in.
+    pub fn check(&self, ip: IpAddr) -> Result<(), &'static str> {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return Err("denied");
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|cidr| cidr.contains(ip)) {
+            return Err("not allowed");
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        let list = |cidrs: &[Cidr]| {
+            if cidrs.is_empty() {
+                "(none)".to_string()
+            } else {
+                cidrs
+                    .iter()
+                    .map(Cidr::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+        vec![
+            format!("allow: {}", list(&self.allow)),
+            format!("deny:  {}", list(&self.deny)),
+        ]
+    }
+}
+
+/// Shared between the receive threads and the console.
+#[derive(Resource, Clone)]
+pub struct AccessControl {
+    list: Arc<RwLock<AccessList>>,
+    bans: Arc<Bans>,
+}
+
+impl AccessControl {
+    /// Denies `deny` and every range banned in an earlier run.
+    pub fn new(allow: Vec<Cidr>, mut deny: Vec<Cidr>, bans: Bans) -> storage::Result<Self> {
+        for range in bans.all()? {
+            match parse_cidr(&range) {
+                Ok(cidr) if !deny.contains(&cidr) => deny.push(cidr),
+                Ok(_) => {}
+                Err(e) => warn!("Ignoring stored ban {}: {}", range, e),
+            }
+        }
+        Ok(Self {
+            list: Arc::new(RwLock::new(AccessList { allow, deny })),
+            bans: Arc::new(bans),
+        })
+    }
+
+    pub fn check(&self, ip: IpAddr) -> Result<(), &'static str> {
+        self.list.read().unwrap().check(ip)
+    }
+
+    pub fn allow(&self, cidr: Cidr) {
+        let mut list = self.list.write().unwrap();
+        if !list.allow.contains(&cidr) {
+            list.allow.push(cidr);
+        }
+    }
+
+    /// Bans `cidr`. It is denied at once even if storing the ban fails.
+    pub fn deny(&self, cidr: Cidr) -> storage::Result<()> {
+        // The receive threads wait while the list is locked, so not for the disk
+        {
+            let mut list = self.list.write().unwrap();
+            if !list.deny.contains(&cidr) {
+                list.deny.push(cidr);
+            }
+        }
+        self.bans.add(&cidr.to_string())
+    }
+
+    /// Empties both lists and lifts every stored ban.
+    pub fn clear(&self) -> storage::Result<()> {
+        {
+            let mut list = self.list.write().unwrap();
+            list.allow.clear();
+            list.deny.clear();
+        }
+        self.bans.clear()
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        self.list.read().unwrap().describe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(value: &str) -> Cidr {
+        parse_cidr(value).unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn zero_prefix_holds_every_address() {
+        let all_v4 = cidr("0.0.0.0/0");
+        assert!(all_v4.contains(ip("1.2.3.4")));
+        assert!(all_v4.contains(ip("255.255.255.255")));
+        let all_v6 = cidr("::/0");
+        assert!(all_v6.contains(ip("::1")));
+        assert!(all_v6.contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn full_prefix_holds_one_host() {
+        let host = cidr("10.0.0.1/32");
+        assert!(host.contains(ip("10.0.0.1")));
+        assert!(!host.contains(ip("10.0.0.2")));
+        assert_eq!(cidr("10.0.0.1"), host);
+
+        let host = cidr("::1/128");
+        assert!(host.contains(ip("::1")));
+        assert!(!host.contains(ip("::2")));
+        assert_eq!(cidr("::1"), host);
+    }
+
+    #[test]
+    fn host_bits_are_dropped() {
+        assert_eq!(cidr("10.1.2.3/8"), cidr("10.0.0.0/8"));
+        assert_eq!(cidr("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(cidr("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert!(cidr("10.1.2.3/8").contains(ip("10.200.0.1")));
+    }
+
+    #[test]
+    fn ipv4_ranges_hold_mapped_ipv6_addresses() {
+        let v4 = cidr("10.0.0.0/8");
+        assert!(v4.contains(ip("::ffff:10.1.2.3")));
+        assert!(!v4.contains(ip("::ffff:11.1.2.3")));
+        assert!(!v4.contains(ip("::1")));
+
+        let mapped = cidr("::ffff:10.0.0.0/104");
+        assert!(mapped.contains(ip("10.1.2.3")));
+        assert!(!mapped.contains(ip("11.1.2.3")));
+        assert!(!cidr("2001:db8::/32").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn rejects_invalid_prefixes() {
+        for value in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0.0/-1",
+            "10.0.0.0/eight",
+            "10.0.0.0/8/8",
+            "10.0.0/8",
+            "/8",
+        ] {
+            assert!(parse_cidr(value).is_err(), "{} parsed", value);
+        }
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let list = AccessList {
+            allow: vec![cidr("10.0.0.0/8")],
+            deny: vec![cidr("10.0.0.5")],
+        };
+        assert_eq!(list.check(ip("10.0.0.5")), Err("denied"));
+        assert_eq!(list.check(ip("10.0.0.6")), Ok(()));
+        assert_eq!(list.check(ip("192.168.0.1")), Err("not allowed"));
+
+        let deny_only = AccessList {
+            allow: Vec::new(),
+            deny: vec![cidr("10.0.0.0/8")],
+        };
+        assert_eq!(deny_only.check(ip("::ffff:10.0.0.1")), Err("denied"));
+        assert_eq!(deny_only.check(ip("192.168.0.1")), Ok(()));
+    }
+}
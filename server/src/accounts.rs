@@ -0,0 +1,434 @@
+//! Logins and per-account stats.
+//!
+//! After its Welcome a client may log in over the reliable channel
+//! (`common::reliable`), which every peer entity carries as a
+//! [`PeerReliable`]:
+//!
+//! ```text
+//! REL <seq> Login user=<name> password=<len>:<password>    client -> server
+//! REL <seq> LoginOk user=<name> logins=3 pings=120 chats=4[ new]
+//! REL <seq> LoginFailed reason=bad_password|bad_username|malformed|no_session|busy|rate_limited|storage
+//! ```
+//!
+//! The password's length in bytes goes in front of it, so it can hold
+//! spaces; see `common::protocol::login_payload`.
+//!
+//! **The password crosses the network in plain text.** Nothing on the UDP
+//! transport is encrypted (`identity` signs replies, it doesn't hide them),
+//! so anyone on the path can read a Login. Only use throwaway passwords,
+//! and only on a network you trust.
+//!
+//! The password is checked against its argon2id hash on the
+//! `AsyncComputeTaskPool`, which takes tens of milliseconds on purpose, so
+//! a [`PendingLogin`] sits on the peer until [`finish_logins`] sees it
+//! done. A peer gets one login at a time (`busy` otherwise), and each client
+//! address `--login-attempts` per minute (`rate_limited` beyond that, without
+//! hashing anything), so a flood of guesses costs neither frames nor CPU.
+//!
+//! The first login to a free username creates the account. A successful
+//! login puts an [`Account`] on the peer entity, so anything that wants to
+//! know who a connection is just queries for it. While it is there, the
+//! Pings and Chat lines of the peer's session are added to the account's
+//! totals in `common::storage::Accounts`. The component goes away when the
+//! session ends, and the client logs in again after its next Welcome.
+//!
+//! The same channel carries crash reports; see `crash_reports`.
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task, block_on, poll_once};
+use common::handlers::{NetMessage, ReliableAck, ReliableData};
+use common::protocol::parse_login;
+use common::reliable::{ReliableReceiver, ReliableSender};
+use common::replicate::ReplicationJoin;
+use common::storage::{self, AccountStats, Accounts, CrashReports, Login};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use crate::client_list::ConnectionState;
+use crate::crash_reports::file_report;
+use crate::peers::{PeerAddr, PeerIndex, PeerSession, PeerStats};
+#[cfg(feature = "gui")]
+use crate::timeline::PacketTimeline;
+use crate::{NetworkState, ServerState};
+
+/// Most unacked replies per peer
+const WINDOW: usize = 4;
+/// Resend a reply if its ack hasn't come back within this many seconds
+const RESEND_SECS: f32 = 0.5;
+const MAX_USERNAME_LEN: usize = 32;
+/// `--login-attempts` counts over this many seconds
+const LOGIN_WINDOW_SECS: f32 = 60.0;
+
+/// A `REL` or `REL_ACK` line, as received; see `handle_network_messages`.
+#[derive(Event)]
+pub struct ReliableReceived {
+    pub from: String,
+    pub text: String,
+}
+
+#[derive(Component)]
+pub struct PeerReliable {
+    sender: ReliableSender,
+    receiver: ReliableReceiver,
+}
+
+impl PeerReliable {
+    /// Queues a payload for this peer; see `client_config` and `announcements`
+    pub fn push(&mut self, payload: String) {
+        self.sender.push(payload);
+    }
+
+    /// Roughly what both windows hold on the heap
+    pub fn heap_bytes(&self) -> usize {
+        self.sender.heap_bytes() + self.receiver.heap_bytes()
+    }
+}
+
+impl Default for PeerReliable {
+    fn default() -> Self {
+        Self {
+            sender: ReliableSender::new(WINDOW, RESEND_SECS),
+            receiver: ReliableReceiver::default(),
+        }
+    }
+}
+
+/// Who a peer is logged in as.
+#[derive(Component)]
+pub struct Account {
+    pub user: String,
+    pub stats: AccountStats,
+    /// `started_at` of the session this login belongs to
+    session_started: f32,
+    /// Of the session's messages, those already added to the totals
+    counted_pings: u64,
+    counted_chats: u64,
+}
+
+/// A login whose password is being checked off the main thread.
+#[derive(Component)]
+pub struct PendingLogin {
+    user: String,
+    /// `started_at` of the session it was sent in
+    session_started: f32,
+    task: Task<storage::Result<Login>>,
+}
+
+/// Recent login attempts by client address, for `--login-attempts`.
+#[derive(Resource)]
+pub struct LoginLimiter {
+    per_minute: usize,
+    /// Keyed by IP, so a new source port doesn't start the count over
+    attempts: HashMap<String, VecDeque<f32>>,
+}
+
+impl LoginLimiter {
+    pub fn new(per_minute: usize) -> Self {
+        Self {
+            per_minute: per_minute.max(1),
+            attempts: HashMap::new(),
+        }
+    }
+
+    /// Counts an attempt from `addr`, unless it is one too many.
+    fn allow(&mut self, addr: &str, now: f32) -> bool {
+        let ip = addr
+            .parse::<SocketAddr>()
+            .map_or_else(|_| addr.to_string(), |addr| addr.ip().to_string());
+        if !self.attempts.contains_key(&ip) {
+            // Addresses only come back into the map by trying again
+            self.attempts.retain(|_, recent| {
+                recent
+                    .back()
+                    .is_some_and(|at| now - at <= LOGIN_WINDOW_SECS)
+            });
+        }
+        let recent = self.attempts.entry(ip).or_default();
+        while recent
+            .front()
+            .is_some_and(|at| now - at > LOGIN_WINDOW_SECS)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= self.per_minute {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+}
+
+/// Both ends start a fresh reliable channel with every Welcome, so a client
+/// that restarted from the same address isn't mistaken for resends.
+pub fn reset_reliable_on_join(
+    index: Res<PeerIndex>,
+    mut joins: EventReader<ReplicationJoin>,
+    mut channels: Query<&mut PeerReliable>,
+) {
+    for join in joins.read() {
+        let Some(mut channel) = index
+            .get(&join.peer)
+            .and_then(|peer| channels.get_mut(peer).ok())
+        else {
+            continue;
+        };
+        *channel = PeerReliable::default();
+    }
+}
+
+fn valid_username(user: &str) -> bool {
+    !user.is_empty()
+        && user.len() <= MAX_USERNAME_LEN
+        && user
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub fn handle_reliable(
+    mut commands: Commands,
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    index: Res<PeerIndex>,
+    accounts: Res<Accounts>,
+    mut limiter: ResMut<LoginLimiter>,
+    mut crash_reports: Option<ResMut<CrashReports>>,
+    mut received: EventReader<ReliableReceived>,
+    mut peers: Query<(&mut PeerReliable, &PeerSession, Has<PendingLogin>)>,
+    #[cfg(feature = "gui")] mut timeline: ResMut<PacketTimeline>,
+    server_state: Res<ServerState>,
+) {
+    let now = time.elapsed_seconds();
+    for message in received.read() {
+        let Some(peer) = index.get(&message.from) else {
+            continue;
+        };
+        let Ok((mut channel, session, mut pending)) = peers.get_mut(peer) else {
+            continue;
+        };
+        if let Some(ack) = ReliableAck::parse(&message.text) {
+            channel.sender.ack(ack.seq);
+            #[cfg(feature = "gui")]
+            timeline.ack(&message.from, ack.seq);
+            continue;
+        }
+        let Some(data) = ReliableData::parse(&message.text) else {
+            warn!(
+                "Malformed reliable message from {}: {}",
+                message.from, message.text
+            );
+            continue;
+        };
+        let (ack, delivered) = channel.receiver.receive(data.seq, &data.payload);
+        if let Some(ack) = ack {
+            network.send_control(ack.as_bytes(), &message.from);
+        }
+        for payload in delivered {
+            if let Some(report) = payload.strip_prefix("CrashReport ") {
+                let (reply, line) =
+                    file_report(crash_reports.as_deref_mut(), &message.from, report);
+                channel.sender.push(reply);
+                server_state.log.push(line);
+                continue;
+            }
+            if !payload.starts_with("Login ") {
+                warn!(
+                    "Unknown reliable payload from {}: {}",
+                    message.from, payload
+                );
+                continue;
+            }
+            let Some((user, password)) = parse_login(&payload) else {
+                channel.sender.push("LoginFailed reason=malformed");
+                server_state.log.push(format!(
+                    "[Account]: {} sent a malformed login",
+                    message.from
+                ));
+                continue;
+            };
+            let Some(open) = &session.open else {
+                // Logins only count within a session
+                channel.sender.push("LoginFailed reason=no_session");
+                continue;
+            };
+            if !valid_username(user) {
+                channel.sender.push("LoginFailed reason=bad_username");
+                server_state.log.push(format!(
+                    "[Account]: {} sent an invalid username",
+                    message.from
+                ));
+                continue;
+            }
+            if pending {
+                channel.sender.push("LoginFailed reason=busy");
+                continue;
+            }
+            if !limiter.allow(&message.from, now) {
+                channel.sender.push("LoginFailed reason=rate_limited");
+                server_state.log.push(format!(
+                    "[Account]: {} is trying too many logins",
+                    message.from
+                ));
+                continue;
+            }
+            let accounts = accounts.clone();
+            let (user, password) = (user.to_string(), password.to_string());
+            let task = AsyncComputeTaskPool::get().spawn({
+                let user = user.clone();
+                async move { accounts.login(&user, &password) }
+            });
+            commands.entity(peer).insert(PendingLogin {
+                user,
+                session_started: open.started_at,
+                task,
+            });
+            pending = true;
+        }
+    }
+}
+
+/// Answers each login whose password check has finished.
+pub fn finish_logins(
+    mut commands: Commands,
+    mut peers: Query<(
+        Entity,
+        &PeerAddr,
+        &PeerSession,
+        &mut PeerReliable,
+        &mut PendingLogin,
+    )>,
+    server_state: Res<ServerState>,
+) {
+    for (peer, PeerAddr(addr), session, mut channel, mut pending) in peers.iter_mut() {
+        let Some(login) = block_on(poll_once(&mut pending.task)) else {
+            continue;
+        };
+        commands.entity(peer).remove::<PendingLogin>();
+        let user = pending.user.as_str();
+        let Some(open) = session
+            .open
+            .as_ref()
+            .filter(|open| open.started_at == pending.session_started)
+        else {
+            // The session ended while the hash was checked
+            continue;
+        };
+        // Passwords stay out of the log
+        let (reply, line) = match login {
+            Ok(Login::LoggedIn { stats, created }) => {
+                commands.entity(peer).insert(Account {
+                    user: user.to_string(),
+                    stats,
+                    session_started: open.started_at,
+                    counted_pings: open.received("Ping"),
+                    counted_chats: open.received("Chat"),
+                });
+                (
+                    format!(
+                        "LoginOk user={} logins={} pings={} chats={}{}",
+                        user,
+                        stats.logins,
+                        stats.pings,
+                        stats.chat_lines,
+                        if created { " new" } else { "" }
+                    ),
+                    format!(
+                        "[Account]: {} logged in as {}{}",
+                        addr,
+                        user,
+                        if created { " (new account)" } else { "" }
+                    ),
+                )
+            }
+            Ok(Login::BadPassword) => (
+                "LoginFailed reason=bad_password".to_string(),
+                format!("[Account]: {} failed to log in as {}", addr, user),
+            ),
+            Err(e) => (
+                "LoginFailed reason=storage".to_string(),
+                format!("[Error]: login for {} failed: {}", user, e),
+            ),
+        };
+        channel.sender.push(reply);
+        server_state.log.push(line);
+    }
+}
+
+pub fn flush_reliable(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut peers: Query<(&PeerAddr, &PeerStats, &mut PeerReliable)>,
+    #[cfg(feature = "gui")] mut timeline: ResMut<PacketTimeline>,
+) {
+    let now = time.elapsed_seconds();
+    for (PeerAddr(addr), stats, mut channel) in peers.iter_mut() {
+        // Resends to a peer that went away would never be acked
+        if stats.state == ConnectionState::Disconnected {
+            continue;
+        }
+        for line in channel.sender.poll(now) {
+            network.send_control(line.as_bytes(), addr);
+            #[cfg(feature = "gui")]
+            if let Some(data) = ReliableData::parse(&line) {
+                timeline.record_reliable(addr, data.seq, now);
+            }
+        }
+    }
+}
+
+/// Adds each logged-in session's new Pings and Chat lines to its account,
+/// and logs the account out when the session ends.
+pub fn track_account_activity(
+    mut commands: Commands,
+    accounts: Res<Accounts>,
+    mut peers: Query<(Entity, &PeerAddr, &PeerSession, &mut Account), Changed<PeerSession>>,
+    server_state: Res<ServerState>,
+) {
+    for (entity, PeerAddr(addr), session, mut account) in peers.iter_mut() {
+        let open = session
+            .open
+            .as_ref()
+            .filter(|open| open.started_at == account.session_started);
+        let Some(open) = open else {
+            commands.entity(entity).remove::<Account>();
+            server_state
+                .log
+                .push(format!("[Account]: {} ({}) logged out", account.user, addr));
+            continue;
+        };
+        let pings = open.received("Ping") - account.counted_pings;
+        let chats = open.received("Chat") - account.counted_chats;
+        if pings == 0 && chats == 0 {
+            continue;
+        }
+        match accounts.add_activity(&account.user, pings, chats) {
+            Ok(Some(stats)) => account.stats = stats,
+            Ok(None) => {}
+            Err(e) => warn!("Could not update account {}: {}", account.user, e),
+        }
+        account.counted_pings += pings;
+        account.counted_chats += chats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_login_attempts_per_address() {
+        let mut limiter = LoginLimiter::new(2);
+        assert!(limiter.allow("10.0.0.1:5000", 0.0));
+        // Another port on the same host shares the count
+        assert!(limiter.allow("10.0.0.1:5001", 1.0));
+        assert!(!limiter.allow("10.0.0.1:5000", 2.0));
+        assert!(limiter.allow("10.0.0.2:5000", 2.0));
+
+        // The first attempt has left the window
+        assert!(limiter.allow("10.0.0.1:5000", LOGIN_WINDOW_SECS + 0.5));
+        assert!(!limiter.allow("10.0.0.1:5000", LOGIN_WINDOW_SECS + 0.5));
+
+        // Addresses that went quiet are forgotten
+        assert!(limiter.allow("10.0.0.3:5000", LOGIN_WINDOW_SECS * 3.0));
+        assert_eq!(limiter.attempts.len(), 1);
+    }
+}
@@ -0,0 +1,259 @@
+//! The admin plane shared by the dashboard and the gRPC API: a snapshot of
+//! the server for them to read, and commands for them to send.
+//!
+//! Neither touches the ECS. Once a second [`publish_admin_snapshot`] copies
+//! what they show into an [`AdminSnapshot`] behind a lock, and what they ask
+//! for goes down a channel as an [`AdminCommand`], which
+//! [`apply_admin_commands`] carries out on the next frame, waking an idle
+//! server for it. A command can carry a reply channel for its outcome; the
+//! outcome also goes to the server log either way.
+
+use bevy::prelude::*;
+use common::players::PlayerCount;
+use crossbeam::channel::{Receiver, Sender, unbounded};
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use tokio::sync::oneshot;
+
+use crate::client_list::ConnectionState;
+use crate::console::kick;
+use crate::idle::Wakeup;
+use crate::metrics::{Metric, MetricsSample};
+use crate::peers::{Peer, PeerAddr, PeerSession, PeerStats, PeersByAddr};
+use crate::{NetworkState, ServerState};
+
+/// Seconds between snapshots
+const PUBLISH_SECS: f32 = 1.0;
+/// Rate samples kept, one per snapshot
+pub const RATE_SAMPLES: usize = 120;
+/// Longest broadcast accepted, in characters
+pub const MAX_BROADCAST: usize = 200;
+
+/// What became of a command: the log line either way.
+pub type Outcome = Result<String, String>;
+
+pub enum AdminCommand {
+    Kick(String),
+    /// One line of text, sent as `Chat server: <text>`
+    Broadcast(String),
+}
+
+#[derive(Clone)]
+pub struct ClientInfo {
+    pub addr: String,
+    pub state: ConnectionState,
+    /// Seconds, as the client last reported it
+    pub rtt: Option<f32>,
+    pub loss: Option<f32>,
+    pub silent_secs: f32,
+}
+
+/// Packet rates over one snapshot interval.
+#[derive(Clone)]
+pub struct RateSample {
+    /// Seconds since startup
+    pub at: f32,
+    pub packets_in: f64,
+    pub packets_out: f64,
+    pub players: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct AdminSnapshot {
+    pub port: u16,
+    pub listening: bool,
+    pub uptime_secs: f32,
+    pub players: usize,
+    pub spectators: usize,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// Not disconnected, by address
+    pub clients: Vec<ClientInfo>,
+    /// The dashboard shows it; gRPC has no call for it
+    #[cfg(feature = "dashboard")]
+    pub log: Vec<String>,
+    /// Oldest first
+    pub samples: Vec<RateSample>,
+}
+
+/// What the admin servers hold; cheap to clone.
+#[derive(Clone)]
+pub struct AdminLink {
+    snapshot: Arc<RwLock<AdminSnapshot>>,
+    commands: Sender<(AdminCommand, Option<oneshot::Sender<Outcome>>)>,
+    wakeup: Wakeup,
+}
+
+impl AdminLink {
+    pub fn snapshot(&self) -> AdminSnapshot {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// Queues `command` for the next frame. False once the app has exited.
+    #[cfg(feature = "dashboard")]
+    pub fn send(&self, command: AdminCommand) -> bool {
+        self.queue(command, None)
+    }
+
+    /// Queues `command` and waits for its outcome. `None` if the app exited
+    /// first.
+    #[cfg(feature = "grpc")]
+    pub async fn call(&self, command: AdminCommand) -> Option<Outcome> {
+        let (reply, outcome) = oneshot::channel();
+        if !self.queue(command, Some(reply)) {
+            return None;
+        }
+        outcome.await.ok()
+    }
+
+    fn queue(&self, command: AdminCommand, reply: Option<oneshot::Sender<Outcome>>) -> bool {
+        if self.commands.send((command, reply)).is_err() {
+            return false;
+        }
+        self.wakeup.notify();
+        true
+    }
+}
+
+/// A broadcast as one line, or why it can't be sent.
+pub fn broadcast_text(text: &str) -> Result<String, &'static str> {
+    // Messages are split on whitespace and framed by line
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return Err("text is required");
+    }
+    if text.chars().count() > MAX_BROADCAST {
+        return Err("text is too long");
+    }
+    Ok(text)
+}
+
+#[derive(Resource)]
+pub struct AdminPlane {
+    link: AdminLink,
+    commands: Receiver<(AdminCommand, Option<oneshot::Sender<Outcome>>)>,
+    /// (time, packets in, packets out, players) at the last snapshots
+    counts: VecDeque<(f32, u64, u64, usize)>,
+    next_publish: f32,
+}
+
+impl AdminPlane {
+    pub fn new(wakeup: Wakeup) -> Self {
+        let (commands, receiver) = unbounded();
+        Self {
+            link: AdminLink {
+                snapshot: default(),
+                commands,
+                wakeup,
+            },
+            commands: receiver,
+            counts: VecDeque::new(),
+            next_publish: 0.0,
+        }
+    }
+
+    pub fn link(&self) -> AdminLink {
+        self.link.clone()
+    }
+}
+
+pub fn publish_admin_snapshot(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    metrics: Res<MetricsSample>,
+    players: Res<PlayerCount>,
+    #[cfg(feature = "dashboard")] server_state: Res<ServerState>,
+    peers: Query<(&PeerAddr, &PeerStats), With<Peer>>,
+    mut admin: ResMut<AdminPlane>,
+) {
+    let now = time.elapsed_seconds();
+    if now < admin.next_publish {
+        return;
+    }
+    admin.next_publish = now + PUBLISH_SECS;
+
+    let packets_received = metrics.total(Metric::PacketsReceived);
+    let packets_sent = metrics.total(Metric::PacketsSent);
+    admin
+        .counts
+        .push_back((now, packets_received, packets_sent, players.connected));
+    while admin.counts.len() > RATE_SAMPLES + 1 {
+        admin.counts.pop_front();
+    }
+    let samples = admin
+        .counts
+        .iter()
+        .zip(admin.counts.iter().skip(1))
+        .map(|(before, after)| {
+            let secs = (after.0 - before.0).max(f32::EPSILON) as f64;
+            RateSample {
+                at: after.0,
+                packets_in: (after.1 - before.1) as f64 / secs,
+                packets_out: (after.2 - before.2) as f64 / secs,
+                players: after.3,
+            }
+        })
+        .collect();
+    let mut clients: Vec<ClientInfo> = peers
+        .iter()
+        .filter(|(_, stats)| stats.state != ConnectionState::Disconnected)
+        .map(|(PeerAddr(addr), stats)| ClientInfo {
+            addr: addr.clone(),
+            state: stats.state,
+            rtt: stats.rtt,
+            loss: stats.loss,
+            silent_secs: now - stats.last_heard,
+        })
+        .collect();
+    clients.sort_by(|a, b| a.addr.cmp(&b.addr));
+
+    *admin.link.snapshot.write().unwrap() = AdminSnapshot {
+        port: network.port,
+        listening: network.listening(),
+        uptime_secs: now,
+        players: players.connected,
+        spectators: players.spectators,
+        packets_sent,
+        packets_received,
+        clients,
+        #[cfg(feature = "dashboard")]
+        log: server_state.panel.lines(),
+        samples,
+    };
+}
+
+type PeerSessions<'w, 's> =
+    Query<'w, 's, (&'static PeerAddr, &'static PeerStats, &'static PeerSession), With<Peer>>;
+
+pub fn apply_admin_commands(
+    admin: Res<AdminPlane>,
+    network: Res<NetworkState>,
+    // Kicking needs the stats mutably, broadcasting iterates them
+    mut peers: ParamSet<(PeersByAddr, PeerSessions)>,
+    server_state: Res<ServerState>,
+) {
+    for (command, reply) in admin.commands.try_iter() {
+        let outcome = match command {
+            AdminCommand::Kick(addr) => kick(&addr, &network, &mut peers.p0()),
+            AdminCommand::Broadcast(_) if !cfg!(feature = "chat") => {
+                Err("this server was built without chat".to_string())
+            }
+            AdminCommand::Broadcast(text) => {
+                let message = format!("Chat server: {}", text);
+                let mut sent = 0;
+                for (PeerAddr(addr), stats, session) in peers.p1().iter() {
+                    if stats.state != ConnectionState::Disconnected && session.supports("chat") {
+                        network.send_gameplay(message.as_bytes(), addr);
+                        sent += 1;
+                    }
+                }
+                Ok(format!("[Chat]: server to {} clients: {}", sent, text))
+            }
+        };
+        let (Ok(line) | Err(line)) = &outcome;
+        server_state.log.push(format!("[Admin]: {}", line));
+        if let Some(reply) = reply {
+            let _ = reply.send(outcome);
+        }
+    }
+}
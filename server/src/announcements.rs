@@ -0,0 +1,164 @@
+//! The message of the day and scheduled announcements.
+//!
+//! Both come from `--announcements <path>`, a text file read at startup:
+//!
+//! ```text
+//! # Sent to every client right after its Welcome, a line at a time
+//! motd Welcome to the test server!
+//! motd Be nice in chat.
+//! # every <seconds> <text>: sent to every connected client on a schedule
+//! every 600 The server restarts daily at 04:00 UTC
+//! ```
+//!
+//! Each line goes out as an `Announce` (see `common::handlers`) over the
+//! client's reliable channel (see `accounts`), so unlike the replies to what
+//! a client sends, it's the server that starts the exchange and nothing is
+//! lost. A schedule's first announcement goes out one period after the
+//! server starts. `/announce <text>` in the console sends one at once. A
+//! client whose protocol version predates `announcements` is never sent
+//! any.
+
+use bevy::prelude::*;
+use common::handlers::{Announcement, AnnouncementKind};
+use common::replicate::ReplicationJoin;
+use std::path::Path;
+
+use crate::accounts::PeerReliable;
+use crate::client_list::ConnectionState;
+use crate::peers::{PeerIndex, PeerSession, PeerStats};
+
+/// Shortest period `every` takes, so a typo can't flood every client
+const MIN_PERIOD_SECS: f32 = 10.0;
+
+struct Scheduled {
+    period: f32,
+    text: String,
+    next_at: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct Announcements {
+    motd: Vec<String>,
+    schedule: Vec<Scheduled>,
+    /// Set by `/announce`, sent by [`send_announcements`]
+    pending: Vec<String>,
+}
+
+impl Announcements {
+    /// Reads an announcements file; the error names the line that's wrong.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut announcements = Announcements::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let wrong = |reason: &str| format!("line {}: {}", number + 1, reason);
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "motd" if !rest.trim().is_empty() => {
+                    announcements.motd.push(rest.trim().to_string())
+                }
+                "every" => {
+                    let (period, text) = rest
+                        .trim()
+                        .split_once(' ')
+                        .ok_or_else(|| wrong("expected every <seconds> <text>"))?;
+                    let period: f32 = period
+                        .parse()
+                        .ok()
+                        .filter(|period| *period >= MIN_PERIOD_SECS)
+                        .ok_or_else(|| {
+                            wrong(&format!("the period must be {}s or more", MIN_PERIOD_SECS))
+                        })?;
+                    announcements.schedule.push(Scheduled {
+                        period,
+                        text: text.trim().to_string(),
+                        next_at: period,
+                    });
+                }
+                _ => return Err(wrong("expected motd <text> or every <seconds> <text>")),
+            }
+        }
+        Ok(announcements)
+    }
+
+    /// Queues `text` for every connected client.
+    pub fn announce(&mut self, text: &str) {
+        self.pending.push(text.to_string());
+    }
+
+    /// What clients are told, for the startup log
+    pub fn describe(&self) -> String {
+        format!(
+            "{} MOTD lines, {} scheduled announcements",
+            self.motd.len(),
+            self.schedule.len()
+        )
+    }
+}
+
+/// Sends what `/announce` queued and every scheduled announcement that is
+/// due.
+pub fn send_announcements(
+    time: Res<Time>,
+    mut announcements: ResMut<Announcements>,
+    mut peers: Query<(&PeerStats, &PeerSession, &mut PeerReliable)>,
+) {
+    let now = time.elapsed_seconds();
+    let mut due: Vec<String> = announcements.pending.drain(..).collect();
+    for scheduled in announcements.schedule.iter_mut() {
+        if now >= scheduled.next_at {
+            due.push(scheduled.text.clone());
+            // A long stall sends it once, not once per missed period
+            while scheduled.next_at <= now {
+                scheduled.next_at += scheduled.period;
+            }
+        }
+    }
+    for text in due {
+        let message = Announcement {
+            kind: AnnouncementKind::Notice,
+            text,
+        }
+        .message();
+        for (stats, session, mut channel) in peers.iter_mut() {
+            if stats.state != ConnectionState::Disconnected && session.supports("announcements") {
+                channel.push(message.clone());
+            }
+        }
+    }
+}
+
+/// Runs after `accounts::reset_reliable_on_join`, so the MOTD goes out on
+/// the fresh channel.
+pub fn send_motd_on_join(
+    announcements: Res<Announcements>,
+    index: Res<PeerIndex>,
+    mut joins: EventReader<ReplicationJoin>,
+    mut channels: Query<(&PeerSession, &mut PeerReliable)>,
+) {
+    if announcements.motd.is_empty() {
+        joins.clear();
+        return;
+    }
+    for join in joins.read() {
+        let Some((session, mut channel)) = index
+            .get(&join.peer)
+            .and_then(|peer| channels.get_mut(peer).ok())
+        else {
+            continue;
+        };
+        if !session.supports("announcements") {
+            continue;
+        }
+        for text in &announcements.motd {
+            let message = Announcement {
+                kind: AnnouncementKind::Motd,
+                text: text.clone(),
+            };
+            channel.push(message.message());
+        }
+    }
+}
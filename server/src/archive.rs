@@ -0,0 +1,354 @@
+//! Archiving inactive clients, so the server's memory stays bounded however
+//! many addresses it has heard from.
+//!
+//! Every address that sends a packet gets a peer entity (see `peers`), and
+//! after it disconnects the entity keeps its rate window, send queue,
+//! reliable windows (the unacked payloads and the reorder buffer) and
+//! sparklines, in case it comes back. Once a second [`archive_inactive_peers`]
+//! despawns every peer that has been silent for `--archive-after` seconds
+//! while disconnected with no session held for a Resume, and keeps a compact
+//! [`ArchivedClient`] instead: why and when it left, its last RTT and loss,
+//! and what its last session came to. Past `--max-inactive-peers` such
+//! peers, the longest silent are archived early. Connected clients are never
+//! archived. One that comes back later starts over with a new peer.
+//!
+//! Archiving also drops what the rest of the server keeps by address
+//! outside the entity: the peer's packet timeline track, its chat and
+//! effect rate windows, and the nonce its Hello pinned for the signing
+//! stage. Anything else that keeps state by address has to go through
+//! [`KeptByAddr`] too, or the bound doesn't hold.
+//!
+//! The archive holds at most `--archive-max` summaries. When it is full,
+//! `--archive-evict` picks what goes: `oldest` drops the first archived,
+//! `least-traffic` the one whose last session moved the fewest bytes, which
+//! keeps the heavy users around for investigating.
+//!
+//! The same sweep measures the networking state: [`PEER_ENTITIES`] counts
+//! the live peers, [`PEER_STATE_BYTES`] estimates what they take (each
+//! component plus what it holds on the heap, the peer index and what's
+//! kept by address elsewhere), and
+//! [`ARCHIVE_BYTES`] what the archive takes. The F5 panel shows them. The
+//! console's `/archive` lists the newest summaries, `/archive <addr>` one
+//! client's.
+
+#[cfg(feature = "gui")]
+use bevy::diagnostic::{Diagnostic, DiagnosticsStore};
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use clap::ValueEnum;
+use common::disconnect::DisconnectReason;
+use common::middleware::Pipeline;
+use common::session::SessionTotals;
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use crate::ServerState;
+use crate::accounts::PeerReliable;
+#[cfg(feature = "chat")]
+use crate::chat::Moderation;
+use crate::client_list::ConnectionState;
+use crate::effects::EffectRelay;
+use crate::peers::{Peer, PeerAddr, PeerIndex, PeerSession, PeerStats};
+use crate::send_rate::SendRate;
+use crate::sparklines::RateHistory;
+#[cfg(feature = "gui")]
+use crate::timeline::PacketTimeline;
+
+/// Live peer entities
+pub const PEER_ENTITIES: DiagnosticPath = DiagnosticPath::const_new("network/peer_entities");
+/// Estimated bytes of the live peers' state
+pub const PEER_STATE_BYTES: DiagnosticPath = DiagnosticPath::const_new("network/peer_state_bytes");
+/// Estimated bytes of the archived summaries
+pub const ARCHIVE_BYTES: DiagnosticPath = DiagnosticPath::const_new("network/archive_bytes");
+
+/// Seconds between sweeps
+const SWEEP_SECS: f32 = 1.0;
+/// Summaries a bare `/archive` lists
+const LISTED: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Eviction {
+    /// The first summary archived goes first
+    Oldest,
+    /// The summary whose last session moved the fewest bytes goes first
+    LeastTraffic,
+}
+
+/// What's kept of a client once its peer is gone.
+pub struct ArchivedClient {
+    pub addr: String,
+    /// Server seconds, like `PeerStats::last_heard`
+    pub last_heard: f32,
+    pub reason: DisconnectReason,
+    pub rtt: Option<f32>,
+    pub loss: Option<f32>,
+    /// `None` if it never finished a handshake
+    pub last_session: Option<SessionTotals>,
+}
+
+impl ArchivedClient {
+    fn traffic(&self) -> u64 {
+        self.last_session
+            .map_or(0, |totals| totals.bytes_sent + totals.bytes_received)
+    }
+
+    /// `127.0.0.1:50122: left 312s ago (timeout), last session 41.0s, 80
+    /// messages / 2311 bytes sent, 92 / 2650 received, rtt 31.2 ms`
+    pub fn describe(&self, now: f32) -> String {
+        let mut line = format!(
+            "{}: left {:.0}s ago ({})",
+            self.addr,
+            now - self.last_heard,
+            self.reason
+        );
+        match self.last_session {
+            Some(totals) => line.push_str(&format!(
+                ", last session {:.1}s, {} messages / {} bytes sent, {} / {} received",
+                totals.secs,
+                totals.messages_sent,
+                totals.bytes_sent,
+                totals.messages_received,
+                totals.bytes_received
+            )),
+            None => line.push_str(", no session"),
+        }
+        if let Some(rtt) = self.rtt {
+            line.push_str(&format!(", rtt {:.1} ms", rtt * 1000.0));
+        }
+        if let Some(loss) = self.loss {
+            line.push_str(&format!(", loss {:.0}%", loss * 100.0));
+        }
+        line
+    }
+}
+
+#[derive(Resource)]
+pub struct ClientArchive {
+    /// `--archive-after`
+    after_secs: f32,
+    /// `--max-inactive-peers`
+    max_inactive: usize,
+    /// `--archive-max`
+    max_archived: usize,
+    /// `--archive-evict`
+    eviction: Eviction,
+    /// Oldest first
+    archived: VecDeque<ArchivedClient>,
+    sweep: Timer,
+}
+
+impl ClientArchive {
+    pub fn new(
+        after_secs: f32,
+        max_inactive: usize,
+        max_archived: usize,
+        eviction: Eviction,
+    ) -> Self {
+        Self {
+            after_secs,
+            max_inactive,
+            max_archived,
+            eviction,
+            archived: VecDeque::new(),
+            sweep: Timer::from_seconds(SWEEP_SECS, TimerMode::Repeating),
+        }
+    }
+
+    fn push(&mut self, client: ArchivedClient) {
+        if self.max_archived == 0 {
+            return;
+        }
+        if self.archived.len() >= self.max_archived {
+            let evicted = match self.eviction {
+                Eviction::Oldest => Some(0),
+                Eviction::LeastTraffic => self
+                    .archived
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, archived)| archived.traffic())
+                    .filter(|(_, archived)| archived.traffic() <= client.traffic())
+                    .map(|(index, _)| index),
+            };
+            // With `least-traffic`, a newcomer lighter than everything kept
+            // is the one that goes
+            let Some(evicted) = evicted else {
+                return;
+            };
+            self.archived.remove(evicted);
+        }
+        self.archived.push_back(client);
+    }
+
+    fn heap_bytes(&self) -> usize {
+        let clients = self.archived.capacity() * size_of::<ArchivedClient>();
+        clients
+            + self
+                .archived
+                .iter()
+                .map(|client| client.addr.capacity())
+                .sum::<usize>()
+    }
+
+    /// For `/archive`: the newest summaries, or `addr`'s.
+    pub fn lines(&self, addr: Option<&str>, now: f32) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .archived
+            .iter()
+            .rev()
+            .filter(|client| addr.is_none_or(|addr| client.addr == addr))
+            .take(LISTED)
+            .map(|client| client.describe(now))
+            .collect();
+        if lines.is_empty() {
+            lines.push(match addr {
+                Some(addr) => format!("[Info]: {} is not archived", addr),
+                None => "[Info]: no archived clients".to_string(),
+            });
+        }
+        lines
+    }
+
+    /// For the F5 panel: the latest measurements.
+    #[cfg(feature = "gui")]
+    pub fn panel_lines(&self, store: &DiagnosticsStore) -> Vec<String> {
+        let latest =
+            |path: &DiagnosticPath| store.get(path).and_then(Diagnostic::value).unwrap_or(0.0);
+        vec![
+            format!("{:<18} {:>7.0}", "peer entities", latest(&PEER_ENTITIES)),
+            format!(
+                "{:<18} {:>7.1} KiB",
+                "peer state",
+                latest(&PEER_STATE_BYTES) / 1024.0
+            ),
+            format!(
+                "{:<18} {:>7.1} KiB   ({} of {} summaries)",
+                "archive",
+                latest(&ARCHIVE_BYTES) / 1024.0,
+                self.archived.len(),
+                self.max_archived
+            ),
+        ]
+    }
+}
+
+type PeerState<'a> = (
+    Entity,
+    &'a PeerAddr,
+    &'a PeerStats,
+    &'a PeerSession,
+    &'a SendRate,
+    &'a PeerReliable,
+    &'a RateHistory,
+);
+
+/// Roughly what one peer entity takes, inline and on the heap.
+fn peer_bytes((_, addr, stats, session, rate, reliable, history): &PeerState) -> usize {
+    size_of::<PeerAddr>()
+        + addr.0.capacity()
+        + size_of::<PeerStats>()
+        + stats.heap_bytes()
+        + size_of::<PeerSession>()
+        + session.heap_bytes()
+        + size_of::<SendRate>()
+        + rate.heap_bytes()
+        + size_of::<PeerReliable>()
+        + reliable.heap_bytes()
+        + size_of::<RateHistory>()
+        + history.heap_bytes()
+}
+
+/// What the rest of the server keeps by peer address, outside the peer
+/// entity.
+#[derive(SystemParam)]
+pub struct KeptByAddr<'w> {
+    pipeline: Res<'w, Pipeline>,
+    effects: ResMut<'w, EffectRelay>,
+    #[cfg(feature = "chat")]
+    moderation: ResMut<'w, Moderation>,
+    #[cfg(feature = "gui")]
+    timeline: ResMut<'w, PacketTimeline>,
+}
+
+impl KeptByAddr<'_> {
+    fn forget(&mut self, addr: &str) {
+        self.pipeline.forget(addr);
+        self.effects.forget(addr);
+        #[cfg(feature = "chat")]
+        self.moderation.forget(addr);
+        #[cfg(feature = "gui")]
+        self.timeline.forget(addr);
+    }
+
+    fn heap_bytes(&self) -> usize {
+        let bytes = self.pipeline.heap_bytes() + self.effects.heap_bytes();
+        #[cfg(feature = "chat")]
+        let bytes = bytes + self.moderation.heap_bytes();
+        #[cfg(feature = "gui")]
+        let bytes = bytes + self.timeline.heap_bytes();
+        bytes
+    }
+}
+
+/// Archives the peers that have been gone long enough, or that are over
+/// the cap, and measures what's left.
+pub fn archive_inactive_peers(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut archive: ResMut<ClientArchive>,
+    mut index: ResMut<PeerIndex>,
+    peers: Query<PeerState, With<Peer>>,
+    mut kept: KeptByAddr,
+    server_state: Res<ServerState>,
+    mut diagnostics: Diagnostics,
+) {
+    if !archive.sweep.tick(time.delta()).just_finished() {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    let mut state_bytes: usize = peers.iter().map(|peer| peer_bytes(&peer)).sum();
+    let mut inactive: Vec<PeerState> = peers
+        .iter()
+        .filter(|(_, _, stats, session, ..)| {
+            stats.state == ConnectionState::Disconnected
+                && session.open.is_none()
+                && session.suspended_since.is_none()
+        })
+        .collect();
+    // Longest silent first, so the ones over the cap come first too
+    inactive.sort_by(|a, b| a.2.last_heard.total_cmp(&b.2.last_heard));
+    let over = inactive.len().saturating_sub(archive.max_inactive);
+    let mut archived = 0;
+    for (position, peer) in inactive.iter().enumerate() {
+        let (entity, addr, stats, session, ..) = peer;
+        if position >= over && now - stats.last_heard < archive.after_secs {
+            break;
+        }
+        if index.get(&addr.0) == Some(*entity) {
+            index.remove(&addr.0);
+        }
+        commands.entity(*entity).despawn();
+        kept.forget(&addr.0);
+        state_bytes -= peer_bytes(peer);
+        archive.push(ArchivedClient {
+            addr: addr.0.clone(),
+            last_heard: stats.last_heard,
+            reason: stats.departed.unwrap_or(DisconnectReason::Timeout),
+            rtt: stats.rtt,
+            loss: stats.loss,
+            last_session: session.last,
+        });
+        archived += 1;
+    }
+    if archived > 0 {
+        server_state
+            .log
+            .push(format!("[Info]: Archived {} inactive clients", archived));
+    }
+    let entities = peers.iter().count() - archived;
+    state_bytes += index.heap_bytes() + kept.heap_bytes();
+    let archive_bytes = archive.heap_bytes();
+    diagnostics.add_measurement(&PEER_ENTITIES, || entities as f64);
+    diagnostics.add_measurement(&PEER_STATE_BYTES, || state_bytes as f64);
+    diagnostics.add_measurement(&ARCHIVE_BYTES, || archive_bytes as f64);
+}
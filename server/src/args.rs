@@ -0,0 +1,312 @@
+//! The server's command line.
+
+use bevy::prelude::*;
+use clap::Parser;
+use common::storage::Backend;
+#[cfg(feature = "gui")]
+use common::ui::fonts::default_fonts;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::access::{Cidr, parse_cidr};
+use crate::archive::Eviction;
+use crate::send_delay::parse_duration;
+
+#[derive(Parser, Resource, Debug, Clone)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// Port to listen on
+    #[arg(short, long, default_value_t = 12345)]
+    pub port: u16,
+
+    /// Name given in answers to server queries (ServerInfoRequest); no spaces
+    #[arg(long, default_value = "bevy-server")]
+    pub name: String,
+
+    /// Server queries answered per second, across every sender; 0 answers none
+    #[arg(long, default_value_t = 50)]
+    pub query_rate: u32,
+
+    /// Packets per second at which a client row in the list turns fully red
+    #[arg(long, default_value_t = 30.0)]
+    pub heat_max_rate: f32,
+
+    /// Seconds with only heartbeats before a client is shown as idle
+    #[arg(long, default_value_t = 10.0)]
+    pub idle_timeout: f32,
+
+    /// Seconds with no traffic at all before a client is shown as disconnected
+    #[arg(long, default_value_t = 5.0)]
+    pub disconnect_timeout: f32,
+
+    /// Seconds a timed-out client's session is held for it to resume with its
+    /// token; 0 ends sessions on timeout
+    #[arg(long, default_value_t = 30.0)]
+    pub resume_grace: f32,
+
+    /// Only start sessions for clients with a connect ticket signed with this
+    /// key, i.e. sent by a matchmaker sharing it
+    #[arg(long)]
+    pub ticket_key: Option<String>,
+
+    /// Sign the tokens the console's /handoff gives out with this key, and
+    /// honour those of other servers started with the same one
+    #[arg(long)]
+    pub handoff_key: Option<String>,
+
+    /// Sign handshake replies with the Ed25519 key in this file (created if missing), for clients' --server-key
+    #[arg(long)]
+    pub identity_key: Option<PathBuf>,
+
+    /// Sign everything sent to a pinning client, not just the handshake
+    #[arg(long, requires = "identity_key")]
+    pub sign_all: bool,
+
+    /// Replication updates per second for clients on the worst links
+    #[arg(long, default_value_t = 5.0)]
+    pub min_send_rate: f32,
+
+    /// Replication updates per second for clients on the best links
+    #[arg(long, default_value_t = 30.0)]
+    pub max_send_rate: f32,
+
+    /// Comma-separated words the chat filter replaces with asterisks
+    #[cfg(feature = "chat")]
+    #[arg(long, value_delimiter = ',')]
+    pub banned_words: Vec<String>,
+
+    /// Chat lines a client may send per 5 seconds; more are dropped
+    #[cfg(feature = "chat")]
+    #[arg(long, default_value_t = 5)]
+    pub chat_rate_limit: usize,
+
+    /// Login attempts a client address may make per minute; more are refused unchecked
+    #[arg(long, default_value_t = 5)]
+    pub login_attempts: usize,
+
+    /// Optional second port for gameplay data, advertised to clients in the handshake
+    #[arg(long)]
+    pub data_port: Option<u16>,
+
+    /// Largest datagram we accept, in bytes; bigger ones are dropped and counted
+    #[arg(long, default_value_t = 1024)]
+    pub recv_buffer: usize,
+
+    /// Largest message we send, in bytes; bigger ones are refused with an error
+    #[arg(long, default_value_t = 1024)]
+    pub max_message_size: usize,
+
+    /// Send each client's replication updates in as few sends as GSO allows, and take
+    /// bursts GRO coalesced in one read, where the OS has them; see `common::offload`
+    #[arg(long)]
+    pub udp_offload: bool,
+
+    /// Log every datagram sent and received
+    #[arg(long)]
+    pub trace_packets: bool,
+
+    /// Drop and log messages a client may not send yet, such as Chat before its handshake
+    #[arg(long)]
+    pub validate_protocol: bool,
+
+    /// Drop bare legacy lines (`ping` from netcat) instead of reading them as Ping
+    #[arg(long)]
+    pub no_legacy_text: bool,
+
+    /// Write every datagram sent and received to this file, for `logdiff`
+    #[arg(long)]
+    pub packet_log: Option<PathBuf>,
+
+    /// Mirror every message in and out as JSON lines to tools that connect to this localhost port
+    #[arg(long)]
+    pub observer_port: Option<u16>,
+
+    /// Also accept clients over TCP on the control port, for networks that block UDP
+    #[arg(long)]
+    pub tcp: bool,
+
+    /// Connections `--tcp` holds open at most; each has a thread of its own
+    #[arg(long, default_value_t = 256)]
+    pub max_tcp_connections: usize,
+
+    /// Compress datagrams for clients that compress theirs (see `common::compression`)
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Message of the day and scheduled announcements for clients, one
+    /// `motd <text>` or `every <seconds> <text>` per line (see `announcements`)
+    #[arg(long)]
+    pub announcements: Option<PathBuf>,
+
+    /// How a channel is encoded, e.g. data=json (repeatable; see `common::codec`);
+    /// clients have to use the same
+    #[arg(long = "codec")]
+    pub codecs: Vec<String>,
+
+    /// Run without a window, printing the log and a status line every few seconds (see `fleet`).
+    /// Always on in a build without `gui`
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Serve a web dashboard on this address; it has no authentication, so keep it local
+    #[cfg(feature = "dashboard")]
+    #[arg(long, num_args = 0..=1, default_missing_value = "127.0.0.1:8080")]
+    pub dashboard: Option<String>,
+
+    /// Serve the gRPC admin API (proto/admin.proto) on this address; no authentication either
+    #[cfg(feature = "grpc")]
+    #[arg(long, num_args = 0..=1, default_missing_value = "127.0.0.1:50051")]
+    pub grpc: Option<String>,
+
+    /// Seconds with no peer connected and no window input before the server idles at low power (0 disables)
+    #[arg(long, default_value_t = 30.0)]
+    pub idle_after: f32,
+
+    /// Longest sleep per frame while idle; an arriving packet cuts it short
+    #[arg(long, default_value_t = 250)]
+    pub idle_wait_ms: u64,
+
+    /// Keep the simulation running while no client is connected, instead of pausing it
+    #[arg(long)]
+    pub keep_simulating: bool,
+
+    /// Exchange cross-shard chat with `fleet` over stdin and stdout
+    #[cfg(feature = "chat")]
+    #[arg(long)]
+    pub shard_link: bool,
+
+    /// Print the wire protocol as JSON and exit
+    #[arg(long)]
+    pub dump_protocol: bool,
+
+    /// Run the self-checks and a loopback session against a built-in peer,
+    /// print the results as JSON and exit: 0 if all passed, 1 if not
+    #[arg(long)]
+    pub validate: bool,
+
+    /// Most packets handled per frame; the rest wait for the next frame
+    #[arg(long, default_value_t = 256)]
+    pub packet_budget: usize,
+
+    /// Most milliseconds spent handling packets per frame
+    #[arg(long, default_value_t = 4.0)]
+    pub time_budget_ms: f32,
+
+    /// Most packets handled from one client per frame, 0 for no limit
+    #[arg(long, default_value_t = 32)]
+    pub peer_packet_budget: usize,
+
+    /// Packets a client can have waiting before more from it are dropped
+    #[arg(long, default_value_t = 1024)]
+    pub peer_queue_limit: usize,
+
+    /// Seconds a disconnected client's state is kept before it is archived as a summary
+    #[arg(long, default_value_t = 300.0)]
+    pub archive_after: f32,
+
+    /// Most disconnected clients whose state is kept; past it the longest silent are archived
+    #[arg(long, default_value_t = 1024)]
+    pub max_inactive_peers: usize,
+
+    /// Most archived client summaries kept
+    #[arg(long, default_value_t = 10_000)]
+    pub archive_max: usize,
+
+    /// Which summary a full archive drops
+    #[arg(long, value_enum, default_value_t = Eviction::Oldest)]
+    pub archive_evict: Eviction,
+
+    /// Only accept packets from this address range (repeatable, e.g. 192.168.0.0/16)
+    #[arg(long = "allow-cidr", value_parser = parse_cidr)]
+    pub allow_cidrs: Vec<Cidr>,
+
+    /// Drop packets from this address range (repeatable); wins over --allow-cidr
+    #[arg(long = "deny-cidr", value_parser = parse_cidr)]
+    pub deny_cidrs: Vec<Cidr>,
+
+    /// MaxMind country database (GeoLite2-Country.mmdb) for client tags
+    #[cfg(feature = "geoip")]
+    #[arg(long)]
+    pub geoip_db: Option<PathBuf>,
+
+    /// MaxMind ASN database (GeoLite2-ASN.mmdb) for client tags
+    #[cfg(feature = "geoip")]
+    #[arg(long)]
+    pub geoip_asn_db: Option<PathBuf>,
+
+    /// Received messages kept for the F3 replay panel
+    #[arg(long, default_value_t = 64)]
+    pub message_history: usize,
+
+    /// Hold every Pong back this long before sending (e.g. 200ms, 1.5s)
+    #[arg(long, value_parser = parse_duration, default_value = "0ms")]
+    pub delay_pongs: Duration,
+
+    /// Hold every handshake Welcome back this long before sending
+    #[arg(long, value_parser = parse_duration, default_value = "0ms")]
+    pub delay_welcomes: Duration,
+
+    /// Hold every HeartbeatAck back this long before sending
+    #[arg(long, value_parser = parse_duration, default_value = "0ms")]
+    pub delay_heartbeat_acks: Duration,
+
+    /// Append every accepted/rejected packet to this file
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Append every line of the window's log to this file
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate the audit log to `<path>.1` once it grows past this many bytes
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    pub audit_max_bytes: u64,
+
+    /// Keep chat history, accounts and bans in this database so they survive restarts
+    #[arg(long)]
+    pub database: Option<PathBuf>,
+
+    /// What keeps the --database: sled (the default), sqlite (built with --features sqlite) or
+    /// memory. Without --database everything is kept in memory
+    #[arg(long)]
+    pub storage: Option<Backend>,
+
+    /// Accept crash reports from clients started with --crash-report, and keep them in the database
+    #[arg(long)]
+    pub crash_reports: bool,
+
+    /// Append a summary of every finished client session to this file
+    #[arg(long)]
+    pub session_log: Option<PathBuf>,
+
+    /// Write a replay file of every session into this directory (see the client's --play-replay)
+    #[arg(long)]
+    pub record_sessions: Option<PathBuf>,
+
+    /// Post connect/disconnect/startup notices to this Discord webhook URL
+    #[cfg(feature = "webhook")]
+    #[arg(long)]
+    pub webhook_url: Option<String>,
+
+    /// Seed for the randomness every peer draws the same, sent in the Welcome (default: from the clock)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Run this many bot clients in-process, joining, chatting and leaving, to fill the UI
+    #[arg(long, default_value_t = 0)]
+    pub fake_clients: u16,
+
+    /// Run this many bot clients that send Pings as fast as --flood-rate allows, to stress the
+    /// receive queue
+    #[arg(long, default_value_t = 0)]
+    pub fake_flooders: u16,
+
+    /// Packets per second each fake flooder sends
+    #[arg(long, default_value_t = 5000)]
+    pub flood_rate: u32,
+
+    /// Font files for UI text, in fallback order (repeat to add more)
+    #[cfg(feature = "gui")]
+    #[arg(long = "font", default_values_t = default_fonts())]
+    pub fonts: Vec<String>,
+}
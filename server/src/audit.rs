@@ -0,0 +1,188 @@
+//! Append-only audit log of every packet the server accepts or rejects.
+//!
+//! Separate from the on-screen log, which only keeps 20 lines. Each record is
+//! one line: unix time, decision, peer, stream, size, message kind, the
+//! reason for a rejection and the peer's GeoIP tag (see `geoip`). Writing happens on a background thread so a slow
+//! disk never stalls a frame; when the file passes `--audit-max-bytes` it is
+//! renamed to `<path>.1` (replacing the previous one) and a fresh file starts.
+//!
+//! The last [`RECENT_RECORDS`] records are also kept in memory, with or
+//! without the file, for the console's `/history <addr> [n]`.
+
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Stream;
+use crate::geoip::GeoIp;
+
+/// Records kept in memory, across all peers
+pub const RECENT_RECORDS: usize = 2048;
+
+#[derive(Clone, Copy)]
+pub enum Decision {
+    Accepted,
+    Rejected(&'static str),
+}
+
+#[derive(Clone)]
+pub struct AuditRecord {
+    /// Unix time, in seconds
+    pub at: f64,
+    pub peer: String,
+    pub stream: Stream,
+    pub size: usize,
+    pub kind: String,
+    pub decision: Decision,
+}
+
+impl AuditRecord {
+    /// One console line, timed relative to `now`.
+    pub fn describe(&self, now: f64) -> String {
+        let decision = match self.decision {
+            Decision::Accepted => "accept".to_string(),
+            Decision::Rejected(reason) => format!("reject ({})", reason),
+        };
+        format!(
+            "{:>8.2}s ago  {:<7}  {:>5} B  {:<12}  {}",
+            now - self.at,
+            self.stream.label(),
+            self.size,
+            self.kind,
+            decision
+        )
+    }
+}
+
+/// Cheap to clone; the receive threads hold one each. Writes no file when
+/// `--audit-log` is not given.
+#[derive(Resource, Clone, Default)]
+pub struct AuditLog {
+    sender: Option<Sender<String>>,
+    geoip: GeoIp,
+    /// Newest last
+    recent: Arc<Mutex<VecDeque<AuditRecord>>>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf, max_bytes: u64, geoip: GeoIp) -> std::io::Result<Self> {
+        let file = open_append(&path)?;
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let mut writer = RotatingFile {
+                written: file.metadata().map(|m| m.len()).unwrap_or(0),
+                file,
+                path,
+                max_bytes,
+            };
+            for line in receiver {
+                if let Err(e) = writer.write_line(&line) {
+                    warn!("Audit log write failed, stopping audit log: {}", e);
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            sender: Some(sender),
+            geoip,
+            recent: default(),
+        })
+    }
+
+    /// `kind` is the decoded message kind, or "-" when the packet was
+    /// rejected before decoding.
+    pub fn record(&self, peer: &str, stream: Stream, size: usize, kind: &str, decision: Decision) {
+        let now = unix_now();
+        {
+            let mut recent = self.recent.lock().unwrap();
+            recent.push_back(AuditRecord {
+                at: now,
+                peer: peer.to_string(),
+                stream,
+                size,
+                kind: kind.to_string(),
+                decision,
+            });
+            if recent.len() > RECENT_RECORDS {
+                recent.pop_front();
+            }
+        }
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let (decision, reason) = match decision {
+            Decision::Accepted => ("accept", "-"),
+            Decision::Rejected(reason) => ("reject", reason),
+        };
+        // One field, so the line stays space-separated
+        let geo = self
+            .geoip
+            .tag_peer(peer)
+            .map_or("-".to_string(), |tag| tag.replace(' ', ","));
+        // A closed channel means the writer gave up; it already said why
+        let _ = sender.send(format!(
+            "{:.3} {} {} {} {} {} {} {}",
+            now,
+            decision,
+            peer,
+            stream.label(),
+            size,
+            kind,
+            reason,
+            geo
+        ));
+    }
+
+    /// The last `count` records for `peer` still in memory, oldest first.
+    pub fn recent_for(&self, peer: &str, count: usize) -> Vec<AuditRecord> {
+        let recent = self.recent.lock().unwrap();
+        let mut records: Vec<AuditRecord> = recent
+            .iter()
+            .rev()
+            .filter(|record| record.peer == peer)
+            .take(count)
+            .cloned()
+            .collect();
+        records.reverse();
+        records
+    }
+}
+
+pub fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+struct RotatingFile {
+    file: File,
+    path: PathBuf,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl RotatingFile {
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written >= self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, rotated)?;
+            self.file = open_append(&self.path)?;
+            self.written = 0;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! A colored circle per client, arranged in a ring around the middle of the
+//! window. An avatar swells briefly whenever its client sends a packet, so
+//! you can see who is talking at a glance.
+
+use bevy::prelude::*;
+use bevy::sprite::MaterialMesh2dBundle;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerAddr, PeerStats};
+
+/// Above the packet timeline tracks, which start at y = -120
+pub const RING_CENTER: Vec2 = Vec2::new(0.0, 60.0);
+const RING_RADIUS: f32 = 110.0;
+pub const AVATAR_RADIUS: f32 = 18.0;
+/// How much bigger an avatar gets right after a packet
+const PULSE_SCALE: f32 = 0.6;
+/// How quickly the pulse decays, per second
+const PULSE_DECAY: f32 = 8.0;
+
+#[derive(Component)]
+pub struct ClientAvatar {
+    pub addr: String,
+    color: Color,
+}
+
+/// A stable color per address, so a client that reconnects from the same
+/// address keeps its color.
+pub fn avatar_color(addr: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    addr.hash(&mut hasher);
+    let hue = (hasher.finish() % 360) as f32;
+    Color::hsl(hue, 0.7, 0.55)
+}
+
+/// Spawns an avatar for every new peer.
+pub fn spawn_avatars(
+    mut commands: Commands,
+    new_peers: Query<&PeerAddr, Added<Peer>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for PeerAddr(addr) in new_peers.iter() {
+        let color = avatar_color(addr);
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(Circle::new(AVATAR_RADIUS)).into(),
+                material: materials.add(color),
+                transform: Transform::from_translation(RING_CENTER.extend(0.0)),
+                ..default()
+            },
+            ClientAvatar {
+                addr: addr.clone(),
+                color,
+            },
+        ));
+    }
+}
+
+/// Spreads the avatars evenly around the ring, pulses them on traffic and
+/// greys out disconnected clients.
+pub fn update_avatars(
+    time: Res<Time>,
+    peers: Query<(&PeerAddr, &PeerStats), With<Peer>>,
+    mut avatars: Query<(&ClientAvatar, &mut Transform, &Handle<ColorMaterial>)>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let now = time.elapsed_seconds();
+    // Address order keeps each client in the same slot from frame to frame
+    let mut slots: Vec<(&str, &PeerStats)> = peers
+        .iter()
+        .map(|(addr, stats)| (addr.0.as_str(), stats))
+        .collect();
+    slots.sort_by_key(|(addr, _)| *addr);
+    let count = slots.len().max(1);
+
+    for (avatar, mut transform, material) in avatars.iter_mut() {
+        let Ok(slot) = slots.binary_search_by_key(&avatar.addr.as_str(), |(addr, _)| *addr) else {
+            continue;
+        };
+        let traffic = slots[slot].1;
+
+        let angle = std::f32::consts::TAU * slot as f32 / count as f32;
+        let position = RING_CENTER + Vec2::from_angle(angle) * RING_RADIUS;
+        let pulse = PULSE_SCALE * (-(now - traffic.last_heard) * PULSE_DECAY).exp();
+        transform.translation = position.extend(0.0);
+        transform.scale = Vec3::splat(1.0 + pulse);
+
+        let color = match traffic.state {
+            ConnectionState::Disconnected => Color::DARK_GRAY,
+            ConnectionState::Active | ConnectionState::Idle => avatar.color,
+        };
+        // get_mut marks the asset modified, so only call it on a change
+        if materials.get(material).is_some_and(|m| m.color != color)
+            && let Some(material) = materials.get_mut(material)
+        {
+            material.color = color;
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Fake clients, for working on the UI without a room full of windows.
+//!
+//! `--fake-clients <n>` starts `n` bots in the server's own process. Each
+//! has a UDP socket of its own on localhost and talks to the server's port
+//! like any client: a Hello, Heartbeats carrying the RTT and loss it
+//! measures, Pings every few seconds and now and then a chat line, if the
+//! server was built with chat. After a while a bot quits with a
+//! `Disconnect` and comes back a few seconds later from a new port, so the
+//! client list, the event history and the graphs always have joins and
+//! leaves to show. To the server they are ordinary
+//! clients, so everything they exercise is the real path.
+//!
+//! Bots don't pin a server key, bring connect tickets or use codecs other
+//! than text; a server started with `--ticket-key` or `--codec` rejects
+//! them, and they give up.
+//!
+//! `--fake-flooders <n>` starts `n` bots of another kind, for stress testing:
+//! each joins and then sends `--flood-rate` Pings a second, and Heartbeats so
+//! it stays connected, until it's kicked. Run them next to `--fake-clients`
+//! to see `receive_queue` at work: the ordinary bots stay green and
+//! connected in the client list, while each flooder is held to
+//! `--peer-packet-budget` packets a frame and the rest of its flood is
+//! dropped once its queue is full.
+//!
+//! While flooders run, [`report_throughput`] logs every
+//! [`THROUGHPUT_SECS`] how many packets a second the server handled and
+//! what decoding them and updating their sessions took per packet and as a
+//! share of the handling time: the stress test's measurement.
+
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use common::handlers::{Heartbeat, NetMessage, Welcome};
+use common::message::Message;
+use common::protocol::PROTOCOL_VERSION;
+use common::session::message_kind;
+use common::shared_rng::SharedRng;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ServerState;
+use crate::metrics::{Metric, MetricsSample};
+use crate::profiler::{Stage, TickProfile};
+
+const CHAT_LINES: &[&str] = &[
+    "hi all",
+    "anyone up for a round?",
+    "lag is fine here",
+    "brb",
+    "gg",
+    "which port is the data stream on again?",
+    "ping looks good",
+    "back",
+];
+
+/// How often an unanswered Hello is sent again
+const HELLO_RETRY: Duration = Duration::from_secs(1);
+const HEARTBEAT: Duration = Duration::from_secs(1);
+/// A Ping without a Pong by then counts as lost
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+/// Same gain the client smooths its RTT with
+const RTT_GAIN: f32 = 0.125;
+
+/// Starts `count` bots talking to the server on `port`.
+pub fn spawn_bots(count: u16, port: u16) {
+    let server = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    for number in 1..=count {
+        thread::spawn(move || run_bot(number, server));
+    }
+}
+
+enum Visit {
+    /// Quit, or was sent away; comes back later
+    Left,
+    /// The server won't have us; the reply says why
+    Rejected(String),
+}
+
+fn run_bot(number: u16, server: SocketAddr) {
+    // Bots only need to not all do the same thing at once
+    let mut rng = SharedRng::new(SharedRng::random_seed().wrapping_add(number as u64));
+    // Staggered, so they don't all join on the same frame
+    thread::sleep(between(&mut rng, 0.0, 3.0));
+    loop {
+        match visit(&mut rng, server) {
+            Ok(Visit::Left) => thread::sleep(between(&mut rng, 3.0, 10.0)),
+            Ok(Visit::Rejected(reply)) => {
+                warn!(
+                    "Fake client {} gives up, the server said: {}",
+                    number, reply
+                );
+                return;
+            }
+            Err(e) => {
+                warn!("Fake client {}: {}", number, e);
+                thread::sleep(Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+/// One connection, from the Hello to leaving.
+fn visit(rng: &mut SharedRng, server: SocketAddr) -> io::Result<Visit> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+    let send = |text: &str, to: SocketAddr| socket.send_to(text.as_bytes(), to).map(|_| ());
+    let hello = format!("Hello version={}", PROTOCOL_VERSION);
+    send(&hello, server)?;
+
+    let started = Instant::now();
+    let stay = between(rng, 20.0, 90.0);
+    let mut last_hello = started;
+    // Set by the Welcome
+    let mut data: Option<SocketAddr> = None;
+    let mut next_heartbeat = started;
+    let mut next_ping = started + between(rng, 0.5, 2.0);
+    let mut next_chat = started + between(rng, 5.0, 20.0);
+    let mut ping_sent: Option<Instant> = None;
+    let mut rtt: Option<f32> = None;
+    let (mut pings, mut lost) = (0u32, 0u32);
+    let mut buf = [0u8; 2048];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((size, _)) => {
+                let text = String::from_utf8_lossy(&buf[..size]);
+                match message_kind(&text) {
+                    "Welcome" => {
+                        let port = Welcome::parse(&text).and_then(|welcome| welcome.data_port);
+                        let ip = server.ip();
+                        data = Some(port.map_or(server, |port| SocketAddr::new(ip, port)));
+                    }
+                    "Reject" => return Ok(Visit::Rejected(text.into_owned())),
+                    "Disconnect" => return Ok(Visit::Left),
+                    // Ours, or PING ALL's, which answers nobody's in particular
+                    "Pong" => {
+                        if let Some(Message::Pong { seq }) = Message::parse(&text)
+                            && (seq == 0 || seq == pings)
+                            && let Some(sent) = ping_sent.take()
+                        {
+                            let sample = sent.elapsed().as_secs_f32();
+                            let smoothed =
+                                rtt.map_or(sample, |rtt| rtt + (sample - rtt) * RTT_GAIN);
+                            rtt = Some(smoothed);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Err(ref e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            // On Windows a server that isn't up yet shows up as a receive error
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+            Err(e) => return Err(e),
+        }
+
+        let now = Instant::now();
+        let Some(data) = data else {
+            if now - last_hello >= HELLO_RETRY {
+                send(&hello, server)?;
+                last_hello = now;
+            }
+            continue;
+        };
+        if now - started >= stay {
+            send(&DisconnectReason::UserQuit.message(), server)?;
+            return Ok(Visit::Left);
+        }
+        if ping_sent.is_some_and(|sent| now - sent > PING_TIMEOUT) {
+            ping_sent = None;
+            lost += 1;
+        }
+        if ping_sent.is_none() && now >= next_ping {
+            pings += 1;
+            send(&Message::Ping { seq: pings }.to_string(), data)?;
+            ping_sent = Some(now);
+            next_ping = now + between(rng, 0.5, 3.0);
+        }
+        // A server built without chat would only log the line
+        if cfg!(feature = "chat") && now >= next_chat {
+            let line = CHAT_LINES[rng.below(CHAT_LINES.len())];
+            send(&format!("Chat {}", line), data)?;
+            next_chat = now + between(rng, 10.0, 40.0);
+        }
+        if now >= next_heartbeat {
+            let heartbeat = Heartbeat {
+                rtt_ms: rtt.map(|rtt| (rtt * 10_000.0).round() / 10.0),
+                loss: (pings > 0).then(|| (lost as f32 * 100.0 / pings as f32).round() / 100.0),
+            };
+            send(&heartbeat.message(), server)?;
+            next_heartbeat = now + HEARTBEAT;
+        }
+    }
+}
+
+/// Seconds between throughput lines
+pub const THROUGHPUT_SECS: f32 = 5.0;
+
+/// The totals since startup that a `[Stress]` line is the difference of
+#[derive(Clone, Copy)]
+pub struct ThroughputTotals {
+    at: f32,
+    handled: u64,
+    decoding: Duration,
+    updating: Duration,
+    handling: Duration,
+}
+
+/// Logs the packets handled a second and, for decoding and the per-packet
+/// session updates, the time per packet and the share of the time spent
+/// handling packets, since the last line. Those are the two stages sharding
+/// would spread out, and their shares together are the most it could save,
+/// on however many cores.
+pub fn report_throughput(
+    time: Res<Time>,
+    metrics: Res<MetricsSample>,
+    profile: Res<TickProfile>,
+    server_state: Res<ServerState>,
+    mut last: Local<Option<ThroughputTotals>>,
+) {
+    let totals = ThroughputTotals {
+        at: time.elapsed_seconds(),
+        handled: metrics.total(Metric::Drained),
+        decoding: profile.total(Stage::Decode),
+        updating: profile.session_updates(),
+        handling: profile.handling(),
+    };
+    let Some(then) = *last else {
+        *last = Some(totals);
+        return;
+    };
+    if totals.at - then.at < THROUGHPUT_SECS {
+        return;
+    }
+    *last = Some(totals);
+    let packets = totals.handled - then.handled;
+    let handling = totals.handling - then.handling;
+    let per_packet = |spent: Duration| match packets {
+        0 => 0.0,
+        n => spent.as_secs_f64() * 1e6 / n as f64,
+    };
+    let share = |spent: Duration| {
+        if handling.is_zero() {
+            0.0
+        } else {
+            spent.as_secs_f64() / handling.as_secs_f64() * 100.0
+        }
+    };
+    let decoding = totals.decoding - then.decoding;
+    let updating = totals.updating - then.updating;
+    server_state.log.push(format!(
+        "[Stress]: {:.0} packets/s handled, decode {:.2} us/packet, {:.1}% of handling, \
+         session update {:.2} us/packet, {:.1}% of handling",
+        packets as f32 / (totals.at - then.at),
+        per_packet(decoding),
+        share(decoding),
+        per_packet(updating),
+        share(updating)
+    ));
+}
+
+/// Starts `count` flooders talking to the server on `port`, each sending
+/// `rate` Pings a second once it's welcomed.
+pub fn spawn_flooders(count: u16, port: u16, rate: u32) {
+    let server = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    for number in 1..=count {
+        thread::spawn(move || {
+            if let Err(e) = flood(server, rate) {
+                warn!("Fake flooder {}: {}", number, e);
+            }
+        });
+    }
+}
+
+/// Joins like a bot, then sends Pings at `rate` a second, and Heartbeats so
+/// it never times out, until the server sends it away.
+fn flood(server: SocketAddr, rate: u32) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    socket.set_nonblocking(true)?;
+    // A full send buffer just drops the packet; that's a flood for you
+    let send = |text: &str, to: SocketAddr| match socket.send_to(text.as_bytes(), to) {
+        Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+        _ => Ok(()),
+    };
+    let hello = format!("Hello version={}", PROTOCOL_VERSION);
+    let mut last_hello: Option<Instant> = None;
+    // Set by the Welcome, with when it came
+    let mut data: Option<(SocketAddr, Instant)> = None;
+    let mut sent = 0u64;
+    let mut next_heartbeat = Instant::now();
+    let mut buf = [0u8; 2048];
+    loop {
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((size, _)) => {
+                    let text = String::from_utf8_lossy(&buf[..size]);
+                    match message_kind(&text) {
+                        "Welcome" if data.is_none() => {
+                            let port = Welcome::parse(&text).and_then(|welcome| welcome.data_port);
+                            let addr =
+                                port.map_or(server, |port| SocketAddr::new(server.ip(), port));
+                            data = Some((addr, Instant::now()));
+                        }
+                        "Reject" => {
+                            warn!("Fake flooder gives up, the server said: {}", text);
+                            return Ok(());
+                        }
+                        "Disconnect" => return Ok(()),
+                        _ => {}
+                    }
+                }
+                Err(ref e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::ConnectionReset
+                    ) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let now = Instant::now();
+        let Some((data, welcomed)) = data else {
+            if last_hello.is_none_or(|last| now - last >= HELLO_RETRY) {
+                send(&hello, server)?;
+                last_hello = Some(now);
+            }
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+        let due = ((now - welcomed).as_secs_f64() * rate as f64) as u64;
+        while sent < due {
+            send("Ping", data)?;
+            sent += 1;
+        }
+        if now >= next_heartbeat {
+            send("Heartbeat", server)?;
+            next_heartbeat = now + HEARTBEAT;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn between(rng: &mut SharedRng, min_secs: f32, max_secs: f32) -> Duration {
+    Duration::from_secs_f32(min_secs + rng.unit() * (max_secs - min_secs))
+}
@@ -0,0 +1,348 @@
+//! Chat relay with server-side moderation.
+//!
+//! A client sends `Chat <text>`; the server relays it to every connected
+//! client, spectators included, as `Chat <addr>: <text>`. Nothing is relayed
+//! as sent: the server is the authority on what everyone else sees.
+//!
+//! - Words from `--banned-words` are replaced with asterisks, whole words
+//!   only and ignoring case.
+//! - `/mute <addr>` in the console drops everything said from that
+//!   client's IP address, whatever port it reconnects from, until
+//!   `/unmute <addr>`. A bare IP works as well.
+//! - A client sending more than `--chat-rate-limit` lines in
+//!   [`RATE_WINDOW_SECS`] has the extra lines dropped. Its window goes when
+//!   it disconnects.
+//!
+//! Muted or too-fast senders get a `Chat server: ...` notice, at most one
+//! per window so the notices can't be turned into a flood of their own.
+//!
+//! `/shard <port> <text>` sends the (filtered) line to another shard of the
+//! fleet instead; see `shards`.
+//!
+//! Relayed lines also go into the [`History`], and a client that says Hello
+//! or Resume is sent the recent ones as `History <addr>: <text>` before
+//! anything new.
+//!
+//! All of it, `shards` included, is the `chat` cargo feature. A client
+//! built without chat says so in its Hello (see `common::protocol::SUBSETS`)
+//! and is sent neither relays nor history.
+
+use bevy::prelude::*;
+use common::disconnect::Disconnected;
+use common::replicate::ReplicationJoin;
+use common::storage::History;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerAddr, PeerIndex, PeerSession, PeerStats};
+use crate::shards::{ShardLink, parse_shard_command};
+use crate::{NetworkState, ServerState};
+
+/// Window for `--chat-rate-limit`, and for the notices
+pub const RATE_WINDOW_SECS: f32 = 5.0;
+
+/// A `Chat` line as received; see `handle_network_messages`.
+#[derive(Event)]
+pub struct ChatReceived {
+    pub from: String,
+    pub text: String,
+}
+
+#[derive(Default)]
+struct Sender {
+    /// When the lines inside the rate window arrived
+    recent: VecDeque<f32>,
+    last_notice: Option<f32>,
+}
+
+#[derive(Resource)]
+pub struct Moderation {
+    /// Lowercase
+    banned: Vec<String>,
+    muted: HashSet<IpAddr>,
+    rate_limit: usize,
+    senders: HashMap<String, Sender>,
+}
+
+enum Verdict {
+    Relay(String),
+    Muted,
+    TooFast,
+}
+
+/// The IP address of `addr:port`, or of a bare IP.
+fn ip_of(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.parse())
+        .ok()
+}
+
+impl Moderation {
+    pub fn new(banned: &[String], rate_limit: usize) -> Self {
+        Self {
+            banned: banned.iter().map(|word| word.to_lowercase()).collect(),
+            muted: HashSet::new(),
+            rate_limit: rate_limit.max(1),
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Replaces banned words with asterisks, keeping everything else as is.
+    pub fn filter(&self, text: &str) -> String {
+        let mut filtered = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, out: &mut String| {
+            if self.banned.contains(&word.to_lowercase()) {
+                out.extend(word.chars().map(|_| '*'));
+            } else {
+                out.push_str(word);
+            }
+            word.clear();
+        };
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut filtered);
+                filtered.push(c);
+            }
+        }
+        flush(&mut word, &mut filtered);
+        filtered
+    }
+
+    /// Mutes `addr`'s IP address; `None` if it isn't an address.
+    pub fn mute(&mut self, addr: &str) -> Option<IpAddr> {
+        let ip = ip_of(addr)?;
+        self.muted.insert(ip);
+        Some(ip)
+    }
+
+    /// Whether `addr`'s IP address was muted, and now isn't.
+    pub fn unmute(&mut self, addr: &str) -> bool {
+        ip_of(addr).is_some_and(|ip| self.muted.remove(&ip))
+    }
+
+    pub fn is_muted(&self, addr: &str) -> bool {
+        ip_of(addr).is_some_and(|ip| self.muted.contains(&ip))
+    }
+
+    fn judge(&mut self, from: &str, text: &str, now: f32) -> Verdict {
+        if self.is_muted(from) {
+            return Verdict::Muted;
+        }
+        let sender = self.senders.entry(from.to_string()).or_default();
+        while sender
+            .recent
+            .front()
+            .is_some_and(|at| now - at > RATE_WINDOW_SECS)
+        {
+            sender.recent.pop_front();
+        }
+        if sender.recent.len() >= self.rate_limit {
+            return Verdict::TooFast;
+        }
+        sender.recent.push_back(now);
+        Verdict::Relay(self.filter(text))
+    }
+
+    /// Drops `peer`'s rate window, once it has left.
+    pub fn forget(&mut self, peer: &str) {
+        self.senders.remove(peer);
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(String, Sender)>();
+        self.senders.capacity() * entry
+            + self
+                .senders
+                .iter()
+                .map(|(peer, sender)| {
+                    peer.capacity() + sender.recent.capacity() * std::mem::size_of::<f32>()
+                })
+                .sum::<usize>()
+    }
+
+    /// Whether `to` may be sent another notice now.
+    fn may_notify(&mut self, to: &str, now: f32) -> bool {
+        let sender = self.senders.entry(to.to_string()).or_default();
+        if sender
+            .last_notice
+            .is_some_and(|at| now - at < RATE_WINDOW_SECS)
+        {
+            return false;
+        }
+        sender.last_notice = Some(now);
+        true
+    }
+}
+
+pub fn moderate_chat(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    peers: Query<(&PeerAddr, &PeerStats, &PeerSession), With<Peer>>,
+    mut received: EventReader<ChatReceived>,
+    mut moderation: ResMut<Moderation>,
+    link: Res<ShardLink>,
+    mut history: ResMut<History>,
+    server_state: Res<ServerState>,
+) {
+    let now = time.elapsed_seconds();
+    for chat in received.read() {
+        if chat.text.is_empty() {
+            continue;
+        }
+        let notice = match moderation.judge(&chat.from, &chat.text, now) {
+            Verdict::Relay(text) => match parse_shard_command(&text) {
+                Some((port, text)) if link.is_linked() => {
+                    link.forward(port, &chat.from, text);
+                    server_state
+                        .log
+                        .push(format!("[Chat]: {} to shard {}: {}", chat.from, port, text));
+                    None
+                }
+                Some(_) => Some((
+                    "used /shard outside a fleet",
+                    "This server is not part of a fleet",
+                )),
+                None => {
+                    let line = format!("{}: {}", chat.from, text);
+                    let relay = format!("Chat {}", line);
+                    for (PeerAddr(addr), stats, session) in peers.iter() {
+                        if stats.state != ConnectionState::Disconnected && session.supports("chat")
+                        {
+                            network.send_gameplay(relay.as_bytes(), addr);
+                        }
+                    }
+                    history.append(line);
+                    server_state
+                        .log
+                        .push(format!("[Chat]: {}: {}", chat.from, text));
+                    None
+                }
+            },
+            Verdict::Muted => Some(("is muted", "You are muted")),
+            Verdict::TooFast => Some(("is sending too fast", "You are sending too fast")),
+        };
+        if let Some((why, notice)) = notice {
+            server_state
+                .log
+                .push(format!("[Chat]: dropped, {} {}", chat.from, why));
+            if moderation.may_notify(&chat.from, now) {
+                let reply = format!("Chat server: {}", notice);
+                network.send_gameplay(reply.as_bytes(), &chat.from);
+            }
+        }
+    }
+}
+
+/// Forgets the rate windows of the clients that left.
+pub fn forget_chat_senders(
+    mut disconnected: EventReader<Disconnected>,
+    mut moderation: ResMut<Moderation>,
+) {
+    for left in disconnected.read() {
+        moderation.forget(&left.peer);
+    }
+}
+
+/// Catches a newcomer up on what was said before it joined, unless it
+/// goes without chat.
+pub fn send_chat_history(
+    network: Res<NetworkState>,
+    history: Res<History>,
+    index: Res<PeerIndex>,
+    sessions: Query<&PeerSession>,
+    mut joins: EventReader<ReplicationJoin>,
+) {
+    for join in joins.read() {
+        let chats = index
+            .get(&join.peer)
+            .and_then(|peer| sessions.get(peer).ok())
+            .is_some_and(|session| session.supports("chat"));
+        if !chats {
+            continue;
+        }
+        for line in history.recent() {
+            let message = format!("History {}", line);
+            network.send_gameplay(message.as_bytes(), &join.peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moderation(rate_limit: usize) -> Moderation {
+        Moderation::new(&["Darn".to_string()], rate_limit)
+    }
+
+    #[test]
+    fn filters_whole_banned_words_in_any_case() {
+        let moderation = moderation(5);
+        assert_eq!(moderation.filter("darn it, DARN!"), "**** it, ****!");
+        assert_eq!(moderation.filter("darnation"), "darnation");
+        assert_eq!(moderation.filter("  hello  "), "  hello  ");
+    }
+
+    #[test]
+    fn mutes_every_port_of_an_address() {
+        let mut moderation = moderation(5);
+        assert_eq!(moderation.mute("10.0.0.1:4000"), "10.0.0.1".parse().ok());
+        assert!(matches!(
+            moderation.judge("10.0.0.1:5000", "hi", 0.0),
+            Verdict::Muted
+        ));
+        assert!(matches!(
+            moderation.judge("10.0.0.2:4000", "hi", 0.0),
+            Verdict::Relay(_)
+        ));
+        assert!(moderation.unmute("10.0.0.1"));
+        assert!(!moderation.unmute("10.0.0.1"));
+        assert!(matches!(
+            moderation.judge("10.0.0.1:5000", "hi", 0.0),
+            Verdict::Relay(_)
+        ));
+        assert_eq!(moderation.mute("nobody"), None);
+    }
+
+    #[test]
+    fn drops_lines_over_the_rate_limit_until_the_window_passes() {
+        let mut moderation = moderation(2);
+        let from = "10.0.0.1:4000";
+        assert!(matches!(
+            moderation.judge(from, "a", 0.0),
+            Verdict::Relay(_)
+        ));
+        assert!(matches!(
+            moderation.judge(from, "b", 1.0),
+            Verdict::Relay(_)
+        ));
+        assert!(matches!(moderation.judge(from, "c", 2.0), Verdict::TooFast));
+        assert!(matches!(
+            moderation.judge("10.0.0.1:4001", "d", 2.0),
+            Verdict::Relay(_)
+        ));
+        assert!(matches!(
+            moderation.judge(from, "e", RATE_WINDOW_SECS + 0.5),
+            Verdict::Relay(_)
+        ));
+    }
+
+    #[test]
+    fn forgets_a_sender_that_left() {
+        let mut moderation = moderation(1);
+        let from = "10.0.0.1:4000";
+        moderation.judge(from, "a", 0.0);
+        assert!(matches!(moderation.judge(from, "b", 0.0), Verdict::TooFast));
+        moderation.forget(from);
+        assert!(moderation.senders.is_empty());
+        assert!(matches!(
+            moderation.judge(from, "c", 0.0),
+            Verdict::Relay(_)
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+//! Client settings changed from the console while clients stay connected.
+//!
+//! `/config net_tick_hz=30 chat_cooldown_secs=2` checks the values, keeps
+//! them, and pushes a `ConfigUpdate` (see `common::handlers`) to every
+//! connected client over its reliable channel (see `accounts`). A client
+//! that joins later gets everything changed so far right after its
+//! Welcome, so a reconnect doesn't lose the tuning. `/config` alone lists
+//! the current changes and `/config reset` forgets them; clients go back
+//! to their own values with their next Welcome. A client whose protocol
+//! version predates `config_push` is never sent any.
+
+use bevy::prelude::*;
+use common::handlers::{CONFIG_KEYS, ConfigUpdate};
+use common::replicate::ReplicationJoin;
+use std::collections::BTreeMap;
+
+use crate::accounts::PeerReliable;
+use crate::client_list::ConnectionState;
+use crate::peers::{PeerIndex, PeerSession, PeerStats};
+
+#[derive(Resource)]
+pub struct ClientConfig {
+    values: BTreeMap<String, f32>,
+    /// Heartbeats must come more often than this, or clients time out
+    max_heartbeat_secs: f32,
+    /// Set by the console, pushed by [`push_config_updates`]
+    pending: Option<ConfigUpdate>,
+}
+
+impl ClientConfig {
+    /// `disconnect_timeout` is the server's `--disconnect-timeout`.
+    pub fn new(disconnect_timeout: f32) -> Self {
+        Self {
+            values: BTreeMap::new(),
+            max_heartbeat_secs: disconnect_timeout,
+            pending: None,
+        }
+    }
+
+    /// Checks `key=value` fields and, if they are all fine, keeps them and
+    /// queues them for every connected client.
+    pub fn set<'a>(&mut self, fields: impl Iterator<Item = &'a str>) -> Result<String, String> {
+        let mut values = Vec::new();
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("{} is not key=value", field))?;
+            if !CONFIG_KEYS.contains(&key) {
+                return Err(format!(
+                    "{} is not a client setting; try {}",
+                    key,
+                    CONFIG_KEYS.join(", ")
+                ));
+            }
+            let value: f32 = value
+                .parse()
+                .ok()
+                .filter(|value: &f32| value.is_finite() && *value >= 0.0)
+                .ok_or_else(|| format!("{} must be a number, 0 or more", key))?;
+            if key == "heartbeat_secs" && !(value > 0.0 && value < self.max_heartbeat_secs) {
+                return Err(format!(
+                    "heartbeat_secs must be above 0 and below the {}s disconnect timeout",
+                    self.max_heartbeat_secs
+                ));
+            }
+            values.push((key.to_string(), value));
+        }
+        if values.is_empty() {
+            return Err("usage: /config [<key>=<value>...|reset]".to_string());
+        }
+        self.values.extend(values.iter().cloned());
+        let update = ConfigUpdate { values };
+        let message = update.message();
+        self.pending = Some(update);
+        Ok(message)
+    }
+
+    /// Forgets every change; clients keep the values until they reconnect.
+    pub fn reset(&mut self) {
+        self.values.clear();
+    }
+
+    /// What clients are told after their Welcome
+    pub fn describe(&self) -> String {
+        match self.current() {
+            Some(update) => format!("pushed: {}", update.message()),
+            None => "pushed: (none, clients use their own settings)".to_string(),
+        }
+    }
+
+    /// Everything changed so far, for a client that just joined
+    fn current(&self) -> Option<ConfigUpdate> {
+        (!self.values.is_empty()).then(|| ConfigUpdate {
+            values: self
+                .values
+                .iter()
+                .map(|(key, value)| (key.clone(), *value))
+                .collect(),
+        })
+    }
+}
+
+pub fn push_config_updates(
+    mut config: ResMut<ClientConfig>,
+    mut peers: Query<(&PeerStats, &PeerSession, &mut PeerReliable)>,
+) {
+    let Some(update) = config.pending.take() else {
+        return;
+    };
+    let message = update.message();
+    for (stats, session, mut channel) in peers.iter_mut() {
+        if stats.state != ConnectionState::Disconnected && session.supports("config_push") {
+            channel.push(message.clone());
+        }
+    }
+}
+
+/// Runs after `accounts::reset_reliable_on_join`, so the update goes out on
+/// the fresh channel.
+pub fn push_config_on_join(
+    config: Res<ClientConfig>,
+    index: Res<PeerIndex>,
+    mut joins: EventReader<ReplicationJoin>,
+    mut channels: Query<(&PeerSession, &mut PeerReliable)>,
+) {
+    let Some(update) = config.current() else {
+        joins.clear();
+        return;
+    };
+    let message = update.message();
+    for join in joins.read() {
+        if let Some((session, mut channel)) = index
+            .get(&join.peer)
+            .and_then(|peer| channels.get_mut(peer).ok())
+            && session.supports("config_push")
+        {
+            channel.push(message.clone());
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Client list panel. Each row is color-coded by the client's recent packet
+//! rate (green -> yellow -> red) and carries a bar sized by bytes per second,
+//! so a flooding client stands out during load tests.
+//!
+//! Rows also show the connection state: a client whose heartbeats still
+//! arrive but who sends nothing else is idle, a client we hear nothing from
+//! at all, or who said Disconnect or was kicked, is disconnected. The rows show the peer entities (see `peers`).
+//!
+//! Each row ends in sparklines of the client's packet rates over the last
+//! minute; see `sparklines`.
+//!
+//! The panel has [`PAGE_ROWS`] rows however many peers there are. Past that
+//! the list is split into pages, turned with the arrows next to the count or
+//! with PageUp/PageDown, so a stress test with hundreds of clients costs no
+//! more UI than one with twenty.
+
+use bevy::prelude::*;
+use common::disconnect::{DisconnectReason, Disconnected};
+use common::players::PlayerCount;
+#[cfg(feature = "gui")]
+use common::ui::widgets::{ThemedButton, UiTheme};
+use std::time::Instant;
+
+use crate::ServerState;
+#[cfg(feature = "gui")]
+use crate::accounts::Account;
+#[cfg(feature = "gui")]
+use crate::args::Args;
+#[cfg(feature = "gui")]
+use crate::geoip::GeoIp;
+use crate::history::{NetworkEventHistory, NetworkEventKind};
+use crate::peers::{Peer, PeerAddr, PeerSession, PeerStats};
+use crate::profiler::{Stage, TickProfile};
+use crate::resume::Resumptions;
+#[cfg(feature = "gui")]
+use crate::send_rate::SendRate;
+use crate::session::Sessions;
+#[cfg(feature = "gui")]
+use crate::sparklines::spawn_sparklines;
+use crate::webhook::Webhook;
+
+/// Rows in the panel; more clients than this are split into pages
+#[cfg(feature = "gui")]
+pub const PAGE_ROWS: usize = 20;
+#[cfg(feature = "gui")]
+const BAR_MAX_WIDTH: f32 = 120.0;
+/// Bytes per second that fill the bar completely
+#[cfg(feature = "gui")]
+const BAR_FULL_BYTES_PER_SEC: f32 = 4096.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConnectionState {
+    Active,
+    Idle,
+    Disconnected,
+}
+
+impl ConnectionState {
+    pub fn label(self) -> &'static str {
+        match self {
+            ConnectionState::Active => "active",
+            ConnectionState::Idle => "idle",
+            ConnectionState::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// Thresholds for the connection states; the rows' colors go by
+/// `--heat-max-rate`.
+#[derive(Resource)]
+pub struct ClientList {
+    /// Seconds without gameplay traffic before a client counts as idle
+    pub idle_timeout: f32,
+    /// Seconds without any traffic before a client counts as disconnected
+    pub disconnect_timeout: f32,
+}
+
+impl ClientList {
+    pub fn new(idle_timeout: f32, disconnect_timeout: f32) -> Self {
+        Self {
+            idle_timeout,
+            disconnect_timeout,
+        }
+    }
+
+    fn classify(&self, stats: &PeerStats, now: f32) -> ConnectionState {
+        if stats.departed.is_some() || now - stats.last_heard > self.disconnect_timeout {
+            ConnectionState::Disconnected
+        } else if now - stats.last_activity > self.idle_timeout {
+            ConnectionState::Idle
+        } else {
+            ConnectionState::Active
+        }
+    }
+}
+
+/// Maps a 0..=1 load fraction onto green -> yellow -> red.
+#[cfg(feature = "gui")]
+pub fn heat_color(load: f32) -> Color {
+    let load = load.clamp(0.0, 1.0);
+    if load < 0.5 {
+        let t = load * 2.0;
+        Color::rgb(0.2 + 0.7 * t, 0.8, 0.2 - 0.1 * t)
+    } else {
+        let t = (load - 0.5) * 2.0;
+        Color::rgb(0.9, 0.8 - 0.6 * t, 0.1 + 0.1 * t)
+    }
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ClientListPanel;
+
+/// Which page of the list is shown; `update_client_rows` keeps it in range.
+#[cfg(feature = "gui")]
+#[derive(Resource, Default)]
+pub struct ClientListPage(pub usize);
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ClientCount;
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct PageButton {
+    /// -1 for the previous page, 1 for the next
+    pub step: isize,
+}
+
+/// One of the [`PAGE_ROWS`] rows; shows whichever peer is at its position
+/// on the current page.
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ClientRow {
+    pub slot: usize,
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct RowLabel {
+    pub slot: usize,
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct HeatBar {
+    pub slot: usize,
+}
+
+#[cfg(feature = "gui")]
+pub fn setup_client_list(mut commands: Commands, theme: Res<UiTheme>) {
+    let page_button = |parent: &mut ChildBuilder, label: &str, step: isize| {
+        parent
+            .spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(28.0),
+                        height: Val::Px(22.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: theme.button_color.into(),
+                    ..default()
+                },
+                ThemedButton,
+                PageButton { step },
+            ))
+            .with_children(|button| {
+                button.spawn(TextBundle::from_section(
+                    label,
+                    TextStyle {
+                        font_size: 16.0,
+                        color: theme.text_color,
+                        ..default()
+                    },
+                ));
+            });
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            },
+            ClientListPanel,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(8.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|header| {
+                    header.spawn((
+                        TextBundle::from_section(
+                            "Clients",
+                            TextStyle {
+                                font_size: 18.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                                ..default()
+                            },
+                        ),
+                        ClientCount,
+                    ));
+                    page_button(header, "<", -1);
+                    page_button(header, ">", 1);
+                });
+            // The rows are only ever repainted, never spawned, however many
+            // clients connect
+            for slot in 0..PAGE_ROWS {
+                parent
+                    .spawn((
+                        NodeBundle {
+                            style: Style {
+                                display: Display::None,
+                                flex_direction: FlexDirection::Row,
+                                align_items: AlignItems::Center,
+                                column_gap: Val::Px(8.0),
+                                padding: UiRect::all(Val::Px(4.0)),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        ClientRow { slot },
+                    ))
+                    .with_children(|row| {
+                        row.spawn((
+                            TextBundle::from_section(
+                                "",
+                                TextStyle {
+                                    font_size: 14.0,
+                                    color: Color::BLACK,
+                                    ..default()
+                                },
+                            ),
+                            RowLabel { slot },
+                        ));
+                        row.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    width: Val::Px(0.0),
+                                    height: Val::Px(8.0),
+                                    ..default()
+                                },
+                                background_color: Color::rgb(0.1, 0.1, 0.1).into(),
+                                ..default()
+                            },
+                            HeatBar { slot },
+                        ));
+                        spawn_sparklines(row, slot);
+                    });
+            }
+        });
+}
+
+pub fn prune_traffic(time: Res<Time>, mut peers: Query<&mut PeerStats>) {
+    let now = time.elapsed_seconds();
+    for mut stats in peers.iter_mut() {
+        stats.bypass_change_detection().prune(now);
+    }
+}
+
+/// Re-evaluates every client's state and logs the transitions.
+pub fn update_connection_states(
+    time: Res<Time>,
+    client_list: Res<ClientList>,
+    mut peers: Query<(Entity, &PeerAddr, &mut PeerStats, &mut PeerSession), With<Peer>>,
+    server_state: Res<ServerState>,
+    mut history: ResMut<NetworkEventHistory>,
+    sessions: Res<Sessions>,
+    mut resumptions: ResMut<Resumptions>,
+    webhook: Res<Webhook>,
+    mut disconnected: EventWriter<Disconnected>,
+    mut profile: ResMut<TickProfile>,
+) {
+    let started = Instant::now();
+    let now = time.elapsed_seconds();
+
+    for (entity, addr, mut stats, mut session) in peers.iter_mut() {
+        let state = client_list.classify(&stats, now);
+        if state == stats.state {
+            continue;
+        }
+        stats.state = state;
+        let reason = stats.departed.unwrap_or(DisconnectReason::Timeout);
+        let kind = match state {
+            ConnectionState::Active => NetworkEventKind::Resumed,
+            ConnectionState::Idle => NetworkEventKind::Idle,
+            ConnectionState::Disconnected if reason == DisconnectReason::Timeout => {
+                NetworkEventKind::TimedOut
+            }
+            ConnectionState::Disconnected => NetworkEventKind::Disconnected(reason),
+        };
+        history.record(now, &addr.0, kind);
+        let resumable = reason == DisconnectReason::Timeout
+            && resumptions.grace_secs > 0.0
+            && resumptions.has_token(entity)
+            && session.open.is_some();
+        if state == ConnectionState::Disconnected && resumable {
+            // `resume::expire_suspended_sessions` ends it if no Resume comes
+            session.suspended_since = Some(now);
+            server_state.log.push(format!(
+                "[Info]: Holding {}'s session for {}s",
+                addr.0, resumptions.grace_secs
+            ));
+        } else if state == ConnectionState::Disconnected {
+            resumptions.revoke(entity);
+            sessions.end(&mut session, now, &reason.to_string(), &server_state);
+            webhook.post(format!("Client {} disconnected ({})", addr.0, reason));
+            disconnected.send(Disconnected {
+                peer: addr.0.clone(),
+                reason,
+            });
+        }
+        server_state
+            .log
+            .push(format!("[Info]: {} is now {}", addr.0, state.label()));
+    }
+    profile.add(Stage::SessionUpdate, started.elapsed());
+}
+
+/// Keeps the replicated [`PlayerCount`] in line with the peers.
+pub fn update_player_count(
+    peers: Query<(&PeerStats, &PeerSession), With<Peer>>,
+    mut players: ResMut<PlayerCount>,
+) {
+    let (mut connected, mut spectators) = (0, 0);
+    for (stats, session) in peers.iter() {
+        if stats.state == ConnectionState::Disconnected {
+            continue;
+        }
+        if session.spectator {
+            spectators += 1;
+        } else {
+            connected += 1;
+        }
+    }
+    if players.connected != connected || players.spectators != spectators {
+        players.connected = connected;
+        players.spectators = spectators;
+    }
+}
+
+/// The arrows in the panel header, or PageUp and PageDown, turn the page.
+#[cfg(feature = "gui")]
+pub fn page_client_list(
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Query<(&Interaction, &PageButton), Changed<Interaction>>,
+    mut page: ResMut<ClientListPage>,
+) {
+    let mut step = 0;
+    if keys.just_pressed(KeyCode::PageUp) {
+        step -= 1;
+    }
+    if keys.just_pressed(KeyCode::PageDown) {
+        step += 1;
+    }
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Pressed {
+            step += button.step;
+        }
+    }
+    if step != 0 {
+        page.0 = page.0.saturating_add_signed(step);
+    }
+}
+
+/// What a row shows of its peer
+#[cfg(feature = "gui")]
+type RowPeers<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static PeerAddr,
+        &'static PeerStats,
+        Option<&'static SendRate>,
+        Option<&'static Account>,
+    ),
+    With<Peer>,
+>;
+
+/// Paints the current page's peers, oldest first, into the fixed rows and
+/// hides the rows left over.
+#[cfg(feature = "gui")]
+pub fn update_client_rows(
+    args: Res<Args>,
+    mut page: ResMut<ClientListPage>,
+    peers: RowPeers,
+    geoip: Res<GeoIp>,
+    mut counts: Query<&mut Text, (With<ClientCount>, Without<RowLabel>)>,
+    mut rows: Query<(&ClientRow, &mut Style, &mut BackgroundColor), Without<HeatBar>>,
+    mut labels: Query<(&RowLabel, &mut Text), Without<ClientCount>>,
+    mut bars: Query<(&HeatBar, &mut Style), Without<ClientRow>>,
+) {
+    let mut listed: Vec<_> = peers.iter().collect();
+    listed.sort_by_key(|(entity, ..)| *entity);
+    let pages = listed.len().div_ceil(PAGE_ROWS).max(1);
+    if page.0 >= pages {
+        page.0 = pages - 1;
+    }
+    let shown = &listed[(page.0 * PAGE_ROWS).min(listed.len())..];
+
+    let count = if pages > 1 {
+        format!("Clients: {} (page {}/{})", listed.len(), page.0 + 1, pages)
+    } else {
+        format!("Clients: {}", listed.len())
+    };
+    for mut text in counts.iter_mut() {
+        if text.sections[0].value != count {
+            text.sections[0].value = count.clone();
+        }
+    }
+
+    for (row, mut style, mut background) in rows.iter_mut() {
+        let Some((_, _, traffic, ..)) = shown.get(row.slot) else {
+            if style.display != Display::None {
+                style.display = Display::None;
+            }
+            continue;
+        };
+        if style.display != Display::Flex {
+            style.display = Display::Flex;
+        }
+        *background = match traffic.state {
+            ConnectionState::Active => heat_color(traffic.packets_per_sec() / args.heat_max_rate),
+            ConnectionState::Idle => Color::rgb(0.45, 0.5, 0.65),
+            ConnectionState::Disconnected => Color::DARK_GRAY,
+        }
+        .into();
+    }
+
+    for (label, mut text) in labels.iter_mut() {
+        let Some((_, PeerAddr(addr), traffic, rate, account)) = shown.get(label.slot) else {
+            continue;
+        };
+        let geo = geoip
+            .tag_peer(addr)
+            .map_or(String::new(), |tag| format!(" ({})", tag));
+        let rate = rate.map_or(String::new(), |rate| format!("  {:.0} Hz", rate.hz));
+        let user = account.map_or(String::new(), |account| format!(" as {}", account.user));
+        let value = format!(
+            "{}{}{} [{}]  {:.0} pkt/s  {:.0} B/s{}",
+            addr,
+            user,
+            geo,
+            traffic.state.label(),
+            traffic.packets_per_sec(),
+            traffic.bytes_per_sec(),
+            rate
+        );
+        // Unchanged text would still be laid out again
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+
+    for (bar, mut style) in bars.iter_mut() {
+        if let Some((_, _, traffic, ..)) = shown.get(bar.slot) {
+            let fill = (traffic.bytes_per_sec() / BAR_FULL_BYTES_PER_SEC).min(1.0);
+            let width = Val::Px(BAR_MAX_WIDTH * fill);
+            if style.width != width {
+                style.width = width;
+            }
+        }
+    }
+}
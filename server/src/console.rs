@@ -0,0 +1,349 @@
+//! Server console: a text field at the bottom of the window that takes
+//! slash commands. Output goes to the server log.
+//!
+//! What is typed reaches `handle_console_commands` as a [`ConsoleCommand`];
+//! a build without `gui` has no field to type it into.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use common::message::Message;
+use common::packet_log::PacketLogFile;
+use common::sequence_diagram::{self, DiagramFormat};
+use common::storage::CrashReports;
+#[cfg(feature = "gui")]
+use common::ui::text_input::{TextInput, TextInputSubmitted, spawn_text_input};
+use std::path::Path;
+
+use crate::access::{AccessControl, parse_cidr};
+use crate::announcements::Announcements;
+use crate::archive::ClientArchive;
+use crate::audit::{AuditLog, unix_now};
+#[cfg(feature = "chat")]
+use crate::chat::Moderation;
+use crate::client_config::ClientConfig;
+use crate::crash_reports;
+use crate::handoff::{Handoffs, hand_off};
+use crate::history::NetworkEventHistory;
+use crate::peers::PeersByAddr;
+use crate::send_delay::{DelayedSends, Reply};
+use crate::{Args, NetworkState, ServerState};
+
+/// Packets `/history <addr>` lists by default
+const HISTORY_ROWS: usize = 10;
+/// At most, with the command and the header: the log keeps 20 lines
+const MAX_HISTORY_ROWS: usize = 18;
+/// Arrows `/diagram` draws by default; a diagram renderer struggles with
+/// many more
+const DIAGRAM_MESSAGES: usize = 200;
+
+/// A line entered in the console.
+#[derive(Event)]
+pub struct ConsoleCommand(pub String);
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ConsoleInput;
+
+/// What the console looks back on: connection events, the audit log and
+/// the archived clients.
+#[derive(SystemParam)]
+pub struct Records<'w> {
+    history: Res<'w, NetworkEventHistory>,
+    audit: Res<'w, AuditLog>,
+    archive: Res<'w, ClientArchive>,
+}
+
+#[cfg(feature = "gui")]
+pub fn setup_console(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(35.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            spawn_text_input(
+                parent,
+                TextInput::new("Type /help for console commands").clear_on_submit(),
+                Val::Px(420.0),
+                ConsoleInput,
+            );
+        });
+}
+
+/// Hands what is submitted in the console's field on as a [`ConsoleCommand`].
+#[cfg(feature = "gui")]
+pub fn read_console_input(
+    mut submitted: EventReader<TextInputSubmitted>,
+    console: Query<(), With<ConsoleInput>>,
+    mut entered: EventWriter<ConsoleCommand>,
+) {
+    for event in submitted.read() {
+        if console.get(event.entity).is_ok() {
+            entered.send(ConsoleCommand(event.value.clone()));
+        }
+    }
+}
+
+pub fn handle_console_commands(
+    mut entered: EventReader<ConsoleCommand>,
+    time: Res<Time>,
+    mut delayed: ResMut<DelayedSends>,
+    records: Records,
+    access: Res<AccessControl>,
+    network: Res<NetworkState>,
+    mut peers: PeersByAddr,
+    mut handoffs: ResMut<Handoffs>,
+    #[cfg(feature = "chat")] mut moderation: ResMut<Moderation>,
+    mut client_config: ResMut<ClientConfig>,
+    mut announcements: ResMut<Announcements>,
+    crash_reports: Option<Res<CrashReports>>,
+    server_state: Res<ServerState>,
+    args: Res<Args>,
+) {
+    for ConsoleCommand(line) in entered.read() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let output = match words.next() {
+            Some("/help") => vec![
+                "/help            this list".to_string(),
+                "/clear           clear the log".to_string(),
+                "/pong <addr>     send a Pong to a specific client".to_string(),
+                "/kick <addr>     disconnect a client".to_string(),
+                "/handoff <addr|all> <host:port>  move clients to another server".to_string(),
+                "/mute <addr>     drop a client's chat, from any port of its IP".to_string(),
+                "/unmute <addr>   let a muted client chat again".to_string(),
+                "/history         connection events of every client".to_string(),
+                "/history <addr> [n]  that client's last n packets (default 10)".to_string(),
+                "/archive [addr]  summaries of clients whose state was archived".to_string(),
+                "/allow <cidr>    only accept traffic from listed ranges".to_string(),
+                "/deny <cidr>     ban a range, until /acl clear".to_string(),
+                "/acl [clear]     show or clear the allow/deny lists and bans".to_string(),
+                "/crashes         the newest crash reports from clients".to_string(),
+                "/diagram [mermaid|plantuml] [<addr>] [n]  the packet log as a sequence diagram"
+                    .to_string(),
+                "/config [<key>=<value>...|reset]  show, push or forget client settings"
+                    .to_string(),
+                "/announce <text>  send an announcement to every client".to_string(),
+            ],
+            Some("/clear") => {
+                server_state.panel.clear();
+                Vec::new()
+            }
+            Some("/pong") => match words.next() {
+                Some(addr) => {
+                    // Answers the client's last Ping, if it is one we know
+                    let seq = peers.get_mut(addr).map_or(0, |(stats, _)| stats.last_ping);
+                    let pong = Message::Pong { seq }.to_string();
+                    delayed.push(Reply::Pong, pong.as_bytes(), addr, time.elapsed_seconds());
+                    vec![format!("[Tx]: Pong to {}", addr)]
+                }
+                None => vec!["[Error]: usage: /pong <addr>".to_string()],
+            },
+            Some("/kick") => match words.next() {
+                Some(addr) => match kick(addr, &network, &mut peers) {
+                    Ok(line) | Err(line) => vec![line],
+                },
+                None => vec!["[Error]: usage: /kick <addr>".to_string()],
+            },
+            Some("/handoff") => match (words.next(), words.next()) {
+                (Some(which), Some(target)) => {
+                    hand_off(which, target, &mut handoffs, &network, &mut peers)
+                }
+                _ => vec!["[Error]: usage: /handoff <addr|all> <host:port>".to_string()],
+            },
+            #[cfg(not(feature = "chat"))]
+            Some("/mute" | "/unmute") => {
+                vec!["[Error]: this server was built without chat".to_string()]
+            }
+            #[cfg(feature = "chat")]
+            Some(command @ ("/mute" | "/unmute")) => match words.next() {
+                Some(addr) => {
+                    if command == "/mute" {
+                        match moderation.mute(addr) {
+                            Some(ip) => vec![format!("[Info]: Muted {}", ip)],
+                            None => vec![format!("[Error]: {} is not an address", addr)],
+                        }
+                    } else if moderation.unmute(addr) {
+                        vec![format!("[Info]: Unmuted {}", addr)]
+                    } else {
+                        vec![format!("[Error]: {} is not muted", addr)]
+                    }
+                }
+                None => vec![format!("[Error]: usage: {} <addr>", command)],
+            },
+            Some("/history") => match words.next() {
+                Some(addr) => {
+                    let count = match words.next().map(str::parse::<usize>) {
+                        Some(Ok(count)) => Ok(count.clamp(1, MAX_HISTORY_ROWS)),
+                        Some(Err(_)) => Err(()),
+                        None => Ok(HISTORY_ROWS),
+                    };
+                    match count {
+                        Ok(count) => peer_history(addr, count, &records.history, &records.audit),
+                        Err(()) => vec!["[Error]: usage: /history <addr> [n]".to_string()],
+                    }
+                }
+                None => {
+                    let mut lines: Vec<String> = records
+                        .history
+                        .events()
+                        .rev()
+                        .take(HISTORY_ROWS)
+                        .map(|event| event.describe())
+                        .collect();
+                    lines.reverse();
+                    if lines.is_empty() {
+                        lines.push("[Info]: no connection events".to_string());
+                    }
+                    lines
+                }
+            },
+            Some("/archive") => records.archive.lines(words.next(), time.elapsed_seconds()),
+            Some(command @ ("/allow" | "/deny")) => match words.next().map(parse_cidr) {
+                Some(Ok(cidr)) if command == "/allow" => {
+                    access.allow(cidr);
+                    vec![format!("[Info]: Allowing {}", cidr)]
+                }
+                Some(Ok(cidr)) => {
+                    let mut lines = vec![format!("[Info]: Denying {}", cidr)];
+                    if let Err(e) = access.deny(cidr) {
+                        lines.push(format!(
+                            "[Error]: could not store the ban, so a restart lifts it: {}",
+                            e
+                        ));
+                    }
+                    lines
+                }
+                Some(Err(e)) => vec![format!("[Error]: {}", e)],
+                None => vec![format!("[Error]: usage: {} <cidr>", command)],
+            },
+            Some("/acl") => {
+                let cleared = if words.next() == Some("clear") {
+                    access.clear()
+                } else {
+                    Ok(())
+                };
+                let mut lines = Vec::new();
+                if let Err(e) = cleared {
+                    lines.push(format!("[Error]: could not lift the stored bans: {}", e));
+                }
+                lines.extend(access.describe());
+                lines
+            }
+            Some("/crashes") => crash_reports::list(crash_reports.as_deref()),
+            Some("/diagram") => diagram(words, args.packet_log.as_deref()),
+            Some("/config") => match words.clone().next() {
+                None => vec![client_config.describe()],
+                Some("reset") => {
+                    client_config.reset();
+                    vec!["[Info]: Client settings reset, from the next Welcome on".to_string()]
+                }
+                Some(_) => match client_config.set(words) {
+                    Ok(message) => vec![format!("[Tx]: {} to every client", message)],
+                    Err(e) => vec![format!("[Error]: {}", e)],
+                },
+            },
+            Some("/announce") => {
+                let text = words.collect::<Vec<_>>().join(" ");
+                if text.is_empty() {
+                    vec!["[Error]: usage: /announce <text>".to_string()]
+                } else {
+                    announcements.announce(&text);
+                    vec![format!("[Tx]: Announce notice {} to every client", text)]
+                }
+            }
+            Some(other) => vec![format!("[Error]: unknown command {}", other)],
+            None => Vec::new(),
+        };
+
+        server_state.log.push(format!("> {}", line));
+        for line in output {
+            server_state.log.push(line);
+        }
+    }
+}
+
+/// Draws the first `n` datagrams of `--packet-log`, or of those exchanged
+/// with `<addr>`, next to it, e.g. `server.log` to `server.mmd`.
+fn diagram<'a>(words: impl Iterator<Item = &'a str>, packet_log: Option<&Path>) -> Vec<String> {
+    let Some(path) = packet_log else {
+        return vec!["[Error]: /diagram draws the packet log; start with --packet-log".to_string()];
+    };
+    let mut format = DiagramFormat::Mermaid;
+    let mut peer = None;
+    let mut limit = DIAGRAM_MESSAGES;
+    for word in words {
+        if let Some(parsed) = DiagramFormat::parse(word) {
+            format = parsed;
+        } else if let Ok(count) = word.parse::<usize>() {
+            limit = count.max(1);
+        } else {
+            peer = Some(word);
+        }
+    }
+    let log = match PacketLogFile::load(path) {
+        Ok(log) => log,
+        Err(e) => return vec![format!("[Error]: could not read {}: {}", path.display(), e)],
+    };
+    let (diagram, arrows) = sequence_diagram::render(&log, peer, format, limit);
+    let out = path.with_extension(format.extension());
+    match std::fs::write(&out, diagram) {
+        Ok(()) => vec![format!(
+            "[Info]: Wrote {} messages to {}",
+            arrows,
+            out.display()
+        )],
+        Err(e) => vec![format!("[Error]: could not write {}: {}", out.display(), e)],
+    }
+}
+
+/// Says Disconnect to `addr` and marks it departed. Either way, the line
+/// for the log.
+pub fn kick(addr: &str, network: &NetworkState, peers: &mut PeersByAddr) -> Result<String, String> {
+    let Some((mut stats, _)) = peers.get_mut(addr) else {
+        return Err(format!("[Error]: no client {}", addr));
+    };
+    let message = DisconnectReason::Kicked.message();
+    network.send_control(message.as_bytes(), addr);
+    // `update_connection_states` ends the session and reports it
+    stats.departed = Some(DisconnectReason::Kicked);
+    Ok(format!("[Tx control]: {} to {}", message, addr))
+}
+
+/// One client's latest connection event, then its last `count` packets
+/// from the audit log, accepted or not.
+fn peer_history(
+    addr: &str,
+    count: usize,
+    history: &NetworkEventHistory,
+    audit: &AuditLog,
+) -> Vec<String> {
+    let records = audit.recent_for(addr, count);
+    let latest = history.for_peer(addr).next_back();
+    if records.is_empty() && latest.is_none() {
+        return vec![format!("[Info]: nothing from {}", addr)];
+    }
+    let mut lines = vec![match latest {
+        Some(event) => format!(
+            "[Info]: {}, {} at {:.2}s; last {} packets:",
+            addr,
+            event.kind.label(),
+            event.at,
+            records.len()
+        ),
+        None => format!("[Info]: {}, last {} packets:", addr, records.len()),
+    }];
+    let now = unix_now();
+    lines.extend(records.iter().map(|record| record.describe(now)));
+    lines
+}
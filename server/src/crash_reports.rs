@@ -0,0 +1,100 @@
+//! Crash reports from clients, with `--crash-reports`.
+//!
+//! A client started with `--crash-report <path>` notes a panic, or a run
+//! that never got to exit cleanly, and sends it over the reliable channel
+//! (see `accounts`) after its next Welcome:
+//!
+//! ```text
+//! REL <seq> CrashReport kind=panic|abnormal_exit version=0.1.0 os=linux ... message=<text>
+//! REL <seq> CrashReportOk id=<n>                          server -> client
+//! REL <seq> CrashReportRefused reason=disabled|storage
+//! ```
+//!
+//! The fields are the client's business; the server only stores the line in
+//! `common::storage::CrashReports` and logs it. Without `--crash-reports`
+//! there is no store and every report is refused, so the client discards
+//! it instead of sending it again next time. `/crashes` lists the newest.
+
+use common::storage::{CrashReports, KEPT_REPORTS};
+
+/// Reports `/crashes` lists
+pub const LISTED_REPORTS: usize = 5;
+
+fn field<'a>(report: &'a str, name: &str) -> Option<&'a str> {
+    report
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// The free-text message, which runs to the end of the line.
+fn message(report: &str) -> &str {
+    report
+        .split_once(" message=")
+        .map_or("", |(_, message)| message)
+}
+
+/// A short description of `report` for the log.
+pub fn summarize(report: &str) -> String {
+    let kind = field(report, "kind").unwrap_or("unknown").replace('_', " ");
+    let version = field(report, "version").unwrap_or("?");
+    match (field(report, "location"), message(report)) {
+        (Some(location), "") => format!("{} in {} at {}", kind, version, location),
+        (Some(location), message) => {
+            format!("{} in {} at {}: {}", kind, version, location, message)
+        }
+        (None, "") => format!("{} in {}", kind, version),
+        (None, message) => format!("{} in {}: {}", kind, version, message),
+    }
+}
+
+/// Stores a `CrashReport` payload from `peer`: the reply for the client
+/// and the line for the server log.
+pub fn file_report(store: Option<&mut CrashReports>, peer: &str, report: &str) -> (String, String) {
+    let Some(store) = store else {
+        return (
+            "CrashReportRefused reason=disabled".to_string(),
+            format!(
+                "[Crash]: {} sent a report; run with --crash-reports to keep them",
+                peer
+            ),
+        );
+    };
+    match store.file(peer, report) {
+        Ok(id) => (
+            format!("CrashReportOk id={}", id),
+            format!("[Crash]: #{} from {}: {}", id, peer, summarize(report)),
+        ),
+        Err(e) => (
+            "CrashReportRefused reason=storage".to_string(),
+            format!(
+                "[Error]: could not store a crash report from {}: {}",
+                peer, e
+            ),
+        ),
+    }
+}
+
+/// `/crashes`: the newest reports, one line each.
+pub fn list(store: Option<&CrashReports>) -> Vec<String> {
+    let Some(store) = store else {
+        return vec!["[Info]: crash reports are off; run with --crash-reports".to_string()];
+    };
+    let reports = store.recent(LISTED_REPORTS);
+    if reports.is_empty() {
+        return vec!["[Info]: no crash reports".to_string()];
+    }
+    let mut lines = vec![format!(
+        "[Info]: newest {} crash reports (up to {} are kept):",
+        reports.len(),
+        KEPT_REPORTS
+    )];
+    lines.extend(reports.iter().map(|report| {
+        format!(
+            "#{} from {}: {}",
+            report.id,
+            report.peer,
+            summarize(&report.report)
+        )
+    }));
+    lines
+}
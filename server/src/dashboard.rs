@@ -0,0 +1,270 @@
+//! Web dashboard, with `--features dashboard`: the window's view of the
+//! server in a browser, for headless deployments.
+//!
+//! `--dashboard <addr>` serves a page with the connected clients, the log
+//! tail and charts of the packet rates and player count, plus a kick button
+//! per client and a broadcast box. The HTTP side (axum, on a thread of its
+//! own) works from the admin plane (see `admin`): `GET /api/state` is the
+//! latest snapshot as JSON, and the buttons queue commands whose outcome
+//! shows up in the log tail.
+//!
+//! There is no authentication. The default address is loopback only; to
+//! reach a remote server's dashboard, tunnel to it rather than binding it
+//! to a public address. Loopback alone doesn't stop a web page in the
+//! operator's browser from reaching it, though. Every request is refused
+//! unless its `Host` is an IP address or `localhost`, not a name a DNS
+//! rebinding attack could point here to read the state or post, and the
+//! two POSTs also need to come from the dashboard's own page: an `Origin`,
+//! which browsers send with every POST, has to be that host.
+
+use axum::Router;
+use axum::extract::{Form, Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::thread;
+
+use crate::admin::{AdminCommand, AdminLink, AdminSnapshot, broadcast_text};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Binds `addr` and starts serving on a thread of its own. Binding happens
+/// here, so a taken port is a startup error.
+pub fn serve(addr: &str, link: AdminLink) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let router = router(link);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let served = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => axum::serve(listener, router).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = served {
+                warn!("Dashboard stopped: {}", e);
+            }
+        });
+    });
+    Ok(())
+}
+
+fn router(link: AdminLink) -> Router {
+    Router::new()
+        .route("/", get(|| async { Html(PAGE) }))
+        .route("/api/state", get(state))
+        .route("/api/kick", post(kick_client))
+        .route("/api/broadcast", post(broadcast))
+        .layer(middleware::from_fn(local_host_only))
+        .with_state(link)
+}
+
+/// `localhost` or an IP address, with or without a port.
+fn is_local_host(host: &str) -> bool {
+    if host.parse::<SocketAddr>().is_ok() || host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    name.eq_ignore_ascii_case("localhost")
+}
+
+fn local_host(headers: &HeaderMap) -> Option<&str> {
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    is_local_host(host).then_some(host)
+}
+
+/// Refuses any request for another host; see the module docs.
+async fn local_host_only(request: Request, next: Next) -> Response {
+    if local_host(request.headers()).is_none() {
+        return (StatusCode::FORBIDDEN, "host refused").into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether a POST came from the dashboard's own page, or from a tool that
+/// sends no `Origin`; see the module docs.
+fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(host) = local_host(headers) else {
+        return false;
+    };
+    match headers.get(header::ORIGIN).map(|origin| origin.to_str()) {
+        Some(Ok(origin)) => origin.strip_prefix("http://") == Some(host),
+        Some(Err(_)) => false,
+        None => true,
+    }
+}
+
+const CROSS_ORIGIN: (StatusCode, &str) = (StatusCode::FORBIDDEN, "cross-origin request refused");
+
+fn to_json(snapshot: &AdminSnapshot) -> String {
+    let clients: Vec<_> = snapshot
+        .clients
+        .iter()
+        .map(|client| {
+            serde_json::json!({
+                "addr": client.addr,
+                "state": client.state.label(),
+                "rtt_ms": client.rtt.map(|rtt| rtt * 1000.0),
+                "loss": client.loss,
+                "silent_secs": client.silent_secs,
+            })
+        })
+        .collect();
+    let samples: Vec<_> = snapshot
+        .samples
+        .iter()
+        .map(|sample| {
+            serde_json::json!({
+                "at": sample.at,
+                "packets_in": sample.packets_in,
+                "packets_out": sample.packets_out,
+                "players": sample.players,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "port": snapshot.port,
+        "listening": snapshot.listening,
+        "uptime_secs": snapshot.uptime_secs,
+        "players": snapshot.players,
+        "spectators": snapshot.spectators,
+        "packets_sent": snapshot.packets_sent,
+        "packets_received": snapshot.packets_received,
+        "clients": clients,
+        "log": snapshot.log,
+        "samples": samples,
+    })
+    .to_string()
+}
+
+async fn state(State(link): State<AdminLink>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        to_json(&link.snapshot()),
+    )
+}
+
+fn queue(link: &AdminLink, command: AdminCommand) -> (StatusCode, &'static str) {
+    if link.send(command) {
+        (StatusCode::ACCEPTED, "queued")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "server is shutting down")
+    }
+}
+
+async fn kick_client(
+    State(link): State<AdminLink>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> (StatusCode, &'static str) {
+    if !same_origin(&headers) {
+        return CROSS_ORIGIN;
+    }
+    match form.get("addr") {
+        Some(addr) if !addr.is_empty() => queue(&link, AdminCommand::Kick(addr.clone())),
+        _ => (StatusCode::BAD_REQUEST, "addr is required"),
+    }
+}
+
+async fn broadcast(
+    State(link): State<AdminLink>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> (StatusCode, &'static str) {
+    if !same_origin(&headers) {
+        return CROSS_ORIGIN;
+    }
+    match broadcast_text(form.get("text").map_or("", String::as_str)) {
+        Ok(text) => queue(&link, AdminCommand::Broadcast(text)),
+        Err(why) => (StatusCode::BAD_REQUEST, why),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::AdminPlane;
+    use crate::idle::Wakeup;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn post_kick(host: &str, origin: Option<&str>) -> StatusCode {
+        let mut request = Request::post("/api/kick")
+            .header(header::HOST, host)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        if let Some(origin) = origin {
+            request = request.header(header::ORIGIN, origin);
+        }
+        respond(request.body(Body::from("addr=10.0.0.2:5000")).unwrap())
+    }
+
+    fn get(path: &str, host: &str) -> StatusCode {
+        let request = Request::get(path).header(header::HOST, host);
+        respond(request.body(Body::empty()).unwrap())
+    }
+
+    fn respond(request: Request<Body>) -> StatusCode {
+        let plane = AdminPlane::new(Wakeup::default());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(router(plane.link()).oneshot(request))
+            .unwrap()
+            .status()
+    }
+
+    #[test]
+    fn refuses_a_cross_origin_post() {
+        assert_eq!(
+            post_kick("127.0.0.1:8080", Some("http://evil.example")),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            post_kick("127.0.0.1:8080", Some("null")),
+            StatusCode::FORBIDDEN
+        );
+        // DNS rebinding: the attacker's name, resolving to loopback
+        assert_eq!(
+            post_kick("evil.example:8080", Some("http://evil.example:8080")),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(post_kick("evil.example:8080", None), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn refuses_to_be_read_under_another_host() {
+        assert_eq!(
+            get("/api/state", "evil.example:8080"),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(get("/", "evil.example:8080"), StatusCode::FORBIDDEN);
+        assert_eq!(get("/api/state", "127.0.0.1:8080"), StatusCode::OK);
+        assert_eq!(get("/", "localhost:8080"), StatusCode::OK);
+    }
+
+    #[test]
+    fn accepts_its_own_page_and_tools() {
+        assert_eq!(
+            post_kick("127.0.0.1:8080", Some("http://127.0.0.1:8080")),
+            StatusCode::ACCEPTED
+        );
+        // Through an SSH tunnel
+        assert_eq!(
+            post_kick("localhost:9000", Some("http://localhost:9000")),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(post_kick("[::1]:8080", None), StatusCode::ACCEPTED);
+    }
+}
@@ -0,0 +1,121 @@
+//! Fan-out of cosmetic events; see `common::effects`.
+//!
+//! An `Fx` from a client is sent on to every connected client, spectators
+//! included, and shown here too. A repeat of one already relayed is
+//! dropped, and so is anything a client sends sooner than
+//! [`MIN_INTERVAL_SECS`] after its last one: each event goes to every
+//! client, so an unlimited sender would cost everyone's bandwidth. Muted
+//! clients' events are dropped like their chat, and a client whose protocol
+//! version predates `effects` is left out. What is kept per sender goes
+//! when it disconnects.
+
+use bevy::prelude::*;
+use common::disconnect::Disconnected;
+#[cfg(feature = "gui")]
+use common::effects::FLASH;
+use common::effects::{AtMostOnce, Effect};
+#[cfg(feature = "gui")]
+use common::ui::screen_flash::FlashScreen;
+use std::collections::HashMap;
+
+#[cfg(feature = "chat")]
+use crate::chat::Moderation;
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerAddr, PeerSession, PeerStats};
+use crate::{NetworkState, ServerState};
+
+/// Shortest gap between two events from one client
+pub const MIN_INTERVAL_SECS: f32 = 0.1;
+
+/// An `Fx` as received; see `handle_network_messages`.
+#[derive(Event)]
+pub struct EffectReceived {
+    pub from: String,
+    pub text: String,
+}
+
+#[derive(Default)]
+struct Sender {
+    seen: AtMostOnce,
+    last_relayed: Option<f32>,
+}
+
+#[derive(Resource, Default)]
+pub struct EffectRelay {
+    senders: HashMap<String, Sender>,
+    /// Numbers the relayed events, across all senders
+    next_seq: u32,
+}
+
+impl EffectRelay {
+    /// Drops `peer`'s dedup and rate state, once it has left.
+    pub fn forget(&mut self, peer: &str) {
+        self.senders.remove(peer);
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(String, Sender)>();
+        self.senders.capacity() * entry + self.senders.keys().map(String::capacity).sum::<usize>()
+    }
+}
+
+pub fn relay_effects(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    peers: Query<(&PeerAddr, &PeerStats, &PeerSession), With<Peer>>,
+    #[cfg(feature = "chat")] moderation: Res<Moderation>,
+    mut received: EventReader<EffectReceived>,
+    mut relay: ResMut<EffectRelay>,
+    #[cfg(feature = "gui")] mut flashes: EventWriter<FlashScreen>,
+    server_state: Res<ServerState>,
+) {
+    let now = time.elapsed_seconds();
+    for event in received.read() {
+        let Some((seq, effect)) = Effect::parse_request(&event.text) else {
+            warn!("Malformed Fx from {}: {}", event.from, event.text);
+            continue;
+        };
+        #[cfg(feature = "chat")]
+        if moderation.is_muted(&event.from) {
+            continue;
+        }
+        let sender = relay.senders.entry(event.from.clone()).or_default();
+        if !sender.seen.accept(seq) {
+            continue;
+        }
+        if sender
+            .last_relayed
+            .is_some_and(|at| now - at < MIN_INTERVAL_SECS)
+        {
+            continue;
+        }
+        sender.last_relayed = Some(now);
+
+        relay.next_seq += 1;
+        let message = effect.relay(relay.next_seq, &event.from);
+        for (PeerAddr(addr), stats, session) in peers.iter() {
+            if stats.state != ConnectionState::Disconnected && session.supports("effects") {
+                network.send_gameplay(message.as_bytes(), addr);
+            }
+        }
+        #[cfg(feature = "gui")]
+        if effect.name == FLASH {
+            flashes.send(FlashScreen {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.6),
+            });
+        }
+        server_state
+            .log
+            .push(format!("[Fx]: {} {}", event.from, effect.describe()));
+    }
+}
+
+/// Forgets the senders that left.
+pub fn forget_effect_senders(
+    mut disconnected: EventReader<Disconnected>,
+    mut relay: ResMut<EffectRelay>,
+) {
+    for left in disconnected.read() {
+        relay.forget(&left.peer);
+    }
+}
@@ -0,0 +1,77 @@
+//! Optional GeoIP tagging of client addresses.
+//!
+//! Built with `--features geoip`, the server can load MaxMind databases
+//! (`--geoip-db` for country, `--geoip-asn-db` for the network operator) and
+//! tags each client as e.g. `US AS15169` in the client list and audit log.
+//! Without the feature, or without a database, tagging is a no-op.
+//!
+//! Lookups are cached per IP; a client sending 100 packets a second costs
+//! one lookup, not 100.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Resource, Clone, Default)]
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    country: Option<Arc<maxminddb::Reader<Vec<u8>>>>,
+    #[cfg(feature = "geoip")]
+    asn: Option<Arc<maxminddb::Reader<Vec<u8>>>>,
+    cache: Arc<Mutex<HashMap<IpAddr, Option<String>>>>,
+}
+
+impl GeoIp {
+    #[cfg(feature = "geoip")]
+    pub fn open(
+        country: Option<&std::path::Path>,
+        asn: Option<&std::path::Path>,
+    ) -> Result<Self, maxminddb::MaxMindDBError> {
+        let open = |path: Option<&std::path::Path>| {
+            path.map(|path| maxminddb::Reader::open_readfile(path).map(Arc::new))
+                .transpose()
+        };
+        Ok(Self {
+            country: open(country)?,
+            asn: open(asn)?,
+            cache: Arc::default(),
+        })
+    }
+
+    /// A short tag like `US AS15169`, or None when nothing is known.
+    pub fn tag(&self, ip: IpAddr) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(ip).or_insert_with(|| self.lookup(ip)).clone()
+    }
+
+    /// Tags a `host:port` peer string as used throughout the server.
+    pub fn tag_peer(&self, peer: &str) -> Option<String> {
+        let ip = peer.parse::<std::net::SocketAddr>().ok()?.ip();
+        self.tag(ip)
+    }
+
+    #[cfg(feature = "geoip")]
+    fn lookup(&self, ip: IpAddr) -> Option<String> {
+        use maxminddb::geoip2;
+
+        let country = self.country.as_ref().and_then(|reader| {
+            let record: geoip2::Country = reader.lookup(ip).ok()?;
+            record.country?.iso_code.map(str::to_string)
+        });
+        let asn = self.asn.as_ref().and_then(|reader| {
+            let record: geoip2::Asn = reader.lookup(ip).ok()?;
+            record
+                .autonomous_system_number
+                .map(|number| format!("AS{}", number))
+        });
+
+        let parts: Vec<String> = country.into_iter().chain(asn).collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn lookup(&self, _ip: IpAddr) -> Option<String> {
+        None
+    }
+}
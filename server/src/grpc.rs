@@ -0,0 +1,144 @@
+//! gRPC control plane, with `--features grpc`: administration over a
+//! standard RPC protocol, apart from the UDP game traffic.
+//!
+//! `--grpc <addr>` serves the `Admin` service from `proto/admin.proto` with
+//! tonic: list sessions, kick, broadcast and metrics. Like the dashboard it
+//! works from the admin plane (see `admin`), so it reads the snapshot of at
+//! most a second ago; a kick or broadcast waits for the frame that carries
+//! it out and answers with the line the server logged, or `NOT_FOUND` for
+//! an address with no client.
+//!
+//! ```bash
+//! grpcurl -plaintext -import-path server/proto -proto admin.proto \
+//!     127.0.0.1:50051 admin.Admin/ListSessions
+//! ```
+//!
+//! Like the dashboard it has no authentication; keep it on loopback.
+
+use bevy::prelude::*;
+use std::io;
+use std::net::TcpListener;
+use std::thread;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::admin::{AdminCommand, AdminLink, broadcast_text};
+
+pub mod proto {
+    tonic::include_proto!("admin");
+}
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::{
+    BroadcastRequest, CommandReply, KickRequest, ListSessionsReply, ListSessionsRequest,
+    MetricsReply, MetricsRequest, RateSample, Session,
+};
+
+struct AdminService {
+    link: AdminLink,
+}
+
+impl AdminService {
+    async fn run(&self, command: AdminCommand) -> Result<Response<CommandReply>, Status> {
+        match self.link.call(command).await {
+            Some(Ok(message)) => Ok(Response::new(CommandReply { message })),
+            Some(Err(message)) => Err(Status::not_found(message)),
+            None => Err(Status::unavailable("server is shutting down")),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn list_sessions(
+        &self,
+        _request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsReply>, Status> {
+        let sessions = self
+            .link
+            .snapshot()
+            .clients
+            .into_iter()
+            .map(|client| Session {
+                addr: client.addr,
+                state: client.state.label().to_string(),
+                rtt_ms: client.rtt.map(|rtt| rtt as f64 * 1000.0),
+                loss: client.loss.map(f64::from),
+                silent_secs: client.silent_secs as f64,
+            })
+            .collect();
+        Ok(Response::new(ListSessionsReply { sessions }))
+    }
+
+    async fn kick(&self, request: Request<KickRequest>) -> Result<Response<CommandReply>, Status> {
+        let addr = request.into_inner().addr;
+        if addr.is_empty() {
+            return Err(Status::invalid_argument("addr is required"));
+        }
+        self.run(AdminCommand::Kick(addr)).await
+    }
+
+    async fn broadcast(
+        &self,
+        request: Request<BroadcastRequest>,
+    ) -> Result<Response<CommandReply>, Status> {
+        let text = broadcast_text(&request.into_inner().text).map_err(Status::invalid_argument)?;
+        self.run(AdminCommand::Broadcast(text)).await
+    }
+
+    async fn get_metrics(
+        &self,
+        _request: Request<MetricsRequest>,
+    ) -> Result<Response<MetricsReply>, Status> {
+        let snapshot = self.link.snapshot();
+        Ok(Response::new(MetricsReply {
+            port: snapshot.port as u32,
+            listening: snapshot.listening,
+            uptime_secs: snapshot.uptime_secs as f64,
+            players: snapshot.players as u32,
+            spectators: snapshot.spectators as u32,
+            packets_sent: snapshot.packets_sent,
+            packets_received: snapshot.packets_received,
+            samples: snapshot
+                .samples
+                .into_iter()
+                .map(|sample| RateSample {
+                    at_secs: sample.at as f64,
+                    packets_in: sample.packets_in,
+                    packets_out: sample.packets_out,
+                    players: sample.players as u32,
+                })
+                .collect(),
+        }))
+    }
+}
+
+/// Binds `addr` and starts serving on a thread of its own. Binding happens
+/// here, so a taken port is a startup error.
+pub fn serve(addr: &str, link: AdminLink) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!("gRPC API stopped: {}", e);
+                    return;
+                }
+            };
+            let served = Server::builder()
+                .add_service(AdminServer::new(AdminService { link }))
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await;
+            if let Err(e) = served {
+                warn!("gRPC API stopped: {}", e);
+            }
+        });
+    });
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! The server's window: header, log, PING and PING ALL, and the panels,
+//! overlays and controls drawn around them. Left out of a build without the
+//! `gui` feature, which always runs headless; see `headless`.
+//!
+//! The client list and its sparklines are in `client_list` and
+//! `sparklines`, the F2 to F5 panels and overlays in `history`, `replay`,
+//! `topology` and `profiler`, the listener controls in `listener`, the
+//! console's field in `console`, and the avatars and packet timeline in
+//! `avatars` and `timeline`.
+
+use bevy::prelude::*;
+use common::message::Message;
+use common::players::PlayerCount;
+use common::ui::accessibility::{KeyboardNavigationPlugin, LiveStatus};
+use common::ui::clipboard::ClipboardPlugin;
+use common::ui::fonts::FontsPlugin;
+use common::ui::screen_flash::ScreenFlashPlugin;
+use common::ui::text_input::TextInputPlugin;
+use common::ui::widgets::{ButtonFlash, UiTheme, WidgetsPlugin, header, log_text, spawn_button};
+
+use crate::avatars::{spawn_avatars, update_avatars};
+use crate::client_list::{
+    ClientListPage, ConnectionState, page_client_list, setup_client_list, update_client_rows,
+    update_player_count,
+};
+use crate::console::{handle_console_commands, read_console_input, setup_console};
+use crate::headless;
+use crate::history::{setup_history_panel, toggle_history_panel, update_history_panel};
+use crate::listener::{listener_controls, setup_listener_controls};
+use crate::peers::{Peer, PeerAddr, PeerStats};
+use crate::profiler::{setup_profiler_panel, toggle_profiler_panel, update_profiler_panel};
+use crate::replay::{MessageHistory, replay_panel_input, setup_replay_panel, update_replay_panel};
+use crate::send_delay::{DelayedSends, Reply, flush_delayed_sends};
+use crate::sparklines::update_sparklines;
+use crate::timeline::{PacketTimeline, draw_timeline, expire_timeline_marks, update_track_labels};
+use crate::topology::{TopologyView, draw_topology, toggle_topology};
+use crate::traffic::{setup_footer, update_footer};
+use crate::{NetworkState, ServerState};
+
+pub struct GuiPlugin {
+    /// `--font`
+    pub fonts: Vec<String>,
+    /// `--headless`: everything is still built, but nothing is drawn
+    pub headless: bool,
+    /// `--message-history`
+    pub message_history: usize,
+}
+
+impl Plugin for GuiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            headless::plugins(self.headless),
+            TextInputPlugin,
+            ClipboardPlugin,
+            KeyboardNavigationPlugin,
+            WidgetsPlugin,
+            ScreenFlashPlugin,
+            FontsPlugin {
+                paths: self.fonts.clone(),
+            },
+        ))
+        .insert_resource(MessageHistory::new(self.message_history))
+        .init_resource::<PacketTimeline>()
+        .init_resource::<TopologyView>()
+        .init_resource::<ClientListPage>()
+        .add_systems(
+            Startup,
+            (
+                setup_ui,
+                setup_client_list,
+                setup_footer,
+                setup_console,
+                setup_history_panel,
+                setup_replay_panel,
+                setup_profiler_panel,
+                setup_listener_controls,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                (ping_button_system, ping_all_button_system).before(flush_delayed_sends),
+                read_console_input.before(handle_console_commands),
+                update_log_ui,
+                expire_timeline_marks,
+                draw_timeline,
+                update_track_labels,
+                page_client_list,
+                update_client_rows.after(page_client_list),
+                update_sparklines.after(update_client_rows),
+                update_footer,
+                spawn_avatars,
+                update_avatars,
+                toggle_history_panel,
+                update_history_panel,
+                replay_panel_input,
+                update_replay_panel,
+                listener_controls.before(update_header),
+                update_header.after(update_player_count),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                toggle_profiler_panel,
+                update_profiler_panel,
+                toggle_topology,
+                draw_topology.after(update_avatars),
+            ),
+        );
+    }
+}
+
+#[derive(Component)]
+struct HeaderText;
+
+#[derive(Component)]
+struct LogText;
+
+#[derive(Component)]
+struct PingButton;
+
+#[derive(Component)]
+struct PingAllButton;
+
+fn header_text(network: &NetworkState, spectators: usize) -> String {
+    let listening = match network.data_port {
+        _ if !network.listening() => {
+            format!("Server stopped (last on 0.0.0.0:{})", network.port)
+        }
+        Some(data_port) => format!(
+            "Server listening on 0.0.0.0:{} (data on port {})",
+            network.port, data_port
+        ),
+        None => format!("Server listening on 0.0.0.0:{}", network.port),
+    };
+    match spectators {
+        0 => listening,
+        1 => format!("{}, 1 spectator", listening),
+        n => format!("{}, {} spectators", listening, n),
+    }
+}
+
+fn update_header(
+    network: Res<NetworkState>,
+    players: Res<PlayerCount>,
+    mut headers: Query<&mut Text, With<HeaderText>>,
+) {
+    if !players.is_changed() && !network.is_changed() {
+        return;
+    }
+    for mut text in headers.iter_mut() {
+        text.sections[0].value = header_text(&network, players.spectators);
+    }
+}
+
+fn setup_ui(mut commands: Commands, network: Res<NetworkState>, theme: Res<UiTheme>) {
+    commands.spawn(Camera2dBundle::default());
+
+    // Status Header
+    commands.spawn((
+        header(&theme, header_text(&network, 0)),
+        HeaderText,
+        LiveStatus,
+    ));
+
+    // Log Area
+    commands.spawn((log_text(&theme, "Waiting for client...\n"), LogText));
+
+    spawn_button(&mut commands, &theme, "PING", 120.0, PingButton);
+
+    // Left of PING
+    let width = 150.0;
+    let ping_all = spawn_button(&mut commands, &theme, "PING ALL", width, PingAllButton);
+    commands.entity(ping_all).insert(Style {
+        width: Val::Px(width),
+        height: Val::Px(theme.button_height),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(theme.button_margin),
+        right: Val::Px(theme.button_margin * 2.0 + 120.0),
+        ..default()
+    });
+}
+
+fn update_log_ui(
+    server_state: Res<ServerState>,
+    mut shown: Local<Option<u64>>,
+    mut query: Query<&mut Text, With<LogText>>,
+) {
+    let version = server_state.panel.version();
+    if *shown != Some(version) {
+        *shown = Some(version);
+        for mut text in query.iter_mut() {
+            text.sections[0].value = server_state.panel.lines().join("\n");
+        }
+    }
+}
+
+/// The presses on a button marked `B` this frame
+type Pressed<'w, 's, B> = Query<
+    'w,
+    's,
+    (&'static Interaction, &'static mut ButtonFlash),
+    (Changed<Interaction>, With<B>),
+>;
+
+fn ping_button_system(
+    mut interaction_query: Pressed<PingButton>,
+    time: Res<Time>,
+    mut delayed: ResMut<DelayedSends>,
+    server_state: Res<ServerState>,
+) {
+    for (interaction, mut flash) in interaction_query.iter_mut() {
+        if *interaction == Interaction::Pressed {
+            let addr = server_state.client_addr.clone();
+            if let Some(addr) = addr {
+                let pong = Message::Pong {
+                    seq: server_state.last_ping,
+                };
+                let pong = pong.to_string();
+                delayed.push(Reply::Pong, pong.as_bytes(), &addr, time.elapsed_seconds());
+                flash.trigger();
+                server_state.log.push(format!("[Tx]: Pong to {}", addr));
+            } else {
+                server_state
+                    .log
+                    .push("[Error]: No client connected".to_string());
+            }
+        }
+    }
+}
+
+/// PING replies to whoever sent gameplay last; PING ALL sends a Pong to
+/// every client that is still connected, spectators included, answering
+/// each one's last Ping.
+fn ping_all_button_system(
+    mut interaction_query: Pressed<PingAllButton>,
+    time: Res<Time>,
+    peers: Query<(&PeerAddr, &PeerStats), With<Peer>>,
+    mut delayed: ResMut<DelayedSends>,
+    server_state: Res<ServerState>,
+) {
+    for (interaction, mut flash) in interaction_query.iter_mut() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let now = time.elapsed_seconds();
+        let mut sent = 0;
+        for (PeerAddr(addr), stats) in peers.iter() {
+            if stats.state != ConnectionState::Disconnected {
+                let pong = Message::Pong {
+                    seq: stats.last_ping,
+                };
+                delayed.push(Reply::Pong, pong.to_string().as_bytes(), addr, now);
+                sent += 1;
+            }
+        }
+        if sent > 0 {
+            flash.trigger();
+            server_state
+                .log
+                .push(format!("[Tx]: Pong to {} clients", sent));
+        } else {
+            server_state
+                .log
+                .push("[Error]: No client connected".to_string());
+        }
+    }
+}
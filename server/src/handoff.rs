@@ -0,0 +1,110 @@
+//! Session handoff: moving connected clients to another server instance,
+//! to rebalance a fleet or to restart one for an update.
+//!
+//! The console's `/handoff <addr|all> <host:port>` sends each client
+//! `Handoff addr=<host:port> token=<token>` and ends its session here as
+//! `handed_off`. The client connects to that address at once and presents
+//! the token in a Resume. The token is a `common::ticket` signed with
+//! `--handoff-key`, so any instance started with the same key honours it,
+//! once and for [`HANDOFF_TTL_SECS`]: the session it starts is welcomed as
+//! `resumed` and needs no connect ticket. Stats and the session summary
+//! start over on the new instance; only the client sees one connection.
+
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use common::shared_rng::SharedRng;
+use common::ticket;
+use std::collections::HashMap;
+
+use crate::NetworkState;
+use crate::peers::PeersByAddr;
+
+/// How long a handoff token is good for; the client reconnects at once
+const HANDOFF_TTL_SECS: u64 = 30;
+
+#[derive(Resource)]
+pub struct Handoffs {
+    /// `--handoff-key`; without one we neither hand off nor honour handoffs
+    key: Option<String>,
+    /// Handoffs already honoured, by client id, and when their tokens expire
+    redeemed: HashMap<u64, u64>,
+    /// Draws the client id of each token
+    ids: SharedRng,
+}
+
+impl Handoffs {
+    pub fn new(key: Option<String>) -> Self {
+        Self {
+            key,
+            redeemed: HashMap::new(),
+            ids: SharedRng::new(SharedRng::random_seed()),
+        }
+    }
+
+    /// Whether `token` is a handoff signed with our key, unexpired and not
+    /// used before. It can't be used again.
+    pub fn accept(&mut self, token: &str) -> bool {
+        let Some(key) = &self.key else {
+            return false;
+        };
+        let now = ticket::unix_now();
+        let Ok(handoff) = ticket::verify(key.as_bytes(), token, now) else {
+            return false;
+        };
+        self.redeemed.retain(|_, expires| *expires > now);
+        self.redeemed
+            .insert(handoff.client_id, handoff.expires)
+            .is_none()
+    }
+}
+
+/// Hands the client at `which`, or every connected one for `all`, off to
+/// `target`. The lines for the log.
+pub fn hand_off(
+    which: &str,
+    target: &str,
+    handoffs: &mut Handoffs,
+    network: &NetworkState,
+    peers: &mut PeersByAddr,
+) -> Vec<String> {
+    let Some(key) = handoffs.key.clone() else {
+        return vec![
+            "[Error]: /handoff needs --handoff-key, the same as the target server's".to_string(),
+        ];
+    };
+    if target
+        .rsplit_once(':')
+        .is_none_or(|(_, port)| port.parse::<u16>().is_err())
+    {
+        return vec![format!("[Error]: expected <host>:<port>, got {}", target)];
+    }
+    let addrs = if which == "all" {
+        peers.addrs()
+    } else if peers.contains(which) {
+        vec![which.to_string()]
+    } else {
+        return vec![format!("[Error]: no client {}", which)];
+    };
+    let mut lines = Vec::new();
+    for addr in addrs {
+        let Some((mut stats, session)) = peers.get_mut(&addr) else {
+            continue;
+        };
+        // Only a live session has anything to carry on
+        if session.open.is_none() || session.suspended_since.is_some() || stats.departed.is_some() {
+            continue;
+        }
+        let expires = ticket::unix_now() + HANDOFF_TTL_SECS;
+        let token = ticket::issue(key.as_bytes(), handoffs.ids.next_u64(), expires);
+        let message = format!("Handoff addr={} token={}", target, token);
+        network.send_control(message.as_bytes(), &addr);
+        // `update_connection_states` ends the session and reports it
+        stats.departed = Some(DisconnectReason::HandedOff);
+        // The token stays out of the log
+        lines.push(format!("[Tx control]: Handoff addr={} to {}", target, addr));
+    }
+    if lines.is_empty() {
+        lines.push("[Info]: no connected client to hand off".to_string());
+    }
+    lines
+}
@@ -0,0 +1,107 @@
+//! Running without a window.
+//!
+//! With `--headless` the server keeps its full plugin set, so every system
+//! still finds the resources it expects, but opens no window and creates no
+//! GPU device; a fixed-rate loop drives the frames instead of winit. The UI
+//! is still built, just never drawn. A build without the `gui` feature has
+//! no UI to build: it runs on Bevy's minimal plugins, always headless. In
+//! place of the window the server prints a status line to stdout every
+//! [`STATUS_INTERVAL_SECS`]:
+//!
+//! ```text
+//! status port=12345 players=3 spectators=1 packets_sent=812 packets_received=790
+//! ```
+//!
+//! `fleet` reads these to show totals across its instances. Every line of
+//! the window's log (received messages, connections, console replies) is
+//! printed too, as it is added: `main` gives the log a
+//! `common::log_sink::StdoutSink`.
+
+use bevy::app::{PluginGroupBuilder, ScheduleRunnerPlugin};
+#[cfg(not(feature = "gui"))]
+use bevy::diagnostic::DiagnosticsPlugin;
+#[cfg(not(feature = "gui"))]
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+#[cfg(feature = "gui")]
+use bevy::render::RenderPlugin;
+#[cfg(feature = "gui")]
+use bevy::render::settings::WgpuSettings;
+#[cfg(feature = "gui")]
+use bevy::window::ExitCondition;
+#[cfg(feature = "gui")]
+use bevy::winit::WinitPlugin;
+use common::players::PlayerCount;
+use std::time::Duration;
+
+use crate::metrics::{Metric, MetricsSample};
+use crate::{Args, NetworkState};
+
+pub const STATUS_INTERVAL_SECS: f32 = 5.0;
+
+/// Frames per second when no window paces the loop
+const TICK_HZ: f64 = 60.0;
+
+#[cfg(feature = "gui")]
+pub fn plugins(headless: bool) -> PluginGroupBuilder {
+    let plugins = DefaultPlugins.build();
+    if !headless {
+        return plugins;
+    }
+    plugins
+        .set(WindowPlugin {
+            primary_window: None,
+            exit_condition: ExitCondition::DontExit,
+            close_when_requested: false,
+        })
+        .set(RenderPlugin {
+            render_creation: WgpuSettings {
+                backends: None,
+                ..default()
+            }
+            .into(),
+            ..default()
+        })
+        .disable::<WinitPlugin>()
+        .add(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+            1.0 / TICK_HZ,
+        )))
+}
+
+/// The frame loop, the log and diagnostics, and nothing that draws.
+#[cfg(not(feature = "gui"))]
+pub fn plugins() -> PluginGroupBuilder {
+    MinimalPlugins
+        .build()
+        .set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+            1.0 / TICK_HZ,
+        )))
+        .add(LogPlugin::default())
+        .add(DiagnosticsPlugin)
+}
+
+pub fn is_headless(args: Res<Args>) -> bool {
+    args.headless
+}
+
+pub fn print_status(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    metrics: Res<MetricsSample>,
+    players: Res<PlayerCount>,
+    mut next_report: Local<f32>,
+) {
+    let now = time.elapsed_seconds();
+    if now < *next_report {
+        return;
+    }
+    *next_report = now + STATUS_INTERVAL_SECS;
+    println!(
+        "status port={} players={} spectators={} packets_sent={} packets_received={}",
+        network.port,
+        players.connected,
+        players.spectators,
+        metrics.total(Metric::PacketsSent),
+        metrics.total(Metric::PacketsReceived),
+    );
+}
@@ -0,0 +1,217 @@
+//! Connection lifecycle history.
+//!
+//! Every connect, idle, resume, timeout and disconnect is kept with its timestamp in
+//! [`NetworkEventHistory`], so a flaky session can be reconstructed after the
+//! fact: F2 toggles a panel with the latest events, the console's
+//! `/history` prints them, and `/history <addr>` heads one client's recent
+//! packets with its latest event.
+
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use std::collections::VecDeque;
+
+/// Older events are dropped past this many.
+const MAX_EVENTS: usize = 256;
+/// Rows shown in the F2 panel
+#[cfg(feature = "gui")]
+const PANEL_ROWS: usize = 15;
+
+/// Clients whose latest lifecycle event is not a timeout, disconnect or
+/// rejection.
+pub const CONNECTED_CLIENTS: DiagnosticPath =
+    DiagnosticPath::const_new("network/connected_clients");
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetworkEventKind {
+    /// Handshake Hello received
+    Connected,
+    /// Only heartbeats for longer than `--idle-timeout`
+    Idle,
+    /// Gameplay traffic again after idling or timing out
+    Resumed,
+    /// A Resume continued the session held since a timeout
+    SessionResumed,
+    /// A Resume with another instance's handoff token started a session
+    HandedIn,
+    /// Nothing heard for longer than `--disconnect-timeout`
+    TimedOut,
+    /// The connection was ended on purpose, by the client or by us
+    Disconnected(DisconnectReason),
+    /// Hello with a protocol version we don't support
+    Rejected,
+}
+
+impl NetworkEventKind {
+    pub fn label(self) -> String {
+        match self {
+            NetworkEventKind::Connected => "connected".to_string(),
+            NetworkEventKind::Idle => "idle".to_string(),
+            NetworkEventKind::Resumed => "resumed".to_string(),
+            NetworkEventKind::SessionResumed => "resumed its session".to_string(),
+            NetworkEventKind::HandedIn => "handed off from another server".to_string(),
+            NetworkEventKind::TimedOut => "timed out".to_string(),
+            NetworkEventKind::Disconnected(reason) => format!("disconnected ({})", reason),
+            NetworkEventKind::Rejected => "rejected (version mismatch)".to_string(),
+        }
+    }
+}
+
+pub struct NetworkEvent {
+    /// Seconds since startup
+    pub at: f32,
+    pub addr: String,
+    pub kind: NetworkEventKind,
+}
+
+impl NetworkEvent {
+    pub fn describe(&self) -> String {
+        format!("{:>8.2}s  {}  {}", self.at, self.addr, self.kind.label())
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct NetworkEventHistory {
+    events: VecDeque<NetworkEvent>,
+}
+
+impl NetworkEventHistory {
+    pub fn record(&mut self, at: f32, addr: &str, kind: NetworkEventKind) {
+        self.events.push_back(NetworkEvent {
+            at,
+            addr: addr.to_string(),
+            kind,
+        });
+        if self.events.len() > MAX_EVENTS {
+            self.events.pop_front();
+        }
+    }
+
+    /// Oldest first.
+    pub fn events(&self) -> impl DoubleEndedIterator<Item = &NetworkEvent> {
+        self.events.iter()
+    }
+
+    pub fn for_peer<'a>(
+        &'a self,
+        addr: &'a str,
+    ) -> impl DoubleEndedIterator<Item = &'a NetworkEvent> + 'a {
+        self.events.iter().filter(move |event| event.addr == addr)
+    }
+
+    fn connected_clients(&self) -> usize {
+        let mut latest: Vec<(&str, NetworkEventKind)> = Vec::new();
+        for event in &self.events {
+            match latest.iter_mut().find(|(addr, _)| *addr == event.addr) {
+                Some(entry) => entry.1 = event.kind,
+                None => latest.push((&event.addr, event.kind)),
+            }
+        }
+        latest
+            .iter()
+            .filter(|(_, kind)| {
+                !matches!(
+                    kind,
+                    NetworkEventKind::TimedOut
+                        | NetworkEventKind::Disconnected(_)
+                        | NetworkEventKind::Rejected
+                )
+            })
+            .count()
+    }
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct HistoryPanel;
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct HistoryText;
+
+#[cfg(feature = "gui")]
+pub fn setup_history_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(50.0),
+                    left: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            HistoryPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Connection history (F2 to close)",
+                TextStyle {
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                HistoryText,
+            ));
+        });
+}
+
+#[cfg(feature = "gui")]
+pub fn toggle_history_panel(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut panels: Query<&mut Visibility, With<HistoryPanel>>,
+) {
+    if !keys.just_pressed(KeyCode::F2) {
+        return;
+    }
+    for mut visibility in panels.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+pub fn measure_connected_clients(history: Res<NetworkEventHistory>, mut diagnostics: Diagnostics) {
+    let connected = history.connected_clients();
+    diagnostics.add_measurement(&CONNECTED_CLIENTS, || connected as f64);
+}
+
+#[cfg(feature = "gui")]
+pub fn update_history_panel(
+    history: Res<NetworkEventHistory>,
+    mut texts: Query<&mut Text, With<HistoryText>>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+    let mut lines: Vec<String> = history
+        .events()
+        .rev()
+        .take(PANEL_ROWS)
+        .map(NetworkEvent::describe)
+        .collect();
+    lines.reverse();
+    if lines.is_empty() {
+        lines.push("No events yet".to_string());
+    }
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,113 @@
+//! Low-power idling for a server nobody is using.
+//!
+//! Once no peer has been connected and nobody has touched the window for
+//! `--idle-after` seconds, the server stops running flat out: at the end of
+//! each frame it sleeps for up to `--idle-wait-ms`. A datagram arriving on
+//! either socket wakes it at once through [`Wakeup`], which the receive
+//! threads signal, so the first packet of a new client is handled as fast
+//! as ever. Keyboard and mouse input is only noticed on the next frame, up
+//! to one wait later. Works the same with and without `--headless`; a
+//! build without `gui` has no window to touch, so only peers keep it awake.
+
+#[cfg(feature = "gui")]
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::ServerState;
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerStats};
+
+/// Signalled by the receive threads whenever a datagram is queued.
+#[derive(Clone, Default)]
+pub struct Wakeup(Arc<(Mutex<bool>, Condvar)>);
+
+impl Wakeup {
+    pub fn notify(&self) {
+        let (pending, condvar) = &*self.0;
+        *pending.lock().unwrap() = true;
+        condvar.notify_one();
+    }
+
+    /// Returns early if a datagram arrived since the last wait.
+    fn wait(&self, timeout: Duration) {
+        let (pending, condvar) = &*self.0;
+        let guard = pending.lock().unwrap();
+        let (mut guard, _) = condvar
+            .wait_timeout_while(guard, timeout, |pending| !*pending)
+            .unwrap();
+        *guard = false;
+    }
+}
+
+#[derive(Resource)]
+pub struct IdleMode {
+    /// `--idle-after` in seconds; 0 never idles
+    after: f32,
+    wait: Duration,
+    wakeup: Wakeup,
+    last_active: f32,
+    pub idle: bool,
+}
+
+impl IdleMode {
+    pub fn new(after: f32, wait: Duration, wakeup: Wakeup) -> Self {
+        Self {
+            after,
+            wait,
+            wakeup,
+            last_active: 0.0,
+            idle: false,
+        }
+    }
+}
+
+pub fn update_idle_mode(
+    time: Res<Time>,
+    #[cfg(feature = "gui")] keys: Res<ButtonInput<KeyCode>>,
+    #[cfg(feature = "gui")] mouse: Res<ButtonInput<MouseButton>>,
+    #[cfg(feature = "gui")] mut motion: EventReader<MouseMotion>,
+    peers: Query<&PeerStats, With<Peer>>,
+    mut idle: ResMut<IdleMode>,
+    server_state: Res<ServerState>,
+) {
+    if idle.after <= 0.0 {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    #[cfg(feature = "gui")]
+    let touched = keys.get_pressed().next().is_some()
+        || mouse.get_pressed().next().is_some()
+        || motion.read().count() > 0;
+    #[cfg(not(feature = "gui"))]
+    let touched = false;
+    let connected = peers
+        .iter()
+        .any(|stats| stats.state != ConnectionState::Disconnected);
+    if touched || connected {
+        idle.last_active = now;
+    }
+    let should_idle = now - idle.last_active >= idle.after;
+    if should_idle == idle.idle {
+        return;
+    }
+    idle.idle = should_idle;
+    server_state.log.push(if should_idle {
+        format!(
+            "[Idle]: nobody connected for {:.0}s, dropping to low power",
+            idle.after
+        )
+    } else if connected {
+        "[Idle]: woken by traffic".to_string()
+    } else {
+        "[Idle]: woken by input".to_string()
+    });
+}
+
+/// Runs last in the frame, so the sleep stands in for the rest of it.
+pub fn idle_wait(idle: Res<IdleMode>) {
+    if idle.idle {
+        idle.wakeup.wait(idle.wait);
+    }
+}
@@ -0,0 +1,192 @@
+//! Inbound packet decoding.
+//!
+//! A batch is decoded inline, in arrival order, and the results are merged
+//! into ECS state by `handle_network_messages`. Decoding is 5 to 7% of the
+//! time spent handling a packet in a release build, so spreading it over
+//! the compute task pool could save at most that, less the cost of the
+//! tasks; see "Frame Budget" in the README for the measurements.
+//!
+//! Gameplay decodes to a typed `common::message::Message`, whether it came
+//! as text or bincode; the handshake and the other protocol messages stay
+//! text. A datagram that looks encoded but doesn't decode is `Malformed`.
+
+use common::message::{Message, Payload};
+
+use crate::Stream;
+
+/// A datagram as the socket thread saw it.
+pub struct RawPacket {
+    pub bytes: Vec<u8>,
+    pub addr: String,
+    pub stream: Stream,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageKind {
+    Heartbeat,
+    Hello,
+    Resume,
+    Disconnect,
+    #[cfg(feature = "chat")]
+    Chat,
+    /// `Fx`: a cosmetic event to fan out; see `effects`
+    Effect,
+    /// `Pos`: where the client's sprite is; see `sprites`
+    Position,
+    /// `REL` and `REL_ACK`; see `accounts`
+    Reliable,
+    /// `ReplNack`: a client lost replication messages
+    ReplicationNack,
+    Gameplay,
+    /// Bincode that doesn't decode; `text` says why
+    Malformed,
+}
+
+pub struct Decoded {
+    pub addr: String,
+    pub stream: Stream,
+    pub size: usize,
+    pub text: String,
+    pub kind: MessageKind,
+    /// The typed gameplay message, if it is one
+    pub message: Option<Message>,
+}
+
+fn decode(packet: RawPacket) -> Decoded {
+    let (text, message) = match Payload::read(&packet.bytes) {
+        Ok(Payload::Message(message)) => (message.to_string(), Some(message)),
+        Ok(Payload::Text(text)) => {
+            let message = Message::parse(&text);
+            (text.into_owned(), message)
+        }
+        Err(e) => {
+            return Decoded {
+                addr: packet.addr,
+                stream: packet.stream,
+                size: packet.bytes.len(),
+                text: e.to_string(),
+                kind: MessageKind::Malformed,
+                message: None,
+            };
+        }
+    };
+    let kind = match &message {
+        #[cfg(feature = "chat")]
+        Some(Message::Chat { .. }) => MessageKind::Chat,
+        Some(Message::Pos(_)) => MessageKind::Position,
+        Some(_) => MessageKind::Gameplay,
+        None => kind_of(&text),
+    };
+    Decoded {
+        addr: packet.addr,
+        stream: packet.stream,
+        size: packet.bytes.len(),
+        text,
+        kind,
+        message,
+    }
+}
+
+/// The kind of a text message, by its first word
+fn kind_of(text: &str) -> MessageKind {
+    // Heartbeats may carry fields after the kind, e.g. `Heartbeat rtt_ms=12.5`
+    match text.split(' ').next().unwrap_or("") {
+        "Heartbeat" => MessageKind::Heartbeat,
+        "Hello" => MessageKind::Hello,
+        "Resume" => MessageKind::Resume,
+        "Disconnect" => MessageKind::Disconnect,
+        #[cfg(feature = "chat")]
+        "Chat" => MessageKind::Chat,
+        "Fx" => MessageKind::Effect,
+        "Pos" => MessageKind::Position,
+        "REL" | "REL_ACK" => MessageKind::Reliable,
+        "ReplNack" => MessageKind::ReplicationNack,
+        _ => MessageKind::Gameplay,
+    }
+}
+
+/// Decodes a batch, in arrival order.
+pub fn decode_batch(batch: Vec<RawPacket>) -> Vec<Decoded> {
+    batch.into_iter().map(decode).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::handlers::PositionUpdate;
+
+    fn packet(bytes: &[u8]) -> RawPacket {
+        RawPacket {
+            bytes: bytes.to_vec(),
+            addr: "127.0.0.1:5000".to_string(),
+            stream: Stream::Data,
+        }
+    }
+
+    #[test]
+    fn text_and_bincode_decode_to_the_same_message() {
+        let ping = Message::Ping { seq: 3 };
+        for bytes in [b"Ping 3".to_vec(), ping.encode()] {
+            let decoded = decode(packet(&bytes));
+            assert_eq!(decoded.kind, MessageKind::Gameplay);
+            assert_eq!(decoded.message, Some(ping.clone()));
+            assert_eq!(decoded.text, "Ping 3");
+        }
+    }
+
+    #[test]
+    fn positions_are_typed() {
+        let update = PositionUpdate {
+            seq: 2,
+            x: 1.5,
+            y: -4.0,
+        };
+        let decoded = decode(packet(&Message::Pos(update).encode()));
+        assert_eq!(decoded.kind, MessageKind::Position);
+        assert_eq!(decoded.message, Some(Message::Pos(update)));
+        // Still a Pos, for the handler to warn about
+        let decoded = decode(packet(b"Pos two 1.5"));
+        assert_eq!(decoded.kind, MessageKind::Position);
+        assert_eq!(decoded.message, None);
+    }
+
+    #[test]
+    fn protocol_messages_stay_text() {
+        let decoded = decode(packet(b"Hello version=3"));
+        assert_eq!(decoded.kind, MessageKind::Hello);
+        assert_eq!(decoded.message, None);
+        assert_eq!(decoded.text, "Hello version=3");
+    }
+
+    #[test]
+    fn a_batch_keeps_its_arrival_order() {
+        let batch = (1..=40).map(|seq| {
+            let mut packet = packet(&Message::Ping { seq }.encode());
+            packet.addr = format!("127.0.0.1:{}", 5000 + seq % 3);
+            packet
+        });
+        let seqs: Vec<_> = decode_batch(batch.collect())
+            .into_iter()
+            .map(|decoded| match decoded.message {
+                Some(Message::Ping { seq }) => seq,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(seqs, (1..=40).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn broken_bincode_is_malformed() {
+        let mut bytes = Message::Chat {
+            text: "hi".to_string(),
+        }
+        .encode();
+        bytes.truncate(bytes.len() - 1);
+        for bytes in [bytes, b"\0\0\0\x63".to_vec()] {
+            let decoded = decode(packet(&bytes));
+            assert_eq!(decoded.kind, MessageKind::Malformed);
+            assert_eq!(decoded.message, None);
+            assert_eq!(decoded.size, bytes.len());
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Listener controls: STOP, START and RESTART buttons above PING, with the
+//! port to listen on next to them.
+//!
+//! STOP tells every connected client the server is shutting down (the same
+//! Disconnect they get when the window closes, so they retry), closes the
+//! sockets and ends the receive threads. START binds the port in the field
+//! again, and RESTART does both, so typing a new port and pressing RESTART
+//! (or Enter in the field) moves the server there. `--data-port`, if given,
+//! stays where it is. A port that can't be bound leaves the server stopped,
+//! with the error in the log.
+
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use common::ui::text_input::{TextInput, TextInputSubmitted, spawn_text_input};
+use common::ui::widgets::{ButtonFlash, ThemedButton, UiTheme};
+
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerAddr, PeerStats};
+use crate::{NetworkState, ServerState};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListenerAction {
+    Stop,
+    Start,
+    Restart,
+}
+
+#[derive(Component)]
+pub struct ListenerButton(pub ListenerAction);
+
+#[derive(Component)]
+pub struct PortInput;
+
+pub fn setup_listener_controls(
+    mut commands: Commands,
+    network: Res<NetworkState>,
+    theme: Res<UiTheme>,
+) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(theme.button_margin * 2.0 + theme.button_height),
+                right: Val::Px(theme.button_margin),
+                column_gap: Val::Px(6.0),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(TextBundle::from_section(
+                "Port",
+                TextStyle {
+                    font_size: theme.log_font_size,
+                    color: theme.text_color,
+                    ..default()
+                },
+            ));
+            spawn_text_input(
+                row,
+                TextInput::new("port").with_value(network.port.to_string()),
+                Val::Px(70.0),
+                PortInput,
+            );
+            for (label, action) in [
+                ("STOP", ListenerAction::Stop),
+                ("START", ListenerAction::Start),
+                ("RESTART", ListenerAction::Restart),
+            ] {
+                row.spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(84.0),
+                            height: Val::Px(28.0),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        background_color: theme.button_color.into(),
+                        ..default()
+                    },
+                    ThemedButton,
+                    ButtonFlash::default(),
+                    ListenerButton(action),
+                ))
+                .with_children(|button| {
+                    button.spawn(TextBundle::from_section(
+                        label,
+                        TextStyle {
+                            font_size: 16.0,
+                            color: theme.text_color,
+                            ..default()
+                        },
+                    ));
+                });
+            }
+        });
+}
+
+fn parse_port(value: &str) -> Result<u16, String> {
+    match value.trim().parse::<u16>() {
+        Ok(0) | Err(_) => Err(format!("'{}' is not a port to listen on", value.trim())),
+        Ok(port) => Ok(port),
+    }
+}
+
+pub fn listener_controls(
+    mut buttons: Query<(&Interaction, &ListenerButton, &mut ButtonFlash), Changed<Interaction>>,
+    mut submitted: EventReader<TextInputSubmitted>,
+    inputs: Query<&TextInput, With<PortInput>>,
+    mut network: ResMut<NetworkState>,
+    mut peers: Query<(&PeerAddr, &mut PeerStats), With<Peer>>,
+    server_state: Res<ServerState>,
+) {
+    let mut action = None;
+    for (interaction, button, mut flash) in buttons.iter_mut() {
+        if *interaction == Interaction::Pressed {
+            flash.trigger();
+            action = Some(button.0);
+        }
+    }
+    for event in submitted.read() {
+        if inputs.get(event.entity).is_ok() {
+            action = Some(ListenerAction::Restart);
+        }
+    }
+    let Some(action) = action else {
+        return;
+    };
+
+    let mut log = Vec::new();
+    let listening = network.listening();
+    match action {
+        ListenerAction::Start if listening => {
+            log.push(format!(
+                "[Error]: Already listening on port {}",
+                network.port
+            ));
+        }
+        ListenerAction::Stop if !listening => {
+            log.push("[Error]: Not listening".to_string());
+        }
+        _ => {
+            if listening {
+                let message = DisconnectReason::ServerShutdown.message();
+                for (PeerAddr(addr), mut stats) in peers.iter_mut() {
+                    if stats.state != ConnectionState::Disconnected {
+                        network.send_control(message.as_bytes(), addr);
+                        // `update_connection_states` ends the session and reports it
+                        stats.departed = Some(DisconnectReason::ServerShutdown);
+                        log.push(format!("[Tx control]: {} to {}", message, addr));
+                    }
+                }
+                network.stop_listening();
+                log.push(format!(
+                    "[Info]: Stopped listening on port {}",
+                    network.port
+                ));
+            }
+            if action != ListenerAction::Stop {
+                let port = inputs
+                    .iter()
+                    .next()
+                    .map_or(Ok(network.port), |input| parse_port(&input.value));
+                match port {
+                    Ok(port) => match network.listen(port) {
+                        Ok(()) => log.push(format!("[Info]: Listening on port {}", port)),
+                        Err(e) => log.push(format!("[Error]: {}", e)),
+                    },
+                    Err(reason) => log.push(format!("[Error]: {}", reason)),
+                }
+            }
+        }
+    }
+    for line in log {
+        server_state.log.push(line);
+    }
+}
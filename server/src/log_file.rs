@@ -0,0 +1,55 @@
+//! `--log-file <path>`: the window's log, appended to a file.
+//!
+//! Unlike the panel, which keeps 20 lines, the file keeps every line, each
+//! prefixed with its unix time:
+//!
+//! ```text
+//! 1767225600.125 [Info]: 127.0.0.1:50211 is now idle
+//! ```
+//!
+//! Lines go to a writer thread, so a slow disk never stalls a frame.
+
+use bevy::prelude::*;
+use common::log_sink::{LogEntry, LogSink};
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+pub struct LogFile {
+    lines: Sender<String>,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (lines, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let mut writer = BufWriter::new(file);
+            // Flushed whenever the channel runs dry, so the file stays current
+            while let Ok(line) = receiver.recv() {
+                let written = std::iter::once(line)
+                    .chain(receiver.try_iter())
+                    .try_for_each(|line| writeln!(writer, "{}", line))
+                    .and_then(|()| writer.flush());
+                if let Err(e) = written {
+                    warn!("Log file write failed, stopping the log file: {}", e);
+                    break;
+                }
+            }
+        });
+        Ok(Self { lines })
+    }
+}
+
+impl LogSink for LogFile {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        // A closed channel means the writer gave up; it already said why
+        let _ = self.lines.send(format!("{:.3} {}", entry.at, entry.line()));
+    }
+}
@@ -1,207 +1,1580 @@
+use bevy::app::AppExit;
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use std::net::UdpSocket;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "gui")]
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use bevy_udp_net::{NetworkSocket, Receive, Receiver};
 use clap::Parser;
+use common::bandwidth::{BandwidthMeter, BandwidthStats, METER_ORDER};
+use common::codec::{ALL_TEXT, CODEC_ORDER, ChannelCodecs, Codecs};
+use common::compression::{
+    COMPRESSION_ORDER, CompressionPlugin, CompressionStats, DictCompression,
+};
+use common::disconnect::{DisconnectReason, Disconnected};
+use common::error::{NetError, NetErrorPlugin, NetErrorSink};
+use common::handlers::{Disconnect, Heartbeat, NetMessage, ServerInfoRequest};
+use common::identity::{ServerIdentity, SignReplies};
+use common::legacy::{LEGACY_ORDER, LegacyText};
+use common::log_sink::{LogSinks, RecentLines, StdoutSink};
+use common::message::Message;
+use common::middleware::{PacketTrace, Pipeline, TRACE_ORDER};
+use common::offload::{GRO_BUFFER, UdpOffload};
+use common::packet_log::{PACKET_LOG_ORDER, PacketLog, Side};
+use common::players::PlayerCount;
+use common::protocol::{self, MIN_SUPPORTED_VERSION, PROTOCOL_VERSION};
+use common::replicate::{
+    REPLICATION_BUILD, ReplicateResourceExt, ReplicationJoin, ReplicationNack, ReplicationOutgoing,
+    ReplicationPlugin, ReplicationResync, ReplicationRole,
+};
+use common::self_check::{report, run_self_checks};
+use common::session::message_kind;
+use common::shared_rng::SharedRng;
+use common::smoke_test;
+use common::storage::{self, Accounts, Bans, CrashReports, History};
+use common::validator::{ProtocolValidator, VALIDATOR_ORDER};
 
-#[derive(Parser, Resource, Debug, Clone)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Port to listen on
-    #[arg(short, long, default_value_t = 12345)]
-    port: u16,
+mod access;
+mod accounts;
+#[cfg(any(feature = "dashboard", feature = "grpc"))]
+mod admin;
+mod announcements;
+mod archive;
+mod args;
+mod audit;
+#[cfg(feature = "gui")]
+mod avatars;
+mod bots;
+#[cfg(feature = "chat")]
+mod chat;
+mod client_config;
+mod client_list;
+mod console;
+mod crash_reports;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod effects;
+mod geoip;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "gui")]
+mod gui;
+mod handoff;
+mod headless;
+mod history;
+mod idle;
+mod inbound;
+#[cfg(feature = "gui")]
+mod listener;
+mod log_file;
+mod metrics;
+mod observer;
+mod peers;
+mod profiler;
+mod query;
+mod receive_queue;
+mod recordings;
+#[cfg(feature = "gui")]
+mod replay;
+mod resume;
+mod send_delay;
+mod send_rate;
+mod session;
+#[cfg(feature = "chat")]
+mod shards;
+mod simulation;
+mod sparklines;
+mod sprites;
+mod tcp;
+mod tickets;
+#[cfg(feature = "gui")]
+mod timeline;
+#[cfg(feature = "gui")]
+mod topology;
+#[cfg(feature = "gui")]
+mod traffic;
+mod validation;
+mod webhook;
+
+use access::AccessControl;
+use accounts::{
+    LoginLimiter, ReliableReceived, finish_logins, flush_reliable, handle_reliable,
+    reset_reliable_on_join, track_account_activity,
+};
+#[cfg(any(feature = "dashboard", feature = "grpc"))]
+use admin::{AdminPlane, apply_admin_commands, publish_admin_snapshot};
+use announcements::{Announcements, send_announcements, send_motd_on_join};
+use archive::{
+    ARCHIVE_BYTES, ClientArchive, PEER_ENTITIES, PEER_STATE_BYTES, archive_inactive_peers,
+};
+use args::Args;
+use audit::{AuditLog, Decision};
+use bots::{report_throughput, spawn_bots, spawn_flooders};
+#[cfg(feature = "chat")]
+use chat::{ChatReceived, Moderation, forget_chat_senders, moderate_chat, send_chat_history};
+use client_config::{ClientConfig, push_config_on_join, push_config_updates};
+use client_list::{
+    ClientList, ConnectionState, prune_traffic, update_connection_states, update_player_count,
+};
+use console::{ConsoleCommand, handle_console_commands};
+use effects::{EffectReceived, EffectRelay, forget_effect_senders, relay_effects};
+use geoip::GeoIp;
+#[cfg(feature = "gui")]
+use gui::GuiPlugin;
+use handoff::Handoffs;
+use headless::{is_headless, print_status};
+use history::{
+    CONNECTED_CLIENTS, NetworkEventHistory, NetworkEventKind, measure_connected_clients,
+};
+use idle::{IdleMode, Wakeup, idle_wait, update_idle_mode};
+use inbound::{MessageKind, RawPacket, decode_batch};
+use log_file::LogFile;
+use metrics::{Metric, MetricsRegistry, MetricsSample, sample_metrics};
+use observer::Observer;
+use peers::{Peer, PeerAddr, PeerIndex, PeerStats, PeersByAddr, spawn_peers};
+use profiler::{Stage, TickProfile, flush_tick_profile};
+use query::{QueryInfo, is_query, publish_player_count};
+use receive_queue::ReceiveQueue;
+use recordings::{
+    InputReceived, SessionRecordings, record_inputs, record_state_changes, start_recordings,
+};
+#[cfg(feature = "gui")]
+use replay::MessageHistory;
+use resume::{Resumptions, expire_suspended_sessions};
+use send_delay::{DelayedSends, Reply, flush_delayed_sends};
+use send_rate::{SendRate, SendRates, flush_send_rates, update_send_rates};
+use session::{Sessions, end_sessions_on_exit};
+#[cfg(feature = "chat")]
+use shards::{ShardLink, deliver_shard_messages};
+use simulation::{Simulation, SimulationSet, count_ticks, pause_when_empty, simulation_running};
+use sparklines::{PeerRateCounter, PeerRates, sample_peer_rates};
+use sprites::{PositionReceived, move_sprites, receive_positions, remove_departed_sprites};
+use tcp::{TcpLimits, TcpPeers, spawn_tcp_listener};
+use tickets::TicketCheck;
+#[cfg(feature = "gui")]
+use timeline::{Direction, PacketTimeline};
+use validation::{LEGACY_LINES, ServerRules};
+use webhook::{Webhook, announce_server_start};
+
+/// The protocol subsets this build has; a session goes without any that
+/// it or the client lacks, and the Welcome names them in `without=`.
+const SUBSETS: &[&str] = &[
+    #[cfg(feature = "chat")]
+    "chat",
+];
+
+/// Which of the server's sockets a packet travelled on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stream {
+    /// Handshake and heartbeats
+    Control,
+    /// Gameplay traffic (Ping/Pong) when `--data-port` is set
+    Data,
+    /// Everything from a client connected over TCP (`--tcp`)
+    Tcp,
 }
 
+impl Stream {
+    fn label(self) -> &'static str {
+        match self {
+            Stream::Control => "control",
+            Stream::Data => "data",
+            Stream::Tcp => "tcp",
+        }
+    }
+}
+
+/// Packets taken from the queue per decode batch
+const BATCH_SIZE: usize = 128;
+
+/// Packets still waiting in the receive queue after this frame's budget ran out.
+const QUEUE_DEPTH: DiagnosticPath = DiagnosticPath::const_new("network/queue_depth");
+/// Datagrams one send on the control socket carries: more than 1 while GSO
+/// is on, 1 without `--udp-offload` or once the kernel has refused GSO
+const GSO_SEGMENTS: DiagnosticPath = DiagnosticPath::const_new("network/gso_segments");
+
 #[derive(Resource)]
 struct NetworkState {
-    received_message: Arc<Mutex<Option<(String, String)>>>,
-    socket: Arc<UdpSocket>,
+    received_messages: ReceiveQueue,
+    /// `None` while stopped from the listener controls
+    socket: Option<NetworkSocket>,
+    /// Bound only when `--data-port` is given; gameplay replies go out on it
+    data_socket: Option<NetworkSocket>,
+    /// `--udp-offload`
+    udp_offload: bool,
+    /// The offload each socket got, with `--udp-offload`
+    offload: Option<Arc<UdpOffload>>,
+    data_offload: Option<Arc<UdpOffload>>,
+    metrics: Arc<MetricsRegistry>,
+    errors: NetErrorSink,
+    /// `--max-message-size`
+    max_message_size: usize,
+    pipeline: Pipeline,
+    /// The control port, as last bound; the listener controls can change it
+    port: u16,
+    /// `--data-port`
+    data_port: Option<u16>,
+    /// `--tcp`
+    tcp: bool,
+    /// `--max-tcp-connections`, and `--disconnect-timeout` for reads
+    tcp_limits: TcpLimits,
+    /// Clients connected over TCP; sends to them skip the sockets
+    tcp_peers: TcpPeers,
+    /// What the receive threads of a new binding get
+    shared: ReceiverShared,
+    /// `--recv-buffer`
+    recv_buffer: usize,
+    /// The sockets' receive threads
+    receivers: Vec<Receiver>,
+    /// Set to end the TCP listener's thread
+    stop: Arc<AtomicBool>,
+    tcp_listener: Option<JoinHandle<()>>,
 }
 
-impl Default for NetworkState {
-    fn default() -> Self {
-        Self {
-            received_message: Arc::new(Mutex::new(None)),
-            // This default is unused since we initialize in setup_network, but good to keep valid
-            socket: Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap()),
+impl NetworkState {
+    #[cfg(any(feature = "gui", feature = "dashboard", feature = "grpc"))]
+    fn listening(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Binds the control socket on `port`, the data socket if we have a
+    /// data port and the TCP listener with `--tcp`, and starts their threads.
+    fn listen(&mut self, port: u16) -> Result<(), NetError> {
+        let bind_addr = format!("0.0.0.0:{}", port);
+        let socket = self.bind_socket(&bind_addr)?;
+        // Bound before any thread starts, so a taken port leaves nothing behind
+        let data_socket = match self.data_port {
+            Some(data_port) => Some(self.bind_socket(&format!("0.0.0.0:{}", data_port))?),
+            None => None,
+        };
+        let tcp_listener = if self.tcp {
+            let listener = TcpListener::bind(&bind_addr).map_err(|source| NetError::Bind {
+                addr: format!("tcp {}", bind_addr),
+                source,
+            })?;
+            Some(listener)
+        } else {
+            None
+        };
+        println!("Server listening on {}", bind_addr);
+        if let Some(data_port) = self.data_port {
+            println!("Server data stream on 0.0.0.0:{}", data_port);
+        }
+        if tcp_listener.is_some() {
+            println!("Server accepting TCP on {}", bind_addr);
+        }
+        let offload = self.offload_for(&socket, &bind_addr);
+        let data_offload = match (&data_socket, self.data_port) {
+            (Some(data_socket), Some(data_port)) => {
+                self.offload_for(data_socket, &format!("0.0.0.0:{}", data_port))
+            }
+            _ => None,
+        };
+
+        self.stop = Arc::default();
+        if let Some(tcp_listener) = tcp_listener {
+            self.tcp_listener = Some(
+                spawn_tcp_listener(
+                    tcp_listener,
+                    self.recv_buffer,
+                    self.tcp_limits,
+                    self.shared.clone(),
+                    self.tcp_peers.clone(),
+                    self.stop.clone(),
+                )
+                .map_err(NetError::Socket)?,
+            );
+        }
+        self.receivers.push(socket.receive(ServerReceiver {
+            shared: self.shared.clone(),
+            stream: Stream::Control,
+            socket: socket.clone(),
+            offload: offload.clone(),
+        }));
+        if let Some(data_socket) = &data_socket {
+            self.receivers.push(data_socket.receive(ServerReceiver {
+                shared: self.shared.clone(),
+                stream: Stream::Data,
+                socket: data_socket.clone(),
+                offload: data_offload.clone(),
+            }));
+        }
+        self.socket = Some(socket);
+        self.data_socket = data_socket;
+        self.offload = offload;
+        self.data_offload = data_offload;
+        self.port = port;
+        Ok(())
+    }
+
+    fn bind_socket(&self, addr: &str) -> Result<NetworkSocket, NetError> {
+        NetworkSocket::bind(addr, self.recv_buffer).map_err(|source| NetError::Bind {
+            addr: addr.to_string(),
+            source,
+        })
+    }
+
+    /// With `--udp-offload`, whatever offload the OS has for `socket`.
+    fn offload_for(&self, socket: &NetworkSocket, addr: &str) -> Option<Arc<UdpOffload>> {
+        let socket = socket.udp().filter(|_| self.udp_offload)?;
+        match UdpOffload::new(socket) {
+            Ok(offload) => {
+                println!("UDP offload on {}: {}", addr, offload.describe());
+                Some(Arc::new(offload))
+            }
+            Err(e) => {
+                println!("No UDP offload on {} ({}), one datagram a send", addr, e);
+                None
+            }
+        }
+    }
+
+    /// Closes the sockets. The receive threads are waited for, so the ports
+    /// are free again when this returns.
+    #[cfg(feature = "gui")]
+    fn stop_listening(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.socket = None;
+        self.data_socket = None;
+        self.offload = None;
+        self.data_offload = None;
+        self.tcp_peers.close_all();
+        for receiver in self.receivers.drain(..) {
+            receiver.stop();
+        }
+        if let Some(tcp_listener) = self.tcp_listener.take() {
+            let _ = tcp_listener.join();
+        }
+        println!("Server stopped listening on 0.0.0.0:{}", self.port);
+    }
+
+    /// Sends on the control socket (handshake, heartbeats).
+    fn send_control(&self, bytes: &[u8], addr: &str) {
+        if let Some(socket) = &self.socket {
+            self.send_on(socket, bytes, addr);
+        }
+    }
+
+    /// Sends gameplay messages, from the data socket if we have one.
+    fn send_gameplay(&self, bytes: &[u8], addr: &str) {
+        if let Some(socket) = self.data_socket.as_ref().or(self.socket.as_ref()) {
+            self.send_on(socket, bytes, addr);
+        }
+    }
+
+    /// Sends `messages` to `addr` on the control socket, in order, in as few
+    /// sends as GSO allows with `--udp-offload`.
+    fn send_control_batch(&self, messages: &[String], addr: &str) {
+        // A TCP client gets a stream, not datagrams
+        let udp = self.socket.as_ref().and_then(NetworkSocket::udp);
+        let offloaded = match (udp, &self.offload, addr.parse::<SocketAddr>()) {
+            (Some(socket), Some(offload), Ok(peer)) if !self.tcp_peers.contains(addr) => {
+                Some((socket, offload, peer))
+            }
+            _ => None,
+        };
+        let Some((socket, offload, peer)) = offloaded else {
+            for message in messages {
+                self.send_control(message.as_bytes(), addr);
+            }
+            return;
+        };
+        let datagrams: Vec<Vec<u8>> = messages
+            .iter()
+            .filter_map(|message| self.prepare(message.as_bytes(), addr))
+            .collect();
+        match offload.send_batch(socket, peer, &datagrams) {
+            Ok(()) => {
+                for datagram in &datagrams {
+                    self.metrics.record_sent(datagram.len());
+                }
+            }
+            Err(source) => self.errors.push(NetError::Send {
+                addr: addr.to_string(),
+                source,
+            }),
+        }
+    }
+
+    /// What goes out for `bytes` once through the pipeline, unless a stage
+    /// dropped it or it's over `--max-message-size`.
+    fn prepare(&self, bytes: &[u8], addr: &str) -> Option<Vec<u8>> {
+        let bytes = self.pipeline.outgoing(addr, bytes)?;
+        self.errors
+            .within_max_size(addr, &bytes, self.max_message_size)
+            .then_some(bytes)
+    }
+
+    fn send_on(&self, socket: &NetworkSocket, bytes: &[u8], addr: &str) {
+        let Some(bytes) = self.prepare(bytes, addr) else {
+            return;
+        };
+        let sent = self
+            .tcp_peers
+            .send(addr, &bytes)
+            .unwrap_or_else(|| socket.send_bytes_to(&bytes, addr));
+        match sent {
+            Ok(()) => self.metrics.record_sent(bytes.len()),
+            Err(source) => self.errors.push(NetError::Send {
+                addr: addr.to_string(),
+                source,
+            }),
         }
     }
 }
 
-#[derive(Resource, Default)]
+/// Lines the log panel shows
+const LOG_LINES: usize = 20;
+
+#[derive(Resource)]
 struct ServerState {
     client_addr: Option<String>,
-    log: Vec<String>,
+    /// The seq of that client's last Ping, which PING's Pong answers
+    last_ping: u32,
+    /// Every line pushed here reaches every sink, the panel among them
+    log: LogSinks,
+    /// What the log panel shows
+    panel: RecentLines,
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    // Without a window to open, there's only headless
+    if cfg!(not(feature = "gui")) {
+        args.headless = true;
+    }
+    if args.dump_protocol {
+        println!("{}", protocol::to_json(args.max_message_size));
+        return;
+    }
+    if args.validate {
+        let mut ports = vec![args.port];
+        ports.extend(args.data_port);
+        let result = smoke_test::run(Side::Server, &ports, args.recv_buffer);
+        println!("{}", result.to_json());
+        std::process::exit(if result.ok() { 0 } else { 1 });
+    }
+    let mut ports = vec![args.port];
+    ports.extend(args.data_port);
+    let checks = report(&run_self_checks(&ports, args.recv_buffer));
+    let log = LogSinks::default();
+    let panel = RecentLines::new(LOG_LINES);
+    log.add(panel.clone());
+    if args.headless {
+        log.add(StdoutSink);
+    }
+    if let Some(path) = &args.log_file {
+        match LogFile::open(path) {
+            Ok(sink) => log.add(sink),
+            Err(e) => {
+                eprintln!("Error: could not open log file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(feature = "geoip")]
+    let geoip = match GeoIp::open(args.geoip_db.as_deref(), args.geoip_asn_db.as_deref()) {
+        Ok(geoip) => geoip,
+        Err(e) => {
+            eprintln!("Error: could not open GeoIP database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(not(feature = "geoip"))]
+    let geoip = GeoIp::default();
+    let audit = match &args.audit_log {
+        Some(path) => match AuditLog::open(path.clone(), args.audit_max_bytes, geoip.clone()) {
+            Ok(audit) => audit,
+            Err(e) => {
+                eprintln!("Error: could not open audit log {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => AuditLog::default(),
+    };
+    let backend = match storage::choose(args.storage, args.database.as_deref()) {
+        Ok((backend, warning)) => {
+            if let Some(warning) = warning {
+                eprintln!("Warning: {}", warning);
+            }
+            backend
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stores = storage::open(backend, args.database.as_deref()).and_then(|database| {
+        let database = database.as_ref();
+        let crash_reports = if args.crash_reports {
+            Some(CrashReports::open(database)?)
+        } else {
+            None
+        };
+        let access = AccessControl::new(
+            args.allow_cidrs.clone(),
+            args.deny_cidrs.clone(),
+            Bans::open(database)?,
+        )?;
+        Ok((
+            History::open(database)?,
+            Accounts::open(database)?,
+            crash_reports,
+            access,
+        ))
+    });
+    let (chat_history, accounts, crash_reports, access) = match stores {
+        Ok(stores) => stores,
+        Err(e) => {
+            eprintln!("Error: could not open the database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let errors = NetErrorSink::default();
+    let pipeline = Pipeline::default();
+    if args.trace_packets {
+        pipeline.add(TRACE_ORDER, PacketTrace);
+    }
+    let bandwidth = BandwidthStats::default();
+    pipeline.add(METER_ORDER, BandwidthMeter::new(bandwidth.clone()));
+    let peer_rates = PeerRates::default();
+    pipeline.add(METER_ORDER, PeerRateCounter::new(peer_rates.clone()));
+    if let Some(path) = &args.identity_key {
+        match ServerIdentity::load_or_create(path) {
+            Ok((identity, created)) => {
+                if created {
+                    println!("Created server key {}", path.display());
+                }
+                println!(
+                    "Server key fingerprint {}; clients pin it with --server-key",
+                    identity.fingerprint()
+                );
+                SignReplies::new(identity, args.sign_all).install(&pipeline);
+            }
+            Err(e) => {
+                eprintln!("Error: could not load {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if args.validate_protocol {
+        pipeline.add(
+            VALIDATOR_ORDER,
+            ProtocolValidator::new(ServerRules).reporting_to(errors.clone()),
+        );
+    }
+    if let Some(path) = &args.packet_log {
+        match PacketLog::create(path, Side::Server, args.port) {
+            Ok(stage) => pipeline.add(PACKET_LOG_ORDER, stage),
+            Err(e) => {
+                eprintln!("Error: could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(port) = args.observer_port {
+        match Observer::listen(port) {
+            Ok(stage) => {
+                println!("Mirroring traffic to observers on 127.0.0.1:{}", port);
+                log.add(stage.log_sink());
+                pipeline.add(PACKET_LOG_ORDER, stage);
+            }
+            Err(e) => {
+                eprintln!(
+                    "Error: could not listen for observers on port {}: {}",
+                    port, e
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    let codecs = match Codecs::from_args(&args.codecs) {
+        Ok(codecs) => codecs,
+        Err(e) => {
+            eprintln!("Error: --codec: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !args.codecs.is_empty() {
+        pipeline.add(CODEC_ORDER, ChannelCodecs::new(codecs.clone()));
+    }
+    let compression = CompressionStats::default();
+    if args.compress {
+        match DictCompression::new(args.recv_buffer, compression.clone()) {
+            Ok(stage) => pipeline.add(COMPRESSION_ORDER, stage),
+            Err(e) => {
+                eprintln!("Error: could not set up compression: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if !args.no_legacy_text {
+        pipeline.add(LEGACY_ORDER, LegacyText::new(LEGACY_LINES));
+    }
+    let server_state = ServerState {
+        client_addr: None,
+        last_ping: 0,
+        log: log.clone(),
+        panel,
+    };
+    for line in checks {
+        server_state.log.push(line);
+    }
+    let wakeup = Wakeup::default();
+    if args.name.is_empty() || args.name.contains(char::is_whitespace) {
+        eprintln!("Error: --name has to be one word, without spaces");
+        std::process::exit(1);
+    }
+    let query = QueryInfo::new(
+        args.name.clone(),
+        args.ticket_key.is_some(),
+        args.query_rate,
+    );
+    let network = match bind_network(
+        &args,
+        errors.clone(),
+        audit.clone(),
+        access.clone(),
+        pipeline.clone(),
+        wakeup.clone(),
+        query.clone(),
+    ) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if args.fake_clients > 0 {
+        spawn_bots(args.fake_clients, args.port);
+        println!("Started {} fake clients", args.fake_clients);
+    }
+    let flooding = args.fake_flooders > 0;
+    if flooding {
+        spawn_flooders(args.fake_flooders, args.port, args.flood_rate);
+        println!(
+            "Started {} fake flooders at {} packets/s each",
+            args.fake_flooders, args.flood_rate
+        );
+    }
+    let delayed_sends = DelayedSends::new(
+        args.delay_welcomes,
+        args.delay_heartbeat_acks,
+        args.delay_pongs,
+    );
+    let sessions = Sessions::new(args.session_log.clone());
+    if let Some(dir) = &args.record_sessions
+        && let Err(e) = std::fs::create_dir_all(dir)
+    {
+        eprintln!("Error: could not create {}: {}", dir.display(), e);
+        std::process::exit(1);
+    }
+    let recordings = SessionRecordings {
+        dir: args.record_sessions.clone(),
+    };
+    let resumptions = Resumptions::new(args.resume_grace);
+    let archive = ClientArchive::new(
+        args.archive_after,
+        args.max_inactive_peers,
+        args.archive_max,
+        args.archive_evict,
+    );
+    let tickets = TicketCheck::new(args.ticket_key.clone());
+    let handoffs = Handoffs::new(args.handoff_key.clone());
+    let shared_rng = SharedRng::new(args.seed.unwrap_or_else(SharedRng::random_seed));
+    println!("Shared seed {:x}", shared_rng.seed());
+    let send_rates = SendRates::new(args.min_send_rate, args.max_send_rate);
+    #[cfg(any(feature = "dashboard", feature = "grpc"))]
+    let admin = AdminPlane::new(wakeup.clone());
+    #[cfg(feature = "dashboard")]
+    if let Some(addr) = &args.dashboard {
+        if let Err(e) = dashboard::serve(addr, admin.link()) {
+            eprintln!("Error: could not serve the dashboard on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+        println!("Dashboard on http://{}", addr);
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = &args.grpc {
+        if let Err(e) = grpc::serve(addr, admin.link()) {
+            eprintln!("Error: could not serve the gRPC API on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+        println!("gRPC admin API on {}", addr);
+    }
+    let idle = IdleMode::new(
+        args.idle_after,
+        Duration::from_millis(args.idle_wait_ms),
+        wakeup,
+    );
+    let simulation = Simulation::new(args.keep_simulating);
+    let login_limiter = LoginLimiter::new(args.login_attempts);
+    #[cfg(feature = "chat")]
+    let moderation = Moderation::new(&args.banned_words, args.chat_rate_limit);
+    let client_config = ClientConfig::new(args.disconnect_timeout);
+    let announcements = match &args.announcements {
+        Some(path) => match Announcements::load(path) {
+            Ok(announcements) => {
+                println!("Announcements: {}", announcements.describe());
+                announcements
+            }
+            Err(e) => {
+                eprintln!("Error: could not load {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => Announcements::default(),
+    };
+    #[cfg(feature = "chat")]
+    let shard_link = if args.shard_link {
+        ShardLink::connect()
+    } else {
+        ShardLink::default()
+    };
+    #[cfg(feature = "webhook")]
+    let webhook = Webhook::new(args.webhook_url.clone());
+    #[cfg(not(feature = "webhook"))]
+    let webhook = Webhook::default();
+    let client_list = ClientList::new(args.idle_timeout, args.disconnect_timeout);
 
-    App::new()
-        .add_plugins(DefaultPlugins)
+    let mut app = App::new();
+    #[cfg(feature = "gui")]
+    app.add_plugins(GuiPlugin {
+        fonts: args.fonts.clone(),
+        headless: args.headless,
+        message_history: args.message_history,
+    });
+    #[cfg(not(feature = "gui"))]
+    app.add_plugins(headless::plugins());
+    app.insert_resource(errors)
+        .insert_resource(pipeline)
+        .insert_resource(codecs)
+        .add_plugins(NetErrorPlugin)
+        .add_plugins(CompressionPlugin { stats: compression })
+        .insert_resource(bandwidth)
+        .insert_resource(peer_rates)
+        .add_plugins(ReplicationPlugin {
+            role: ReplicationRole::Server,
+        })
+        .replicate_resource::<PlayerCount>()
         .insert_resource(args)
-        .init_resource::<ServerState>()
-        .add_systems(Startup, (setup_network, setup_ui))
+        .insert_resource(network)
+        .insert_resource(audit)
+        .insert_resource(access)
+        .insert_resource(geoip)
+        .insert_resource(client_list)
+        .init_resource::<PeerIndex>()
+        .insert_resource(resumptions)
+        .insert_resource(archive)
+        .insert_resource(tickets)
+        .insert_resource(handoffs)
+        .insert_resource(shared_rng)
+        .insert_resource(send_rates)
+        .insert_resource(client_config)
+        .insert_resource(announcements)
+        .insert_resource(idle)
+        .insert_resource(simulation)
+        .insert_resource(query)
+        .insert_resource(chat_history)
+        .insert_resource(accounts)
+        .insert_resource(login_limiter)
+        .add_event::<EffectReceived>()
+        .add_event::<PositionReceived>()
+        .init_resource::<EffectRelay>()
+        .add_event::<ReliableReceived>()
+        .add_event::<InputReceived>()
+        .add_event::<Disconnected>()
+        .add_event::<ConsoleCommand>()
+        .insert_resource(delayed_sends)
+        .insert_resource(sessions)
+        .insert_resource(recordings)
+        .insert_resource(webhook)
+        .register_diagnostic(Diagnostic::new(QUEUE_DEPTH))
+        .register_diagnostic(Diagnostic::new(GSO_SEGMENTS))
+        .register_diagnostic(Diagnostic::new(CONNECTED_CLIENTS))
+        .register_diagnostic(Diagnostic::new(PEER_ENTITIES))
+        .register_diagnostic(Diagnostic::new(PEER_STATE_BYTES))
+        .register_diagnostic(Diagnostic::new(ARCHIVE_BYTES))
+        .register_diagnostic(Diagnostic::new(Stage::ReceiveDrain.path()))
+        .register_diagnostic(Diagnostic::new(Stage::Decode.path()))
+        .register_diagnostic(Diagnostic::new(Stage::SessionUpdate.path()))
+        .register_diagnostic(Diagnostic::new(Stage::Send.path()))
+        .register_diagnostic(Diagnostic::new(REPLICATION_BUILD))
+        .register_diagnostic(Diagnostic::new(Metric::PacketsSent.path()))
+        .register_diagnostic(Diagnostic::new(Metric::BytesSent.path()))
+        .register_diagnostic(Diagnostic::new(Metric::PacketsReceived.path()))
+        .register_diagnostic(Diagnostic::new(Metric::BytesReceived.path()))
+        .register_diagnostic(Diagnostic::new(Metric::TruncatedPackets.path()))
+        .register_diagnostic(Diagnostic::new(Metric::RejectedPackets.path()))
+        .register_diagnostic(Diagnostic::new(Metric::Queued.path()))
+        .register_diagnostic(Diagnostic::new(Metric::Drained.path()))
+        .init_resource::<MetricsSample>()
+        .init_resource::<TickProfile>()
+        .init_resource::<NetworkEventHistory>()
+        .insert_resource(server_state)
+        .insert_resource(log)
+        .add_systems(Startup, announce_server_start)
+        .add_systems(
+            Update,
+            (
+                handle_network_messages,
+                prune_traffic,
+                update_connection_states,
+                report_truncated_packets,
+                handle_console_commands,
+                measure_connected_clients,
+                log_net_errors,
+            ),
+        )
+        .add_systems(First, sample_metrics)
+        .add_systems(Update, spawn_peers.before(handle_network_messages))
+        .add_systems(
+            Update,
+            (
+                measure_queue_depth.after(handle_network_messages),
+                measure_udp_offload,
+                relay_effects.after(handle_network_messages),
+                forget_effect_senders
+                    .after(relay_effects)
+                    .after(update_connection_states),
+                receive_positions.after(handle_network_messages),
+                remove_departed_sprites.after(receive_positions),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                reset_reliable_on_join.after(handle_network_messages),
+                handle_reliable.after(reset_reliable_on_join),
+                push_config_on_join.after(reset_reliable_on_join),
+                push_config_updates.after(handle_console_commands),
+                send_motd_on_join.after(reset_reliable_on_join),
+                send_announcements.after(handle_console_commands),
+                finish_logins.after(handle_reliable),
+                flush_reliable.after(finish_logins),
+                track_account_activity
+                    .after(handle_network_messages)
+                    .before(update_connection_states),
+            ),
+        )
         .add_systems(
             Update,
-            (handle_network_messages, ping_button_system, update_log_ui),
+            (
+                start_recordings.after(handle_network_messages),
+                record_inputs.after(start_recordings),
+                record_state_changes.after(start_recordings),
+            ),
         )
-        .run();
+        .add_systems(Update, print_status.run_if(is_headless))
+        .add_systems(Update, update_idle_mode.after(update_connection_states))
+        .add_systems(Update, pause_when_empty.after(update_connection_states))
+        .configure_sets(FixedUpdate, SimulationSet.run_if(simulation_running))
+        .add_systems(
+            FixedUpdate,
+            (count_ticks, move_sprites).in_set(SimulationSet),
+        )
+        .add_systems(
+            Update,
+            (
+                update_player_count.after(update_connection_states),
+                publish_player_count.after(update_player_count),
+                sample_peer_rates,
+                send_replication,
+                update_send_rates,
+                flush_send_rates
+                    .after(send_replication)
+                    .after(update_send_rates),
+                expire_suspended_sessions.after(update_connection_states),
+                archive_inactive_peers
+                    .after(expire_suspended_sessions)
+                    .after(update_player_count),
+            ),
+        )
+        .add_systems(
+            Update,
+            flush_delayed_sends
+                .after(handle_network_messages)
+                .after(handle_console_commands),
+        )
+        .add_systems(
+            Last,
+            (
+                send_shutdown_notices,
+                end_sessions_on_exit,
+                flush_tick_profile,
+                idle_wait.after(flush_tick_profile),
+            ),
+        );
+    if let Some(crash_reports) = crash_reports {
+        app.insert_resource(crash_reports);
+    }
+    if flooding {
+        app.add_systems(Last, report_throughput);
+    }
+    #[cfg(feature = "chat")]
+    app.insert_resource(moderation)
+        .insert_resource(shard_link)
+        .add_event::<ChatReceived>()
+        .add_systems(
+            Update,
+            (
+                moderate_chat.after(handle_network_messages),
+                forget_chat_senders
+                    .after(moderate_chat)
+                    .after(update_connection_states),
+                deliver_shard_messages,
+                send_chat_history.after(flush_delayed_sends),
+            ),
+        );
+    #[cfg(any(feature = "dashboard", feature = "grpc"))]
+    app.insert_resource(admin).add_systems(
+        Update,
+        (
+            publish_admin_snapshot.after(update_player_count),
+            apply_admin_commands,
+        ),
+    );
+    app.run();
 }
 
-fn setup_network(mut commands: Commands, args: Res<Args>) {
-    let bind_addr = format!("0.0.0.0:{}", args.port);
-    let socket = Arc::new(UdpSocket::bind(&bind_addr).expect("Failed to bind socket"));
-    println!("Server listening on {}", bind_addr);
+/// Binds the sockets and starts their receive threads. Runs before the app
+/// is built so a port that's already taken ends the program with a clear
+/// error instead of a panic.
+fn bind_network(
+    args: &Args,
+    errors: NetErrorSink,
+    audit: AuditLog,
+    access: AccessControl,
+    pipeline: Pipeline,
+    wakeup: Wakeup,
+    query: QueryInfo,
+) -> Result<NetworkState, NetError> {
+    let shared = ReceiverShared {
+        received: ReceiveQueue::new(args.peer_queue_limit),
+        metrics: Arc::default(),
+        errors: errors.clone(),
+        audit,
+        access,
+        pipeline: pipeline.clone(),
+        wakeup,
+        query,
+    };
+    let mut network = NetworkState {
+        received_messages: shared.received.clone(),
+        socket: None,
+        data_socket: None,
+        udp_offload: args.udp_offload,
+        offload: None,
+        data_offload: None,
+        metrics: shared.metrics.clone(),
+        errors,
+        max_message_size: args.max_message_size,
+        pipeline,
+        port: args.port,
+        data_port: args.data_port,
+        tcp: args.tcp,
+        tcp_limits: TcpLimits {
+            max_connections: args.max_tcp_connections,
+            read_timeout: (args.disconnect_timeout > 0.0)
+                .then(|| Duration::from_secs_f32(args.disconnect_timeout)),
+        },
+        tcp_peers: TcpPeers::default(),
+        shared,
+        recv_buffer: args.recv_buffer,
+        receivers: Vec::new(),
+        stop: Arc::default(),
+        tcp_listener: None,
+    };
+    network.listen(args.port)?;
+    Ok(network)
+}
 
-    socket
-        .set_nonblocking(true)
-        .expect("Failed to set non-blocking");
+/// What every receive thread shares with the app.
+#[derive(Clone)]
+struct ReceiverShared {
+    received: ReceiveQueue,
+    metrics: Arc<MetricsRegistry>,
+    errors: NetErrorSink,
+    audit: AuditLog,
+    access: AccessControl,
+    pipeline: Pipeline,
+    wakeup: Wakeup,
+    query: QueryInfo,
+}
 
-    let received_message = Arc::new(Mutex::new(None));
-    let socket_clone = socket.clone();
-    let received_clone = received_message.clone();
+/// What a socket's receive thread does with each datagram: the access
+/// list, the pipeline and server queries, then the receive queue.
+struct ServerReceiver {
+    shared: ReceiverShared,
+    stream: Stream,
+    /// Server queries are answered from the thread, on the socket they came in on
+    socket: NetworkSocket,
+    /// With offload, reads are GRO reads
+    offload: Option<Arc<UdpOffload>>,
+}
 
-    thread::spawn(move || {
-        let mut buf = [0u8; 1024];
-        loop {
-            match socket_clone.recv_from(&mut buf) {
-                Ok((size, addr)) => {
-                    let message = String::from_utf8_lossy(&buf[..size]).to_string();
-                    let mut received = received_clone.lock().unwrap();
-                    *received = Some((message, addr.to_string()));
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    thread::sleep(Duration::from_millis(10));
-                }
-                Err(_) => break,
+impl Receive for ServerReceiver {
+    fn received(&mut self, addr: SocketAddr, datagram: &[u8]) {
+        let ReceiverShared {
+            received,
+            metrics,
+            audit,
+            access,
+            pipeline,
+            wakeup,
+            query,
+            ..
+        } = &self.shared;
+        let stream = self.stream;
+        let size = datagram.len();
+        metrics.record_received(size);
+        let peer = addr.to_string();
+        if let Err(reason) = access.check(addr.ip()) {
+            audit.record(&peer, stream, size, "-", Decision::Rejected(reason));
+            metrics.add(Metric::RejectedPackets, 1);
+            return;
+        }
+        let Some(bytes) = pipeline.incoming(&peer, datagram.to_vec()) else {
+            audit.record(&peer, stream, size, "-", Decision::Rejected("middleware"));
+            metrics.add(Metric::RejectedPackets, 1);
+            return;
+        };
+        // Answered here, before any handshake; see `query`
+        if is_query(&bytes) {
+            let kind = ServerInfoRequest::KIND;
+            let Some(answer) = query.answer() else {
+                audit.record(&peer, stream, size, kind, Decision::Rejected("query rate"));
+                metrics.add(Metric::RejectedPackets, 1);
+                return;
+            };
+            audit.record(&peer, stream, size, kind, Decision::Accepted);
+            let sent = pipeline.outgoing(&peer, answer.as_bytes()).map(|reply| {
+                self.socket
+                    .send_bytes_to(&reply, addr)
+                    .map(|()| reply.len())
+            });
+            if let Some(Ok(sent)) = sent {
+                metrics.record_sent(sent);
             }
+            return;
         }
-    });
+        let packet = RawPacket {
+            bytes,
+            addr: peer.clone(),
+            stream,
+        };
+        if !received.push(packet) {
+            audit.record(&peer, stream, size, "-", Decision::Rejected("queue full"));
+            metrics.add(Metric::RejectedPackets, 1);
+            return;
+        }
+        metrics.add(Metric::Queued, 1);
+        wakeup.notify();
+    }
 
-    commands.insert_resource(NetworkState {
-        received_message,
-        socket,
-    });
-}
+    fn oversized(&mut self, from: Option<SocketAddr>, size: usize) {
+        self.shared.metrics.add(Metric::TruncatedPackets, 1);
+        // Windows doesn't tell us who sent it or how big it was
+        let peer = from.map_or_else(|| "?".to_string(), |from| from.to_string());
+        let decision = Decision::Rejected("oversized");
+        self.shared
+            .audit
+            .record(&peer, self.stream, size, "-", decision);
+    }
 
-#[derive(Component)]
-struct LogText;
+    fn failed(&mut self, error: std::io::Error) {
+        self.shared.errors.push(NetError::Recv(error));
+    }
 
-#[derive(Component)]
-struct PingButton;
+    fn buffer_len(&self, recv_buffer: usize) -> usize {
+        // Room for a GRO read
+        if self.offload.is_some() {
+            GRO_BUFFER.max(recv_buffer + 1)
+        } else {
+            recv_buffer + 1
+        }
+    }
 
-fn setup_ui(mut commands: Commands, args: Res<Args>) {
-    commands.spawn(Camera2dBundle::default());
+    fn read(
+        &mut self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> std::io::Result<(usize, SocketAddr, usize)> {
+        match &self.offload {
+            Some(offload) => offload.recv(socket, buf),
+            None => socket.recv_from(buf).map(|(size, addr)| (size, addr, size)),
+        }
+    }
+}
 
-    // Status Header
-    commands.spawn(
-        TextBundle::from_section(
-            format!("Server listening on 0.0.0.0:{}", args.port),
-            TextStyle {
-                font_size: 20.0,
-                color: Color::rgb(0.9, 0.9, 0.9),
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(10.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
-    );
+/// Who gets a session: only peers using our codecs, resumed and handed off
+/// ones by token, new ones by connect ticket. The Welcome hands them the
+/// shared seed.
+#[derive(SystemParam)]
+struct Admission<'w> {
+    resumptions: ResMut<'w, Resumptions>,
+    handoffs: ResMut<'w, Handoffs>,
+    tickets: ResMut<'w, TicketCheck>,
+    shared_rng: Res<'w, SharedRng>,
+    codecs: Res<'w, Codecs>,
+}
 
-    // Log Area
-    commands.spawn((
-        TextBundle::from_section(
-            "Waiting for client...\n",
-            TextStyle {
-                font_size: 16.0,
-                color: Color::WHITE,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(50.0),
-            left: Val::Px(10.0),
-            ..default()
-        }),
-        LogText,
-    ));
-
-    commands
-        .spawn((
-            ButtonBundle {
-                style: Style {
-                    width: Val::Px(120.0),
-                    height: Val::Px(50.0),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    position_type: PositionType::Absolute,
-                    bottom: Val::Px(20.0),
-                    right: Val::Px(20.0),
-                    ..default()
-                },
-                background_color: Color::rgb(0.3, 0.5, 0.9).into(),
-                ..default()
-            },
-            PingButton,
-        ))
-        .with_children(|parent| {
-            parent.spawn(TextBundle::from_section(
-                "PING",
-                TextStyle {
-                    font_size: 24.0,
-                    color: Color::WHITE,
-                    ..default()
-                },
-            ));
-        });
+/// Where every handled packet leaves a trace: the timeline, the event and
+/// message histories (the timeline and messages only with `gui`), the
+/// audit log and the tick profile, and the webhook for new connections.
+#[derive(SystemParam)]
+struct Records<'w> {
+    #[cfg(feature = "gui")]
+    timeline: ResMut<'w, PacketTimeline>,
+    history: ResMut<'w, NetworkEventHistory>,
+    #[cfg(feature = "gui")]
+    messages: ResMut<'w, MessageHistory>,
+    audit: Res<'w, AuditLog>,
+    profile: ResMut<'w, TickProfile>,
+    webhook: Res<'w, Webhook>,
+}
+
+/// Messages `handle_network_messages` hands on to the modules that own them,
+/// and the joins replication starts sending state on.
+#[derive(SystemParam)]
+struct Forwards<'w> {
+    joins: EventWriter<'w, ReplicationJoin>,
+    #[cfg(feature = "chat")]
+    chat: EventWriter<'w, ChatReceived>,
+    effects: EventWriter<'w, EffectReceived>,
+    positions: EventWriter<'w, PositionReceived>,
+    reliable: EventWriter<'w, ReliableReceived>,
+    inputs: EventWriter<'w, InputReceived>,
+    resyncs: EventWriter<'w, ReplicationResync>,
 }
 
-fn handle_network_messages(network: Res<NetworkState>, mut server_state: ResMut<ServerState>) {
-    let mut received = network.received_message.lock().unwrap();
-    if let Some((message, client_addr)) = received.take() {
-        server_state.client_addr = Some(client_addr);
-        let log_entry = format!("[Rx]: {}", message);
-        server_state.log.push(log_entry);
-        if server_state.log.len() > 20 {
-            server_state.log.remove(0);
+/// Handles queued packets until the per-frame packet or time budget runs out,
+/// leaving the rest for the next frame so a flood can't stall rendering.
+///
+/// Packets are taken in batches, round-robin across peers (see
+/// `receive_queue`), and decoded (see `inbound`); merging the results into
+/// ECS state happens here.
+fn handle_network_messages(
+    time: Res<Time>,
+    args: Res<Args>,
+    network: Res<NetworkState>,
+    mut server_state: ResMut<ServerState>,
+    mut peers: PeersByAddr,
+    mut delayed: ResMut<DelayedSends>,
+    sessions: Res<Sessions>,
+    mut admission: Admission,
+    mut records: Records,
+    mut forwards: Forwards,
+) {
+    let started = Instant::now();
+    let time_budget = Duration::from_secs_f32(args.time_budget_ms / 1000.0);
+    let now = time.elapsed_seconds();
+    let mut handled = 0;
+    // Packets handled per peer this frame, for `--peer-packet-budget`
+    let mut taken = HashMap::new();
+
+    while handled < args.packet_budget && started.elapsed() <= time_budget {
+        let drain_started = Instant::now();
+        let take = (args.packet_budget - handled).min(BATCH_SIZE);
+        // A packet that arrived after `spawn_peers` ran has no peer yet; it
+        // waits on its queue until next frame
+        let batch =
+            network
+                .received_messages
+                .take(take, args.peer_packet_budget, &mut taken, |addr| {
+                    peers.contains(addr)
+                });
+        records
+            .profile
+            .add(Stage::ReceiveDrain, drain_started.elapsed());
+        if batch.is_empty() {
+            break;
+        }
+        handled += batch.len();
+        network.metrics.add(Metric::Drained, batch.len() as u64);
+
+        let decode_started = Instant::now();
+        let decoded = decode_batch(batch);
+        records.profile.add(Stage::Decode, decode_started.elapsed());
+        for packet in decoded {
+            if packet.kind == MessageKind::Malformed {
+                records.audit.record(
+                    &packet.addr,
+                    packet.stream,
+                    packet.size,
+                    "-",
+                    Decision::Rejected("malformed"),
+                );
+                server_state.log.push(format!(
+                    "[Warn]: dropped a datagram from {}: {}",
+                    packet.addr, packet.text
+                ));
+                continue;
+            }
+            let is_heartbeat = packet.kind == MessageKind::Heartbeat;
+            records.audit.record(
+                &packet.addr,
+                packet.stream,
+                packet.size,
+                &format!("{:?}", packet.kind),
+                Decision::Accepted,
+            );
+            #[cfg(feature = "gui")]
+            records.messages.record(&packet, now);
+            let Some(entity) = peers.entity(&packet.addr) else {
+                continue;
+            };
+            #[cfg(feature = "gui")]
+            records.timeline.record(&packet.addr, Direction::Rx, now);
+            // A bare Hello predates versioning and speaks the current protocol
+            let version = protocol::parse_version(&packet.text).unwrap_or(PROTOCOL_VERSION);
+            let supported = (MIN_SUPPORTED_VERSION..=PROTOCOL_VERSION).contains(&version);
+            // A peer encoding a channel differently could read neither us nor we it
+            let codecs_agree = admission.codecs.agree(&packet.text);
+            let handshake = matches!(packet.kind, MessageKind::Hello | MessageKind::Resume)
+                && supported
+                && codecs_agree;
+            // A Resume whose token we still honour continues the old session
+            let resumed = handshake
+                && packet.kind == MessageKind::Resume
+                && protocol::parse_token(&packet.text)
+                    .and_then(|token| admission.resumptions.redeem(token))
+                    .is_some_and(|previous| peers.resume_session(previous, &packet.addr));
+            // As does, in effect, one handed off to us by another instance
+            let handed_off = handshake
+                && !resumed
+                && packet.kind == MessageKind::Resume
+                && protocol::parse_token(&packet.text)
+                    .is_some_and(|token| admission.handoffs.accept(token));
+            // Anything else is a new session, which needs a ticket
+            let refused = if handshake && !resumed && !handed_off {
+                admission.tickets.check(&packet.text, &packet.addr).err()
+            } else {
+                None
+            };
+            let handshake = handshake && refused.is_none();
+            let update_started = Instant::now();
+            let Some((mut stats, mut session)) = peers.get_mut(&packet.addr) else {
+                continue;
+            };
+            stats.record(packet.size, is_heartbeat, now);
+            if handshake && !resumed {
+                sessions.start(&mut session, &packet.addr, now, &server_state);
+            }
+            session.record_received(message_kind(&packet.text), packet.size);
+            records.profile.add_session_update(update_started.elapsed());
+            if !is_heartbeat {
+                forwards.inputs.send(InputReceived {
+                    from: packet.addr.clone(),
+                    text: packet.text.clone(),
+                    at: now,
+                });
+            }
+
+            match (packet.kind, refused) {
+                (MessageKind::Heartbeat, _) => {
+                    // A malformed report still keeps the peer alive
+                    if let Some(heartbeat) = Heartbeat::parse(&packet.text) {
+                        stats.record_heartbeat_report(&heartbeat);
+                    }
+                    // Heartbeats only keep the client's row alive; they're not worth a
+                    // log line. The ack lets the client notice when we go away.
+                    delayed.push(Reply::HeartbeatAck, b"HeartbeatAck", &packet.addr, now);
+                    continue;
+                }
+                (MessageKind::Hello | MessageKind::Resume, _) if !supported => {
+                    records
+                        .history
+                        .record(now, &packet.addr, NetworkEventKind::Rejected);
+                    let reject = format!("Reject version={}", PROTOCOL_VERSION);
+                    network.send_control(reject.as_bytes(), &packet.addr);
+                    server_state.log.push(format!(
+                        "[Rx control]: {} (version {}, we speak {}-{}), sent {}",
+                        message_kind(&packet.text),
+                        version,
+                        MIN_SUPPORTED_VERSION,
+                        PROTOCOL_VERSION,
+                        reject
+                    ));
+                }
+                (MessageKind::Hello | MessageKind::Resume, _) if !codecs_agree => {
+                    records
+                        .history
+                        .record(now, &packet.addr, NetworkEventKind::Rejected);
+                    let reject = format!(
+                        "Reject version={} codecs={}",
+                        PROTOCOL_VERSION,
+                        admission.codecs.describe()
+                    );
+                    network.send_control(reject.as_bytes(), &packet.addr);
+                    server_state.log.push(format!(
+                        "[Rx control]: {} from {} (codecs {}), sent {}",
+                        message_kind(&packet.text),
+                        packet.addr,
+                        protocol::parse_codecs(&packet.text).unwrap_or(ALL_TEXT),
+                        reject
+                    ));
+                }
+                (MessageKind::Hello | MessageKind::Resume, Some(refused)) => {
+                    records
+                        .history
+                        .record(now, &packet.addr, NetworkEventKind::Rejected);
+                    let reject = format!(
+                        "Reject version={} ticket={}",
+                        PROTOCOL_VERSION,
+                        refused.code()
+                    );
+                    network.send_control(reject.as_bytes(), &packet.addr);
+                    // The ticket itself stays out of the log, like tokens
+                    server_state.log.push(format!(
+                        "[Rx control]: {} from {} ({}), sent {}",
+                        message_kind(&packet.text),
+                        packet.addr,
+                        refused,
+                        reject
+                    ));
+                }
+                (MessageKind::Hello | MessageKind::Resume, _) => {
+                    stats.departed = None;
+                    session.spectator = protocol::is_spectator(&packet.text);
+                    session.missing_features = protocol::missing_features(version);
+                    session.missing_subsets = protocol::missing_subsets(SUBSETS, &packet.text);
+                    let kind = if resumed {
+                        NetworkEventKind::SessionResumed
+                    } else if handed_off {
+                        NetworkEventKind::HandedIn
+                    } else {
+                        NetworkEventKind::Connected
+                    };
+                    records.history.record(now, &packet.addr, kind);
+                    forwards.joins.send(ReplicationJoin {
+                        peer: packet.addr.clone(),
+                    });
+                    // Tokens stay out of the log
+                    let received = match (packet.kind, resumed) {
+                        (MessageKind::Resume, true) => "Resume (session continued)",
+                        (MessageKind::Resume, false) if handed_off => {
+                            "Resume (handed off from another server)"
+                        }
+                        (MessageKind::Resume, false) => "Resume (unknown token, new session)",
+                        _ => packet.text.as_str(),
+                    };
+                    if !resumed {
+                        records
+                            .webhook
+                            .post(format!("Client {} connected", packet.addr));
+                    }
+                    server_state
+                        .log
+                        .push(format!("[Rx {}]: {}", packet.stream.label(), received));
+                    // Handshake reply, advertising the data port when we have one
+                    // A TCP client gets everything over its connection
+                    let data_port = args.data_port.filter(|_| packet.stream != Stream::Tcp);
+                    let mut welcome = match data_port {
+                        Some(data_port) => format!("Welcome data_port={}", data_port),
+                        None => "Welcome".to_string(),
+                    };
+                    welcome.push_str(&format!(" seed={:x}", admission.shared_rng.seed()));
+                    // An older client is still served, and told what it misses
+                    if !session.missing_features.is_empty() {
+                        welcome.push_str(&format!(
+                            " deprecated={}",
+                            session.missing_features.join(",")
+                        ));
+                    }
+                    // As is a client built without something we have, or the reverse
+                    if !session.missing_subsets.is_empty() {
+                        welcome
+                            .push_str(&format!(" without={}", session.missing_subsets.join(",")));
+                    }
+                    server_state
+                        .log
+                        .push(format!("[Tx control]: {} to {}", welcome, packet.addr));
+                    if let Some(token) = admission.resumptions.issue(entity) {
+                        welcome.push_str(&format!(" token={}", token));
+                    }
+                    if resumed || handed_off {
+                        welcome.push_str(" resumed");
+                    }
+                    delayed.push(Reply::Welcome, welcome.as_bytes(), &packet.addr, now);
+                }
+                (MessageKind::Disconnect, _) => {
+                    server_state.log.push(format!(
+                        "[Rx {}]: {}",
+                        packet.stream.label(),
+                        packet.text
+                    ));
+                    // `update_connection_states` ends the session and reports it
+                    match Disconnect::parse(&packet.text) {
+                        Some(disconnect) => stats.departed = Some(disconnect.reason),
+                        None => warn!("Malformed Disconnect from {}: {}", packet.addr, packet.text),
+                    }
+                }
+                #[cfg(feature = "chat")]
+                (MessageKind::Chat, _) => {
+                    // Logged by `chat::moderate_chat` once it has been judged
+                    if let Some(Message::Chat { text }) = packet.message {
+                        forwards.chat.send(ChatReceived {
+                            from: packet.addr.clone(),
+                            text,
+                        });
+                    }
+                }
+                (MessageKind::Effect, _) => {
+                    // Logged by `effects::relay_effects` if it is relayed
+                    forwards.effects.send(EffectReceived {
+                        from: packet.addr.clone(),
+                        text: packet.text.clone(),
+                    });
+                }
+                // Too frequent to log; a spectator has no sprite to move
+                (MessageKind::Position, _) if session.spectator => {}
+                (MessageKind::Position, _) => {
+                    let Some(Message::Pos(update)) = packet.message else {
+                        warn!("Malformed Pos from {}: {}", packet.addr, packet.text);
+                        continue;
+                    };
+                    forwards.positions.send(PositionReceived {
+                        from: packet.addr.clone(),
+                        update,
+                    });
+                }
+                (MessageKind::Reliable, _) => {
+                    // Handled, and logged if it is a login, by `accounts`
+                    forwards.reliable.send(ReliableReceived {
+                        from: packet.addr.clone(),
+                        text: packet.text.clone(),
+                    });
+                }
+                (MessageKind::ReplicationNack, _) => {
+                    // Spectators get state too, so they may ask for it again
+                    let Some(nack) = ReplicationNack::parse(&packet.text) else {
+                        warn!("Malformed ReplNack from {}: {}", packet.addr, packet.text);
+                        continue;
+                    };
+                    server_state.log.push(format!(
+                        "[Rx {}]: {} lost {} after #{}, resending it in full",
+                        packet.stream.label(),
+                        packet.addr,
+                        nack.name,
+                        nack.last_seq
+                            .map_or("none".to_string(), |seq| seq.to_string())
+                    ));
+                    forwards.resyncs.send(ReplicationResync {
+                        peer: packet.addr.clone(),
+                        name: nack.name,
+                    });
+                }
+                (MessageKind::Gameplay, _) if session.spectator => {
+                    server_state.log.push(format!(
+                        "[Rx {}]: dropped input from spectator {}: {}",
+                        packet.stream.label(),
+                        packet.addr,
+                        packet.text
+                    ));
+                }
+                (MessageKind::Gameplay, _) => {
+                    server_state.log.push(format!(
+                        "[Rx {}]: {}",
+                        packet.stream.label(),
+                        packet.text
+                    ));
+                    if let Some(Message::Ping { seq }) = packet.message {
+                        server_state.last_ping = seq;
+                        stats.last_ping = seq;
+                    }
+                    server_state.client_addr = Some(packet.addr);
+                }
+                (MessageKind::Malformed, _) => {}
+            }
         }
     }
+    records.profile.add_handling(started.elapsed());
+}
+
+fn measure_queue_depth(network: Res<NetworkState>, mut diagnostics: Diagnostics) {
+    let depth = network.metrics.queue_depth();
+    diagnostics.add_measurement(&QUEUE_DEPTH, || depth as f64);
 }
 
-fn update_log_ui(server_state: Res<ServerState>, mut query: Query<&mut Text, With<LogText>>) {
-    if server_state.is_changed() {
-        for mut text in query.iter_mut() {
-            text.sections[0].value = server_state.log.join("\n");
+fn measure_udp_offload(network: Res<NetworkState>, mut diagnostics: Diagnostics) {
+    let segments = network
+        .offload
+        .as_ref()
+        .map_or(1, |offload| offload.gso_segments());
+    diagnostics.add_measurement(&GSO_SEGMENTS, || segments as f64);
+}
+
+/// Queues replicated resource changes for one client or for every one;
+/// `send_rate` decides when they go out.
+fn send_replication(
+    index: Res<PeerIndex>,
+    mut peers: Query<&mut SendRate, With<Peer>>,
+    mut outgoing: EventReader<ReplicationOutgoing>,
+) {
+    for message in outgoing.read() {
+        match &message.to {
+            Some(peer) => {
+                let Some(mut rate) = index.get(peer).and_then(|peer| peers.get_mut(peer).ok())
+                else {
+                    continue;
+                };
+                rate.queue(message.text.clone());
+                // A newcomer shouldn't wait an interval for its first copy
+                rate.hurry();
+            }
+            None => {
+                for mut rate in peers.iter_mut() {
+                    rate.queue(message.text.clone());
+                }
+            }
         }
     }
 }
 
-fn ping_button_system(
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<PingButton>)>,
+/// Tells every connected client we're going away, so they retry instead of
+/// waiting out their timeout.
+fn send_shutdown_notices(
+    mut exits: EventReader<AppExit>,
     network: Res<NetworkState>,
-    mut server_state: ResMut<ServerState>,
+    peers: Query<(&PeerAddr, &PeerStats), With<Peer>>,
 ) {
-    for interaction in interaction_query.iter() {
-        if *interaction == Interaction::Pressed {
-            let addr = server_state.client_addr.clone();
-            if let Some(addr) = addr {
-                let _ = network.socket.send_to("Pong".as_bytes(), &addr);
-                server_state.log.push(format!("[Tx]: Pong to {}", addr));
-            } else {
-                server_state
-                    .log
-                    .push("[Error]: No client connected".to_string());
-            }
-            if server_state.log.len() > 20 {
-                server_state.log.remove(0);
-            }
+    if exits.read().next().is_none() {
+        return;
+    }
+    let message = DisconnectReason::ServerShutdown.message();
+    for (PeerAddr(addr), stats) in peers.iter() {
+        if stats.state != ConnectionState::Disconnected {
+            network.send_control(message.as_bytes(), addr);
         }
     }
 }
+
+fn log_net_errors(mut errors: EventReader<NetError>, server_state: Res<ServerState>) {
+    for error in errors.read() {
+        error!("{}", error);
+        server_state.log.push(format!("[Error]: {}", error));
+    }
+}
+
+fn report_truncated_packets(
+    metrics: Res<MetricsSample>,
+    server_state: Res<ServerState>,
+    mut reported: Local<u64>,
+) {
+    let truncated = metrics.total(Metric::TruncatedPackets);
+    if truncated > *reported {
+        server_state.log.push(format!(
+            "[Warn]: {} oversized packet(s) dropped, raise --recv-buffer",
+            truncated - *reported
+        ));
+        *reported = truncated;
+    }
+}
@@ -0,0 +1,135 @@
+//! Network metrics kept outside the ECS.
+//!
+//! The socket threads and the send helpers on `NetworkState` bump
+//! [`MetricsRegistry`] directly: one relaxed atomic add per packet, with no
+//! lock and no resource access, so a flood of packets never waits on a
+//! frame. Once a frame, in `First`, [`sample_metrics`] reads every metric
+//! into [`MetricsSample`], which is what systems look at, and records the
+//! per-second rates as `network/*_per_sec` diagnostics.
+
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::NetworkState;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Metric {
+    PacketsSent,
+    BytesSent,
+    PacketsReceived,
+    BytesReceived,
+    /// Datagrams dropped because they didn't fit the receive buffer
+    TruncatedPackets,
+    /// Turned away by `--allow-cidr`/`--deny-cidr`, the middleware or a full
+    /// peer queue
+    RejectedPackets,
+    /// Pushed onto the receive queue, replays included
+    Queued,
+    /// Taken off the receive queue to be handled
+    Drained,
+}
+
+impl Metric {
+    const ALL: [Metric; 8] = [
+        Metric::PacketsSent,
+        Metric::BytesSent,
+        Metric::PacketsReceived,
+        Metric::BytesReceived,
+        Metric::TruncatedPackets,
+        Metric::RejectedPackets,
+        Metric::Queued,
+        Metric::Drained,
+    ];
+
+    /// The rate, per second, as a diagnostic
+    pub const fn path(self) -> DiagnosticPath {
+        match self {
+            Metric::PacketsSent => DiagnosticPath::const_new("network/packets_sent_per_sec"),
+            Metric::BytesSent => DiagnosticPath::const_new("network/bytes_sent_per_sec"),
+            Metric::PacketsReceived => {
+                DiagnosticPath::const_new("network/packets_received_per_sec")
+            }
+            Metric::BytesReceived => DiagnosticPath::const_new("network/bytes_received_per_sec"),
+            Metric::TruncatedPackets => {
+                DiagnosticPath::const_new("network/truncated_packets_per_sec")
+            }
+            Metric::RejectedPackets => {
+                DiagnosticPath::const_new("network/rejected_packets_per_sec")
+            }
+            Metric::Queued => DiagnosticPath::const_new("network/queued_per_sec"),
+            Metric::Drained => DiagnosticPath::const_new("network/drained_per_sec"),
+        }
+    }
+}
+
+/// Shared between the app and every socket thread behind an `Arc`.
+#[derive(Default)]
+pub struct MetricsRegistry {
+    values: [AtomicU64; Metric::ALL.len()],
+}
+
+impl MetricsRegistry {
+    pub fn add(&self, metric: Metric, amount: u64) {
+        self.values[metric as usize].fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, metric: Metric) -> u64 {
+        self.values[metric as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        self.add(Metric::PacketsSent, 1);
+        self.add(Metric::BytesSent, bytes as u64);
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.add(Metric::PacketsReceived, 1);
+        self.add(Metric::BytesReceived, bytes as u64);
+    }
+
+    /// Packets on the receive queue right now, without locking it. Drained
+    /// is read first, so a packet queued in between can only add to it.
+    pub fn queue_depth(&self) -> u64 {
+        let drained = self.get(Metric::Drained);
+        self.get(Metric::Queued).saturating_sub(drained)
+    }
+}
+
+/// Every metric as of the start of this frame.
+#[derive(Resource, Default)]
+pub struct MetricsSample {
+    totals: [u64; Metric::ALL.len()],
+}
+
+impl MetricsSample {
+    /// Since startup
+    pub fn total(&self, metric: Metric) -> u64 {
+        self.totals[metric as usize]
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn queue_depth(&self) -> u64 {
+        self.total(Metric::Queued)
+            .saturating_sub(self.total(Metric::Drained))
+    }
+}
+
+pub fn sample_metrics(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut sample: ResMut<MetricsSample>,
+    mut diagnostics: Diagnostics,
+) {
+    let secs = time.delta_seconds_f64();
+    // Drained first, for the same reason as in `queue_depth`
+    for metric in Metric::ALL.into_iter().rev() {
+        let index = metric as usize;
+        let total = network.metrics.get(metric);
+        if secs > 0.0 {
+            let rate = (total - sample.totals[index]) as f64 / secs;
+            diagnostics.add_measurement(&metric.path(), || rate);
+        }
+        sample.totals[index] = total;
+    }
+}
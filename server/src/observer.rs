@@ -0,0 +1,202 @@
+//! Observer port: every message in and out, mirrored as JSON lines to
+//! whatever connects, for dashboards and tools that don't link the crate.
+//!
+//! `--observer-port <port>` listens on localhost and adds [`Observer`] to the
+//! pipeline at `PACKET_LOG_ORDER`, next to `--packet-log`, where messages are plain
+//! text both ways. Each one becomes a line like:
+//!
+//! ```text
+//! {"at":1767225600.125,"dir":"in","fields":{"rtt_ms":"12.5"},"kind":"Heartbeat","peer":"127.0.0.1:50211","size":22,"text":"Heartbeat rtt_ms=12.5"}
+//! ```
+//!
+//! The window's log goes out too (see `common::log_sink`), one line per
+//! entry with `"dir":"log"`:
+//!
+//! ```text
+//! {"at":1767225600.125,"dir":"log","tag":"Info","text":"127.0.0.1:50211 is now idle"}
+//! ```
+//!
+//! Any number of observers can connect (`nc localhost 12400` is enough);
+//! each sees traffic from the moment it connects. Lines queue for a writer
+//! thread, so a slow observer never holds up the receive path: past
+//! [`QUEUE_LINES`] waiting, lines are dropped and counted, and an observer
+//! that takes longer than [`WRITE_TIMEOUT`] to read is disconnected. For a
+//! browser, a bridge such as `websocat` can put a WebSocket in front.
+
+use bevy::prelude::*;
+use common::log_sink::{LogEntry, LogSink};
+use common::middleware::Middleware;
+use common::session::message_kind;
+use crossbeam::channel::{Receiver, Sender, TrySendError, bounded, unbounded};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::audit::unix_now;
+
+/// Lines waiting for the writer before new ones are dropped
+pub const QUEUE_LINES: usize = 4096;
+/// An observer that blocks a write this long is dropped
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The way to the writer thread, shared by the pipeline stage and the log
+/// sink.
+#[derive(Clone)]
+struct Lines {
+    sender: Sender<String>,
+    /// Connected observers; nothing is encoded while there are none
+    connected: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Lines {
+    fn watched(&self) -> bool {
+        self.connected.load(Ordering::Relaxed) > 0
+    }
+
+    fn send(&self, line: String) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(line) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                warn!("Observers are behind; {} lines dropped so far", dropped);
+            }
+        }
+    }
+}
+
+/// The pipeline stage; passes every message on unchanged.
+pub struct Observer {
+    lines: Lines,
+}
+
+impl Observer {
+    /// Listens on `127.0.0.1:<port>` and starts the accept and writer
+    /// threads.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let connected = Arc::new(AtomicUsize::new(0));
+        let (streams, new_streams) = unbounded();
+        let accepted = connected.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                if let Err(e) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+                    warn!("Observer connection failed: {}", e);
+                    continue;
+                }
+                info!(
+                    "Observer connected from {}",
+                    stream
+                        .peer_addr()
+                        .map_or("?".to_string(), |addr| addr.to_string())
+                );
+                accepted.fetch_add(1, Ordering::Relaxed);
+                let _ = streams.send(stream);
+            }
+        });
+        let (sender, receiver) = bounded(QUEUE_LINES);
+        let writing = connected.clone();
+        thread::spawn(move || write_lines(receiver, new_streams, writing));
+        Ok(Self {
+            lines: Lines {
+                sender,
+                connected,
+                dropped: default(),
+            },
+        })
+    }
+
+    /// Sends the log to the same observers; see `common::log_sink`.
+    pub fn log_sink(&self) -> ObserverLog {
+        ObserverLog(self.lines.clone())
+    }
+
+    fn mirror(&self, direction: &str, peer: &str, bytes: &[u8]) {
+        if !self.lines.watched() {
+            return;
+        }
+        let text = String::from_utf8_lossy(bytes);
+        let fields: BTreeMap<&str, &str> = text
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|field| field.split_once('='))
+            .collect();
+        let line = serde_json::json!({
+            "at": (unix_now() * 1000.0).round() / 1000.0,
+            "dir": direction,
+            "peer": peer,
+            "kind": message_kind(&text),
+            "fields": fields,
+            "size": bytes.len(),
+            "text": text,
+        })
+        .to_string();
+        self.lines.send(line);
+    }
+}
+
+pub struct ObserverLog(Lines);
+
+impl LogSink for ObserverLog {
+    fn name(&self) -> &'static str {
+        "observer"
+    }
+
+    fn write(&self, entry: &LogEntry) {
+        if !self.0.watched() {
+            return;
+        }
+        let line = serde_json::json!({
+            "at": (entry.at * 1000.0).round() / 1000.0,
+            "dir": "log",
+            "tag": entry.tag,
+            "text": entry.text,
+        })
+        .to_string();
+        self.0.send(line);
+    }
+}
+
+/// Writes each line to every observer, dropping those that fail. Owns the
+/// connections, so a blocked write only holds up this thread.
+fn write_lines(
+    receiver: Receiver<String>,
+    new_streams: Receiver<TcpStream>,
+    connected: Arc<AtomicUsize>,
+) {
+    let mut observers: Vec<TcpStream> = Vec::new();
+    for mut line in receiver {
+        observers.extend(new_streams.try_iter());
+        line.push('\n');
+        observers.retain_mut(|stream| match stream.write_all(line.as_bytes()) {
+            Ok(()) => true,
+            Err(e) => {
+                info!("Observer disconnected: {}", e);
+                connected.fetch_sub(1, Ordering::Relaxed);
+                false
+            }
+        });
+    }
+}
+
+impl Middleware for Observer {
+    fn name(&self) -> &'static str {
+        "observer"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.mirror("out", peer, &bytes);
+        Some(bytes)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.mirror("in", peer, &bytes);
+        Some(bytes)
+    }
+}
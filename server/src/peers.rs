@@ -0,0 +1,299 @@
+//! Every client we hear from is an entity.
+//!
+//! A peer entity carries [`Peer`], [`PeerAddr`], [`PeerStats`],
+//! [`PeerSession`], a `SendRate` and a `PeerReliable` (plus an `Account`
+//! once it logs in and a `PeerRecording` while its session is recorded), so
+//! the rest of the server (and anyone extending it) can attach its own
+//! components to a client and write ordinary queries over connections. [`PeerIndex`] maps an address back to its entity.
+//!
+//! `spawn_peers` runs before packet handling and creates entities for any
+//! address in the receive queue that doesn't have one yet, so by the time a
+//! packet is handled its peer already exists.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use common::handlers::Heartbeat;
+use common::session::{SessionStats, SessionTotals};
+use std::collections::{HashMap, VecDeque};
+
+use crate::NetworkState;
+use crate::accounts::PeerReliable;
+use crate::client_list::ConnectionState;
+use crate::send_rate::{SendRate, SendRates};
+use crate::sparklines::RateHistory;
+
+/// Rates are measured over this sliding window.
+const RATE_WINDOW_SECS: f32 = 1.0;
+
+#[derive(Component)]
+pub struct Peer;
+
+#[derive(Component, Clone, PartialEq, Eq, Debug)]
+pub struct PeerAddr(pub String);
+
+#[derive(Component)]
+pub struct PeerStats {
+    /// (arrival time, datagram size) for packets inside the rate window
+    recent: VecDeque<(f32, usize)>,
+    /// Time of the last packet of any kind, heartbeats included
+    pub last_heard: f32,
+    /// Time of the last packet that was not a heartbeat
+    pub last_activity: f32,
+    pub state: ConnectionState,
+    /// Smoothed RTT the client last reported in a heartbeat
+    pub rtt: Option<f32>,
+    /// Ping loss the client last reported, 0..=1
+    pub loss: Option<f32>,
+    /// Set when the client said Disconnect or was kicked, so it counts as
+    /// disconnected without waiting out the timeout; cleared by its next
+    /// Hello
+    pub departed: Option<DisconnectReason>,
+    /// The number of the client's last Ping, for a Pong sent from the
+    /// console or PING ALL; 0 before any
+    pub last_ping: u32,
+}
+
+impl PeerStats {
+    fn new(now: f32) -> Self {
+        Self {
+            recent: VecDeque::new(),
+            last_heard: now,
+            last_activity: now,
+            state: ConnectionState::Active,
+            rtt: None,
+            loss: None,
+            departed: None,
+            last_ping: 0,
+        }
+    }
+
+    pub fn record(&mut self, size: usize, is_heartbeat: bool, now: f32) {
+        self.recent.push_back((now, size));
+        self.last_heard = now;
+        if !is_heartbeat {
+            self.last_activity = now;
+        }
+    }
+
+    /// Heartbeats may carry `rtt_ms`, the client's smoothed RTT, and `loss`,
+    /// the share of its Pings that went unanswered.
+    pub fn record_heartbeat_report(&mut self, heartbeat: &Heartbeat) {
+        if let Some(rtt_ms) = heartbeat.rtt_ms {
+            self.rtt = Some(rtt_ms / 1000.0);
+        }
+        if let Some(loss) = heartbeat.loss {
+            self.loss = Some(loss.clamp(0.0, 1.0));
+        }
+    }
+
+    /// Drops packets that have left the rate window.
+    pub fn prune(&mut self, now: f32) {
+        while let Some((at, _)) = self.recent.front() {
+            if now - at > RATE_WINDOW_SECS {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Roughly what the rate window holds on the heap
+    pub fn heap_bytes(&self) -> usize {
+        self.recent.capacity() * std::mem::size_of::<(f32, usize)>()
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn packets_per_sec(&self) -> f32 {
+        self.recent.len() as f32 / RATE_WINDOW_SECS
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn bytes_per_sec(&self) -> f32 {
+        self.recent.iter().map(|(_, size)| *size).sum::<usize>() as f32 / RATE_WINDOW_SECS
+    }
+}
+
+/// The peer's open session, from Hello until it times out, says Hello
+/// again or the server shuts down; see `session`.
+#[derive(Component, Default)]
+pub struct PeerSession {
+    pub open: Option<SessionStats>,
+    /// When the peer timed out while its session is held for a Resume; see
+    /// `resume`
+    pub suspended_since: Option<f32>,
+    /// Asked for in the Hello or Resume with `role=spectator`. A spectator
+    /// gets all replication, but its gameplay messages are dropped.
+    pub spectator: bool,
+    /// Features the peer's protocol version predates, which it isn't sent;
+    /// see `protocol::FEATURES`
+    pub missing_features: Vec<&'static str>,
+    /// Protocol subsets this server or the peer was built without, which
+    /// the session goes without; see `protocol::SUBSETS`
+    pub missing_subsets: Vec<&'static str>,
+    /// What the last closed session came to, for the archive; see `archive`
+    pub last: Option<SessionTotals>,
+}
+
+impl PeerSession {
+    /// Whether the peer is sent what goes with `feature`, a feature or a
+    /// protocol subset.
+    pub fn supports(&self, feature: &str) -> bool {
+        !self.missing_features.contains(&feature) && !self.missing_subsets.contains(&feature)
+    }
+
+    /// Roughly what the open session and the feature lists hold on the heap
+    pub fn heap_bytes(&self) -> usize {
+        let features = self.missing_features.capacity() + self.missing_subsets.capacity();
+        self.open.as_ref().map_or(0, SessionStats::heap_bytes)
+            + features * std::mem::size_of::<&str>()
+    }
+
+    pub fn record_sent(&mut self, kind: &str, size: usize) {
+        if let Some(stats) = &mut self.open {
+            stats.record_sent(kind, size);
+        }
+    }
+
+    pub fn record_received(&mut self, kind: &str, size: usize) {
+        if let Some(stats) = &mut self.open {
+            stats.record_received(kind, size);
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PeerIndex(HashMap<String, Entity>);
+
+impl PeerIndex {
+    pub fn get(&self, addr: &str) -> Option<Entity> {
+        self.0.get(addr).copied()
+    }
+
+    /// Forgets the peer at `addr`; a packet from it spawns a new one
+    pub fn remove(&mut self, addr: &str) -> Option<Entity> {
+        self.0.remove(addr)
+    }
+
+    /// Roughly what the index itself takes
+    pub fn heap_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(String, Entity)>();
+        self.0.capacity() * entry + self.0.keys().map(String::capacity).sum::<usize>()
+    }
+}
+
+/// Looks up a peer's stats and session by address, for systems that are
+/// handed addresses (the receive queue, delayed sends).
+#[derive(SystemParam)]
+pub struct PeersByAddr<'w, 's> {
+    index: Res<'w, PeerIndex>,
+    peers: Query<'w, 's, (&'static mut PeerStats, &'static mut PeerSession), With<Peer>>,
+}
+
+impl PeersByAddr<'_, '_> {
+    pub fn contains(&self, addr: &str) -> bool {
+        self.index.get(addr).is_some()
+    }
+
+    pub fn entity(&self, addr: &str) -> Option<Entity> {
+        self.index.get(addr)
+    }
+
+    /// Every peer's address, in no particular order.
+    pub fn addrs(&self) -> Vec<String> {
+        self.index.0.keys().cloned().collect()
+    }
+
+    pub fn get_mut(&mut self, addr: &str) -> Option<(Mut<'_, PeerStats>, Mut<'_, PeerSession>)> {
+        let entity = self.index.get(addr)?;
+        self.peers.get_mut(entity).ok()
+    }
+
+    /// Continues `previous`'s open session on the peer at `addr`, which is
+    /// the same peer unless the client came back from another address.
+    /// False if there is no session left to continue.
+    pub fn resume_session(&mut self, previous: Entity, addr: &str) -> bool {
+        let Some(current) = self.index.get(addr) else {
+            return false;
+        };
+        let mut session = if previous == current {
+            let Ok((_, session)) = self.peers.get_mut(current) else {
+                return false;
+            };
+            session
+        } else {
+            let Ok([(_, mut old), (_, mut new)]) = self.peers.get_many_mut([previous, current])
+            else {
+                return false;
+            };
+            let Some(mut stats) = old.open.take() else {
+                return false;
+            };
+            old.suspended_since = None;
+            stats.peer = addr.to_string();
+            new.open = Some(stats);
+            new
+        };
+        session.suspended_since = None;
+        session.open.is_some()
+    }
+}
+
+/// Spawns a peer for every queued packet from an address we haven't seen.
+pub fn spawn_peers(
+    mut commands: Commands,
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    rates: Res<SendRates>,
+    mut index: ResMut<PeerIndex>,
+) {
+    let now = time.elapsed_seconds();
+    for addr in network.received_messages.peers() {
+        if index.0.contains_key(&addr) {
+            continue;
+        }
+        let entity = commands
+            .spawn((
+                Peer,
+                PeerAddr(addr.clone()),
+                PeerStats::new(now),
+                PeerSession::default(),
+                SendRate::new(rates.max_hz),
+                PeerReliable::default(),
+                RateHistory::default(),
+            ))
+            .id();
+        index.0.insert(addr, entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::protocol::{self, MIN_SUPPORTED_VERSION, PROTOCOL_VERSION};
+
+    #[test]
+    fn an_old_client_is_not_sent_what_it_goes_without() {
+        let session = PeerSession {
+            missing_features: protocol::missing_features(MIN_SUPPORTED_VERSION),
+            missing_subsets: vec!["chat"],
+            ..Default::default()
+        };
+        assert!(!session.supports("effects"));
+        assert!(!session.supports("config_push"));
+        assert!(!session.supports("chat"));
+        // Anything that isn't a feature or subset goes to everyone
+        assert!(session.supports("replication"));
+    }
+
+    #[test]
+    fn a_current_client_is_sent_everything() {
+        let session = PeerSession {
+            missing_features: protocol::missing_features(PROTOCOL_VERSION),
+            ..Default::default()
+        };
+        for feature in protocol::FEATURES {
+            assert!(session.supports(feature.name));
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Tick profiler: where the networking time goes each frame.
+//!
+//! The networking systems add what they spend to [`TickProfile`], split by
+//! [`Stage`]. At the end of the frame the totals become `profile/*`
+//! diagnostics in milliseconds, so they also show up in anything that reads
+//! Bevy diagnostics. Replication build time comes from
+//! `common::replicate`. F5 toggles a panel with the smoothed and peak
+//! values, which is the place to look when a stress test stops scaling.
+//! Below them the panel shows what the peers' state takes in memory (see
+//! `archive`) and breaks the traffic down by message type (see
+//! `common::bandwidth`), for when it's the memory or the bandwidth that
+//! stops scaling.
+
+#[cfg(feature = "gui")]
+use bevy::diagnostic::{Diagnostic, DiagnosticsStore};
+use bevy::diagnostic::{DiagnosticPath, Diagnostics};
+use bevy::prelude::*;
+#[cfg(feature = "gui")]
+use common::bandwidth::BandwidthStats;
+#[cfg(feature = "gui")]
+use common::replicate::REPLICATION_BUILD;
+use std::time::Duration;
+
+#[cfg(feature = "gui")]
+use crate::archive::ClientArchive;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    /// Locking the receive queue and taking batches off it
+    ReceiveDrain,
+    /// Turning raw datagrams into messages
+    Decode,
+    /// Connection states, timeouts and held sessions
+    SessionUpdate,
+    /// Replication and delayed replies going out
+    Send,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [
+        Stage::ReceiveDrain,
+        Stage::Decode,
+        Stage::SessionUpdate,
+        Stage::Send,
+    ];
+
+    pub const fn path(self) -> DiagnosticPath {
+        match self {
+            Stage::ReceiveDrain => DiagnosticPath::const_new("profile/receive_drain_ms"),
+            Stage::Decode => DiagnosticPath::const_new("profile/decode_ms"),
+            Stage::SessionUpdate => DiagnosticPath::const_new("profile/session_update_ms"),
+            Stage::Send => DiagnosticPath::const_new("profile/send_ms"),
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    fn label(self) -> &'static str {
+        match self {
+            Stage::ReceiveDrain => "receive drain",
+            Stage::Decode => "decode",
+            Stage::SessionUpdate => "session update",
+            Stage::Send => "send",
+        }
+    }
+}
+
+/// Time spent per stage this frame.
+#[derive(Resource, Default)]
+pub struct TickProfile {
+    spent: [Duration; Stage::ALL.len()],
+    /// Since startup
+    totals: [Duration; Stage::ALL.len()],
+    /// Since startup, all of `handle_network_messages`, decoding included
+    handling: Duration,
+    /// Since startup, the part of handling that updates each packet's
+    /// session: its stats, a new session's start and the received counts
+    session_updates: Duration,
+}
+
+impl TickProfile {
+    pub fn add(&mut self, stage: Stage, spent: Duration) {
+        self.spent[stage as usize] += spent;
+        self.totals[stage as usize] += spent;
+    }
+
+    /// What `stage` has taken since startup
+    pub fn total(&self, stage: Stage) -> Duration {
+        self.totals[stage as usize]
+    }
+
+    pub fn add_handling(&mut self, spent: Duration) {
+        self.handling += spent;
+    }
+
+    /// What handling packets has taken since startup
+    pub fn handling(&self) -> Duration {
+        self.handling
+    }
+
+    pub fn add_session_update(&mut self, spent: Duration) {
+        self.session_updates += spent;
+    }
+
+    /// What updating sessions while handling packets has taken since startup
+    pub fn session_updates(&self) -> Duration {
+        self.session_updates
+    }
+}
+
+/// Runs in `Last`, after every stage has reported.
+pub fn flush_tick_profile(mut profile: ResMut<TickProfile>, mut diagnostics: Diagnostics) {
+    for stage in Stage::ALL {
+        let spent = std::mem::take(&mut profile.spent[stage as usize]);
+        diagnostics.add_measurement(&stage.path(), || spent.as_secs_f64() * 1000.0);
+    }
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ProfilerPanel;
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ProfilerText;
+
+#[cfg(feature = "gui")]
+pub fn setup_profiler_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(50.0),
+                    left: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            ProfilerPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Tick profile, ms per frame (F5 to close)",
+                TextStyle {
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                ProfilerText,
+            ));
+        });
+}
+
+#[cfg(feature = "gui")]
+pub fn toggle_profiler_panel(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut panels: Query<&mut Visibility, With<ProfilerPanel>>,
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+    for mut visibility in panels.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+/// Message types listed before the rest are lumped together
+#[cfg(feature = "gui")]
+const BANDWIDTH_ROWS: usize = 10;
+
+#[cfg(feature = "gui")]
+pub fn update_profiler_panel(
+    store: Res<DiagnosticsStore>,
+    bandwidth: Res<BandwidthStats>,
+    archive: Res<ClientArchive>,
+    panels: Query<&Visibility, With<ProfilerPanel>>,
+    mut texts: Query<&mut Text, With<ProfilerText>>,
+) {
+    if panels
+        .iter()
+        .all(|visibility| *visibility == Visibility::Hidden)
+    {
+        return;
+    }
+    let rows = Stage::ALL
+        .into_iter()
+        .map(|stage| (stage.label(), stage.path()))
+        .chain(std::iter::once(("replication build", REPLICATION_BUILD)));
+    let mut lines: Vec<String> = rows
+        .map(|(label, path)| {
+            let diagnostic = store.get(&path);
+            let smoothed = diagnostic.and_then(Diagnostic::smoothed).unwrap_or(0.0);
+            let peak = diagnostic
+                .map(|diagnostic| diagnostic.values().fold(0.0, |peak: f64, v| peak.max(*v)))
+                .unwrap_or(0.0);
+            format!("{:<18} {:>7.3}   peak {:>7.3}", label, smoothed, peak)
+        })
+        .collect();
+    lines.push(String::new());
+    lines.push("Networking state, estimated".to_string());
+    lines.extend(archive.panel_lines(&store));
+    lines.push(String::new());
+    lines.push("Bandwidth by message type, since startup".to_string());
+    lines.extend(bandwidth.table(BANDWIDTH_ROWS));
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,96 @@
+//! The connectionless server query: a `ServerInfoRequest` is answered with
+//! a `ServerInfo` (see `common::handlers::ServerQuery`) by the UDP receive
+//! thread it arrives on. It never reaches `handle_network_messages`, so it
+//! spawns no peer, starts no session and doesn't show in the client list;
+//! a server list or a matchmaker can ask as often as it likes. The access
+//! lists and the middleware still see it first, like any packet.
+//!
+//! The threads can't read the ECS, so [`QueryInfo`] holds what they answer
+//! with: the name and mode, fixed at startup, and the player counts,
+//! which [`publish_player_count`] copies in whenever they change. The
+//! uptime is worked out for each answer.
+//!
+//! An answer is several times the size of its request, and anyone can put
+//! someone else's address on a UDP request, so an open query port could
+//! be used to flood a third party. Answers are capped at `--query-rate` a
+//! second across every sender; the rest are dropped and recorded in the
+//! audit log as `reject (query rate)`. `--query-rate 0` answers none.
+
+use bevy::prelude::*;
+use common::handlers::{NetMessage, ServerInfo, ServerInfoRequest};
+use common::players::PlayerCount;
+use common::protocol::PROTOCOL_VERSION;
+use common::session::message_kind;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Shared {
+    name: String,
+    /// `open`, or `tickets` with `--ticket-key`
+    mode: &'static str,
+    players: u32,
+    spectators: u32,
+    started: Instant,
+    /// `--query-rate`
+    rate: u32,
+    /// When the current second of answers began, and how many it has had
+    window: Instant,
+    answered: u32,
+}
+
+#[derive(Resource, Clone)]
+pub struct QueryInfo(Arc<Mutex<Shared>>);
+
+impl QueryInfo {
+    pub fn new(name: String, tickets: bool, rate: u32) -> Self {
+        let now = Instant::now();
+        Self(Arc::new(Mutex::new(Shared {
+            name,
+            mode: if tickets { "tickets" } else { "open" },
+            players: 0,
+            spectators: 0,
+            started: now,
+            rate,
+            window: now,
+            answered: 0,
+        })))
+    }
+
+    /// The `ServerInfo` to send back, or `None` when this second's answers
+    /// are used up.
+    pub fn answer(&self) -> Option<String> {
+        let mut shared = self.0.lock().unwrap();
+        let now = Instant::now();
+        if now.duration_since(shared.window) >= Duration::from_secs(1) {
+            shared.window = now;
+            shared.answered = 0;
+        }
+        if shared.answered >= shared.rate {
+            return None;
+        }
+        shared.answered += 1;
+        let info = ServerInfo {
+            name: shared.name.clone(),
+            version: PROTOCOL_VERSION,
+            players: shared.players,
+            spectators: shared.spectators,
+            mode: shared.mode.to_string(),
+            uptime_secs: shared.started.elapsed().as_secs(),
+        };
+        Some(info.message())
+    }
+}
+
+/// Whether a datagram, as it came out of the middleware, is a query
+pub fn is_query(bytes: &[u8]) -> bool {
+    std::str::from_utf8(bytes).is_ok_and(|text| message_kind(text) == ServerInfoRequest::KIND)
+}
+
+pub fn publish_player_count(players: Res<PlayerCount>, info: Res<QueryInfo>) {
+    if !players.is_changed() {
+        return;
+    }
+    let mut shared = info.0.lock().unwrap();
+    shared.players = players.connected as u32;
+    shared.spectators = players.spectators as u32;
+}
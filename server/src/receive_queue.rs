@@ -0,0 +1,223 @@
+//! The receive queue, one queue per peer.
+//!
+//! A single first-in, first-out queue lets a client that floods the server
+//! fill the whole frame budget, so everyone else's packets wait behind its
+//! backlog. Instead every peer gets a queue of its own, and
+//! `handle_network_messages` takes from them round-robin: one packet from
+//! each peer with any waiting, then around again, until the frame budget
+//! runs out or every peer has had `--peer-packet-budget` packets this frame.
+//! A peer's packets stay in their arrival order. A peer already holding
+//! `--peer-queue-limit` packets has new ones dropped, so a flood costs the
+//! flooder its own packets and not memory.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::inbound::RawPacket;
+
+#[derive(Default)]
+struct Queues {
+    by_peer: HashMap<String, VecDeque<RawPacket>>,
+    /// Peers with packets waiting, the next one to take from first
+    turns: VecDeque<String>,
+}
+
+/// Cheap to clone; the receive threads hold one each.
+#[derive(Clone)]
+pub struct ReceiveQueue {
+    queues: Arc<Mutex<Queues>>,
+    /// `--peer-queue-limit`
+    limit: usize,
+}
+
+impl ReceiveQueue {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            queues: Arc::default(),
+            limit,
+        }
+    }
+
+    /// False, and the packet is dropped, if its peer's queue is full.
+    pub fn push(&self, packet: RawPacket) -> bool {
+        let mut queues = self.queues.lock().unwrap();
+        let Queues { by_peer, turns } = &mut *queues;
+        match by_peer.entry(packet.addr.clone()) {
+            Entry::Occupied(mut queue) => {
+                if queue.get().len() >= self.limit {
+                    return false;
+                }
+                queue.get_mut().push_back(packet);
+            }
+            Entry::Vacant(entry) => {
+                turns.push_back(packet.addr.clone());
+                entry.insert(VecDeque::from([packet]));
+            }
+        }
+        true
+    }
+
+    /// Every peer with packets waiting
+    pub fn peers(&self) -> Vec<String> {
+        self.queues.lock().unwrap().turns.iter().cloned().collect()
+    }
+
+    /// Takes up to `max` packets round-robin, from peers that are `ready`
+    /// and have had fewer than `per_peer` this frame (no limit when it is
+    /// 0), counting them in `taken`. Peers that aren't ready keep their
+    /// place in the round.
+    pub fn take(
+        &self,
+        max: usize,
+        per_peer: usize,
+        taken: &mut HashMap<String, usize>,
+        ready: impl Fn(&str) -> bool,
+    ) -> Vec<RawPacket> {
+        let mut queues = self.queues.lock().unwrap();
+        let Queues { by_peer, turns } = &mut *queues;
+        let mut batch = Vec::new();
+        // Turns since one last gave a packet; a whole round of none ends it
+        let mut idle_turns = 0;
+        while batch.len() < max && idle_turns < turns.len() {
+            let Some(addr) = turns.pop_front() else {
+                break;
+            };
+            let count = taken.get(&addr).copied().unwrap_or(0);
+            if (per_peer > 0 && count >= per_peer) || !ready(&addr) {
+                turns.push_back(addr);
+                idle_turns += 1;
+                continue;
+            }
+            idle_turns = 0;
+            // Every turn has a queue; one without just loses its turn
+            let Some(queue) = by_peer.get_mut(&addr) else {
+                continue;
+            };
+            batch.extend(queue.pop_front());
+            if queue.is_empty() {
+                by_peer.remove(&addr);
+            } else {
+                turns.push_back(addr.clone());
+            }
+            *taken.entry(addr).or_default() += 1;
+        }
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stream;
+
+    const LIMIT: usize = 64;
+    const NORMAL_PEERS: usize = 8;
+    const NORMAL_PACKETS: usize = 3;
+    const PER_PEER: usize = 4;
+
+    fn packet(addr: &str, seq: usize) -> RawPacket {
+        RawPacket {
+            bytes: format!("Ping {}", seq).into_bytes(),
+            addr: addr.to_string(),
+            stream: Stream::Control,
+        }
+    }
+
+    fn normal(peer: usize) -> String {
+        format!("10.0.0.{}:5000", peer + 1)
+    }
+
+    /// A flooder that got there first, then peers sending a few packets each
+    fn flooded() -> (ReceiveQueue, usize) {
+        let queue = ReceiveQueue::new(LIMIT);
+        let kept = (0..1000)
+            .filter(|seq| queue.push(packet("6.6.6.6:666", *seq)))
+            .count();
+        for seq in 0..NORMAL_PACKETS {
+            for peer in 0..NORMAL_PEERS {
+                assert!(queue.push(packet(&normal(peer), seq)));
+            }
+        }
+        (queue, kept)
+    }
+
+    #[test]
+    fn a_flooder_only_fills_its_own_queue() {
+        let (queue, kept) = flooded();
+        assert_eq!(kept, LIMIT);
+        assert_eq!(queue.peers().len(), NORMAL_PEERS + 1);
+    }
+
+    #[test]
+    fn a_flooder_gets_its_budget_and_everyone_else_their_turn() {
+        let (queue, _) = flooded();
+        let mut taken = HashMap::new();
+        let batch = queue.take(usize::MAX, PER_PEER, &mut taken, |_| true);
+
+        // Round-robin in order of arrival: the flooder, then each peer, again
+        let first_round: Vec<&str> = batch[..=NORMAL_PEERS]
+            .iter()
+            .map(|packet| packet.addr.as_str())
+            .collect();
+        let expected: Vec<String> = std::iter::once("6.6.6.6:666".to_string())
+            .chain((0..NORMAL_PEERS).map(normal))
+            .collect();
+        assert_eq!(first_round, expected);
+
+        assert_eq!(taken["6.6.6.6:666"], PER_PEER);
+        for peer in 0..NORMAL_PEERS {
+            assert_eq!(taken[&normal(peer)], NORMAL_PACKETS);
+            // Each peer's packets stay in the order they came
+            let seqs: Vec<&[u8]> = batch
+                .iter()
+                .filter(|packet| packet.addr == normal(peer))
+                .map(|packet| &packet.bytes[..])
+                .collect();
+            assert_eq!(seqs, [b"Ping 0", b"Ping 1", b"Ping 2"]);
+        }
+        assert_eq!(batch.len(), PER_PEER + NORMAL_PEERS * NORMAL_PACKETS);
+
+        // Only the flooder is left, and it waits for the next frame
+        assert_eq!(queue.peers(), ["6.6.6.6:666"]);
+        assert!(
+            queue
+                .take(usize::MAX, PER_PEER, &mut taken, |_| true)
+                .is_empty()
+        );
+        let mut next_frame = HashMap::new();
+        let batch = queue.take(usize::MAX, PER_PEER, &mut next_frame, |_| true);
+        assert_eq!(batch.len(), PER_PEER);
+        assert_eq!(batch[0].bytes, b"Ping 4");
+    }
+
+    #[test]
+    fn a_small_frame_budget_still_goes_around() {
+        let (queue, _) = flooded();
+        let mut taken = HashMap::new();
+        let batch = queue.take(NORMAL_PEERS + 1, 0, &mut taken, |_| true);
+        let peers: std::collections::HashSet<&str> =
+            batch.iter().map(|packet| packet.addr.as_str()).collect();
+        assert_eq!(peers.len(), NORMAL_PEERS + 1);
+    }
+
+    #[test]
+    fn peers_that_are_not_ready_keep_their_place() {
+        let (queue, _) = flooded();
+        let mut taken = HashMap::new();
+        let busy = normal(0);
+        let batch = queue.take(usize::MAX, PER_PEER, &mut taken, |addr| addr != busy);
+        assert!(batch.iter().all(|packet| packet.addr != busy));
+        let mut waiting = queue.peers();
+        waiting.sort();
+        assert_eq!(waiting, [busy.clone(), "6.6.6.6:666".to_string()]);
+        // Its packets are all still there, in order, for the next frame
+        let batch = queue.take(usize::MAX, PER_PEER, &mut HashMap::new(), |_| true);
+        let seqs: Vec<&[u8]> = batch
+            .iter()
+            .filter(|packet| packet.addr == busy)
+            .map(|packet| &packet.bytes[..])
+            .collect();
+        assert_eq!(seqs, [b"Ping 0", b"Ping 1", b"Ping 2"]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Replay files of whole sessions, with `--record-sessions <dir>`.
+//!
+//! Every session gets its own file in the directory (see
+//! `common::recording` for the format): each message the client sent,
+//! heartbeats aside, and each replication message the server sent it. The
+//! file is started when the session opens and closed when it ends. Play one
+//! back with the client's `--play-replay`.
+
+use bevy::prelude::*;
+use common::recording::{Recorder, Track};
+use common::replicate::ReplicationOutgoing;
+use std::path::PathBuf;
+
+use crate::ServerState;
+use crate::peers::{PeerAddr, PeerIndex, PeerSession};
+
+/// A message from a peer, as received; see `handle_network_messages`.
+#[derive(Event)]
+pub struct InputReceived {
+    pub from: String,
+    pub text: String,
+    pub at: f32,
+}
+
+/// Does nothing without `--record-sessions`.
+#[derive(Resource, Default)]
+pub struct SessionRecordings {
+    pub dir: Option<PathBuf>,
+}
+
+/// The replay file of the peer's open session.
+#[derive(Component)]
+pub struct PeerRecording {
+    recorder: Recorder,
+    /// `started_at` of the session being recorded
+    session_started: f32,
+}
+
+impl PeerRecording {
+    fn record(&mut self, now: f32, track: Track, text: &str) {
+        let at = (now - self.session_started).max(0.0);
+        if let Err(e) = self.recorder.record(at, track, text) {
+            warn!(
+                "Could not write replay {}: {}",
+                self.recorder.path().display(),
+                e
+            );
+        }
+    }
+}
+
+/// Starts a file when a session opens and closes it when the session ends.
+pub fn start_recordings(
+    mut commands: Commands,
+    recordings: Res<SessionRecordings>,
+    peers: Query<(Entity, &PeerAddr, &PeerSession, Option<&PeerRecording>), Changed<PeerSession>>,
+    server_state: Res<ServerState>,
+) {
+    let Some(dir) = &recordings.dir else {
+        return;
+    };
+    for (entity, PeerAddr(addr), session, recording) in peers.iter() {
+        let started = session.open.as_ref().map(|open| open.started_at);
+        if let Some(recording) = recording {
+            if started == Some(recording.session_started) {
+                continue;
+            }
+            commands.entity(entity).remove::<PeerRecording>();
+            server_state.log.push(format!(
+                "[Replay]: saved {}",
+                recording.recorder.path().display()
+            ));
+        }
+        let Some(started) = started else {
+            continue;
+        };
+        match Recorder::create(dir, addr) {
+            Ok(recorder) => {
+                commands.entity(entity).insert(PeerRecording {
+                    recorder,
+                    session_started: started,
+                });
+            }
+            Err(e) => server_state.log.push(format!(
+                "[Error]: could not record session of {}: {}",
+                addr, e
+            )),
+        }
+    }
+}
+
+pub fn record_inputs(
+    index: Res<PeerIndex>,
+    mut inputs: EventReader<InputReceived>,
+    mut peers: Query<&mut PeerRecording>,
+) {
+    for input in inputs.read() {
+        let Some(mut recording) = index
+            .get(&input.from)
+            .and_then(|peer| peers.get_mut(peer).ok())
+        else {
+            continue;
+        };
+        recording.record(input.at, Track::Input, &input.text);
+    }
+}
+
+/// Records replication as it is queued rather than as it goes out, so a
+/// replay holds every change even for a peer on a slow send rate.
+pub fn record_state_changes(
+    time: Res<Time>,
+    index: Res<PeerIndex>,
+    mut outgoing: EventReader<ReplicationOutgoing>,
+    mut peers: Query<&mut PeerRecording>,
+) {
+    let now = time.elapsed_seconds();
+    for message in outgoing.read() {
+        match &message.to {
+            Some(peer) => {
+                let Some(mut recording) = index.get(peer).and_then(|peer| peers.get_mut(peer).ok())
+                else {
+                    continue;
+                };
+                recording.record(now, Track::State, &message.text);
+            }
+            None => {
+                for mut recording in peers.iter_mut() {
+                    recording.record(now, Track::State, &message.text);
+                }
+            }
+        }
+    }
+}
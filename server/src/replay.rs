@@ -0,0 +1,231 @@
+//! Received-message history with replay.
+//!
+//! The last `--message-history` decoded messages are kept in full. F3 opens a
+//! panel listing them; Up/Down selects one and shows every decoded field, and
+//! R puts the selected message back on the receive queue so
+//! `handle_network_messages` handles it again exactly as if it had just
+//! arrived. Handy for poking at the protocol without a client.
+
+use bevy::prelude::*;
+#[cfg(feature = "gui")]
+use common::ui::text_input::TextInput;
+use std::collections::VecDeque;
+
+use crate::Stream;
+#[cfg(feature = "gui")]
+use crate::inbound::RawPacket;
+use crate::inbound::{Decoded, MessageKind};
+#[cfg(feature = "gui")]
+use crate::metrics::Metric;
+#[cfg(feature = "gui")]
+use crate::{NetworkState, ServerState};
+
+/// Entries listed around the selection
+#[cfg(feature = "gui")]
+const PANEL_ROWS: usize = 10;
+
+pub struct RecordedMessage {
+    pub at: f32,
+    pub addr: String,
+    pub stream: Stream,
+    pub size: usize,
+    pub kind: MessageKind,
+    pub text: String,
+}
+
+#[derive(Resource)]
+pub struct MessageHistory {
+    entries: VecDeque<RecordedMessage>,
+    capacity: usize,
+    /// Index into `entries`; 0 is the oldest
+    selected: usize,
+}
+
+impl MessageHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            selected: 0,
+        }
+    }
+
+    pub fn record(&mut self, decoded: &Decoded, at: f32) {
+        if self.capacity == 0 {
+            return;
+        }
+        let following_newest = self.selected + 1 >= self.entries.len();
+        self.entries.push_back(RecordedMessage {
+            at,
+            addr: decoded.addr.clone(),
+            stream: decoded.stream,
+            size: decoded.size,
+            kind: decoded.kind,
+            text: decoded.text.clone(),
+        });
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+            self.selected = self.selected.saturating_sub(1);
+        }
+        // Stay on the newest entry unless the user has scrolled back
+        if following_newest {
+            self.selected = self.entries.len() - 1;
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    fn selected(&self) -> Option<&RecordedMessage> {
+        self.entries.get(self.selected)
+    }
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ReplayPanel;
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct ReplayText;
+
+#[cfg(feature = "gui")]
+pub fn setup_replay_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(50.0),
+                    left: Val::Px(10.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::rgba(0.05, 0.05, 0.05, 0.9).into(),
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            ReplayPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Received messages (F3 to close, Up/Down to select, R to replay)",
+                TextStyle {
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                ReplayText,
+            ));
+        });
+}
+
+/// F3 toggles the panel; while it's open, Up/Down move the selection and R
+/// replays it. Keys are ignored while a text field has focus.
+#[cfg(feature = "gui")]
+pub fn replay_panel_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    inputs: Query<&TextInput>,
+    mut panels: Query<&mut Visibility, With<ReplayPanel>>,
+    mut history: ResMut<MessageHistory>,
+    network: Res<NetworkState>,
+    server_state: Res<ServerState>,
+) {
+    if inputs.iter().any(|input| input.focused) {
+        return;
+    }
+    let Ok(mut visibility) = panels.get_single_mut() else {
+        return;
+    };
+    if keys.just_pressed(KeyCode::F3) {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        history.selected = history.selected.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) && history.selected + 1 < history.entries.len() {
+        history.selected += 1;
+    }
+    if keys.just_pressed(KeyCode::KeyR)
+        && let Some(message) = history.selected()
+    {
+        let packet = RawPacket {
+            bytes: message.text.as_bytes().to_vec(),
+            addr: message.addr.clone(),
+            stream: message.stream,
+        };
+        let line = if network.received_messages.push(packet) {
+            network.metrics.add(Metric::Queued, 1);
+            format!("[Info]: Replaying {:?} from {}", message.kind, message.addr)
+        } else {
+            format!(
+                "[Warn]: {}'s receive queue is full, not replaying",
+                message.addr
+            )
+        };
+        server_state.log.push(line);
+    }
+}
+
+#[cfg(feature = "gui")]
+pub fn update_replay_panel(
+    history: Res<MessageHistory>,
+    mut texts: Query<&mut Text, With<ReplayText>>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+
+    let first = history
+        .selected
+        .saturating_sub(PANEL_ROWS / 2)
+        .min(history.entries.len().saturating_sub(PANEL_ROWS));
+    let mut lines: Vec<String> = history
+        .entries
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(PANEL_ROWS)
+        .map(|(index, message)| {
+            let marker = if index == history.selected { ">" } else { " " };
+            format!(
+                "{} {:>8.2}s  {}  {:?}",
+                marker, message.at, message.addr, message.kind
+            )
+        })
+        .collect();
+
+    match history.selected() {
+        Some(message) => {
+            lines.push(String::new());
+            lines.push(format!("time:   {:.3}s", message.at));
+            lines.push(format!("peer:   {}", message.addr));
+            lines.push(format!("stream: {}", message.stream.label()));
+            lines.push(format!("size:   {} bytes", message.size));
+            lines.push(format!("kind:   {:?}", message.kind));
+            lines.push(format!("text:   {:?}", message.text));
+        }
+        None => lines.push("Nothing received yet".to_string()),
+    }
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,128 @@
+//! Session resumption.
+//!
+//! Every Welcome carries a token. A client that loses the connection
+//! presents it in a `Resume` instead of a Hello, and if the server has held
+//! its session (`--resume-grace` seconds from the timeout) the session
+//! carries on where it left off: same stats, no new connect notice, and no
+//! new session summary. The peer may even come back from another address.
+//!
+//! Tokens are 16 bytes from the OS's random source, in hex, and single-use;
+//! the reply to a Resume carries a fresh one. A client that quits or is
+//! kicked loses its token.
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use common::disconnect::{DisconnectReason, Disconnected};
+
+use crate::ServerState;
+use crate::peers::{PeerAddr, PeerSession};
+use crate::profiler::{Stage, TickProfile};
+use crate::session::Sessions;
+use crate::webhook::Webhook;
+
+/// Random bytes in a token, as many as a session id needs not to be guessed
+const TOKEN_BYTES: usize = 16;
+
+#[derive(Resource)]
+pub struct Resumptions {
+    /// `--resume-grace`; 0 ends sessions on timeout as before
+    pub grace_secs: f32,
+    tokens: HashMap<String, Entity>,
+}
+
+impl Resumptions {
+    pub fn new(grace_secs: f32) -> Self {
+        Self {
+            grace_secs,
+            tokens: HashMap::new(),
+        }
+    }
+
+    /// A new token for `peer`, replacing any it had. `None` when the OS
+    /// has no randomness to give, and the peer then can't resume.
+    pub fn issue(&mut self, peer: Entity) -> Option<String> {
+        self.revoke(peer);
+        let mut bytes = [0u8; TOKEN_BYTES];
+        if let Err(e) = getrandom::getrandom(&mut bytes) {
+            warn!("No randomness for a resume token: {}", e);
+            return None;
+        }
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.tokens.insert(token.clone(), peer);
+        Some(token)
+    }
+
+    /// The peer a token was issued to. The token can't be used again.
+    pub fn redeem(&mut self, token: &str) -> Option<Entity> {
+        self.tokens.remove(token)
+    }
+
+    pub fn revoke(&mut self, peer: Entity) {
+        self.tokens.retain(|_, holder| *holder != peer);
+    }
+
+    pub fn has_token(&self, peer: Entity) -> bool {
+        self.tokens.values().any(|holder| *holder == peer)
+    }
+}
+
+/// Ends sessions that were held for resumption and weren't resumed in time,
+/// and reports the timeout that was held back.
+pub fn expire_suspended_sessions(
+    time: Res<Time>,
+    sessions: Res<Sessions>,
+    mut resumptions: ResMut<Resumptions>,
+    mut peers: Query<(Entity, &PeerAddr, &mut PeerSession)>,
+    server_state: Res<ServerState>,
+    webhook: Res<Webhook>,
+    mut disconnected: EventWriter<Disconnected>,
+    mut profile: ResMut<TickProfile>,
+) {
+    let started = Instant::now();
+    let now = time.elapsed_seconds();
+    for (entity, addr, mut session) in peers.iter_mut() {
+        let Some(since) = session.suspended_since else {
+            continue;
+        };
+        if now - since <= resumptions.grace_secs {
+            continue;
+        }
+        resumptions.revoke(entity);
+        let reason = DisconnectReason::Timeout;
+        sessions.end(&mut session, now, &reason.to_string(), &server_state);
+        webhook.post(format!("Client {} disconnected ({})", addr.0, reason));
+        disconnected.send(Disconnected {
+            peer: addr.0.clone(),
+            reason,
+        });
+        server_state.log.push(format!(
+            "[Info]: {} did not resume within {}s",
+            addr.0, resumptions.grace_secs
+        ));
+    }
+    profile.add(Stage::SessionUpdate, started.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_random_and_single_use() {
+        let mut resumptions = Resumptions::new(30.0);
+        let peer = Entity::from_raw(1);
+        let first = resumptions.issue(peer).unwrap();
+        assert_eq!(first.len(), TOKEN_BYTES * 2);
+        assert!(first.bytes().all(|byte| byte.is_ascii_hexdigit()));
+
+        // A new token replaces the old one
+        let second = resumptions.issue(peer).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(resumptions.redeem(&first), None);
+        assert_eq!(resumptions.redeem(&second), Some(peer));
+        assert_eq!(resumptions.redeem(&second), None);
+        assert!(!resumptions.has_token(peer));
+    }
+}
@@ -0,0 +1,122 @@
+//! Sender-side delay injection.
+//!
+//! `--delay-pongs 200ms` (and the same for Welcomes and heartbeat acks) holds
+//! a reply back before it is sent, reproducing a server that is slow to
+//! respond rather than a slow network. Replies go through
+//! [`DelayedSends`]; with no delay configured they are simply sent on the
+//! same frame. The packet timeline marks a reply when it actually leaves.
+
+use bevy::prelude::*;
+use std::time::{Duration, Instant};
+
+use crate::peers::PeersByAddr;
+use crate::profiler::{Stage, TickProfile};
+#[cfg(feature = "gui")]
+use crate::timeline::{Direction, PacketTimeline};
+use crate::{NetworkState, Stream};
+
+/// Parses `200ms`, `1.5s` or a bare number of milliseconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        (secs, 1.0)
+    } else {
+        (value, 0.001)
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|e| format!("'{}': {}", value, e))?;
+    if number < 0.0 {
+        return Err(format!("'{}': delay can't be negative", value));
+    }
+    Ok(Duration::from_secs_f64(number * scale))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reply {
+    Welcome,
+    HeartbeatAck,
+    Pong,
+}
+
+impl Reply {
+    /// Handshake traffic stays on the control socket.
+    fn stream(self) -> Stream {
+        match self {
+            Reply::Welcome | Reply::HeartbeatAck => Stream::Control,
+            Reply::Pong => Stream::Data,
+        }
+    }
+}
+
+struct Delayed {
+    due: f32,
+    reply: Reply,
+    bytes: Vec<u8>,
+    addr: String,
+}
+
+#[derive(Resource, Default)]
+pub struct DelayedSends {
+    pub welcome_delay: f32,
+    pub heartbeat_ack_delay: f32,
+    pub pong_delay: f32,
+    queue: Vec<Delayed>,
+}
+
+impl DelayedSends {
+    pub fn new(welcome: Duration, heartbeat_ack: Duration, pong: Duration) -> Self {
+        Self {
+            welcome_delay: welcome.as_secs_f32(),
+            heartbeat_ack_delay: heartbeat_ack.as_secs_f32(),
+            pong_delay: pong.as_secs_f32(),
+            queue: Vec::new(),
+        }
+    }
+
+    /// Schedules `reply` for now plus its configured delay.
+    pub fn push(&mut self, reply: Reply, bytes: &[u8], addr: &str, now: f32) {
+        let delay = match reply {
+            Reply::Welcome => self.welcome_delay,
+            Reply::HeartbeatAck => self.heartbeat_ack_delay,
+            Reply::Pong => self.pong_delay,
+        };
+        self.queue.push(Delayed {
+            due: now + delay,
+            reply,
+            bytes: bytes.to_vec(),
+            addr: addr.to_string(),
+        });
+    }
+}
+
+pub fn flush_delayed_sends(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut delayed: ResMut<DelayedSends>,
+    #[cfg(feature = "gui")] mut timeline: ResMut<PacketTimeline>,
+    mut peers: PeersByAddr,
+    mut profile: ResMut<TickProfile>,
+) {
+    let started = Instant::now();
+    let now = time.elapsed_seconds();
+    delayed.queue.retain(|send| {
+        if send.due > now {
+            return true;
+        }
+        match send.reply.stream() {
+            Stream::Control | Stream::Tcp => network.send_control(&send.bytes, &send.addr),
+            Stream::Data => network.send_gameplay(&send.bytes, &send.addr),
+        }
+        if let Some((_, mut session)) = peers.get_mut(&send.addr) {
+            session.record_sent(&format!("{:?}", send.reply), send.bytes.len());
+            #[cfg(feature = "gui")]
+            timeline.record(&send.addr, Direction::Tx, now);
+        }
+        false
+    });
+    profile.add(Stage::Send, started.elapsed());
+}
@@ -0,0 +1,133 @@
+//! Adaptive send rate: how often each client gets replication updates.
+//!
+//! Updates for a peer queue up in its [`SendRate`] and go out together, at
+//! a rate somewhere between `--min-send-rate` and `--max-send-rate`. A peer
+//! with low RTT and no loss gets the maximum, so a LAN client sees every
+//! change almost as it happens. As the RTT the client reports approaches
+//! [`SLOW_RTT_SECS`] or its loss approaches [`BAD_LOSS`], the rate falls
+//! towards the minimum, so a laggy link isn't buried under updates it can't
+//! keep up with. Queued messages are merged with
+//! `common::replicate::coalesce`, so a slower peer gets fewer, not stale,
+//! messages. Each message is numbered as it leaves, so the client can tell
+//! when one was lost and ask for that resource again.
+
+use bevy::prelude::*;
+use common::replicate::{ReplicationSequence, coalesce};
+use std::time::Instant;
+
+use crate::NetworkState;
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerAddr, PeerStats};
+use crate::profiler::{Stage, TickProfile};
+
+/// RTT at which a peer is down to the minimum rate
+pub const SLOW_RTT_SECS: f32 = 0.25;
+/// Ping loss at which a peer is down to the minimum rate
+pub const BAD_LOSS: f32 = 0.2;
+
+#[derive(Resource)]
+pub struct SendRates {
+    /// Updates per second for the worst links
+    pub min_hz: f32,
+    /// Updates per second for the best links, and for peers that haven't
+    /// reported their RTT yet
+    pub max_hz: f32,
+}
+
+impl SendRates {
+    pub fn new(min_hz: f32, max_hz: f32) -> Self {
+        Self {
+            min_hz: min_hz.max(0.1),
+            max_hz: max_hz.max(min_hz.max(0.1)),
+        }
+    }
+
+    /// 1 for a perfect link, 0 at or past the slow RTT or the bad loss.
+    fn link_quality(stats: &PeerStats) -> f32 {
+        let rtt = stats.rtt.map_or(1.0, |rtt| 1.0 - rtt / SLOW_RTT_SECS);
+        let loss = stats.loss.map_or(1.0, |loss| 1.0 - loss / BAD_LOSS);
+        rtt.clamp(0.0, 1.0) * loss.clamp(0.0, 1.0)
+    }
+
+    pub fn rate_for(&self, stats: &PeerStats) -> f32 {
+        self.min_hz + (self.max_hz - self.min_hz) * Self::link_quality(stats)
+    }
+}
+
+#[derive(Component)]
+pub struct SendRate {
+    /// Effective updates per second
+    pub hz: f32,
+    next_send: f32,
+    queued: Vec<String>,
+    sequence: ReplicationSequence,
+}
+
+impl SendRate {
+    pub fn new(hz: f32) -> Self {
+        Self {
+            hz,
+            next_send: 0.0,
+            queued: Vec::new(),
+            sequence: ReplicationSequence::default(),
+        }
+    }
+
+    /// Roughly what the queued messages hold on the heap
+    pub fn heap_bytes(&self) -> usize {
+        self.queued
+            .iter()
+            .map(|message| message.capacity() + std::mem::size_of::<String>())
+            .sum()
+    }
+
+    pub fn queue(&mut self, message: String) {
+        self.queued.push(message);
+    }
+
+    /// Sends on the next flush instead of waiting for the interval, e.g. for
+    /// a newcomer's first full copy or one a client asked for.
+    pub fn hurry(&mut self) {
+        self.next_send = 0.0;
+    }
+}
+
+pub fn update_send_rates(
+    rates: Res<SendRates>,
+    mut peers: Query<(&PeerStats, &mut SendRate), Changed<PeerStats>>,
+) {
+    for (stats, mut rate) in peers.iter_mut() {
+        let hz = rates.rate_for(stats);
+        if (rate.hz - hz).abs() > 0.05 {
+            rate.hz = hz;
+        }
+    }
+}
+
+/// Sends each peer what has queued up for it, once its interval has passed.
+pub fn flush_send_rates(
+    time: Res<Time>,
+    network: Res<NetworkState>,
+    mut peers: Query<(&PeerAddr, &PeerStats, &mut SendRate), With<Peer>>,
+    mut profile: ResMut<TickProfile>,
+) {
+    let started = Instant::now();
+    let now = time.elapsed_seconds();
+    for (PeerAddr(addr), stats, mut rate) in peers.iter_mut() {
+        if rate.queued.is_empty() || now < rate.next_send {
+            continue;
+        }
+        rate.next_send = now + 1.0 / rate.hz;
+        let queued = std::mem::take(&mut rate.queued);
+        // Nobody is listening; a returning peer gets a full copy on Hello
+        if stats.state == ConnectionState::Disconnected {
+            continue;
+        }
+        let messages: Vec<String> = coalesce(queued)
+            .into_iter()
+            .map(|message| rate.sequence.stamp(message))
+            .collect();
+        network.send_control_batch(&messages, addr);
+    }
+    profile.add(Stage::Send, started.elapsed());
+}
@@ -0,0 +1,85 @@
+//! Per-client sessions on the server; see `common::session`.
+//!
+//! A session opens on Hello and closes when the client times out, says Hello
+//! again, disconnects or is kicked, or the server shuts down. Summaries go to the server log and, with
+//! `--session-log`, to a file.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use common::disconnect::DisconnectReason;
+use common::session::{SessionStats, append_summary};
+use std::path::PathBuf;
+
+use crate::ServerState;
+use crate::peers::PeerSession;
+
+/// Opens and closes the [`PeerSession`] on each peer entity.
+#[derive(Resource, Default)]
+pub struct Sessions {
+    /// `--session-log`: where summaries are appended
+    pub log_path: Option<PathBuf>,
+}
+
+impl Sessions {
+    pub fn new(log_path: Option<PathBuf>) -> Self {
+        Self { log_path }
+    }
+
+    /// Opens a session for `peer`, closing any earlier one it left open.
+    pub fn start(
+        &self,
+        session: &mut PeerSession,
+        peer: &str,
+        now: f32,
+        server_state: &ServerState,
+    ) {
+        self.end(session, now, "client said Hello again", server_state);
+        session.open = Some(SessionStats::new(peer, now));
+    }
+
+    /// Closes the session, if one is open, and logs its summary.
+    pub fn end(
+        &self,
+        session: &mut PeerSession,
+        now: f32,
+        reason: &str,
+        server_state: &ServerState,
+    ) {
+        session.suspended_since = None;
+        let Some(stats) = session.open.take() else {
+            return;
+        };
+        session.last = Some(stats.totals(now));
+        let lines = stats.summary(now, reason);
+        for line in &lines {
+            info!("{}", line);
+            server_state
+                .log
+                .push(format!("[Session]: {}", line.trim_start()));
+        }
+        if let Some(path) = &self.log_path
+            && let Err(e) = append_summary(path, &lines)
+        {
+            warn!("Could not write session log {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub fn end_sessions_on_exit(
+    mut exits: EventReader<AppExit>,
+    time: Res<Time>,
+    sessions: Res<Sessions>,
+    mut peers: Query<&mut PeerSession>,
+    server_state: Res<ServerState>,
+) {
+    if exits.read().next().is_some() {
+        for mut session in peers.iter_mut() {
+            sessions.end(
+                &mut session,
+                time.elapsed_seconds(),
+                &DisconnectReason::ServerShutdown.to_string(),
+                &server_state,
+            );
+        }
+    }
+}
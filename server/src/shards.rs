@@ -0,0 +1,114 @@
+//! Messages between the shards of a `fleet`.
+//!
+//! With `--shard-link` (which `fleet` passes) the server's stdin and stdout
+//! double as a channel to the fleet, one line per message:
+//!
+//! - `forward <to_port> <sender> <text>`, printed by the sending shard
+//! - `deliver <from_port> <sender> <text>`, written to the receiving shard
+//! - `undeliverable <to_port> <sender>`, written back when `<to_port>` isn't
+//!   a running instance
+//!
+//! A player reaches another shard by chatting `/shard <port> <text>`. The
+//! line is moderated as usual on the way out, and every client on the other
+//! shard sees it as `Chat <sender>@<from_port>: <text>`.
+
+use bevy::prelude::*;
+use common::storage::History;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::chat::Moderation;
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerAddr, PeerSession, PeerStats};
+use crate::{NetworkState, ServerState};
+
+/// Does nothing when `--shard-link` is not given.
+#[derive(Resource, Default)]
+pub struct ShardLink {
+    /// Lines read from stdin, waiting for the next frame
+    inbox: Option<Arc<Mutex<VecDeque<String>>>>,
+}
+
+impl ShardLink {
+    /// Starts reading fleet lines from stdin.
+    pub fn connect() -> Self {
+        let inbox = Arc::new(Mutex::new(VecDeque::new()));
+        let lines = inbox.clone();
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                lines.lock().unwrap().push_back(line);
+            }
+        });
+        Self { inbox: Some(inbox) }
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.inbox.is_some()
+    }
+
+    pub fn forward(&self, to_port: u16, sender: &str, text: &str) {
+        if self.is_linked() {
+            println!("forward {} {} {}", to_port, sender, text);
+        }
+    }
+}
+
+/// `/shard <port> <text>` from a chat line
+pub fn parse_shard_command(text: &str) -> Option<(u16, &str)> {
+    let (port, text) = text.strip_prefix("/shard ")?.trim_start().split_once(' ')?;
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some((port.parse().ok()?, text))
+}
+
+pub fn deliver_shard_messages(
+    link: Res<ShardLink>,
+    network: Res<NetworkState>,
+    peers: Query<(&PeerAddr, &PeerStats, &PeerSession), With<Peer>>,
+    moderation: Res<Moderation>,
+    mut history: ResMut<History>,
+    server_state: Res<ServerState>,
+) {
+    let Some(inbox) = &link.inbox else {
+        return;
+    };
+    let lines: Vec<String> = inbox.lock().unwrap().drain(..).collect();
+    for line in lines {
+        let mut words = line.splitn(4, ' ');
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("deliver"), Some(from_port), Some(sender), Some(text)) => {
+                let line = format!("{}@{}: {}", sender, from_port, moderation.filter(text));
+                let relay = format!("Chat {}", line);
+                for (PeerAddr(addr), stats, session) in peers.iter() {
+                    if stats.state != ConnectionState::Disconnected && session.supports("chat") {
+                        network.send_gameplay(relay.as_bytes(), addr);
+                    }
+                }
+                history.append(line);
+                server_state
+                    .log
+                    .push(format!("[Shard {}]: {}: {}", from_port, sender, text));
+            }
+            (Some("undeliverable"), Some(to_port), Some(sender), None) => {
+                let reply = format!("Chat server: Shard {} is not reachable", to_port);
+                network.send_gameplay(reply.as_bytes(), sender);
+                server_state.log.push(format!(
+                    "[Shard {}]: not reachable, message from {} dropped",
+                    to_port, sender
+                ));
+            }
+            _ => {
+                server_state
+                    .log
+                    .push(format!("[Error]: unknown fleet line: {}", line));
+            }
+        }
+    }
+}
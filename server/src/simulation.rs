@@ -0,0 +1,95 @@
+//! The server's own simulation, paused while nobody is connected.
+//!
+//! What the server works out itself rather than relays, for now the
+//! sprites gliding toward where their clients last said they are (see
+//! `sprites`), runs in `FixedUpdate` in [`SimulationSet`], at Bevy's fixed
+//! timestep. The set runs only if [`simulation_running`], which turns
+//! false the frame the last client disconnects and true again the frame one
+//! connects: there is no world to advance with no one to show it to. Only
+//! the simulation stops; the socket, sessions and UI run as before, and the
+//! fixed clock keeps going, so a resume doesn't try to catch up on the
+//! steps it skipped. Each pause and resume is logged with the tick it
+//! happened at and, on resume, how long the pause lasted.
+//!
+//! With `--keep-simulating` the simulation never pauses. Idle mode (see
+//! `idle`) is separate: it slows the whole frame down, and later.
+
+use bevy::prelude::*;
+
+use crate::ServerState;
+use crate::client_list::ConnectionState;
+use crate::peers::{Peer, PeerStats};
+
+/// The systems that step the simulation, in `FixedUpdate`
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SimulationSet;
+
+#[derive(Resource)]
+pub struct Simulation {
+    /// `--keep-simulating`
+    keep_running: bool,
+    running: bool,
+    /// Fixed steps simulated so far
+    tick: u64,
+    /// When the current pause began, in seconds since startup
+    paused_at: Option<f32>,
+    /// Seconds spent in earlier pauses
+    paused_secs: f32,
+}
+
+impl Simulation {
+    /// Paused until the first client connects, unless `keep_running`.
+    pub fn new(keep_running: bool) -> Self {
+        Self {
+            keep_running,
+            running: keep_running,
+            tick: 0,
+            paused_at: if keep_running { None } else { Some(0.0) },
+            paused_secs: 0.0,
+        }
+    }
+}
+
+/// The run condition for [`SimulationSet`]
+pub fn simulation_running(simulation: Res<Simulation>) -> bool {
+    simulation.running
+}
+
+pub fn count_ticks(mut simulation: ResMut<Simulation>) {
+    simulation.tick += 1;
+}
+
+/// Pauses the simulation when the last client goes and resumes it when the
+/// next one comes.
+pub fn pause_when_empty(
+    time: Res<Time>,
+    peers: Query<&PeerStats, With<Peer>>,
+    mut simulation: ResMut<Simulation>,
+    server_state: Res<ServerState>,
+) {
+    if simulation.keep_running {
+        return;
+    }
+    let connected = peers
+        .iter()
+        .any(|stats| stats.state != ConnectionState::Disconnected);
+    if connected == simulation.running {
+        return;
+    }
+    simulation.running = connected;
+    let now = time.elapsed_seconds();
+    if connected {
+        let paused = now - simulation.paused_at.take().unwrap_or(now);
+        simulation.paused_secs += paused;
+        server_state.log.push(format!(
+            "[Simulation]: resumed at tick {} after {:.1}s paused ({:.0}s paused in all)",
+            simulation.tick, paused, simulation.paused_secs
+        ));
+    } else {
+        simulation.paused_at = Some(now);
+        server_state.log.push(format!(
+            "[Simulation]: paused at tick {}, nobody connected",
+            simulation.tick
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+//! Packet-rate sparklines in the client list.
+//!
+//! Every row of the client list ends in two small graphs of the peer's
+//! packets per second over the last minute, sends on top and receives
+//! below, so a client whose traffic pattern changed (a burst, a stall, a
+//! flood that started a while ago) stands out without opening any other
+//! tooling. Each row is scaled to its own busiest bucket, so a quiet
+//! client's pattern shows as clearly as a busy one's.
+//!
+//! [`PeerRateCounter`] is a `middleware` stage next to the bandwidth meter
+//! that counts every datagram by peer address. Every `BUCKET_SECS`,
+//! [`sample_peer_rates`] moves the counts into each peer's [`RateHistory`]
+//! and forgets the rest, so addresses that never became peers cost nothing.
+
+use bevy::prelude::*;
+use common::middleware::Middleware;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "gui")]
+use crate::client_list::{ClientListPage, PAGE_ROWS};
+#[cfg(feature = "gui")]
+use crate::peers::Peer;
+use crate::peers::PeerAddr;
+
+/// Bars per graph; together they cover a minute
+const BUCKETS: usize = 30;
+const BUCKET_SECS: f32 = 2.0;
+#[cfg(feature = "gui")]
+const BAR_WIDTH: f32 = 2.0;
+#[cfg(feature = "gui")]
+const GRAPH_HEIGHT: f32 = 8.0;
+#[cfg(feature = "gui")]
+const SENT_COLOR: Color = Color::rgb(0.1, 0.2, 0.5);
+#[cfg(feature = "gui")]
+const RECEIVED_COLOR: Color = Color::rgb(0.1, 0.1, 0.1);
+
+/// Datagrams per peer address since the last sample, as (sent, received);
+/// shared between the stage and the app.
+#[derive(Resource, Clone, Default)]
+pub struct PeerRates(Arc<Mutex<HashMap<String, (u32, u32)>>>);
+
+impl PeerRates {
+    fn count(&self, peer: &str, sent: bool) {
+        let mut peers = self.0.lock().unwrap();
+        let (sends, receives) = peers.entry(peer.to_string()).or_default();
+        if sent {
+            *sends += 1;
+        } else {
+            *receives += 1;
+        }
+    }
+}
+
+/// Counts every datagram into its [`PeerRates`] and passes it on.
+pub struct PeerRateCounter {
+    rates: PeerRates,
+}
+
+impl PeerRateCounter {
+    pub fn new(rates: PeerRates) -> Self {
+        Self { rates }
+    }
+}
+
+impl Middleware for PeerRateCounter {
+    fn name(&self) -> &'static str {
+        "peer-rates"
+    }
+
+    fn outgoing(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.rates.count(peer, true);
+        Some(bytes)
+    }
+
+    fn incoming(&self, peer: &str, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        self.rates.count(peer, false);
+        Some(bytes)
+    }
+}
+
+/// Packets per second of one peer, a bucket each, oldest first.
+#[derive(Component)]
+pub struct RateHistory {
+    sent: VecDeque<f32>,
+    received: VecDeque<f32>,
+}
+
+impl Default for RateHistory {
+    fn default() -> Self {
+        Self {
+            sent: VecDeque::from(vec![0.0; BUCKETS]),
+            received: VecDeque::from(vec![0.0; BUCKETS]),
+        }
+    }
+}
+
+impl RateHistory {
+    pub fn heap_bytes(&self) -> usize {
+        (self.sent.capacity() + self.received.capacity()) * std::mem::size_of::<f32>()
+    }
+
+    fn push(&mut self, sent: u32, received: u32) {
+        for (series, count) in [(&mut self.sent, sent), (&mut self.received, received)] {
+            series.push_back(count as f32 / BUCKET_SECS);
+            if series.len() > BUCKETS {
+                series.pop_front();
+            }
+        }
+    }
+
+    /// What a full bar stands for: the busiest bucket, at least 1 pkt/s
+    #[cfg(feature = "gui")]
+    fn scale(&self) -> f32 {
+        self.sent
+            .iter()
+            .chain(&self.received)
+            .fold(1.0, |max, rate| rate.max(max))
+    }
+}
+
+/// One bar of a row's graph.
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct SparkBar {
+    slot: usize,
+    sent: bool,
+    bucket: usize,
+}
+
+/// Adds the two graphs to the client list row at `slot`.
+#[cfg(feature = "gui")]
+pub fn spawn_sparklines(row: &mut ChildBuilder, slot: usize) {
+    row.spawn(NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(1.0),
+            ..default()
+        },
+        ..default()
+    })
+    .with_children(|graphs| {
+        for (sent, color) in [(true, SENT_COLOR), (false, RECEIVED_COLOR)] {
+            graphs
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(BUCKETS as f32 * (BAR_WIDTH + 1.0)),
+                        height: Val::Px(GRAPH_HEIGHT),
+                        align_items: AlignItems::FlexEnd,
+                        column_gap: Val::Px(1.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|graph| {
+                    for bucket in 0..BUCKETS {
+                        graph.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    width: Val::Px(BAR_WIDTH),
+                                    height: Val::Percent(0.0),
+                                    ..default()
+                                },
+                                background_color: color.into(),
+                                ..default()
+                            },
+                            SparkBar { slot, sent, bucket },
+                        ));
+                    }
+                });
+        }
+    });
+}
+
+pub fn sample_peer_rates(
+    time: Res<Time>,
+    rates: Res<PeerRates>,
+    mut peers: Query<(&PeerAddr, &mut RateHistory)>,
+    mut next_at: Local<f32>,
+) {
+    let now = time.elapsed_seconds();
+    if now < *next_at {
+        return;
+    }
+    *next_at = now + BUCKET_SECS;
+    let counts = std::mem::take(&mut *rates.0.lock().unwrap());
+    for (PeerAddr(addr), mut history) in peers.iter_mut() {
+        let (sent, received) = counts.get(addr).copied().unwrap_or_default();
+        history.push(sent, received);
+    }
+}
+
+/// Paints the graphs of the page `client_list::update_client_rows` shows,
+/// after it has kept the page in range.
+#[cfg(feature = "gui")]
+pub fn update_sparklines(
+    page: Res<ClientListPage>,
+    peers: Query<(Entity, &RateHistory), With<Peer>>,
+    mut bars: Query<(&SparkBar, &mut Style)>,
+) {
+    let mut listed: Vec<_> = peers.iter().collect();
+    listed.sort_by_key(|(entity, _)| *entity);
+    let shown = &listed[(page.0 * PAGE_ROWS).min(listed.len())..];
+    let scales: Vec<f32> = shown.iter().map(|(_, history)| history.scale()).collect();
+    for (bar, mut style) in bars.iter_mut() {
+        let Some((_, history)) = shown.get(bar.slot) else {
+            continue;
+        };
+        let series = if bar.sent {
+            &history.sent
+        } else {
+            &history.received
+        };
+        let height = Val::Percent(series[bar.bucket] / scales[bar.slot] * 100.0);
+        // Unchanged styles would still be laid out again
+        if style.height != height {
+            style.height = height;
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! A sprite per client that moves one, mirroring where it is on the client.
+//!
+//! The client sends `Pos <seq> <x> <y>` (see `common::handlers`) a fixed
+//! number of times a second. The first one from a client spawns its sprite,
+//! in the client's avatar color; each later one sets where the sprite is
+//! headed, and the sprite glides there instead of jumping, a little every
+//! fixed step of the simulation (see `simulation`), so 20 updates a second
+//! still look smooth. UDP may deliver an update after a
+//! newer one, which would pull the sprite back; an update whose seq isn't
+//! above the newest seen is dropped. A new session (a Hello, or a Resume)
+//! starts the numbering over. The sprite goes when its client disconnects.
+//!
+//! The server doesn't take a client's word for where it is, either. An update
+//! is also dropped if it's outside `POSITION_BOUNDS`, if it's further from the
+//! last accepted one than the square could move at `POSITION_SPEED` in the
+//! time between them (plus [`SPEED_SLACK_SECS`], since UDP bunches updates
+//! up), or if the client has sent more than [`MAX_POSITION_RATE`] in the last
+//! second. Those drops go in the server log, at most once a second per client.
+
+use bevy::prelude::*;
+use common::effects::AtMostOnce;
+use common::handlers::{POSITION_BOUNDS, POSITION_SPEED, PositionUpdate};
+use common::replicate::ReplicationJoin;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::ServerState;
+#[cfg(feature = "gui")]
+use crate::avatars::avatar_color;
+use crate::client_list::ConnectionState;
+use crate::peers::{PeerIndex, PeerStats};
+
+#[cfg(feature = "gui")]
+const SPRITE_SIZE: f32 = 24.0;
+/// How quickly a sprite closes on its target: the share of the distance
+/// left after a second is e^-SMOOTHING
+const SMOOTHING: f32 = 12.0;
+/// Seconds of movement an update may carry on top of the time since the last
+/// accepted one
+pub const SPEED_SLACK_SECS: f32 = 0.25;
+/// The most updates a client may send a second; the client's default
+/// `--position-hz` is 20
+pub const MAX_POSITION_RATE: usize = 60;
+const RATE_WINDOW_SECS: f32 = 1.0;
+/// Seconds between two log lines about the same client's dropped updates
+const DROP_LOG_SECS: f32 = 1.0;
+
+/// A `Pos` as received; see `handle_network_messages`.
+#[derive(Event)]
+pub struct PositionReceived {
+    pub from: String,
+    pub update: PositionUpdate,
+}
+
+/// Why an update was dropped.
+#[derive(Debug, PartialEq)]
+enum Rejected {
+    /// Not newer than the newest seen: UDP reordering, not cheating
+    Stale,
+    OutOfBounds,
+    TooFast {
+        distance: f32,
+        allowed: f32,
+    },
+    TooMany,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejected::Stale => write!(f, "stale"),
+            Rejected::OutOfBounds => write!(f, "out of bounds"),
+            Rejected::TooFast { distance, allowed } => {
+                write!(
+                    f,
+                    "moved {:.0} units, at most {:.0} allowed",
+                    distance, allowed
+                )
+            }
+            Rejected::TooMany => write!(f, "over {} a second", MAX_POSITION_RATE),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PlayerSprite {
+    addr: String,
+    /// The newest position accepted
+    target: Vec2,
+    seen: AtMostOnce,
+    /// When `target` was accepted; `None` until the first update of a session
+    accepted_at: Option<f32>,
+    /// When the updates of the last second arrived
+    recent: VecDeque<f32>,
+    /// Updates dropped since the last log line about them
+    dropped: u32,
+    logged_at: f32,
+}
+
+impl PlayerSprite {
+    fn new(addr: String) -> Self {
+        Self {
+            addr,
+            target: Vec2::ZERO,
+            seen: AtMostOnce::default(),
+            accepted_at: None,
+            recent: VecDeque::new(),
+            dropped: 0,
+            logged_at: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Starts a new session: the numbering starts over, and the first
+    /// position of it is taken as is.
+    fn restart(&mut self) {
+        self.seen.reset();
+        self.accepted_at = None;
+    }
+
+    /// Heads the sprite to `update` if it passes the checks, received at
+    /// `now` seconds.
+    fn accept(&mut self, update: &PositionUpdate, now: f32) -> Result<(), Rejected> {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&at| now - at >= RATE_WINDOW_SECS)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= MAX_POSITION_RATE {
+            return Err(Rejected::TooMany);
+        }
+        self.recent.push_back(now);
+        // Checked first, so a late update isn't taken for a teleport
+        if !self.seen.accept(update.seq) {
+            return Err(Rejected::Stale);
+        }
+
+        let target = Vec2::new(update.x, update.y);
+        if target.abs().cmpgt(POSITION_BOUNDS).any() {
+            return Err(Rejected::OutOfBounds);
+        }
+        if let Some(accepted_at) = self.accepted_at {
+            let distance = target.distance(self.target);
+            let allowed = POSITION_SPEED * (now - accepted_at + SPEED_SLACK_SECS);
+            if distance > allowed {
+                return Err(Rejected::TooFast { distance, allowed });
+            }
+        }
+        self.target = target;
+        self.accepted_at = Some(now);
+        Ok(())
+    }
+}
+
+pub fn receive_positions(
+    mut commands: Commands,
+    time: Res<Time>,
+    server_state: Res<ServerState>,
+    mut received: EventReader<PositionReceived>,
+    mut joins: EventReader<ReplicationJoin>,
+    mut sprites: Query<&mut PlayerSprite>,
+) {
+    for join in joins.read() {
+        for mut sprite in sprites.iter_mut() {
+            if sprite.addr == join.peer {
+                sprite.restart();
+            }
+        }
+    }
+    // Spawned after the loop, so two updates in one frame make one sprite
+    let mut new_sprites: HashMap<String, PlayerSprite> = HashMap::new();
+    let now = time.elapsed_seconds();
+    for event in received.read() {
+        let existing = sprites.iter_mut().find(|sprite| sprite.addr == event.from);
+        let sprite = match existing {
+            Some(sprite) => sprite.into_inner(),
+            None => new_sprites
+                .entry(event.from.clone())
+                .or_insert_with(|| PlayerSprite::new(event.from.clone())),
+        };
+        match sprite.accept(&event.update, now) {
+            Ok(()) | Err(Rejected::Stale) => {}
+            Err(rejected) => {
+                sprite.dropped += 1;
+                if now - sprite.logged_at >= DROP_LOG_SECS {
+                    server_state.log.push(format!(
+                        "Dropped {} position update(s) from {}: {}",
+                        sprite.dropped, event.from, rejected
+                    ));
+                    sprite.dropped = 0;
+                    sprite.logged_at = now;
+                }
+            }
+        }
+    }
+    // A client none of whose updates passed gets no sprite yet
+    for sprite in new_sprites.into_values() {
+        if sprite.accepted_at.is_none() {
+            continue;
+        }
+        let transform = Transform::from_translation(sprite.target.extend(1.0));
+        #[cfg(feature = "gui")]
+        let bundle = SpriteBundle {
+            sprite: Sprite {
+                color: avatar_color(&sprite.addr),
+                custom_size: Some(Vec2::splat(SPRITE_SIZE)),
+                ..default()
+            },
+            transform,
+            ..default()
+        };
+        // Nothing to draw, but the checks still need to know where it is
+        #[cfg(not(feature = "gui"))]
+        let bundle = TransformBundle::from_transform(transform);
+        commands.spawn((bundle, sprite));
+    }
+}
+
+/// Removes the sprites of clients that disconnected.
+pub fn remove_departed_sprites(
+    mut commands: Commands,
+    index: Res<PeerIndex>,
+    peers: Query<&PeerStats>,
+    sprites: Query<(Entity, &PlayerSprite)>,
+) {
+    for (entity, sprite) in sprites.iter() {
+        let connected = index
+            .get(&sprite.addr)
+            .and_then(|peer| peers.get(peer).ok())
+            .is_some_and(|stats| stats.state != ConnectionState::Disconnected);
+        if !connected {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Glides every sprite toward its target, one fixed step at a time; part of
+/// the simulation (see `simulation`).
+pub fn move_sprites(time: Res<Time>, mut sprites: Query<(&PlayerSprite, &mut Transform)>) {
+    let step = 1.0 - (-SMOOTHING * time.delta_seconds()).exp();
+    for (sprite, mut transform) in sprites.iter_mut() {
+        let position = transform.translation.truncate();
+        transform.translation = position.lerp(sprite.target, step).extend(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(seq: u32, x: f32, y: f32) -> PositionUpdate {
+        PositionUpdate { seq, x, y }
+    }
+
+    #[test]
+    fn accepts_updates_at_the_clients_speed() {
+        let mut sprite = PlayerSprite::new("client".into());
+        assert_eq!(sprite.accept(&update(1, 0.0, 0.0), 0.0), Ok(()));
+        // 1/20 of a second at full speed, and a little diagonal rounding
+        assert_eq!(sprite.accept(&update(2, 12.0, 0.1), 0.05), Ok(()));
+        assert_eq!(sprite.target, Vec2::new(12.0, 0.1));
+    }
+
+    #[test]
+    fn drops_a_teleport_and_keeps_the_old_target() {
+        let mut sprite = PlayerSprite::new("client".into());
+        sprite.accept(&update(1, 0.0, 0.0), 0.0).unwrap();
+        assert!(matches!(
+            sprite.accept(&update(2, 500.0, 0.0), 0.05),
+            Err(Rejected::TooFast { .. })
+        ));
+        assert_eq!(sprite.target, Vec2::ZERO);
+        // Given time to get there, the same position is fine
+        assert_eq!(sprite.accept(&update(3, 500.0, 0.0), 2.0), Ok(()));
+    }
+
+    #[test]
+    fn bunched_up_updates_pass() {
+        let mut sprite = PlayerSprite::new("client".into());
+        sprite.accept(&update(1, 0.0, 0.0), 0.0).unwrap();
+        // Five updates' worth of movement arriving in the same frame
+        for seq in 2..=6 {
+            let x = (seq - 1) as f32 * 12.0;
+            assert_eq!(sprite.accept(&update(seq, x, 0.0), 0.3), Ok(()));
+        }
+    }
+
+    #[test]
+    fn drops_positions_out_of_bounds() {
+        let mut sprite = PlayerSprite::new("client".into());
+        let outside = POSITION_BOUNDS.x + 1.0;
+        assert_eq!(
+            sprite.accept(&update(1, outside, 0.0), 0.0),
+            Err(Rejected::OutOfBounds)
+        );
+        assert_eq!(sprite.accepted_at, None);
+        assert_eq!(
+            sprite.accept(&update(2, POSITION_BOUNDS.x, 0.0), 0.0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn caps_updates_a_second() {
+        let mut sprite = PlayerSprite::new("client".into());
+        for seq in 1..=MAX_POSITION_RATE as u32 {
+            assert_eq!(sprite.accept(&update(seq, 0.0, 0.0), 0.5), Ok(()));
+        }
+        let next = MAX_POSITION_RATE as u32 + 1;
+        assert_eq!(
+            sprite.accept(&update(next, 0.0, 0.0), 0.5),
+            Err(Rejected::TooMany)
+        );
+        // A second later the window has room again
+        assert_eq!(sprite.accept(&update(next, 0.0, 0.0), 1.5), Ok(()));
+    }
+
+    #[test]
+    fn drops_stale_updates_until_a_new_session() {
+        let mut sprite = PlayerSprite::new("client".into());
+        sprite.accept(&update(5, 0.0, 0.0), 0.0).unwrap();
+        assert_eq!(
+            sprite.accept(&update(4, 1.0, 0.0), 0.05),
+            Err(Rejected::Stale)
+        );
+        sprite.restart();
+        assert_eq!(sprite.accept(&update(1, 300.0, 0.0), 0.1), Ok(()));
+    }
+}
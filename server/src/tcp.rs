@@ -0,0 +1,197 @@
+//! TCP alongside UDP, for clients on networks that block UDP.
+//!
+//! With `--tcp` the server also accepts TCP connections on the control port.
+//! Every connection is a peer like any other, keyed by its address. Its
+//! messages are framed with their length (see `common::framing`) and are
+//! handled exactly like datagrams, labelled `tcp` in the log. Whatever is
+//! sent to a peer with a connection goes over the connection, on the control
+//! and the data stream alike, so a TCP client never needs the data port.
+//! Stopping the listener closes every connection.
+//!
+//! Each connection costs the server a thread, so there are at most
+//! `--max-tcp-connections` at a time; the listener turns away any more, like
+//! an address the access list refuses. A connection that sends nothing for
+//! `--disconnect-timeout` seconds, not even part of a frame, is closed, rather
+//! than keeping its thread blocked in a read forever.
+
+use bevy::prelude::*;
+use common::framing::{self, Frame};
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::audit::Decision;
+use crate::inbound::RawPacket;
+use crate::metrics::Metric;
+use crate::{ReceiverShared, Stream};
+
+/// `--max-tcp-connections`, and how long a read may block.
+#[derive(Clone, Copy)]
+pub struct TcpLimits {
+    pub max_connections: usize,
+    /// `None` waits forever
+    pub read_timeout: Option<Duration>,
+}
+
+/// Open connections by peer address, shared with their reader threads.
+#[derive(Clone, Default)]
+pub struct TcpPeers(Arc<Mutex<HashMap<String, TcpStream>>>);
+
+impl TcpPeers {
+    pub fn contains(&self, addr: &str) -> bool {
+        self.0.lock().unwrap().contains_key(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// `None` if `addr` isn't connected over TCP, so the caller sends a
+    /// datagram instead.
+    pub fn send(&self, addr: &str, bytes: &[u8]) -> Option<std::io::Result<()>> {
+        let mut peers = self.0.lock().unwrap();
+        let stream = peers.get_mut(addr)?;
+        Some(framing::write_frame(stream, bytes))
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn close_all(&self) {
+        for (_, stream) in self.0.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Accepts connections until `stop` is set.
+pub fn spawn_tcp_listener(
+    listener: TcpListener,
+    buf_size: usize,
+    limits: TcpLimits,
+    shared: ReceiverShared,
+    peers: TcpPeers,
+    stop: Arc<AtomicBool>,
+) -> std::io::Result<JoinHandle<()>> {
+    // Polled, so the thread notices `stop`
+    listener.set_nonblocking(true)?;
+    Ok(thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    let peer = addr.to_string();
+                    if let Err(reason) = shared.access.check(addr.ip()) {
+                        shared
+                            .audit
+                            .record(&peer, Stream::Tcp, 0, "-", Decision::Rejected(reason));
+                        continue;
+                    }
+                    // Only this thread adds connections, so the count can't
+                    // grow before the insert
+                    if peers.len() >= limits.max_connections {
+                        shared.audit.record(
+                            &peer,
+                            Stream::Tcp,
+                            0,
+                            "-",
+                            Decision::Rejected("too many connections"),
+                        );
+                        let _ = stream.shutdown(Shutdown::Both);
+                        continue;
+                    }
+                    if let Err(e) = accept(stream, &peer, buf_size, limits, &shared, &peers) {
+                        warn!("Dropped TCP connection from {}: {}", peer, e);
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => warn!("TCP accept failed: {}", e),
+            }
+        }
+    }))
+}
+
+fn accept(
+    stream: TcpStream,
+    peer: &str,
+    buf_size: usize,
+    limits: TcpLimits,
+    shared: &ReceiverShared,
+    peers: &TcpPeers,
+) -> std::io::Result<()> {
+    // Accepted sockets inherit non-blocking mode on some platforms
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(limits.read_timeout)?;
+    let mut reader = stream.try_clone()?;
+    peers.0.lock().unwrap().insert(peer.to_string(), stream);
+    info!("TCP connection from {}", peer);
+
+    let peer = peer.to_string();
+    let shared = shared.clone();
+    let peers = peers.clone();
+    thread::spawn(move || {
+        loop {
+            match framing::read_frame(&mut reader, buf_size) {
+                Ok(Frame::Message(bytes)) => {
+                    shared.metrics.record_received(bytes.len());
+                    let size = bytes.len();
+                    let Some(bytes) = shared.pipeline.incoming(&peer, bytes) else {
+                        shared.audit.record(
+                            &peer,
+                            Stream::Tcp,
+                            size,
+                            "-",
+                            Decision::Rejected("middleware"),
+                        );
+                        shared.metrics.add(Metric::RejectedPackets, 1);
+                        continue;
+                    };
+                    let packet = RawPacket {
+                        bytes,
+                        addr: peer.clone(),
+                        stream: Stream::Tcp,
+                    };
+                    if !shared.received.push(packet) {
+                        shared.audit.record(
+                            &peer,
+                            Stream::Tcp,
+                            size,
+                            "-",
+                            Decision::Rejected("queue full"),
+                        );
+                        shared.metrics.add(Metric::RejectedPackets, 1);
+                        continue;
+                    }
+                    shared.metrics.add(Metric::Queued, 1);
+                    shared.wakeup.notify();
+                }
+                Ok(Frame::Oversized(size)) => {
+                    shared.metrics.add(Metric::TruncatedPackets, 1);
+                    shared.audit.record(
+                        &peer,
+                        Stream::Tcp,
+                        size,
+                        "-",
+                        Decision::Rejected("oversized"),
+                    );
+                }
+                Err(ref e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    info!("TCP connection from {} timed out", peer);
+                    break;
+                }
+                Ok(Frame::Closed) | Err(_) => break,
+            }
+        }
+        peers.0.lock().unwrap().remove(&peer);
+        info!("TCP connection from {} closed", peer);
+    });
+    Ok(())
+}
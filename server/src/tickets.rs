@@ -0,0 +1,52 @@
+//! Connect tickets: with `--ticket-key`, only players the matchmaker sent
+//! get a session.
+//!
+//! A new session (a Hello, or a Resume whose token we don't honour) needs a
+//! `ticket=` signed with the same key, not yet expired. Anything else is
+//! answered with `Reject version=.. ticket=<reason>` and gets no session. A
+//! ticket is good for one address: presented again from another before it
+//! expires, it is refused as reused, so one leaked from a player's Hello
+//! can't let someone else in. Resuming a session needs no ticket, since the
+//! resume token already proves who it is. See `common::ticket` and the
+//! `matchmaker` binary.
+
+use bevy::prelude::*;
+use common::protocol;
+use common::ticket::{self, TicketError};
+use std::collections::HashMap;
+
+#[derive(Resource, Default)]
+pub struct TicketCheck {
+    /// `--ticket-key`; `None` lets everyone in
+    key: Option<String>,
+    /// Tickets already presented, by client id: who from, and when they expire
+    redeemed: HashMap<u64, (String, u64)>,
+}
+
+impl TicketCheck {
+    pub fn new(key: Option<String>) -> Self {
+        Self {
+            key,
+            redeemed: HashMap::new(),
+        }
+    }
+
+    /// Checks the ticket in a handshake message from `addr`.
+    pub fn check(&mut self, message: &str, addr: &str) -> Result<(), TicketError> {
+        let Some(key) = &self.key else {
+            return Ok(());
+        };
+        let presented = protocol::parse_ticket(message).ok_or(TicketError::Missing)?;
+        let now = ticket::unix_now();
+        let ticket = ticket::verify(key.as_bytes(), presented, now)?;
+        self.redeemed.retain(|_, (_, expires)| *expires > now);
+        match self.redeemed.get(&ticket.client_id) {
+            Some((first, _)) if first != addr => Err(TicketError::Reused),
+            _ => {
+                self.redeemed
+                    .insert(ticket.client_id, (addr.to_string(), ticket.expires));
+                Ok(())
+            }
+        }
+    }
+}
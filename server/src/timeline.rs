@@ -0,0 +1,235 @@
+//! Packet timeline: one horizontal track per peer. Packets received from the
+//! peer are drawn below the line, packets we sent to it above the line.
+//!
+//! A reliable payload (see `common::reliable`) is drawn yellow while it
+//! waits for its ack, and turns green when the ack arrives or red when it
+//! has to be resent, as the client colours its Pings. The resend is a mark
+//! of its own. Only peers get a track, and a track goes once its last mark
+//! has scrolled off.
+
+use bevy::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+
+/// How many seconds of history each track shows.
+const WINDOW_SECS: f32 = 10.0;
+#[cfg(feature = "gui")]
+const TRACK_LEFT: f32 = -600.0;
+#[cfg(feature = "gui")]
+const TRACK_WIDTH: f32 = 1000.0;
+#[cfg(feature = "gui")]
+const FIRST_TRACK_Y: f32 = -120.0;
+#[cfg(feature = "gui")]
+const TRACK_SPACING: f32 = 40.0;
+#[cfg(feature = "gui")]
+const TICK_HEIGHT: f32 = 10.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MarkState {
+    /// Nothing acks it
+    Unacked,
+    /// A reliable payload, waiting for the ack of this seq
+    Pending(u32),
+    Acked,
+    /// Resent, because the ack was late
+    TimedOut,
+}
+
+pub struct PacketMark {
+    pub at: f32,
+    pub direction: Direction,
+    pub state: MarkState,
+}
+
+#[derive(Resource, Default)]
+pub struct PacketTimeline {
+    /// Keyed by peer address; a BTreeMap keeps track order stable
+    pub tracks: BTreeMap<String, VecDeque<PacketMark>>,
+}
+
+impl PacketTimeline {
+    /// Callers check `peer` is a peer first, so a spoofed address can't
+    /// add a track.
+    pub fn record(&mut self, peer: &str, direction: Direction, now: f32) {
+        self.push(peer, direction, MarkState::Unacked, now);
+    }
+
+    /// A reliable payload going out; an earlier send of `seq` still waiting
+    /// for its ack timed out.
+    pub fn record_reliable(&mut self, peer: &str, seq: u32, now: f32) {
+        if let Some(mark) = self.pending(peer, seq) {
+            mark.state = MarkState::TimedOut;
+        }
+        self.push(peer, Direction::Tx, MarkState::Pending(seq), now);
+    }
+
+    /// The ack for `seq` arrived.
+    pub fn ack(&mut self, peer: &str, seq: u32) {
+        if let Some(mark) = self.pending(peer, seq) {
+            mark.state = MarkState::Acked;
+        }
+    }
+
+    fn pending(&mut self, peer: &str, seq: u32) -> Option<&mut PacketMark> {
+        self.tracks
+            .get_mut(peer)?
+            .iter_mut()
+            .rev()
+            .find(|mark| mark.state == MarkState::Pending(seq))
+    }
+
+    fn push(&mut self, peer: &str, direction: Direction, state: MarkState, now: f32) {
+        self.tracks
+            .entry(peer.to_string())
+            .or_default()
+            .push_back(PacketMark {
+                at: now,
+                direction,
+                state,
+            });
+    }
+
+    /// Drops `peer`'s track, once the peer is archived.
+    pub fn forget(&mut self, peer: &str) {
+        self.tracks.remove(peer);
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(String, VecDeque<PacketMark>)>();
+        self.tracks
+            .iter()
+            .map(|(peer, marks)| {
+                entry + peer.capacity() + marks.capacity() * std::mem::size_of::<PacketMark>()
+            })
+            .sum()
+    }
+}
+
+#[cfg(feature = "gui")]
+#[derive(Component)]
+pub struct TrackLabel;
+
+pub fn expire_timeline_marks(time: Res<Time>, mut timeline: ResMut<PacketTimeline>) {
+    let now = time.elapsed_seconds();
+    for marks in timeline.tracks.values_mut() {
+        while let Some(mark) = marks.front() {
+            if now - mark.at > WINDOW_SECS {
+                marks.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+    timeline.tracks.retain(|_, marks| !marks.is_empty());
+}
+
+#[cfg(feature = "gui")]
+pub fn draw_timeline(time: Res<Time>, timeline: Res<PacketTimeline>, mut gizmos: Gizmos) {
+    let now = time.elapsed_seconds();
+
+    for (index, marks) in timeline.tracks.values().enumerate() {
+        let y = FIRST_TRACK_Y - index as f32 * TRACK_SPACING;
+        gizmos.line_2d(
+            Vec2::new(TRACK_LEFT, y),
+            Vec2::new(TRACK_LEFT + TRACK_WIDTH, y),
+            Color::GRAY,
+        );
+
+        for mark in marks.iter() {
+            let age = now - mark.at;
+            let x = TRACK_LEFT + TRACK_WIDTH * (1.0 - age / WINDOW_SECS);
+            let (from, to) = match mark.direction {
+                Direction::Rx => (y - TICK_HEIGHT, y),
+                Direction::Tx => (y, y + TICK_HEIGHT),
+            };
+            let color = match (mark.state, mark.direction) {
+                (MarkState::Unacked, Direction::Rx) => Color::CYAN,
+                (MarkState::Unacked, Direction::Tx) => Color::ORANGE,
+                (MarkState::Pending(_), _) => Color::YELLOW,
+                (MarkState::Acked, _) => Color::GREEN,
+                (MarkState::TimedOut, _) => Color::RED,
+            };
+            gizmos.line_2d(Vec2::new(x, from), Vec2::new(x, to), color);
+        }
+    }
+}
+
+/// Keeps one address label per track, respawning them when a new peer appears.
+#[cfg(feature = "gui")]
+pub fn update_track_labels(
+    mut commands: Commands,
+    timeline: Res<PacketTimeline>,
+    labels: Query<Entity, With<TrackLabel>>,
+) {
+    if !timeline.is_changed() || labels.iter().count() == timeline.tracks.len() {
+        return;
+    }
+
+    for entity in labels.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    for (index, peer) in timeline.tracks.keys().enumerate() {
+        let y = FIRST_TRACK_Y - index as f32 * TRACK_SPACING;
+        commands.spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    peer.clone(),
+                    TextStyle {
+                        font_size: 14.0,
+                        color: Color::GRAY,
+                        ..default()
+                    },
+                ),
+                transform: Transform::from_xyz(TRACK_LEFT, y + TICK_HEIGHT, 0.0),
+                text_anchor: bevy::sprite::Anchor::BottomLeft,
+                ..default()
+            },
+            TrackLabel,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "127.0.0.1:50000";
+
+    fn states(timeline: &PacketTimeline) -> Vec<MarkState> {
+        timeline.tracks[PEER]
+            .iter()
+            .map(|mark| mark.state)
+            .collect()
+    }
+
+    #[test]
+    fn a_resent_payload_times_out_and_its_resend_is_acked() {
+        let mut timeline = PacketTimeline::default();
+        timeline.record_reliable(PEER, 7, 1.0);
+        timeline.record_reliable(PEER, 7, 2.0);
+        timeline.ack(PEER, 7);
+        assert_eq!(states(&timeline), [MarkState::TimedOut, MarkState::Acked]);
+        // A late or repeated ack changes nothing
+        timeline.ack(PEER, 7);
+        assert_eq!(states(&timeline), [MarkState::TimedOut, MarkState::Acked]);
+    }
+
+    #[test]
+    fn an_idle_track_goes() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .init_resource::<PacketTimeline>()
+            .add_systems(Update, expire_timeline_marks);
+        app.world
+            .resource_mut::<PacketTimeline>()
+            .record(PEER, Direction::Rx, -WINDOW_SECS - 1.0);
+        app.update();
+        assert!(app.world.resource::<PacketTimeline>().tracks.is_empty());
+    }
+}
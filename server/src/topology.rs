@@ -0,0 +1,82 @@
+//! Topology overlay: F4 draws the server as a node in the middle of the
+//! avatar ring, with an edge to every client.
+//!
+//! An edge gets thicker with the client's bytes per second and goes from
+//! green to red as its RTT grows. Clients report their smoothed RTT in their
+//! heartbeats, so an edge stays grey until the first report arrives. Edges
+//! to disconnected clients are thin and grey.
+
+use bevy::prelude::*;
+
+use crate::avatars::{AVATAR_RADIUS, ClientAvatar, RING_CENTER};
+use crate::client_list::{ConnectionState, heat_color};
+use crate::peers::{PeerIndex, PeerStats};
+
+const SERVER_NODE_RADIUS: f32 = 24.0;
+/// Thickest edge, in pixels
+const MAX_EDGE_WIDTH: f32 = 8.0;
+/// Bytes per second at which an edge is drawn at full width
+const FULL_WIDTH_BYTES_PER_SEC: f32 = 2000.0;
+/// RTT at which an edge is fully red
+const RED_RTT_SECS: f32 = 0.25;
+
+#[derive(Resource, Default)]
+pub struct TopologyView {
+    pub visible: bool,
+}
+
+pub fn toggle_topology(keys: Res<ButtonInput<KeyCode>>, mut view: ResMut<TopologyView>) {
+    if keys.just_pressed(KeyCode::F4) {
+        view.visible = !view.visible;
+    }
+}
+
+pub fn draw_topology(
+    view: Res<TopologyView>,
+    index: Res<PeerIndex>,
+    peers: Query<&PeerStats>,
+    avatars: Query<(&ClientAvatar, &Transform)>,
+    mut gizmos: Gizmos,
+) {
+    if !view.visible {
+        return;
+    }
+    gizmos.circle_2d(RING_CENTER, SERVER_NODE_RADIUS, Color::WHITE);
+
+    for (avatar, transform) in avatars.iter() {
+        let Some(traffic) = index
+            .get(&avatar.addr)
+            .and_then(|peer| peers.get(peer).ok())
+        else {
+            continue;
+        };
+        let end = transform.translation.truncate();
+        let Some(direction) = (end - RING_CENTER).try_normalize() else {
+            continue;
+        };
+        // Start and stop at the node edges rather than their centers
+        let start = RING_CENTER + direction * SERVER_NODE_RADIUS;
+        let end = end - direction * AVATAR_RADIUS * transform.scale.x;
+
+        let (width, color) = match traffic.state {
+            ConnectionState::Disconnected => (1.0, Color::DARK_GRAY),
+            ConnectionState::Active | ConnectionState::Idle => {
+                let load = (traffic.bytes_per_sec() / FULL_WIDTH_BYTES_PER_SEC).min(1.0);
+                let color = match traffic.rtt {
+                    Some(rtt) => heat_color(rtt / RED_RTT_SECS),
+                    None => Color::GRAY,
+                };
+                (1.0 + load * (MAX_EDGE_WIDTH - 1.0), color)
+            }
+        };
+
+        // Gizmo lines are one pixel wide, so a thick edge is several
+        // parallel lines
+        let normal = direction.perp();
+        let lines = width.round().max(1.0) as i32;
+        for i in 0..lines {
+            let offset = normal * (i as f32 - (lines - 1) as f32 / 2.0);
+            gizmos.line_2d(start + offset, end + offset, color);
+        }
+    }
+}
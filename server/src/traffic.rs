@@ -0,0 +1,55 @@
+//! Session-wide traffic counters (see `metrics`), shown in a footer line so
+//! it's obvious whether traffic is flowing before any log entry is rendered.
+
+use bevy::prelude::*;
+use common::compression::CompressionStats;
+
+use crate::metrics::{Metric, MetricsSample};
+
+#[derive(Component)]
+pub struct FooterText;
+
+pub fn setup_footer(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 14.0,
+                color: Color::rgb(0.7, 0.7, 0.7),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        FooterText,
+    ));
+}
+
+pub fn update_footer(
+    metrics: Res<MetricsSample>,
+    compression: Res<CompressionStats>,
+    mut query: Query<&mut Text, With<FooterText>>,
+) {
+    let mut footer = format!(
+        "Sent: {} packets / {} bytes   Received: {} packets / {} bytes   Dropped oversized: {}   Queued: {}",
+        metrics.total(Metric::PacketsSent),
+        metrics.total(Metric::BytesSent),
+        metrics.total(Metric::PacketsReceived),
+        metrics.total(Metric::BytesReceived),
+        metrics.total(Metric::TruncatedPackets),
+        metrics.queue_depth(),
+    );
+    if let Some(summary) = compression.summary() {
+        footer.push_str("   ");
+        footer.push_str(&summary);
+    }
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != footer {
+            text.sections[0].value = footer.clone();
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! `--validate-protocol`: what a client may send before, during and after
+//! its handshake, checked by a `common::validator` stage at
+//! `VALIDATOR_ORDER`.
+//!
+//! ```text
+//! idle ──Hello/Resume──▶ handshaking ──(we send Welcome)──▶ connected
+//!  ▲                          │                                │
+//!  └──(we send Reject)────────┘      Disconnect either way ────┘
+//! ```
+//!
+//! An idle client may only start a handshake. One that is handshaking may
+//! also send heartbeats, whose timer runs from startup; anything else waits
+//! for the Welcome. A connected client may send anything, and a Hello or
+//! Resume starts over. So a client that kept talking across a server
+//! restart has its messages dropped, heartbeats included, until it times
+//! out and reconnects, rather than chatting with no session behind it. A
+//! client that times out without a Disconnect keeps its state until it
+//! comes back.
+//!
+//! One exception is a bare [`LEGACY_LINES`] message, which an idle
+//! client may send without a handshake and stay idle: that's all a client
+//! from before the handshake, or a netcat user, ever sends (see
+//! `common::legacy`). The other is a `ServerInfoRequest`, which needs no
+//! session in any state and changes none (see `query`).
+
+use common::session::message_kind;
+use common::validator::ProtocolRules;
+
+/// What the plain-text protocol from before the handshake had, which the
+/// server still reads
+pub const LEGACY_LINES: &[&str] = &["Ping"];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Phase {
+    #[default]
+    Idle,
+    Handshaking,
+    Connected,
+}
+
+pub struct ServerRules;
+
+impl ProtocolRules for ServerRules {
+    type State = Phase;
+
+    fn received(&self, phase: Phase, message: &str) -> Result<Phase, &'static str> {
+        match (phase, message_kind(message)) {
+            (_, "ServerInfoRequest") => Ok(phase),
+            (_, "Hello" | "Resume") => Ok(Phase::Handshaking),
+            (Phase::Connected, "Disconnect") => Ok(Phase::Idle),
+            (Phase::Connected, _) => Ok(Phase::Connected),
+            (Phase::Handshaking, "Heartbeat") => Ok(Phase::Handshaking),
+            (Phase::Handshaking, _) => Err("the handshake hasn't finished"),
+            (Phase::Idle, _) if LEGACY_LINES.contains(&message) => Ok(Phase::Idle),
+            (Phase::Idle, _) => Err("no session; a client starts with Hello or Resume"),
+        }
+    }
+
+    fn sent(&self, phase: Phase, message: &str) -> Phase {
+        match message_kind(message) {
+            "Welcome" => Phase::Connected,
+            "Reject" | "Disconnect" => Phase::Idle,
+            _ => phase,
+        }
+    }
+
+    fn label(&self, phase: Phase) -> &'static str {
+        match phase {
+            Phase::Idle => "idle",
+            Phase::Handshaking => "handshaking",
+            Phase::Connected => "connected",
+        }
+    }
+}
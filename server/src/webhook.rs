@@ -0,0 +1,56 @@
+//! Optional Discord-style webhook notifications.
+//!
+//! Built with `--features webhook`, `--webhook-url` makes the server post a
+//! short message when it starts and whenever a client connects or times
+//! out, so whoever runs the demo server hears about it without watching the
+//! window. ureq blocks, so each post gets a thread of its own rather than a
+//! task on Bevy's pools, where it would hold up a worker; a slow or failing
+//! webhook only costs a warning in the log. Without the feature, or without
+//! a URL, posting is a no-op.
+
+use bevy::prelude::*;
+
+use crate::Args;
+
+#[derive(Resource, Clone, Default)]
+pub struct Webhook {
+    #[cfg(feature = "webhook")]
+    url: Option<String>,
+}
+
+impl Webhook {
+    #[cfg(feature = "webhook")]
+    pub fn new(url: Option<String>) -> Self {
+        Self { url }
+    }
+
+    #[cfg(feature = "webhook")]
+    pub fn post(&self, text: impl Into<String>) {
+        let Some(url) = self.url.clone() else {
+            return;
+        };
+        let text = text.into();
+        let spawned = std::thread::Builder::new()
+            .name("webhook".into())
+            .spawn(move || {
+                let body = serde_json::json!({ "content": text });
+                if let Err(e) = ureq::post(&url).send_json(body) {
+                    warn!("Webhook post failed: {}", e);
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Webhook post failed: no thread: {}", e);
+        }
+    }
+
+    #[cfg(not(feature = "webhook"))]
+    pub fn post(&self, _text: impl Into<String>) {}
+}
+
+pub fn announce_server_start(args: Res<Args>, webhook: Res<Webhook>) {
+    let data = match args.data_port {
+        Some(data_port) => format!(", gameplay on {}", data_port),
+        None => String::new(),
+    };
+    webhook.post(format!("Server started on port {}{}", args.port, data));
+}